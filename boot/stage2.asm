@@ -12,6 +12,8 @@
 ;   7. Jump to kernel with boot info
 ;
 ; Assemble: nasm -f bin -o stage2.bin stage2.asm
+; Kernel command line: nasm ... -DBOOT_CMDLINE='"safe"'
+; Disk image loaded by QEMU: nasm ... -DRAMDISK_ADDR=0x2000000 -DRAMDISK_SIZE=n
 ; ============================================================================
 
 [BITS 16]
@@ -111,6 +113,16 @@ load_kernel:
     mov     si, msg_ok
     call    print_string
 
+    ; Shift held through the kernel load requests safe mode
+    mov     ah, 0x02            ; Read keyboard shift flags
+    int     0x16
+    test    al, 0x03            ; Right or left Shift
+    jz      .no_safe_mode
+    or      byte [boot_flags], 1
+    mov     si, msg_safe_mode
+    call    print_string
+.no_safe_mode:
+
     ; ========================================================================
     ; Step 5: Switch to Protected Mode
     ; ========================================================================
@@ -419,8 +431,18 @@ setup_vesa:
 ; Load Kernel (uses standard CHS reads to low memory)
 ; ============================================================================
 
+; KERNEL_SECTORS is the size of the flat kernel.bin in sectors, passed in
+; by the build (-DKERNEL_SECTORS=n) once the kernel is linked. The kernel is
+; read to 0x20000 before being copied up, so it has to end below the
+; protected mode stack at 0x90000.
+%ifndef KERNEL_SECTORS
+%error "KERNEL_SECTORS not given: assemble with -DKERNEL_SECTORS=<sectors in kernel.bin>"
+%endif
+%if KERNEL_SECTORS > (0x90000 - 0x20000) / 512
+%error "kernel.bin is too big to load below 0x90000"
+%endif
+
 KERNEL_SECTOR   equ 33          ; After stage2 (sector 1-32)
-KERNEL_SECTORS  equ 128         ; 64KB kernel (adjust as needed)
 KERNEL_LOAD_SEG equ 0x2000      ; Load at 0x20000 (128KB mark)
 KERNEL_LOAD_OFF equ 0x0000
 KERNEL_DEST     equ 0x100000    ; Final destination: 1MB
@@ -688,6 +710,21 @@ protected_mode_entry:
     mov     [edi], eax
     add     edi, 4
 
+    ; Boot flags and command line
+    movzx   eax, byte [boot_flags]
+    mov     [edi], eax
+    add     edi, 4
+
+    mov     dword [edi], boot_cmdline
+    add     edi, 4
+
+    ; Disk image placed in memory by the emulator (0 = none)
+    mov     dword [edi], RAMDISK_ADDR
+    add     edi, 4
+
+    mov     dword [edi], RAMDISK_SIZE
+    add     edi, 4
+
     ; Jump to kernel!
     ; Debug: Write '!' to top-left corner of VGA text buffer
     mov     byte [0xB8000], '!'
@@ -710,6 +747,19 @@ vesa_width:         dw 0
 vesa_height:        dw 0
 vesa_bpp:           db 0
 vesa_pitch:         dw 0
+boot_flags:         db 0
+
+%ifndef BOOT_CMDLINE
+%define BOOT_CMDLINE ''
+%endif
+boot_cmdline:       db BOOT_CMDLINE, 0
+
+%ifndef RAMDISK_ADDR
+%define RAMDISK_ADDR 0
+%endif
+%ifndef RAMDISK_SIZE
+%define RAMDISK_SIZE 0
+%endif
 
 ; Messages
 msg_stage2:         db 13, 10
@@ -726,6 +776,7 @@ msg_e820_fail:      db 13, '  [FAIL] E820 query failed!', 13, 10, 0
 msg_a20_fail:       db 13, '  [FAIL] Could not enable A20!', 13, 10, 0
 msg_vesa_fallback:  db 13, '  [WARN] VESA unavailable, using VGA text', 13, 10, 0
 msg_kernel_fail:    db 13, '  [FAIL] Could not load kernel!', 13, 10, 0
+msg_safe_mode:      db '  [SAFE] Shift held, booting in safe mode', 13, 10, 0
 
 ; Pad to sector boundary
 times 16384 - ($ - $$) db 0     ; 32 sectors = 16KB
@@ -6,30 +6,28 @@ echo "  Rustacean OS Build System"
 echo "========================================"
 echo ""
 
-echo "[1/5] Assembling bootloader..."
 mkdir -p build
-nasm -f bin -o build/boot.bin boot/boot.asm
-nasm -f bin -o build/stage2.bin boot/stage2.asm
-echo "      boot.bin: $(stat -c%s build/boot.bin) bytes"
-echo "      stage2.bin: $(stat -c%s build/stage2.bin) bytes"
-echo ""
-
-echo "[2/5] Building kernel..."
-cd kernel
 
 # Show FULL cargo output
-if cargo +nightly build --release --target ../i686-rustacean.json \
-    -Zbuild-std=core,alloc \
-    -Zbuild-std-features=compiler-builtins-mem 2>&1; then
+build_kernel() {
+    (cd kernel && cargo +nightly build --release --target ../i686-rustacean.json \
+        -Zbuild-std=core,alloc \
+        -Zbuild-std-features=compiler-builtins-mem 2>&1)
+}
+
+echo "[1/5] Building kernel..."
+if build_kernel; then
     echo "      Kernel build successful!"
+    # Relink with room in .ksyms for the symbol table (src/ksyms.rs)
+    export KSYMS_SIZE=$(python3 tools/ksyms.py --size kernel/target/kernel.map)
+    echo "      Relinking with a ${KSYMS_SIZE}-byte symbol table..."
+    build_kernel
 else
     echo ""
     echo "      ERROR: Kernel build failed!"
     echo ""
 fi
 
-cd ..
-
 # Find the kernel binary
 echo ""
 echo "      Searching for kernel binary..."
@@ -45,6 +43,11 @@ fi
 if [ -n "$KERNEL_BIN" ]; then
     # Convert ELF to flat binary - THIS IS CRITICAL!
     # The bootloader jumps directly to 0x100000, so we need raw machine code
+    # Fill in the symbol table for crash traces (src/ksyms.rs)
+    echo "      Embedding kernel symbols..."
+    python3 tools/ksyms.py kernel/target/kernel.map build/ksyms.bin
+    objcopy --update-section .ksyms=build/ksyms.bin "$KERNEL_BIN"
+
     echo "      Converting ELF to flat binary..."
     objcopy -O binary "$KERNEL_BIN" build/kernel.bin
     echo "      kernel.bin: $(stat -c%s build/kernel.bin) bytes"
@@ -55,6 +58,18 @@ else
 fi
 echo ""
 
+echo "[2/5] Assembling bootloader..."
+nasm -f bin -o build/boot.bin boot/boot.asm
+# stage2 loads exactly kernel.bin, and refuses to assemble if it's too big
+KERNEL_SECTORS=0
+if [ -f build/kernel.bin ]; then
+    KERNEL_SECTORS=$(( ($(stat -c%s build/kernel.bin) + 511) / 512 ))
+fi
+nasm -f bin -DKERNEL_SECTORS=$KERNEL_SECTORS -o build/stage2.bin boot/stage2.asm
+echo "      boot.bin: $(stat -c%s build/boot.bin) bytes"
+echo "      stage2.bin: $(stat -c%s build/stage2.bin) bytes (loads $KERNEL_SECTORS kernel sectors)"
+echo ""
+
 echo "[3/5] Creating floppy image..."
 # Create 1.44MB floppy image
 dd if=/dev/zero of=build/rustacean.img bs=512 count=2880 2>/dev/null
@@ -0,0 +1,25 @@
+//! Build script: have the linker write a map of the kernel, which
+//! `tools/ksyms.py` turns into the symbol table (see src/ksyms.rs), and
+//! reserve `KSYMS_SIZE` bytes for that table in the `.ksyms` section.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=linker.ld");
+    println!("cargo:rerun-if-env-changed=KSYMS_SIZE");
+
+    // Only the kernel target links with rust-lld; host builds (cargo check)
+    // don't link at all
+    if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("x86") {
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        println!("cargo:rustc-link-arg-bins=-Map={}/target/kernel.map", dir);
+
+        // Unset (or empty) on the first link, before there is a map to
+        // measure the table from
+        let ksyms_size = std::env::var("KSYMS_SIZE").unwrap_or_default();
+        let ksyms_size: u32 = match ksyms_size.trim() {
+            "" => 0,
+            size => size.parse().expect("KSYMS_SIZE must be a byte count"),
+        };
+        println!("cargo:rustc-link-arg-bins=--defsym=__ksyms_size={}", ksyms_size);
+    }
+}
@@ -0,0 +1,40 @@
+//! Host-side kernel tests
+//!
+//! The kernel only builds for i686 with its own allocator and panic
+//! handler, so `cargo test` can't run there. Modules that are pure logic
+//! are compiled into this crate instead, at the same module paths, and
+//! their `#[cfg(test)]` tests run on the host:
+//!
+//! ```text
+//! cd kernel/hosttest && cargo test
+//! ```
+//!
+//! Anything a module imports from the rest of the kernel is stood in for
+//! here.
+
+#![no_std]
+
+#[cfg(test)]
+extern crate std;
+
+#[path = "../../src/gui"]
+pub mod gui {
+    pub mod color;
+    pub mod pixel;
+
+    pub use color::Color;
+
+    /// Stand-in for the kernel's `gui::dither`: packs without dithering
+    pub mod dither {
+        use super::pixel::PixelFormat;
+        use super::Color;
+
+        pub fn pack_rgb565(color: Color, _x: i32, _y: i32) -> u16 {
+            PixelFormat::Rgb565.pack(color) as u16
+        }
+
+        pub fn pack_rgb332(color: Color, _x: i32, _y: i32) -> u8 {
+            PixelFormat::Indexed8.pack(color) as u8
+        }
+    }
+}
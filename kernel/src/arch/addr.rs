@@ -0,0 +1,111 @@
+//! Address Types
+//!
+//! Physical and virtual addresses as pointer-sized newtypes, so code that
+//! passes addresses around doesn't assume 32 bits and can't hand a
+//! virtual address to something expecting a physical one. Memory below
+//! `mm::paging::KERNEL_SPACE_END` is identity mapped, where `identity`
+//! converts one to the other.
+//!
+//! Hardware registers and older i686-only code still take `u32`; `as_u32`
+//! is for those boundaries.
+
+use core::fmt;
+
+macro_rules! address_type {
+    ($name:ident) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+        #[repr(transparent)]
+        pub struct $name(usize);
+
+        impl $name {
+            pub const NULL: Self = Self(0);
+
+            pub const fn new(addr: usize) -> Self {
+                Self(addr)
+            }
+
+            /// Address from a 32-bit field (boot info, device registers)
+            pub const fn from_u32(addr: u32) -> Self {
+                Self(addr as usize)
+            }
+
+            pub const fn as_usize(self) -> usize {
+                self.0
+            }
+
+            /// Address for a 32-bit field; must be below 4GB
+            pub fn as_u32(self) -> u32 {
+                crate::kassert!(self.0 <= u32::MAX as usize, "address {:#x} above 4GB", self.0);
+                self.0 as u32
+            }
+
+            pub const fn is_null(self) -> bool {
+                self.0 == 0
+            }
+
+            /// Address `bytes` further on (None on overflow)
+            pub const fn checked_add(self, bytes: usize) -> Option<Self> {
+                match self.0.checked_add(bytes) {
+                    Some(addr) => Some(Self(addr)),
+                    None => None,
+                }
+            }
+
+            /// Round down to a power-of-two alignment
+            pub const fn align_down(self, align: usize) -> Self {
+                Self(self.0 & !(align - 1))
+            }
+
+            /// Round up to a power-of-two alignment
+            pub const fn align_up(self, align: usize) -> Self {
+                Self((self.0 + align - 1) & !(align - 1))
+            }
+
+            pub const fn is_aligned(self, align: usize) -> bool {
+                self.0 & (align - 1) == 0
+            }
+
+            /// Index of the 4KB page holding this address
+            pub const fn page_index(self) -> usize {
+                self.0 / 4096
+            }
+
+            /// Offset within its 4KB page
+            pub const fn page_offset(self) -> usize {
+                self.0 & 0xFFF
+            }
+        }
+
+        impl fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.0, f)
+            }
+        }
+
+        impl fmt::UpperHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::UpperHex::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+address_type!(PhysAddr);
+address_type!(VirtAddr);
+
+impl PhysAddr {
+    /// Virtual address of identity-mapped physical memory
+    pub const fn identity(self) -> VirtAddr {
+        VirtAddr(self.0)
+    }
+}
+
+impl VirtAddr {
+    pub const fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+}
@@ -1,5 +1,143 @@
 //! Architecture-specific code
 //!
-//! Currently only x86 (i686) is supported.
+//! `Arch` is the interface the rest of the kernel uses for interrupts,
+//! port I/O, page tables and context switches, and `Current` is the
+//! implementation for the target being built:
+//!
+//! - `x86`: i686 protected mode with two-level paging. The kernel runs here.
+//! - `x86_64`: long mode with four-level paging, for 64-bit machines and
+//!   QEMU q35. Only the `Arch` layer exists so far: the GDT, IDT, syscall
+//!   entry and drivers are still reached through `arch::x86` directly and
+//!   assume i686, so a 64-bit kernel does not boot yet. Moving those
+//!   callers onto `Arch` (and onto `PhysAddr`/`VirtAddr` instead of `u32`)
+//!   is what remains of the port.
+//!
+//! New code should go through `Current` and the free functions below
+//! rather than naming `arch::x86`.
 
+pub mod addr;
 pub mod x86;
+#[cfg(target_arch = "x86_64")]
+pub mod x86_64;
+
+pub use addr::{PhysAddr, VirtAddr};
+
+/// What the kernel needs from a CPU architecture
+pub trait Arch {
+    /// Short name for logs (`uname`-style)
+    const NAME: &'static str;
+
+    /// Bytes per page
+    const PAGE_SIZE: usize = 4096;
+
+    /// Saved state of a suspended kernel thread
+    type Context;
+
+    // =========================================================================
+    // Interrupts
+    // =========================================================================
+
+    fn interrupts_enabled() -> bool;
+
+    /// # Safety
+    ///
+    /// Handlers must be installed for everything that can now fire.
+    unsafe fn enable_interrupts();
+
+    fn disable_interrupts();
+
+    /// Enable interrupts and sleep until one arrives
+    fn wait_for_interrupt();
+
+    /// Run a closure with interrupts disabled, restoring the previous state
+    fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+        let enabled = Self::interrupts_enabled();
+        Self::disable_interrupts();
+        let result = f();
+        if enabled {
+            unsafe { Self::enable_interrupts() };
+        }
+        result
+    }
+
+    // =========================================================================
+    // Port I/O
+    // =========================================================================
+
+    /// # Safety
+    ///
+    /// Port access can reconfigure hardware; the caller must own the port.
+    unsafe fn inb(port: u16) -> u8;
+    /// # Safety
+    ///
+    /// As for `inb`.
+    unsafe fn outb(port: u16, value: u8);
+    /// # Safety
+    ///
+    /// As for `inb`.
+    unsafe fn inw(port: u16) -> u16;
+    /// # Safety
+    ///
+    /// As for `inb`.
+    unsafe fn outw(port: u16, value: u16);
+    /// # Safety
+    ///
+    /// As for `inb`.
+    unsafe fn inl(port: u16) -> u32;
+    /// # Safety
+    ///
+    /// As for `inb`.
+    unsafe fn outl(port: u16, value: u32);
+
+    // =========================================================================
+    // Paging
+    // =========================================================================
+
+    /// Physical address of the active top-level page table
+    fn page_table() -> PhysAddr;
+
+    /// Switch to another top-level page table
+    ///
+    /// # Safety
+    ///
+    /// The table must map the kernel exactly as the current one does.
+    unsafe fn set_page_table(root: PhysAddr);
+
+    /// Drop any cached translation for a page
+    fn flush_page(addr: VirtAddr);
+
+    // =========================================================================
+    // Context Switch
+    // =========================================================================
+
+    /// Save the running thread into `old` and resume `new`
+    ///
+    /// # Safety
+    ///
+    /// Interrupts must be disabled and `new` must hold a thread saved by
+    /// this function (or set up to look like one).
+    unsafe fn switch_context(old: *mut Self::Context, new: *mut Self::Context);
+}
+
+/// Implementation for the target being built
+#[cfg(target_arch = "x86")]
+pub type Current = x86::X86;
+
+/// Implementation for the target being built
+#[cfg(target_arch = "x86_64")]
+pub type Current = x86_64::X86_64;
+
+/// Run a closure with interrupts disabled, restoring the previous state
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    Current::without_interrupts(f)
+}
+
+/// Are interrupts enabled on this CPU?
+pub fn interrupts_enabled() -> bool {
+    Current::interrupts_enabled()
+}
+
+/// Sleep until the next interrupt
+pub fn wait_for_interrupt() {
+    Current::wait_for_interrupt()
+}
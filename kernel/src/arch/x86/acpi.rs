@@ -0,0 +1,112 @@
+//! ACPI Tables
+//!
+//! Just enough table lookup for reset, power-off and CPU discovery: find
+//! the RSDP in the BIOS areas, walk the RSDT, and validate table
+//! checksums.
+//!
+//! Tables are read in place through the identity map when they lie in it
+//! (below `KERNEL_SPACE_END`). Firmware puts them at the top of RAM, which
+//! on machines with more than 256MB is past the identity map, so those
+//! are mapped through `iomap`: the header first, for the length, then the
+//! whole table. A `Table` holds its mapping and gives it back when
+//! dropped, so reading tables doesn't use up device mappings.
+
+use crate::mm::iomap::{iomap, iounmap};
+use crate::mm::paging::KERNEL_SPACE_END;
+
+/// Size of the header every table starts with
+const HEADER_LEN: usize = 36;
+
+/// A table (or part of one) readable until dropped
+pub struct Table {
+    /// Address to read it through
+    pub addr: usize,
+    /// `addr` is an `iomap` mapping rather than the identity map
+    mapped: bool,
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        if self.mapped {
+            iounmap(self.addr as u32);
+        }
+    }
+}
+
+/// Locate the FADT ("FACP") through the RSDP and RSDT
+pub unsafe fn find_fadt() -> Option<Table> {
+    find_table(b"FACP")
+}
+
+/// Locate a table by signature through the RSDP and RSDT
+pub unsafe fn find_table(signature: &[u8; 4]) -> Option<Table> {
+    let rsdp = find_rsdp()?;
+    let rsdt = map_table(((rsdp + 16) as *const u32).read_unaligned() as usize, b"RSDT")?;
+
+    let length = ((rsdt.addr + 4) as *const u32).read_unaligned() as usize;
+    let entries = (length.saturating_sub(HEADER_LEN)) / 4;
+    (0..entries).find_map(|i| {
+        let table = ((rsdt.addr + HEADER_LEN + i * 4) as *const u32).read_unaligned() as usize;
+        map_table(table, signature)
+    })
+}
+
+/// Map the table at physical address `phys` and check its signature and
+/// checksum
+pub unsafe fn map_table(phys: usize, signature: &[u8; 4]) -> Option<Table> {
+    if phys == 0 {
+        return None;
+    }
+    let length = {
+        let header = map(phys, HEADER_LEN)?;
+        if core::slice::from_raw_parts(header.addr as *const u8, 4) != signature {
+            return None;
+        }
+        ((header.addr + 4) as *const u32).read_unaligned() as usize
+    };
+    // However long the header says (a DSDT can run to hundreds of KB), as
+    // long as it all maps and sums to zero
+    if length < HEADER_LEN {
+        return None;
+    }
+    let table = map(phys, length)?;
+    (checksum(table.addr, length) == 0).then_some(table)
+}
+
+/// Make `len` bytes at `phys` readable (see the module comment)
+unsafe fn map(phys: usize, len: usize) -> Option<Table> {
+    if phys.checked_add(len)? <= KERNEL_SPACE_END as usize {
+        return Some(Table { addr: phys, mapped: false });
+    }
+    let addr = iomap(phys as u32, len as u32).ok()? as usize;
+    Some(Table { addr, mapped: addr != phys })
+}
+
+/// Byte sum of a table (0 when valid)
+pub unsafe fn checksum(addr: usize, len: usize) -> u8 {
+    let bytes = core::slice::from_raw_parts(addr as *const u8, len);
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Search the EBDA and BIOS ROM area for the RSDP
+unsafe fn find_rsdp() -> Option<usize> {
+    let ebda = (*(0x40E as *const u16) as usize) << 4;
+    if ebda >= 0x8_0000 && ebda < 0xA_0000 {
+        if let Some(addr) = scan_rsdp(ebda, ebda + 1024) {
+            return Some(addr);
+        }
+    }
+    scan_rsdp(0xE_0000, 0x10_0000)
+}
+
+unsafe fn scan_rsdp(start: usize, end: usize) -> Option<usize> {
+    let mut addr = start;
+    while addr + 20 <= end {
+        let sig = core::slice::from_raw_parts(addr as *const u8, 8);
+        if sig == b"RSD PTR " && checksum(addr, 20) == 0 {
+            return Some(addr);
+        }
+        addr += 16;
+    }
+    None
+}
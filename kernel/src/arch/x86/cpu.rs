@@ -0,0 +1,119 @@
+//! CPU Identification and Model-Specific Registers
+//!
+//! CPUID feature bits and RDMSR/WRMSR, shared by the machine check, MTRR
+//! and other CPU feature code. Callers check the CPUID bit for a feature
+//! before touching its MSRs; a missing MSR raises #GP.
+
+use alloc::string::String;
+use core::fmt::Write;
+#[cfg(target_arch = "x86")]
+use core::arch::x86::__cpuid;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::__cpuid;
+
+/// CPUID.1:EDX feature bits
+pub mod features {
+    pub const TSC: u32 = 1 << 4;
+    pub const MCE: u32 = 1 << 7;
+    pub const APIC: u32 = 1 << 9;
+    pub const MTRR: u32 = 1 << 12;
+    pub const MCA: u32 = 1 << 14;
+    pub const PAT: u32 = 1 << 16;
+}
+
+/// CPUID leaf registers (eax, ebx, ecx, edx)
+pub fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
+    #[allow(unused_unsafe)]
+    let r = unsafe { __cpuid(leaf) };
+    (r.eax, r.ebx, r.ecx, r.edx)
+}
+
+/// CPUID.1:EDX
+pub fn features() -> u32 {
+    cpuid(1).3
+}
+
+/// Does the CPU report this CPUID.1:EDX feature?
+pub fn has(feature: u32) -> bool {
+    features() & feature != 0
+}
+
+/// Physical address width in bits (36 if the CPU doesn't say)
+pub fn physical_address_bits() -> u32 {
+    if cpuid(0x8000_0000).0 >= 0x8000_0008 {
+        cpuid(0x8000_0008).0 & 0xFF
+    } else {
+        36
+    }
+}
+
+/// Read a model-specific register
+///
+/// # Safety
+/// The MSR must exist on this CPU
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+    core::arch::asm!("rdmsr", in("ecx") msr, out("eax") lo, out("edx") hi, options(nomem, nostack));
+    (hi as u64) << 32 | lo as u64
+}
+
+/// Write a model-specific register
+///
+/// # Safety
+/// The MSR must exist on this CPU and `value` must be valid for it
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    core::arch::asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32,
+        options(nomem, nostack));
+}
+
+/// Names of CPUID.1:EDX bits for /proc/cpuinfo (bit, name)
+const FLAG_NAMES: [(u32, &str); 14] = [
+    (0, "fpu"), (4, "tsc"), (5, "msr"), (6, "pae"), (7, "mce"), (8, "cx8"),
+    (9, "apic"), (12, "mtrr"), (14, "mca"), (15, "cmov"), (16, "pat"),
+    (23, "mmx"), (25, "sse"), (26, "sse2"),
+];
+
+/// Vendor, model, brand, feature flags and clock rate (/proc/cpuinfo)
+pub fn report(out: &mut String) {
+    let (max_leaf, ebx, ecx, edx) = cpuid(0);
+    let mut vendor = [0u8; 12];
+    vendor[0..4].copy_from_slice(&ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&ecx.to_le_bytes());
+    let _ = writeln!(out, "vendor: {}", core::str::from_utf8(&vendor).unwrap_or("?"));
+
+    if max_leaf >= 1 {
+        let eax = cpuid(1).0;
+        let mut family = (eax >> 8) & 0xF;
+        let mut model = (eax >> 4) & 0xF;
+        if family == 0xF {
+            family += (eax >> 20) & 0xFF;
+        }
+        if family >= 6 {
+            model |= ((eax >> 16) & 0xF) << 4;
+        }
+        let _ = writeln!(out, "family: {}  model: {}  stepping: {}", family, model, eax & 0xF);
+    }
+
+    if cpuid(0x8000_0000).0 >= 0x8000_0004 {
+        let mut brand = [0u8; 48];
+        for (i, leaf) in (0x8000_0002..=0x8000_0004u32).enumerate() {
+            let (a, b, c, d) = cpuid(leaf);
+            for (j, reg) in [a, b, c, d].iter().enumerate() {
+                let at = i * 16 + j * 4;
+                brand[at..at + 4].copy_from_slice(&reg.to_le_bytes());
+            }
+        }
+        let len = brand.iter().position(|&c| c == 0).unwrap_or(brand.len());
+        let _ = writeln!(out, "model name: {}",
+            core::str::from_utf8(&brand[..len]).unwrap_or("?").trim());
+    }
+
+    let bits = features();
+    let _ = write!(out, "flags:");
+    for (_, name) in FLAG_NAMES.iter().filter(|(bit, _)| bits & (1 << bit) != 0) {
+        let _ = write!(out, " {}", name);
+    }
+    let _ = writeln!(out);
+    super::tsc::report(out);
+}
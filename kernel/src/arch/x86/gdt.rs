@@ -4,6 +4,7 @@
 //! We use a flat memory model with separate code/data segments.
 
 use core::mem::size_of;
+use super::smp::{self, MAX_CPUS};
 
 /// GDT Entry (Segment Descriptor)
 #[derive(Debug, Clone, Copy)]
@@ -93,12 +94,23 @@ pub mod selectors {
     pub const KERNEL_DATA: u16 = 0x10;  // Index 2, GDT, Ring 0
     pub const USER_CODE: u16 = 0x18 | 3;  // Index 3, GDT, Ring 3
     pub const USER_DATA: u16 = 0x20 | 3;  // Index 4, GDT, Ring 3
-    pub const TSS: u16 = 0x28;  // Index 5, GDT, Ring 0
+    pub const TSS: u16 = 0x28;  // Index 5, GDT, Ring 0 (CPU 0)
+
+    /// TSS selector of a CPU (one descriptor each: a loaded TSS is busy)
+    pub const fn tss(cpu: usize) -> u16 {
+        TSS + (cpu as u16) * 8
+    }
 }
 
+/// Descriptors before the per-CPU TSS entries
+const FIXED_ENTRIES: usize = 5;
+
+/// Total descriptors
+const GDT_ENTRIES: usize = FIXED_ENTRIES + MAX_CPUS;
+
 /// Wrapper for aligned GDT
 #[repr(C, align(8))]
-struct AlignedGdt([GdtEntry; 6]);
+struct AlignedGdt([GdtEntry; GDT_ENTRIES]);
 
 /// The Global Descriptor Table
 /// 
@@ -108,15 +120,15 @@ struct AlignedGdt([GdtEntry; 6]);
 /// - 0x10: Kernel data segment
 /// - 0x18: User code segment
 /// - 0x20: User data segment
-/// - 0x28: TSS (set up later)
-static mut GDT: AlignedGdt = AlignedGdt([
-    GdtEntry::null(),           // 0x00: Null
-    GdtEntry::kernel_code(),    // 0x08: Kernel Code
-    GdtEntry::kernel_data(),    // 0x10: Kernel Data
-    GdtEntry::user_code(),      // 0x18: User Code
-    GdtEntry::user_data(),      // 0x20: User Data
-    GdtEntry::null(),           // 0x28: TSS (placeholder)
-]);
+/// - 0x28: TSS of CPU 0, then one per further CPU (set up later)
+static mut GDT: AlignedGdt = {
+    let mut entries = [GdtEntry::null(); GDT_ENTRIES];
+    entries[1] = GdtEntry::kernel_code();   // 0x08: Kernel Code
+    entries[2] = GdtEntry::kernel_data();   // 0x10: Kernel Data
+    entries[3] = GdtEntry::user_code();     // 0x18: User Code
+    entries[4] = GdtEntry::user_data();     // 0x20: User Data
+    AlignedGdt(entries)
+};
 
 /// GDT pointer for LGDT instruction
 static mut GDT_PTR: GdtPointer = GdtPointer {
@@ -128,13 +140,18 @@ static mut GDT_PTR: GdtPointer = GdtPointer {
 pub fn init() {
     unsafe {
         // Set up GDT pointer
-        GDT_PTR.limit = (size_of::<[GdtEntry; 6]>() - 1) as u16;
+        GDT_PTR.limit = (size_of::<[GdtEntry; GDT_ENTRIES]>() - 1) as u16;
         GDT_PTR.base = GDT.0.as_ptr() as u32;
-        
-        // Load GDT
+    }
+    load();
+}
+
+/// Load the GDT on this CPU and reload the segment registers
+pub fn load() {
+    unsafe {
         core::arch::asm!(
             "lgdt [{}]",
-            in(reg) &GDT_PTR,
+            in(reg) core::ptr::addr_of!(GDT_PTR),
             options(nostack, preserves_flags)
         );
         
@@ -160,21 +177,115 @@ pub fn init() {
     }
 }
 
-/// Set up the TSS entry (called after memory manager is ready)
-pub fn set_tss(tss_base: u32, tss_limit: u32) {
+/// End the kernel code segment at `end` (page aligned), so the kernel
+/// can't execute anything at or above it (W^X, see `mm::paging`)
+///
+/// Reloads this CPU's segments; APs pick the limit up when they load the
+/// GDT.
+pub fn limit_kernel_code(end: u32) {
+    unsafe {
+        GDT.0[1] = GdtEntry::new(0, (end >> 12) - 1, 0b10011010, 0b1100);
+    }
+    load();
+}
+
+/// Set up a CPU's TSS entry
+pub fn set_tss(cpu: usize, tss_base: u32, tss_limit: u32) {
     unsafe {
         // TSS descriptor: Present, Ring 0, Type 0x9 (32-bit TSS available)
-        GDT.0[5] = GdtEntry::new(tss_base, tss_limit, 0b10001001, 0b0000);
+        GDT.0[FIXED_ENTRIES + cpu] = GdtEntry::new(tss_base, tss_limit, 0b10001001, 0b0000);
     }
 }
 
-/// Load the TSS
-pub fn load_tss() {
+/// Load a CPU's TSS on it
+pub fn load_tss(cpu: usize) {
     unsafe {
         core::arch::asm!(
             "ltr ax",
-            in("ax") selectors::TSS,
+            in("ax") selectors::tss(cpu),
             options(nostack, preserves_flags)
         );
     }
 }
+
+// =============================================================================
+// Task State Segment
+// =============================================================================
+
+/// 32-bit Task State Segment
+///
+/// Only SS0:ESP0 matter: the stack the CPU switches to when an interrupt
+/// or INT 0x80 arrives while running in ring 3. Each user program has its
+/// own kernel stack, and `exec` points ESP0 at it every time it switches
+/// to the program. Hardware task switching is not used. Each CPU has its
+/// own TSS; user programs only run on CPU 0.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Tss {
+    link: u32,
+    esp0: u32,
+    ss0: u32,
+    /// ESP1..LDT, unused
+    unused: [u32; 22],
+    trap: u16,
+    iomap_base: u16,
+}
+
+impl Tss {
+    const fn new() -> Self {
+        Self {
+            link: 0,
+            esp0: 0,
+            ss0: selectors::KERNEL_DATA as u32,
+            unused: [0; 22],
+            trap: 0,
+            // No I/O permission bitmap: ring 3 gets no port access
+            iomap_base: size_of::<Tss>() as u16,
+        }
+    }
+}
+
+/// Ring 0 stack size for user-mode entries (per program, too)
+pub const RING0_STACK_SIZE: usize = 16 * 1024;
+
+#[repr(C, align(16))]
+struct Ring0Stack([u8; RING0_STACK_SIZE]);
+
+/// Stack used on ring 3 -> ring 0 transitions while no program has its own
+static mut RING0_STACK: Ring0Stack = Ring0Stack([0; RING0_STACK_SIZE]);
+
+/// TSS of each CPU
+static mut TSS: [Tss; MAX_CPUS] = [Tss::new(); MAX_CPUS];
+
+/// Install and load the boot CPU's TSS
+pub fn init_tss() {
+    reset_kernel_stack();
+    install_tss(0);
+}
+
+/// Install and load a further CPU's TSS, with ESP0 at its idle stack
+pub fn init_ap_tss(cpu: usize, esp0: u32) {
+    unsafe { (*core::ptr::addr_of_mut!(TSS))[cpu].esp0 = esp0 };
+    install_tss(cpu);
+}
+
+fn install_tss(cpu: usize) {
+    let tss = unsafe { core::ptr::addr_of!(TSS[cpu]) };
+    set_tss(cpu, tss as u32, size_of::<Tss>() as u32 - 1);
+    load_tss(cpu);
+}
+
+/// Set the stack used when this CPU enters ring 0 from ring 3
+pub fn set_kernel_stack(esp0: u32) {
+    unsafe { (*core::ptr::addr_of_mut!(TSS))[smp::cpu_index()].esp0 = esp0 }
+}
+
+/// Go back to the shared ring 0 stack
+pub fn reset_kernel_stack() {
+    set_kernel_stack(core::ptr::addr_of!(RING0_STACK) as u32 + RING0_STACK_SIZE as u32);
+}
+
+/// Top of this CPU's ring 0 stack in use (ESP0)
+pub fn kernel_stack() -> u32 {
+    unsafe { (*core::ptr::addr_of!(TSS))[smp::cpu_index()].esp0 }
+}
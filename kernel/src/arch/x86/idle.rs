@@ -0,0 +1,55 @@
+//! CPU Idle
+//!
+//! `halt` parks the CPU with `sti; hlt` until the next interrupt (the
+//! timer at the latest), instead of spinning. Idle time is sampled by the
+//! timer: a tick that lands while the CPU is halted counts as idle, so
+//! idle ticks over total ticks for an interval is the idle fraction, at
+//! tick resolution. The timer only interrupts CPU 0, so that is the CPU
+//! whose idle time is measured.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use super::smp::{self, MAX_CPUS};
+
+/// Each CPU is in `halt`
+static HALTED: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// Timer ticks that arrived while halted
+static IDLE_TICKS: AtomicU32 = AtomicU32::new(0);
+
+/// Halt until the next interrupt
+///
+/// Enables interrupts; `sti` holds them off for one more instruction, so
+/// one can't slip in between and leave the `hlt` waiting a whole tick.
+pub fn halt() {
+    let halted = &HALTED[smp::cpu_index()];
+    halted.store(true, Ordering::Relaxed);
+    unsafe { core::arch::asm!("sti; hlt", options(nomem, nostack)) };
+    halted.store(false, Ordering::Relaxed);
+}
+
+/// Account a timer tick (timer IRQ)
+pub fn note_tick() {
+    if HALTED[smp::cpu_index()].load(Ordering::Relaxed) {
+        IDLE_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Ticks spent halted since boot (wraps)
+pub fn idle_ticks() -> u32 {
+    IDLE_TICKS.load(Ordering::Relaxed)
+}
+
+/// Idle percentage between two (idle ticks, ticks) samples
+pub fn percent(start: (u32, u32), end: (u32, u32)) -> u32 {
+    let idle = end.0.wrapping_sub(start.0);
+    let total = end.1.wrapping_sub(start.1);
+    if total == 0 {
+        return 0;
+    }
+    (idle.min(total) * 100) / total
+}
+
+/// Current (idle ticks, ticks) sample for `percent`
+pub fn sample() -> (u32, u32) {
+    (idle_ticks(), super::pit::ticks())
+}
@@ -3,10 +3,12 @@
 //! Sets up interrupt handlers for exceptions and hardware interrupts.
 //! In the EventChains architecture, interrupts can dispatch events.
 
+use alloc::string::String;
 use core::arch::global_asm;
+use core::fmt::Write;
 use core::mem::size_of;
 use super::gdt::selectors;
-use super::pic;
+use super::{irqchip, pic};
 
 /// IDT Entry (Interrupt Gate Descriptor)
 #[derive(Debug, Clone, Copy)]
@@ -146,8 +148,25 @@ extern "C" {
     fn isr_stub_default();
     fn irq_stub_0();
     fn irq_stub_1();
+    fn irq_stub_2();
+    fn irq_stub_3();
+    fn irq_stub_4();
+    fn irq_stub_5();
+    fn irq_stub_6();
+    fn irq_stub_7();
+    fn irq_stub_8();
+    fn irq_stub_9();
+    fn irq_stub_10();
+    fn irq_stub_11();
     fn irq_stub_12();
+    fn irq_stub_13();
+    fn irq_stub_14();
+    fn irq_stub_15();
     fn irq_stub_default();
+    fn isr_stub_syscall();
+    fn ipi_stub_reschedule();
+    fn ipi_stub_local_timer();
+    fn ipi_stub_spurious();
 }
 
 // ISR stubs in assembly using global_asm!
@@ -300,19 +319,134 @@ global_asm!(
     "    push 33",             // IRQ 1 = INT 33
     "    jmp isr_common",
 
+    ".global irq_stub_2",
+    "irq_stub_2:",
+    "    push 0",
+    "    push 34",
+    "    jmp isr_common",
+
+    ".global irq_stub_3",
+    "irq_stub_3:",
+    "    push 0",
+    "    push 35",
+    "    jmp isr_common",
+
+    ".global irq_stub_4",
+    "irq_stub_4:",
+    "    push 0",
+    "    push 36",
+    "    jmp isr_common",
+
+    ".global irq_stub_5",
+    "irq_stub_5:",
+    "    push 0",
+    "    push 37",
+    "    jmp isr_common",
+
+    ".global irq_stub_6",
+    "irq_stub_6:",
+    "    push 0",
+    "    push 38",
+    "    jmp isr_common",
+
+    ".global irq_stub_7",
+    "irq_stub_7:",
+    "    push 0",
+    "    push 39",
+    "    jmp isr_common",
+
+    ".global irq_stub_8",
+    "irq_stub_8:",
+    "    push 0",
+    "    push 40",
+    "    jmp isr_common",
+
+    ".global irq_stub_9",
+    "irq_stub_9:",
+    "    push 0",
+    "    push 41",
+    "    jmp isr_common",
+
+    ".global irq_stub_10",
+    "irq_stub_10:",
+    "    push 0",
+    "    push 42",
+    "    jmp isr_common",
+
+    ".global irq_stub_11",
+    "irq_stub_11:",
+    "    push 0",
+    "    push 43",
+    "    jmp isr_common",
+
     ".global irq_stub_12",
     "irq_stub_12:",
     "    push 0",
     "    push 44",             // IRQ 12 = INT 44 (mouse/touchpad)
     "    jmp isr_common",
 
+    ".global irq_stub_13",
+    "irq_stub_13:",
+    "    push 0",
+    "    push 45",
+    "    jmp isr_common",
+
+    ".global irq_stub_14",
+    "irq_stub_14:",
+    "    push 0",
+    "    push 46",
+    "    jmp isr_common",
+
+    ".global irq_stub_15",
+    "irq_stub_15:",
+    "    push 0",
+    "    push 47",
+    "    jmp isr_common",
+
     ".global irq_stub_default",
     "irq_stub_default:",
     "    push 0",
     "    push 255",
     "    jmp isr_common",
+
+    // INT 0x80 - system call (result is written back into the saved EAX)
+    ".global isr_stub_syscall",
+    "isr_stub_syscall:",
+    "    push 0",
+    "    push 128",
+    "    jmp isr_common",
+
+    // Inter-processor interrupts from the local APIC
+    ".global ipi_stub_reschedule",
+    "ipi_stub_reschedule:",
+    "    push 0",
+    "    push 240",
+    "    jmp isr_common",
+
+    ".global ipi_stub_local_timer",
+    "ipi_stub_local_timer:",
+    "    push 0",
+    "    push 239",
+    "    jmp isr_common",
+
+    // Spurious APIC interrupts take no EOI and need no handler
+    ".global ipi_stub_spurious",
+    "ipi_stub_spurious:",
+    "    iretd",
 );
 
+/// Interrupt vector for system calls
+pub const SYSCALL_VECTOR: usize = 0x80;
+
+/// Local APIC timer (scheduler ticks on the other CPUs)
+pub const LOCAL_TIMER_VECTOR: usize = 0xEF;
+
+/// IPI asking a CPU to look at its run queue again
+pub const RESCHEDULE_VECTOR: usize = 0xF0;
+
+/// Local APIC spurious interrupt vector
+pub const SPURIOUS_VECTOR: usize = 0xFF;
+
 /// Initialize the IDT
 pub fn init() {
     // Initialize PIC first
@@ -325,14 +459,27 @@ pub fn init() {
         // Set up IRQ handlers (interrupts 32-47)
         set_irq_handlers();
 
+        // System call gate, callable from ring 3. A trap gate keeps
+        // interrupts on so syscalls that wait on the timer still see ticks.
+        IDT.0[SYSCALL_VECTOR] = IdtEntry::trap_gate(isr_stub_syscall as u32, selectors::KERNEL_CODE, 3);
+
+        IDT.0[LOCAL_TIMER_VECTOR] = IdtEntry::interrupt_gate(ipi_stub_local_timer as *const () as u32, selectors::KERNEL_CODE, 0);
+        IDT.0[RESCHEDULE_VECTOR] = IdtEntry::interrupt_gate(ipi_stub_reschedule as u32, selectors::KERNEL_CODE, 0);
+        IDT.0[SPURIOUS_VECTOR] = IdtEntry::interrupt_gate(ipi_stub_spurious as u32, selectors::KERNEL_CODE, 0);
+
         // Set up IDT pointer
         IDT_PTR.limit = (size_of::<[IdtEntry; IDT_ENTRIES]>() - 1) as u16;
         IDT_PTR.base = IDT.0.as_ptr() as u32;
+    }
+    load();
+}
 
-        // Load IDT
+/// Load the IDT on this CPU (every CPU shares the one table)
+pub fn load() {
+    unsafe {
         core::arch::asm!(
         "lidt [{}]",
-        in(reg) &IDT_PTR,
+        in(reg) core::ptr::addr_of!(IDT_PTR),
         options(nostack, preserves_flags)
         );
     }
@@ -374,24 +521,239 @@ unsafe fn set_irq_handlers() {
     // IRQ 1 - Keyboard (INT 33)
     IDT.0[33] = IdtEntry::interrupt_gate(irq_stub_1 as u32, selectors::KERNEL_CODE, 0);
 
-    // IRQ 2-11 use default handler
-    for i in 2..12 {
-        IDT.0[32 + i] = IdtEntry::interrupt_gate(irq_stub_default as u32, selectors::KERNEL_CODE, 0);
-    }
-
     // IRQ 12 - PS/2 Mouse/Touchpad (INT 44) - dedicated handler
     IDT.0[44] = IdtEntry::interrupt_gate(irq_stub_12 as u32, selectors::KERNEL_CODE, 0);
 
-    // IRQ 13-15 use default handler
-    for i in 13..16 {
-        IDT.0[32 + i] = IdtEntry::interrupt_gate(irq_stub_default as u32, selectors::KERNEL_CODE, 0);
+    // IRQ 2-11 and 13-15 dispatch to registered (possibly shared) handlers
+    let stubs: [unsafe extern "C" fn(); 13] = [
+        irq_stub_2, irq_stub_3, irq_stub_4, irq_stub_5, irq_stub_6,
+        irq_stub_7, irq_stub_8, irq_stub_9, irq_stub_10, irq_stub_11,
+        irq_stub_13, irq_stub_14, irq_stub_15,
+    ];
+    let lines = [2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14, 15];
+    for (stub, irq) in stubs.iter().zip(lines.iter()) {
+        IDT.0[32 + irq] = IdtEntry::interrupt_gate(*stub as u32, selectors::KERNEL_CODE, 0);
+    }
+}
+
+// =============================================================================
+// IRQ Handler Registration
+// =============================================================================
+
+/// Device interrupt handler
+///
+/// Called with the IRQ line number. Returns true if the device raised the
+/// interrupt; PCI lines are level-triggered and may be shared, so every
+/// handler on the line is called and each must check its own status.
+pub type IrqHandler = fn(irq: u8) -> bool;
+
+/// Maximum handlers sharing one IRQ line
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+/// A registered handler and the name shown in /proc/interrupts
+#[derive(Clone, Copy)]
+struct IrqAction {
+    handler: IrqHandler,
+    name: &'static str,
+}
+
+/// Registered handlers per IRQ line
+static mut IRQ_HANDLERS: [[Option<IrqAction>; MAX_SHARED_HANDLERS]; 16] =
+    [[None; MAX_SHARED_HANDLERS]; 16];
+
+/// Interrupts taken on each vector
+static mut INTERRUPT_COUNTS: [u32; 256] = [0; 256];
+
+/// Interrupts on each line that no handler claimed
+static mut UNCLAIMED_IRQS: [u32; 16] = [0; 16];
+
+/// Lines with dedicated built-in handlers (timer, keyboard, cascade, mouse)
+const RESERVED_IRQS: [u8; 4] = [0, 1, 2, 12];
+
+/// Register a handler for an IRQ line and unmask it
+///
+/// Several handlers may share a line (up to `MAX_SHARED_HANDLERS`).
+/// `name` identifies the device in /proc/interrupts.
+pub fn register_irq_handler(irq: u8, name: &'static str, handler: IrqHandler) -> Result<(), &'static str> {
+    if irq >= 16 {
+        return Err("IRQ out of range");
+    }
+    if RESERVED_IRQS.contains(&irq) {
+        return Err("IRQ reserved for built-in handler");
+    }
+
+    let registered = without_interrupts(|| unsafe {
+        let slots = &mut IRQ_HANDLERS[irq as usize];
+        if slots.iter().flatten().any(|a| a.handler as usize == handler as usize) {
+            return Err("Handler already registered");
+        }
+        match slots.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(IrqAction { handler, name });
+                Ok(())
+            }
+            None => Err("Too many handlers on IRQ line"),
+        }
+    });
+
+    if registered.is_ok() {
+        irqchip::enable_irq(irq);
+    }
+    registered
+}
+
+/// Remove a handler, masking the line when no handlers remain
+pub fn unregister_irq_handler(irq: u8, handler: IrqHandler) {
+    if irq >= 16 {
+        return;
+    }
+
+    let now_empty = without_interrupts(|| unsafe {
+        let slots = &mut IRQ_HANDLERS[irq as usize];
+        for slot in slots.iter_mut() {
+            if matches!(slot, Some(a) if a.handler as usize == handler as usize) {
+                *slot = None;
+            }
+        }
+        slots.iter().all(|slot| slot.is_none())
+    });
+
+    if now_empty {
+        irqchip::disable_irq(irq);
     }
 }
 
+/// Number of handlers registered on a line
+pub fn irq_handler_count(irq: u8) -> usize {
+    if irq >= 16 {
+        return 0;
+    }
+    unsafe { IRQ_HANDLERS[irq as usize].iter().flatten().count() }
+}
+
+/// Interrupts on a line that no registered handler claimed
+pub fn unclaimed_irqs(irq: u8) -> u32 {
+    if irq >= 16 {
+        return 0;
+    }
+    unsafe { UNCLAIMED_IRQS[irq as usize] }
+}
+
+/// Interrupts taken on a vector (IRQ n is vector 32 + n)
+pub fn interrupt_count(vector: u8) -> u32 {
+    unsafe { INTERRUPT_COUNTS[vector as usize] }
+}
+
+/// Name of a built-in handler's line
+fn builtin_irq_name(irq: u8) -> Option<&'static str> {
+    match irq {
+        0 => Some("timer"),
+        1 => Some("keyboard"),
+        2 => Some("cascade"),
+        12 if crate::drivers::synaptics::is_initialized() => Some("synaptics"),
+        12 => Some("mouse"),
+        _ => None,
+    }
+}
+
+/// Name of a CPU exception or other non-IRQ vector
+fn vector_name(vector: u8) -> &'static str {
+    match vector {
+        0 => "divide error",
+        1 => "debug",
+        2 => "nmi",
+        3 => "breakpoint",
+        4 => "overflow",
+        5 => "bound range",
+        6 => "invalid opcode",
+        7 => "no fpu",
+        8 => "double fault",
+        10 => "invalid tss",
+        11 => "segment not present",
+        12 => "stack fault",
+        13 => "general protection",
+        14 => "page fault",
+        16 => "fpu error",
+        17 => "alignment check",
+        18 => "machine check",
+        19 => "simd error",
+        0x80 => "syscall",
+        0xEF => "local timer",
+        0xF0 => "reschedule",
+        _ => "unknown",
+    }
+}
+
+/// Per-IRQ counts, masks and handlers, then other vectors taken (/proc/interrupts)
+pub fn report(out: &mut String) {
+    let mask = irqchip::mask();
+    let _ = writeln!(out, "irq  count       unclaimed  spurious  masked  handlers");
+    for irq in 0..16u8 {
+        let _ = write!(out, "{:<4} {:<11} {:<10} {:<9} {:<7} ",
+            irq,
+            interrupt_count(pic::IRQ_BASE_MASTER + irq),
+            unclaimed_irqs(irq),
+            pic::spurious_count(irq),
+            if mask & (1 << irq) != 0 { "yes" } else { "no" });
+        let mut names = builtin_irq_name(irq).into_iter()
+            .chain(unsafe { IRQ_HANDLERS[irq as usize] }.into_iter().flatten().map(|a| a.name));
+        match names.next() {
+            Some(first) => {
+                let _ = write!(out, "{}", first);
+                for name in names {
+                    let _ = write!(out, ", {}", name);
+                }
+                let _ = writeln!(out);
+            }
+            None => { let _ = writeln!(out, "-"); }
+        }
+    }
+
+    let _ = writeln!(out, "vector  count       name");
+    for vector in (0..=255u8).filter(|v| !(32..48).contains(v)) {
+        let count = interrupt_count(vector);
+        if count != 0 {
+            let _ = writeln!(out, "{:<7} {:<11} {}", vector, count, vector_name(vector));
+        }
+    }
+    irqchip::report(out);
+}
+
+/// Run a closure with interrupts disabled, restoring the previous state
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let eflags: u32;
+    unsafe {
+        core::arch::asm!("pushfd; pop {}; cli", out(reg) eflags);
+    }
+    let result = f();
+    if eflags & (1 << 9) != 0 {
+        unsafe { core::arch::asm!("sti"); }
+    }
+    result
+}
+
+/// Call every handler registered on a line, then acknowledge the controller
+fn dispatch_irq(irq: u8) {
+    let mut claimed = false;
+    unsafe {
+        for action in IRQ_HANDLERS[irq as usize].iter().flatten() {
+            claimed |= (action.handler)(irq);
+        }
+        if !claimed {
+            UNCLAIMED_IRQS[irq as usize] = UNCLAIMED_IRQS[irq as usize].wrapping_add(1);
+        }
+    }
+    irqchip::eoi(irq);
+}
+
 /// Main interrupt handler (called from assembly)
 #[no_mangle]
-extern "C" fn interrupt_handler(frame: &InterruptFrame) {
+extern "C" fn interrupt_handler(frame: &mut InterruptFrame) {
     let int_num = frame.interrupt_number;
+    unsafe {
+        let count = &mut INTERRUPT_COUNTS[int_num as usize & 0xFF];
+        *count = count.wrapping_add(1);
+    }
 
     match int_num {
         // CPU Exceptions
@@ -401,14 +763,47 @@ extern "C" fn interrupt_handler(frame: &InterruptFrame) {
         13 => exception_handler("General protection fault", frame),
         14 => page_fault_handler(frame),
 
-        // IRQs (32-47)
-        32 => timer_handler(),
-        33 => keyboard_handler(),
-        44 => mouse_handler(),  // IRQ12 = interrupt 44
+        // Hardware errors
+        2 => hardware_error(super::mce::nmi(frame), frame),
+        18 => hardware_error(super::mce::machine_check(frame), frame),
+
+        // System call
+        0x80 => syscall_handler(frame),
 
-        // Other IRQs
+        // Scheduler tick on another CPU
+        0xEF => super::lapic::timer_interrupt(),
+
+        // Another CPU queued work for this one
+        0xF0 => {
+            super::lapic::eoi();
+            crate::sched::schedule();
+        }
+
+        // IRQs (32-47)
         32..=47 => {
-            pic::send_eoi(int_num as u8);
+            let irq = int_num - 32;
+            // Noise on IRQ7/15: no handler and no EOI
+            if irqchip::is_spurious(irq as u8) {
+                return;
+            }
+            crate::trace!(IrqEntry, irq);
+            match irq {
+                0 => timer_handler(),
+                1 => keyboard_handler(),
+                12 => mouse_handler(),
+                // Other IRQs go to registered device handlers
+                _ => dispatch_irq(irq as u8),
+            }
+            crate::trace!(IrqExit, irq);
+
+            // Bottom halves, with interrupts on (the EOI has been sent)
+            crate::softirq::irq_exit();
+
+            // A user program that has used up its slice goes back to the main loop
+            if irq == 0 {
+                crate::profile::sample(frame);
+                crate::exec::preempt(frame);
+            }
         }
 
         _ => {
@@ -418,6 +813,22 @@ extern "C" fn interrupt_handler(frame: &InterruptFrame) {
 }
 
 fn exception_handler(name: &str, frame: &InterruptFrame) {
+    // A user program's fault kills the program, not the machine
+    crate::exec::fault_exit(frame, name);
+    fatal_exception(name, frame);
+}
+
+/// Act on an NMI or machine check once `mce` has logged it
+fn hardware_error(outcome: super::mce::Outcome, frame: &InterruptFrame) {
+    match outcome {
+        super::mce::Outcome::Continue => {}
+        super::mce::Outcome::Kill(name) => exception_handler(name, frame),
+        super::mce::Outcome::Fatal(name) => fatal_exception(name, frame),
+    }
+}
+
+/// Show the exception, record a crash dump and halt
+fn fatal_exception(name: &str, frame: &InterruptFrame) -> ! {
     // Write directly to VGA buffer for debugging
     unsafe {
         let vga = 0xB8000 as *mut u8;
@@ -460,7 +871,7 @@ fn exception_handler(name: &str, frame: &InterruptFrame) {
         if let Some(writer) = crate::drivers::vga::WRITER.as_mut() {
             use core::fmt::Write;
             let _ = writeln!(writer, "\n!!! EXCEPTION: {} !!!", name);
-            let _ = writeln!(writer, "EIP: 0x{:08X}", frame.eip);
+            let _ = writeln!(writer, "EIP: {}", crate::ksyms::Addr(frame.eip));
             let _ = writeln!(writer, "Error code: 0x{:08X}", frame.error_code);
             let _ = writeln!(writer, "EAX: 0x{:08X}  EBX: 0x{:08X}", frame.eax, frame.ebx);
             let _ = writeln!(writer, "ECX: 0x{:08X}  EDX: 0x{:08X}", frame.ecx, frame.edx);
@@ -469,17 +880,42 @@ fn exception_handler(name: &str, frame: &InterruptFrame) {
         }
     }
 
+    crate::crashdump::record_exception(name, frame, 0);
+    write_trace();
+
     loop {
         unsafe { core::arch::asm!("cli; hlt"); }
     }
 }
 
-fn page_fault_handler(frame: &InterruptFrame) {
+/// Show the call trace from the crash dump just recorded
+fn write_trace() {
+    let writer = unsafe { (*core::ptr::addr_of_mut!(crate::drivers::vga::WRITER)).as_mut() };
+    if let (Some(writer), Some(dump)) = (writer, crate::crashdump::last()) {
+        crate::ksyms::write_trace(writer, dump.stack());
+    }
+}
+
+fn page_fault_handler(frame: &mut InterruptFrame) {
     let fault_addr: u32;
     unsafe {
         core::arch::asm!("mov {}, cr2", out(reg) fault_addr);
     }
 
+    // Copy-on-write and other recoverable faults
+    if crate::mm::paging::handle_fault(fault_addr, frame.error_code) {
+        return;
+    }
+    // A fault the kernel was testing for (the W^X self-test), or a bad
+    // user pointer in a syscall's copy (which then returns EFAULT)
+    if let Some(resume) = crate::mm::paging::probe_fault(fault_addr)
+        .or_else(|| crate::syscall::usercopy::fixup(frame.eip))
+    {
+        frame.eip = resume;
+        return;
+    }
+    crate::exec::fault_exit(frame, "page fault");
+
     // Write to VGA text buffer directly
     unsafe {
         let vga = 0xB8000 as *mut u8;
@@ -506,7 +942,7 @@ fn page_fault_handler(frame: &InterruptFrame) {
             use core::fmt::Write;
             let _ = writeln!(writer, "\n!!! PAGE FAULT !!!");
             let _ = writeln!(writer, "Faulting address: 0x{:08X}", fault_addr);
-            let _ = writeln!(writer, "EIP: 0x{:08X}", frame.eip);
+            let _ = writeln!(writer, "EIP: {}", crate::ksyms::Addr(frame.eip));
             let _ = writeln!(writer, "Error code: 0x{:08X}", frame.error_code);
 
             // Decode error code
@@ -519,19 +955,37 @@ fn page_fault_handler(frame: &InterruptFrame) {
         }
     }
 
+    crate::crashdump::record_exception("Page fault", frame, fault_addr);
+    write_trace();
+
     loop {
         unsafe { core::arch::asm!("cli; hlt"); }
     }
 }
 
+fn syscall_handler(frame: &mut InterruptFrame) {
+    let number = frame.eax;
+    let params = crate::syscall::SyscallParams::from_regs(
+        frame.eax, frame.ebx, frame.ecx, frame.edx, frame.esi, frame.edi);
+    frame.eax = crate::syscall::handle_syscall(params);
+
+    // Exit, Exec, Fork and blocking syscalls finish here
+    crate::exec::after_syscall(frame, number);
+}
+
 fn timer_handler() {
-    // Increment local tick counter
-    // If you have a PIT module, replace this with: crate::arch::x86::pit::tick();
+    // Increment local tick counter and drive PIT timekeeping
     unsafe {
         TICK_COUNT = TICK_COUNT.wrapping_add(1);
     }
-
-    pic::send_eoi(32);
+    super::pit::tick();
+    super::tsc::tick(super::pit::ticks());
+    crate::rng::add_timer_sample();
+    crate::sched::timer::tick();
+    super::idle::note_tick();
+    crate::watchdog::check();
+
+    irqchip::eoi(0);
 }
 
 /// Get current tick count
@@ -541,15 +995,12 @@ pub fn ticks() -> u32 {
 
 fn keyboard_handler() {
     let scancode = unsafe { super::io::inb(0x60) };
+    crate::rng::add_input(scancode as u32);
 
-    // Process through keyboard driver
-    unsafe {
-        if let Some(_event) = crate::drivers::keyboard::KEYBOARD.process_scancode(scancode) {
-            // Event will be handled by GUI event loop
-        }
-    }
+    // Decoded by the keyboard softirq
+    crate::drivers::keyboard::queue_scancode(scancode);
 
-    pic::send_eoi(33);
+    irqchip::eoi(1);
 }
 
 /// Mouse/Touchpad IRQ handler
@@ -559,24 +1010,24 @@ fn mouse_handler() {
     let status = unsafe { super::io::inb(0x64) };
     if status & 0x20 == 0 {
         // Not mouse data, send EOI and return
-        pic::send_eoi(44);
+        irqchip::eoi(12);
         return;
     }
 
     // Read the data byte
     let byte = unsafe { super::io::inb(0x60) };
+    crate::rng::add_input(byte as u32);
 
     // Route to appropriate driver based on what's initialized
-    // Check Synaptics first (preferred driver)
+    // Check Synaptics first (preferred driver); either decodes in the
+    // mouse softirq
     if crate::drivers::synaptics::is_initialized() {
-        crate::drivers::synaptics::handle_irq_byte(byte);
+        crate::drivers::synaptics::queue_byte(byte);
     } else {
         // Fall back to generic PS/2 mouse driver
-        unsafe {
-            crate::drivers::mouse::MOUSE.process_byte(byte);
-        }
+        crate::drivers::mouse::queue_byte(byte);
     }
 
-    // IRQ12 is on the slave PIC, so we need to send EOI to both
-    pic::send_eoi(44);
+    // IRQ12 is on the slave PIC, so that gets an EOI as well as the master
+    irqchip::eoi(12);
 }
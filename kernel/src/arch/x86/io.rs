@@ -1,10 +1,13 @@
 //! x86 I/O Port Access
 //!
-//! Provides safe wrappers for port I/O operations.
+//! Provides safe wrappers for port I/O operations. Debug builds check
+//! each access against the claim registry (see `ioport`).
 
 /// Read a byte from an I/O port
 #[inline]
 pub unsafe fn inb(port: u16) -> u8 {
+    #[cfg(debug_assertions)]
+    super::ioport::check(port);
     let value: u8;
     core::arch::asm!(
         "in al, dx",
@@ -18,6 +21,8 @@ pub unsafe fn inb(port: u16) -> u8 {
 /// Write a byte to an I/O port
 #[inline]
 pub unsafe fn outb(port: u16, value: u8) {
+    #[cfg(debug_assertions)]
+    super::ioport::check(port);
     core::arch::asm!(
         "out dx, al",
         in("dx") port,
@@ -29,6 +34,8 @@ pub unsafe fn outb(port: u16, value: u8) {
 /// Read a word (16-bit) from an I/O port
 #[inline]
 pub unsafe fn inw(port: u16) -> u16 {
+    #[cfg(debug_assertions)]
+    super::ioport::check(port);
     let value: u16;
     core::arch::asm!(
         "in ax, dx",
@@ -42,6 +49,8 @@ pub unsafe fn inw(port: u16) -> u16 {
 /// Write a word (16-bit) to an I/O port
 #[inline]
 pub unsafe fn outw(port: u16, value: u16) {
+    #[cfg(debug_assertions)]
+    super::ioport::check(port);
     core::arch::asm!(
         "out dx, ax",
         in("dx") port,
@@ -53,6 +62,8 @@ pub unsafe fn outw(port: u16, value: u16) {
 /// Read a dword (32-bit) from an I/O port
 #[inline]
 pub unsafe fn inl(port: u16) -> u32 {
+    #[cfg(debug_assertions)]
+    super::ioport::check(port);
     let value: u32;
     core::arch::asm!(
         "in eax, dx",
@@ -66,6 +77,8 @@ pub unsafe fn inl(port: u16) -> u32 {
 /// Write a dword (32-bit) to an I/O port
 #[inline]
 pub unsafe fn outl(port: u16, value: u32) {
+    #[cfg(debug_assertions)]
+    super::ioport::check(port);
     core::arch::asm!(
         "out dx, eax",
         in("dx") port,
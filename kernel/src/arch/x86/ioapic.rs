@@ -0,0 +1,155 @@
+//! IO-APIC
+//!
+//! Routes device interrupts to a local APIC in place of the 8259 PIC. Each
+//! IO-APIC handles a range of global system interrupts (GSIs) starting at
+//! its `gsi_base`; ISA IRQ n arrives on GSI n unless the MADT overrides it
+//! (the PIT usually comes in on GSI 2). Registers are reached through an
+//! index register (IOREGSEL) and a data window (IOWIN).
+//!
+//! Only the 16 ISA IRQs are routed, each to vector 32 + n so the IDT and
+//! handlers are the same as with the PIC. PCI lines above them would need
+//! the ACPI `_PRT` tables, which aren't parsed.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use super::topology::{Topology, MAX_IOAPICS};
+
+// Register offsets from the base
+const IOREGSEL: u32 = 0x00;
+const IOWIN: u32 = 0x10;
+
+// Register indices
+const REG_ID: u32 = 0x00;
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION: u32 = 0x10;
+
+// Redirection entry bits (low dword)
+const ENTRY_POLARITY_LOW: u32 = 1 << 13;
+const ENTRY_LEVEL: u32 = 1 << 15;
+const ENTRY_MASKED: u32 = 1 << 16;
+
+// MPS INTI flags, as in MADT overrides
+const INTI_POLARITY_MASK: u16 = 0b11;
+const INTI_POLARITY_LOW: u16 = 0b11;
+const INTI_TRIGGER_MASK: u16 = 0b11 << 2;
+const INTI_TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+/// GSI of an ISA IRQ not routed
+const NO_GSI: u32 = u32::MAX;
+
+/// A mapped IO-APIC
+#[derive(Clone, Copy)]
+struct Chip {
+    id: u8,
+    /// Virtual address of the registers
+    base: u32,
+    gsi_base: u32,
+    /// Redirection entries (one per input pin)
+    pins: u32,
+}
+
+/// IO-APICs set up by `init`
+static mut CHIPS: [Option<Chip>; MAX_IOAPICS] = [None; MAX_IOAPICS];
+
+/// GSI each ISA IRQ is routed to
+static ISA_GSI: [AtomicU32; 16] = [const { AtomicU32::new(NO_GSI) }; 16];
+
+unsafe fn read(chip: &Chip, reg: u32) -> u32 {
+    ((chip.base + IOREGSEL) as *mut u32).write_volatile(reg);
+    ((chip.base + IOWIN) as *const u32).read_volatile()
+}
+
+unsafe fn write(chip: &Chip, reg: u32, value: u32) {
+    ((chip.base + IOREGSEL) as *mut u32).write_volatile(reg);
+    ((chip.base + IOWIN) as *mut u32).write_volatile(value);
+}
+
+fn chips() -> impl Iterator<Item = &'static Chip> {
+    unsafe { (*core::ptr::addr_of!(CHIPS)).iter().flatten() }
+}
+
+/// The IO-APIC handling a GSI, and the pin it arrives on
+fn lookup(gsi: u32) -> Option<(&'static Chip, u32)> {
+    chips().find(|c| gsi >= c.gsi_base && gsi < c.gsi_base + c.pins)
+        .map(|c| (c, gsi - c.gsi_base))
+}
+
+/// Map every IO-APIC the topology lists, with all pins masked; returns
+/// how many there are
+pub fn init(topology: &Topology) -> Result<usize, &'static str> {
+    if topology.ioapics().is_empty() {
+        return Err("No IO-APIC");
+    }
+    for (slot, ioapic) in topology.ioapics().iter().enumerate() {
+        let base = crate::mm::iomap::iomap(ioapic.addr, 4096)?;
+        let mut chip = Chip { id: ioapic.id, base, gsi_base: ioapic.gsi_base, pins: 0 };
+        unsafe {
+            chip.pins = ((read(&chip, REG_VERSION) >> 16) & 0xFF) + 1;
+            for pin in 0..chip.pins {
+                write(&chip, REG_REDIRECTION + pin * 2, ENTRY_MASKED);
+            }
+            (*core::ptr::addr_of_mut!(CHIPS))[slot] = Some(chip);
+        }
+    }
+    Ok(topology.ioapics().len())
+}
+
+/// Route ISA IRQ `irq` to `vector` on the CPU with local APIC `dest`,
+/// masked until `unmask`
+pub fn route_isa(topology: &Topology, irq: u8, vector: u8, dest: u8) -> Result<(), &'static str> {
+    let (gsi, flags) = topology.isa_irq(irq);
+    let (chip, pin) = lookup(gsi).ok_or("GSI not on any IO-APIC")?;
+
+    // ISA defaults: active high, edge triggered
+    let mut low = vector as u32 | ENTRY_MASKED;
+    if flags & INTI_POLARITY_MASK == INTI_POLARITY_LOW {
+        low |= ENTRY_POLARITY_LOW;
+    }
+    if flags & INTI_TRIGGER_MASK == INTI_TRIGGER_LEVEL {
+        low |= ENTRY_LEVEL;
+    }
+    unsafe {
+        write(chip, REG_REDIRECTION + pin * 2 + 1, (dest as u32) << 24);
+        write(chip, REG_REDIRECTION + pin * 2, low);
+    }
+    ISA_GSI[irq as usize].store(gsi, Ordering::Release);
+    Ok(())
+}
+
+/// Mask or unmask a routed ISA IRQ
+pub fn set_masked(irq: u8, masked: bool) {
+    let gsi = ISA_GSI.get(irq as usize).map_or(NO_GSI, |g| g.load(Ordering::Acquire));
+    let Some((chip, pin)) = lookup(gsi) else {
+        return;
+    };
+    unsafe {
+        let low = read(chip, REG_REDIRECTION + pin * 2);
+        let low = if masked { low | ENTRY_MASKED } else { low & !ENTRY_MASKED };
+        write(chip, REG_REDIRECTION + pin * 2, low);
+    }
+}
+
+/// Is an ISA IRQ masked (or not routed)?
+pub fn is_masked(irq: u8) -> bool {
+    let gsi = ISA_GSI.get(irq as usize).map_or(NO_GSI, |g| g.load(Ordering::Acquire));
+    match lookup(gsi) {
+        Some((chip, pin)) => unsafe { read(chip, REG_REDIRECTION + pin * 2) & ENTRY_MASKED != 0 },
+        None => true,
+    }
+}
+
+/// IO-APICs and ISA routing (/proc/interrupts)
+pub fn report(out: &mut String) {
+    for chip in chips() {
+        let id = unsafe { (read(chip, REG_ID) >> 24) & 0xF };
+        let _ = writeln!(out, "ioapic {} (reg id {}): gsi {}-{}", chip.id, id,
+            chip.gsi_base, chip.gsi_base + chip.pins - 1);
+    }
+    for (irq, gsi) in ISA_GSI.iter().enumerate() {
+        let gsi = gsi.load(Ordering::Relaxed);
+        if gsi != NO_GSI && gsi != irq as u32 {
+            let _ = writeln!(out, "irq {} -> gsi {}", irq, gsi);
+        }
+    }
+}
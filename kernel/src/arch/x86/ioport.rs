@@ -0,0 +1,161 @@
+//! I/O Port Claims
+//!
+//! A registry of which driver owns which I/O port range, so two drivers
+//! can't quietly program the same device. Drivers claim their ports when
+//! they initialize; a claim that overlaps another owner's range fails
+//! with an error the driver chain records like any other init failure.
+//!
+//! Several drivers may share one device by claiming under the same owner
+//! name: the keyboard, PS/2 mouse and Synaptics drivers all claim the
+//! 8042 controller as "i8042". Fixed platform devices (PIC, PIT, CMOS,
+//! VGA, PCI configuration) are claimed by `init`.
+//!
+//! Debug builds check every port access in `io` once the driver chain has
+//! run (`enforce`) and panic on a port nobody claimed. Power off and
+//! reset stop the checks, since firmware tables name their ports.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Claims the registry can hold
+const MAX_CLAIMS: usize = 32;
+
+/// A claimed range of ports
+#[derive(Clone, Copy)]
+struct Claim {
+    base: u16,
+    len: u16,
+    owner: &'static str,
+    /// Drivers sharing the claim under this owner
+    users: u32,
+}
+
+impl Claim {
+    fn end(&self) -> u32 {
+        self.base as u32 + self.len as u32
+    }
+
+    fn overlaps(&self, base: u16, len: u16) -> bool {
+        (base as u32) < self.end() && (self.base as u32) < base as u32 + len as u32
+    }
+
+    fn contains(&self, port: u16) -> bool {
+        port >= self.base && (port as u32) < self.end()
+    }
+}
+
+static mut CLAIMS: [Option<Claim>; MAX_CLAIMS] = [None; MAX_CLAIMS];
+
+/// Panic on unclaimed accesses (debug builds)
+static ENFORCING: AtomicBool = AtomicBool::new(false);
+
+/// Legacy devices every PC has: (base, length, owner)
+const PLATFORM: [(u16, u16, &str); 10] = [
+    (0x20, 2, "pic"),
+    (0xA0, 2, "pic"),
+    (0x40, 4, "pit"),
+    (0x61, 1, "port-b"),
+    (0x70, 2, "rtc"),
+    (0x80, 1, "io-delay"),
+    (0xE9, 1, "debugcon"),
+    (0x1CE, 2, "bochs-vbe"),
+    (0x3C0, 32, "vga"),
+    (0xCF8, 8, "pci"),
+];
+
+fn claims() -> &'static [Option<Claim>; MAX_CLAIMS] {
+    unsafe { &*core::ptr::addr_of!(CLAIMS) }
+}
+
+/// Claim the fixed platform devices
+pub fn init() {
+    for (base, len, owner) in PLATFORM {
+        let _ = claim(base, len, owner);
+    }
+}
+
+/// Claim `len` ports from `base` for `owner`
+///
+/// Claiming a range the same owner already holds just adds a user.
+pub fn claim(base: u16, len: u16, owner: &'static str) -> Result<(), &'static str> {
+    if len == 0 || base as u32 + len as u32 > 0x10000 {
+        return Err("Invalid I/O port range");
+    }
+
+    super::idt::without_interrupts(|| unsafe {
+        let claims = &mut *core::ptr::addr_of_mut!(CLAIMS);
+        for claim in claims.iter_mut().flatten() {
+            if !claim.overlaps(base, len) {
+                continue;
+            }
+            if claim.owner == owner && claim.base == base && claim.len == len {
+                claim.users += 1;
+                return Ok(());
+            }
+            crate::klog::write_fmt(format_args!(
+                "[IO  ] {} ports {:04X}-{:04X} conflict with {} {:04X}-{:04X}\n",
+                owner, base, base as u32 + len as u32 - 1, claim.owner, claim.base, claim.end() - 1));
+            return Err("I/O ports claimed by another driver");
+        }
+        match claims.iter_mut().find(|c| c.is_none()) {
+            Some(slot) => {
+                *slot = Some(Claim { base, len, owner, users: 1 });
+                Ok(())
+            }
+            None => Err("Too many I/O port claims"),
+        }
+    })
+}
+
+/// Drop one user of a claim; the range is free once no users remain
+pub fn release(base: u16, owner: &'static str) {
+    super::idt::without_interrupts(|| unsafe {
+        let claims = &mut *core::ptr::addr_of_mut!(CLAIMS);
+        for slot in claims.iter_mut() {
+            if let Some(claim) = slot {
+                if claim.base == base && claim.owner == owner {
+                    claim.users -= 1;
+                    if claim.users == 0 {
+                        *slot = None;
+                    }
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Owner of a port, if claimed
+pub fn owner_of(port: u16) -> Option<&'static str> {
+    claims().iter().flatten().find(|c| c.contains(port)).map(|c| c.owner)
+}
+
+/// Start checking accesses (debug builds); called once drivers are up
+pub fn enforce() {
+    ENFORCING.store(cfg!(debug_assertions), Ordering::Relaxed);
+}
+
+/// Stop checking accesses (power off, reset)
+pub fn stop_enforcing() {
+    ENFORCING.store(false, Ordering::Relaxed);
+}
+
+/// Panic if `port` is unclaimed while enforcing
+#[inline]
+pub fn check(port: u16) {
+    if ENFORCING.load(Ordering::Relaxed) && owner_of(port).is_none() {
+        stop_enforcing();
+        panic!("Access to unclaimed I/O port {:04X}", port);
+    }
+}
+
+/// Claimed ranges (/proc/ioports)
+pub fn report(out: &mut String) {
+    let mut claims: alloc::vec::Vec<Claim> = claims().iter().flatten().copied().collect();
+    claims.sort_by_key(|c| c.base);
+    for claim in claims {
+        let _ = writeln!(out, "{:04X}-{:04X} {} ({})", claim.base, claim.end() - 1, claim.owner, claim.users);
+    }
+    let _ = writeln!(out, "checking {}", if ENFORCING.load(Ordering::Relaxed) { "on" } else { "off" });
+}
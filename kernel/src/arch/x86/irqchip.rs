@@ -0,0 +1,118 @@
+//! Interrupt Controller
+//!
+//! Device interrupts come through the 8259 PIC until `use_apic` hands them
+//! to the IO-APIC and local APIC. Either way ISA IRQ n is vector 32 + n, so
+//! handlers don't change; only masking and the EOI go to a different chip.
+//! The switch is made once, by the driver EventChain (`apic_init`), and is
+//! skipped in safe mode, with `noapic`, on machines without an IO-APIC,
+//! and when the native ATI Rage driver is up: the Armada's PCI lines are
+//! only routed for the PIC, so it keeps the legacy path.
+//!
+//! Code outside `arch::x86` should mask, unmask and acknowledge IRQs here
+//! rather than through `pic`.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::{idt, ioapic, lapic, pic, topology};
+
+/// Set once device interrupts go through the IO-APIC
+static APIC_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Are device interrupts going through the IO-APIC?
+pub fn is_apic() -> bool {
+    APIC_MODE.load(Ordering::Acquire)
+}
+
+/// Controller in use, for logs
+pub fn name() -> &'static str {
+    if is_apic() { "IO-APIC" } else { "8259 PIC" }
+}
+
+/// Move device interrupts from the PIC to the IO-APIC, keeping each IRQ's
+/// mask
+///
+/// Needs the local APIC (`smp::init`). IRQs are delivered to the boot CPU.
+pub fn use_apic() -> Result<(), &'static str> {
+    if is_apic() {
+        return Ok(());
+    }
+    if !lapic::is_enabled() {
+        return Err("Local APIC not enabled");
+    }
+    let topology = topology::get().ok_or("No MADT or MP table")?;
+    if topology.ioapics().is_empty() {
+        return Err("No IO-APIC");
+    }
+
+    idt::without_interrupts(|| {
+        ioapic::init(topology)?;
+        let mask = pic::get_mask();
+        let dest = lapic::id();
+        // IRQ2 is the PIC cascade; nothing arrives on it. Nothing is
+        // unmasked until every line is routed, so a failure leaves the PIC
+        // in sole charge.
+        for irq in (0..16u8).filter(|&irq| irq != 2) {
+            ioapic::route_isa(topology, irq, pic::IRQ_BASE_MASTER + irq, dest)?;
+        }
+        for irq in (0..16u8).filter(|&irq| irq != 2 && mask & (1 << irq) == 0) {
+            ioapic::set_masked(irq, false);
+        }
+        pic::disable();
+        lapic::mask_lint0();
+        APIC_MODE.store(true, Ordering::Release);
+        Ok(())
+    })?;
+    crate::klog!(Info, "irq", "device interrupts through the IO-APIC");
+    Ok(())
+}
+
+/// Unmask an IRQ line
+pub fn enable_irq(irq: u8) {
+    if is_apic() {
+        ioapic::set_masked(irq, false);
+    } else {
+        pic::enable_irq(irq);
+    }
+}
+
+/// Mask an IRQ line
+pub fn disable_irq(irq: u8) {
+    if is_apic() {
+        ioapic::set_masked(irq, true);
+    } else {
+        pic::disable_irq(irq);
+    }
+}
+
+/// Masked lines (bit per IRQ)
+pub fn mask() -> u16 {
+    if is_apic() {
+        (0..16u8).filter(|&irq| ioapic::is_masked(irq)).fold(0, |m, irq| m | 1 << irq)
+    } else {
+        pic::get_mask()
+    }
+}
+
+/// Acknowledge IRQ `irq` (the end of every IRQ handler)
+pub fn eoi(irq: u8) {
+    if is_apic() {
+        lapic::eoi();
+    } else {
+        pic::send_eoi(pic::IRQ_BASE_MASTER + irq);
+    }
+}
+
+/// Check an IRQ for being spurious before handling it (see
+/// `pic::is_spurious`); the local APIC has its own spurious vector
+pub fn is_spurious(irq: u8) -> bool {
+    !is_apic() && pic::is_spurious(irq)
+}
+
+/// Controller and routing (/proc/interrupts)
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "controller: {}", name());
+    if is_apic() {
+        ioapic::report(out);
+    }
+}
@@ -0,0 +1,239 @@
+//! Local APIC
+//!
+//! Every CPU has its own local APIC at the same physical address. It gives
+//! the CPU's APIC ID, sends INIT, startup and reschedule IPIs, takes the
+//! EOI once device interrupts go through the IO-APIC (see `irqchip`), and
+//! has a timer.
+//!
+//! The timer counts down at the bus clock, which nothing reports, so
+//! `calibrate_timer` measures it against the PIT. The PIT stays the
+//! system tick on the boot CPU; the other CPUs run their local timer at
+//! the same rate for scheduler ticks, so an AP busy with a kernel task
+//! still gets preempted and an idle one wakes up to steal work.
+//!
+//! The registers are mapped uncached through `iomap` once, by the boot
+//! CPU; the other CPUs enable their own APIC with `enable_local`.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use super::{cpu, pit};
+use super::idt::{LOCAL_TIMER_VECTOR, SPURIOUS_VECTOR};
+use super::smp::MAX_CPUS;
+
+/// IA32_APIC_BASE MSR
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+// Register offsets
+const REG_ID: u32 = 0x020;
+const REG_TPR: u32 = 0x080;
+const REG_EOI: u32 = 0x0B0;
+const REG_SVR: u32 = 0x0F0;
+const REG_ESR: u32 = 0x280;
+const REG_ICR_LOW: u32 = 0x300;
+const REG_ICR_HIGH: u32 = 0x310;
+const REG_LVT_TIMER: u32 = 0x320;
+const REG_LVT_LINT0: u32 = 0x350;
+const REG_TIMER_INITIAL: u32 = 0x380;
+const REG_TIMER_CURRENT: u32 = 0x390;
+const REG_TIMER_DIVIDE: u32 = 0x3E0;
+
+/// Spurious vector register: APIC software enable
+const SVR_ENABLE: u32 = 1 << 8;
+
+// Interrupt command register bits
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+
+/// Polls of the ICR before a send is given up
+const SEND_SPINS: u32 = 100_000;
+
+// Local vector table bits
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+/// Timer divide configuration: bus clock / 16
+const TIMER_DIVIDE_16: u32 = 0b0011;
+
+/// PIT ticks the timer is measured over
+const CALIBRATE_TICKS: u32 = 5;
+
+/// Virtual address of the registers (0 = not set up)
+static BASE: AtomicU32 = AtomicU32::new(0);
+
+/// Timer counts per second at divide 16 (0 = not calibrated)
+static TIMER_RATE: AtomicU32 = AtomicU32::new(0);
+
+/// Local timer interrupts taken, per CPU index
+static TIMER_TICKS: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+/// Map the local APIC at `phys` and enable the boot CPU's
+pub fn init(phys: u32) -> Result<(), &'static str> {
+    if !cpu::has(cpu::features::APIC) {
+        return Err("CPU has no local APIC");
+    }
+    let virt = crate::mm::iomap::iomap(phys, 4096)?;
+    BASE.store(virt, Ordering::Release);
+    enable_local();
+    Ok(())
+}
+
+/// Is the local APIC mapped?
+pub fn is_enabled() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+unsafe fn read(reg: u32) -> u32 {
+    ((BASE.load(Ordering::Relaxed) + reg) as *const u32).read_volatile()
+}
+
+unsafe fn write(reg: u32, value: u32) {
+    ((BASE.load(Ordering::Relaxed) + reg) as *mut u32).write_volatile(value)
+}
+
+/// Enable this CPU's local APIC, accepting every priority
+pub fn enable_local() {
+    unsafe {
+        let base = cpu::rdmsr(IA32_APIC_BASE);
+        cpu::wrmsr(IA32_APIC_BASE, base | APIC_BASE_ENABLE);
+        write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+        write(REG_TPR, 0);
+    }
+}
+
+/// This CPU's local APIC ID (0 before `init`)
+pub fn id() -> u8 {
+    if !is_enabled() {
+        return 0;
+    }
+    unsafe { (read(REG_ID) >> 24) as u8 }
+}
+
+/// Acknowledge the interrupt being handled
+pub fn eoi() {
+    if is_enabled() {
+        unsafe { write(REG_EOI, 0) };
+    }
+}
+
+/// Send an interrupt command to one CPU
+fn send(apic_id: u8, command: u32) -> Result<(), &'static str> {
+    if !is_enabled() {
+        return Err("Local APIC not enabled");
+    }
+    unsafe {
+        write(REG_ESR, 0);
+        write(REG_ICR_HIGH, (apic_id as u32) << 24);
+        write(REG_ICR_LOW, command);
+        for _ in 0..SEND_SPINS {
+            if read(REG_ICR_LOW) & ICR_PENDING == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+    }
+    Err("IPI not delivered")
+}
+
+/// Interrupt another CPU with `vector`
+pub fn send_ipi(apic_id: u8, vector: u8) -> Result<(), &'static str> {
+    send(apic_id, ICR_ASSERT | vector as u32)
+}
+
+/// Reset a CPU into its wait-for-startup state
+pub fn send_init(apic_id: u8) -> Result<(), &'static str> {
+    send(apic_id, ICR_INIT | ICR_ASSERT)
+}
+
+/// Start a CPU in real mode at `page * 4096`
+pub fn send_startup(apic_id: u8, page: u8) -> Result<(), &'static str> {
+    send(apic_id, ICR_STARTUP | ICR_ASSERT | page as u32)
+}
+
+/// Keep the 8259 from reaching this CPU through LINT0 (virtual wire mode)
+pub fn mask_lint0() {
+    if is_enabled() {
+        unsafe { write(REG_LVT_LINT0, read(REG_LVT_LINT0) | LVT_MASKED) };
+    }
+}
+
+// =============================================================================
+// Timer
+// =============================================================================
+
+/// Measure the timer against the PIT; returns counts per second
+///
+/// Needs interrupts on (it waits on PIT ticks).
+pub fn calibrate_timer() -> Result<u32, &'static str> {
+    if !is_enabled() {
+        return Err("Local APIC not enabled");
+    }
+    unsafe {
+        write(REG_LVT_TIMER, LVT_MASKED | LOCAL_TIMER_VECTOR as u32);
+        write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    }
+
+    // Start on a tick edge so the window is whole ticks
+    let edge = pit::ticks();
+    while pit::ticks() == edge {
+        core::hint::spin_loop();
+    }
+    let start = pit::ticks();
+    unsafe { write(REG_TIMER_INITIAL, u32::MAX) };
+    while pit::ticks().wrapping_sub(start) < CALIBRATE_TICKS {
+        core::hint::spin_loop();
+    }
+    let elapsed = u32::MAX - unsafe { read(REG_TIMER_CURRENT) };
+    unsafe { write(REG_TIMER_INITIAL, 0) };
+
+    let rate = (elapsed as u64 * pit::frequency() as u64 / CALIBRATE_TICKS as u64) as u32;
+    if rate == 0 {
+        return Err("Timer not counting");
+    }
+    TIMER_RATE.store(rate, Ordering::Release);
+    crate::klog!(Info, "lapic", "timer {} kHz (bus {} MHz)", rate / 1000, rate / 62_500);
+    Ok(rate)
+}
+
+/// Timer counts per second (0 = not calibrated)
+pub fn timer_rate() -> u32 {
+    TIMER_RATE.load(Ordering::Acquire)
+}
+
+/// Interrupt this CPU `hz` times a second on `LOCAL_TIMER_VECTOR`
+pub fn start_timer(hz: u32) -> Result<(), &'static str> {
+    let rate = timer_rate();
+    if rate == 0 || hz == 0 {
+        return Err("Timer not calibrated");
+    }
+    unsafe {
+        write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        write(REG_LVT_TIMER, LVT_TIMER_PERIODIC | LOCAL_TIMER_VECTOR as u32);
+        write(REG_TIMER_INITIAL, (rate / hz).max(1));
+    }
+    Ok(())
+}
+
+/// Local timer interrupt (called from the IDT handler)
+pub fn timer_interrupt() {
+    let cpu = super::smp::cpu_index();
+    TIMER_TICKS[cpu].fetch_add(1, Ordering::Relaxed);
+    eoi();
+    crate::sched::timer_tick();
+}
+
+/// Local timer interrupts a CPU has taken
+pub fn timer_ticks(cpu: usize) -> u32 {
+    TIMER_TICKS.get(cpu).map_or(0, |t| t.load(Ordering::Relaxed))
+}
+
+/// Timer calibration (/proc/cpus)
+pub fn report(out: &mut String) {
+    match timer_rate() {
+        0 => { let _ = writeln!(out, "lapic timer: not calibrated"); }
+        rate => { let _ = writeln!(out, "lapic timer: {} Hz (bus {} MHz)", rate, rate / 62_500); }
+    }
+}
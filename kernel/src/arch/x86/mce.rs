@@ -0,0 +1,263 @@
+//! NMI and Machine Check Handling
+//!
+//! Hardware error reports that used to fall into the IDT's unknown
+//! interrupt branch:
+//!
+//! - NMI (vector 2): on PC hardware the cause is latched in system control
+//!   port B (0x61). SERR means a memory parity or system error and is
+//!   fatal, since the bad memory can't be located. IOCHK means an
+//!   expansion card reported an error; that and NMIs with no reason given
+//!   (watchdogs, the QEMU `nmi` command) are logged and ignored.
+//! - Machine check (#MC, vector 18): on CPUs with MCA the banks say what
+//!   happened. Corrected errors are logged and cleared. An uncorrected
+//!   error with a valid return address and intact processor context kills
+//!   the interrupted user program; anything else halts the machine.
+//!
+//! `init` enables machine checks (CR4.MCE) and reports errors left in the
+//! banks from before the last reset. Counts are in /proc/mce.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use super::idt::InterruptFrame;
+use super::cpu::{self, rdmsr, wrmsr};
+use super::io::{inb, outb};
+
+/// System control port B
+const PORT_B: u16 = 0x61;
+
+/// Port B: memory parity / system error (read)
+const NMI_SERR: u8 = 1 << 7;
+/// Port B: I/O channel check (read)
+const NMI_IOCHK: u8 = 1 << 6;
+/// Port B: disable (and clear) the SERR latch (write)
+const CLEAR_SERR: u8 = 1 << 2;
+/// Port B: disable (and clear) the IOCHK latch (write)
+const CLEAR_IOCHK: u8 = 1 << 3;
+/// Port B bits that read back what was written
+const PORT_B_WRITABLE: u8 = 0x0F;
+
+/// CR4 machine check enable
+const CR4_MCE: u32 = 1 << 6;
+
+const MSR_MCG_CAP: u32 = 0x179;
+const MSR_MCG_STATUS: u32 = 0x17A;
+const MSR_MCG_CTL: u32 = 0x17B;
+/// Bank i registers are at MC0_CTL + 4i (CTL, STATUS, ADDR, MISC)
+const MSR_MC0_CTL: u32 = 0x400;
+
+/// MCG_CAP: bank count
+const MCG_BANK_MASK: u64 = 0xFF;
+/// MCG_CAP: MCG_CTL is present
+const MCG_CTL_P: u64 = 1 << 8;
+
+/// MCG_STATUS: restarting at the saved EIP is safe
+const MCG_RIPV: u64 = 1 << 0;
+/// MCG_STATUS: a machine check is in progress
+const MCG_MCIP: u64 = 1 << 2;
+
+/// MCi_STATUS bits
+const MCI_VAL: u64 = 1 << 63;
+const MCI_UC: u64 = 1 << 61;
+const MCI_ADDRV: u64 = 1 << 58;
+const MCI_PCC: u64 = 1 << 57;
+
+/// Banks we look at (the architecture allows up to 255)
+const MAX_BANKS: u32 = 32;
+
+// =============================================================================
+// Counters
+// =============================================================================
+
+static NMI_SERR_COUNT: AtomicU32 = AtomicU32::new(0);
+static NMI_IOCHK_COUNT: AtomicU32 = AtomicU32::new(0);
+static NMI_UNKNOWN_COUNT: AtomicU32 = AtomicU32::new(0);
+static MCE_CORRECTED: AtomicU32 = AtomicU32::new(0);
+static MCE_UNCORRECTED: AtomicU32 = AtomicU32::new(0);
+/// Valid bank reports found at boot
+static MCE_BOOT: AtomicU32 = AtomicU32::new(0);
+
+/// Machine checks enabled (CR4.MCE set)
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Banks present (0 without MCA)
+static BANKS: AtomicU32 = AtomicU32::new(0);
+
+/// What the IDT should do after a hardware error
+pub enum Outcome {
+    /// Logged; return to the interrupted code
+    Continue,
+    /// Kill the interrupted user program (halt if it was the kernel)
+    Kill(&'static str),
+    /// Halt the machine
+    Fatal(&'static str),
+}
+
+fn has_mca() -> bool {
+    cpu::has(cpu::features::MCA)
+}
+
+// =============================================================================
+// Setup
+// =============================================================================
+
+/// Enable machine checks and report errors left over from before boot
+///
+/// Returns the number of banks (0 if the CPU has MCE but no MCA), or an
+/// error if the CPU has no machine check support at all.
+pub fn init() -> Result<u32, &'static str> {
+    if !cpu::has(cpu::features::MCE) {
+        return Err("CPU has no machine check support");
+    }
+
+    let banks = if has_mca() {
+        let cap = unsafe { rdmsr(MSR_MCG_CAP) };
+        let banks = ((cap & MCG_BANK_MASK) as u32).min(MAX_BANKS);
+        unsafe {
+            if cap & MCG_CTL_P != 0 {
+                wrmsr(MSR_MCG_CTL, u64::MAX);
+            }
+            // Bank 0's CTL is owned by firmware on P6-family parts
+            for bank in 1..banks {
+                wrmsr(MSR_MC0_CTL + bank * 4, u64::MAX);
+            }
+        }
+        banks
+    } else {
+        0
+    };
+    BANKS.store(banks, Ordering::Relaxed);
+
+    // Errors logged before the reset (the usual cause of a mystery reboot)
+    for bank in 0..banks {
+        let status = unsafe { rdmsr(status_msr(bank)) };
+        if status & MCI_VAL != 0 {
+            log_bank("boot", bank, status);
+            MCE_BOOT.fetch_add(1, Ordering::Relaxed);
+            unsafe { wrmsr(status_msr(bank), 0) };
+        }
+    }
+
+    unsafe {
+        core::arch::asm!(
+            "mov {tmp}, cr4",
+            "or {tmp}, {mce}",
+            "mov cr4, {tmp}",
+            tmp = out(reg) _,
+            mce = const CR4_MCE,
+        );
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(banks)
+}
+
+fn status_msr(bank: u32) -> u32 {
+    MSR_MC0_CTL + bank * 4 + 1
+}
+
+fn addr_msr(bank: u32) -> u32 {
+    MSR_MC0_CTL + bank * 4 + 2
+}
+
+fn log_bank(when: &str, bank: u32, status: u64) {
+    let kind = if status & MCI_UC != 0 { "uncorrected" } else { "corrected" };
+    crate::klog::write_fmt(format_args!("[MCE ] {} bank {} {} status {:016X}", when, bank, kind, status));
+    if status & MCI_ADDRV != 0 {
+        let addr = unsafe { rdmsr(addr_msr(bank)) };
+        crate::klog::write_fmt(format_args!(" addr {:X}", addr));
+    }
+    crate::klog::write_str("\n");
+}
+
+// =============================================================================
+// Handlers
+// =============================================================================
+
+/// NMI: decode and clear port B
+pub fn nmi(frame: &InterruptFrame) -> Outcome {
+    let reason = unsafe { inb(PORT_B) };
+
+    if reason & NMI_SERR != 0 {
+        NMI_SERR_COUNT.fetch_add(1, Ordering::Relaxed);
+        crate::klog::write_fmt(format_args!("[NMI ] memory parity / system error at {:08X}\n", frame.eip));
+        clear(reason, CLEAR_SERR);
+        return Outcome::Fatal("NMI: memory parity error");
+    }
+
+    if reason & NMI_IOCHK != 0 {
+        NMI_IOCHK_COUNT.fetch_add(1, Ordering::Relaxed);
+        crate::klog::write_fmt(format_args!("[NMI ] I/O channel check at {:08X}\n", frame.eip));
+        clear(reason, CLEAR_IOCHK);
+        return Outcome::Continue;
+    }
+
+    NMI_UNKNOWN_COUNT.fetch_add(1, Ordering::Relaxed);
+    crate::klog::write_fmt(format_args!("[NMI ] unknown reason {:02X} at {:08X}\n", reason, frame.eip));
+    Outcome::Continue
+}
+
+/// Pulse a port B latch disable bit to clear it
+fn clear(reason: u8, bit: u8) {
+    let value = reason & PORT_B_WRITABLE;
+    unsafe {
+        outb(PORT_B, value | bit);
+        outb(PORT_B, value & !bit);
+    }
+}
+
+/// Machine check: log and clear the banks, then decide whether to go on
+pub fn machine_check(frame: &InterruptFrame) -> Outcome {
+    if !has_mca() {
+        MCE_UNCORRECTED.fetch_add(1, Ordering::Relaxed);
+        crate::klog::write_fmt(format_args!("[MCE ] machine check at {:08X} (no MCA)\n", frame.eip));
+        return Outcome::Fatal("Machine check");
+    }
+
+    let mcg_status = unsafe { rdmsr(MSR_MCG_STATUS) };
+    let mut uncorrected = false;
+    let mut context_lost = false;
+
+    for bank in 0..BANKS.load(Ordering::Relaxed) {
+        let status = unsafe { rdmsr(status_msr(bank)) };
+        if status & MCI_VAL == 0 {
+            continue;
+        }
+        log_bank("error", bank, status);
+        uncorrected |= status & MCI_UC != 0;
+        context_lost |= status & MCI_PCC != 0;
+        unsafe { wrmsr(status_msr(bank), 0) };
+    }
+
+    crate::klog::write_fmt(format_args!("[MCE ] at {:08X} mcg_status {:X}\n", frame.eip, mcg_status));
+
+    // Another #MC while MCIP is set shuts the CPU down, so clear it
+    // before going anywhere
+    unsafe { wrmsr(MSR_MCG_STATUS, mcg_status & !MCG_MCIP) };
+
+    if context_lost || mcg_status & MCG_RIPV == 0 {
+        MCE_UNCORRECTED.fetch_add(1, Ordering::Relaxed);
+        return Outcome::Fatal("Machine check: processor context corrupt");
+    }
+    if uncorrected {
+        MCE_UNCORRECTED.fetch_add(1, Ordering::Relaxed);
+        return Outcome::Kill("Machine check: uncorrected error");
+    }
+    MCE_CORRECTED.fetch_add(1, Ordering::Relaxed);
+    Outcome::Continue
+}
+
+// =============================================================================
+// Reporting
+// =============================================================================
+
+/// Counts for /proc/mce
+pub fn report(out: &mut String) {
+    let enabled = ENABLED.load(Ordering::Relaxed);
+    let _ = writeln!(out, "machine checks {} banks {}",
+        if enabled { "enabled" } else { "disabled" }, BANKS.load(Ordering::Relaxed));
+    let _ = writeln!(out, "mce corrected {}", MCE_CORRECTED.load(Ordering::Relaxed));
+    let _ = writeln!(out, "mce uncorrected {}", MCE_UNCORRECTED.load(Ordering::Relaxed));
+    let _ = writeln!(out, "mce at boot {}", MCE_BOOT.load(Ordering::Relaxed));
+    let _ = writeln!(out, "nmi parity {}", NMI_SERR_COUNT.load(Ordering::Relaxed));
+    let _ = writeln!(out, "nmi iochk {}", NMI_IOCHK_COUNT.load(Ordering::Relaxed));
+    let _ = writeln!(out, "nmi unknown {}", NMI_UNKNOWN_COUNT.load(Ordering::Relaxed));
+}
@@ -6,4 +6,94 @@ pub mod gdt;
 pub mod idt;
 pub mod pic;
 pub mod pit;
+pub mod tsc;
+pub mod idle;
 pub mod io;
+pub mod ioport;
+pub mod acpi;
+pub mod reboot;
+pub mod power;
+pub mod rtc;
+pub mod cpu;
+pub mod mce;
+pub mod mtrr;
+pub mod lapic;
+pub mod ioapic;
+pub mod irqchip;
+pub mod topology;
+pub mod smp;
+
+use super::{Arch, PhysAddr, VirtAddr};
+
+/// i686 implementation of `Arch`
+pub struct X86;
+
+impl Arch for X86 {
+    const NAME: &'static str = "i686";
+
+    type Context = crate::sched::Task;
+
+    fn interrupts_enabled() -> bool {
+        let eflags: u32;
+        unsafe { core::arch::asm!("pushfd; pop {:e}", out(reg) eflags) };
+        eflags & (1 << 9) != 0
+    }
+
+    unsafe fn enable_interrupts() {
+        core::arch::asm!("sti", options(nomem, nostack));
+    }
+
+    fn disable_interrupts() {
+        unsafe { core::arch::asm!("cli", options(nomem, nostack)) };
+    }
+
+    fn wait_for_interrupt() {
+        idle::halt();
+    }
+
+    fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+        idt::without_interrupts(f)
+    }
+
+    unsafe fn inb(port: u16) -> u8 {
+        io::inb(port)
+    }
+
+    unsafe fn outb(port: u16, value: u8) {
+        io::outb(port, value)
+    }
+
+    unsafe fn inw(port: u16) -> u16 {
+        io::inw(port)
+    }
+
+    unsafe fn outw(port: u16, value: u16) {
+        io::outw(port, value)
+    }
+
+    unsafe fn inl(port: u16) -> u32 {
+        io::inl(port)
+    }
+
+    unsafe fn outl(port: u16, value: u32) {
+        io::outl(port, value)
+    }
+
+    fn page_table() -> PhysAddr {
+        let cr3: u32;
+        unsafe { core::arch::asm!("mov {:e}, cr3", out(reg) cr3) };
+        PhysAddr::from_u32(cr3 & !0xFFF)
+    }
+
+    unsafe fn set_page_table(root: PhysAddr) {
+        crate::mm::paging::activate(root.as_u32());
+    }
+
+    fn flush_page(addr: VirtAddr) {
+        unsafe { core::arch::asm!("invlpg [{}]", in(reg) addr.as_usize(), options(nostack)) };
+    }
+
+    unsafe fn switch_context(old: *mut Self::Context, new: *mut Self::Context) {
+        crate::sched::Scheduler::context_switch(old, new);
+    }
+}
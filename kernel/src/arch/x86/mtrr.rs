@@ -0,0 +1,264 @@
+//! Write-Combining for Framebuffers
+//!
+//! Uncached framebuffer writes reach the bus one at a time; write-combining
+//! lets the CPU gather them into bursts, which is what makes software
+//! drawing bearable on P6-class machines. Two ways to get it:
+//!
+//! - PAT (Pentium III and later): `init` reprograms PAT entry 1 (PWT alone
+//!   in a PTE) from write-through to WC, and `mm::iomap_framebuffer` maps
+//!   with it. PAT WC beats any MTRR type, so firmware ranges don't matter.
+//! - Variable MTRRs otherwise: `set_write_combining` covers the range with
+//!   power-of-two aligned WC ranges, which upgrade the framebuffer's UC-
+//!   mapping to WC. A range that overlaps an MTRR the firmware set up is
+//!   left alone: UC overlapping WC stays UC, and other overlaps are
+//!   undefined.
+//!
+//! CPUs with neither (or MTRRs without WC) keep uncached framebuffers.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::cpu::{self, rdmsr, wrmsr};
+
+const MSR_MTRRCAP: u32 = 0xFE;
+const MSR_MTRR_PHYSBASE0: u32 = 0x200;
+const MSR_PAT: u32 = 0x277;
+const MSR_MTRR_DEF_TYPE: u32 = 0x2FF;
+
+/// MTRRCAP: variable range count
+const MTRRCAP_VCNT: u64 = 0xFF;
+/// MTRRCAP: WC type supported
+const MTRRCAP_WC: u64 = 1 << 10;
+/// DEF_TYPE: MTRRs enabled
+const DEF_TYPE_ENABLE: u64 = 1 << 11;
+/// DEF_TYPE / PHYSBASE: memory type field
+const TYPE_MASK: u64 = 0xFF;
+/// PHYSMASK: range valid
+const PHYSMASK_VALID: u64 = 1 << 11;
+
+/// Memory types (MTRR and PAT encodings agree)
+const TYPE_UC: u8 = 0;
+const TYPE_WC: u8 = 1;
+
+/// PAT entries reprogrammed to WC: 1 (PWT) and its PAT-bit twin 5
+const PAT_WC_ENTRIES: [u32; 2] = [1, 5];
+
+/// Most ranges one request may use
+const MAX_BLOCKS: usize = 8;
+
+const CR0_NW: usize = 1 << 29;
+const CR0_CD: usize = 1 << 30;
+
+/// PAT entry 1 is WC
+static PAT_WC: AtomicBool = AtomicBool::new(false);
+
+/// How a range was made write-combining
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Pat,
+    Mtrr,
+}
+
+impl Method {
+    pub fn name(self) -> &'static str {
+        match self {
+            Method::Pat => "PAT",
+            Method::Mtrr => "MTRR",
+        }
+    }
+}
+
+/// Is PAT entry 1 write-combining? (`mm::iomap_framebuffer` asks)
+pub fn pat_write_combining() -> bool {
+    PAT_WC.load(Ordering::Relaxed)
+}
+
+// =============================================================================
+// Setup
+// =============================================================================
+
+/// Set up PAT write-combining if the CPU has PAT
+///
+/// Call before anything maps a framebuffer.
+pub fn init() -> Result<Method, &'static str> {
+    if cpu::has(cpu::features::PAT) {
+        unsafe {
+            with_caches_disabled(|| {
+                let mut pat = rdmsr(MSR_PAT);
+                for entry in PAT_WC_ENTRIES {
+                    pat = (pat & !(TYPE_MASK << (entry * 8))) | (TYPE_WC as u64) << (entry * 8);
+                }
+                wrmsr(MSR_PAT, pat);
+            });
+        }
+        PAT_WC.store(true, Ordering::Relaxed);
+        return Ok(Method::Pat);
+    }
+    mtrr_wc_supported().map(|_| Method::Mtrr)
+}
+
+/// Variable MTRR count, if MTRRs with WC are available
+fn mtrr_wc_supported() -> Result<u32, &'static str> {
+    if !cpu::has(cpu::features::MTRR) {
+        return Err("CPU has no PAT or MTRRs");
+    }
+    let cap = unsafe { rdmsr(MSR_MTRRCAP) };
+    if cap & MTRRCAP_WC == 0 {
+        return Err("MTRRs don't support write-combining");
+    }
+    Ok((cap & MTRRCAP_VCNT) as u32)
+}
+
+/// Run `f` with caches off and MTRRs disabled, as MTRR/PAT changes require
+unsafe fn with_caches_disabled(f: impl FnOnce()) {
+    let has_mtrr = cpu::has(cpu::features::MTRR);
+    super::idt::without_interrupts(|| {
+        let cr0: usize;
+        core::arch::asm!("mov {}, cr0", out(reg) cr0);
+        core::arch::asm!("mov cr0, {}", "wbinvd", in(reg) (cr0 | CR0_CD) & !CR0_NW);
+        flush_tlb();
+
+        let def_type = if has_mtrr { rdmsr(MSR_MTRR_DEF_TYPE) } else { 0 };
+        if has_mtrr {
+            wrmsr(MSR_MTRR_DEF_TYPE, def_type & !DEF_TYPE_ENABLE);
+        }
+
+        f();
+
+        core::arch::asm!("wbinvd");
+        flush_tlb();
+        if has_mtrr {
+            wrmsr(MSR_MTRR_DEF_TYPE, def_type);
+        }
+        core::arch::asm!("mov cr0, {}", in(reg) cr0);
+    });
+}
+
+unsafe fn flush_tlb() {
+    core::arch::asm!("mov {tmp}, cr3", "mov cr3, {tmp}", tmp = out(reg) _);
+}
+
+// =============================================================================
+// Variable Ranges
+// =============================================================================
+
+/// A valid variable MTRR: (base, size, type)
+fn variable_range(index: u32) -> Option<(u64, u64, u8)> {
+    let (base, mask) = unsafe {
+        (rdmsr(MSR_MTRR_PHYSBASE0 + index * 2), rdmsr(MSR_MTRR_PHYSBASE0 + index * 2 + 1))
+    };
+    if mask & PHYSMASK_VALID == 0 {
+        return None;
+    }
+    let width = (1u64 << cpu::physical_address_bits()) - 1;
+    let mask = mask & width & !0xFFF;
+    let size = (!mask & width) + 1;
+    Some((base & mask, size, (base & TYPE_MASK) as u8))
+}
+
+/// Split [base, end) into power-of-two sized, size-aligned blocks
+fn split(base: u64, end: u64, blocks: &mut [(u64, u64); MAX_BLOCKS]) -> Option<usize> {
+    let mut count = 0;
+    let mut addr = base;
+    while addr < end {
+        let mut size = if addr == 0 { 1 << 32 } else { 1u64 << addr.trailing_zeros() };
+        while size > end - addr {
+            size >>= 1;
+        }
+        *blocks.get_mut(count)? = (addr, size);
+        count += 1;
+        addr += size;
+    }
+    Some(count)
+}
+
+/// Make a framebuffer range write-combining
+///
+/// With PAT the mapping already is; otherwise free variable MTRRs are
+/// programmed. Fails without changing anything if the range conflicts
+/// with an existing MTRR or there aren't enough free ones.
+pub fn set_write_combining(base: u32, len: u32) -> Result<Method, &'static str> {
+    if pat_write_combining() {
+        return Ok(Method::Pat);
+    }
+    let count = mtrr_wc_supported()?;
+
+    let start = base as u64 & !0xFFF;
+    let end = (base as u64 + len as u64 + 0xFFF) & !0xFFF;
+    let mut blocks = [(0, 0); MAX_BLOCKS];
+    let needed = split(start, end, &mut blocks).ok_or("Framebuffer needs too many MTRRs")?;
+    let blocks = &blocks[..needed];
+
+    let mut free = [0u32; MAX_BLOCKS];
+    let mut found = 0;
+    for index in 0..count {
+        match variable_range(index) {
+            Some((mtrr_base, size, kind)) => {
+                if mtrr_base < end && start < mtrr_base + size {
+                    // Already done (a previous call, or kind firmware)
+                    if kind == TYPE_WC && mtrr_base <= start && end <= mtrr_base + size {
+                        return Ok(Method::Mtrr);
+                    }
+                    return Err(if kind == TYPE_UC {
+                        "Framebuffer inside an uncacheable MTRR"
+                    } else {
+                        "Framebuffer overlaps an existing MTRR"
+                    });
+                }
+            }
+            None if found < needed => {
+                free[found] = index;
+                found += 1;
+            }
+            None => {}
+        }
+    }
+    if found < needed {
+        return Err("No free MTRRs");
+    }
+
+    let width = (1u64 << cpu::physical_address_bits()) - 1;
+    unsafe {
+        with_caches_disabled(|| {
+            for (&(block, size), &index) in blocks.iter().zip(free.iter()) {
+                wrmsr(MSR_MTRR_PHYSBASE0 + index * 2, block | TYPE_WC as u64);
+                wrmsr(MSR_MTRR_PHYSBASE0 + index * 2 + 1, (!(size - 1) & width & !0xFFF) | PHYSMASK_VALID);
+            }
+        });
+    }
+    crate::klog::write_fmt(format_args!("[MTRR] {:08X}-{:08X} write-combining ({} ranges)\n",
+        start, end - 1, needed));
+    Ok(Method::Mtrr)
+}
+
+// =============================================================================
+// Reporting
+// =============================================================================
+
+fn type_name(kind: u8) -> &'static str {
+    match kind {
+        0 => "uncachable",
+        1 => "write-combining",
+        4 => "write-through",
+        5 => "write-protect",
+        6 => "write-back",
+        _ => "reserved",
+    }
+}
+
+/// PAT state and variable MTRRs (/proc/mtrr)
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "pat {}", if pat_write_combining() { "write-combining" } else { "off" });
+    if !cpu::has(cpu::features::MTRR) {
+        let _ = writeln!(out, "no mtrrs");
+        return;
+    }
+    let (cap, def_type) = unsafe { (rdmsr(MSR_MTRRCAP), rdmsr(MSR_MTRR_DEF_TYPE)) };
+    let _ = writeln!(out, "default {} {}", type_name((def_type & TYPE_MASK) as u8),
+        if def_type & DEF_TYPE_ENABLE != 0 { "enabled" } else { "disabled" });
+    for index in 0..(cap & MTRRCAP_VCNT) as u32 {
+        if let Some((base, size, kind)) = variable_range(index) {
+            let _ = writeln!(out, "reg{:02} base {:09X} size {:>7}K {}", index, base, size >> 10, type_name(kind));
+        }
+    }
+}
@@ -2,7 +2,14 @@
 //!
 //! The 8259 PIC is used on older x86 systems to manage hardware interrupts.
 //! We remap IRQs 0-15 to interrupts 32-47 to avoid conflicts with CPU exceptions.
+//!
+//! A request that goes away before the CPU acknowledges it (line noise, a
+//! device deasserting early) is still delivered as the lowest priority
+//! IRQ of that PIC: IRQ7 or IRQ15. These spurious IRQs are told apart by
+//! the in-service register and must not get an EOI, or a real IRQ being
+//! serviced would be acknowledged early.
 
+use core::sync::atomic::{AtomicU32, Ordering};
 use super::io::{outb, inb, io_wait};
 
 // PIC ports
@@ -16,6 +23,8 @@ const ICW1_INIT: u8 = 0x10;
 const ICW1_ICW4: u8 = 0x01;
 const ICW4_8086: u8 = 0x01;
 const PIC_EOI: u8 = 0x20;
+/// OCW3: next command port read returns the in-service register
+const OCW3_READ_ISR: u8 = 0x0B;
 
 /// IRQ base for master PIC (IRQ 0-7 -> INT 32-39)
 pub const IRQ_BASE_MASTER: u8 = 32;
@@ -80,6 +89,42 @@ pub fn send_eoi(interrupt: u8) {
     }
 }
 
+/// Spurious IRQs seen, per IRQ line (only 7 and 15 can be spurious)
+static SPURIOUS: [AtomicU32; 16] = [const { AtomicU32::new(0) }; 16];
+
+/// In-service register (slave in the high byte)
+pub fn read_isr() -> u16 {
+    unsafe {
+        outb(PIC1_COMMAND, OCW3_READ_ISR);
+        outb(PIC2_COMMAND, OCW3_READ_ISR);
+        ((inb(PIC2_COMMAND) as u16) << 8) | inb(PIC1_COMMAND) as u16
+    }
+}
+
+/// Check an IRQ for being spurious before handling it
+///
+/// Returns true (and counts it) if `irq` is a spurious IRQ7 or IRQ15 that
+/// must be ignored. A spurious IRQ15 still gets an EOI on the master,
+/// which did see a real request on the cascade line.
+pub fn is_spurious(irq: u8) -> bool {
+    if irq != 7 && irq != 15 {
+        return false;
+    }
+    if read_isr() & (1 << irq) != 0 {
+        return false;
+    }
+    SPURIOUS[irq as usize].fetch_add(1, Ordering::Relaxed);
+    if irq == 15 {
+        unsafe { outb(PIC1_COMMAND, PIC_EOI) };
+    }
+    true
+}
+
+/// Spurious IRQs seen on an IRQ line
+pub fn spurious_count(irq: u8) -> u32 {
+    SPURIOUS.get(irq as usize).map_or(0, |c| c.load(Ordering::Relaxed))
+}
+
 /// Enable a specific IRQ
 pub fn enable_irq(irq: u8) {
     unsafe {
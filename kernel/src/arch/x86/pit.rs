@@ -3,7 +3,7 @@
 //! The PIT provides the system timer interrupt (IRQ 0).
 //! We use it for scheduling and timekeeping.
 
-use super::io::outb;
+use super::io::{inb, outb};
 use core::sync::atomic::{AtomicU32, Ordering};
 
 // PIT ports
@@ -69,6 +69,36 @@ pub fn uptime_ms() -> u32 {
     (ticks / hz) * 1000 + ((ticks % hz) * 1000) / hz
 }
 
+/// Get uptime in microseconds
+///
+/// Adds the part of the current tick already counted down by channel 0,
+/// so intervals shorter than a tick don't all read as zero.
+pub fn uptime_us() -> u64 {
+    let hz = unsafe { TIMER_HZ };
+    let divisor = PIT_FREQUENCY / hz;
+    loop {
+        let ticks = ticks();
+        let (out, count) = unsafe {
+            // Read-back: latch status and count of channel 0
+            outb(PIT_COMMAND, 0xC2);
+            let status = inb(PIT_CHANNEL_0);
+            let lo = inb(PIT_CHANNEL_0) as u32;
+            let hi = inb(PIT_CHANNEL_0) as u32;
+            (status & 0x80 != 0, (hi << 8) | lo)
+        };
+        // A tick landing between the two reads would pair old ticks with
+        // a fresh count
+        if ticks == self::ticks() {
+            // Mode 3 counts down by 2 twice per period; OUT is high for
+            // the first half
+            let half = divisor.saturating_sub(count) / 2;
+            let elapsed = if out { half } else { divisor / 2 + half } as u64;
+            return ticks as u64 * 1_000_000 / hz as u64
+                + elapsed * 1_000_000 / PIT_FREQUENCY as u64;
+        }
+    }
+}
+
 /// Get uptime in seconds
 pub fn uptime_secs() -> u32 {
     let ticks = TICK_COUNT.load(Ordering::Relaxed);
@@ -0,0 +1,135 @@
+//! Power Off
+//!
+//! Turns the machine off through ACPI sleep state S5: the sleep type
+//! values come from the `\_S5` package in the DSDT, and writing them with
+//! SLP_EN to the PM1 control registers powers down. ACPI mode is entered
+//! first through the SMI command port if the firmware left it off.
+//!
+//! Machines without usable ACPI fall back to the emulator power-off
+//! ports (QEMU, Bochs, VirtualBox). APM would need a real-mode BIOS call
+//! and isn't attempted. If nothing works the CPU halts with interrupts
+//! off, and the machine can be switched off by hand.
+
+use super::acpi;
+use super::io::{inb, inw, outb, outw};
+
+/// PM1 control: sleep enable
+const SLP_EN: u16 = 1 << 13;
+/// PM1 control: ACPI (SCI) mode active
+const SCI_EN: u16 = 1 << 0;
+
+/// AML opcodes met while decoding `\_S5`
+const AML_NAME_OP: u8 = 0x08;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+
+/// (port, value) pairs emulators treat as "power off"
+const EMULATOR_PORTS: [(u16, u16); 3] = [
+    (0x604, 0x2000),    // QEMU (ACPI PM on i440fx/q35)
+    (0xB004, 0x2000),   // Bochs, older QEMU
+    (0x4004, 0x3400),   // VirtualBox
+];
+
+/// Registers and sleep types needed to enter S5
+struct SleepControl {
+    pm1a: u16,
+    pm1b: u16,
+    smi_command: u16,
+    acpi_enable: u8,
+    slp_typ_a: u16,
+    slp_typ_b: u16,
+}
+
+/// Power the machine off
+///
+/// Never returns: if every method fails the CPU halts.
+pub fn power_off() -> ! {
+    crate::klog::write_str("[BOOT] Powering off\n");
+    super::ioport::stop_enforcing();
+    unsafe {
+        core::arch::asm!("cli");
+
+        if let Some(control) = sleep_control() {
+            enter_s5(&control);
+        }
+        for (port, value) in EMULATOR_PORTS {
+            outw(port, value);
+        }
+
+        crate::klog::write_str("[BOOT] Power-off failed; it is now safe to switch off\n");
+        loop {
+            core::arch::asm!("hlt");
+        }
+    }
+}
+
+/// Read the PM1 registers from the FADT and `\_S5` from the DSDT
+unsafe fn sleep_control() -> Option<SleepControl> {
+    let fadt_table = acpi::find_fadt()?;
+    let fadt = fadt_table.addr;
+    let read_u32 = |offset: usize| ((fadt + offset) as *const u32).read_unaligned();
+
+    let dsdt = acpi::map_table(read_u32(40) as usize, b"DSDT")?;
+    let (slp_typ_a, slp_typ_b) = find_s5(dsdt.addr)?;
+    Some(SleepControl {
+        pm1a: read_u32(64) as u16,
+        pm1b: read_u32(68) as u16,
+        smi_command: read_u32(48) as u16,
+        acpi_enable: *((fadt + 52) as *const u8),
+        slp_typ_a,
+        slp_typ_b,
+    })
+}
+
+/// Sleep type values from the `\_S5` package: Name(_S5, Package { a, b, ... })
+unsafe fn find_s5(dsdt: usize) -> Option<(u16, u16)> {
+    let length = ((dsdt + 4) as *const u32).read_unaligned() as usize;
+    let aml = core::slice::from_raw_parts((dsdt + 36) as *const u8, length.saturating_sub(36));
+    let at = aml.windows(4).position(|w| w == b"_S5_")?;
+
+    // NameOp directly before the name, or before a root prefix '\'
+    let name_op = at >= 1 && aml[at - 1] == AML_NAME_OP
+        || at >= 2 && aml[at - 2] == AML_NAME_OP && aml[at - 1] == b'\\';
+    if !name_op || aml.get(at + 4) != Some(&AML_PACKAGE_OP) {
+        return None;
+    }
+
+    // PkgLength: top two bits of the lead byte count the extra bytes
+    let lead = *aml.get(at + 5)?;
+    let mut i = at + 5 + 1 + (lead >> 6) as usize;
+    i += 1; // NumElements
+
+    let mut element = || -> Option<u16> {
+        let mut value = *aml.get(i)?;
+        if value == AML_BYTE_PREFIX {
+            i += 1;
+            value = *aml.get(i)?;
+        }
+        i += 1;
+        Some(value as u16 & 0x07)
+    };
+    let a = element()?;
+    let b = element()?;
+    Some((a, b))
+}
+
+/// Switch to ACPI mode if needed, then write the S5 sleep type
+unsafe fn enter_s5(control: &SleepControl) {
+    if control.pm1a == 0 {
+        return;
+    }
+    if inw(control.pm1a) & SCI_EN == 0 && control.smi_command != 0 && control.acpi_enable != 0 {
+        outb(control.smi_command, control.acpi_enable);
+        for _ in 0..1_000_000 {
+            if inw(control.pm1a) & SCI_EN != 0 {
+                break;
+            }
+            let _ = inb(0x80);
+        }
+    }
+
+    outw(control.pm1a, (control.slp_typ_a << 10) | SLP_EN);
+    if control.pm1b != 0 {
+        outw(control.pm1b, (control.slp_typ_b << 10) | SLP_EN);
+    }
+}
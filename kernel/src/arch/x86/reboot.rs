@@ -0,0 +1,143 @@
+//! Machine Reset
+//!
+//! Restarts the machine without a power cycle. Driver shutdown hooks run
+//! first through the driver EventChain, then each reset method is tried
+//! in turn until one works:
+//!
+//! 1. 8042 keyboard controller reset line pulse
+//! 2. ACPI FADT reset register (ACPI 2.0+ firmware)
+//! 3. Triple fault (load an empty IDT and raise an exception)
+
+use super::acpi;
+use super::io::{inb, outb, outl};
+
+/// 8042 status/command port
+const KBC_STATUS: u16 = 0x64;
+
+/// 8042 "pulse output line" command with only the reset bit low
+const KBC_PULSE_RESET: u8 = 0xFE;
+
+/// Busy-wait iterations between reset attempts (interrupts are off,
+/// so the PIT can't be used)
+const RESET_SETTLE_SPINS: u32 = 10_000_000;
+
+/// Reboot the machine
+///
+/// Never returns. The triple fault at the end cannot fail.
+pub fn reboot() -> ! {
+    crate::klog::write_str("[BOOT] Rebooting\n");
+
+    let failed = crate::drivers::shutdown_all_drivers();
+    if failed > 0 {
+        crate::klog::write_fmt(format_args!(
+            "[BOOT] {} driver shutdown hook(s) failed\n", failed));
+    }
+    reset()
+}
+
+/// Reset the machine without running driver shutdown hooks
+///
+/// For callers that already shut the drivers down (see `crate::shutdown`).
+pub fn reset() -> ! {
+    super::ioport::stop_enforcing();
+    unsafe {
+        core::arch::asm!("cli");
+
+        kbc_reset();
+        settle();
+
+        acpi_reset();
+        settle();
+
+        triple_fault();
+    }
+}
+
+fn settle() {
+    for _ in 0..RESET_SETTLE_SPINS {
+        unsafe { core::arch::asm!("nop"); }
+    }
+}
+
+/// Pulse the CPU reset line through the keyboard controller
+unsafe fn kbc_reset() {
+    // Wait for the input buffer to drain
+    for _ in 0..0x10000 {
+        if inb(KBC_STATUS) & 0x02 == 0 {
+            break;
+        }
+    }
+    outb(KBC_STATUS, KBC_PULSE_RESET);
+}
+
+// =============================================================================
+// ACPI Reset Register
+// =============================================================================
+
+/// FADT flag: RESET_REG is supported
+const FADT_RESET_REG_SUP: u32 = 1 << 10;
+
+/// Generic Address Structure address spaces
+const GAS_SYSTEM_MEMORY: u8 = 0;
+const GAS_SYSTEM_IO: u8 = 1;
+const GAS_PCI_CONFIG: u8 = 2;
+
+/// Write the FADT reset value to the FADT reset register, if present
+unsafe fn acpi_reset() {
+    let fadt_table = match acpi::find_fadt() {
+        Some(table) => table,
+        None => return,
+    };
+    let fadt = fadt_table.addr;
+
+    // RESET_REG needs a revision 2+ FADT (at least 129 bytes)
+    let length = (fadt as *const u32).add(1).read_unaligned();
+    if length < 129 {
+        return;
+    }
+    let flags = (fadt + 112) as *const u32;
+    if flags.read_unaligned() & FADT_RESET_REG_SUP == 0 {
+        return;
+    }
+
+    let space = *((fadt + 116) as *const u8);
+    let address = ((fadt + 120) as *const u64).read_unaligned();
+    let value = *((fadt + 128) as *const u8);
+
+    match space {
+        GAS_SYSTEM_IO => outb(address as u16, value),
+        GAS_SYSTEM_MEMORY => (address as usize as *mut u8).write_volatile(value),
+        GAS_PCI_CONFIG => {
+            // Bus 0; device in bits 32-47, function in bits 16-31, offset in 0-15
+            let device = ((address >> 32) & 0x1F) as u32;
+            let function = ((address >> 16) & 0x07) as u32;
+            let offset = (address & 0xFF) as u32;
+            outl(0xCF8, 0x8000_0000 | (device << 11) | (function << 8) | (offset & 0xFC));
+            outb(0xCFC + (offset & 3) as u16, value);
+        }
+        _ => {}
+    }
+}
+
+// =============================================================================
+// Triple Fault
+// =============================================================================
+
+/// Load a zero-length IDT and raise an exception
+///
+/// With no valid IDT the CPU can't deliver the exception or the resulting
+/// double fault, so it shuts down and the chipset resets it.
+unsafe fn triple_fault() -> ! {
+    #[repr(C, packed)]
+    struct NullIdt {
+        limit: u16,
+        base: u32,
+    }
+
+    let idt = NullIdt { limit: 0, base: 0 };
+    core::arch::asm!("lidt [{}]", "int3", in(reg) &idt);
+
+    loop {
+        core::arch::asm!("hlt");
+    }
+}
@@ -0,0 +1,113 @@
+//! CMOS Real-Time Clock
+//!
+//! Reads the battery-backed wall clock once at boot; after that the PIT
+//! keeps time (see `crate::time`). The RTC is assumed to hold UTC, as
+//! is usual outside Windows. BCD and 12-hour formats are converted, and
+//! the registers are read until two passes agree so an update in
+//! progress can't give a torn value.
+
+use super::io::{inb, outb};
+use crate::time::DateTime;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Keep NMIs disabled while selecting a register
+const NMI_DISABLE: u8 = 0x80;
+
+/// Left selected after a read (NMIs enabled again)
+const REG_STATUS_D: u8 = 0x0D;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+/// Century register (FADT default; absent on some machines)
+const REG_CENTURY: u8 = 0x32;
+
+/// Status A: update in progress
+const UIP: u8 = 0x80;
+/// Status B: values are binary, not BCD
+const BINARY: u8 = 0x04;
+/// Status B: 24-hour clock
+const HOUR_24: u8 = 0x02;
+/// PM flag in the hours register (12-hour mode)
+const HOUR_PM: u8 = 0x80;
+
+fn read_register(reg: u8) -> u8 {
+    unsafe {
+        outb(CMOS_ADDRESS, NMI_DISABLE | reg);
+        let value = inb(CMOS_DATA);
+        outb(CMOS_ADDRESS, REG_STATUS_D);
+        value
+    }
+}
+
+fn bcd(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+/// Raw registers: seconds, minutes, hours, day, month, year, century
+fn read_raw() -> [u8; 7] {
+    let mut spins = 0u32;
+    while read_register(REG_STATUS_A) & UIP != 0 && spins < 100_000 {
+        spins += 1;
+    }
+    [REG_SECONDS, REG_MINUTES, REG_HOURS, REG_DAY, REG_MONTH, REG_YEAR, REG_CENTURY]
+        .map(read_register)
+}
+
+/// Current date and time from the RTC, or None if it reads as nonsense
+pub fn read() -> Option<DateTime> {
+    let mut raw = read_raw();
+    for _ in 0..5 {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status = read_register(REG_STATUS_B);
+    let [mut second, mut minute, hour_raw, mut day, mut month, mut year, mut century] = raw;
+    let pm = hour_raw & HOUR_PM != 0;
+    let mut hour = hour_raw & !HOUR_PM;
+    if status & BINARY == 0 {
+        second = bcd(second);
+        minute = bcd(minute);
+        hour = bcd(hour);
+        day = bcd(day);
+        month = bcd(month);
+        year = bcd(year);
+        century = bcd(century);
+    }
+    if status & HOUR_24 == 0 {
+        hour = match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (h, true) => h + 12,
+            (h, false) => h,
+        };
+    }
+    let century = if (19..=30).contains(&century) {
+        century as u16
+    } else if year < 80 {
+        20
+    } else {
+        19
+    };
+
+    let time = DateTime {
+        year: century * 100 + year as u16,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    };
+    time.is_valid().then_some(time)
+}
@@ -0,0 +1,291 @@
+//! Multiprocessor Bring-up
+//!
+//! The boot CPU starts the others (application processors, APs) that the
+//! MADT or MP table lists. Each AP gets an idle task and a stack, then is
+//! sent INIT and STARTUP IPIs pointing at a trampoline copied to
+//! `TRAMPOLINE_ADDR`. The trampoline switches to protected mode with the
+//! kernel's page directory and jumps to `ap_main`, which loads the shared
+//! GDT and IDT and its own TSS, enables its local APIC, and idles in the
+//! scheduler until there is work in its run queue or another CPU's to
+//! steal.
+//!
+//! APs are started one at a time, so one parameter block in the
+//! trampoline is enough. Device interrupts and the PIT stay on CPU 0 (APs
+//! tick from their local APIC timer), and so do user programs: exec, the syscall paths and the drivers
+//! aren't SMP-safe yet, so only kernel tasks run elsewhere. `nosmp` on the
+//! command line (or safe mode) leaves the APs parked.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use super::{gdt, idle, idt, lapic, pit, topology};
+use crate::sched::{self, Priority, Task};
+
+/// Most CPUs used (the rest are left parked)
+pub const MAX_CPUS: usize = 8;
+
+/// Where the trampoline is copied: below the boot sector, unused once the
+/// kernel runs. STARTUP IPIs take it as a page number.
+const TRAMPOLINE_ADDR: usize = 0x7000;
+
+/// Stack of each AP's idle task
+const AP_STACK_SIZE: usize = 16 * 1024;
+
+/// How long an AP gets to reach `ap_main` after each STARTUP IPI
+const AP_START_TIMEOUT_MS: u32 = 20;
+
+/// APIC ID of a CPU index not in use
+const NO_CPU: u8 = 0xFF;
+
+/// Local APIC ID of each CPU index (0 = boot CPU)
+static APIC_IDS: [AtomicU8; MAX_CPUS] = [const { AtomicU8::new(NO_CPU) }; MAX_CPUS];
+
+/// CPU indices handed out
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+/// CPUs running (bit per index)
+static ONLINE: AtomicU32 = AtomicU32::new(1);
+
+/// CPU index of the AP being started
+static STARTING: AtomicUsize = AtomicUsize::new(0);
+
+/// Set by the AP being started once it runs on its own stack
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Index of the CPU we are running on
+pub fn cpu_index() -> usize {
+    // Only the boot CPU runs until an AP comes online; skip the APIC read
+    if ONLINE.load(Ordering::Relaxed) == 1 {
+        return 0;
+    }
+    let id = lapic::id();
+    APIC_IDS.iter().position(|a| a.load(Ordering::Relaxed) == id).unwrap_or(0)
+}
+
+/// CPUs running (bit per index)
+pub fn online_mask() -> u32 {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Number of CPUs running
+pub fn online_count() -> u32 {
+    online_mask().count_ones()
+}
+
+/// Ask another CPU to look at its run queue
+pub fn send_reschedule(cpu: usize) {
+    if cpu < MAX_CPUS && cpu != cpu_index() && online_mask() & (1 << cpu) != 0 {
+        let _ = lapic::send_ipi(APIC_IDS[cpu].load(Ordering::Relaxed), idt::RESCHEDULE_VECTOR as u8);
+    }
+}
+
+/// Find the other CPUs and start them (unless `start_aps` is false);
+/// returns how many came online
+///
+/// Needs the heap and interrupts on (start-up waits on the PIT).
+pub fn init(start_aps: bool) -> Result<usize, &'static str> {
+    let topology = topology::init().ok_or("No MADT or MP table")?;
+    lapic::init(topology.lapic_addr)?;
+    let bsp = lapic::id();
+    APIC_IDS[0].store(bsp, Ordering::Relaxed);
+    if let Err(e) = lapic::calibrate_timer() {
+        crate::klog!(Warning, "smp", "local timer: {}", e);
+    }
+    if !start_aps {
+        return Ok(0);
+    }
+
+    install_trampoline();
+    let mut started = 0;
+    for &apic_id in topology.cpu_ids().iter().filter(|&&id| id != bsp) {
+        match start_ap(apic_id) {
+            Ok(cpu) => {
+                started += 1;
+                crate::klog!(Info, "smp", "CPU {} (APIC {}) online", cpu, apic_id);
+            }
+            Err(e) => crate::klog!(Warning, "smp", "APIC {}: {}", apic_id, e),
+        }
+    }
+    Ok(started)
+}
+
+/// Start one AP; returns its CPU index
+fn start_ap(apic_id: u8) -> Result<usize, &'static str> {
+    let cpu = CPU_COUNT.load(Ordering::Relaxed);
+    if cpu >= MAX_CPUS {
+        return Err("Too many CPUs");
+    }
+
+    // Its idle task is the context `ap_main` runs in
+    let stack = Box::leak(vec![0u8; AP_STACK_SIZE].into_boxed_slice());
+    let stack_top = (stack.as_ptr() as usize + AP_STACK_SIZE) & !0xF;
+    let idle_task = Box::leak(Box::new(Task::new("idle", Priority::Idle)));
+    idle_task.affinity = 1 << cpu;
+    idle_task.kernel_stack = stack_top as u32;
+    unsafe { sched::start_cpu(cpu, idle_task) };
+
+    APIC_IDS[cpu].store(apic_id, Ordering::Relaxed);
+    STARTING.store(cpu, Ordering::Relaxed);
+    STARTED.store(false, Ordering::Release);
+    unsafe { set_trampoline_params(stack_top as u32) };
+
+    lapic::send_init(apic_id)?;
+    pit::delay_ms(10);
+    let page = (TRAMPOLINE_ADDR / 4096) as u8;
+    // The second STARTUP is only for CPUs that missed the first
+    for _ in 0..2 {
+        lapic::send_startup(apic_id, page)?;
+        if wait_started(AP_START_TIMEOUT_MS) {
+            CPU_COUNT.store(cpu + 1, Ordering::Release);
+            return Ok(cpu);
+        }
+    }
+    APIC_IDS[cpu].store(NO_CPU, Ordering::Relaxed);
+    Err("did not start")
+}
+
+/// Wait for the AP being started to check in
+fn wait_started(ms: u32) -> bool {
+    let ticks = (ms * pit::frequency() / 1000).max(1) + 1;
+    let start = pit::ticks();
+    while pit::ticks().wrapping_sub(start) < ticks {
+        if STARTED.load(Ordering::Acquire) {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    STARTED.load(Ordering::Acquire)
+}
+
+/// First Rust code on an AP, on its idle task's stack
+extern "C" fn ap_main() -> ! {
+    let cpu = STARTING.load(Ordering::Relaxed);
+    gdt::load();
+    idt::load();
+    let stack_top = unsafe { (*sched::idle_task(cpu)).kernel_stack };
+    gdt::init_ap_tss(cpu, stack_top);
+    lapic::enable_local();
+    let _ = lapic::start_timer(pit::frequency());
+    ONLINE.fetch_or(1 << cpu, Ordering::AcqRel);
+    STARTED.store(true, Ordering::Release);
+
+    loop {
+        sched::schedule();
+        idle::halt();
+    }
+}
+
+/// CPUs and topology (/proc/cpus)
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "online: {} of {} max", online_count(), MAX_CPUS);
+    let count = CPU_COUNT.load(Ordering::Acquire);
+    for (cpu, id) in APIC_IDS[..count].iter().enumerate() {
+        let up = online_mask() & (1 << cpu) != 0;
+        let _ = writeln!(out, "cpu{} apic {} {} ticks {}", cpu, id.load(Ordering::Relaxed),
+            if up { "online" } else { "offline" }, lapic::timer_ticks(cpu));
+    }
+    lapic::report(out);
+    topology::report(out);
+}
+
+// =============================================================================
+// Trampoline
+// =============================================================================
+
+/// Filled in before each STARTUP IPI (layout matches the trampoline)
+#[repr(C)]
+struct TrampolineParams {
+    cr4: u32,
+    cr3: u32,
+    cr0: u32,
+    stack_top: u32,
+    entry: u32,
+}
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_params: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Copy the trampoline to low memory
+fn install_trampoline() {
+    unsafe {
+        let start = core::ptr::addr_of!(ap_trampoline_start);
+        let len = core::ptr::addr_of!(ap_trampoline_end) as usize - start as usize;
+        core::ptr::copy_nonoverlapping(start, TRAMPOLINE_ADDR as *mut u8, len);
+    }
+}
+
+/// Point the trampoline at the boot CPU's paging setup and a stack
+unsafe fn set_trampoline_params(stack_top: u32) {
+    let (cr0, cr3, cr4): (u32, u32, u32);
+    core::arch::asm!("mov {:e}, cr0", out(reg) cr0);
+    core::arch::asm!("mov {:e}, cr3", out(reg) cr3);
+    core::arch::asm!("mov {:e}, cr4", out(reg) cr4);
+    let offset = core::ptr::addr_of!(ap_trampoline_params) as usize
+        - core::ptr::addr_of!(ap_trampoline_start) as usize;
+    let params = (TRAMPOLINE_ADDR + offset) as *mut TrampolineParams;
+    params.write_volatile(TrampolineParams { cr4, cr3, cr0, stack_top, entry: ap_main as *const () as u32 });
+}
+
+// Runs at TRAMPOLINE_ADDR, so addresses are the link-time offset from
+// the start plus that base
+core::arch::global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\"",
+    ".code16",
+    ".global ap_trampoline_start",
+    "ap_trampoline_start:",
+    "    cli",
+    "    cld",
+    "    xor ax, ax",
+    "    mov ds, ax",
+    "    lgdt [{base} + ap_trampoline_gdt_ptr - ap_trampoline_start]",
+    "    mov eax, cr0",
+    "    or eax, 1",
+    "    mov cr0, eax",
+    // jmp dword 0x08:ap_trampoline_32
+    "    .byte 0x66, 0xEA",
+    "    .long {base} + ap_trampoline_32 - ap_trampoline_start",
+    "    .word 0x08",
+
+    ".code32",
+    "ap_trampoline_32:",
+    "    mov ax, 0x10",
+    "    mov ds, ax",
+    "    mov es, ax",
+    "    mov fs, ax",
+    "    mov gs, ax",
+    "    mov ss, ax",
+    "    lea ebx, [{base} + ap_trampoline_params - ap_trampoline_start]",
+    "    mov eax, [ebx + 0]",      // CR4 (PSE) before paging goes on
+    "    mov cr4, eax",
+    "    mov eax, [ebx + 4]",      // Kernel page directory
+    "    mov cr3, eax",
+    "    mov eax, [ebx + 8]",      // CR0 with PG and WP
+    "    mov cr0, eax",
+    "    mov esp, [ebx + 12]",
+    "    mov eax, [ebx + 16]",
+    "    jmp eax",
+
+    // Flat code and data segments, as in the kernel GDT
+    ".balign 8",
+    "ap_trampoline_gdt:",
+    "    .quad 0",
+    "    .quad 0x00CF9A000000FFFF",
+    "    .quad 0x00CF92000000FFFF",
+    "ap_trampoline_gdt_ptr:",
+    "    .word 23",
+    "    .long {base} + ap_trampoline_gdt - ap_trampoline_start",
+
+    ".balign 4",
+    ".global ap_trampoline_params",
+    "ap_trampoline_params:",
+    "    .fill 5, 4, 0",
+    ".global ap_trampoline_end",
+    "ap_trampoline_end:",
+    ".popsection",
+    base = const TRAMPOLINE_ADDR,
+);
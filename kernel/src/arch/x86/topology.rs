@@ -0,0 +1,293 @@
+//! CPU and Interrupt Controller Topology
+//!
+//! Which processors and IO-APICs the machine has, from the ACPI MADT
+//! ("APIC") or, on machines from before ACPI described them, the Intel
+//! MultiProcessor table. Read once at boot; machines with neither are
+//! treated as a single CPU behind the 8259 PIC.
+
+use alloc::string::String;
+use core::fmt::Write;
+use super::acpi;
+use super::smp::MAX_CPUS;
+
+/// IO-APICs remembered
+pub const MAX_IOAPICS: usize = 4;
+
+/// An IO-APIC and the global system interrupts it serves
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    /// Physical address of its registers
+    pub addr: u32,
+    /// First global system interrupt (GSI) it handles
+    pub gsi_base: u32,
+}
+
+/// ISA IRQ wired to a different GSI (MADT interrupt source override)
+#[derive(Debug, Clone, Copy)]
+pub struct IrqOverride {
+    pub gsi: u32,
+    /// MPS INTI flags (polarity bits 0-1, trigger mode bits 2-3)
+    pub flags: u16,
+}
+
+/// What the firmware told us
+pub struct Topology {
+    /// Where it came from ("ACPI MADT" or "MP table")
+    pub source: &'static str,
+    /// Physical address of the local APICs
+    pub lapic_addr: u32,
+    /// Local APIC IDs of usable CPUs, boot CPU included
+    pub cpus: [u8; MAX_CPUS],
+    pub cpu_count: usize,
+    /// Enabled CPUs past `MAX_CPUS`
+    pub cpus_ignored: usize,
+    pub ioapics: [IoApic; MAX_IOAPICS],
+    pub ioapic_count: usize,
+    /// Overrides for ISA IRQs 0-15
+    pub overrides: [Option<IrqOverride>; 16],
+    /// 8259 PICs are present as well
+    pub legacy_pic: bool,
+}
+
+impl Topology {
+    const fn empty(source: &'static str) -> Self {
+        Self {
+            source,
+            lapic_addr: 0xFEE0_0000,
+            cpus: [0; MAX_CPUS],
+            cpu_count: 0,
+            cpus_ignored: 0,
+            ioapics: [IoApic { id: 0, addr: 0, gsi_base: 0 }; MAX_IOAPICS],
+            ioapic_count: 0,
+            overrides: [None; 16],
+            legacy_pic: true,
+        }
+    }
+
+    fn add_cpu(&mut self, apic_id: u8) {
+        if self.cpus[..self.cpu_count].contains(&apic_id) {
+            return;
+        }
+        if self.cpu_count < MAX_CPUS {
+            self.cpus[self.cpu_count] = apic_id;
+            self.cpu_count += 1;
+        } else {
+            self.cpus_ignored += 1;
+        }
+    }
+
+    fn add_ioapic(&mut self, ioapic: IoApic) {
+        if self.ioapic_count < MAX_IOAPICS {
+            self.ioapics[self.ioapic_count] = ioapic;
+            self.ioapic_count += 1;
+        }
+    }
+
+    /// Usable CPUs' local APIC IDs
+    pub fn cpu_ids(&self) -> &[u8] {
+        &self.cpus[..self.cpu_count]
+    }
+
+    pub fn ioapics(&self) -> &[IoApic] {
+        &self.ioapics[..self.ioapic_count]
+    }
+
+    /// GSI and flags an ISA IRQ arrives on (identity unless overridden)
+    pub fn isa_irq(&self, irq: u8) -> (u32, u16) {
+        match self.overrides.get(irq as usize).copied().flatten() {
+            Some(o) => (o.gsi, o.flags),
+            None => (irq as u32, 0),
+        }
+    }
+}
+
+/// The topology, once `init` has run (None = nothing found)
+static mut TOPOLOGY: Option<Topology> = None;
+
+/// Read the MADT, falling back to the MP table
+pub fn init() -> Option<&'static Topology> {
+    unsafe {
+        let found = parse_madt().or_else(|| parse_mp_table());
+        *core::ptr::addr_of_mut!(TOPOLOGY) = found;
+    }
+    get()
+}
+
+/// The topology read at boot
+pub fn get() -> Option<&'static Topology> {
+    unsafe { (*core::ptr::addr_of!(TOPOLOGY)).as_ref() }
+}
+
+/// Topology summary (/proc/cpus)
+pub fn report(out: &mut String) {
+    let Some(topology) = get() else {
+        let _ = writeln!(out, "source: none (single CPU)");
+        return;
+    };
+    let _ = writeln!(out, "source: {}", topology.source);
+    let _ = writeln!(out, "local APIC: {:#010x}", topology.lapic_addr);
+    let _ = write!(out, "CPU APIC IDs:");
+    for id in topology.cpu_ids() {
+        let _ = write!(out, " {}", id);
+    }
+    let _ = writeln!(out);
+    if topology.cpus_ignored > 0 {
+        let _ = writeln!(out, "ignored: {} CPUs past {}", topology.cpus_ignored, MAX_CPUS);
+    }
+    for ioapic in topology.ioapics() {
+        let _ = writeln!(out, "IO-APIC {}: {:#010x} GSI {}+", ioapic.id, ioapic.addr, ioapic.gsi_base);
+    }
+    for (irq, o) in topology.overrides.iter().enumerate() {
+        if let Some(o) = o {
+            let _ = writeln!(out, "IRQ {} -> GSI {} flags {:#x}", irq, o.gsi, o.flags);
+        }
+    }
+    let _ = writeln!(out, "8259 PIC: {}", if topology.legacy_pic { "yes" } else { "no" });
+}
+
+// =============================================================================
+// ACPI MADT
+// =============================================================================
+
+/// MADT flag: dual 8259 PICs present
+const MADT_PCAT_COMPAT: u32 = 1 << 0;
+
+/// Local APIC entry flags: enabled, or can be brought online
+const LAPIC_ENABLED: u32 = 1 << 0;
+const LAPIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+unsafe fn parse_madt() -> Option<Topology> {
+    let madt_table = acpi::find_table(b"APIC")?;
+    let madt = madt_table.addr;
+    let length = ((madt + 4) as *const u32).read_unaligned() as usize;
+    let mut topology = Topology::empty("ACPI MADT");
+    topology.lapic_addr = ((madt + 36) as *const u32).read_unaligned();
+    topology.legacy_pic = ((madt + 40) as *const u32).read_unaligned() & MADT_PCAT_COMPAT != 0;
+
+    let mut offset = 44;
+    while offset + 2 <= length {
+        let entry = madt + offset;
+        let kind = *(entry as *const u8);
+        let len = *((entry + 1) as *const u8) as usize;
+        if len < 2 || offset + len > length {
+            break;
+        }
+        match kind {
+            // Processor local APIC
+            0 if len >= 8 => {
+                let apic_id = *((entry + 3) as *const u8);
+                let flags = ((entry + 4) as *const u32).read_unaligned();
+                if flags & (LAPIC_ENABLED | LAPIC_ONLINE_CAPABLE) != 0 {
+                    topology.add_cpu(apic_id);
+                }
+            }
+            // IO-APIC
+            1 if len >= 12 => topology.add_ioapic(IoApic {
+                id: *((entry + 2) as *const u8),
+                addr: ((entry + 4) as *const u32).read_unaligned(),
+                gsi_base: ((entry + 8) as *const u32).read_unaligned(),
+            }),
+            // Interrupt source override (bus 0 = ISA)
+            2 if len >= 10 => {
+                let irq = *((entry + 3) as *const u8) as usize;
+                if irq < 16 {
+                    topology.overrides[irq] = Some(IrqOverride {
+                        gsi: ((entry + 4) as *const u32).read_unaligned(),
+                        flags: ((entry + 8) as *const u16).read_unaligned(),
+                    });
+                }
+            }
+            // 64-bit local APIC address override
+            5 if len >= 12 => {
+                let addr = ((entry + 4) as *const u64).read_unaligned();
+                if addr <= u32::MAX as u64 {
+                    topology.lapic_addr = addr as u32;
+                }
+            }
+            _ => {}
+        }
+        offset += len;
+    }
+
+    (topology.cpu_count > 0).then_some(topology)
+}
+
+// =============================================================================
+// MultiProcessor Table
+// =============================================================================
+
+/// Processor entry flag: usable
+const MP_CPU_ENABLED: u8 = 1 << 0;
+
+unsafe fn parse_mp_table() -> Option<Topology> {
+    let floating = find_mp_floating()?;
+    let table = ((floating + 4) as *const u32).read_unaligned() as usize;
+    // A zero table with a default configuration byte is not supported
+    if table == 0 || core::slice::from_raw_parts(table as *const u8, 4) != b"PCMP" {
+        return None;
+    }
+    let length = ((table + 4) as *const u16).read_unaligned() as usize;
+    if length < 44 || acpi::checksum(table, length) != 0 {
+        return None;
+    }
+
+    let mut topology = Topology::empty("MP table");
+    topology.lapic_addr = ((table + 36) as *const u32).read_unaligned();
+    let entries = ((table + 34) as *const u16).read_unaligned();
+
+    let mut offset = 44;
+    for _ in 0..entries {
+        if offset >= length {
+            break;
+        }
+        let entry = table + offset;
+        match *(entry as *const u8) {
+            // Processor
+            0 => {
+                if *((entry + 3) as *const u8) & MP_CPU_ENABLED != 0 {
+                    topology.add_cpu(*((entry + 1) as *const u8));
+                }
+                offset += 20;
+            }
+            // IO-APIC (usable flag in bit 0)
+            2 => {
+                if *((entry + 3) as *const u8) & 1 != 0 {
+                    // MP tables don't give GSI bases; IO-APICs are numbered in order
+                    let gsi_base = topology.ioapic_count as u32 * 24;
+                    topology.add_ioapic(IoApic {
+                        id: *((entry + 1) as *const u8),
+                        addr: ((entry + 4) as *const u32).read_unaligned(),
+                        gsi_base,
+                    });
+                }
+                offset += 8;
+            }
+            // Bus, IO interrupt and local interrupt entries
+            1 | 3 | 4 => offset += 8,
+            _ => break,
+        }
+    }
+
+    (topology.cpu_count > 0).then_some(topology)
+}
+
+/// Find the MP floating pointer ("_MP_") in the EBDA, the last KB of base
+/// memory, or the BIOS ROM
+unsafe fn find_mp_floating() -> Option<usize> {
+    let ebda = (*(0x40E as *const u16) as usize) << 4;
+    let areas = [(ebda, ebda + 1024), (0x9_FC00, 0xA_0000), (0xF_0000, 0x10_0000)];
+    for (start, end) in areas {
+        if start == 0 {
+            continue;
+        }
+        let mut addr = start;
+        while addr + 16 <= end {
+            if core::slice::from_raw_parts(addr as *const u8, 4) == b"_MP_" && acpi::checksum(addr, 16) == 0 {
+                return Some(addr);
+            }
+            addr += 16;
+        }
+    }
+    None
+}
@@ -0,0 +1,196 @@
+//! Timestamp Counter
+//!
+//! `rdtsc` counts CPU cycles, which makes it the cheapest high-resolution
+//! clock there is once its rate is known. `init` measures the rate against
+//! the PIT at boot and `now_ns` (`time::now_ns`) counts from the TSC from
+//! then on.
+//!
+//! The TSC only keeps time if it runs at a constant rate. CPUs that say
+//! so (invariant TSC) are trusted. Older ones, like the Armada's SpeedStep
+//! Pentium III, tick with the core clock, so the rate is measured twice at
+//! boot and checked against the PIT once a second after; if it moves,
+//! `now_ns` falls back to the PIT's microsecond clock, carrying on from
+//! where the TSC left off so time never runs backwards.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use super::{cpu, pit};
+
+/// PIT ticks each calibration run is measured over
+const CALIBRATE_TICKS: u32 = 5;
+
+/// How far a measurement may stray from the calibrated rate (parts per
+/// thousand)
+const TOLERANCE_PPT: u64 = 10;
+
+/// Once-a-second checks in a row that may fail before the TSC is dropped
+const MAX_DRIFTS: u32 = 3;
+
+/// CPUID 0x8000_0007:EDX: TSC runs at a constant rate in every P/C-state
+const INVARIANT_TSC: u32 = 1 << 8;
+
+/// Measured rate in kHz (0 = not calibrated)
+static KHZ: AtomicU32 = AtomicU32::new(0);
+
+/// `now_ns` reads the TSC
+static STABLE: AtomicBool = AtomicBool::new(false);
+
+/// CPU reports an invariant TSC
+static INVARIANT: AtomicBool = AtomicBool::new(false);
+
+/// TSC and uptime when the rate was measured
+static mut BASE_TSC: u64 = 0;
+static mut BASE_NS: u64 = 0;
+
+/// Added to the PIT clock after a fallback, so it carries on from the TSC
+static mut PIT_OFFSET_NS: u64 = 0;
+
+/// TSC at the last once-a-second check (timer IRQ only)
+static mut CHECK_TSC: u64 = 0;
+static mut DRIFTS: u32 = 0;
+
+/// Read the CPU timestamp counter
+#[inline(always)]
+pub fn read() -> u64 {
+    let lo: u32;
+    let hi: u32;
+    unsafe {
+        core::arch::asm!("rdtsc", out("eax") lo, out("edx") hi, options(nomem, nostack));
+    }
+    ((hi as u64) << 32) | lo as u64
+}
+
+/// Measure the TSC against the PIT; returns the rate in kHz
+///
+/// Needs interrupts on (it waits on PIT ticks).
+pub fn init() -> Result<u32, &'static str> {
+    if !cpu::has(cpu::features::TSC) {
+        return Err("No TSC");
+    }
+    let invariant = cpu::cpuid(0x8000_0000).0 >= 0x8000_0007
+        && cpu::cpuid(0x8000_0007).3 & INVARIANT_TSC != 0;
+    INVARIANT.store(invariant, Ordering::Relaxed);
+
+    let first = measure();
+    let second = measure();
+    let tsc = read();
+    let pit_ns = pit::uptime_us() * 1000;
+    if first == 0 || second == 0 {
+        return Err("TSC not counting");
+    }
+    if !invariant && !within_tolerance(first, second) {
+        crate::klog!(Warning, "tsc", "rate moved from {} to {} kHz, using the PIT", first, second);
+        return Err("TSC rate not constant");
+    }
+
+    unsafe {
+        BASE_TSC = tsc;
+        BASE_NS = pit_ns;
+    }
+    KHZ.store(second as u32, Ordering::Relaxed);
+    STABLE.store(true, Ordering::Release);
+    crate::klog!(Info, "tsc", "{}.{:03} MHz{}", second / 1000, second % 1000,
+        if invariant { " (invariant)" } else { "" });
+    Ok(second as u32)
+}
+
+/// Cycles per millisecond over `CALIBRATE_TICKS` whole PIT ticks
+fn measure() -> u64 {
+    let edge = pit::ticks();
+    while pit::ticks() == edge {
+        core::hint::spin_loop();
+    }
+    let start_tick = pit::ticks();
+    let start = read();
+    while pit::ticks().wrapping_sub(start_tick) < CALIBRATE_TICKS {
+        core::hint::spin_loop();
+    }
+    let cycles = read().wrapping_sub(start);
+    cycles * pit::frequency() as u64 / (CALIBRATE_TICKS as u64 * 1000)
+}
+
+fn within_tolerance(expected: u64, measured: u64) -> bool {
+    expected.abs_diff(measured) * 1000 <= expected * TOLERANCE_PPT
+}
+
+/// Is `now_ns` counting from the TSC?
+pub fn is_stable() -> bool {
+    STABLE.load(Ordering::Acquire)
+}
+
+/// Does the CPU report an invariant TSC?
+pub fn is_invariant() -> bool {
+    INVARIANT.load(Ordering::Relaxed)
+}
+
+/// Measured TSC rate in kHz (0 = not calibrated)
+pub fn khz() -> u32 {
+    KHZ.load(Ordering::Relaxed)
+}
+
+/// Cycles to nanoseconds at the measured rate
+fn cycles_to_ns(cycles: u64) -> u64 {
+    let per_sec = khz() as u64 * 1000;
+    (cycles / per_sec) * 1_000_000_000 + (cycles % per_sec) * 1_000_000_000 / per_sec
+}
+
+/// Nanoseconds since boot: the TSC when stable, otherwise the PIT (to the
+/// microsecond)
+pub fn now_ns() -> u64 {
+    if is_stable() {
+        unsafe { BASE_NS + cycles_to_ns(read().wrapping_sub(BASE_TSC)) }
+    } else {
+        pit::uptime_us() * 1000 + unsafe { PIT_OFFSET_NS }
+    }
+}
+
+/// Timer tick: compare a second of TSC cycles with a second of PIT ticks
+pub fn tick(ticks: u32) {
+    let hz = pit::frequency();
+    if is_invariant() || !is_stable() || hz == 0 || ticks % hz != 0 {
+        return;
+    }
+    let now = read();
+    let last = unsafe { core::mem::replace(&mut *core::ptr::addr_of_mut!(CHECK_TSC), now) };
+    if last == 0 {
+        return;
+    }
+    let drifts = unsafe { &mut *core::ptr::addr_of_mut!(DRIFTS) };
+    let expected = khz() as u64;
+    let measured = now.wrapping_sub(last) / 1000;
+    if within_tolerance(expected, measured) {
+        *drifts = 0;
+        return;
+    }
+    *drifts += 1;
+    if *drifts >= MAX_DRIFTS {
+        fall_back(measured);
+    }
+}
+
+/// Stop using the TSC, keeping `now_ns` from going backwards
+fn fall_back(measured_khz: u64) {
+    let tsc_ns = now_ns();
+    unsafe { PIT_OFFSET_NS = tsc_ns.saturating_sub(pit::uptime_us() * 1000) };
+    STABLE.store(false, Ordering::Release);
+    crate::klog!(Warning, "tsc", "rate moved from {} to {} kHz, using the PIT", khz(), measured_khz);
+}
+
+/// TSC state (/proc/cpuinfo)
+pub fn report(out: &mut String) {
+    let khz = khz();
+    if khz != 0 {
+        let _ = writeln!(out, "cpu MHz: {}.{:03}", khz / 1000, khz % 1000);
+    }
+    let state = if !cpu::has(cpu::features::TSC) {
+        "none"
+    } else if is_stable() && is_invariant() {
+        "invariant"
+    } else if is_stable() {
+        "stable"
+    } else {
+        "unstable (using PIT)"
+    };
+    let _ = writeln!(out, "tsc: {}", state);
+}
@@ -0,0 +1,78 @@
+//! x86_64 Context Switch
+//!
+//! A suspended kernel thread is its callee-saved registers (System V ABI)
+//! and RFLAGS; everything else was already saved by the compiler around
+//! the call to `switch`. A new thread's `rsp` points at its entry
+//! function's address, which the final `ret` jumps to.
+
+/// Saved registers of a suspended kernel thread
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Context {
+    pub rsp: u64,
+    pub rbx: u64,
+    pub rbp: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rflags: u64,
+}
+
+impl Context {
+    /// Context that starts `entry` on a fresh stack with interrupts enabled
+    ///
+    /// # Safety
+    ///
+    /// `stack_top` must be the 16-byte aligned end of a mapped stack with
+    /// room for the return address.
+    pub unsafe fn new(entry: extern "C" fn() -> !, stack_top: u64) -> Self {
+        // Leave the stack as a `call` would: misaligned by the return address
+        let rsp = stack_top - 8;
+        (rsp as *mut u64).write(entry as usize as u64);
+        Self { rsp, rflags: super::RFLAGS_IF, ..Self::default() }
+    }
+}
+
+extern "C" {
+    fn asm_context_switch_64(old: *mut Context, new: *const Context);
+}
+
+/// Save the running thread into `old` and resume `new`
+///
+/// # Safety
+///
+/// Interrupts must be disabled and `new` must have been saved by this
+/// function or built with `Context::new`.
+pub unsafe fn switch(old: *mut Context, new: *const Context) {
+    asm_context_switch_64(old, new);
+}
+
+// Offsets follow the field order of `Context`
+core::arch::global_asm!(
+    ".global asm_context_switch_64",
+    "asm_context_switch_64:",
+    // Save old thread (rdi)
+    "    mov [rdi + 0], rsp",
+    "    mov [rdi + 8], rbx",
+    "    mov [rdi + 16], rbp",
+    "    mov [rdi + 24], r12",
+    "    mov [rdi + 32], r13",
+    "    mov [rdi + 40], r14",
+    "    mov [rdi + 48], r15",
+    "    pushfq",
+    "    pop qword ptr [rdi + 56]",
+
+    // Load new thread (rsi)
+    "    mov rsp, [rsi + 0]",
+    "    mov rbx, [rsi + 8]",
+    "    mov rbp, [rsi + 16]",
+    "    mov r12, [rsi + 24]",
+    "    mov r13, [rsi + 32]",
+    "    mov r14, [rsi + 40]",
+    "    mov r15, [rsi + 48]",
+    "    push qword ptr [rsi + 56]",
+    "    popfq",
+
+    "    ret",
+);
@@ -0,0 +1,84 @@
+//! x86_64 (Long Mode) Architecture Support
+//!
+//! The `Arch` implementation for 64-bit processors: RFLAGS, CR3 and
+//! four-level page tables (`paging`), and callee-saved register context
+//! switches (`context`). Port I/O instructions are the same in long mode,
+//! so they go through `arch::x86::io` and its port registry.
+//!
+//! Built only when compiling for `x86_64`.
+
+pub mod context;
+pub mod paging;
+
+use super::x86::{idle, io};
+use super::{Arch, PhysAddr, VirtAddr};
+
+/// RFLAGS interrupt enable bit
+const RFLAGS_IF: u64 = 1 << 9;
+
+/// x86_64 implementation of `Arch`
+pub struct X86_64;
+
+impl Arch for X86_64 {
+    const NAME: &'static str = "x86_64";
+
+    type Context = context::Context;
+
+    fn interrupts_enabled() -> bool {
+        let rflags: u64;
+        unsafe { core::arch::asm!("pushfq; pop {}", out(reg) rflags) };
+        rflags & RFLAGS_IF != 0
+    }
+
+    unsafe fn enable_interrupts() {
+        core::arch::asm!("sti", options(nomem, nostack));
+    }
+
+    fn disable_interrupts() {
+        unsafe { core::arch::asm!("cli", options(nomem, nostack)) };
+    }
+
+    fn wait_for_interrupt() {
+        idle::halt();
+    }
+
+    unsafe fn inb(port: u16) -> u8 {
+        io::inb(port)
+    }
+
+    unsafe fn outb(port: u16, value: u8) {
+        io::outb(port, value)
+    }
+
+    unsafe fn inw(port: u16) -> u16 {
+        io::inw(port)
+    }
+
+    unsafe fn outw(port: u16, value: u16) {
+        io::outw(port, value)
+    }
+
+    unsafe fn inl(port: u16) -> u32 {
+        io::inl(port)
+    }
+
+    unsafe fn outl(port: u16, value: u32) {
+        io::outl(port, value)
+    }
+
+    fn page_table() -> PhysAddr {
+        paging::root()
+    }
+
+    unsafe fn set_page_table(root: PhysAddr) {
+        paging::activate(root);
+    }
+
+    fn flush_page(addr: VirtAddr) {
+        paging::invalidate(addr);
+    }
+
+    unsafe fn switch_context(old: *mut Self::Context, new: *mut Self::Context) {
+        context::switch(old, new);
+    }
+}
@@ -0,0 +1,158 @@
+//! x86_64 Page Tables
+//!
+//! Four levels (PML4, PDPT, PD, PT) of 512 eight-byte entries, each level
+//! taking 9 bits of a 48-bit virtual address. Tables are allocated from
+//! the physical memory manager and reached through the identity map, so
+//! they must come from low memory like every other kernel page.
+
+use crate::arch::{PhysAddr, VirtAddr};
+use crate::mm::pmm::{self, PageKind};
+
+pub const PTE_PRESENT: u64 = 1 << 0;
+pub const PTE_WRITABLE: u64 = 1 << 1;
+pub const PTE_USER: u64 = 1 << 2;
+pub const PTE_WRITE_THROUGH: u64 = 1 << 3;
+pub const PTE_CACHE_DISABLE: u64 = 1 << 4;
+pub const PTE_ACCESSED: u64 = 1 << 5;
+pub const PTE_DIRTY: u64 = 1 << 6;
+/// 2MB (PD) or 1GB (PDPT) page
+pub const PTE_HUGE: u64 = 1 << 7;
+pub const PTE_GLOBAL: u64 = 1 << 8;
+/// Needs EFER.NXE
+pub const PTE_NO_EXECUTE: u64 = 1 << 63;
+
+/// Physical address bits of an entry
+const FRAME_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Entries per table
+const ENTRIES: usize = 512;
+
+/// Levels of tables
+const LEVELS: usize = 4;
+
+/// Flags given to intermediate tables; the leaf entry restricts access
+const TABLE_FLAGS: u64 = PTE_PRESENT | PTE_WRITABLE | PTE_USER;
+
+/// Index into the table at `level` (3 = PML4, 0 = PT)
+fn table_index(virt: VirtAddr, level: usize) -> usize {
+    (virt.as_usize() >> (12 + 9 * level)) & (ENTRIES - 1)
+}
+
+/// Is this address canonical (bits 48-63 copies of bit 47)?
+pub fn is_canonical(virt: VirtAddr) -> bool {
+    let top = (virt.as_usize() as u64) >> 47;
+    top == 0 || top == 0x1_FFFF
+}
+
+/// Entries of the table at a physical address
+///
+/// # Safety
+///
+/// `table` must be a page table in identity-mapped memory.
+unsafe fn entries<'a>(table: PhysAddr) -> &'a mut [u64; ENTRIES] {
+    &mut *table.identity().as_mut_ptr::<[u64; ENTRIES]>()
+}
+
+/// Physical address of the active PML4
+pub fn root() -> PhysAddr {
+    let cr3: u64;
+    unsafe { core::arch::asm!("mov {}, cr3", out(reg) cr3) };
+    PhysAddr::new((cr3 & FRAME_MASK) as usize)
+}
+
+/// Switch to another PML4
+///
+/// # Safety
+///
+/// The new tables must map the kernel exactly as the current ones do.
+pub unsafe fn activate(root_table: PhysAddr) {
+    if root() != root_table {
+        core::arch::asm!("mov cr3, {}", in(reg) root_table.as_usize() as u64);
+    }
+}
+
+/// Drop the cached translation for a page
+pub fn invalidate(virt: VirtAddr) {
+    unsafe { core::arch::asm!("invlpg [{}]", in(reg) virt.as_usize(), options(nostack)) };
+}
+
+/// Allocate an empty table (a new PML4, or one level of a walk)
+pub fn alloc_table() -> Result<PhysAddr, &'static str> {
+    pmm::alloc_zeroed_page_as(PageKind::PageTable)
+        .map(PhysAddr::new)
+        .ok_or("Out of memory for page table")
+}
+
+/// Physical address a virtual address maps to
+pub fn translate(root_table: PhysAddr, virt: VirtAddr) -> Option<PhysAddr> {
+    if !is_canonical(virt) {
+        return None;
+    }
+    let mut table = root_table;
+    for level in (0..LEVELS).rev() {
+        let entry = unsafe { entries(table) }[table_index(virt, level)];
+        if entry & PTE_PRESENT == 0 {
+            return None;
+        }
+        let frame = (entry & FRAME_MASK) as usize;
+        if level == 0 || (entry & PTE_HUGE != 0 && level <= 2) {
+            // A leaf at `level` covers 4KB << (9 * level)
+            let span = 1usize << (12 + 9 * level);
+            return Some(PhysAddr::new((frame & !(span - 1)) | (virt.as_usize() & (span - 1))));
+        }
+        table = PhysAddr::new(frame);
+    }
+    None
+}
+
+/// Map one 4KB page, allocating intermediate tables as needed
+pub fn map(root_table: PhysAddr, virt: VirtAddr, phys: PhysAddr, flags: u64) -> Result<(), &'static str> {
+    if !is_canonical(virt) {
+        return Err("Non-canonical address");
+    }
+    if !virt.is_aligned(4096) || !phys.is_aligned(4096) {
+        return Err("Address not page aligned");
+    }
+    let mut table = root_table;
+    for level in (1..LEVELS).rev() {
+        let entry = &mut unsafe { entries(table) }[table_index(virt, level)];
+        if *entry & PTE_PRESENT == 0 {
+            *entry = alloc_table()?.as_usize() as u64 | TABLE_FLAGS;
+        } else if *entry & PTE_HUGE != 0 {
+            return Err("Address inside a huge page");
+        }
+        table = PhysAddr::new((*entry & FRAME_MASK) as usize);
+    }
+    let entry = &mut unsafe { entries(table) }[table_index(virt, 0)];
+    if *entry & PTE_PRESENT != 0 {
+        return Err("Page already mapped");
+    }
+    *entry = phys.as_usize() as u64 | flags | PTE_PRESENT;
+    invalidate(virt);
+    Ok(())
+}
+
+/// Unmap one 4KB page; returns the frame it mapped
+///
+/// Intermediate tables are kept even when they become empty.
+pub fn unmap(root_table: PhysAddr, virt: VirtAddr) -> Option<PhysAddr> {
+    if !is_canonical(virt) {
+        return None;
+    }
+    let mut table = root_table;
+    for level in (1..LEVELS).rev() {
+        let entry = unsafe { entries(table) }[table_index(virt, level)];
+        if entry & PTE_PRESENT == 0 || entry & PTE_HUGE != 0 {
+            return None;
+        }
+        table = PhysAddr::new((entry & FRAME_MASK) as usize);
+    }
+    let entry = &mut unsafe { entries(table) }[table_index(virt, 0)];
+    if *entry & PTE_PRESENT == 0 {
+        return None;
+    }
+    let frame = PhysAddr::new((*entry & FRAME_MASK) as usize);
+    *entry = 0;
+    invalidate(virt);
+    Some(frame)
+}
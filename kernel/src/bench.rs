@@ -0,0 +1,338 @@
+//! Micro-Benchmarks
+//!
+//! `bench` runs a fixed set of measurements so that a slowdown shows up
+//! as a number on the machine it happens on:
+//!
+//! - memcpy: `memcpy32` between heap buffers (MB/s)
+//! - fill_sw, blit_sw: software `fill_rect` and `copy_rect_from` on heap
+//!   framebuffers (kpix/s)
+//! - fill_vram: the software fill into video memory below the visible
+//!   screen, which is where write-combining pays (kpix/s)
+//! - fill_gpu, blit_gpu: fill and copy with the ATI 2D engine (kpix/s)
+//! - dispatch: in-kernel syscall dispatch of GetPid (ns)
+//!
+//! The VRAM and GPU runs need the ATI driver and enough video memory past
+//! the screen, and are skipped without them. Costs that need ring 3 (the
+//! syscall round trip and a context switch) are measured by /bin/ubench.
+//!
+//! Each benchmark repeats until `RUN_US` has passed on the PIT clock.
+//! Results are compared with the baselines in `BASELINE_PATH`
+//! (`name=value` lines, shared with ubench), which `bench save` records.
+//! Anything more than `REGRESSION_PERCENT` worse is flagged.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::arch::x86::pit;
+use crate::fs::{OpenFlags, vfs::VFS};
+use crate::gui::{Color, Framebuffer, Rect};
+use crate::syscall::{SyscallNumber, SyscallParams};
+
+/// Baselines file
+pub const BASELINE_PATH: &str = "/boot/bench.cfg";
+
+/// Largest baselines file we read
+const MAX_BASELINE_SIZE: usize = 2048;
+
+/// How long each benchmark runs
+const RUN_US: u64 = 250_000;
+
+/// Worse than the baseline by more than this is a regression
+const REGRESSION_PERCENT: i64 = 10;
+
+/// memcpy buffer size (bigger than the L2 caches of the era)
+const COPY_BYTES: usize = 512 * 1024;
+
+/// Heap framebuffer size for the software runs
+const SCRATCH_WIDTH: u32 = 640;
+const SCRATCH_HEIGHT: u32 = 480;
+
+/// Rows used below the visible screen (twice this must fit in VRAM)
+const VRAM_ROWS: u32 = 128;
+
+/// GPU operations queued between waits for the engine
+const GPU_BATCH: u32 = 16;
+
+/// Syscalls dispatched between clock reads
+const DISPATCH_BATCH: u32 = 1000;
+
+/// One benchmark result
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub name: &'static str,
+    pub value: u64,
+    pub unit: &'static str,
+    /// Rates go up when things improve, times go down
+    pub higher_is_better: bool,
+}
+
+impl Measurement {
+    fn rate(name: &'static str, value: u64, unit: &'static str) -> Self {
+        Self { name, value, unit, higher_is_better: true }
+    }
+
+    fn time(name: &'static str, value: u64, unit: &'static str) -> Self {
+        Self { name, value, unit, higher_is_better: false }
+    }
+
+    /// Improvement over `baseline` in percent (negative when worse)
+    pub fn change(&self, baseline: u64) -> i64 {
+        let (value, baseline) = (self.value as i64, baseline.max(1) as i64);
+        let diff = if self.higher_is_better { value - baseline } else { baseline - value };
+        diff * 100 / baseline
+    }
+
+    /// One report line, with the verdict against `baseline`
+    pub fn format(&self, baseline: Option<u64>, out: &mut String) {
+        let _ = write!(out, "{:<10} {:>9} {:<6}", self.name, self.value, self.unit);
+        if let Some(baseline) = baseline {
+            let change = self.change(baseline);
+            let verdict = if change < -REGRESSION_PERCENT { "REGRESSED" } else { "ok" };
+            let _ = write!(out, " base {:>9} {:+4}% {}", baseline, change, verdict);
+        }
+    }
+}
+
+// =============================================================================
+// Timing
+// =============================================================================
+
+/// Repeat `op` (which returns the units of work it did) until `RUN_US`
+/// has passed; returns (units, elapsed us)
+fn repeat(mut op: impl FnMut() -> u64) -> (u64, u64) {
+    let start = pit::uptime_us();
+    let mut units = 0;
+    loop {
+        units += op();
+        let elapsed = pit::uptime_us().saturating_sub(start);
+        if elapsed >= RUN_US {
+            return (units, elapsed);
+        }
+    }
+}
+
+fn per_second(units: u64, us: u64) -> u64 {
+    units * 1_000_000 / us.max(1)
+}
+
+/// A heap framebuffer at 32bpp
+///
+/// The framebuffer borrows `pixels`, which must outlive it.
+fn scratch(pixels: &mut [u32]) -> Framebuffer {
+    unsafe {
+        Framebuffer::new(pixels.as_mut_ptr() as *mut u8, SCRATCH_WIDTH, SCRATCH_HEIGHT, 4, SCRATCH_WIDTH * 4)
+    }
+}
+
+/// Alternate fill colors so no pass repeats the previous one
+fn color(pass: u64) -> Color {
+    if pass & 1 == 0 { Color::rgb(0x20, 0x60, 0xA0) } else { Color::rgb(0xA0, 0x60, 0x20) }
+}
+
+// =============================================================================
+// Benchmarks
+// =============================================================================
+
+fn memcpy() -> Measurement {
+    let src = vec![0x5Au8; COPY_BYTES];
+    let mut dst = vec![0u8; COPY_BYTES];
+    let (bytes, us) = repeat(|| {
+        unsafe { crate::gui::fbcon::memcpy32(dst.as_mut_ptr(), src.as_ptr(), COPY_BYTES) };
+        COPY_BYTES as u64
+    });
+    Measurement::rate("memcpy", per_second(bytes, us) >> 20, "MB/s")
+}
+
+fn fill_sw() -> Measurement {
+    let mut pixels = vec![0u32; (SCRATCH_WIDTH * SCRATCH_HEIGHT) as usize];
+    let mut fb = scratch(&mut pixels);
+    let mut pass = 0;
+    let (count, us) = repeat(|| {
+        pass += 1;
+        fb.fill_rect(0, 0, SCRATCH_WIDTH, SCRATCH_HEIGHT, color(pass));
+        (SCRATCH_WIDTH * SCRATCH_HEIGHT) as u64
+    });
+    Measurement::rate("fill_sw", per_second(count, us) / 1000, "kpix/s")
+}
+
+fn blit_sw() -> Measurement {
+    let mut src_pixels = vec![0x00406080u32; (SCRATCH_WIDTH * SCRATCH_HEIGHT) as usize];
+    let mut dst_pixels = vec![0u32; (SCRATCH_WIDTH * SCRATCH_HEIGHT) as usize];
+    let src = scratch(&mut src_pixels);
+    let mut dst = scratch(&mut dst_pixels);
+    let (count, us) = repeat(|| {
+        dst.copy_rect_from(&src, Rect::new(0, 0, SCRATCH_WIDTH, SCRATCH_HEIGHT));
+        (SCRATCH_WIDTH * SCRATCH_HEIGHT) as u64
+    });
+    Measurement::rate("blit_sw", per_second(count, us) / 1000, "kpix/s")
+}
+
+/// The ATI engine, if there is room for two `VRAM_ROWS` bands below the
+/// screen; returns it with the first spare row
+fn offscreen() -> Option<(&'static mut crate::drivers::ati_rage::AtiRage, u32)> {
+    let gpu = crate::drivers::ati_rage::get().filter(|gpu| gpu.is_initialized())?;
+    let visible = gpu.pitch() * gpu.height();
+    let spare_rows = gpu.framebuffer_size().saturating_sub(visible) / gpu.pitch().max(1);
+    if spare_rows < VRAM_ROWS * 2 {
+        return None;
+    }
+    let y = gpu.height();
+    Some((gpu, y))
+}
+
+fn fill_vram() -> Option<Measurement> {
+    let (gpu, y) = offscreen()?;
+    let bytes = gpu.bpp().div_ceil(8);
+    let base = (gpu.vram() + y * gpu.pitch()) as *mut u8;
+    let mut fb = unsafe { Framebuffer::new(base, gpu.width(), VRAM_ROWS, bytes, gpu.pitch()) };
+    let mut pass = 0;
+    let (count, us) = repeat(|| {
+        pass += 1;
+        fb.fill_rect(0, 0, gpu.width(), VRAM_ROWS, color(pass));
+        (gpu.width() * VRAM_ROWS) as u64
+    });
+    Some(Measurement::rate("fill_vram", per_second(count, us) / 1000, "kpix/s"))
+}
+
+fn fill_gpu() -> Option<Measurement> {
+    let (gpu, y) = offscreen()?;
+    let mut pass = 0;
+    let (count, us) = repeat(|| {
+        for _ in 0..GPU_BATCH {
+            pass += 1;
+            gpu.fill_rect(0, y, gpu.width(), VRAM_ROWS, color(pass).to_u32());
+        }
+        gpu.wait_for_idle();
+        (gpu.width() * VRAM_ROWS * GPU_BATCH) as u64
+    });
+    Some(Measurement::rate("fill_gpu", per_second(count, us) / 1000, "kpix/s"))
+}
+
+fn blit_gpu() -> Option<Measurement> {
+    let (gpu, y) = offscreen()?;
+    let (count, us) = repeat(|| {
+        for _ in 0..GPU_BATCH {
+            gpu.copy_rect(0, y, 0, y + VRAM_ROWS, gpu.width(), VRAM_ROWS);
+        }
+        gpu.wait_for_idle();
+        (gpu.width() * VRAM_ROWS * GPU_BATCH) as u64
+    });
+    Some(Measurement::rate("blit_gpu", per_second(count, us) / 1000, "kpix/s"))
+}
+
+fn dispatch() -> Measurement {
+    let params = SyscallParams {
+        number: SyscallNumber::GetPid,
+        arg1: 0,
+        arg2: 0,
+        arg3: 0,
+        arg4: 0,
+        arg5: 0,
+    };
+    let (calls, us) = repeat(|| {
+        for _ in 0..DISPATCH_BATCH {
+            core::hint::black_box(crate::syscall::handle_syscall(core::hint::black_box(params)));
+        }
+        DISPATCH_BATCH as u64
+    });
+    Measurement::time("dispatch", us * 1000 / calls.max(1), "ns")
+}
+
+/// Run every benchmark (takes a couple of seconds); skipped ones are
+/// returned by name
+pub fn run() -> (Vec<Measurement>, Vec<&'static str>) {
+    let mut results = vec![memcpy(), fill_sw(), blit_sw()];
+    let mut skipped = Vec::new();
+    let optional: [(&'static str, fn() -> Option<Measurement>); 3] =
+        [("fill_vram", fill_vram), ("fill_gpu", fill_gpu), ("blit_gpu", blit_gpu)];
+    for (name, bench) in optional {
+        match bench() {
+            Some(result) => results.push(result),
+            None => skipped.push(name),
+        }
+    }
+    results.push(dispatch());
+    (results, skipped)
+}
+
+// =============================================================================
+// Baselines
+// =============================================================================
+
+/// Stored baselines as (name, value); empty if there are none
+pub fn baselines() -> Vec<(String, u64)> {
+    let text = read_file().unwrap_or_default();
+    text.lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            Some((String::from(name.trim()), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Baseline for one benchmark
+pub fn baseline_of(baselines: &[(String, u64)], name: &str) -> Option<u64> {
+    baselines.iter().find(|(n, _)| n == name).map(|&(_, v)| v)
+}
+
+/// Record `results` as the new baselines, keeping those of other
+/// benchmarks (ubench's)
+pub fn save(results: &[Measurement]) -> Result<(), &'static str> {
+    let mut baselines = baselines();
+    for result in results {
+        match baselines.iter_mut().find(|(n, _)| n == result.name) {
+            Some(entry) => entry.1 = result.value,
+            None => baselines.push((String::from(result.name), result.value)),
+        }
+    }
+
+    let mut text = String::new();
+    for (name, value) in &baselines {
+        let _ = writeln!(text, "{}={}", name, value);
+    }
+    write_file(text.as_bytes())
+}
+
+fn read_file() -> Result<String, &'static str> {
+    let vfs = unsafe { &mut VFS };
+
+    let size = vfs.stat(BASELINE_PATH).map_err(|e| e.as_str())?.size as usize;
+    if size > MAX_BASELINE_SIZE {
+        return Err("Baselines file too large");
+    }
+
+    let fd = vfs.open(BASELINE_PATH, OpenFlags::read_only()).map_err(|e| e.as_str())?;
+    let mut data = vec![0u8; size];
+    let mut read = 0;
+    while read < size {
+        match vfs.read(fd, &mut data[read..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => read += n,
+        }
+    }
+    let _ = vfs.close(fd);
+    data.truncate(read);
+    String::from_utf8(data).map_err(|_| "Baselines file not text")
+}
+
+fn write_file(bytes: &[u8]) -> Result<(), &'static str> {
+    let vfs = unsafe { &mut VFS };
+
+    let flags = OpenFlags::write_only().with_create().with_truncate();
+    let fd = vfs.open(BASELINE_PATH, flags).map_err(|e| e.as_str())?;
+
+    let mut written = 0;
+    let result = loop {
+        if written == bytes.len() {
+            break Ok(());
+        }
+        match vfs.write(fd, &bytes[written..]) {
+            Ok(0) => break Err("Short write"),
+            Ok(n) => written += n,
+            Err(e) => break Err(e.as_str()),
+        }
+    };
+    let _ = vfs.close(fd);
+    result
+}
@@ -14,11 +14,21 @@
 //! 0x14    4     Screen height
 //! 0x18    4     Bits per pixel
 //! 0x1C    4     Pitch (bytes per scanline)
+//! 0x20    4     Boot flags (BOOT_FLAG_*)
+//! 0x24    4     Command line address (NUL-terminated, 0 = none)
 //! ```
 
+use crate::arch::PhysAddr;
+
 /// Magic value: 'RUST' in little-endian
 pub const BOOT_MAGIC: u32 = 0x54535552;
 
+/// Shift was held while stage2 loaded the kernel
+pub const BOOT_FLAG_SHIFT: u32 = 1 << 0;
+
+/// Longest command line stage2 passes on (excluding the NUL)
+pub const MAX_CMDLINE: usize = 63;
+
 /// Boot information passed from bootloader to kernel
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -26,11 +36,11 @@ pub struct BootInfo {
     /// Magic number ('RUST' = 0x54535552)
     pub magic: u32,
     /// Address of E820 memory map
-    pub e820_map_addr: u32,
+    pub e820_map_addr: PhysAddr,
     /// Whether VESA mode is enabled (vs VGA text)
     pub vesa_enabled: bool,
     /// Physical address of framebuffer
-    pub framebuffer_addr: u32,
+    pub framebuffer_addr: PhysAddr,
     /// Screen width in pixels (or columns for text mode)
     pub screen_width: u32,
     /// Screen height in pixels (or rows for text mode)
@@ -39,6 +49,14 @@ pub struct BootInfo {
     pub bits_per_pixel: u32,
     /// Pitch: bytes per scanline
     pub pitch: u32,
+    /// Boot flags (BOOT_FLAG_*)
+    pub flags: u32,
+    /// Address of the NUL-terminated command line (null = none)
+    pub cmdline_addr: PhysAddr,
+    /// Address of a disk image loaded alongside the kernel (null = none)
+    pub ramdisk_addr: PhysAddr,
+    /// Size of that image in bytes
+    pub ramdisk_size: usize,
 }
 
 impl BootInfo {
@@ -53,13 +71,17 @@ impl BootInfo {
         
         Self {
             magic: *data.offset(0),
-            e820_map_addr: *data.offset(1),
+            e820_map_addr: PhysAddr::from_u32(*data.offset(1)),
             vesa_enabled: *data.offset(2) != 0,
-            framebuffer_addr: *data.offset(3),
+            framebuffer_addr: PhysAddr::from_u32(*data.offset(3)),
             screen_width: *data.offset(4),
             screen_height: *data.offset(5),
             bits_per_pixel: *data.offset(6),
             pitch: *data.offset(7),
+            flags: *data.offset(8),
+            cmdline_addr: PhysAddr::from_u32(*data.offset(9)),
+            ramdisk_addr: PhysAddr::from_u32(*data.offset(10)),
+            ramdisk_size: *data.offset(11) as usize,
         }
     }
     
@@ -67,6 +89,34 @@ impl BootInfo {
     pub fn verify_magic(&self) -> bool {
         self.magic == BOOT_MAGIC
     }
+
+    /// Kernel command line (set with `make CMDLINE=...`)
+    pub fn cmdline(&self) -> &'static str {
+        if self.cmdline_addr.is_null() {
+            return "";
+        }
+        let bytes = unsafe {
+            core::slice::from_raw_parts(self.cmdline_addr.identity().as_ptr::<u8>(), MAX_CMDLINE)
+        };
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(MAX_CMDLINE);
+        core::str::from_utf8(&bytes[..len]).unwrap_or("")
+    }
+
+    /// Check for a word on the command line
+    pub fn has_option(&self, option: &str) -> bool {
+        self.cmdline().split_ascii_whitespace().any(|word| word == option)
+    }
+
+    /// Value of a `key=value` word on the command line
+    pub fn option_value(&self, key: &str) -> Option<&'static str> {
+        self.cmdline().split_ascii_whitespace()
+            .find_map(|word| word.strip_prefix(key)?.strip_prefix('='))
+    }
+
+    /// Safe mode: Shift held during boot or `safe` on the command line
+    pub fn safe_mode(&self) -> bool {
+        self.flags & BOOT_FLAG_SHIFT != 0 || self.has_option("safe")
+    }
 }
 
 /// E820 Memory Map Entry
@@ -138,10 +188,10 @@ impl E820Map {
     ///
     /// The address must point to a valid E820 map structure
     /// created by the stage2 bootloader.
-    pub unsafe fn from_addr(addr: u32) -> Self {
-        let count_ptr = addr as *const u16;
-        let count = *count_ptr as usize;
-        let entries_ptr = (addr + 4) as *const E820Entry;
+    pub unsafe fn from_addr(addr: PhysAddr) -> Self {
+        let map = addr.identity();
+        let count = *map.as_ptr::<u16>() as usize;
+        let entries_ptr = map.as_ptr::<u8>().add(4) as *const E820Entry;
         
         Self { entries_ptr, count }
     }
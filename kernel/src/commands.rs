@@ -0,0 +1,945 @@
+//! Kernel Command Registry
+//!
+//! Built-in commands that only need the kernel, not the desktop, so the
+//! GUI terminal and the text-mode fallback shell share one implementation.
+//! Each front end supplies an `Output` and handles its own UI commands
+//! (clear, theme, run...) before falling back to `execute`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// Where a command's output goes
+pub trait Output {
+    /// Print one line
+    fn print(&mut self, line: &str);
+
+    /// Usable width in characters, for commands that wrap their output
+    fn columns(&self) -> usize;
+
+    /// Lines that fit on screen, for commands that page their output
+    fn rows(&self) -> usize;
+}
+
+/// A built-in command
+pub struct Command {
+    /// First word of the command line
+    pub name: &'static str,
+    /// Shown by `help`
+    pub usage: &'static str,
+    /// Runs with the rest of the line (trimmed)
+    pub run: fn(&str, &mut dyn Output),
+}
+
+/// Registered commands
+static COMMANDS: &[Command] = &[
+    Command { name: "ls", usage: "ls [dir]", run: ls },
+    Command { name: "cd", usage: "cd [dir]", run: cd },
+    Command { name: "pwd", usage: "pwd", run: pwd },
+    Command { name: "cat", usage: "cat <file>", run: cat },
+    Command { name: "stat", usage: "stat <path>", run: stat },
+    Command { name: "hexdump", usage: "hexdump <file> [offset] [len]", run: hexdump },
+    Command { name: "readsector", usage: "readsector <devN> <lba> [offset]", run: readsector },
+    Command { name: "heap", usage: "heap", run: heap },
+    Command { name: "heapmap", usage: "heapmap", run: heapmap },
+    Command { name: "drivers", usage: "drivers", run: drivers },
+    Command { name: "irqs", usage: "irqs", run: irqs },
+    Command { name: "crashdump", usage: "crashdump [clear]", run: crashdump },
+    Command { name: "asserts", usage: "asserts [clear|kill on|off]", run: asserts },
+    Command { name: "loglevel", usage: "loglevel [module] [level|default]", run: loglevel },
+    Command { name: "trace", usage: "trace <on|off|clear|dump>", run: trace },
+    Command { name: "prof", usage: "prof <start|stop|clear|dump>", run: prof },
+    Command { name: "bench", usage: "bench [save]", run: bench },
+    Command { name: "selftest", usage: "selftest [name]", run: selftest },
+    Command { name: "vfb", usage: "vfb [checkpoint <name>|shots on|off]", run: vfb },
+    Command { name: "date", usage: "date [@unix]", run: date },
+    Command { name: "tz", usage: "tz [+HH:MM]", run: tz },
+    Command { name: "access", usage: "access [sticky|slow on|off|<ms>]", run: access },
+    Command { name: "record", usage: "record <start|stop [file]|play <file>|cancel>", run: record },
+    Command { name: "sync", usage: "sync", run: sync },
+    Command { name: "fsck", usage: "fsck [-r] [devN]", run: fsck },
+    Command { name: "blkid", usage: "blkid [-n]", run: blkid },
+    Command { name: "losetup", usage: "losetup <image>", run: losetup },
+    Command { name: "reboot", usage: "reboot", run: reboot },
+    Command { name: "shutdown", usage: "shutdown", run: shutdown },
+];
+
+/// Run a registered command; false if the name is unknown
+pub fn execute(line: &str, out: &mut dyn Output) -> bool {
+    let line = line.trim();
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+    match COMMANDS.iter().find(|c| c.name == name) {
+        Some(command) => {
+            (command.run)(args.trim(), out);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Print the usage of every registered command, wrapped to the output
+pub fn help(out: &mut dyn Output) {
+    let mut line = String::new();
+    for command in COMMANDS {
+        if !line.is_empty() && line.len() + command.usage.len() + 2 > out.columns() {
+            out.print(&line);
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push_str("  ");
+        }
+        line.push_str(command.usage);
+    }
+    if !line.is_empty() {
+        out.print(&line);
+    }
+}
+
+// =============================================================================
+// Filesystem
+// =============================================================================
+
+/// List a directory through the VFS
+fn ls(args: &str, out: &mut dyn Output) {
+    use crate::fs::{vfs::VFS, FileType};
+
+    let path = if args.is_empty() { "." } else { args };
+    match unsafe { VFS.readdir(path) } {
+        Ok(mut dir) => {
+            let mut line = String::new();
+            for entry in dir.by_ref() {
+                let name = entry.name();
+                let suffix = match entry.file_type {
+                    FileType::Directory => "/",
+                    FileType::Symlink => "@",
+                    _ => "",
+                };
+                if !line.is_empty() && line.len() + name.len() + suffix.len() + 1 > out.columns() {
+                    out.print(&line);
+                    line.clear();
+                }
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(name);
+                line.push_str(suffix);
+            }
+            if !line.is_empty() {
+                out.print(&line);
+            }
+            if let Some(e) = dir.error() {
+                out.print(e.as_str());
+            }
+        }
+        Err(e) => out.print(e.as_str()),
+    }
+}
+
+/// Change the working directory
+fn cd(args: &str, out: &mut dyn Output) {
+    let path = if args.is_empty() { "/" } else { args };
+    if let Err(e) = unsafe { crate::fs::vfs::VFS.chdir(path) } {
+        out.print(e.as_str());
+    }
+}
+
+fn pwd(_args: &str, out: &mut dyn Output) {
+    let cwd = unsafe { crate::fs::vfs::VFS.cwd() };
+    out.print(&cwd);
+}
+
+/// Print a file through the VFS (e.g. /proc/sched)
+fn cat(args: &str, out: &mut dyn Output) {
+    use crate::fs::{vfs::VFS, OpenFlags};
+
+    if args.is_empty() {
+        out.print("usage: cat <file>");
+        return;
+    }
+    let fd = match unsafe { VFS.open(args, OpenFlags::read_only()) } {
+        Ok(fd) => fd,
+        Err(e) => {
+            out.print(e.as_str());
+            return;
+        }
+    };
+
+    let mut data = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        match unsafe { VFS.read(fd, &mut buf) } {
+            Ok(0) => break,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) => {
+                out.print(e.as_str());
+                break;
+            }
+        }
+    }
+    let _ = unsafe { VFS.close(fd) };
+
+    let text = String::from_utf8_lossy(&data);
+    for line in text.lines() {
+        out.print(line);
+    }
+}
+
+/// Show a path's metadata (symlinks themselves, not their targets)
+fn stat(args: &str, out: &mut dyn Output) {
+    use crate::fs::{vfs::VFS, FileType, PermissionBits};
+
+    if args.is_empty() {
+        out.print("usage: stat <path>");
+        return;
+    }
+    let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+    let meta = match vfs.lstat(args) {
+        Ok(meta) => meta,
+        Err(e) => {
+            out.print(e.as_str());
+            return;
+        }
+    };
+
+    let kind = match meta.file_type {
+        FileType::Regular => "file",
+        FileType::Directory => "directory",
+        FileType::Symlink => "symlink",
+        FileType::BlockDevice => "block device",
+        FileType::CharDevice => "character device",
+        FileType::Pipe => "pipe",
+        FileType::Socket => "socket",
+    };
+    let mut line = String::new();
+    let _ = write!(line, "{}: {}", args, kind);
+    if meta.file_type == FileType::Symlink {
+        if let Ok(target) = vfs.readlink(args) {
+            let _ = write!(line, " -> {}", target);
+        }
+    }
+    out.print(&line);
+
+    line.clear();
+    let _ = write!(line, "Size: {} bytes", meta.size);
+    out.print(&line);
+
+    line.clear();
+    line.push_str("Mode: ");
+    for bits in [meta.permissions.owner, meta.permissions.group, meta.permissions.other] {
+        let PermissionBits { read, write, execute } = bits;
+        line.push(if read { 'r' } else { '-' });
+        line.push(if write { 'w' } else { '-' });
+        line.push(if execute { 'x' } else { '-' });
+    }
+    out.print(&line);
+
+    for (label, time) in [("Created", meta.created), ("Modified", meta.modified), ("Accessed", meta.accessed)] {
+        line.clear();
+        if time == 0 {
+            let _ = write!(line, "{}: -", label);
+        } else {
+            // Stored as UTC; shown in the configured zone like `date`
+            let local = (time.min(u32::MAX as u64) as u32).saturating_add_signed(crate::time::utc_offset() * 60);
+            let _ = write!(line, "{}: {}", label, crate::time::DateTime::from_unix(local));
+        }
+        out.print(&line);
+    }
+}
+
+// =============================================================================
+// Hex Dumps
+// =============================================================================
+
+/// Bytes per hex dump line
+const DUMP_WIDTH: usize = 16;
+
+/// Parse a decimal or 0x-prefixed hexadecimal number
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Print `data` as offset, hex and ASCII columns
+///
+/// `base` is the offset of the first byte. Runs of identical lines are
+/// collapsed into a single `*`, as most of a fresh disk is zeros.
+fn dump_lines(data: &[u8], base: u64, out: &mut dyn Output) {
+    let mut previous: Option<&[u8]> = None;
+    let mut skipping = false;
+    let mut line = String::new();
+    for (i, chunk) in data.chunks(DUMP_WIDTH).enumerate() {
+        let last = (i + 1) * DUMP_WIDTH >= data.len();
+        if !last && chunk.len() == DUMP_WIDTH && previous == Some(chunk) {
+            if !skipping {
+                out.print("*");
+                skipping = true;
+            }
+            continue;
+        }
+        skipping = false;
+        previous = Some(chunk);
+
+        line.clear();
+        let _ = write!(line, "{:08x} ", base + (i * DUMP_WIDTH) as u64);
+        for col in 0..DUMP_WIDTH {
+            match chunk.get(col) {
+                Some(byte) => { let _ = write!(line, " {:02x}", byte); }
+                None => line.push_str("   "),
+            }
+        }
+        line.push_str("  |");
+        for &byte in chunk {
+            line.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' });
+        }
+        line.push('|');
+        out.print(&line);
+    }
+}
+
+/// Bytes that fit in one page of output, leaving a line for the
+/// header or `more` hint and one for the prompt
+fn page_bytes(out: &dyn Output) -> usize {
+    out.rows().saturating_sub(2).max(1) * DUMP_WIDTH
+}
+
+/// Dump a file in hex, a page at a time
+fn hexdump(args: &str, out: &mut dyn Output) {
+    use crate::fs::{vfs::VFS, OpenFlags, SeekFrom};
+
+    let mut words = args.split_ascii_whitespace();
+    let path = words.next();
+    let offset = words.next().map_or(Some(0), parse_number);
+    let len = words.next().map_or(Some(page_bytes(out) as u64), parse_number);
+    let (Some(path), Some(offset), Some(len)) = (path, offset, len) else {
+        out.print("usage: hexdump <file> [offset] [len]");
+        return;
+    };
+
+    let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+    let fd = match vfs.open(path, OpenFlags::read_only()) {
+        Ok(fd) => fd,
+        Err(e) => {
+            out.print(e.as_str());
+            return;
+        }
+    };
+    let size = vfs.seek(fd, 0, SeekFrom::End);
+    let start = vfs.seek(fd, offset.min(i64::MAX as u64) as i64, SeekFrom::Start);
+
+    let mut data = Vec::new();
+    let mut result = size.and(start).map(|_| ());
+    let mut buf = [0u8; 256];
+    while result.is_ok() && (data.len() as u64) < len {
+        let want = (len - data.len() as u64).min(buf.len() as u64) as usize;
+        match vfs.read(fd, &mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) => result = Err(e),
+        }
+    }
+    let _ = vfs.close(fd);
+
+    dump_lines(&data, offset, out);
+    if let Err(e) = result {
+        out.print(e.as_str());
+        return;
+    }
+    // Files that report no size (/proc) are read until a short read
+    let next = offset + data.len() as u64;
+    let more = match size {
+        Ok(size) if size > 0 => next < size,
+        _ => data.len() as u64 == len,
+    };
+    if more {
+        let line = alloc::format!("-- more: hexdump {} {:#x} --", path, next);
+        out.print(&line);
+    }
+}
+
+/// Dump one block of a device in hex, read through the buffer cache
+fn readsector(args: &str, out: &mut dyn Output) {
+    use crate::fs::{bcache::BCACHE, block::DeviceId};
+
+    let mut words = args.split_ascii_whitespace();
+    let device = words.next()
+        .and_then(|w| w.strip_prefix("dev"))
+        .and_then(|n| n.parse::<u8>().ok())
+        .map(DeviceId);
+    let lba = words.next().and_then(parse_number);
+    let offset = words.next().map_or(Some(0), parse_number);
+    let (Some(id), Some(lba), Some(offset)) = (device, lba, offset) else {
+        out.print("usage: readsector <devN> <lba> [offset]");
+        return;
+    };
+
+    let cache = unsafe { &mut *core::ptr::addr_of_mut!(BCACHE) };
+    let block_size = match cache.block_size(id) {
+        Ok(size) => size,
+        Err(e) => {
+            out.print(e.as_str());
+            return;
+        }
+    };
+    if offset >= block_size as u64 {
+        let line = alloc::format!("Offset past the {}-byte block", block_size);
+        out.print(&line);
+        return;
+    }
+    let mut block = alloc::vec![0u8; block_size];
+    if let Err(e) = cache.read(id, lba, &mut block) {
+        out.print(e.as_str());
+        return;
+    }
+
+    let start = offset as usize;
+    let end = (start + page_bytes(out)).min(block_size);
+    let line = alloc::format!("dev{} block {} ({} bytes)", id.0, lba, block_size);
+    out.print(&line);
+    dump_lines(&block[start..end], start as u64, out);
+    if end < block_size {
+        let line = alloc::format!("-- more: readsector dev{} {} {:#x} --", id.0, lba, end);
+        out.print(&line);
+    }
+}
+
+fn sync(_args: &str, out: &mut dyn Output) {
+    match unsafe { crate::fs::vfs::VFS.sync() } {
+        Ok(()) => out.print("Synced"),
+        Err(e) => out.print(e.as_str()),
+    }
+}
+
+/// Check the root volume or `devN`; `-r` repairs what it can
+fn fsck(args: &str, out: &mut dyn Output) {
+    let mut repair = false;
+    let mut device = None;
+    for word in args.split_ascii_whitespace() {
+        match word {
+            "-r" => repair = true,
+            _ => match word.strip_prefix("dev").and_then(|n| n.parse::<u8>().ok()) {
+                Some(n) if device.is_none() => device = Some(crate::fs::block::DeviceId(n)),
+                _ => {
+                    out.print("Usage: fsck [-r] [devN]");
+                    return;
+                }
+            },
+        }
+    }
+    let mut report = String::new();
+    let vfs = unsafe { &mut *core::ptr::addr_of_mut!(crate::fs::vfs::VFS) };
+    let result = match device {
+        Some(id) => vfs.check_volume(id, repair, &mut report),
+        None => vfs.check_root(repair, &mut report),
+    };
+    for line in report.lines() {
+        out.print(line);
+    }
+    let mut buf = String::new();
+    match result {
+        Ok(0) => out.print("No problems found"),
+        Ok(n) => {
+            let _ = write!(buf, "{} problem(s) found", n);
+            out.print(&buf);
+        }
+        Err(e) => out.print(e.as_str()),
+    }
+}
+
+/// List volumes with their filesystem and identity; `-n` gives the root
+/// volume a new random one
+fn blkid(args: &str, out: &mut dyn Output) {
+    use crate::util::Uuid;
+    let vfs = unsafe { &mut *core::ptr::addr_of_mut!(crate::fs::vfs::VFS) };
+    let mut buf = String::new();
+    match args {
+        "" => {}
+        "-n" => {
+            match vfs.set_root_volume_id(Some(Uuid::new_v4())) {
+                Ok(()) => {
+                    let _ = write!(buf, "Root UUID: {}", vfs.root_volume_id().unwrap_or(Uuid::NIL));
+                    out.print(&buf);
+                }
+                Err(e) => out.print(e.as_str()),
+            }
+            return;
+        }
+        _ => {
+            out.print("Usage: blkid [-n]");
+            return;
+        }
+    }
+    let volumes = crate::fs::init::volumes();
+    if volumes.is_empty() {
+        out.print("No volumes");
+    }
+    for volume in volumes {
+        buf.clear();
+        let _ = write!(buf, "dev{}: {}", volume.id.0, volume.kind.map_or("unknown", |k| k.as_str()));
+        if let Some(uuid) = vfs.volume_id(volume.id) {
+            let _ = write!(buf, " UUID={}", uuid);
+        }
+        if volume.mounted {
+            buf.push_str(" (root)");
+        }
+        out.print(&buf);
+    }
+}
+
+/// Attach a disk image file as a block device
+fn losetup(args: &str, out: &mut dyn Output) {
+    use crate::fs::loopback::FileDisk;
+    if args.is_empty() {
+        out.print("Usage: losetup <image>");
+        return;
+    }
+    let disk = match FileDisk::open(args) {
+        Ok(disk) => disk,
+        Err(e) => {
+            out.print(e.as_str());
+            return;
+        }
+    };
+    let writable = disk.is_writable();
+    let disk: &'static mut FileDisk = alloc::boxed::Box::leak(alloc::boxed::Box::new(disk));
+    let mut buf = String::new();
+    match unsafe { (*core::ptr::addr_of_mut!(crate::fs::vfs::VFS)).mount_disk(disk) } {
+        Ok(volumes) => {
+            let _ = write!(buf, "Attached {}{}:", args, if writable { "" } else { " (read-only)" });
+            for id in volumes {
+                let _ = write!(buf, " dev{}", id.0);
+            }
+            out.print(&buf);
+        }
+        Err(e) => out.print(e.as_str()),
+    }
+}
+
+// =============================================================================
+// Diagnostics
+// =============================================================================
+
+fn heap(_args: &str, out: &mut dyn Output) {
+    use crate::mm::heap;
+    let stats = heap::stats();
+    let mut buf = String::new();
+    let _ = write!(buf, "Used: {} bytes", stats.used);
+    out.print(&buf);
+    buf.clear();
+    let _ = write!(buf, "Free: {} bytes (largest block {})", stats.free, stats.largest_free);
+    out.print(&buf);
+    buf.clear();
+    let _ = write!(buf, "Live: {} bytes (peak {})", stats.live, stats.peak_live);
+    out.print(&buf);
+    buf.clear();
+    let _ = write!(buf, "Fragmentation: {}% ({} bytes padding), {} failed",
+        stats.fragmentation_percent(), stats.padding, stats.failed);
+    out.print(&buf);
+
+    out.print("Size      allocs    frees     live");
+    for class in 0..heap::SIZE_CLASSES {
+        let (allocs, frees) = (stats.allocs[class], stats.frees[class]);
+        if allocs == 0 {
+            continue;
+        }
+        buf.clear();
+        let _ = match heap::class_limit(class) {
+            Some(limit) => write!(buf, "<={:<7}", limit),
+            None => write!(buf, "{:<9}", "more"),
+        };
+        let _ = write!(buf, " {:<9} {:<9} {}", allocs, frees, allocs.saturating_sub(frees));
+        out.print(&buf);
+    }
+}
+
+/// One character per heap chunk: how much of it is still live
+fn heapmap(_args: &str, out: &mut dyn Output) {
+    use crate::mm::heap::{self, Chunk, CHUNK_SIZE};
+    // "01000000 " prefix, then chunks in multiples of 16
+    let per_line = (out.columns().saturating_sub(9) / 16 * 16).max(16);
+
+    let mut map = String::new();
+    heap::map(|chunk| map.push(match chunk {
+        Chunk::Unused => '_',
+        Chunk::Used(live) if live as usize >= CHUNK_SIZE => '#',
+        Chunk::Used(live) if live as usize >= CHUNK_SIZE / 2 => '+',
+        Chunk::Used(0) => ' ',
+        Chunk::Used(_) => '.',
+    }));
+
+    let mut buf = String::new();
+    for (i, line) in map.as_bytes().chunks(per_line).enumerate() {
+        buf.clear();
+        let _ = write!(buf, "{:08X} {}", heap::start() + i * per_line * CHUNK_SIZE,
+            core::str::from_utf8(line).unwrap_or(""));
+        out.print(&buf);
+    }
+    buf.clear();
+    let _ = write!(buf, "{}KB/char: # full  + >half live  . <half live  ' ' freed  _ unused",
+        CHUNK_SIZE / 1024);
+    out.print(&buf);
+}
+
+/// Driver init report (same as /proc/drivers)
+fn drivers(_args: &str, out: &mut dyn Output) {
+    let mut text = String::new();
+    crate::drivers::report::report(&mut text);
+    for line in text.lines() {
+        out.print(line);
+    }
+}
+
+/// Interrupt counts and handlers (same as /proc/interrupts)
+fn irqs(_args: &str, out: &mut dyn Output) {
+    let mut text = String::new();
+    crate::arch::x86::idt::report(&mut text);
+    for line in text.lines() {
+        out.print(line);
+    }
+}
+
+fn crashdump(args: &str, out: &mut dyn Output) {
+    if args == "clear" {
+        crate::crashdump::clear();
+        out.print("Crash dump cleared");
+        return;
+    }
+    match crate::crashdump::last() {
+        Some(dump) => {
+            let mut buf = String::new();
+            let _ = write!(buf, "{:?} at {} ms", dump.kind(), dump.uptime_ms);
+            out.print(&buf);
+            let msg = dump.message();
+            let mut len = msg.len().min(out.columns() + 8);
+            while !msg.is_char_boundary(len) {
+                len -= 1;
+            }
+            out.print(&msg[..len]);
+            buf.clear();
+            let _ = write!(buf, "EIP {:08X} ESP {:08X}", dump.regs.eip, dump.regs.esp);
+            out.print(&buf);
+            if let Some(symbol) = crate::ksyms::symbolize(dump.regs.eip) {
+                buf.clear();
+                let _ = write!(buf, "  in {}", symbol);
+                out.print(&buf);
+            }
+            buf.clear();
+            let _ = write!(buf, "Vec {} Err {:X} CR2 {:08X}",
+                           dump.vector, dump.error_code, dump.regs.cr2);
+            out.print(&buf);
+            out.print("Call trace (scanned from the stack):");
+            for addr in crate::ksyms::return_addresses(dump.stack()) {
+                buf.clear();
+                let _ = write!(buf, "  ? {}", crate::ksyms::Addr(addr));
+                out.print(&buf);
+            }
+        }
+        None => out.print("No crash dump"),
+    }
+}
+
+/// Failed kernel assertions, and whether they end the running program
+fn asserts(args: &str, out: &mut dyn Output) {
+    match args {
+        "" => {
+            let mut report = String::new();
+            crate::kassert::report(&mut report);
+            for line in report.lines() {
+                out.print(line);
+            }
+        }
+        "clear" => {
+            crate::kassert::clear();
+            out.print("Assertion records cleared");
+        }
+        "kill on" | "kill off" => {
+            crate::kassert::set_kill_task(args == "kill on");
+            out.print(if crate::kassert::kill_task() { "Failures end the running program" } else { "Failures are only reported" });
+        }
+        _ => out.print("usage: asserts [clear|kill on|off]"),
+    }
+}
+
+/// Show or change log thresholds: `loglevel debug`, `loglevel gui debug`,
+/// `loglevel gui default`
+fn loglevel(args: &str, out: &mut dyn Output) {
+    use crate::klog::{self, Level};
+
+    let words: Vec<&str> = args.split_ascii_whitespace().collect();
+    let result = match words.as_slice() {
+        [] => {
+            let mut report = String::new();
+            klog::report(&mut report);
+            for line in report.lines() {
+                out.print(line);
+            }
+            return;
+        }
+        [level] => match Level::parse(level) {
+            Some(level) => {
+                klog::set_default_level(level);
+                Ok(())
+            }
+            None => Err("Unknown level"),
+        },
+        [module, "default"] => klog::set_module_level(module, None),
+        [module, level] => match Level::parse(level) {
+            Some(level) => klog::set_module_level(module, Some(level)),
+            None => Err("Unknown level"),
+        },
+        _ => Err("usage: loglevel [module] [level|default]"),
+    };
+    match result {
+        Ok(()) if Level::parse(words[words.len() - 1]).is_some_and(|l| l > klog::MAX_LEVEL) => {
+            let line = alloc::format!("Set (this build drops messages above {})", klog::MAX_LEVEL.as_str());
+            out.print(&line);
+        }
+        Ok(()) => out.print("Set"),
+        Err(e) => out.print(e),
+    }
+}
+
+fn trace(args: &str, out: &mut dyn Output) {
+    match args {
+        "on" => {
+            crate::trace::set_enabled(true);
+            out.print("Tracing on (cat /proc/trace)");
+        }
+        "off" => {
+            crate::trace::set_enabled(false);
+            out.print("Tracing off");
+        }
+        "clear" => {
+            crate::trace::clear();
+            out.print("Trace cleared");
+        }
+        "dump" => {
+            if crate::trace::dump_serial() {
+                out.print("Trace sent to COM1");
+            } else {
+                out.print("No serial port");
+            }
+        }
+        _ => out.print("usage: trace <on|off|clear|dump>"),
+    }
+}
+
+fn prof(args: &str, out: &mut dyn Output) {
+    match args {
+        "start" => match crate::profile::start() {
+            Ok(()) => out.print("Profiling on (prof dump, or cat /proc/profile)"),
+            Err(e) => out.print(e),
+        },
+        "stop" => {
+            crate::profile::stop();
+            out.print("Profiling off");
+        }
+        "clear" => {
+            crate::profile::clear();
+            out.print("Profile cleared");
+        }
+        "dump" => {
+            let mut text = String::new();
+            crate::profile::report(&mut text);
+            for line in text.lines() {
+                out.print(line);
+            }
+        }
+        _ => out.print("usage: prof <start|stop|clear|dump>"),
+    }
+}
+
+/// Run the micro-benchmarks against the stored baselines
+fn bench(args: &str, out: &mut dyn Output) {
+    let save = match args {
+        "" => false,
+        "save" => true,
+        _ => return out.print("usage: bench [save]"),
+    };
+
+    let (results, skipped) = crate::bench::run();
+    let baselines = crate::bench::baselines();
+    let mut line = String::new();
+    for result in &results {
+        line.clear();
+        result.format(crate::bench::baseline_of(&baselines, result.name), &mut line);
+        out.print(&line);
+    }
+    if !skipped.is_empty() {
+        line.clear();
+        let _ = write!(line, "Skipped (no GPU or VRAM): {}", skipped.join(" "));
+        out.print(&line);
+    }
+    out.print("Syscall and context switch times: run /bin/ubench");
+
+    if save {
+        match crate::bench::save(&results) {
+            Ok(()) => out.print("Baselines saved"),
+            Err(e) => out.print(e),
+        }
+    }
+}
+
+/// Run the kernel self-tests (those whose name starts with the argument)
+fn selftest(args: &str, out: &mut dyn Output) {
+    let mut line = String::new();
+    let (passed, failed) = crate::selftest::run(args, |name, outcome| {
+        line.clear();
+        let _ = match outcome {
+            Ok(()) => write!(line, "{:<24} ok", name),
+            Err(e) => write!(line, "{:<24} FAILED: {}", name, e),
+        };
+        out.print(&line);
+    });
+    line.clear();
+    let _ = write!(line, "{} passed, {} failed", passed, failed);
+    out.print(&line);
+}
+
+/// Virtual framebuffer status, or frame checkpoints on the serial port
+fn vfb(args: &str, out: &mut dyn Output) {
+    use crate::drivers::vfb;
+
+    let (action, value) = args.split_once(' ').unwrap_or((args, ""));
+    match (action, value.trim()) {
+        ("", _) => {
+            let display = match vfb::size() {
+                Some((width, height)) => alloc::format!("virtual framebuffer {}x{}", width, height),
+                None => String::from("hardware display"),
+            };
+            out.print(&alloc::format!("{}, screenshots {}", display, if vfb::screenshots() { "on" } else { "off" }));
+        }
+        ("checkpoint", name) if !name.is_empty() && !name.contains(' ') => {
+            match vfb::request_checkpoint(name) {
+                Ok(()) => out.print("Checkpoint after the next frame (on COM1)"),
+                Err(e) => out.print(e),
+            }
+        }
+        ("shots", "on") => vfb::set_screenshots(true),
+        ("shots", "off") => vfb::set_screenshots(false),
+        _ => out.print("usage: vfb [checkpoint <name>|shots on|off]"),
+    }
+}
+
+// =============================================================================
+// Time
+// =============================================================================
+
+fn date(args: &str, out: &mut dyn Output) {
+    if let Some(unix) = args.strip_prefix('@') {
+        match unix.trim().parse::<u32>() {
+            Ok(unix) => {
+                crate::time::set_realtime(unix);
+                out.print("Clock set");
+            }
+            Err(_) => out.print("usage: date @<unix seconds>"),
+        }
+        return;
+    }
+    let line = alloc::format!("{} (UTC{})", crate::time::local_now(),
+        crate::time::format_utc_offset(crate::time::utc_offset()));
+    out.print(&line);
+}
+
+fn tz(args: &str, out: &mut dyn Output) {
+    if args.is_empty() {
+        let line = alloc::format!("UTC{}", crate::time::format_utc_offset(crate::time::utc_offset()));
+        out.print(&line);
+        return;
+    }
+    let result = crate::time::parse_utc_offset(args)
+        .ok_or("usage: tz <+HH:MM>")
+        .and_then(crate::settings::set_utc_offset);
+    match result {
+        Ok(()) => out.print("Time zone saved"),
+        Err(e) => out.print(e),
+    }
+}
+
+// =============================================================================
+// Input
+// =============================================================================
+
+/// Show or change keyboard accessibility (sticky and slow keys)
+fn access(args: &str, out: &mut dyn Output) {
+    use crate::drivers::accessibility;
+
+    let (feature, value) = args.split_once(' ').unwrap_or((args, ""));
+    let result = match (feature, value.trim()) {
+        ("", _) => {
+            let settings = crate::settings::get();
+            out.print(&alloc::format!("sticky keys: {}", if settings.sticky_keys { "on" } else { "off" }));
+            out.print(&alloc::format!("slow keys: {} ({} ms)",
+                if settings.slow_keys { "on" } else { "off" }, settings.slow_keys_ms));
+            out.print("Hotkeys: Shift x5 = sticky, hold Shift 8s = slow");
+            return;
+        }
+        ("sticky", "on") => crate::settings::set_sticky_keys(true),
+        ("sticky", "off") => crate::settings::set_sticky_keys(false),
+        ("slow", "on") => crate::settings::set_slow_keys(true),
+        ("slow", "off") => crate::settings::set_slow_keys(false),
+        ("slow", ms) => match ms.parse::<u32>() {
+            Ok(ms) => crate::settings::set_slow_keys_ms(ms)
+                .and_then(|_| crate::settings::set_slow_keys(true)),
+            Err(_) => Err("usage: access [sticky|slow on|off|<ms>]"),
+        },
+        _ => Err("usage: access [sticky|slow on|off|<ms>]"),
+    };
+    match result {
+        Ok(()) => out.print(&alloc::format!("sticky {}, slow {} ms",
+            if accessibility::sticky_keys() { "on" } else { "off" }, accessibility::slow_keys())),
+        Err(e) => out.print(e),
+    }
+}
+
+/// Record GUI input, or play a recording back
+fn record(args: &str, out: &mut dyn Output) {
+    use crate::gui::recorder;
+
+    let (action, file) = args.split_once(' ').unwrap_or((args, ""));
+    match (action, file.trim()) {
+        ("start", "") => match recorder::start_recording(crate::arch::x86::pit::uptime_ms()) {
+            Ok(()) => out.print("Recording input (record stop [file] to end)"),
+            Err(e) => out.print(e),
+        },
+        ("stop", file) => {
+            let Some(steps) = recorder::stop_recording() else {
+                return out.print("Not recording");
+            };
+            if file.is_empty() {
+                for line in recorder::format(&steps).lines() {
+                    out.print(line);
+                }
+                return;
+            }
+            match recorder::save(file, &steps) {
+                Ok(()) => out.print(&alloc::format!("{} inputs saved to {}", steps.len(), file)),
+                Err(e) => out.print(e),
+            }
+        }
+        ("play", file) if !file.is_empty() => match recorder::play_source(file) {
+            Ok(count) => out.print(&alloc::format!("Replaying {} inputs", count)),
+            Err(e) => out.print(e),
+        },
+        ("cancel", "") => {
+            let stopped = recorder::stop_recording().is_some() | recorder::stop_playback();
+            out.print(if stopped { "Cancelled" } else { "Nothing to cancel" });
+        }
+        _ => out.print("usage: record <start|stop [file]|play <file>|cancel>"),
+    }
+}
+
+// =============================================================================
+// Power
+// =============================================================================
+
+fn reboot(_args: &str, out: &mut dyn Output) {
+    out.print("Rebooting...");
+    crate::shutdown::request(crate::shutdown::Action::Reboot);
+}
+
+fn shutdown(_args: &str, out: &mut dyn Output) {
+    out.print("Shutting down...");
+    crate::shutdown::request(crate::shutdown::Action::PowerOff);
+}
@@ -0,0 +1,296 @@
+//! Crash Dumps
+//!
+//! On a kernel panic or fatal CPU exception, the panic message, registers,
+//! a window of stack bytes and the kernel log tail are captured into a
+//! reserved RAM area and streamed over COM1 in a framed hex format.
+//!
+//! The RAM area is excluded from the page allocator and is not cleared on
+//! boot, so after a warm reboot the `crashdump` terminal command can show
+//! what happened. The serial stream is for machines where RAM does not
+//! survive the reset:
+//!
+//! ```text
+//! === CRASHDUMP BEGIN v1 <length> ===
+//! <64 hex digits per line>
+//! === CRASHDUMP END <fnv1a-32> ===
+//! ```
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::x86::idt::InterruptFrame;
+use crate::drivers::serial::SERIAL;
+
+/// Physical address of the dump area (just past the 16-20MB kernel heap)
+pub const CRASH_AREA: usize = 0x0140_0000;
+
+/// Size of the dump area (whole pages)
+pub const CRASH_AREA_SIZE: usize = 4 * 4096;
+
+/// "CRSH"
+const CRASH_MAGIC: u32 = 0x4853_5243;
+
+/// Dump format version
+const CRASH_VERSION: u32 = 1;
+
+/// Captured panic message size
+pub const MESSAGE_SIZE: usize = 256;
+
+/// Captured stack window size
+pub const STACK_SIZE: usize = 512;
+
+/// Captured kernel log tail size
+pub const KLOG_TAIL_SIZE: usize = 2048;
+
+/// What kind of crash produced the dump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum CrashKind {
+    Panic = 1,
+    Exception = 2,
+}
+
+/// Register snapshot
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct CrashRegs {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub esp: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub cs: u32,
+    pub cr2: u32,
+}
+
+/// On-RAM dump layout
+#[repr(C)]
+pub struct CrashDump {
+    magic: u32,
+    version: u32,
+    /// FNV-1a over everything after this field
+    checksum: u32,
+    kind: u32,
+    /// Exception vector (0 for panics)
+    pub vector: u32,
+    pub error_code: u32,
+    pub uptime_ms: u32,
+    pub regs: CrashRegs,
+    message_len: u32,
+    message: [u8; MESSAGE_SIZE],
+    /// Address the stack window was captured from
+    pub stack_base: u32,
+    stack_len: u32,
+    stack: [u8; STACK_SIZE],
+    klog_len: u32,
+    klog: [u8; KLOG_TAIL_SIZE],
+}
+
+const _: () = assert!(core::mem::size_of::<CrashDump>() <= CRASH_AREA_SIZE);
+
+/// Offset of the first checksummed byte
+const CHECKSUM_START: usize = 12;
+
+impl CrashDump {
+    /// Crash kind
+    pub fn kind(&self) -> CrashKind {
+        if self.kind == CrashKind::Exception as u32 {
+            CrashKind::Exception
+        } else {
+            CrashKind::Panic
+        }
+    }
+
+    /// Panic message or exception name
+    pub fn message(&self) -> &str {
+        let len = (self.message_len as usize).min(MESSAGE_SIZE);
+        core::str::from_utf8(&self.message[..len]).unwrap_or("???")
+    }
+
+    /// Captured stack bytes
+    pub fn stack(&self) -> &[u8] {
+        &self.stack[..(self.stack_len as usize).min(STACK_SIZE)]
+    }
+
+    /// Captured kernel log tail
+    pub fn klog(&self) -> &str {
+        let len = (self.klog_len as usize).min(KLOG_TAIL_SIZE);
+        core::str::from_utf8(&self.klog[..len]).unwrap_or("???")
+    }
+
+    /// Raw bytes of the dump
+    fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self as *const Self as *const u8,
+                core::mem::size_of::<Self>(),
+            )
+        }
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        fnv1a(&self.as_bytes()[CHECKSUM_START..])
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == CRASH_MAGIC
+            && self.version == CRASH_VERSION
+            && self.checksum == self.compute_checksum()
+    }
+}
+
+fn fnv1a(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811C_9DC5;
+    for &b in data {
+        hash ^= b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+/// Set while a dump is being written (guards against recursive crashes)
+static DUMPING: AtomicBool = AtomicBool::new(false);
+
+/// Is this physical page part of the dump area?
+pub fn is_reserved_page(page_idx: usize) -> bool {
+    let addr = page_idx * 4096;
+    addr >= CRASH_AREA && addr < CRASH_AREA + CRASH_AREA_SIZE
+}
+
+fn area() -> &'static mut CrashDump {
+    unsafe { &mut *(CRASH_AREA as *mut CrashDump) }
+}
+
+/// Get the dump left by the previous crash, if any
+pub fn last() -> Option<&'static CrashDump> {
+    let dump = area();
+    if dump.is_valid() {
+        Some(dump)
+    } else {
+        None
+    }
+}
+
+/// Discard the stored dump
+pub fn clear() {
+    area().magic = 0;
+}
+
+/// Fixed-buffer writer for formatting the panic message
+struct MessageWriter<'a> {
+    buf: &'a mut [u8; MESSAGE_SIZE],
+    len: usize,
+}
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &b in s.as_bytes() {
+            if self.len >= MESSAGE_SIZE {
+                break;
+            }
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Fill in everything except the crash-specific fields and write it out
+fn finish(dump: &mut CrashDump, kind: CrashKind, stack_base: u32) {
+    dump.magic = CRASH_MAGIC;
+    dump.version = CRASH_VERSION;
+    dump.kind = kind as u32;
+    dump.uptime_ms = crate::arch::x86::pit::uptime_ms();
+
+    dump.stack_base = stack_base;
+    for i in 0..STACK_SIZE {
+        dump.stack[i] = unsafe { ((stack_base as usize + i) as *const u8).read_volatile() };
+    }
+    dump.stack_len = STACK_SIZE as u32;
+
+    dump.klog_len = crate::klog::tail(&mut dump.klog) as u32;
+    dump.checksum = dump.compute_checksum();
+
+    stream(dump);
+}
+
+/// Record a kernel panic
+pub fn record_panic(info: &core::panic::PanicInfo) {
+    if DUMPING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let dump = area();
+    let mut msg = MessageWriter { buf: &mut dump.message, len: 0 };
+    let _ = write!(msg, "{}", info);
+    dump.message_len = msg.len as u32;
+
+    let (esp, ebp): (u32, u32);
+    unsafe {
+        core::arch::asm!("mov {}, esp", out(reg) esp);
+        core::arch::asm!("mov {}, ebp", out(reg) ebp);
+    }
+    dump.regs = CrashRegs { esp, ebp, ..CrashRegs::default() };
+    dump.vector = 0;
+    dump.error_code = 0;
+
+    finish(dump, CrashKind::Panic, esp);
+}
+
+/// Record a fatal CPU exception
+pub fn record_exception(name: &str, frame: &InterruptFrame, cr2: u32) {
+    if DUMPING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let dump = area();
+    let len = name.len().min(MESSAGE_SIZE);
+    dump.message[..len].copy_from_slice(&name.as_bytes()[..len]);
+    dump.message_len = len as u32;
+
+    // Kernel-mode exceptions don't push SS:ESP; the interrupted stack
+    // starts where user_esp would be
+    let stack_base = core::ptr::addr_of!(frame.user_esp) as u32;
+
+    dump.regs = CrashRegs {
+        eax: frame.eax,
+        ebx: frame.ebx,
+        ecx: frame.ecx,
+        edx: frame.edx,
+        esi: frame.esi,
+        edi: frame.edi,
+        ebp: frame.ebp,
+        esp: stack_base,
+        eip: frame.eip,
+        eflags: frame.eflags,
+        cs: frame.cs,
+        cr2,
+    };
+    dump.vector = frame.interrupt_number;
+    dump.error_code = frame.error_code;
+
+    finish(dump, CrashKind::Exception, stack_base);
+}
+
+/// Stream a dump over COM1 in the framed hex format
+fn stream(dump: &CrashDump) {
+    let serial = unsafe { &mut SERIAL };
+    if !serial.is_present() {
+        return;
+    }
+
+    let bytes = dump.as_bytes();
+    let _ = writeln!(serial, "\n=== CRASHDUMP BEGIN v{} {} ===", CRASH_VERSION, bytes.len());
+    for line in bytes.chunks(32) {
+        for b in line {
+            let _ = write!(serial, "{:02x}", b);
+        }
+        let _ = writeln!(serial);
+    }
+    let _ = writeln!(serial, "=== CRASHDUMP END {:08x} ===", fnv1a(bytes));
+}
@@ -0,0 +1,237 @@
+//! Keyboard Accessibility
+//!
+//! Optional transforms applied by the keyboard driver before keys are
+//! buffered:
+//!
+//! - Sticky keys: tapping Shift, Ctrl or Alt on its own latches it for the
+//!   next key, so chords can be typed one key at a time. Tapping a latched
+//!   modifier again releases it.
+//! - Slow keys: a key only counts once it has been held for a set time,
+//!   so brushing against keys (or a bouncy old keyboard) types nothing.
+//!
+//! Both follow the usual hotkeys: Shift tapped five times toggles sticky
+//! keys, Shift held for eight seconds toggles slow keys. The filter runs in
+//! the keyboard IRQ, so a hotkey only queues the change; `poll` in the main
+//! loop saves it to the settings file and tells the user.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use super::keyboard::KeyCode;
+
+/// Modifier bits (for latching)
+pub const MOD_SHIFT: u8 = 1 << 0;
+pub const MOD_CTRL: u8 = 1 << 1;
+pub const MOD_ALT: u8 = 1 << 2;
+
+/// Slow keys delay used when the hotkey turns it on without a setting
+pub const DEFAULT_SLOW_KEYS_MS: u32 = 500;
+
+/// Allowed slow keys delays
+pub const MIN_SLOW_KEYS_MS: u32 = 100;
+pub const MAX_SLOW_KEYS_MS: u32 = 2000;
+
+/// Shift taps that toggle sticky keys
+const STICKY_HOTKEY_TAPS: u8 = 5;
+
+/// Longest gap between those taps (ms)
+const STICKY_HOTKEY_GAP_MS: u32 = 1000;
+
+/// Shift hold that toggles slow keys (ms)
+const SLOW_HOTKEY_HOLD_MS: u32 = 8000;
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+static STICKY_KEYS: AtomicBool = AtomicBool::new(false);
+
+/// Slow keys delay in ms (0 = off)
+static SLOW_KEYS_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Turn sticky keys on or off
+pub fn set_sticky_keys(on: bool) {
+    STICKY_KEYS.store(on, Ordering::Relaxed);
+}
+
+/// Set the slow keys delay (0 turns it off)
+pub fn set_slow_keys(ms: u32) {
+    SLOW_KEYS_MS.store(ms, Ordering::Relaxed);
+}
+
+pub fn sticky_keys() -> bool {
+    STICKY_KEYS.load(Ordering::Relaxed)
+}
+
+/// Slow keys delay in ms (0 = off)
+pub fn slow_keys() -> u32 {
+    SLOW_KEYS_MS.load(Ordering::Relaxed)
+}
+
+// =============================================================================
+// Hotkey Requests
+// =============================================================================
+
+const TOGGLE_STICKY: u8 = 1 << 0;
+const TOGGLE_SLOW: u8 = 1 << 1;
+
+/// Toggles requested from the IRQ, applied by `poll`
+static REQUESTS: AtomicU8 = AtomicU8::new(0);
+
+/// Carry out hotkey toggles (main loop)
+pub fn poll() {
+    let requests = REQUESTS.swap(0, Ordering::AcqRel);
+    if requests & TOGGLE_STICKY != 0 {
+        let on = !sticky_keys();
+        announce(crate::settings::set_sticky_keys(on), if on { "Sticky keys on" } else { "Sticky keys off" });
+    }
+    if requests & TOGGLE_SLOW != 0 {
+        let on = slow_keys() == 0;
+        announce(crate::settings::set_slow_keys(on), if on { "Slow keys on" } else { "Slow keys off" });
+    }
+}
+
+fn announce(saved: Result<(), &'static str>, message: &str) {
+    crate::klog::write_fmt(format_args!("[KBD ] {}\n", message));
+    crate::gui::notify::info(message);
+    if let Err(e) = saved {
+        crate::gui::notify::warn(e);
+    }
+}
+
+// =============================================================================
+// Filter
+// =============================================================================
+
+/// A key held back by slow keys
+#[derive(Clone, Copy)]
+struct SlowKey {
+    keycode: KeyCode,
+    since_ms: u32,
+    accepted: bool,
+}
+
+/// Per-keyboard filter state
+pub struct AccessFilter {
+    /// Modifiers latched by sticky keys
+    latched: u8,
+    /// Modifiers currently held
+    held: u8,
+    /// Another key was pressed while a modifier was held
+    chorded: bool,
+    /// Key being timed by slow keys
+    slow: Option<SlowKey>,
+    /// Consecutive Shift taps (sticky keys hotkey)
+    shift_taps: u8,
+    last_tap_ms: u32,
+    /// When Shift went down (slow keys hotkey)
+    shift_down_ms: u32,
+}
+
+impl AccessFilter {
+    pub const fn new() -> Self {
+        Self {
+            latched: 0,
+            held: 0,
+            chorded: false,
+            slow: None,
+            shift_taps: 0,
+            last_tap_ms: 0,
+            shift_down_ms: 0,
+        }
+    }
+
+    /// Modifiers latched for the next key
+    pub fn latched(&self) -> u8 {
+        self.latched
+    }
+
+    /// Track a modifier press or release (typematic repeats included)
+    pub fn modifier(&mut self, bit: u8, released: bool, now_ms: u32) {
+        if !released {
+            if self.held & bit == 0 {
+                self.held |= bit;
+                if self.held == bit {
+                    self.chorded = false;
+                }
+                if bit == MOD_SHIFT {
+                    self.shift_down_ms = now_ms;
+                }
+            }
+            return;
+        }
+
+        self.held &= !bit;
+        let tapped = !self.chorded;
+
+        if bit == MOD_SHIFT {
+            self.shift_hotkeys(tapped, now_ms);
+        }
+        if tapped && sticky_keys() {
+            self.latched ^= bit;
+        }
+    }
+
+    /// Filter a non-modifier key; returns the key to deliver, if any
+    ///
+    /// A press held back by slow keys is delivered by a later typematic
+    /// repeat or its release, once it has been down long enough.
+    pub fn key(&mut self, keycode: KeyCode, released: bool, now_ms: u32) -> Option<KeyCode> {
+        self.chorded = true;
+        self.shift_taps = 0;
+
+        let delay = slow_keys();
+        if delay == 0 {
+            self.slow = None;
+            return (!released).then_some(keycode);
+        }
+
+        match self.slow {
+            Some(mut slow) if slow.keycode == keycode => {
+                let due = !slow.accepted && now_ms.wrapping_sub(slow.since_ms) >= delay;
+                if released {
+                    self.slow = None;
+                    return due.then_some(keycode);
+                }
+                if due || slow.accepted {
+                    slow.accepted = true;
+                    self.slow = Some(slow);
+                    return Some(keycode);
+                }
+                None
+            }
+            // Release of a key we weren't timing (it was replaced)
+            _ if released => None,
+            _ => {
+                self.slow = Some(SlowKey { keycode, since_ms: now_ms, accepted: false });
+                None
+            }
+        }
+    }
+
+    /// Drop latches once a key has used them
+    pub fn consume_latches(&mut self) {
+        self.latched = 0;
+    }
+
+    fn shift_hotkeys(&mut self, tapped: bool, now_ms: u32) {
+        if now_ms.wrapping_sub(self.shift_down_ms) >= SLOW_HOTKEY_HOLD_MS {
+            self.shift_taps = 0;
+            REQUESTS.fetch_or(TOGGLE_SLOW, Ordering::AcqRel);
+            return;
+        }
+        if !tapped {
+            self.shift_taps = 0;
+            return;
+        }
+        if now_ms.wrapping_sub(self.last_tap_ms) > STICKY_HOTKEY_GAP_MS {
+            self.shift_taps = 0;
+        }
+        self.last_tap_ms = now_ms;
+        self.shift_taps += 1;
+        if self.shift_taps >= STICKY_HOTKEY_TAPS {
+            self.shift_taps = 0;
+            // The taps themselves shouldn't leave Shift latched
+            self.latched &= !MOD_SHIFT;
+            REQUESTS.fetch_or(TOGGLE_STICKY, Ordering::AcqRel);
+        }
+    }
+}
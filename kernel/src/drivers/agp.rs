@@ -0,0 +1,451 @@
+//! AGP Aperture and GART
+//!
+//! Programs the host bridge's AGP aperture and Graphics Address Remapping
+//! Table so scattered system RAM pages appear to the GPU (and the CPU) as
+//! one linear range. The Rage Mobility can then blit from surfaces far
+//! larger than its 8MB of VRAM.
+//!
+//! Supported host bridges are the Intel 440 family found in the Armada
+//! E500 and its contemporaries. Register usage follows the 82443BX
+//! datasheet (290633) and the Linux intel-agp driver.
+
+use super::pci::{self, config_read, config_write, offsets, cap_id};
+use crate::mm::pmm::PAGE_SIZE;
+
+// =============================================================================
+// Chipset Identification
+// =============================================================================
+
+/// Intel Vendor ID
+pub const INTEL_VENDOR_ID: u16 = 0x8086;
+
+/// Supported host bridges (device ID, name)
+const SUPPORTED_BRIDGES: [(u16, &str); 4] = [
+    (0x7180, "Intel 440LX"),
+    (0x7190, "Intel 440BX/ZX"),
+    (0x71A0, "Intel 440GX"),
+    (0x7194, "Intel 440MX"),
+];
+
+/// Intel 440 host bridge config registers
+mod regs {
+    pub const NBXCFG: u8 = 0x50;
+    pub const AGPCTRL: u8 = 0xB0;
+    pub const APSIZE: u8 = 0xB4;
+    pub const ATTBASE: u8 = 0xB8;
+    pub const ERRSTS: u8 = 0x90;
+}
+
+/// AGPCTRL: GART TLB enable (with the BIOS-default upper bits)
+const AGPCTRL_GTLB_ENABLE: u32 = 0x2280;
+const AGPCTRL_GTLB_DISABLE: u32 = 0x2200;
+
+/// NBXCFG: aperture access global enable
+const NBXCFG_AAGE: u32 = 1 << 9;
+
+/// GATT entry flags (valid, cacheable snoop bits as used by intel-agp)
+const GATT_ENTRY_FLAGS: u32 = 0x17;
+
+/// AGP capability register offsets (from capability header)
+const AGP_STATUS: u8 = 4;
+const AGP_COMMAND: u8 = 8;
+
+/// AGP command: enable
+const AGP_CMD_ENABLE: u32 = 1 << 8;
+/// AGP status/command: sideband addressing
+const AGP_SBA: u32 = 1 << 9;
+/// AGP status/command: transfer rate bits (1x, 2x, 4x)
+const AGP_RATE_MASK: u32 = 0x07;
+
+// =============================================================================
+// GATT
+// =============================================================================
+
+/// Largest aperture we will program (GATT lives in a static table)
+pub const MAX_APERTURE_SIZE: u32 = 64 * 1024 * 1024;
+
+/// GATT entries for the largest aperture
+const MAX_GATT_ENTRIES: usize = MAX_APERTURE_SIZE as usize / PAGE_SIZE;
+
+/// Graphics Address Translation Table (must be 4KB aligned)
+#[repr(C, align(4096))]
+struct Gatt([u32; MAX_GATT_ENTRIES]);
+
+static mut GATT: Gatt = Gatt([0; MAX_GATT_ENTRIES]);
+
+/// Unused GATT entries point here so stray GPU accesses hit harmless RAM
+#[repr(C, align(4096))]
+struct ScratchPage([u8; PAGE_SIZE]);
+
+static mut SCRATCH_PAGE: ScratchPage = ScratchPage([0; PAGE_SIZE]);
+
+/// APSIZE encodings, smallest first (size in bytes, register value)
+const APERTURE_SIZES: [(u32, u8); 7] = [
+    (4 << 20, 0x3F),
+    (8 << 20, 0x3E),
+    (16 << 20, 0x3C),
+    (32 << 20, 0x38),
+    (64 << 20, 0x30),
+    (128 << 20, 0x20),
+    (256 << 20, 0x00),
+];
+
+/// Look up the APSIZE/AGP_CNTL encoding for an aperture size
+pub fn aperture_size_bits(size: u32) -> Option<u8> {
+    APERTURE_SIZES.iter().find(|(s, _)| *s == size).map(|(_, bits)| *bits)
+}
+
+// =============================================================================
+// AGP Memory
+// =============================================================================
+
+/// A run of system RAM pages mapped contiguously into the aperture
+#[derive(Debug)]
+pub struct AgpMemory {
+    /// Byte offset into the aperture
+    offset: u32,
+    /// Number of pages
+    pages: usize,
+}
+
+impl AgpMemory {
+    /// Byte offset into the aperture
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Size in bytes
+    pub fn size(&self) -> usize {
+        self.pages * PAGE_SIZE
+    }
+
+    /// Bus address as seen by the GPU
+    pub fn bus_addr(&self) -> u32 {
+        unsafe { AGP.aperture_base + self.offset }
+    }
+
+    /// Linear CPU pointer (through the aperture)
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.bus_addr() as *mut u8
+    }
+}
+
+/// An offscreen pixel surface in AGP memory
+#[derive(Debug)]
+pub struct AgpSurface {
+    pub memory: AgpMemory,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes per pixel
+    pub bpp: u32,
+    /// Bytes per row
+    pub pitch: u32,
+}
+
+// =============================================================================
+// Bridge State
+// =============================================================================
+
+/// AGP host bridge state
+pub struct Agp {
+    bus: u8,
+    device: u8,
+    func: u8,
+    /// Offset of the bridge's AGP capability
+    cap: u8,
+    /// Chipset name
+    name: &'static str,
+    /// Aperture physical base
+    aperture_base: u32,
+    /// Aperture size in bytes
+    aperture_size: u32,
+    /// GATT entries in use
+    entries: usize,
+    /// Aperture page allocation bitmap
+    allocated: [u32; MAX_GATT_ENTRIES / 32],
+    /// Negotiated AGP command value (0 until enabled)
+    mode: u32,
+    initialized: bool,
+}
+
+impl Agp {
+    pub const fn new() -> Self {
+        Self {
+            bus: 0,
+            device: 0,
+            func: 0,
+            cap: 0,
+            name: "",
+            aperture_base: 0,
+            aperture_size: 0,
+            entries: 0,
+            allocated: [0; MAX_GATT_ENTRIES / 32],
+            mode: 0,
+            initialized: false,
+        }
+    }
+
+    /// Find a supported host bridge with an AGP capability
+    fn probe(&mut self) -> Result<(), &'static str> {
+        let id = unsafe { config_read(0, 0, 0, offsets::VENDOR_DEVICE) };
+        if id == 0xFFFFFFFF {
+            return Err("No host bridge");
+        }
+        if (id & 0xFFFF) as u16 != INTEL_VENDOR_ID {
+            return Err("Unsupported host bridge vendor");
+        }
+
+        let device_id = (id >> 16) as u16;
+        let name = SUPPORTED_BRIDGES.iter()
+            .find(|(id, _)| *id == device_id)
+            .map(|(_, name)| *name)
+            .ok_or("Unsupported host bridge")?;
+
+        let cap = pci::find_capability(0, 0, 0, cap_id::AGP)
+            .ok_or("Host bridge has no AGP capability")?;
+
+        self.bus = 0;
+        self.device = 0;
+        self.func = 0;
+        self.cap = cap;
+        self.name = name;
+        Ok(())
+    }
+
+    /// Program aperture size, GATT base and enable the GART TLB
+    fn configure(&mut self) -> Result<(), &'static str> {
+        let (bus, dev, func) = (self.bus, self.device, self.func);
+
+        unsafe {
+            let base = config_read(bus, dev, func, offsets::BAR0) & 0xFFFF_FFF0;
+            if base == 0 {
+                return Err("BIOS did not assign an aperture");
+            }
+
+            // Current size from APSIZE; clamp to what our GATT can cover
+            let apsize_reg = config_read(bus, dev, func, regs::APSIZE & 0xFC);
+            let shift = (regs::APSIZE & 3) * 8;
+            let current = ((apsize_reg >> shift) & 0x3F) as u8;
+            let bios_size = APERTURE_SIZES.iter()
+                .find(|(_, bits)| *bits == current)
+                .map(|(size, _)| *size)
+                .unwrap_or(4 << 20);
+
+            let (size, bits) = *APERTURE_SIZES.iter()
+                .rev()
+                .find(|(size, _)| *size <= bios_size.min(MAX_APERTURE_SIZE))
+                .ok_or("No usable aperture size")?;
+
+            let new_reg = (apsize_reg & !(0xFF << shift)) | ((bits as u32) << shift);
+            config_write(bus, dev, func, regs::APSIZE & 0xFC, new_reg);
+
+            self.aperture_base = base;
+            self.aperture_size = size;
+            self.entries = size as usize / PAGE_SIZE;
+
+            // Point every entry at the scratch page
+            let scratch = core::ptr::addr_of!(SCRATCH_PAGE) as u32;
+            for entry in GATT.0[..self.entries].iter_mut() {
+                *entry = scratch | GATT_ENTRY_FLAGS;
+            }
+            self.allocated = [0; MAX_GATT_ENTRIES / 32];
+
+            // GATT base, TLB enable, aperture access enable
+            let gatt = core::ptr::addr_of!(GATT) as u32;
+            config_write(bus, dev, func, regs::ATTBASE, gatt);
+            config_write(bus, dev, func, regs::AGPCTRL, AGPCTRL_GTLB_ENABLE);
+
+            let nbxcfg = config_read(bus, dev, func, regs::NBXCFG);
+            config_write(bus, dev, func, regs::NBXCFG, (nbxcfg & !(1 << 10)) | NBXCFG_AAGE);
+
+            // Clear latched AGP error status (ERRSTS+1, write-1-to-clear)
+            let errsts = config_read(bus, dev, func, regs::ERRSTS);
+            config_write(bus, dev, func, regs::ERRSTS, (errsts & 0xFFFF_00FF) | (0x07 << 8));
+        }
+
+        Ok(())
+    }
+
+    /// Flush the GART TLB after changing entries
+    fn flush_tlb(&self) {
+        unsafe {
+            config_write(self.bus, self.device, self.func, regs::AGPCTRL, AGPCTRL_GTLB_DISABLE);
+            config_write(self.bus, self.device, self.func, regs::AGPCTRL, AGPCTRL_GTLB_ENABLE);
+        }
+    }
+
+    /// Negotiate and enable AGP mode on the bridge and a device
+    ///
+    /// Picks the fastest common rate and the bridge's request queue depth.
+    pub fn enable_device(&mut self, bus: u8, device: u8, func: u8) -> Result<u32, &'static str> {
+        if !self.initialized {
+            return Err("AGP not initialized");
+        }
+        let dev_cap = pci::find_capability(bus, device, func, cap_id::AGP)
+            .ok_or("Device has no AGP capability")?;
+
+        unsafe {
+            let bridge_status = config_read(self.bus, self.device, self.func, self.cap + AGP_STATUS);
+            let dev_status = config_read(bus, device, func, dev_cap + AGP_STATUS);
+
+            let common = bridge_status & dev_status & AGP_RATE_MASK;
+            let rate = match common {
+                r if r & 0x4 != 0 => 0x4,
+                r if r & 0x2 != 0 => 0x2,
+                r if r & 0x1 != 0 => 0x1,
+                _ => return Err("No common AGP rate"),
+            };
+
+            let mut mode = (bridge_status & 0xFF00_0000) | rate | AGP_CMD_ENABLE;
+            if bridge_status & dev_status & AGP_SBA != 0 {
+                mode |= AGP_SBA;
+            }
+
+            // Bridge first, then the master
+            config_write(self.bus, self.device, self.func, self.cap + AGP_COMMAND, mode);
+            config_write(bus, device, func, dev_cap + AGP_COMMAND, mode);
+
+            self.mode = mode;
+            Ok(mode)
+        }
+    }
+
+    fn is_allocated(&self, entry: usize) -> bool {
+        self.allocated[entry / 32] & (1 << (entry % 32)) != 0
+    }
+
+    fn set_allocated(&mut self, entry: usize, used: bool) {
+        if used {
+            self.allocated[entry / 32] |= 1 << (entry % 32);
+        } else {
+            self.allocated[entry / 32] &= !(1 << (entry % 32));
+        }
+    }
+
+    /// Back `pages` aperture pages with system RAM
+    pub fn alloc(&mut self, pages: usize) -> Result<AgpMemory, &'static str> {
+        if !self.initialized {
+            return Err("AGP not initialized");
+        }
+        if pages == 0 || pages > self.entries {
+            return Err("Invalid AGP allocation size");
+        }
+
+        // First fit over the aperture
+        let mut start = 0;
+        let mut run = 0;
+        for entry in 0..self.entries {
+            if self.is_allocated(entry) {
+                run = 0;
+                start = entry + 1;
+                continue;
+            }
+            run += 1;
+            if run == pages {
+                break;
+            }
+        }
+        if run < pages {
+            return Err("AGP aperture full");
+        }
+
+        for i in 0..pages {
+            let phys = match crate::mm::pmm::alloc_page() {
+                Some(p) => p,
+                None => {
+                    self.release(start, i);
+                    return Err("Out of memory");
+                }
+            };
+            unsafe {
+                core::ptr::write_bytes(phys as *mut u8, 0, PAGE_SIZE);
+                GATT.0[start + i] = phys as u32 | GATT_ENTRY_FLAGS;
+            }
+            self.set_allocated(start + i, true);
+        }
+        self.flush_tlb();
+
+        Ok(AgpMemory {
+            offset: (start * PAGE_SIZE) as u32,
+            pages,
+        })
+    }
+
+    /// Unmap and free an allocation
+    pub fn free(&mut self, memory: AgpMemory) {
+        let start = memory.offset as usize / PAGE_SIZE;
+        self.release(start, memory.pages);
+        self.flush_tlb();
+    }
+
+    fn release(&mut self, start: usize, pages: usize) {
+        let scratch = core::ptr::addr_of!(SCRATCH_PAGE) as u32;
+        for entry in start..(start + pages).min(self.entries) {
+            if !self.is_allocated(entry) {
+                continue;
+            }
+            unsafe {
+                let phys = GATT.0[entry] & !0xFFF;
+                GATT.0[entry] = scratch | GATT_ENTRY_FLAGS;
+                crate::mm::pmm::free_page(phys as usize);
+            }
+            self.set_allocated(entry, false);
+        }
+    }
+
+    /// Free pages left in the aperture
+    pub fn free_pages(&self) -> usize {
+        (0..self.entries).filter(|&e| !self.is_allocated(e)).count()
+    }
+
+    pub fn name(&self) -> &'static str { self.name }
+    pub fn aperture_base(&self) -> u32 { self.aperture_base }
+    pub fn aperture_size(&self) -> u32 { self.aperture_size }
+    pub fn mode(&self) -> u32 { self.mode }
+    pub fn is_initialized(&self) -> bool { self.initialized }
+}
+
+// =============================================================================
+// Global Instance
+// =============================================================================
+
+/// Global AGP bridge instance
+pub static mut AGP: Agp = Agp::new();
+
+/// Detect the host bridge and set up the aperture and GART
+pub fn init() -> Result<(), &'static str> {
+    unsafe {
+        AGP.probe()?;
+        AGP.configure()?;
+        AGP.initialized = true;
+    }
+    Ok(())
+}
+
+/// Get the global AGP instance
+pub fn get() -> Option<&'static mut Agp> {
+    unsafe {
+        if AGP.is_initialized() {
+            Some(&mut AGP)
+        } else {
+            None
+        }
+    }
+}
+
+/// Allocate an offscreen surface in AGP memory
+pub fn alloc_surface(width: u32, height: u32, bpp: u32) -> Result<AgpSurface, &'static str> {
+    let agp = get().ok_or("AGP not initialized")?;
+    // 2D engine wants pitch in multiples of 8 pixels
+    let pitch = ((width + 7) & !7) * bpp;
+    let bytes = (pitch * height) as usize;
+    let memory = agp.alloc((bytes + PAGE_SIZE - 1) / PAGE_SIZE)?;
+    Ok(AgpSurface { memory, width, height, bpp, pitch })
+}
+
+/// Free an offscreen surface
+pub fn free_surface(surface: AgpSurface) {
+    if let Some(agp) = get() {
+        agp.free(surface.memory);
+    }
+}
@@ -12,7 +12,10 @@
 //! Based on ATI's RAGE 128 PRO Register Reference Guide (RRG-G04500-C)
 //! and the xf86-video-r128/Linux DRM driver sources.
 
-use crate::arch::x86::io::{inb, outb, inl, outl};
+use crate::arch::x86::io::{inb, outb};
+use crate::sched::wait::{yield_until, Deadline};
+use super::pci::{config_read as pci_config_read, config_write as pci_config_write};
+use core::sync::atomic::{AtomicU32, Ordering};
 
 // =============================================================================
 // PCI Identification
@@ -31,22 +34,28 @@ pub const ARMADA_E500_SUBSYS: u32 = 0xB1600E11;
 // Memory Map (from PCI BARs)
 // =============================================================================
 
-/// PCI Configuration Space ports
-const PCI_CONFIG_ADDR: u16 = 0xCF8;
-const PCI_CONFIG_DATA: u16 = 0xCFC;
-
 /// Minimum valid MMIO base address (anything below this is suspicious)
 const MIN_MMIO_ADDR: u32 = 0x80000000;
 
 /// Maximum valid MMIO base address
 const MAX_MMIO_ADDR: u32 = 0xFFF00000;
 
+/// Register aperture size (BAR2)
+const MMIO_SIZE: u32 = 0x4000;
+
+/// How long the pixel clock PLL gets to report lock
+const PLL_LOCK_TIMEOUT_MS: u32 = 10;
+
 /// GPU state
 pub struct AtiRage {
     /// MMIO base address (from BAR2)
     mmio_base: u32,
+    /// Kernel mapping of the registers
+    mmio: crate::mm::VirtAddr,
     /// Framebuffer base address (from BAR0)
     fb_base: u32,
+    /// Kernel mapping of VRAM
+    vram: crate::mm::VirtAddr,
     /// Framebuffer size in bytes
     fb_size: u32,
     /// Current display width
@@ -63,6 +72,20 @@ pub struct AtiRage {
     hw_cursor_enabled: bool,
     /// Is MMIO verified working?
     mmio_verified: bool,
+    /// Legacy IRQ line routed by the BIOS (from PCI config)
+    irq_line: Option<u8>,
+    /// Vblank interrupt registered and enabled?
+    vblank_irq_enabled: bool,
+    /// PCI location (bus, device, function)
+    pci_location: (u8, u8, u8),
+    /// AGP aperture base as programmed into MC_AGP_LOCATION (0 = no AGP)
+    agp_base: u32,
+    /// Current mode timings
+    mode: Option<DisplayMode>,
+    /// Internal flat panel, if one is driven
+    panel: Option<PanelInfo>,
+    /// How modes smaller than the panel are shown
+    panel_scaling: PanelScaling,
 }
 
 // =============================================================================
@@ -146,6 +169,21 @@ mod regs {
     pub const CUR_CLR0: u32 = 0x026C;
     pub const CUR_CLR1: u32 = 0x0270;
 
+    // Flat Panel / LVDS
+    pub const FP_CRTC_H_TOTAL_DISP: u32 = 0x0250;
+    pub const FP_CRTC_V_TOTAL_DISP: u32 = 0x0254;
+    pub const FP_GEN_CNTL: u32 = 0x0284;
+    pub const FP_HORZ_STRETCH: u32 = 0x028C;
+    pub const FP_VERT_STRETCH: u32 = 0x0290;
+    pub const FP_H_SYNC_STRT_WID: u32 = 0x02C4;
+    pub const FP_V_SYNC_STRT_WID: u32 = 0x02C8;
+    pub const LVDS_GEN_CNTL: u32 = 0x02D0;
+
+    // Interrupts
+    pub const GEN_INT_CNTL: u32 = 0x0040;
+    pub const GEN_INT_STATUS: u32 = 0x0044;
+    pub const CRTC_STATUS: u32 = 0x005C;
+
     // Engine Status
     pub const GUI_STAT: u32 = 0x1740;
     pub const FIFO_STAT: u32 = 0x1470;
@@ -179,6 +217,67 @@ mod crtc_gen_cntl {
     pub const CRTC_PIX_WIDTH_32BPP: u32 = 6 << 8;
 }
 
+/// DAC_CNTL: 8 bits per palette channel (else 6)
+const DAC_8BIT_EN: u32 = 1 << 8;
+
+// =============================================================================
+// Flat panel bits
+// =============================================================================
+
+mod fp {
+    // FP_GEN_CNTL
+    pub const FP_ON: u32 = 1 << 0;
+    pub const FP_SEL_CRTC2: u32 = 1 << 13;
+    pub const FP_CRTC_DONT_SHADOW_VPAR: u32 = 1 << 16;
+    pub const FP_CRTC_DONT_SHADOW_HEND: u32 = 1 << 17;
+    pub const FP_CRTC_USE_SHADOW_VEND: u32 = 1 << 18;
+    pub const FP_CRTC_USE_SHADOW_ROWCUR: u32 = 1 << 19;
+    pub const FP_CRTC_HORZ_DIV2_EN: u32 = 1 << 20;
+    pub const FP_CRTC_HOR_CRT_DIV2_DIS: u32 = 1 << 21;
+    pub const FP_USE_SHADOW_EN: u32 = 1 << 24;
+
+    // FP_HORZ_STRETCH
+    pub const HORZ_STRETCH_RATIO_MASK: u32 = 0xFFFF;
+    pub const HORZ_STRETCH_RATIO_MAX: u32 = 4096;
+    pub const HORZ_PANEL_SIZE: u32 = 0xFF << 16;
+    pub const HORZ_PANEL_SHIFT: u32 = 16;
+    pub const HORZ_STRETCH_BLEND: u32 = 1 << 25;
+    pub const HORZ_STRETCH_ENABLE: u32 = 1 << 26;
+    pub const HORZ_FP_LOOP_STRETCH: u32 = 0x7 << 27;
+    pub const HORZ_STRETCH_RESERVED: u32 = 1 << 30;
+    pub const HORZ_AUTO_RATIO_FIX_EN: u32 = 1 << 31;
+
+    // FP_VERT_STRETCH
+    pub const VERT_PANEL_SIZE: u32 = 0x7FF;
+    pub const VERT_STRETCH_RATIO_MASK: u32 = 0x3FF;
+    pub const VERT_STRETCH_RATIO_SHIFT: u32 = 11;
+    pub const VERT_STRETCH_RATIO_MAX: u32 = 1024;
+    pub const VERT_STRETCH_ENABLE: u32 = 1 << 24;
+    pub const VERT_STRETCH_BLEND: u32 = 1 << 25;
+    pub const VERT_STRETCH_RESERVED: u32 = 0xF100_0000;
+
+    // LVDS_GEN_CNTL
+    pub const LVDS_ON: u32 = 1 << 0;
+}
+
+// =============================================================================
+// Interrupt bits
+// =============================================================================
+
+mod gen_int {
+    /// GEN_INT_CNTL: vblank interrupt enable
+    pub const CRTC_VBLANK_INT_EN: u32 = 1 << 0;
+    /// GEN_INT_STATUS: vblank interrupt pending (write 1 to acknowledge)
+    pub const CRTC_VBLANK_INT: u32 = 1 << 0;
+}
+
+mod crtc_status {
+    /// Currently in vertical blank
+    pub const CRTC_VBLANK_CUR: u32 = 1 << 0;
+    /// Latched vblank (write 1 to clear)
+    pub const CRTC_VBLANK_SAVE: u32 = 1 << 1;
+}
+
 // =============================================================================
 // 2D Engine bits
 // =============================================================================
@@ -199,6 +298,64 @@ mod dp_gui {
     pub const GMC_WR_MSK_DIS: u32 = 1 << 30;
 }
 
+// =============================================================================
+// Flat Panel
+// =============================================================================
+
+/// Native size of the internal LCD
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanelInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Driven over LVDS (as opposed to a TMDS/DVI flat panel)
+    pub lvds: bool,
+}
+
+/// How a mode smaller than the panel is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanelScaling {
+    /// Stretch horizontally to the panel width
+    pub h_expand: bool,
+    /// Stretch vertically to the panel height
+    pub v_expand: bool,
+    /// Center along any axis that is not expanded (else top-left)
+    pub center: bool,
+}
+
+impl PanelScaling {
+    /// Full-screen expansion
+    pub const DEFAULT: Self = Self { h_expand: true, v_expand: true, center: true };
+
+    /// Expansion axes as a settings name
+    pub fn expand_name(&self) -> &'static str {
+        match (self.h_expand, self.v_expand) {
+            (true, true) => "hv",
+            (true, false) => "h",
+            (false, true) => "v",
+            (false, false) => "none",
+        }
+    }
+
+    /// Parse expansion axes from a settings name
+    pub fn from_expand_name(name: &str, center: bool) -> Option<Self> {
+        let (h_expand, v_expand) = match name {
+            "hv" => (true, true),
+            "h" => (true, false),
+            "v" => (false, true),
+            "none" => (false, false),
+            _ => return None,
+        };
+        Some(Self { h_expand, v_expand, center })
+    }
+}
+
+/// Panel size assumed when an LCD is active but the BIOS left no size
+/// in the stretch registers (the Armada E500's 14.1" XGA panel)
+const DEFAULT_PANEL: PanelInfo = PanelInfo { width: 1024, height: 768, lvds: true };
+
+/// Hardware cursor image size (64x64 at 2bpp)
+pub const CURSOR_IMAGE_BYTES: usize = 1024;
+
 // =============================================================================
 // Display Mode Timings
 // =============================================================================
@@ -285,7 +442,9 @@ impl AtiRage {
     pub const fn new() -> Self {
         Self {
             mmio_base: 0,
+            mmio: 0,
             fb_base: 0,
+            vram: 0,
             fb_size: 0,
             width: 0,
             height: 0,
@@ -294,6 +453,13 @@ impl AtiRage {
             initialized: false,
             hw_cursor_enabled: false,
             mmio_verified: false,
+            irq_line: None,
+            vblank_irq_enabled: false,
+            pci_location: (0, 0, 0),
+            agp_base: 0,
+            mode: None,
+            panel: None,
+            panel_scaling: PanelScaling::DEFAULT,
         }
     }
 
@@ -330,6 +496,8 @@ impl AtiRage {
 
     /// Initialize the GPU
     pub fn init(&mut self, bus: u8, device: u8, func: u8) -> Result<(), &'static str> {
+        self.pci_location = (bus, device, func);
+
         // Read BARs from PCI config space
         let bar0 = unsafe { pci_config_read(bus, device, func, 0x10) };
         let bar2 = unsafe { pci_config_read(bus, device, func, 0x18) };
@@ -373,6 +541,14 @@ impl AtiRage {
             pci_config_write(bus, device, func, 0x04, command | 0x06);
         }
 
+        // Interrupt line/pin (0x3C): pin 0 = no interrupt, line 0xFF = not routed
+        let int_reg = unsafe { pci_config_read(bus, device, func, 0x3C) };
+        let line = (int_reg & 0xFF) as u8;
+        let pin = ((int_reg >> 8) & 0xFF) as u8;
+        self.irq_line = if pin != 0 && line < 16 && line != 2 { Some(line) } else { None };
+
+        self.mmio = crate::mm::iomap(self.mmio_base, MMIO_SIZE)?;
+
         // Verify MMIO is working by reading a known register
         if !self.verify_mmio() {
             return Err("MMIO verification failed - hardware not responding");
@@ -382,6 +558,7 @@ impl AtiRage {
         // Now safe to do MMIO operations
         // Detect VRAM size
         self.fb_size = self.detect_vram_size();
+        self.vram = crate::mm::iomap_framebuffer(self.fb_base, self.fb_size)?;
 
         // Perform soft reset (only if MMIO verified)
         self.soft_reset();
@@ -389,6 +566,9 @@ impl AtiRage {
         // Initialize memory controller
         self.init_memory_controller();
 
+        // Find the internal LCD before the first mode set
+        self.panel = self.detect_panel();
+
         self.initialized = true;
         Ok(())
     }
@@ -415,7 +595,7 @@ impl AtiRage {
 
     /// Safe MMIO read with basic validation (doesn't panic on failure)
     fn mmio_read_safe(&self, reg: u32) -> Option<u32> {
-        if self.mmio_base == 0 {
+        if self.mmio == 0 || reg >= MMIO_SIZE {
             return None;
         }
 
@@ -428,7 +608,7 @@ impl AtiRage {
 
         // Do the read
         let value = unsafe {
-            let ptr = addr as *const u32;
+            let ptr = (self.mmio + reg) as *const u32;
             ptr.read_volatile()
         };
 
@@ -570,9 +750,36 @@ impl AtiRage {
         self.bpp = bpp;
         self.pitch = pitch_bytes;
 
+        self.mode = Some(*mode);
+
         // Initialize 2D engine for this mode
         self.init_2d_engine();
 
+        // Scale/center the mode on the internal LCD
+        self.program_panel();
+
+        Ok(())
+    }
+
+    /// Hand the display back to the VGA core (for a text console)
+    ///
+    /// Turns off the extended display path so the standard VGA registers
+    /// drive the screen again. `restore_mode` undoes it.
+    pub fn enter_vga_mode(&mut self) {
+        if !self.initialized || !self.mmio_verified {
+            return;
+        }
+        self.wait_for_idle();
+        self.disable_hw_cursor();
+        let crtc_gen_cntl = self.mmio_read(regs::CRTC_GEN_CNTL);
+        self.mmio_write(regs::CRTC_GEN_CNTL, crtc_gen_cntl & !crtc_gen_cntl::CRTC_EXT_DISP_EN);
+    }
+
+    /// Reprogram the last mode set with `set_mode`
+    pub fn restore_mode(&mut self) -> Result<(), &'static str> {
+        let mode = self.mode.ok_or("No mode to restore")?;
+        self.set_mode(&mode, self.bpp)?;
+        self.enable_hw_cursor();
         Ok(())
     }
 
@@ -600,19 +807,10 @@ impl AtiRage {
         // Set feedback and post divider (using PPLL_DIV_0)
         self.pll_write(regs::PPLL_DIV_0, feedback_div | (post_div << 16));
 
-        // Wait for PLL lock with timeout
-        for _ in 0..10000 {
-            let status = self.pll_read(regs::PPLL_CNTL);
-            if status & (1 << 2) != 0 {
-                return Ok(());
-            }
-            // Small delay
-            for _ in 0..100 {
-                unsafe { core::arch::asm!("nop"); }
-            }
-        }
-
-        // PLL may still work even without lock indication
+        // Wait for PLL lock, letting interrupts and other tasks run.
+        // The PLL may still work even without lock indication.
+        yield_until(Deadline::after_ms(PLL_LOCK_TIMEOUT_MS),
+            || self.pll_read(regs::PPLL_CNTL) & (1 << 2) != 0);
         Ok(())
     }
 
@@ -704,7 +902,31 @@ impl AtiRage {
     // 2D Accelerated Operations
     // =========================================================================
 
-    /// Fill a rectangle with a solid color
+    /// GMC destination datatype for the current depth
+    fn gmc_datatype(&self) -> u32 {
+        let datatype = match self.bpp {
+            8 => 2,
+            15 => 3,
+            16 => 4,
+            24 => 5,
+            _ => 6,
+        };
+        datatype << 8
+    }
+
+    /// Pack a 0xRRGGBB color for the current depth
+    ///
+    /// The 2D engine can't dither, so accelerated fills use the nearest
+    /// lower color (8bpp assumes the RGB332 palette).
+    pub fn pack_color(&self, rgb: u32) -> u32 {
+        let color = crate::gui::Color::from_u32(rgb);
+        match crate::gui::pixel::PixelFormat::from_depth(self.bpp) {
+            Some(format) => format.pack(color) & !0xFF00_0000,
+            None => rgb & 0xFFFFFF,
+        }
+    }
+
+    /// Fill a rectangle with a solid color (0xRRGGBB)
     pub fn fill_rect(&self, x: u32, y: u32, width: u32, height: u32, color: u32) {
         if !self.initialized || !self.mmio_verified {
             return;
@@ -717,10 +939,10 @@ impl AtiRage {
             | dp_gui::GMC_BRUSH_SOLID_COLOR
             | dp_gui::GMC_CLR_CMP_CNTL_DIS
             | (dp_gui::ROP3_PATCOPY << 16)
-            | (6 << 8);  // 32bpp
+            | self.gmc_datatype();
 
         self.mmio_write(regs::DP_GUI_MASTER_CNTL, gmc);
-        self.mmio_write(regs::DP_BRUSH_FRGD_CLR, color);
+        self.mmio_write(regs::DP_BRUSH_FRGD_CLR, self.pack_color(color));
         self.mmio_write(regs::DP_CNTL, 0x03);
         self.mmio_write(regs::DST_Y_X, (x << 16) | y);
         self.mmio_write(regs::DST_HEIGHT_WIDTH, (width << 16) | height);
@@ -754,7 +976,7 @@ impl AtiRage {
             | dp_gui::GMC_SRC_DATATYPE_COLOR
             | dp_gui::GMC_CLR_CMP_CNTL_DIS
             | (dp_gui::ROP3_SRCCOPY << 16)
-            | (6 << 8);  // 32bpp
+            | self.gmc_datatype();
 
         self.mmio_write(regs::DP_GUI_MASTER_CNTL, gmc);
         self.mmio_write(regs::DP_CNTL, direction);
@@ -821,22 +1043,28 @@ impl AtiRage {
 
     /// Set hardware cursor image (64x64 2bpp bitmap)
     /// Image format: 2 bits per pixel, 00=transparent, 01=color0, 10=color1, 11=inverted
-    pub fn set_cursor_image(&self, offset: u32, image: &[u8]) {
+    pub fn set_cursor_image(&self, offset: u32, image: &[u8; CURSOR_IMAGE_BYTES]) -> Result<(), &'static str> {
         if !self.initialized || !self.mmio_verified {
-            return;
+            return Err("GPU not initialized");
+        }
+        // CUR_OFFSET is in 1KB units and the image must lie inside VRAM
+        if offset % 1024 != 0 {
+            return Err("Cursor offset not 1KB aligned");
+        }
+        match offset.checked_add(CURSOR_IMAGE_BYTES as u32) {
+            Some(end) if end <= self.fb_size => {}
+            _ => return Err("Cursor image outside VRAM"),
         }
 
-        // Cursor image lives in VRAM
-        // Set cursor offset register
-        self.mmio_write(regs::CUR_OFFSET, offset >> 10);  // In 1KB units
+        self.mmio_write(regs::CUR_OFFSET, offset >> 10);
 
-        // Copy cursor image to VRAM at offset
-        let cursor_ptr = (self.fb_base + offset) as *mut u8;
-        for (i, &byte) in image.iter().enumerate().take(1024) {  // 64x64x2bpp = 1KB
+        let cursor_ptr = (self.vram + offset) as *mut u8;
+        for (i, &byte) in image.iter().enumerate() {
             unsafe {
                 cursor_ptr.add(i).write_volatile(byte);
             }
         }
+        Ok(())
     }
 
     // =========================================================================
@@ -871,6 +1099,322 @@ impl AtiRage {
         self.mmio_write(regs::CRTC_GEN_CNTL, crtc_gen | crtc_gen_cntl::CRTC_EN);
     }
 
+    // =========================================================================
+    // Palette
+    // =========================================================================
+
+    /// Program `count` DAC palette entries starting at `start`
+    ///
+    /// `color` maps a palette index to 8-bit (r, g, b). Switches the DAC
+    /// to 8 bits per channel.
+    pub fn set_palette(&self, start: u8, color: &dyn Fn(u8) -> (u8, u8, u8), count: usize) {
+        if !self.mmio_verified {
+            return;
+        }
+
+        let dac_cntl = self.mmio_read(regs::DAC_CNTL);
+        self.mmio_write(regs::DAC_CNTL, dac_cntl | DAC_8BIT_EN);
+        self.mmio_write8(regs::DAC_MASK, 0xFF);
+
+        self.mmio_write8(regs::DAC_W_INDEX, start);
+        for i in 0..count.min(256 - start as usize) {
+            let (r, g, b) = color(start.wrapping_add(i as u8));
+            self.mmio_write8(regs::DAC_DATA, r);
+            self.mmio_write8(regs::DAC_DATA, g);
+            self.mmio_write8(regs::DAC_DATA, b);
+        }
+    }
+
+    // =========================================================================
+    // Flat Panel
+    // =========================================================================
+
+    /// Detect an active flat panel and its native size
+    ///
+    /// The BIOS programs the panel size into the stretch registers during
+    /// POST; fall back to the Armada's XGA panel if they are empty.
+    fn detect_panel(&self) -> Option<PanelInfo> {
+        let lvds = self.mmio_read(regs::LVDS_GEN_CNTL) & fp::LVDS_ON != 0;
+        let fp_on = self.mmio_read(regs::FP_GEN_CNTL) & fp::FP_ON != 0;
+        if !lvds && !fp_on {
+            return None;
+        }
+
+        let horz = self.mmio_read(regs::FP_HORZ_STRETCH);
+        let vert = self.mmio_read(regs::FP_VERT_STRETCH);
+        let width = (((horz & fp::HORZ_PANEL_SIZE) >> fp::HORZ_PANEL_SHIFT) + 1) * 8;
+        let height = (vert & fp::VERT_PANEL_SIZE) + 1;
+
+        if width < 640 || height < 480 {
+            return Some(PanelInfo { lvds, ..DEFAULT_PANEL });
+        }
+        Some(PanelInfo { width, height, lvds })
+    }
+
+    /// Program flat panel timing, expansion and centering for the current mode
+    fn program_panel(&self) {
+        let (panel, mode) = match (self.panel, self.mode) {
+            (Some(panel), Some(mode)) => (panel, mode),
+            _ => return,
+        };
+        let scaling = self.panel_scaling;
+
+        // Horizontal stretch (panel size in 8-pixel units)
+        let mut horz = self.mmio_read(regs::FP_HORZ_STRETCH)
+            & (fp::HORZ_STRETCH_RESERVED | fp::HORZ_FP_LOOP_STRETCH | fp::HORZ_AUTO_RATIO_FIX_EN);
+        horz |= ((panel.width / 8 - 1) << fp::HORZ_PANEL_SHIFT) & fp::HORZ_PANEL_SIZE;
+        let h_stretch = scaling.h_expand && mode.width < panel.width;
+        if h_stretch {
+            let ratio = (mode.width * fp::HORZ_STRETCH_RATIO_MAX + panel.width / 2) / panel.width;
+            horz |= (ratio & fp::HORZ_STRETCH_RATIO_MASK)
+                | fp::HORZ_STRETCH_ENABLE
+                | fp::HORZ_STRETCH_BLEND;
+        }
+
+        // Vertical stretch
+        let mut vert = self.mmio_read(regs::FP_VERT_STRETCH) & fp::VERT_STRETCH_RESERVED;
+        vert |= (panel.height - 1) & fp::VERT_PANEL_SIZE;
+        let v_stretch = scaling.v_expand && mode.height < panel.height;
+        if v_stretch {
+            let ratio = (mode.height * fp::VERT_STRETCH_RATIO_MAX + panel.height / 2) / panel.height;
+            vert |= ((ratio & fp::VERT_STRETCH_RATIO_MASK) << fp::VERT_STRETCH_RATIO_SHIFT)
+                | fp::VERT_STRETCH_ENABLE
+                | fp::VERT_STRETCH_BLEND;
+        }
+
+        // Panel CRTC timing follows the main CRTC; centering moves sync
+        // earlier by half the unused area so the image lands mid-panel
+        let h_shift = if scaling.center && !h_stretch {
+            panel.width.saturating_sub(mode.width) / 2 / 8
+        } else {
+            0
+        };
+        let v_shift = if scaling.center && !v_stretch {
+            panel.height.saturating_sub(mode.height) / 2
+        } else {
+            0
+        };
+
+        let h_sync = self.mmio_read(regs::CRTC_H_SYNC_STRT_WID);
+        let v_sync = self.mmio_read(regs::CRTC_V_SYNC_STRT_WID);
+        let h_start = (h_sync & 0x7FF).saturating_sub(h_shift);
+        let v_start = (v_sync & 0xFFF).saturating_sub(v_shift);
+
+        let mut gen = self.mmio_read(regs::FP_GEN_CNTL);
+        gen &= !(fp::FP_CRTC_USE_SHADOW_VEND
+            | fp::FP_CRTC_USE_SHADOW_ROWCUR
+            | fp::FP_CRTC_HORZ_DIV2_EN
+            | fp::FP_CRTC_HOR_CRT_DIV2_DIS
+            | fp::FP_USE_SHADOW_EN
+            | fp::FP_SEL_CRTC2);
+        gen |= fp::FP_CRTC_DONT_SHADOW_VPAR | fp::FP_CRTC_DONT_SHADOW_HEND;
+
+        self.mmio_write(regs::FP_CRTC_H_TOTAL_DISP, self.mmio_read(regs::CRTC_H_TOTAL_DISP));
+        self.mmio_write(regs::FP_CRTC_V_TOTAL_DISP, self.mmio_read(regs::CRTC_V_TOTAL_DISP));
+        self.mmio_write(regs::FP_H_SYNC_STRT_WID, (h_sync & !0x7FF) | h_start);
+        self.mmio_write(regs::FP_V_SYNC_STRT_WID, (v_sync & !0xFFF) | v_start);
+        self.mmio_write(regs::FP_HORZ_STRETCH, horz);
+        self.mmio_write(regs::FP_VERT_STRETCH, vert);
+        self.mmio_write(regs::FP_GEN_CNTL, gen);
+    }
+
+    /// Internal flat panel, if one was detected
+    pub fn panel_info(&self) -> Option<PanelInfo> {
+        self.panel
+    }
+
+    /// Current panel scaling options
+    pub fn panel_scaling(&self) -> PanelScaling {
+        self.panel_scaling
+    }
+
+    /// Change panel expansion/centering and reprogram the panel
+    pub fn set_panel_scaling(&mut self, scaling: PanelScaling) -> Result<(), &'static str> {
+        if self.panel.is_none() {
+            return Err("No flat panel");
+        }
+        self.panel_scaling = scaling;
+        self.program_panel();
+        Ok(())
+    }
+
+    // =========================================================================
+    // AGP
+    // =========================================================================
+
+    /// Enable AGP transfers and map the host bridge aperture into the
+    /// GPU's memory controller
+    ///
+    /// The AGP bridge must already be initialized (`agp::init`).
+    pub fn enable_agp(&mut self) -> Result<(), &'static str> {
+        if !self.initialized || !self.mmio_verified {
+            return Err("GPU not initialized");
+        }
+        let agp = crate::drivers::agp::get().ok_or("AGP not initialized")?;
+
+        let (bus, device, func) = self.pci_location;
+        agp.enable_device(bus, device, func)?;
+
+        let base = agp.aperture_base();
+        let size = agp.aperture_size();
+        let size_bits = crate::drivers::agp::aperture_size_bits(size)
+            .ok_or("Unsupported aperture size")?;
+
+        // Same address space as MC_FB_LOCATION: top and base in 64KB units
+        let location = ((base + size - 1) & 0xFFFF0000) | (base >> 16);
+        self.mmio_write(regs::MC_AGP_LOCATION, location);
+        self.mmio_write(regs::AGP_BASE, base);
+
+        let cntl = self.mmio_read(regs::AGP_CNTL);
+        self.mmio_write(regs::AGP_CNTL, (cntl & !0x3F) | size_bits as u32);
+
+        self.agp_base = base;
+        Ok(())
+    }
+
+    /// Is AGP memory reachable from the 2D engine?
+    pub fn agp_enabled(&self) -> bool {
+        self.agp_base != 0
+    }
+
+    /// Blit a rectangle from an AGP surface to the screen
+    pub fn blit_from_agp(
+        &self,
+        surface: &crate::drivers::agp::AgpSurface,
+        src_x: u32, src_y: u32,
+        dst_x: u32, dst_y: u32,
+        width: u32, height: u32,
+    ) -> Result<(), &'static str> {
+        if !self.initialized || !self.mmio_verified {
+            return Err("GPU not initialized");
+        }
+        if !self.agp_enabled() {
+            return Err("AGP not enabled");
+        }
+        if surface.bpp * 8 != self.bpp {
+            return Err("Surface depth does not match display");
+        }
+
+        // Source offset is relative to the framebuffer base, like DST_OFFSET
+        let src_offset = surface.memory.bus_addr().wrapping_sub(self.fb_base);
+        let src_pitch = surface.pitch / surface.bpp;
+        let screen_pitch = self.pitch / (self.bpp / 8).max(1);
+
+        self.wait_for_fifo(10);
+
+        let gmc = dp_gui::GMC_DST_PITCH_OFFSET_CNTL
+            | dp_gui::GMC_SRC_PITCH_OFFSET_CNTL
+            | dp_gui::GMC_SRC_DATATYPE_COLOR
+            | dp_gui::GMC_CLR_CMP_CNTL_DIS
+            | (dp_gui::ROP3_SRCCOPY << 16)
+            | self.gmc_datatype();
+
+        self.mmio_write(regs::DP_GUI_MASTER_CNTL, gmc);
+        self.mmio_write(regs::SRC_OFFSET, src_offset);
+        self.mmio_write(regs::SRC_PITCH, src_pitch);
+        self.mmio_write(regs::DP_CNTL, 0x03);
+        self.mmio_write(regs::SRC_Y_X, (src_x << 16) | src_y);
+        self.mmio_write(regs::DST_Y_X, (dst_x << 16) | dst_y);
+        self.mmio_write(regs::DST_HEIGHT_WIDTH, (width << 16) | height);
+
+        // Restore on-screen source for copy_rect
+        self.wait_for_fifo(2);
+        self.mmio_write(regs::SRC_OFFSET, 0);
+        self.mmio_write(regs::SRC_PITCH, screen_pitch);
+        Ok(())
+    }
+
+    // =========================================================================
+    // Interrupts and Vblank
+    // =========================================================================
+
+    /// Register the interrupt handler and enable vblank interrupts
+    pub fn enable_vblank_irq(&mut self) -> Result<(), &'static str> {
+        if !self.mmio_verified {
+            return Err("MMIO not verified");
+        }
+        let irq = self.irq_line.ok_or("No IRQ routed to GPU")?;
+
+        // Start from a clean state: everything masked, nothing pending
+        self.mmio_write(regs::GEN_INT_CNTL, 0);
+        self.mmio_write(regs::GEN_INT_STATUS, gen_int::CRTC_VBLANK_INT);
+
+        crate::arch::x86::idt::register_irq_handler(irq, "ati-rage", irq_handler)?;
+
+        let cntl = self.mmio_read(regs::GEN_INT_CNTL);
+        self.mmio_write(regs::GEN_INT_CNTL, cntl | gen_int::CRTC_VBLANK_INT_EN);
+        self.vblank_irq_enabled = true;
+        Ok(())
+    }
+
+    /// Disable vblank interrupts and release the IRQ line
+    pub fn disable_vblank_irq(&mut self) {
+        if !self.vblank_irq_enabled {
+            return;
+        }
+        let cntl = self.mmio_read(regs::GEN_INT_CNTL);
+        self.mmio_write(regs::GEN_INT_CNTL, cntl & !gen_int::CRTC_VBLANK_INT_EN);
+        if let Some(irq) = self.irq_line {
+            crate::arch::x86::idt::unregister_irq_handler(irq, irq_handler);
+        }
+        self.vblank_irq_enabled = false;
+    }
+
+    /// Wait for the start of the next vertical blank
+    ///
+    /// Sleeps on the vblank interrupt when it is enabled, otherwise polls
+    /// the CRTC status register. Gives up after ~50ms so a dead display
+    /// engine can't hang the caller. Returns false on timeout.
+    pub fn wait_for_vblank(&self) -> bool {
+        if !self.mmio_verified {
+            return false;
+        }
+
+        use crate::arch::x86::pit;
+        let start = pit::ticks();
+        let limit = (pit::frequency() / 20).max(2);
+
+        if self.vblank_irq_enabled {
+            let seen = VBLANK_COUNT.load(Ordering::Acquire);
+            while VBLANK_COUNT.load(Ordering::Acquire) == seen {
+                if pit::ticks().wrapping_sub(start) > limit {
+                    return false;
+                }
+                unsafe { core::arch::asm!("hlt"); }
+            }
+            return true;
+        }
+
+        // Clear the latched flag, then wait for it to be set again
+        self.mmio_write(regs::CRTC_STATUS, crtc_status::CRTC_VBLANK_SAVE);
+        while self.mmio_read(regs::CRTC_STATUS) & crtc_status::CRTC_VBLANK_SAVE == 0 {
+            if pit::ticks().wrapping_sub(start) > limit {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Is the display currently in vertical blank?
+    pub fn in_vblank(&self) -> bool {
+        self.mmio_verified
+            && self.mmio_read(regs::CRTC_STATUS) & crtc_status::CRTC_VBLANK_CUR != 0
+    }
+
+    /// Acknowledge pending interrupts; returns true if any were ours
+    fn handle_interrupt(&self) -> bool {
+        let status = self.mmio_read(regs::GEN_INT_STATUS);
+        let mut claimed = false;
+
+        if status & gen_int::CRTC_VBLANK_INT != 0 {
+            self.mmio_write(regs::GEN_INT_STATUS, gen_int::CRTC_VBLANK_INT);
+            VBLANK_COUNT.fetch_add(1, Ordering::Release);
+            claimed = true;
+        }
+
+        claimed
+    }
+
     // =========================================================================
     // Low-level Register Access
     // =========================================================================
@@ -879,7 +1423,7 @@ impl AtiRage {
     #[inline]
     fn mmio_read(&self, reg: u32) -> u32 {
         unsafe {
-            let ptr = (self.mmio_base + reg) as *const u32;
+            let ptr = (self.mmio + reg) as *const u32;
             ptr.read_volatile()
         }
     }
@@ -888,7 +1432,16 @@ impl AtiRage {
     #[inline]
     fn mmio_write(&self, reg: u32, value: u32) {
         unsafe {
-            let ptr = (self.mmio_base + reg) as *mut u32;
+            let ptr = (self.mmio + reg) as *mut u32;
+            ptr.write_volatile(value);
+        }
+    }
+
+    /// Write 8-bit MMIO register
+    #[inline]
+    fn mmio_write8(&self, reg: u32, value: u8) {
+        unsafe {
+            let ptr = (self.mmio + reg) as *mut u8;
             ptr.write_volatile(value);
         }
     }
@@ -911,48 +1464,47 @@ impl AtiRage {
 
     pub fn framebuffer_addr(&self) -> u32 { self.fb_base }
     pub fn framebuffer_size(&self) -> u32 { self.fb_size }
+    /// Kernel mapping of all of VRAM (including past the visible screen)
+    pub fn vram(&self) -> crate::mm::VirtAddr { self.vram }
     pub fn width(&self) -> u32 { self.width }
     pub fn height(&self) -> u32 { self.height }
     pub fn bpp(&self) -> u32 { self.bpp }
     pub fn pitch(&self) -> u32 { self.pitch }
     pub fn is_initialized(&self) -> bool { self.initialized }
     pub fn mmio_base(&self) -> u32 { self.mmio_base }
+    pub fn irq_line(&self) -> Option<u8> { self.irq_line }
+    pub fn vblank_irq_enabled(&self) -> bool { self.vblank_irq_enabled }
 }
 
 // =============================================================================
-// PCI Configuration Space Access
+// Global Instance
 // =============================================================================
 
-/// Read from PCI configuration space
-unsafe fn pci_config_read(bus: u8, device: u8, func: u8, offset: u8) -> u32 {
-    let address = 0x80000000u32
-        | ((bus as u32) << 16)
-        | ((device as u32) << 11)
-        | ((func as u32) << 8)
-        | ((offset as u32) & 0xFC);
-
-    outl(PCI_CONFIG_ADDR, address);
-    inl(PCI_CONFIG_DATA)
-}
+/// Global ATI Rage GPU instance
+pub static mut ATI_RAGE: AtiRage = AtiRage::new();
 
-/// Write to PCI configuration space
-unsafe fn pci_config_write(bus: u8, device: u8, func: u8, offset: u8, value: u32) {
-    let address = 0x80000000u32
-        | ((bus as u32) << 16)
-        | ((device as u32) << 11)
-        | ((func as u32) << 8)
-        | ((offset as u32) & 0xFC);
+/// Vblank interrupts received since boot
+static VBLANK_COUNT: AtomicU32 = AtomicU32::new(0);
 
-    outl(PCI_CONFIG_ADDR, address);
-    outl(PCI_CONFIG_DATA, value);
+/// IRQ handler (the line may be shared with other PCI devices)
+fn irq_handler(_irq: u8) -> bool {
+    unsafe { ATI_RAGE.is_initialized() && ATI_RAGE.handle_interrupt() }
 }
 
-// =============================================================================
-// Global Instance
-// =============================================================================
+/// Vblank interrupts received since boot
+pub fn vblank_count() -> u32 {
+    VBLANK_COUNT.load(Ordering::Relaxed)
+}
 
-/// Global ATI Rage GPU instance
-pub static mut ATI_RAGE: AtiRage = AtiRage::new();
+/// Wait for vblank if the native driver is active
+///
+/// Returns false immediately when there is no GPU to sync with.
+pub fn wait_for_vblank() -> bool {
+    match get() {
+        Some(gpu) => gpu.wait_for_vblank(),
+        None => false,
+    }
+}
 
 /// Initialize ATI Rage GPU driver
 pub fn init() -> Result<(), &'static str> {
@@ -0,0 +1,56 @@
+//! Emulator Debug Console
+//!
+//! Bochs and QEMU (`-debugcon stdio`, or `-device isa-debugcon`) print
+//! every byte written to port 0xE9, with no setup. `init` runs first thing
+//! in `kernel_main`, so boot failures before the screen or COM1 exist
+//! still leave a trace; after that everything written to the kernel log
+//! is copied here too.
+//!
+//! On real hardware the port is usually unused: it reads back 0xFF where
+//! the emulators return 0xE9, so the console is only used if that reads
+//! back.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::arch::x86::io::{inb, outb};
+
+/// Debug console port
+pub const PORT: u16 = 0xE9;
+
+/// Found at `init`
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Look for the console; true if there is one
+pub fn init() -> bool {
+    let present = unsafe { inb(PORT) } == PORT as u8;
+    ENABLED.store(present, Ordering::Relaxed);
+    present
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Write bytes (dropped without a console)
+pub fn write_bytes(bytes: &[u8]) {
+    if !is_enabled() {
+        return;
+    }
+    for &b in bytes {
+        unsafe { outb(PORT, b) };
+    }
+}
+
+pub fn write_str(s: &str) {
+    write_bytes(s.as_bytes());
+}
+
+/// `fmt::Write` adapter, for output that can't allocate (panics)
+pub struct Debugcon;
+
+impl fmt::Write for Debugcon {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_str(s);
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! DMA Buffers
+//!
+//! `DmaBuffer` is the one way drivers should hand memory to a bus-master
+//! device: physically contiguous pages with a physical address for the
+//! device and bounds-checked accessors for the CPU, instead of raw
+//! pointer/length pairs.
+//!
+//! - Contiguous: allocated as one run from the PMM, so a single
+//!   address/length pair (or a simple PRD/descriptor) covers it
+//! - Aligned: runs start on a page boundary, which satisfies cache-line
+//!   and the usual 4K descriptor alignment; `with_alignment` asks for more
+//! - Owned: the buffer frees its pages on drop, so keep it in the driver
+//!   state for as long as the device may touch it
+//!
+//! The kernel is identity mapped, so the CPU pointer is the physical
+//! address. x86 keeps DMA coherent with the caches; CPU accesses are still
+//! volatile so the compiler can't elide them around device transfers.
+
+use crate::mm::pmm::{self, PAGE_SIZE};
+
+/// Cache line size assumed for alignment (P6 and later)
+pub const CACHE_LINE: usize = 32;
+
+// Page-aligned buffers never share a cache line with other data
+const _: () = assert!(PAGE_SIZE % CACHE_LINE == 0);
+
+/// A physically contiguous, driver-owned DMA buffer
+#[derive(Debug)]
+pub struct DmaBuffer {
+    /// Physical (and virtual) base address
+    phys: usize,
+    /// Usable length in bytes
+    len: usize,
+    /// Pages backing the buffer
+    pages: usize,
+}
+
+impl DmaBuffer {
+    /// Allocate a zeroed buffer of `len` bytes
+    pub fn new(len: usize) -> Result<Self, &'static str> {
+        Self::with_alignment(len, PAGE_SIZE)
+    }
+
+    /// Allocate a zeroed buffer whose address is a multiple of `align` bytes
+    ///
+    /// `align` must be a power of two; anything up to a page is free.
+    pub fn with_alignment(len: usize, align: usize) -> Result<Self, &'static str> {
+        if len == 0 {
+            return Err("Empty DMA buffer");
+        }
+        if !align.is_power_of_two() {
+            return Err("DMA alignment not a power of two");
+        }
+        let pages = len.div_ceil(PAGE_SIZE);
+        let align_pages = (align / PAGE_SIZE).max(1);
+        let phys = pmm::alloc_contiguous(pages, align_pages).ok_or("No contiguous memory for DMA")?;
+        Ok(Self { phys, len, pages })
+    }
+
+    /// Physical address to program into the device
+    pub fn phys_addr(&self) -> u32 {
+        self.phys as u32
+    }
+
+    /// Length in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Physical address of `offset`, if it is inside the buffer
+    pub fn phys_at(&self, offset: usize) -> Option<u32> {
+        (offset < self.len).then(|| (self.phys + offset) as u32)
+    }
+
+    /// Copy `data` into the buffer at `offset`
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), &'static str> {
+        self.check(offset, data.len())?;
+        let base = (self.phys + offset) as *mut u8;
+        for (i, &byte) in data.iter().enumerate() {
+            unsafe { base.add(i).write_volatile(byte) };
+        }
+        Ok(())
+    }
+
+    /// Copy from the buffer at `offset` into `out`
+    pub fn read(&self, offset: usize, out: &mut [u8]) -> Result<(), &'static str> {
+        self.check(offset, out.len())?;
+        let base = (self.phys + offset) as *const u8;
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = unsafe { base.add(i).read_volatile() };
+        }
+        Ok(())
+    }
+
+    /// Read a little-endian u32 (descriptor and status words)
+    pub fn read_u32(&self, offset: usize) -> Result<u32, &'static str> {
+        self.check(offset, 4)?;
+        if offset % 4 != 0 {
+            return Err("Unaligned DMA access");
+        }
+        Ok(unsafe { ((self.phys + offset) as *const u32).read_volatile() })
+    }
+
+    /// Write a little-endian u32 (descriptor and status words)
+    pub fn write_u32(&mut self, offset: usize, value: u32) -> Result<(), &'static str> {
+        self.check(offset, 4)?;
+        if offset % 4 != 0 {
+            return Err("Unaligned DMA access");
+        }
+        unsafe { ((self.phys + offset) as *mut u32).write_volatile(value) };
+        Ok(())
+    }
+
+    /// Zero the whole buffer
+    pub fn clear(&mut self) {
+        unsafe { core::ptr::write_bytes(self.phys as *mut u8, 0, self.len) };
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result<(), &'static str> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(()),
+            _ => Err("DMA access out of bounds"),
+        }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        unsafe { pmm::free_contiguous(self.phys, self.pages) };
+    }
+}
@@ -3,6 +3,13 @@
 //! Uses EventChains to initialize hardware drivers in a fault-tolerant way.
 //! Optional drivers (GPU, touchpad) can fail without stopping boot.
 //! Required drivers (keyboard, basic display) must succeed.
+//!
+//! With `vfb` on the command line a virtual framebuffer (`vfb`) takes the
+//! place of the GPU, for headless test runs.
+//!
+//! In safe mode a chain filter drops the experimental drivers (ATI native
+//! GPU, AGP, Synaptics, IO-APIC), leaving VESA, the generic PS/2 mouse and
+//! the 8259 PIC.
 
 use crate::event_chains::{
     ChainableEvent, EventChain, EventContext, EventMiddleware,
@@ -21,6 +28,7 @@ pub mod gpu_type {
     pub const ATI_RAGE: u32 = 1;
     pub const VESA: u32 = 2;
     pub const VGA_TEXT: u32 = 3;
+    pub const VIRTUAL: u32 = 4;
 }
 
 /// Input types
@@ -46,6 +54,9 @@ pub mod context_keys {
     pub const FB_BPP: &str = "fb_bpp";
     pub const FB_PITCH: &str = "fb_pitch";
     pub const HW_CURSOR: &str = "hw_cursor";
+    pub const VBLANK_IRQ: &str = "vblank_irq";
+    pub const AGP_APERTURE: &str = "agp_aperture";
+    pub const WRITE_COMBINING: &str = "write_comb";
 
     // Input
     pub const INPUT_INITIALIZED: &str = "input_init";
@@ -56,12 +67,23 @@ pub mod context_keys {
     pub const SCREEN_WIDTH: &str = "scr_width";
     pub const SCREEN_HEIGHT: &str = "scr_height";
 
+    // Virtual framebuffer size (set only when one was asked for)
+    pub const VFB_WIDTH: &str = "vfb_w";
+    pub const VFB_HEIGHT: &str = "vfb_h";
+
     // VESA fallback info
     pub const VESA_FB_ADDR: &str = "vesa_addr";
     pub const VESA_WIDTH: &str = "vesa_w";
     pub const VESA_HEIGHT: &str = "vesa_h";
     pub const VESA_BPP: &str = "vesa_bpp";
     pub const VESA_PITCH: &str = "vesa_pitch";
+
+    // Interrupts
+    pub const APIC_ENABLED: &str = "apic";
+
+    // Boot options
+    pub const SAFE_MODE: &str = "safe_mode";
+    pub const NO_APIC: &str = "no_apic";
 }
 
 // =============================================================================
@@ -103,10 +125,58 @@ impl EventMiddleware for DependencyMiddleware {
     }
 }
 
+// =============================================================================
+// Safe Mode Filter
+// =============================================================================
+
+/// Events that may hang or misprogram real hardware
+const OPTIONAL_DRIVERS: [&str; 4] = ["ati_rage_probe", "agp_init", "synaptics_init", "apic_init"];
+
+/// Chain filter: skip optional drivers when booting in safe mode
+fn safe_mode_filter(event: &dyn ChainableEvent, context: &EventContext) -> bool {
+    !(context.get_bool(context_keys::SAFE_MODE).unwrap_or(false)
+        && OPTIONAL_DRIVERS.contains(&event.name()))
+}
+
 // =============================================================================
 // Driver Events
 // =============================================================================
 
+/// Virtual Framebuffer Event
+///
+/// Sets up a frame in RAM instead of probing the GPU when the command line
+/// asks for one.
+pub struct VirtualFbEvent;
+
+impl ChainableEvent for VirtualFbEvent {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let width = context.get_u32(context_keys::VFB_WIDTH).unwrap_or(0);
+        let height = context.get_u32(context_keys::VFB_HEIGHT).unwrap_or(0);
+        match crate::drivers::vfb::init(width, height) {
+            Ok((addr, pitch)) => {
+                context.set_bool(context_keys::GPU_INITIALIZED, true);
+                context.set_u32(context_keys::GPU_TYPE, gpu_type::VIRTUAL);
+                context.set_u32(context_keys::FB_ADDR, addr);
+                context.set_u32(context_keys::FB_WIDTH, width);
+                context.set_u32(context_keys::FB_HEIGHT, height);
+                context.set_u32(context_keys::FB_BPP, crate::drivers::vfb::BYTES_PER_PIXEL);
+                context.set_u32(context_keys::FB_PITCH, pitch);
+                context.set_bool(context_keys::HW_CURSOR, false);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "virtual_fb"
+    }
+
+    fn should_run(&self, context: &EventContext) -> bool {
+        context.get_u32(context_keys::VFB_WIDTH).is_some()
+    }
+}
+
 /// ATI Rage GPU Probe Event
 pub struct AtiRageProbeEvent;
 
@@ -129,6 +199,10 @@ impl ChainableEvent for AtiRageProbeEvent {
                             gpu.enable_hw_cursor();
                             context.set_bool(context_keys::HW_CURSOR, true);
 
+                            // Vblank IRQ is optional; polling works without it
+                            context.set_bool(context_keys::VBLANK_IRQ,
+                                             gpu.enable_vblank_irq().is_ok());
+
                             EventResult::success(())
                         }
                         Err(e) => EventResult::failure(e),
@@ -144,6 +218,45 @@ impl ChainableEvent for AtiRageProbeEvent {
     fn name(&self) -> &'static str {
         "ati_rage_probe"
     }
+
+    fn should_run(&self, context: &EventContext) -> bool {
+        // Not with a virtual display
+        !context.get_bool(context_keys::GPU_INITIALIZED).unwrap_or(false)
+    }
+}
+
+/// AGP Init Event
+///
+/// Sets up the host bridge aperture/GART and hands it to the GPU. Optional:
+/// without it the driver just works from VRAM.
+pub struct AgpInitEvent;
+
+impl ChainableEvent for AgpInitEvent {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        if let Err(e) = crate::drivers::agp::init() {
+            return EventResult::failure(e);
+        }
+        let gpu = match crate::drivers::ati_rage::get() {
+            Some(gpu) => gpu,
+            None => return EventResult::failure("GPU unavailable"),
+        };
+        match gpu.enable_agp() {
+            Ok(()) => {
+                let size = crate::drivers::agp::get().map(|a| a.aperture_size()).unwrap_or(0);
+                context.set_u32(context_keys::AGP_APERTURE, size);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "agp_init"
+    }
+
+    fn should_run(&self, context: &EventContext) -> bool {
+        context.get_u32(context_keys::GPU_TYPE) == Some(gpu_type::ATI_RAGE)
+    }
 }
 
 /// VESA Fallback Event
@@ -151,11 +264,6 @@ pub struct VesaFallbackEvent;
 
 impl ChainableEvent for VesaFallbackEvent {
     fn execute(&self, context: &mut EventContext) -> EventResult<()> {
-        // Skip if GPU already initialized
-        if context.get_bool(context_keys::GPU_INITIALIZED).unwrap_or(false) {
-            return EventResult::success(());
-        }
-
         // Use VESA info passed from bootloader
         let fb_addr = match context.get_u32(context_keys::VESA_FB_ADDR) {
             Some(v) => v,
@@ -193,6 +301,11 @@ impl ChainableEvent for VesaFallbackEvent {
     fn name(&self) -> &'static str {
         "vesa_fallback"
     }
+
+    fn should_run(&self, context: &EventContext) -> bool {
+        // Only needed if no native GPU driver came up
+        !context.get_bool(context_keys::GPU_INITIALIZED).unwrap_or(false)
+    }
 }
 
 /// Framebuffer Init Event
@@ -221,13 +334,30 @@ impl ChainableEvent for FramebufferInitEvent {
             None => return EventResult::failure("No framebuffer pitch"),
         };
 
+        // A virtual frame is already RAM in the identity map
+        let buffer = if context.get_u32(context_keys::GPU_TYPE) == Some(gpu_type::VIRTUAL) {
+            fb_addr
+        } else {
+            let buffer = match crate::mm::iomap_framebuffer(fb_addr, pitch * height) {
+                Ok(virt) => virt,
+                Err(e) => return EventResult::failure(e),
+            };
+
+            // Optional: drawing works uncached, just slowly
+            match crate::arch::x86::mtrr::set_write_combining(fb_addr, pitch * height) {
+                Ok(_) => context.set_bool(context_keys::WRITE_COMBINING, true),
+                Err(e) => crate::klog::write_fmt(format_args!("[DRV ] No write-combining: {}\n", e)),
+            }
+            buffer
+        };
+
         // Store screen dimensions for input drivers
         context.set_u32(context_keys::SCREEN_WIDTH, width);
         context.set_u32(context_keys::SCREEN_HEIGHT, height);
 
         unsafe {
             crate::gui::framebuffer::init(
-                fb_addr as *mut u8,
+                buffer as *mut u8,
                 width,
                 height,
                 bpp,
@@ -235,6 +365,11 @@ impl ChainableEvent for FramebufferInitEvent {
             );
         }
 
+        // Palettized modes draw with the fixed RGB332 palette
+        if bpp == 1 {
+            crate::gui::dither::load_palette();
+        }
+
         EventResult::success(())
     }
 
@@ -243,18 +378,45 @@ impl ChainableEvent for FramebufferInitEvent {
     }
 }
 
+/// IO-APIC Init Event
+///
+/// Moves device interrupts from the 8259 PIC to the IO-APIC. Skipped with
+/// the native ATI Rage driver: the Armada's PCI interrupt lines are routed
+/// for the PIC only, so it keeps the legacy path.
+pub struct ApicInitEvent;
+
+impl ChainableEvent for ApicInitEvent {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        match crate::arch::x86::irqchip::use_apic() {
+            Ok(()) => {
+                context.set_bool(context_keys::APIC_ENABLED, true);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "apic_init"
+    }
+
+    fn should_run(&self, context: &EventContext) -> bool {
+        !context.get_bool(context_keys::NO_APIC).unwrap_or(false)
+            && context.get_u32(context_keys::GPU_TYPE) != Some(gpu_type::ATI_RAGE)
+    }
+}
+
 /// Synaptics Touchpad Init Event
 pub struct SynapticsInitEvent;
 
 impl ChainableEvent for SynapticsInitEvent {
     fn execute(&self, context: &mut EventContext) -> EventResult<()> {
-        if context.get_bool(context_keys::INPUT_INITIALIZED).unwrap_or(false) {
-            return EventResult::success(());
-        }
-
         let width = context.get_u32(context_keys::SCREEN_WIDTH).unwrap_or(800);
         let height = context.get_u32(context_keys::SCREEN_HEIGHT).unwrap_or(600);
 
+        if let Err(e) = crate::drivers::keyboard::claim_controller() {
+            return EventResult::failure(e);
+        }
         match crate::drivers::synaptics::init(width, height) {
             Ok(()) => {
                 if crate::drivers::synaptics::is_synaptics() {
@@ -273,6 +435,10 @@ impl ChainableEvent for SynapticsInitEvent {
     fn name(&self) -> &'static str {
         "synaptics_init"
     }
+
+    fn should_run(&self, context: &EventContext) -> bool {
+        !context.get_bool(context_keys::INPUT_INITIALIZED).unwrap_or(false)
+    }
 }
 
 /// PS/2 Mouse Init Event (fallback)
@@ -280,13 +446,12 @@ pub struct Ps2MouseInitEvent;
 
 impl ChainableEvent for Ps2MouseInitEvent {
     fn execute(&self, context: &mut EventContext) -> EventResult<()> {
-        if context.get_bool(context_keys::INPUT_INITIALIZED).unwrap_or(false) {
-            return EventResult::success(());
-        }
-
         let width = context.get_u32(context_keys::SCREEN_WIDTH).unwrap_or(800);
         let height = context.get_u32(context_keys::SCREEN_HEIGHT).unwrap_or(600);
 
+        if let Err(e) = crate::drivers::keyboard::claim_controller() {
+            return EventResult::failure(e);
+        }
         crate::drivers::mouse::init(width, height);
 
         context.set_bool(context_keys::INPUT_INITIALIZED, true);
@@ -298,6 +463,10 @@ impl ChainableEvent for Ps2MouseInitEvent {
     fn name(&self) -> &'static str {
         "ps2_mouse_init"
     }
+
+    fn should_run(&self, context: &EventContext) -> bool {
+        !context.get_bool(context_keys::INPUT_INITIALIZED).unwrap_or(false)
+    }
 }
 
 /// Keyboard Init Event
@@ -305,6 +474,11 @@ pub struct KeyboardInitEvent;
 
 impl ChainableEvent for KeyboardInitEvent {
     fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        if let Err(e) = crate::drivers::keyboard::claim_controller() {
+            return EventResult::failure(e);
+        }
+        // Never fatal: decoding falls back to the translation default
+        crate::drivers::keyboard::init();
         context.set_bool(context_keys::KEYBOARD_INITIALIZED, true);
         EventResult::success(())
     }
@@ -314,19 +488,91 @@ impl ChainableEvent for KeyboardInitEvent {
     }
 }
 
+// =============================================================================
+// Driver Shutdown Events
+// =============================================================================
+
+/// GPU Shutdown Event
+///
+/// Lets the accelerator drain and hides the hardware cursor so the
+/// firmware comes back to a quiet display engine after reset.
+pub struct GpuShutdownEvent;
+
+impl ChainableEvent for GpuShutdownEvent {
+    fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
+        match crate::drivers::ati_rage::get() {
+            Some(gpu) if gpu.is_initialized() => {
+                gpu.wait_for_idle();
+                gpu.disable_vblank_irq();
+                gpu.disable_hw_cursor();
+                EventResult::success(())
+            }
+            _ => EventResult::success(()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "gpu_shutdown"
+    }
+}
+
+/// Input Shutdown Event
+///
+/// Stops the pointing device from streaming packets into the controller
+/// while the machine resets.
+pub struct InputShutdownEvent;
+
+impl ChainableEvent for InputShutdownEvent {
+    fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
+        unsafe {
+            use crate::arch::x86::io::{inb, outb};
+
+            let wait_input = || {
+                for _ in 0..0x10000 {
+                    if inb(0x64) & 0x02 == 0 {
+                        return true;
+                    }
+                }
+                false
+            };
+
+            // 0xD4: next byte goes to the aux device; 0xF5: disable reporting
+            if !wait_input() {
+                return EventResult::failure("Controller busy");
+            }
+            outb(0x64, 0xD4);
+            if !wait_input() {
+                return EventResult::failure("Controller busy");
+            }
+            outb(0x60, 0xF5);
+        }
+        EventResult::success(())
+    }
+
+    fn name(&self) -> &'static str {
+        "input_shutdown"
+    }
+}
+
 // =============================================================================
 // Global Event Instances
 // =============================================================================
 
+static VIRTUAL_FB: VirtualFbEvent = VirtualFbEvent;
 static ATI_RAGE_PROBE: AtiRageProbeEvent = AtiRageProbeEvent;
+static AGP_INIT: AgpInitEvent = AgpInitEvent;
 static VESA_FALLBACK: VesaFallbackEvent = VesaFallbackEvent;
 static FRAMEBUFFER_INIT: FramebufferInitEvent = FramebufferInitEvent;
+static APIC_INIT: ApicInitEvent = ApicInitEvent;
 static SYNAPTICS_INIT: SynapticsInitEvent = SynapticsInitEvent;
 static PS2_MOUSE_INIT: Ps2MouseInitEvent = Ps2MouseInitEvent;
 static KEYBOARD_INIT: KeyboardInitEvent = KeyboardInitEvent;
+static GPU_SHUTDOWN: GpuShutdownEvent = GpuShutdownEvent;
+static INPUT_SHUTDOWN: InputShutdownEvent = InputShutdownEvent;
 
 static LOGGING_MW: LoggingMiddleware = LoggingMiddleware::new();
 static DEPENDENCY_MW: DependencyMiddleware = DependencyMiddleware::new();
+static REPORT_MW: super::report::ReportMiddleware = super::report::ReportMiddleware::new();
 
 // =============================================================================
 // Public API
@@ -341,9 +587,22 @@ pub struct DriverInitResult {
     pub pitch: u32,
     pub gpu_type: u32,
     pub hw_cursor: bool,
+    /// AGP aperture size in bytes (0 = no AGP)
+    pub agp_aperture: u32,
+    /// GPU vblank interrupt available for frame pacing
+    pub vblank_irq: bool,
+    /// Framebuffer is write-combining (PAT or MTRR)
+    pub write_combining: bool,
+    /// Device interrupts go through the IO-APIC (otherwise the 8259 PIC)
+    pub apic: bool,
     pub input_type: u32,
     pub failures: [Option<&'static str>; 8],
     pub failure_count: usize,
+    /// Booted in safe mode
+    pub safe_mode: bool,
+    /// Drivers the safe mode filter skipped
+    pub skipped: [Option<&'static str>; 8],
+    pub skipped_count: usize,
 }
 
 impl DriverInitResult {
@@ -364,6 +623,7 @@ impl DriverInitResult {
             gpu_type::ATI_RAGE => "ATI Rage Mobility P",
             gpu_type::VESA => "VESA",
             gpu_type::VGA_TEXT => "VGA Text",
+            gpu_type::VIRTUAL => "Virtual framebuffer",
             _ => "Unknown",
         }
     }
@@ -381,14 +641,28 @@ impl DriverInitResult {
 }
 
 /// Initialize all drivers using EventChain
+///
+/// `safe_mode` skips the optional drivers (see `OPTIONAL_DRIVERS`),
+/// `no_apic` keeps device interrupts on the 8259 PIC and `virtual_fb`
+/// (a size) replaces the display with a virtual framebuffer.
 pub fn init_all_drivers(
     vesa_fb_addr: u32,
     vesa_width: u32,
     vesa_height: u32,
     vesa_bpp: u32,
     vesa_pitch: u32,
+    safe_mode: bool,
+    no_apic: bool,
+    virtual_fb: Option<(u32, u32)>,
 ) -> DriverInitResult {
     let mut context = EventContext::new();
+    context.set_bool(context_keys::SAFE_MODE, safe_mode);
+    context.set_bool(context_keys::NO_APIC, no_apic);
+    if let Some((width, height)) = virtual_fb {
+        context.set_u32(context_keys::VFB_WIDTH, width);
+        context.set_u32(context_keys::VFB_HEIGHT, height);
+    }
+    super::report::begin(safe_mode);
 
     // Set VESA fallback info
     context.set_u32(context_keys::VESA_FB_ADDR, vesa_fb_addr);
@@ -401,12 +675,17 @@ pub fn init_all_drivers(
     let chain = EventChain::new()
         .middleware(&LOGGING_MW)
         .middleware(&DEPENDENCY_MW)
+        .middleware(&REPORT_MW)      // Outermost: sees dependency failures too
+        .event(&VIRTUAL_FB)          // Virtual display if asked for
         .event(&ATI_RAGE_PROBE)      // Try native GPU first
+        .event(&AGP_INIT)            // AGP aperture for the native GPU
         .event(&VESA_FALLBACK)       // Fall back to VESA
         .event(&FRAMEBUFFER_INIT)    // Initialize framebuffer subsystem
+        .event(&APIC_INIT)           // IO-APIC, unless the GPU needs the PIC
         .event(&SYNAPTICS_INIT)      // Try Synaptics touchpad
         .event(&PS2_MOUSE_INIT)      // Fall back to PS/2 mouse
         .event(&KEYBOARD_INIT)       // Initialize keyboard
+        .with_filter(safe_mode_filter)
         .with_fault_tolerance(FaultToleranceMode::BestEffort);
 
     let result = chain.execute(&mut context);
@@ -421,8 +700,19 @@ pub fn init_all_drivers(
         }
     }
 
+    // Only the filter's skips are interesting; fallbacks skip themselves
+    let mut skipped: [Option<&'static str>; 8] = [None; 8];
+    let mut skipped_count = 0;
+    for name in result.skipped() {
+        super::report::record_skipped(name);
+        if safe_mode && OPTIONAL_DRIVERS.contains(&name) && skipped_count < 8 {
+            skipped[skipped_count] = Some(name);
+            skipped_count += 1;
+        }
+    }
+
     // Extract results
-    DriverInitResult {
+    let init = DriverInitResult {
         fb_addr: context.get_u32(context_keys::FB_ADDR).unwrap_or(vesa_fb_addr),
         width: context.get_u32(context_keys::FB_WIDTH).unwrap_or(vesa_width),
         height: context.get_u32(context_keys::FB_HEIGHT).unwrap_or(vesa_height),
@@ -430,8 +720,37 @@ pub fn init_all_drivers(
         pitch: context.get_u32(context_keys::FB_PITCH).unwrap_or(vesa_pitch),
         gpu_type: context.get_u32(context_keys::GPU_TYPE).unwrap_or(gpu_type::UNKNOWN),
         hw_cursor: context.get_bool(context_keys::HW_CURSOR).unwrap_or(false),
+        agp_aperture: context.get_u32(context_keys::AGP_APERTURE).unwrap_or(0),
+        vblank_irq: context.get_bool(context_keys::VBLANK_IRQ).unwrap_or(false),
+        write_combining: context.get_bool(context_keys::WRITE_COMBINING).unwrap_or(false),
+        apic: context.get_bool(context_keys::APIC_ENABLED).unwrap_or(false),
         input_type: context.get_u32(context_keys::INPUT_TYPE).unwrap_or(input_type::UNKNOWN),
         failures,
         failure_count,
-    }
+        safe_mode,
+        skipped,
+        skipped_count,
+    };
+    super::report::finish(init.gpu_type_str(), init.input_type_str());
+
+    // Every driver has claimed its ports by now
+    crate::arch::x86::ioport::enforce();
+    init
+}
+
+/// Run driver shutdown hooks before a reboot
+///
+/// Best effort: every hook runs even if an earlier one fails. Returns
+/// the number of hooks that failed.
+pub fn shutdown_all_drivers() -> usize {
+    let mut context = EventContext::new();
+
+    let chain = EventChain::new()
+        .middleware(&LOGGING_MW)
+        .event(&INPUT_SHUTDOWN)
+        .event(&GPU_SHUTDOWN)
+        .with_fault_tolerance(FaultToleranceMode::BestEffort);
+
+    let result = chain.execute(&mut context);
+    result.failures().count()
 }
@@ -1,9 +1,17 @@
 //! PS/2 Keyboard Driver
 //!
 //! Handles PS/2 keyboard input with a buffer for polling from main loop.
-//! The IRQ handler fills the buffer, main loop drains it.
+//! The IRQ handler only queues raw scancodes; the keyboard softirq decodes
+//! them into the key buffer, and the main loop drains that.
+//!
+//! Key codes follow scancode set 1, which is what the i8042 hands us when
+//! its set 2 -> set 1 translation is on (the firmware default). `init`
+//! puts the keyboard in set 2 and checks the controller's translation bit;
+//! without translation the raw set 2 bytes are translated here instead.
 
-use crate::arch::x86::io::inb;
+use crate::arch::x86::io::{inb, outb};
+use crate::util::RingBuffer;
+use super::accessibility::{AccessFilter, MOD_ALT, MOD_CTRL, MOD_SHIFT};
 
 /// Key event types
 #[derive(Debug, Clone, Copy)]
@@ -41,11 +49,26 @@ pub enum KeyCode {
     CapsLock = 0x3A,
     F1 = 0x3B, F2 = 0x3C, F3 = 0x3D, F4 = 0x3E, F5 = 0x3F,
     F6 = 0x40, F7 = 0x41, F8 = 0x42, F9 = 0x43, F10 = 0x44,
-    // Extended keys (0xE0 prefix)
+    F11 = 0x57, F12 = 0x58,
+    NumLock = 0x45, ScrollLock = 0x46,
+    KeypadMinus = 0x4A, KeypadPlus = 0x4E,
+    // Navigation: E0-prefixed keys, or the keypad with NumLock off
+    Home = 0x47,
     Up = 0x48,
+    PageUp = 0x49,
     Left = 0x4B,
     Right = 0x4D,
+    End = 0x4F,
     Down = 0x50,
+    PageDown = 0x51,
+    Insert = 0x52,
+    Delete = 0x53,
+    // Keypad with NumLock on (no set 1 code of their own)
+    Keypad0 = 0x60, Keypad1 = 0x61, Keypad2 = 0x62, Keypad3 = 0x63,
+    Keypad4 = 0x64, Keypad5 = 0x65, Keypad6 = 0x66, Keypad7 = 0x67,
+    Keypad8 = 0x68, Keypad9 = 0x69, KeypadPeriod = 0x6A,
+    // E0-prefixed keypad keys
+    KeypadSlash = 0x6B, KeypadEnter = 0x6C,
     Unknown = 0xFF,
 }
 
@@ -77,9 +100,61 @@ impl KeyCode {
             0x3B => Self::F1, 0x3C => Self::F2, 0x3D => Self::F3,
             0x3E => Self::F4, 0x3F => Self::F5, 0x40 => Self::F6,
             0x41 => Self::F7, 0x42 => Self::F8, 0x43 => Self::F9,
-            0x44 => Self::F10,
-            0x48 => Self::Up, 0x4B => Self::Left,
-            0x4D => Self::Right, 0x50 => Self::Down,
+            0x44 => Self::F10, 0x57 => Self::F11, 0x58 => Self::F12,
+            0x45 => Self::NumLock, 0x46 => Self::ScrollLock,
+            0x47 => Self::Home, 0x48 => Self::Up, 0x49 => Self::PageUp,
+            0x4A => Self::KeypadMinus, 0x4B => Self::Left,
+            0x4D => Self::Right, 0x4E => Self::KeypadPlus,
+            0x4F => Self::End, 0x50 => Self::Down, 0x51 => Self::PageDown,
+            0x52 => Self::Insert, 0x53 => Self::Delete,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Decode a set 1 scancode with its E0 prefix and the NumLock state
+    ///
+    /// The keypad's 0-9 and '.' share codes with the navigation keys; only
+    /// the E0 prefix tells the dedicated navigation block apart.
+    pub fn decode(scancode: u8, extended: bool, num_lock: bool) -> Self {
+        let code = scancode & 0x7F;
+        if extended {
+            return match code {
+                0x1C => Self::KeypadEnter,
+                0x35 => Self::KeypadSlash,
+                // Right Ctrl/Alt act as the left ones
+                0x1D => Self::LeftCtrl,
+                0x38 => Self::LeftAlt,
+                // Fake shifts around PrtScr and navigation keys
+                0x2A | 0x36 => Self::Unknown,
+                0x47..=0x53 => Self::from_scancode(code),
+                _ => Self::Unknown,
+            };
+        }
+        if !num_lock {
+            return match code {
+                // Keypad 5 has no navigation meaning
+                0x4C => Self::Unknown,
+                _ => Self::from_scancode(code),
+            };
+        }
+        match code {
+            0x47 => Self::Keypad7, 0x48 => Self::Keypad8, 0x49 => Self::Keypad9,
+            0x4B => Self::Keypad4, 0x4C => Self::Keypad5, 0x4D => Self::Keypad6,
+            0x4F => Self::Keypad1, 0x50 => Self::Keypad2, 0x51 => Self::Keypad3,
+            0x52 => Self::Keypad0, 0x53 => Self::KeypadPeriod,
+            _ => Self::from_scancode(code),
+        }
+    }
+
+    /// Key with the code `self as u8` gives (Unknown if none does)
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x60 => Self::Keypad0, 0x61 => Self::Keypad1, 0x62 => Self::Keypad2,
+            0x63 => Self::Keypad3, 0x64 => Self::Keypad4, 0x65 => Self::Keypad5,
+            0x66 => Self::Keypad6, 0x67 => Self::Keypad7, 0x68 => Self::Keypad8,
+            0x69 => Self::Keypad9, 0x6A => Self::KeypadPeriod,
+            0x6B => Self::KeypadSlash, 0x6C => Self::KeypadEnter,
+            0x00..=0x7F => Self::from_scancode(code),
             _ => Self::Unknown,
         }
     }
@@ -134,12 +209,78 @@ impl KeyCode {
             Self::Period => if shift { '>' } else { '.' },
             Self::Slash => if shift { '?' } else { '/' },
             Self::Space => ' ',
+            Self::Keypad0 => '0', Self::Keypad1 => '1', Self::Keypad2 => '2',
+            Self::Keypad3 => '3', Self::Keypad4 => '4', Self::Keypad5 => '5',
+            Self::Keypad6 => '6', Self::Keypad7 => '7', Self::Keypad8 => '8',
+            Self::Keypad9 => '9', Self::KeypadPeriod => '.',
+            Self::KeypadPlus => '+', Self::KeypadMinus => '-',
+            Self::KeypadAsterisk => '*', Self::KeypadSlash => '/',
             _ => return None,
         };
         Some(c)
     }
 }
 
+// =============================================================================
+// Scancode Sets
+// =============================================================================
+
+/// Scancode set arriving from the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    /// Set 1 (or set 2 translated by the controller)
+    Set1,
+    /// Untranslated set 2
+    Set2,
+}
+
+impl ScancodeSet {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Set1 => "set 1",
+            Self::Set2 => "set 2",
+        }
+    }
+}
+
+/// Set 2 make code -> set 1 make code, as the i8042 translator does it
+///
+/// Extended (E0) keys share the table: their set 2 codes map to the
+/// matching set 1 codes just like the plain keys. Unlisted codes are 0.
+const SET2_TO_SET1: [u8; 0x84] = {
+    const PAIRS: [(u8, u8); 91] = [
+        (0x76, 0x01), (0x16, 0x02), (0x1E, 0x03), (0x26, 0x04), (0x25, 0x05),
+        (0x2E, 0x06), (0x36, 0x07), (0x3D, 0x08), (0x3E, 0x09), (0x46, 0x0A),
+        (0x45, 0x0B), (0x4E, 0x0C), (0x55, 0x0D), (0x66, 0x0E), (0x0D, 0x0F),
+        (0x15, 0x10), (0x1D, 0x11), (0x24, 0x12), (0x2D, 0x13), (0x2C, 0x14),
+        (0x35, 0x15), (0x3C, 0x16), (0x43, 0x17), (0x44, 0x18), (0x4D, 0x19),
+        (0x54, 0x1A), (0x5B, 0x1B), (0x5A, 0x1C), (0x14, 0x1D), (0x1C, 0x1E),
+        (0x1B, 0x1F), (0x23, 0x20), (0x2B, 0x21), (0x34, 0x22), (0x33, 0x23),
+        (0x3B, 0x24), (0x42, 0x25), (0x4B, 0x26), (0x4C, 0x27), (0x52, 0x28),
+        (0x0E, 0x29), (0x12, 0x2A), (0x5D, 0x2B), (0x1A, 0x2C), (0x22, 0x2D),
+        (0x21, 0x2E), (0x2A, 0x2F), (0x32, 0x30), (0x31, 0x31), (0x3A, 0x32),
+        (0x41, 0x33), (0x49, 0x34), (0x4A, 0x35), (0x59, 0x36), (0x7C, 0x37),
+        (0x11, 0x38), (0x29, 0x39), (0x58, 0x3A), (0x05, 0x3B), (0x06, 0x3C),
+        (0x04, 0x3D), (0x0C, 0x3E), (0x03, 0x3F), (0x0B, 0x40), (0x83, 0x41),
+        (0x0A, 0x42), (0x01, 0x43), (0x09, 0x44), (0x77, 0x45), (0x7E, 0x46),
+        (0x6C, 0x47), (0x75, 0x48), (0x7D, 0x49), (0x7B, 0x4A), (0x6B, 0x4B),
+        (0x73, 0x4C), (0x74, 0x4D), (0x79, 0x4E), (0x69, 0x4F), (0x72, 0x50),
+        (0x7A, 0x51), (0x70, 0x52), (0x71, 0x53), (0x78, 0x57), (0x07, 0x58),
+        (0x61, 0x56), (0x1F, 0x5B), (0x27, 0x5C), (0x2F, 0x5D), (0x37, 0x5E),
+        (0x3F, 0x5F),
+    ];
+    let mut table = [0u8; 0x84];
+    let mut i = 0;
+    while i < PAIRS.len() {
+        table[PAIRS[i].0 as usize] = PAIRS[i].1;
+        i += 1;
+    }
+    table
+};
+
+/// Set 2 break prefix
+const SET2_BREAK: u8 = 0xF0;
+
 // =============================================================================
 // Key Buffer - filled by IRQ, drained by main loop
 // =============================================================================
@@ -152,19 +293,59 @@ pub struct BufferedKey {
     pub keycode: KeyCode,
     pub ascii: Option<char>,
     pub pressed: bool,
+    /// Ctrl was held when the key was pressed
+    pub ctrl: bool,
+    /// Alt was held when the key was pressed
+    pub alt: bool,
+    /// When the key was delivered (`pit::uptime_ms`)
+    pub time_ms: u32,
+}
+
+/// Progress of an LED update (0xED, then the LED byte, each ACKed)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LedCommand {
+    Idle,
+    /// 0xED sent, waiting for its ACK
+    AwaitCommandAck,
+    /// LED byte sent, waiting for its ACK
+    AwaitDataAck,
 }
 
+/// Resends allowed per byte before an LED update is abandoned
+const LED_MAX_RETRIES: u8 = 3;
+
+/// An update with no ACK for this long is abandoned (ms)
+const LED_ACK_TIMEOUT_MS: u32 = 100;
+
 /// Keyboard state with event buffer
 pub struct Keyboard {
     shift_pressed: bool,
     ctrl_pressed: bool,
     alt_pressed: bool,
     caps_lock: bool,
+    num_lock: bool,
+    scroll_lock: bool,
     extended: bool,  // E0 prefix seen
-    // Ring buffer for key events
-    buffer: [Option<BufferedKey>; KEY_BUFFER_SIZE],
-    write_idx: usize,
-    read_idx: usize,
+    /// LED update in flight
+    led_command: LedCommand,
+    /// LED byte being sent
+    led_sent: u8,
+    /// Resends of the current byte
+    led_retries: u8,
+    /// When the update started (uptime ms)
+    led_started_ms: u32,
+    /// Lock state changed while an update was in flight
+    led_pending: bool,
+    /// Updates abandoned after resends or a timeout
+    led_failures: u32,
+    /// Set the controller delivers
+    set: ScancodeSet,
+    /// Set 2 break (F0) prefix seen
+    set2_break: bool,
+    /// Sticky and slow keys
+    access: AccessFilter,
+    /// Key presses waiting for the main loop
+    buffer: RingBuffer<BufferedKey, KEY_BUFFER_SIZE>,
 }
 
 impl Keyboard {
@@ -174,15 +355,74 @@ impl Keyboard {
             ctrl_pressed: false,
             alt_pressed: false,
             caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
             extended: false,
-            buffer: [None; KEY_BUFFER_SIZE],
-            write_idx: 0,
-            read_idx: 0,
+            led_command: LedCommand::Idle,
+            led_sent: 0,
+            led_retries: 0,
+            led_started_ms: 0,
+            led_pending: false,
+            led_failures: 0,
+            set: ScancodeSet::Set1,
+            set2_break: false,
+            access: AccessFilter::new(),
+            buffer: RingBuffer::new(),
+        }
+    }
+
+    /// Scancode set being decoded
+    pub fn scancode_set(&self) -> ScancodeSet {
+        self.set
+    }
+
+    /// Decode bytes as the given set from now on
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        self.set = set;
+        self.set2_break = false;
+        self.extended = false;
+    }
+
+    /// Process a byte from the controller (called from IRQ handler)
+    pub fn process_scancode(&mut self, byte: u8) -> Option<KeyEvent> {
+        // Replies to our LED commands, not keys
+        if byte == KBD_ACK || byte == KBD_RESEND {
+            self.led_reply(byte);
+            return None;
+        }
+        match self.set {
+            ScancodeSet::Set1 => self.process_set1(byte),
+            ScancodeSet::Set2 => {
+                let scancode = self.translate_set2(byte)?;
+                self.process_set1(scancode)
+            }
+        }
+    }
+
+    /// Turn a set 2 byte into its set 1 equivalent (None for prefixes)
+    fn translate_set2(&mut self, byte: u8) -> Option<u8> {
+        match byte {
+            SET2_BREAK => {
+                self.set2_break = true;
+                None
+            }
+            // Prefixes pass through unchanged
+            0xE0 | 0xE1 => Some(byte),
+            _ => {
+                let released = core::mem::take(&mut self.set2_break);
+                let code = SET2_TO_SET1.get(byte as usize).copied().unwrap_or(0);
+                if code == 0 {
+                    // Acks, echo and keys we have no code for
+                    self.extended = false;
+                    return None;
+                }
+                Some(if released { code | 0x80 } else { code })
+            }
         }
     }
 
-    /// Process scancode (called from IRQ handler)
-    pub fn process_scancode(&mut self, scancode: u8) -> Option<KeyEvent> {
+    /// Process a set 1 scancode
+    fn process_set1(&mut self, scancode: u8) -> Option<KeyEvent> {
         // Handle E0 prefix for extended keys
         if scancode == 0xE0 {
             self.extended = true;
@@ -190,41 +430,39 @@ impl Keyboard {
         }
 
         let released = scancode & 0x80 != 0;
-        let keycode = KeyCode::from_scancode(scancode);
+        let keycode = KeyCode::decode(scancode, self.extended, self.num_lock);
+
+        self.extended = false;
+        let now_ms = crate::arch::x86::pit::uptime_ms();
 
         // Update modifier state
-        match keycode {
+        let modifier = match keycode {
             KeyCode::LeftShift | KeyCode::RightShift => {
                 self.shift_pressed = !released;
+                Some(MOD_SHIFT)
             }
             KeyCode::LeftCtrl => {
                 self.ctrl_pressed = !released;
+                Some(MOD_CTRL)
             }
             KeyCode::LeftAlt => {
                 self.alt_pressed = !released;
+                Some(MOD_ALT)
             }
-            KeyCode::CapsLock if !released => {
-                self.caps_lock = !self.caps_lock;
-            }
-            _ => {}
-        }
-
-        self.extended = false;
+            _ => None,
+        };
 
         // Buffer the key event for main loop
-        if !released {
-            let shift = self.shift_pressed ^ self.caps_lock;
-            let ascii = keycode.to_ascii(shift);
-
-            let key = BufferedKey {
-                keycode,
-                ascii,
-                pressed: true,
-            };
-
-            // Add to ring buffer
-            self.buffer[self.write_idx] = Some(key);
-            self.write_idx = (self.write_idx + 1) % KEY_BUFFER_SIZE;
+        if let Some(bit) = modifier {
+            self.access.modifier(bit, released, now_ms);
+            if !released {
+                self.push_key(keycode, 0, now_ms);
+            }
+        } else if let Some(keycode) = self.access.key(keycode, released, now_ms) {
+            self.toggle_lock(keycode);
+            let latched = self.access.latched();
+            self.access.consume_latches();
+            self.push_key(keycode, latched, now_ms);
         }
 
         if released {
@@ -234,15 +472,48 @@ impl Keyboard {
         }
     }
 
+    /// Flip a lock key's state
+    fn toggle_lock(&mut self, keycode: KeyCode) {
+        match keycode {
+            KeyCode::CapsLock => self.caps_lock = !self.caps_lock,
+            KeyCode::NumLock => self.num_lock = !self.num_lock,
+            KeyCode::ScrollLock => self.scroll_lock = !self.scroll_lock,
+            _ => return,
+        }
+        self.update_leds();
+    }
+
+    /// Queue a delivered key press with its modifiers (plus any latched)
+    fn push_key(&mut self, keycode: KeyCode, latched: u8, time_ms: u32) {
+        let shift = (self.shift_pressed || latched & MOD_SHIFT != 0) ^ self.caps_lock;
+        let key = BufferedKey {
+            keycode,
+            ascii: keycode.to_ascii(shift),
+            pressed: true,
+            ctrl: self.ctrl_pressed || latched & MOD_CTRL != 0,
+            alt: self.alt_pressed || latched & MOD_ALT != 0,
+            time_ms,
+        };
+
+        // Dropped (and counted) if the main loop has fallen behind
+        if !self.buffer.push(key) {
+            crate::klog_limited!(Warning, "input", "key buffer full, dropped {:?}", keycode);
+        }
+    }
+
     /// Get next key from buffer (called from main loop)
     pub fn get_key(&mut self) -> Option<BufferedKey> {
-        if self.read_idx == self.write_idx {
-            return None;  // Buffer empty
-        }
+        self.buffer.pop()
+    }
+
+    /// Key presses dropped because the buffer was full
+    pub fn overflows(&self) -> u32 {
+        self.buffer.overflows()
+    }
 
-        let key = self.buffer[self.read_idx].take();
-        self.read_idx = (self.read_idx + 1) % KEY_BUFFER_SIZE;
-        key
+    /// Most key presses ever waiting at once
+    pub fn high_water(&self) -> usize {
+        self.buffer.high_water()
     }
 
     /// Get ASCII for a keycode using current modifier state
@@ -251,15 +522,289 @@ impl Keyboard {
         keycode.to_ascii(shift)
     }
 
+    /// Is NumLock on?
+    pub fn num_lock(&self) -> bool {
+        self.num_lock
+    }
+
+    /// Is CapsLock on?
+    pub fn caps_lock(&self) -> bool {
+        self.caps_lock
+    }
+
+    /// Is ScrollLock on?
+    pub fn scroll_lock(&self) -> bool {
+        self.scroll_lock
+    }
+
+    /// Set all lock states at once (and the LEDs)
+    pub fn set_locks(&mut self, caps: bool, num: bool, scroll: bool) {
+        self.caps_lock = caps;
+        self.num_lock = num;
+        self.scroll_lock = scroll;
+        self.update_leds();
+    }
+
+    /// LED updates abandoned after resends or a timeout
+    pub fn led_failures(&self) -> u32 {
+        self.led_failures
+    }
+
+    /// LED byte for the 0xED command
+    fn led_state(&self) -> u8 {
+        let mut leds = 0;
+        if self.scroll_lock { leds |= LED_SCROLL_LOCK; }
+        if self.num_lock { leds |= LED_NUM_LOCK; }
+        if self.caps_lock { leds |= LED_CAPS_LOCK; }
+        leds
+    }
+
+    /// Start sending the lock state to the keyboard LEDs
+    ///
+    /// This runs from the IRQ handler, so it can't wait for the keyboard:
+    /// the ACKs arrive later as ordinary bytes and `led_reply` advances the
+    /// exchange. A change during an update is sent once it finishes.
+    fn update_leds(&mut self) {
+        let now = crate::arch::x86::pit::uptime_ms();
+        if self.led_command != LedCommand::Idle {
+            if now.wrapping_sub(self.led_started_ms) < LED_ACK_TIMEOUT_MS {
+                self.led_pending = true;
+                return;
+            }
+            // The keyboard never answered; start over
+            self.led_failures += 1;
+        }
+        self.led_pending = false;
+        self.led_sent = self.led_state();
+        self.led_retries = 0;
+        self.led_started_ms = now;
+        self.led_command = if write_data(KBD_CMD_SET_LEDS) {
+            LedCommand::AwaitCommandAck
+        } else {
+            self.led_failures += 1;
+            LedCommand::Idle
+        };
+    }
+
+    /// Advance an LED update on an ACK or resend request
+    fn led_reply(&mut self, reply: u8) {
+        let byte = match self.led_command {
+            LedCommand::Idle => return,
+            LedCommand::AwaitCommandAck => KBD_CMD_SET_LEDS,
+            LedCommand::AwaitDataAck => self.led_sent,
+        };
+
+        if reply == KBD_RESEND {
+            if self.led_retries < LED_MAX_RETRIES && write_data(byte) {
+                self.led_retries += 1;
+            } else {
+                self.led_failures += 1;
+                self.led_command = LedCommand::Idle;
+            }
+            return;
+        }
+
+        self.led_retries = 0;
+        match self.led_command {
+            LedCommand::AwaitCommandAck => {
+                self.led_command = if write_data(self.led_sent) {
+                    LedCommand::AwaitDataAck
+                } else {
+                    self.led_failures += 1;
+                    LedCommand::Idle
+                };
+            }
+            _ => {
+                self.led_command = LedCommand::Idle;
+                if self.led_pending || self.led_sent != self.led_state() {
+                    self.update_leds();
+                }
+            }
+        }
+    }
+
     /// Check if shift is pressed
     pub fn shift(&self) -> bool {
         self.shift_pressed
     }
+
+    /// Check if ctrl is pressed
+    pub fn ctrl(&self) -> bool {
+        self.ctrl_pressed
+    }
 }
 
 /// Global keyboard instance
 pub static mut KEYBOARD: Keyboard = Keyboard::new();
 
+/// Raw scancodes waiting for the keyboard softirq
+static SCANCODES: RingBuffer<u8, 64> = RingBuffer::new();
+
+/// Queue a scancode from the controller (IRQ1, or polling with interrupts
+/// disabled) and raise the keyboard softirq
+pub fn queue_scancode(byte: u8) {
+    SCANCODES.push(byte);
+    crate::softirq::raise(crate::softirq::Softirq::Keyboard);
+}
+
+/// Decode queued scancodes into key events (keyboard softirq)
+pub fn drain() {
+    while let Some(byte) = SCANCODES.pop() {
+        unsafe { KEYBOARD.process_scancode(byte); }
+    }
+}
+
+// =============================================================================
+// Controller Setup
+// =============================================================================
+
+const PS2_DATA: u16 = 0x60;
+const PS2_STATUS: u16 = 0x64;
+const PS2_COMMAND: u16 = 0x64;
+
+/// Controller command: read configuration byte
+const PS2_CMD_READ_CONFIG: u8 = 0x20;
+/// Configuration: set 2 -> set 1 translation enabled
+const CONFIG_TRANSLATE: u8 = 0x40;
+
+/// Keyboard command: set LEDs (followed by the LED byte)
+const KBD_CMD_SET_LEDS: u8 = 0xED;
+/// Keyboard command: get/set scancode set
+const KBD_CMD_SCANCODE_SET: u8 = 0xF0;
+const KBD_ACK: u8 = 0xFA;
+const KBD_RESEND: u8 = 0xFE;
+
+/// LED byte bits
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// BIOS data area keyboard flags (lock states)
+const BDA_KBD_FLAGS: usize = 0x417;
+const BDA_SCROLL_LOCK: u8 = 0x10;
+const BDA_NUM_LOCK: u8 = 0x20;
+const BDA_CAPS_LOCK: u8 = 0x40;
+
+/// Claim the 8042 data and status/command ports
+///
+/// Shared with the mouse and touchpad drivers, which use the same
+/// controller.
+pub fn claim_controller() -> Result<(), &'static str> {
+    crate::arch::x86::ioport::claim(PS2_DATA, 1, "i8042")?;
+    crate::arch::x86::ioport::claim(PS2_STATUS, 1, "i8042")
+}
+
+/// Spin limit for controller reads and writes
+const PS2_TIMEOUT: u32 = 100_000;
+
+fn wait_write() -> Result<(), &'static str> {
+    for _ in 0..PS2_TIMEOUT {
+        if unsafe { inb(PS2_STATUS) } & 0x02 == 0 {
+            return Ok(());
+        }
+    }
+    Err("PS/2 write timeout")
+}
+
+/// Write a byte to the keyboard without waiting for a reply
+fn write_data(byte: u8) -> bool {
+    if wait_write().is_err() {
+        return false;
+    }
+    unsafe { outb(PS2_DATA, byte); }
+    true
+}
+
+/// Read a keyboard byte, discarding any mouse data in the way
+fn read_byte() -> Result<u8, &'static str> {
+    for _ in 0..PS2_TIMEOUT {
+        let status = unsafe { inb(PS2_STATUS) };
+        if status & 0x01 != 0 {
+            let byte = unsafe { inb(PS2_DATA) };
+            if status & 0x20 == 0 {
+                return Ok(byte);
+            }
+        }
+    }
+    Err("PS/2 read timeout")
+}
+
+/// Send a byte to the keyboard and wait for its ACK, resending on request
+fn send(byte: u8) -> Result<(), &'static str> {
+    for _ in 0..=LED_MAX_RETRIES {
+        wait_write()?;
+        unsafe { outb(PS2_DATA, byte); }
+        match read_byte()? {
+            KBD_ACK => return Ok(()),
+            KBD_RESEND => continue,
+            _ => return Err("Keyboard did not ACK"),
+        }
+    }
+    Err("Keyboard kept asking for resend")
+}
+
+/// Ask the keyboard which set it is sending (raw 1-3)
+///
+/// With translation on the reply itself is translated (0x43/0x41/0x3F).
+fn query_set() -> Result<u8, &'static str> {
+    send(KBD_CMD_SCANCODE_SET)?;
+    send(0x00)?;
+    match read_byte()? {
+        0x01 | 0x43 => Ok(1),
+        0x02 | 0x41 => Ok(2),
+        0x03 | 0x3F => Ok(3),
+        _ => Err("Unknown scancode set reply"),
+    }
+}
+
+/// Put the keyboard in scancode set 2 and pick the matching decoder
+///
+/// Runs with IRQ1 masked so the handler can't swallow the replies. If the
+/// keyboard won't switch sets, whatever it reports is decoded; a keyboard
+/// that doesn't answer is assumed to follow the translation bit.
+pub fn init() -> ScancodeSet {
+    let irq_was_masked = crate::arch::x86::irqchip::mask() & (1 << 1) != 0;
+    crate::arch::x86::irqchip::disable_irq(1);
+
+    // Drop stale bytes
+    for _ in 0..16 {
+        if unsafe { inb(PS2_STATUS) } & 0x01 == 0 {
+            break;
+        }
+        let _ = unsafe { inb(PS2_DATA) };
+    }
+
+    let config = wait_write()
+        .map(|_| unsafe { outb(PS2_COMMAND, PS2_CMD_READ_CONFIG) })
+        .and_then(|_| read_byte());
+    let translated = config.map(|c| c & CONFIG_TRANSLATE != 0).unwrap_or(true);
+
+    let _ = send(KBD_CMD_SCANCODE_SET).and_then(|_| send(0x02));
+    let native = query_set().unwrap_or(2);
+
+    let set = match (translated, native) {
+        // The translator only understands set 2
+        (true, 2) => ScancodeSet::Set1,
+        (_, 1) => ScancodeSet::Set1,
+        _ => ScancodeSet::Set2,
+    };
+    unsafe { KEYBOARD.set_scancode_set(set); }
+
+    // Keep the lock states the BIOS left (it usually turns NumLock on)
+    let bios = unsafe { (BDA_KBD_FLAGS as *const u8).read_volatile() };
+    let keyboard = unsafe { &mut *core::ptr::addr_of_mut!(KEYBOARD) };
+    keyboard.caps_lock = bios & BDA_CAPS_LOCK != 0;
+    keyboard.num_lock = bios & BDA_NUM_LOCK != 0;
+    keyboard.scroll_lock = bios & BDA_SCROLL_LOCK != 0;
+    // IRQ1 is masked, so the LEDs can be set synchronously here
+    let _ = send(KBD_CMD_SET_LEDS).and_then(|_| send(keyboard.led_state()));
+
+    if !irq_was_masked {
+        crate::arch::x86::irqchip::enable_irq(1);
+    }
+    set
+}
+
 /// Read scancode directly (for polling, not recommended)
 pub fn read_scancode() -> u8 {
     unsafe { inb(0x60) }
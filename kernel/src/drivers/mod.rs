@@ -7,12 +7,20 @@
 
 pub mod vga;
 pub mod keyboard;
+pub mod accessibility;
 pub mod mouse;
+pub mod pci;
 pub mod ati_rage;
+pub mod agp;
 pub mod synaptics;
+pub mod serial;
+pub mod debugcon;
+pub mod vfb;
+pub mod dma;
 pub mod init;
+pub mod report;
 
 // Re-export common driver types
 pub use ati_rage::AtiRage;
 pub use synaptics::SynapticsTouchpad;
-pub use init::{init_all_drivers, DriverInitResult, gpu_type, input_type};
+pub use init::{init_all_drivers, shutdown_all_drivers, DriverInitResult, gpu_type, input_type};
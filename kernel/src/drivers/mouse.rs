@@ -1,8 +1,16 @@
 //! PS/2 Mouse Driver
 //!
-//! Handles PS/2 mouse input for the GUI.
+//! Handles PS/2 mouse input for the GUI. Bytes from the IRQ handler (or
+//! the main loop's controller poll) are queued raw with their arrival
+//! time and decoded by `poll`, so packet assembly never runs in interrupt
+//! context. A packet is stamped with the arrival of its first byte.
+//!
+//! Mice with a wheel are switched to IntelliMouse mode at init (the
+//! sample rate knock), which adds a fourth packet byte with the wheel
+//! movement; turns accumulate until `take_wheel`.
 
 use crate::arch::x86::io::{inb, outb};
+use crate::util::RingBuffer;
 
 /// PS/2 controller ports
 const PS2_DATA: u16 = 0x60;
@@ -21,6 +29,12 @@ const MOUSE_CMD_ENABLE: u8 = 0xF4;
 const MOUSE_CMD_DISABLE: u8 = 0xF5;
 const MOUSE_CMD_SET_DEFAULTS: u8 = 0xF6;
 const MOUSE_CMD_SET_SAMPLE_RATE: u8 = 0xF3;
+const MOUSE_CMD_GET_ID: u8 = 0xF2;
+
+/// Sample rates that unlock IntelliMouse mode, in order
+const WHEEL_KNOCK: [u8; 3] = [200, 100, 80];
+/// Device ID of a mouse in IntelliMouse mode
+const MOUSE_ID_WHEEL: u8 = 3;
 
 /// Mouse state
 pub struct Mouse {
@@ -31,12 +45,22 @@ pub struct Mouse {
     /// Button state (bit 0 = left, bit 1 = right, bit 2 = middle)
     pub buttons: u8,
     /// Packet buffer
-    packet: [u8; 3],
+    packet: [u8; 4],
     /// Current byte in packet
     packet_idx: u8,
+    /// Bytes per packet (4 with a wheel)
+    packet_len: u8,
+    /// Wheel notches not yet taken (positive = towards the user)
+    wheel: i32,
+    /// Arrival of the packet being assembled, then of the last one
+    /// complete (`pit::uptime_ms`)
+    packet_start_ms: u32,
+    pub last_packet_ms: u32,
     /// Screen bounds
     max_x: i32,
     max_y: i32,
+    /// Movement multiplier in half steps (2 = 1:1)
+    sensitivity: i32,
 }
 
 impl Mouse {
@@ -45,10 +69,15 @@ impl Mouse {
             x: 0,
             y: 0,
             buttons: 0,
-            packet: [0; 3],
+            packet: [0; 4],
             packet_idx: 0,
+            packet_len: 3,
+            wheel: 0,
+            packet_start_ms: 0,
+            last_packet_ms: 0,
             max_x: 800,
             max_y: 600,
+            sensitivity: 2,
         }
     }
     
@@ -60,20 +89,30 @@ impl Mouse {
         self.y = height as i32 / 2;
     }
     
-    /// Process a byte from the mouse
+    /// Set movement multiplier in half steps (2 = 1:1, clamped to at least 1)
+    pub fn set_sensitivity(&mut self, sensitivity: i32) {
+        self.sensitivity = sensitivity.max(1);
+    }
+    
+    /// Process a byte from the mouse that arrived at `time_ms`
     /// Returns true if a complete packet was processed
-    pub fn process_byte(&mut self, byte: u8) -> bool {
+    pub fn process_byte(&mut self, byte: u8, time_ms: u32) -> bool {
         // First byte must have bit 3 set (always 1)
         if self.packet_idx == 0 && (byte & 0x08) == 0 {
             // Out of sync, wait for valid first byte
+            crate::klog_limited!(Debug, "input", "mouse out of sync, dropped {:02X}", byte);
             return false;
         }
+        if self.packet_idx == 0 {
+            self.packet_start_ms = time_ms;
+        }
         
         self.packet[self.packet_idx as usize] = byte;
         self.packet_idx += 1;
         
-        if self.packet_idx >= 3 {
+        if self.packet_idx >= self.packet_len {
             self.packet_idx = 0;
+            self.last_packet_ms = self.packet_start_ms;
             self.process_packet();
             return true;
         }
@@ -81,7 +120,7 @@ impl Mouse {
         false
     }
     
-    /// Process a complete 3-byte packet
+    /// Process a complete packet
     fn process_packet(&mut self) {
         let flags = self.packet[0];
         let mut dx = self.packet[1] as i32;
@@ -103,12 +142,26 @@ impl Mouse {
             dy = 0;
         }
         
+        // Apply sensitivity
+        dx = dx * self.sensitivity / 2;
+        dy = dy * self.sensitivity / 2;
+        
         // Update position (Y is inverted in PS/2)
         self.x = (self.x + dx).max(0).min(self.max_x - 1);
         self.y = (self.y - dy).max(0).min(self.max_y - 1);
         
         // Update buttons
         self.buttons = flags & 0x07;
+        
+        // Wheel: signed 4-bit count in the low nibble of byte 4
+        if self.packet_len == 4 {
+            self.wheel += ((self.packet[3] << 4) as i8 >> 4) as i32;
+        }
+    }
+    
+    /// Does the mouse report a wheel?
+    pub fn has_wheel(&self) -> bool {
+        self.packet_len == 4
     }
     
     /// Check if left button is pressed
@@ -130,6 +183,31 @@ impl Mouse {
 /// Global mouse instance
 pub static mut MOUSE: Mouse = Mouse::new();
 
+/// Raw bytes and their arrival times waiting to be decoded (about 20 packets)
+static RX: RingBuffer<(u8, u32), 64> = RingBuffer::new();
+
+/// Queue a byte from the controller (IRQ12, or polling with interrupts
+/// disabled) and raise the mouse softirq
+pub fn queue_byte(byte: u8) {
+    RX.push((byte, crate::arch::x86::pit::uptime_ms()));
+    crate::softirq::raise(crate::softirq::Softirq::Mouse);
+}
+
+/// Decode queued bytes (mouse softirq); true if a complete packet was
+/// processed
+pub fn poll() -> bool {
+    let mut packet = false;
+    while let Some((byte, time_ms)) = RX.pop() {
+        packet |= unsafe { MOUSE.process_byte(byte, time_ms) };
+    }
+    packet
+}
+
+/// Bytes dropped because `poll` fell behind
+pub fn overflows() -> u32 {
+    RX.overflows()
+}
+
 /// Wait for PS/2 controller to be ready for reading
 fn wait_read() {
     let mut timeout = 100000u32;
@@ -166,6 +244,28 @@ fn mouse_read() -> u8 {
     unsafe { inb(PS2_DATA) }
 }
 
+/// Send a command and its argument, discarding the ACKs
+fn mouse_command(command: u8, arg: u8) {
+    mouse_write(command);
+    let _ = mouse_read();
+    mouse_write(arg);
+    let _ = mouse_read();
+}
+
+/// Try to switch the mouse to IntelliMouse (wheel) mode
+///
+/// Runs with interrupts off so the IRQ handler doesn't take the replies.
+fn enable_wheel() -> bool {
+    crate::arch::x86::idt::without_interrupts(|| {
+        for rate in WHEEL_KNOCK {
+            mouse_command(MOUSE_CMD_SET_SAMPLE_RATE, rate);
+        }
+        mouse_write(MOUSE_CMD_GET_ID);
+        let _ = mouse_read();
+        mouse_read() == MOUSE_ID_WHEEL
+    })
+}
+
 /// Initialize the PS/2 mouse
 pub fn init(screen_width: u32, screen_height: u32) {
     unsafe {
@@ -192,6 +292,10 @@ pub fn init(screen_width: u32, screen_height: u32) {
         wait_write();
         outb(PS2_DATA, status);
         
+        if enable_wheel() {
+            MOUSE.packet_len = 4;
+        }
+        
         // Try to enable the mouse without reset (gentler for trackpads)
         mouse_write(MOUSE_CMD_ENABLE);
         // Ignore response - some trackpads don't ACK properly
@@ -205,7 +309,7 @@ pub fn init(screen_width: u32, screen_height: u32) {
     }
 }
 
-/// Read and process mouse data (called from IRQ12 handler)
+/// Read and queue mouse data (called from IRQ12 handler)
 pub fn handle_irq() -> bool {
     // Check if data is from mouse (bit 5 of status)
     let status = unsafe { inb(PS2_STATUS) };
@@ -213,8 +317,8 @@ pub fn handle_irq() -> bool {
         return false; // Not mouse data
     }
     
-    let byte = unsafe { inb(PS2_DATA) };
-    unsafe { MOUSE.process_byte(byte) }
+    queue_byte(unsafe { inb(PS2_DATA) });
+    true
 }
 
 /// Get current mouse position
@@ -226,3 +330,13 @@ pub fn get_position() -> (i32, i32) {
 pub fn get_buttons() -> u8 {
     unsafe { MOUSE.buttons }
 }
+
+/// Wheel notches since the last call (positive = scroll down)
+pub fn take_wheel() -> i32 {
+    unsafe { core::mem::take(&mut *core::ptr::addr_of_mut!(MOUSE.wheel)) }
+}
+
+/// When the last complete packet arrived (`pit::uptime_ms`)
+pub fn last_packet_ms() -> u32 {
+    unsafe { MOUSE.last_packet_ms }
+}
@@ -0,0 +1,80 @@
+//! PCI Configuration Space Access
+//!
+//! Mechanism #1 (ports 0xCF8/0xCFC) config space helpers shared by the
+//! PCI device drivers.
+
+use crate::arch::x86::io::{inl, outl};
+
+/// PCI Configuration Space ports
+const PCI_CONFIG_ADDR: u16 = 0xCF8;
+const PCI_CONFIG_DATA: u16 = 0xCFC;
+
+/// Standard config space offsets
+pub mod offsets {
+    pub const VENDOR_DEVICE: u8 = 0x00;
+    pub const COMMAND: u8 = 0x04;
+    pub const STATUS: u8 = 0x06;
+    pub const CLASS_REV: u8 = 0x08;
+    pub const BAR0: u8 = 0x10;
+    pub const CAP_PTR: u8 = 0x34;
+    pub const INTERRUPT: u8 = 0x3C;
+}
+
+/// Capability IDs
+pub mod cap_id {
+    pub const AGP: u8 = 0x02;
+}
+
+/// Status register: capability list present
+const STATUS_CAP_LIST: u32 = 1 << 4;
+
+fn address(bus: u8, device: u8, func: u8, offset: u8) -> u32 {
+    0x80000000u32
+        | ((bus as u32) << 16)
+        | ((device as u32) << 11)
+        | ((func as u32) << 8)
+        | ((offset as u32) & 0xFC)
+}
+
+/// Read a dword from PCI configuration space
+pub unsafe fn config_read(bus: u8, device: u8, func: u8, offset: u8) -> u32 {
+    outl(PCI_CONFIG_ADDR, address(bus, device, func, offset));
+    inl(PCI_CONFIG_DATA)
+}
+
+/// Write a dword to PCI configuration space
+pub unsafe fn config_write(bus: u8, device: u8, func: u8, offset: u8, value: u32) {
+    outl(PCI_CONFIG_ADDR, address(bus, device, func, offset));
+    outl(PCI_CONFIG_DATA, value);
+}
+
+/// Read a byte from PCI configuration space
+pub unsafe fn config_read_u8(bus: u8, device: u8, func: u8, offset: u8) -> u8 {
+    (config_read(bus, device, func, offset) >> ((offset & 3) * 8)) as u8
+}
+
+/// Find a capability in a device's capability list
+///
+/// Returns the config space offset of the capability header.
+pub fn find_capability(bus: u8, device: u8, func: u8, id: u8) -> Option<u8> {
+    unsafe {
+        let status = config_read(bus, device, func, offsets::COMMAND) >> 16;
+        if status & STATUS_CAP_LIST == 0 {
+            return None;
+        }
+
+        let mut ptr = config_read_u8(bus, device, func, offsets::CAP_PTR) & 0xFC;
+        // Bounded walk in case of a malformed (looping) list
+        for _ in 0..48 {
+            if ptr < 0x40 {
+                return None;
+            }
+            let header = config_read(bus, device, func, ptr);
+            if (header & 0xFF) as u8 == id {
+                return Some(ptr);
+            }
+            ptr = ((header >> 8) & 0xFC) as u8;
+        }
+        None
+    }
+}
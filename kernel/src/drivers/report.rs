@@ -0,0 +1,177 @@
+//! Driver Init Report
+//!
+//! Keeps the outcome of the driver EventChain after boot: status, error
+//! and run time of every event. `ReportMiddleware` records executed
+//! events; events the chain skipped are added afterwards. Shown by
+//! /proc/drivers and the terminal `drivers` command.
+
+use alloc::string::String;
+use core::fmt::Write;
+use crate::event_chains::{
+    ChainableEvent, EventContext, EventMiddleware,
+    result::{ErrorMessage, EventResult},
+    middleware::NextHandler,
+};
+
+/// Maximum events recorded
+const MAX_RECORDS: usize = 16;
+
+/// Outcome of one driver event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Failed,
+    Skipped,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Failed => "FAILED",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+/// One event of the driver chain
+#[derive(Clone, Copy)]
+pub struct EventRecord {
+    pub name: &'static str,
+    pub status: Status,
+    /// Error message for failed events
+    pub error: Option<ErrorMessage>,
+    /// Time spent in the event (0 when skipped)
+    pub micros: u32,
+}
+
+/// Driver chain outcome
+pub struct DriverReport {
+    records: [Option<EventRecord>; MAX_RECORDS],
+    count: usize,
+    /// Uptime when the chain started
+    pub started_ms: u32,
+    /// Booted in safe mode
+    pub safe_mode: bool,
+    /// Selected drivers (set once the chain is done)
+    pub gpu: &'static str,
+    pub input: &'static str,
+}
+
+impl DriverReport {
+    const fn new() -> Self {
+        Self {
+            records: [None; MAX_RECORDS],
+            count: 0,
+            started_ms: 0,
+            safe_mode: false,
+            gpu: "none",
+            input: "none",
+        }
+    }
+
+    fn push(&mut self, record: EventRecord) {
+        if self.count < MAX_RECORDS {
+            self.records[self.count] = Some(record);
+            self.count += 1;
+        }
+    }
+
+    /// Recorded events, executed ones first in chain order
+    pub fn records(&self) -> impl Iterator<Item = &EventRecord> {
+        self.records[..self.count].iter().flatten()
+    }
+
+    /// Total time spent in driver events
+    pub fn total_micros(&self) -> u32 {
+        self.records().map(|r| r.micros).sum()
+    }
+}
+
+static mut REPORT: DriverReport = DriverReport::new();
+
+/// The driver init report
+pub fn get() -> &'static DriverReport {
+    unsafe { &*core::ptr::addr_of!(REPORT) }
+}
+
+fn report_mut() -> &'static mut DriverReport {
+    unsafe { &mut *core::ptr::addr_of_mut!(REPORT) }
+}
+
+/// Start a new report (the driver chain runs once per boot)
+pub fn begin(safe_mode: bool) {
+    let report = report_mut();
+    *report = DriverReport::new();
+    report.started_ms = crate::arch::x86::pit::uptime_ms();
+    report.safe_mode = safe_mode;
+}
+
+/// Record an event the chain skipped
+pub fn record_skipped(name: &'static str) {
+    report_mut().push(EventRecord { name, status: Status::Skipped, error: None, micros: 0 });
+}
+
+/// Record the drivers the chain settled on
+pub fn finish(gpu: &'static str, input: &'static str) {
+    let report = report_mut();
+    report.gpu = gpu;
+    report.input = input;
+}
+
+// =============================================================================
+// Middleware
+// =============================================================================
+
+/// Middleware that times each driver event and records its outcome
+pub struct ReportMiddleware;
+
+impl ReportMiddleware {
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+impl EventMiddleware for ReportMiddleware {
+    fn execute(
+        &self,
+        event: &dyn ChainableEvent,
+        context: &mut EventContext,
+        next: NextHandler<'_>,
+    ) -> EventResult<()> {
+        let start = crate::time::now_ns();
+        let result = next(context);
+        let micros = crate::time::now_ns().saturating_sub(start) / 1000;
+
+        report_mut().push(EventRecord {
+            name: event.name(),
+            status: if result.is_success() { Status::Ok } else { Status::Failed },
+            error: result.error_message().copied(),
+            micros: micros.min(u32::MAX as u64) as u32,
+        });
+        result
+    }
+
+    fn name(&self) -> &'static str {
+        "ReportMiddleware"
+    }
+}
+
+/// Format the report (for /proc/drivers and the terminal)
+pub fn report(out: &mut String) {
+    let report = get();
+    let _ = writeln!(out, "gpu: {}", report.gpu);
+    let _ = writeln!(out, "input: {}", report.input);
+    let set = unsafe { (*core::ptr::addr_of!(crate::drivers::keyboard::KEYBOARD)).scancode_set() };
+    let _ = writeln!(out, "keyboard: scancode {}", set.as_str());
+    let _ = writeln!(out, "safe mode: {}", if report.safe_mode { "yes" } else { "no" });
+    let _ = writeln!(out, "started: {} ms, took {} us", report.started_ms, report.total_micros());
+    let _ = writeln!(out, "status   time(us)  event");
+    for record in report.records() {
+        let _ = write!(out, "{:<8} {:<9} {}", record.status.as_str(), record.micros, record.name);
+        if let Some(error) = &record.error {
+            let _ = write!(out, ": {}", error);
+        }
+        let _ = writeln!(out);
+    }
+}
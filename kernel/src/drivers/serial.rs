@@ -0,0 +1,159 @@
+//! 16550 UART Serial Driver
+//!
+//! Polled output on COM1 for debugging and crash dumps. Transmit waits are
+//! bounded, so a missing UART never hangs the caller. Received bytes are
+//! queued by the IRQ4 handler once `enable_receive` has run.
+
+use crate::arch::x86::io::{inb, outb};
+use crate::util::RingBuffer;
+use core::fmt;
+
+/// COM1 base port
+pub const COM1: u16 = 0x3F8;
+
+/// COM1 interrupt line
+const COM1_IRQ: u8 = 4;
+
+// Register offsets
+const DATA: u16 = 0;
+const INT_ENABLE: u16 = 1;
+const FIFO_CTRL: u16 = 2;
+const LINE_CTRL: u16 = 3;
+const MODEM_CTRL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+// Read side of FIFO_CTRL
+const INT_IDENT: u16 = 2;
+
+/// Line status: transmit holding register empty
+const LSR_THR_EMPTY: u8 = 0x20;
+/// Line status: received data ready
+const LSR_DATA_READY: u8 = 0x01;
+
+/// Interrupt enable: received data available
+const IER_RX_AVAILABLE: u8 = 0x01;
+/// Interrupt ident: no interrupt pending
+const IIR_NONE_PENDING: u8 = 0x01;
+/// Modem control: DTR + RTS + OUT2 (OUT2 gates the IRQ line)
+const MCR_IRQ_ENABLE: u8 = 0x0B;
+
+/// Spin limit while waiting for the transmitter
+const TX_TIMEOUT: u32 = 100_000;
+
+/// Serial port
+pub struct SerialPort {
+    base: u16,
+    present: bool,
+}
+
+impl SerialPort {
+    pub const fn new(base: u16) -> Self {
+        Self { base, present: false }
+    }
+
+    /// Initialize at 38400 baud, 8N1, FIFOs enabled
+    ///
+    /// Returns false if no UART responds at this port.
+    pub fn init(&mut self) -> bool {
+        unsafe {
+            // Probe via the scratch register
+            outb(self.base + SCRATCH, 0xA5);
+            if inb(self.base + SCRATCH) != 0xA5 {
+                self.present = false;
+                return false;
+            }
+
+            outb(self.base + INT_ENABLE, 0x00);   // Disable interrupts
+            outb(self.base + LINE_CTRL, 0x80);    // Enable DLAB
+            outb(self.base + DATA, 0x03);         // Divisor low (38400 baud)
+            outb(self.base + INT_ENABLE, 0x00);   // Divisor high
+            outb(self.base + LINE_CTRL, 0x03);    // 8 bits, no parity, 1 stop
+            outb(self.base + FIFO_CTRL, 0xC7);    // Enable + clear FIFOs, 14-byte threshold
+            outb(self.base + MODEM_CTRL, 0x03);   // DTR + RTS
+        }
+        self.present = true;
+        true
+    }
+
+    /// Is a UART present?
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Write one byte (dropped if the UART is absent or stuck)
+    pub fn write_byte(&mut self, byte: u8) {
+        if !self.present {
+            return;
+        }
+        unsafe {
+            for _ in 0..TX_TIMEOUT {
+                if inb(self.base + LINE_STATUS) & LSR_THR_EMPTY != 0 {
+                    outb(self.base + DATA, byte);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Write a byte slice
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.write_byte(b);
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Global COM1 instance
+pub static mut SERIAL: SerialPort = SerialPort::new(COM1);
+
+/// Bytes received on COM1 and not yet read
+static RX: RingBuffer<u8, 256> = RingBuffer::new();
+
+/// Initialize COM1
+pub fn init() -> bool {
+    let present = unsafe { SERIAL.init() };
+    present && crate::arch::x86::ioport::claim(COM1, 8, "serial").is_ok()
+}
+
+/// Start interrupt-driven receive on COM1 (after the IDT is up)
+pub fn enable_receive() -> Result<(), &'static str> {
+    if !unsafe { SERIAL.is_present() } {
+        return Err("No serial port");
+    }
+    crate::arch::x86::idt::register_irq_handler(COM1_IRQ, "serial", irq_handler)?;
+    unsafe {
+        outb(COM1 + MODEM_CTRL, MCR_IRQ_ENABLE);
+        outb(COM1 + INT_ENABLE, IER_RX_AVAILABLE);
+    }
+    Ok(())
+}
+
+/// Drain the receive FIFO into the ring
+fn irq_handler(_irq: u8) -> bool {
+    unsafe {
+        if inb(COM1 + INT_IDENT) & IIR_NONE_PENDING != 0 {
+            return false;
+        }
+        while inb(COM1 + LINE_STATUS) & LSR_DATA_READY != 0 {
+            RX.push(inb(COM1 + DATA));
+        }
+    }
+    true
+}
+
+/// Next received byte, if any
+pub fn read_byte() -> Option<u8> {
+    RX.pop()
+}
+
+/// Received bytes dropped because nobody read them in time
+pub fn rx_overflows() -> u32 {
+    RX.overflows()
+}
@@ -1,13 +1,26 @@
 //! Synaptics PS/2 TouchPad Driver - Simplified
 //!
 //! Uses relative mode for reliability on vintage hardware.
+//!
+//! Waits on the controller go through `sched::wait::yield_until`, so the
+//! half-second reset doesn't hold up interrupts or other tasks.
 
 use crate::arch::x86::io::{inb, outb};
+use crate::sched::wait::{yield_until, Deadline};
+use crate::util::RingBuffer;
 
 const PS2_DATA: u16 = 0x60;
 const PS2_STATUS: u16 = 0x64;
 const PS2_COMMAND: u16 = 0x64;
 
+/// How long the controller gets to empty its input buffer
+const PS2_WRITE_TIMEOUT_MS: u32 = 10;
+
+/// Status register: output buffer full (a byte to read)
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+/// Status register: input buffer full (still busy with the last write)
+const STATUS_INPUT_FULL: u8 = 0x02;
+
 /// Touchpad driver (relative mode for reliability)
 pub struct SynapticsTouchpad {
     pub is_initialized: bool,
@@ -19,7 +32,12 @@ pub struct SynapticsTouchpad {
     cursor_x: i32,
     cursor_y: i32,
     buttons: u8,
+    /// Movement multiplier in half steps (2 = 1:1), as for the PS/2 mouse
     sensitivity: i32,
+    /// Arrival of the packet being assembled, then of the last one
+    /// complete (`pit::uptime_ms`)
+    packet_start_ms: u32,
+    last_packet_ms: u32,
 }
 
 impl SynapticsTouchpad {
@@ -34,10 +52,17 @@ impl SynapticsTouchpad {
             cursor_x: 400,
             cursor_y: 300,
             buttons: 0,
-            sensitivity: 2, // Lower = less sensitive
+            sensitivity: 2,
+            packet_start_ms: 0,
+            last_packet_ms: 0,
         }
     }
 
+    /// Set movement multiplier in half steps (2 = 1:1, clamped to at least 1)
+    pub fn set_sensitivity(&mut self, sensitivity: i32) {
+        self.sensitivity = sensitivity.max(1);
+    }
+
     pub fn set_screen_size(&mut self, width: u32, height: u32) {
         self.screen_width = width;
         self.screen_height = height;
@@ -109,6 +134,7 @@ impl SynapticsTouchpad {
                 // Out of sync, skip this byte
                 return false;
             }
+            self.packet_start_ms = crate::arch::x86::pit::uptime_ms();
         }
 
         self.packet[self.packet_idx] = byte;
@@ -116,6 +142,7 @@ impl SynapticsTouchpad {
 
         if self.packet_idx >= 3 {
             self.packet_idx = 0;
+            self.last_packet_ms = self.packet_start_ms;
             self.parse_packet();
             return true;
         }
@@ -141,8 +168,8 @@ impl SynapticsTouchpad {
         self.buttons = flags & 0x07;
 
         // Apply movement with sensitivity scaling
-        self.cursor_x += dx * self.sensitivity;
-        self.cursor_y -= dy * self.sensitivity; // Y is inverted
+        self.cursor_x += dx * self.sensitivity / 2;
+        self.cursor_y -= dy * self.sensitivity / 2; // Y is inverted
 
         // Clamp to screen
         self.cursor_x = self.cursor_x.max(0).min(self.screen_width as i32 - 1);
@@ -157,6 +184,11 @@ impl SynapticsTouchpad {
         self.buttons
     }
 
+    /// When the last complete packet arrived (`pit::uptime_ms`)
+    pub fn last_packet_ms(&self) -> u32 {
+        self.last_packet_ms
+    }
+
     pub fn is_synaptics(&self) -> bool {
         self.is_synaptics
     }
@@ -166,21 +198,9 @@ impl SynapticsTouchpad {
     // =========================================================================
 
     fn ps2_wait_write(&self) -> Result<(), &'static str> {
-        for _ in 0..10000 {
-            if unsafe { inb(PS2_STATUS) } & 0x02 == 0 {
-                return Ok(());
-            }
-        }
-        Err("PS/2 write timeout")
-    }
-
-    fn ps2_wait_read(&self) -> Result<(), &'static str> {
-        for _ in 0..10000 {
-            if unsafe { inb(PS2_STATUS) } & 0x01 != 0 {
-                return Ok(());
-            }
-        }
-        Err("PS/2 read timeout")
+        let ready = yield_until(Deadline::after_ms(PS2_WRITE_TIMEOUT_MS),
+            || unsafe { inb(PS2_STATUS) } & STATUS_INPUT_FULL == 0);
+        if ready { Ok(()) } else { Err("PS/2 write timeout") }
     }
 
     fn ps2_command(&mut self, cmd: u8) -> Result<(), &'static str> {
@@ -196,13 +216,9 @@ impl SynapticsTouchpad {
     }
 
     fn ps2_read_timeout(&mut self, ms: u32) -> Result<u8, &'static str> {
-        for _ in 0..(ms * 1000) {
-            if unsafe { inb(PS2_STATUS) } & 0x01 != 0 {
-                return Ok(unsafe { inb(PS2_DATA) });
-            }
-            for _ in 0..100 { unsafe { core::arch::asm!("nop"); } }
-        }
-        Err("PS/2 read timeout")
+        let ready = yield_until(Deadline::after_ms(ms),
+            || unsafe { inb(PS2_STATUS) } & STATUS_OUTPUT_FULL != 0);
+        if ready { Ok(unsafe { inb(PS2_DATA) }) } else { Err("PS/2 read timeout") }
     }
 
     fn aux_command(&mut self, cmd: u8) -> Result<(), &'static str> {
@@ -242,6 +258,10 @@ pub fn get_buttons() -> u8 {
     unsafe { TOUCHPAD.get_buttons() }
 }
 
+pub fn last_packet_ms() -> u32 {
+    unsafe { TOUCHPAD.last_packet_ms() }
+}
+
 pub fn is_synaptics() -> bool {
     unsafe { TOUCHPAD.is_synaptics() }
 }
@@ -250,6 +270,19 @@ pub fn is_initialized() -> bool {
     unsafe { TOUCHPAD.is_initialized }
 }
 
-pub fn handle_irq_byte(byte: u8) -> bool {
-    unsafe { TOUCHPAD.process_byte(byte) }
+/// Raw bytes waiting for the mouse softirq (about 10 packets)
+static RX: RingBuffer<u8, 64> = RingBuffer::new();
+
+/// Queue a byte from the controller (IRQ12, or polling with interrupts
+/// disabled) and raise the mouse softirq
+pub fn queue_byte(byte: u8) {
+    RX.push(byte);
+    crate::softirq::raise(crate::softirq::Softirq::Mouse);
+}
+
+/// Decode queued bytes (mouse softirq)
+pub fn drain() {
+    while let Some(byte) = RX.pop() {
+        unsafe { TOUCHPAD.process_byte(byte); }
+    }
 }
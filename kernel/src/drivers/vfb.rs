@@ -0,0 +1,236 @@
+//! Virtual Framebuffer
+//!
+//! A display with no hardware behind it, so the GUI can run headless in
+//! CI: boot with `vfb` (or `vfb=<w>x<h>`, at most 800x600) and the driver
+//! chain gives the desktop a 32-bit frame in ordinary RAM instead of the
+//! GPU's. Nothing is shown; what was drawn is checked at checkpoints.
+//!
+//! A checkpoint sends the CRC-32 of the frame to COM1:
+//!
+//! ```text
+//! vfb: checkpoint desktop 800x600 crc32=1c291ca3
+//! ```
+//!
+//! With `vfbshot` on the command line each checkpoint also sends the frame
+//! as a binary PPM in base64, between `--- vfb shot <name> begin ---` and
+//! `--- vfb shot end ---` lines, for the host to decode and diff.
+//!
+//! A checkpoint is taken after the next frame is drawn: `desktop` after
+//! the first one, `replay` when an input replay (`gui::recorder`) ends,
+//! and any name with `vfb checkpoint <name>` (which a replay can type into
+//! the terminal). The command works on any display, but a CRC only means
+//! something if the frame is the same every run. The virtual display
+//! makes sure it is: there is no hardware cursor, and the wall clock is
+//! pinned to `PINNED_TIME` so the taskbar clock reads the same.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::drivers::serial::{SerialPort, SERIAL};
+use crate::gui::Framebuffer;
+use crate::mm::pmm::{self, PageKind, PAGE_SIZE};
+
+/// Size used for plain `vfb`
+pub const DEFAULT_WIDTH: u32 = 800;
+pub const DEFAULT_HEIGHT: u32 = 600;
+
+/// Bytes per pixel (Xrgb8888)
+pub const BYTES_PER_PIXEL: u32 = 4;
+
+/// Largest frame (the GUI's back buffer is this size)
+const MAX_BYTES: u32 = DEFAULT_WIDTH * DEFAULT_HEIGHT * BYTES_PER_PIXEL;
+
+/// Wall clock while the virtual display is up (2000-01-01 00:00 UTC)
+pub const PINNED_TIME: u32 = 946_684_800;
+
+/// Checkpoints waiting for the next frame
+const MAX_PENDING: usize = 8;
+
+/// Frame memory
+struct Vfb {
+    width: u32,
+    height: u32,
+}
+
+static mut VFB: Option<Vfb> = None;
+
+/// Send screenshots with checkpoints (`vfbshot`)
+static SHOTS: AtomicBool = AtomicBool::new(false);
+
+/// Checkpoint names requested, taken by `poll`
+static mut PENDING: Vec<String> = Vec::new();
+
+/// Size from a `vfb=<w>x<h>` option
+pub fn parse_size(text: &str) -> Option<(u32, u32)> {
+    let (w, h) = text.split_once('x')?;
+    Some((w.parse().ok()?, h.parse().ok()?))
+}
+
+/// Allocate a `width` x `height` frame; returns its address and pitch
+pub fn init(width: u32, height: u32) -> Result<(u32, u32), &'static str> {
+    if is_active() {
+        return Err("Virtual framebuffer already set up");
+    }
+    let pitch = width * BYTES_PER_PIXEL;
+    if width == 0 || height == 0 || width.saturating_mul(height) > MAX_BYTES / BYTES_PER_PIXEL {
+        return Err("Virtual framebuffer size out of range");
+    }
+
+    // Physically contiguous, so it sits in the identity map like the
+    // kernel's other buffers
+    let bytes = (pitch * height) as usize;
+    let order = bytes.div_ceil(PAGE_SIZE).next_power_of_two().trailing_zeros();
+    let base = pmm::alloc_pages(order, PageKind::General).ok_or("No memory for virtual framebuffer")?;
+    unsafe {
+        core::ptr::write_bytes(base as *mut u8, 0, bytes);
+        *core::ptr::addr_of_mut!(VFB) = Some(Vfb { width, height });
+    }
+
+    crate::time::set_realtime(PINNED_TIME);
+    Ok((base as u32, pitch))
+}
+
+/// Is the display virtual?
+pub fn is_active() -> bool {
+    unsafe { (*core::ptr::addr_of!(VFB)).is_some() }
+}
+
+/// Size of the virtual display, if there is one
+pub fn size() -> Option<(u32, u32)> {
+    unsafe { (*core::ptr::addr_of!(VFB)).as_ref().map(|vfb| (vfb.width, vfb.height)) }
+}
+
+// =============================================================================
+// Checkpoints
+// =============================================================================
+
+pub fn set_screenshots(on: bool) {
+    SHOTS.store(on, Ordering::Relaxed);
+}
+
+pub fn screenshots() -> bool {
+    SHOTS.load(Ordering::Relaxed)
+}
+
+/// Take a checkpoint called `name` after the next frame
+pub fn request_checkpoint(name: &str) -> Result<(), &'static str> {
+    let pending = unsafe { &mut *core::ptr::addr_of_mut!(PENDING) };
+    if pending.len() == MAX_PENDING {
+        return Err("Too many checkpoints pending");
+    }
+    pending.push(String::from(name));
+    Ok(())
+}
+
+/// Take the checkpoints requested (after a frame is drawn)
+pub fn poll() {
+    let pending = unsafe { &mut *core::ptr::addr_of_mut!(PENDING) };
+    if pending.is_empty() {
+        return;
+    }
+    for name in core::mem::take(pending) {
+        checkpoint(&name);
+    }
+}
+
+/// Report the CRC of the frame on screen now (and a screenshot if
+/// enabled); None without a framebuffer
+pub fn checkpoint(name: &str) -> Option<u32> {
+    let fb = crate::gui::framebuffer::get()?;
+    let crc = frame_crc(fb);
+    let mut line = String::new();
+    let _ = write!(line, "vfb: checkpoint {} {}x{} crc32={:08x}\r\n", name, fb.width, fb.height, crc);
+
+    let serial = unsafe { &mut *core::ptr::addr_of_mut!(SERIAL) };
+    serial.write_bytes(line.as_bytes());
+    if screenshots() {
+        send_shot(serial, fb, name);
+    }
+    crate::klog!(Info, "vfb", "checkpoint {}: crc32={:08x}", name, crc);
+    Some(crc)
+}
+
+/// CRC-32 of the visible pixels as stored (pitch padding left out)
+pub fn frame_crc(fb: &Framebuffer) -> u32 {
+    let row_bytes = (fb.width * fb.bpp) as usize;
+    let mut crc = crate::util::crc32::Crc32::new();
+    for y in 0..fb.height as usize {
+        let row = unsafe {
+            core::slice::from_raw_parts(fb.buffer().add(y * fb.pitch as usize), row_bytes)
+        };
+        crc.update(row);
+    }
+    crc.finish()
+}
+
+/// Send the frame as a base64 PPM
+fn send_shot(serial: &mut SerialPort, fb: &Framebuffer, name: &str) {
+    let mut header = String::new();
+    let _ = write!(header, "--- vfb shot {} begin ---\r\n", name);
+    serial.write_bytes(header.as_bytes());
+
+    header.clear();
+    let _ = write!(header, "P6\n{} {}\n255\n", fb.width, fb.height);
+    let mut out = Base64Out { serial, pending: [0; 3], len: 0, column: 0 };
+    out.write(header.as_bytes());
+    for y in 0..fb.height as i32 {
+        for x in 0..fb.width as i32 {
+            let c = fb.get_pixel(x, y).unwrap_or(crate::gui::Color::BLACK);
+            out.write(&[c.r, c.g, c.b]);
+        }
+    }
+    out.finish();
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Characters per base64 line
+const BASE64_LINE: usize = 76;
+
+/// Streams base64 to the serial port in lines
+struct Base64Out<'a> {
+    serial: &'a mut SerialPort,
+    pending: [u8; 3],
+    len: usize,
+    column: usize,
+}
+
+impl Base64Out<'_> {
+    fn write(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.pending[self.len] = byte;
+            self.len += 1;
+            if self.len == 3 {
+                self.emit();
+            }
+        }
+    }
+
+    /// Encode the pending bytes (padding a short group with '=')
+    fn emit(&mut self) {
+        let [a, b, c] = self.pending;
+        let group = (a as u32) << 16 | (b as u32) << 8 | c as u32;
+        for i in 0..4 {
+            let ch = if i <= self.len { BASE64[(group >> (18 - 6 * i) & 0x3F) as usize] } else { b'=' };
+            self.serial.write_byte(ch);
+        }
+        self.pending = [0; 3];
+        self.len = 0;
+        self.column += 4;
+        if self.column == BASE64_LINE {
+            self.serial.write_bytes(b"\r\n");
+            self.column = 0;
+        }
+    }
+
+    fn finish(mut self) {
+        if self.len > 0 {
+            self.emit();
+        }
+        if self.column > 0 {
+            self.serial.write_bytes(b"\r\n");
+        }
+        self.serial.write_bytes(b"--- vfb shot end ---\r\n");
+    }
+}
@@ -4,6 +4,7 @@
 //! This is the foundation for the Plan 9-style GUI.
 
 use core::fmt;
+use crate::gui::fbcon::FbCon;
 
 /// VGA text mode colors
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -38,32 +39,20 @@ impl ColorCode {
     }
 }
 
-/// Display mode
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum DisplayMode {
-    /// VGA 80x25 text mode
-    TextMode,
-    /// VESA linear framebuffer
-    Framebuffer,
-}
-
 /// Display writer - unified interface for text and graphics
+///
+/// Text mode writes character cells at 0xB8000 directly; graphics mode
+/// delegates to the framebuffer console (`gui::fbcon`).
 pub struct Writer {
-    mode: DisplayMode,
     // Text mode state
     column: usize,
     row: usize,
     color: ColorCode,
-    // Display dimensions
     width: usize,
     height: usize,
-    // Framebuffer info (for VESA mode)
-    framebuffer: *mut u8,
-    pitch: usize,
-    bpp: usize,
-    // Font for framebuffer text rendering
-    char_width: usize,
-    char_height: usize,
+    buffer: *mut u16,
+    /// Framebuffer console (graphics mode)
+    fbcon: Option<FbCon>,
 }
 
 /// Global writer instance
@@ -73,59 +62,42 @@ impl Writer {
     /// Create a new text mode writer
     pub fn text_mode() -> Self {
         Self {
-            mode: DisplayMode::TextMode,
             column: 0,
             row: 0,
             color: ColorCode::new(Color::LightGray, Color::Black),
             width: 80,
             height: 25,
-            framebuffer: 0xB8000 as *mut u8,
-            pitch: 160,
-            bpp: 16,
-            char_width: 1,
-            char_height: 1,
+            buffer: 0xB8000 as *mut u16,
+            fbcon: None,
         }
     }
     
-    /// Create a new framebuffer writer
+    /// Create a new framebuffer writer (`bpp` in bits per pixel)
     pub fn framebuffer(addr: u32, width: u32, height: u32, bpp: u32, pitch: u32) -> Self {
-        // For framebuffer, we use a simple 8x16 font
-        let char_width = 8;
-        let char_height = 16;
-        
-        Self {
-            mode: DisplayMode::Framebuffer,
-            column: 0,
-            row: 0,
-            color: ColorCode::new(Color::LightGray, Color::Black),
-            width: (width as usize) / char_width,
-            height: (height as usize) / char_height,
-            framebuffer: addr as *mut u8,
-            pitch: pitch as usize,
-            bpp: bpp as usize,
-            char_width,
-            char_height,
-        }
+        let mut writer = Self::text_mode();
+        writer.fbcon = Some(unsafe { FbCon::new(addr, width, height, (bpp + 7) / 8, pitch) });
+        writer
     }
     
+    /// Map the console framebuffer through the device window (call once
+    /// paging is on; text mode needs nothing)
+    pub fn remap_framebuffer(&mut self) -> Result<(), &'static str> {
+        match self.fbcon.as_mut() {
+            Some(fbcon) => fbcon.remap(),
+            None => Ok(()),
+        }
+    }
+
     /// Clear the screen
     pub fn clear(&mut self) {
-        match self.mode {
-            DisplayMode::TextMode => {
-                let blank = (self.color.0 as u16) << 8 | b' ' as u16;
-                let buffer = self.framebuffer as *mut u16;
-                for i in 0..(self.width * self.height) {
-                    unsafe {
-                        *buffer.add(i) = blank;
-                    }
-                }
-            }
-            DisplayMode::Framebuffer => {
-                // Clear to black
-                let total_bytes = self.pitch * self.height * self.char_height;
-                unsafe {
-                    core::ptr::write_bytes(self.framebuffer, 0, total_bytes);
-                }
+        if let Some(fbcon) = self.fbcon.as_mut() {
+            fbcon.clear();
+            return;
+        }
+        let blank = (self.color.0 as u16) << 8 | b' ' as u16;
+        for i in 0..(self.width * self.height) {
+            unsafe {
+                *self.buffer.add(i) = blank;
             }
         }
         self.column = 0;
@@ -135,10 +107,17 @@ impl Writer {
     /// Set the text color
     pub fn set_color(&mut self, foreground: Color, background: Color) {
         self.color = ColorCode::new(foreground, background);
+        if let Some(fbcon) = self.fbcon.as_mut() {
+            fbcon.set_attribute(self.color.0);
+        }
     }
     
     /// Write a single byte
     pub fn write_byte(&mut self, byte: u8) {
+        if let Some(fbcon) = self.fbcon.as_mut() {
+            fbcon.write_byte(byte);
+            return;
+        }
         match byte {
             b'\n' => self.new_line(),
             b'\r' => self.column = 0,
@@ -153,85 +132,39 @@ impl Writer {
                 if self.column >= self.width {
                     self.new_line();
                 }
-                
-                match self.mode {
-                    DisplayMode::TextMode => {
-                        self.write_text_char(byte);
-                    }
-                    DisplayMode::Framebuffer => {
-                        self.draw_char(byte);
-                    }
-                }
-                
+                self.write_text_char(byte);
                 self.column += 1;
             }
         }
     }
     
-    /// Write a character in text mode
-    fn write_text_char(&mut self, byte: u8) {
-        let offset = self.row * self.width + self.column;
-        let buffer = self.framebuffer as *mut u16;
-        let value = (self.color.0 as u16) << 8 | byte as u16;
-        unsafe {
-            *buffer.add(offset) = value;
+    /// Is the cursor at the start of a line?
+    pub fn at_line_start(&self) -> bool {
+        match self.fbcon.as_ref() {
+            Some(fbcon) => fbcon.at_line_start(),
+            None => self.column == 0,
         }
     }
     
-    /// Draw a character in framebuffer mode
-    fn draw_char(&mut self, byte: u8) {
-        let x = self.column * self.char_width;
-        let y = self.row * self.char_height;
-        
-        // Get font data for this character
-        let font_data = get_font_char(byte);
-        
-        // Draw each pixel of the character
-        for (row_idx, &font_row) in font_data.iter().enumerate() {
-            for col_idx in 0..8 {
-                let pixel_on = (font_row >> (7 - col_idx)) & 1 != 0;
-                let px = x + col_idx;
-                let py = y + row_idx;
-                
-                if pixel_on {
-                    self.set_pixel(px, py, 0xAAAAAA); // Light gray
-                } else {
-                    self.set_pixel(px, py, 0x000000); // Black
-                }
-            }
+    /// Erase the character before the cursor (stops at the line start)
+    pub fn backspace(&mut self) {
+        if let Some(fbcon) = self.fbcon.as_mut() {
+            fbcon.backspace();
+            return;
         }
-    }
-    
-    /// Set a pixel in framebuffer mode
-    fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
-        if self.mode != DisplayMode::Framebuffer {
+        if self.column == 0 {
             return;
         }
-        
-        let bytes_per_pixel = self.bpp / 8;
-        let offset = y * self.pitch + x * bytes_per_pixel;
-        
+        self.column -= 1;
+        self.write_text_char(b' ');
+    }
+    
+    /// Write a character in text mode
+    fn write_text_char(&mut self, byte: u8) {
+        let offset = self.row * self.width + self.column;
+        let value = (self.color.0 as u16) << 8 | byte as u16;
         unsafe {
-            let pixel = self.framebuffer.add(offset);
-            match self.bpp {
-                32 => {
-                    *(pixel as *mut u32) = color;
-                }
-                24 => {
-                    *pixel = (color & 0xFF) as u8;
-                    *pixel.add(1) = ((color >> 8) & 0xFF) as u8;
-                    *pixel.add(2) = ((color >> 16) & 0xFF) as u8;
-                }
-                16 => {
-                    // Convert 24-bit to 16-bit (RGB565)
-                    let r = ((color >> 16) & 0xFF) as u16;
-                    let g = ((color >> 8) & 0xFF) as u16;
-                    let b = (color & 0xFF) as u16;
-                    let rgb565 = ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3);
-                    *(pixel as *mut u16) = rgb565;
-                }
-                _ => {}
-            }
+            *self.buffer.add(offset) = value;
         }
     }
     
@@ -245,42 +178,19 @@ impl Writer {
         }
     }
     
-    /// Scroll the screen up by one line
+    /// Scroll the text screen up by one line
     fn scroll(&mut self) {
-        match self.mode {
-            DisplayMode::TextMode => {
-                let buffer = self.framebuffer as *mut u16;
-                unsafe {
-                    // Move all lines up
-                    core::ptr::copy(
-                        buffer.add(self.width),
-                        buffer,
-                        self.width * (self.height - 1)
-                    );
-                    // Clear last line
-                    let blank = (self.color.0 as u16) << 8 | b' ' as u16;
-                    for i in 0..self.width {
-                        *buffer.add((self.height - 1) * self.width + i) = blank;
-                    }
-                }
-            }
-            DisplayMode::Framebuffer => {
-                let line_bytes = self.pitch * self.char_height;
-                let total_lines = self.height;
-                unsafe {
-                    // Move all lines up
-                    core::ptr::copy(
-                        self.framebuffer.add(line_bytes),
-                        self.framebuffer,
-                        line_bytes * (total_lines - 1)
-                    );
-                    // Clear last line
-                    core::ptr::write_bytes(
-                        self.framebuffer.add(line_bytes * (total_lines - 1)),
-                        0,
-                        line_bytes
-                    );
-                }
+        unsafe {
+            // Move all lines up
+            core::ptr::copy(
+                self.buffer.add(self.width),
+                self.buffer,
+                self.width * (self.height - 1)
+            );
+            // Clear last line
+            let blank = (self.color.0 as u16) << 8 | b' ' as u16;
+            for i in 0..self.width {
+                *self.buffer.add((self.height - 1) * self.width + i) = blank;
             }
         }
         
@@ -302,6 +212,7 @@ impl Writer {
 
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::klog::write_str(s);
         self.write_string(s);
         Ok(())
     }
@@ -321,40 +232,212 @@ pub unsafe fn init_framebuffer(addr: u32, width: u32, height: u32, bpp: u32, pit
     WRITER = Some(writer);
 }
 
-// Simple 8x16 bitmap font (subset for demo)
-// In production, load a proper font file
-fn get_font_char(c: u8) -> &'static [u8; 16] {
-    // Basic font data - just enough to show text
-    static FONT: [[u8; 16]; 128] = {
-        let mut font = [[0u8; 16]; 128];
-        
-        // Space
-        font[b' ' as usize] = [0; 16];
-        
-        // We'll define a minimal set of characters
-        // In a real OS, you'd load a proper font
-        
-        font
-    };
-    
-    // For now, return a simple pattern for any printable char
-    static DEFAULT_CHAR: [u8; 16] = [
-        0x00, 0x00, 0x7E, 0x81, 0xA5, 0x81, 0x81, 0xBD,
-        0x99, 0x81, 0x81, 0x7E, 0x00, 0x00, 0x00, 0x00,
-    ];
-    
-    // For most characters, use a simple block pattern
-    static BLOCK_CHAR: [u8; 16] = [
-        0x00, 0x00, 0x00, 0x3C, 0x3C, 0x3C, 0x3C, 0x3C,
-        0x3C, 0x3C, 0x3C, 0x00, 0x00, 0x00, 0x00, 0x00,
-    ];
-    
-    if c == b' ' {
-        &[0; 16]
-    } else if c >= 0x20 && c < 0x7F {
-        &BLOCK_CHAR
-    } else {
-        &DEFAULT_CHAR
+// =============================================================================
+// Text Mode Restore
+// =============================================================================
+
+/// Standard register values for VGA mode 3 (80x25 text)
+const MODE3_MISC: u8 = 0x67;
+const MODE3_SEQ: [u8; 5] = [0x03, 0x00, 0x03, 0x00, 0x02];
+const MODE3_CRTC: [u8; 25] = [
+    0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E,
+    0x00, 0x00, 0x00, 0x50, 0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+];
+const MODE3_GC: [u8; 9] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF];
+const MODE3_AC: [u8; 21] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B,
+    0x3C, 0x3D, 0x3E, 0x3F, 0x0C, 0x00, 0x0F, 0x08, 0x00,
+];
+
+// VGA register ports
+const VGA_AC_INDEX: u16 = 0x3C0;
+const VGA_MISC_WRITE: u16 = 0x3C2;
+const VGA_SEQ_INDEX: u16 = 0x3C4;
+const VGA_GC_INDEX: u16 = 0x3CE;
+const VGA_CRTC_INDEX: u16 = 0x3D4;
+const VGA_INSTAT_READ: u16 = 0x3DA;
+
+// Bochs/QEMU display interface (VESA modes in emulators)
+const BGA_INDEX: u16 = 0x1CE;
+const BGA_DATA: u16 = 0x1CF;
+
+unsafe fn write_indexed(index_port: u16, index: u8, value: u8) {
+    use crate::arch::x86::io::outb;
+    outb(index_port, index);
+    outb(index_port + 1, value);
+}
+
+unsafe fn read_indexed(index_port: u16, index: u8) -> u8 {
+    use crate::arch::x86::io::{inb, outb};
+    outb(index_port, index);
+    inb(index_port + 1)
+}
+
+/// Program the standard mode 3 register set
+unsafe fn write_mode3_registers() {
+    use crate::arch::x86::io::{inb, outb};
+
+    outb(VGA_MISC_WRITE, MODE3_MISC);
+    for (i, &value) in MODE3_SEQ.iter().enumerate() {
+        write_indexed(VGA_SEQ_INDEX, i as u8, value);
+    }
+
+    // CRTC 0-7 are write-protected by bit 7 of register 0x11
+    let protect = read_indexed(VGA_CRTC_INDEX, 0x11);
+    write_indexed(VGA_CRTC_INDEX, 0x11, protect & !0x80);
+    for (i, &value) in MODE3_CRTC.iter().enumerate() {
+        write_indexed(VGA_CRTC_INDEX, i as u8, value);
+    }
+
+    for (i, &value) in MODE3_GC.iter().enumerate() {
+        write_indexed(VGA_GC_INDEX, i as u8, value);
+    }
+
+    // Attribute controller: reading input status resets its flip-flop
+    for (i, &value) in MODE3_AC.iter().enumerate() {
+        inb(VGA_INSTAT_READ);
+        outb(VGA_AC_INDEX, i as u8);
+        outb(VGA_AC_INDEX, value);
+    }
+    inb(VGA_INSTAT_READ);
+    outb(VGA_AC_INDEX, 0x20); // Re-enable the display
+}
+
+/// Load the 8x16 GUI font into plane 2 (graphics modes overwrite it)
+unsafe fn load_font() {
+    let seq2 = read_indexed(VGA_SEQ_INDEX, 2);
+    let seq4 = read_indexed(VGA_SEQ_INDEX, 4);
+    let gc4 = read_indexed(VGA_GC_INDEX, 4);
+    let gc5 = read_indexed(VGA_GC_INDEX, 5);
+    let gc6 = read_indexed(VGA_GC_INDEX, 6);
+
+    // Flat addressing into plane 2 only, mapped at 0xA0000
+    write_indexed(VGA_SEQ_INDEX, 4, seq4 | 0x04);
+    write_indexed(VGA_SEQ_INDEX, 2, 0x04);
+    write_indexed(VGA_GC_INDEX, 4, 2);
+    write_indexed(VGA_GC_INDEX, 5, gc5 & !0x10);
+    write_indexed(VGA_GC_INDEX, 6, (gc6 & !0x0E) | 0x04);
+
+    // 32-byte glyph slots, 16 rows used
+    let plane = 0xA0000 as *mut u8;
+    for c in 0..256usize {
+        let glyph = crate::gui::font::get_char(c as u8);
+        for row in 0..32 {
+            let bits = if row < 16 && (32..127).contains(&c) { glyph[row] } else { 0 };
+            plane.add(c * 32 + row).write_volatile(bits);
+        }
+    }
+
+    write_indexed(VGA_SEQ_INDEX, 2, seq2);
+    write_indexed(VGA_SEQ_INDEX, 4, seq4);
+    write_indexed(VGA_GC_INDEX, 4, gc4);
+    write_indexed(VGA_GC_INDEX, 5, gc5);
+    write_indexed(VGA_GC_INDEX, 6, gc6);
+}
+
+// Bochs/QEMU display interface registers
+const BGA_REG_ID: u16 = 0;
+const BGA_REG_XRES: u16 = 1;
+const BGA_REG_YRES: u16 = 2;
+const BGA_REG_BPP: u16 = 3;
+const BGA_REG_ENABLE: u16 = 4;
+const BGA_ENABLED: u16 = 0x01;
+const BGA_LFB_ENABLED: u16 = 0x40;
+
+unsafe fn bga_read(index: u16) -> u16 {
+    use crate::arch::x86::io::{inw, outw};
+    outw(BGA_INDEX, index);
+    inw(BGA_DATA)
+}
+
+unsafe fn bga_write(index: u16, value: u16) {
+    use crate::arch::x86::io::outw;
+    outw(BGA_INDEX, index);
+    outw(BGA_DATA, value);
+}
+
+/// Is the Bochs/QEMU display interface present? (ID 0xB0C0..=0xB0C5)
+fn bga_present() -> bool {
+    (0xB0C0..=0xB0C5).contains(&unsafe { bga_read(BGA_REG_ID) })
+}
+
+/// Switch the display back to 80x25 text mode from protected mode
+///
+/// Used when the GUI can't start after the bootloader already set a VESA
+/// mode. Turns off the emulators' VBE extension if present, reprograms
+/// the standard VGA registers and reloads the font, then points the
+/// global writer at text memory.
+pub unsafe fn restore_text_mode() {
+    if bga_present() {
+        bga_write(BGA_REG_ENABLE, 0);
+    }
+
+    write_mode3_registers();
+    load_font();
+    init_text_mode();
+}
+
+/// A graphics mode that can be left for text mode and set again
+///
+/// The bootloader's VESA mode came from a BIOS call we can't repeat in
+/// protected mode, so only modes some driver can reprogram qualify.
+#[derive(Debug, Clone, Copy)]
+pub enum SavedMode {
+    /// Emulator VBE: replay the display interface registers
+    Bga { width: u16, height: u16, bpp: u16 },
+    /// Native ATI driver: reprogram its CRTC
+    AtiRage,
+}
+
+/// Capture the current graphics mode, if it can be restored later
+pub fn save_graphics_mode() -> Option<SavedMode> {
+    if let Some(gpu) = super::ati_rage::get() {
+        if gpu.is_initialized() {
+            return Some(SavedMode::AtiRage);
+        }
+    }
+    if bga_present() && unsafe { bga_read(BGA_REG_ENABLE) } & BGA_ENABLED != 0 {
+        return Some(unsafe {
+            SavedMode::Bga {
+                width: bga_read(BGA_REG_XRES),
+                height: bga_read(BGA_REG_YRES),
+                bpp: bga_read(BGA_REG_BPP),
+            }
+        });
+    }
+    None
+}
+
+/// Leave a saved graphics mode for VGA text mode
+///
+/// The global writer is not touched; the caller decides what it draws to.
+pub unsafe fn enter_text_mode(saved: SavedMode) {
+    match saved {
+        SavedMode::Bga { .. } => bga_write(BGA_REG_ENABLE, 0),
+        SavedMode::AtiRage => {
+            if let Some(gpu) = super::ati_rage::get() {
+                gpu.enter_vga_mode();
+            }
+        }
+    }
+    write_mode3_registers();
+    load_font();
+}
+
+/// Set a saved graphics mode again (framebuffer contents are lost)
+pub unsafe fn restore_graphics_mode(saved: SavedMode) -> Result<(), &'static str> {
+    match saved {
+        SavedMode::Bga { width, height, bpp } => {
+            bga_write(BGA_REG_ENABLE, 0);
+            bga_write(BGA_REG_XRES, width);
+            bga_write(BGA_REG_YRES, height);
+            bga_write(BGA_REG_BPP, bpp);
+            bga_write(BGA_REG_ENABLE, BGA_ENABLED | BGA_LFB_ENABLED);
+            Ok(())
+        }
+        SavedMode::AtiRage => super::ati_rage::get()
+            .ok_or("GPU unavailable")?
+            .restore_mode(),
     }
 }
 
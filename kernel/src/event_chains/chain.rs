@@ -3,7 +3,7 @@
 //! Fixed-capacity event chain for Rustacean OS kernel.
 
 use super::{
-    ChainableEvent, EventContext, EventMiddleware, FaultToleranceMode,
+    ChainableEvent, EventContext, EventFilter, EventMiddleware, FaultToleranceMode,
     result::{ChainResult, ChainStatus, EventFailure, EventResult, ErrorMessage},
 };
 
@@ -17,6 +17,19 @@ const MAX_MIDDLEWARE: usize = 8;
 ///
 /// Manages a pipeline of events with optional middleware.
 /// Uses fixed-capacity arrays for no_std compatibility.
+///
+/// All builder methods are `const`, so a chain can be defined once as a
+/// `static` and reused on every dispatch:
+///
+/// ```ignore
+/// static CHAIN: EventChain<'static> = EventChain::new()
+///     .middleware(&LOGGING_MW)
+///     .event(&MY_EVENT)
+///     .with_fault_tolerance(FaultToleranceMode::Strict);
+/// ```
+///
+/// Exceeding `MAX_EVENTS` or `MAX_MIDDLEWARE` panics, which is a
+/// compile-time error when the chain is built in a `static` or `const`.
 pub struct EventChain<'a> {
     /// Events to execute (stored as trait object references)
     events: [Option<&'a dyn ChainableEvent>; MAX_EVENTS],
@@ -28,6 +41,9 @@ pub struct EventChain<'a> {
     
     /// Fault tolerance mode
     fault_tolerance: FaultToleranceMode,
+
+    /// Optional filter applied before each event's `should_run`
+    filter: Option<EventFilter>,
 }
 
 impl<'a> EventChain<'a> {
@@ -39,37 +55,64 @@ impl<'a> EventChain<'a> {
             middleware: [None; MAX_MIDDLEWARE],
             middleware_count: 0,
             fault_tolerance: FaultToleranceMode::Strict,
+            filter: None,
         }
     }
     
     /// Set the fault tolerance mode
-    pub fn with_fault_tolerance(mut self, mode: FaultToleranceMode) -> Self {
+    pub const fn with_fault_tolerance(mut self, mode: FaultToleranceMode) -> Self {
         self.fault_tolerance = mode;
         self
     }
     
+    /// Set a filter that can skip events at execution time
+    pub const fn with_filter(mut self, filter: EventFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+    
     /// Add an event to the chain
     ///
     /// Events execute in FIFO order (first added = first executed).
-    pub fn event(mut self, event: &'a dyn ChainableEvent) -> Self {
-        if self.event_count < MAX_EVENTS {
-            self.events[self.event_count] = Some(event);
-            self.event_count += 1;
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chain already holds `MAX_EVENTS` events.
+    pub const fn event(mut self, event: &'a dyn ChainableEvent) -> Self {
+        if self.event_count >= MAX_EVENTS {
+            panic!("EventChain: MAX_EVENTS exceeded");
         }
+        self.events[self.event_count] = Some(event);
+        self.event_count += 1;
         self
     }
     
     /// Add middleware to the chain
     ///
     /// Middleware executes in LIFO order (last added = first executed).
-    pub fn middleware(mut self, mw: &'a dyn EventMiddleware) -> Self {
-        if self.middleware_count < MAX_MIDDLEWARE {
-            self.middleware[self.middleware_count] = Some(mw);
-            self.middleware_count += 1;
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chain already holds `MAX_MIDDLEWARE` middleware.
+    pub const fn middleware(mut self, mw: &'a dyn EventMiddleware) -> Self {
+        if self.middleware_count >= MAX_MIDDLEWARE {
+            panic!("EventChain: MAX_MIDDLEWARE exceeded");
         }
+        self.middleware[self.middleware_count] = Some(mw);
+        self.middleware_count += 1;
         self
     }
     
+    /// Get the number of events in the chain
+    pub const fn event_count(&self) -> usize {
+        self.event_count
+    }
+    
+    /// Get the number of middleware in the chain
+    pub const fn middleware_count(&self) -> usize {
+        self.middleware_count
+    }
+    
     /// Execute the event chain
     pub fn execute(&self, context: &mut EventContext) -> ChainResult {
         let mut result = ChainResult::success();
@@ -81,8 +124,16 @@ impl<'a> EventChain<'a> {
                 None => continue,
             };
             
+            // Filtered and conditional events are skipped before any middleware runs
+            let allowed = self.filter.map_or(true, |filter| filter(event, context));
+            if !allowed || !event.should_run(context) {
+                result.add_skipped(event.name());
+                continue;
+            }
+            
             // Execute event with middleware pipeline
             let event_result = self.execute_with_middleware(event, context);
+            result.record_executed();
             
             if event_result.is_failure() {
                 had_failures = true;
@@ -173,6 +224,12 @@ impl<'a> EventChain<'a> {
     }
 }
 
+// A chain is never changed once built; running it only reads it. The
+// static chains (syscalls, window manager) run only on the boot CPU, which
+// takes every syscall and device interrupt, so the events and middleware
+// they hold are never run from two CPUs at once, Sync or not.
+unsafe impl<'a> Sync for EventChain<'a> {}
+
 impl<'a> Default for EventChain<'a> {
     fn default() -> Self {
         Self::new()
@@ -213,7 +270,13 @@ impl<E: ChainableEvent, const N: usize> StaticChain<E, N> {
         let mut had_failures = false;
         
         for event in &self.events {
+            if !event.should_run(context) {
+                result.add_skipped(event.name());
+                continue;
+            }
+            
             let event_result = event.execute(context);
+            result.record_executed();
             
             if event_result.is_failure() {
                 had_failures = true;
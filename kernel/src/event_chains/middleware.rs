@@ -73,7 +73,18 @@ impl Default for LoggingMiddleware {
     }
 }
 
+/// Context key: PID of the user program making the request (absent for
+/// the kernel)
+pub const CALLER_PID: &str = "caller_pid";
+
+/// Context key: PID of the program owning the object being acted on
+/// (absent for kernel-owned objects)
+pub const OWNER_PID: &str = "owner_pid";
+
 /// Permission checking middleware
+///
+/// Checks the caller's ring, and refuses to let one user program act on
+/// an object owned by another (see `CALLER_PID` and `OWNER_PID`).
 pub struct PermissionMiddleware {
     required_ring: u8,
 }
@@ -106,6 +117,13 @@ impl EventMiddleware for PermissionMiddleware {
             return EventResult::failure("insufficient privileges");
         }
         
+        // The kernel may touch anything; programs only what they own
+        if let Some(caller) = context.get_u32(CALLER_PID) {
+            if context.get_u32(OWNER_PID) != Some(caller) {
+                return EventResult::failure("owned by another process");
+            }
+        }
+        
         next(context)
     }
     
@@ -36,8 +36,23 @@ pub trait ChainableEvent {
     
     /// Get the name of this event (for logging/debugging)
     fn name(&self) -> &'static str;
+    
+    /// Decide whether this event should run for the given context
+    ///
+    /// Events that return false are skipped without invoking middleware
+    /// and are recorded as skipped in the chain result.
+    fn should_run(&self, _context: &EventContext) -> bool {
+        true
+    }
 }
 
+/// Chain-level event filter
+///
+/// Consulted before each event's own `should_run`; events it rejects are
+/// skipped the same way. Lets a caller drop events from a shared chain
+/// (e.g. optional drivers in safe mode) without touching the events.
+pub type EventFilter = fn(&dyn ChainableEvent, &EventContext) -> bool;
+
 /// Fault tolerance mode for event chains
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FaultToleranceMode {
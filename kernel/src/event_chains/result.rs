@@ -177,6 +177,30 @@ impl core::fmt::Display for ChainStatus {
 /// Maximum number of failures to track
 const MAX_FAILURES: usize = 16;
 
+/// Maximum number of skipped events to track
+const MAX_SKIPPED: usize = 16;
+
+/// Per-event execution status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventStatus {
+    /// Event ran and succeeded
+    Success,
+    /// Event was not run because its `should_run` predicate returned false
+    Skipped,
+    /// Event ran and failed
+    Failed,
+}
+
+impl core::fmt::Display for EventStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Success => write!(f, "SUCCESS"),
+            Self::Skipped => write!(f, "SKIPPED"),
+            Self::Failed => write!(f, "FAILED"),
+        }
+    }
+}
+
 /// Event failure record
 #[derive(Debug, Clone, Copy)]
 pub struct EventFailure {
@@ -199,6 +223,12 @@ pub struct ChainResult {
     failures: [Option<EventFailure>; MAX_FAILURES],
     /// Number of failures
     failure_count: usize,
+    /// Names of events skipped by the chain filter or their predicate
+    skipped: [Option<&'static str>; MAX_SKIPPED],
+    /// Number of skipped events
+    skipped_count: usize,
+    /// Number of events that actually ran
+    executed_count: usize,
 }
 
 impl ChainResult {
@@ -209,6 +239,9 @@ impl ChainResult {
             status: ChainStatus::Completed,
             failures: [None; MAX_FAILURES],
             failure_count: 0,
+            skipped: [None; MAX_SKIPPED],
+            skipped_count: 0,
+            executed_count: 0,
         }
     }
     
@@ -219,6 +252,9 @@ impl ChainResult {
             status: ChainStatus::CompletedWithWarnings,
             failures: [None; MAX_FAILURES],
             failure_count: 0,
+            skipped: [None; MAX_SKIPPED],
+            skipped_count: 0,
+            executed_count: 0,
         }
     }
     
@@ -229,6 +265,9 @@ impl ChainResult {
             status: ChainStatus::Failed,
             failures: [None; MAX_FAILURES],
             failure_count: 0,
+            skipped: [None; MAX_SKIPPED],
+            skipped_count: 0,
+            executed_count: 0,
         }
     }
     
@@ -249,4 +288,45 @@ impl ChainResult {
     pub fn failures(&self) -> impl Iterator<Item = &EventFailure> {
         self.failures[..self.failure_count].iter().filter_map(|f| f.as_ref())
     }
+    
+    /// Record an event that was skipped by its predicate
+    pub fn add_skipped(&mut self, event_name: &'static str) {
+        if self.skipped_count < MAX_SKIPPED {
+            self.skipped[self.skipped_count] = Some(event_name);
+            self.skipped_count += 1;
+        }
+    }
+    
+    /// Get the number of skipped events
+    pub fn skipped_count(&self) -> usize {
+        self.skipped_count
+    }
+    
+    /// Iterate over the names of skipped events
+    pub fn skipped(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.skipped[..self.skipped_count].iter().filter_map(|s| *s)
+    }
+    
+    /// Record that an event ran (successfully or not)
+    pub fn record_executed(&mut self) {
+        self.executed_count += 1;
+    }
+    
+    /// Get the number of events that actually ran
+    pub fn executed_count(&self) -> usize {
+        self.executed_count
+    }
+    
+    /// Get the status of a named event in this result
+    ///
+    /// Events with no failure or skip record are reported as Success.
+    pub fn event_status(&self, event_name: &str) -> EventStatus {
+        if self.skipped().any(|name| name == event_name) {
+            EventStatus::Skipped
+        } else if self.failures().any(|f| f.event_name == event_name) {
+            EventStatus::Failed
+        } else {
+            EventStatus::Success
+        }
+    }
 }
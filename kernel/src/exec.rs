@@ -0,0 +1,1411 @@
+//! Program Loading and User Mode
+//!
+//! Loads ELF32 executables (or flat binaries) from the VFS into a fresh
+//! address space and runs them in ring 3. Segments are read from the file
+//! straight into the pages they are mapped at; only the headers pass
+//! through a kernel buffer.
+//!
+//! A new image starts with the usual i386 System V stack: ESP points at
+//! argc, followed by the argv pointers, a NULL, the envp pointers, a NULL
+//! and an empty auxiliary vector, with the strings themselves above.
+//!
+//! User programs live in a small process table and are run cooperatively
+//! from the main loop: `poll` drops into each runnable program with IRET
+//! and gets control back when it exits, blocks, yields, or is preempted
+//! by the timer at the end of its slice. A program's registers are kept
+//! in its process entry while it is not running. Each program has its own
+//! kernel stack: the TSS's ESP0 is pointed at it on every switch, so its
+//! syscalls and the interrupts that hit it never run on another's stack.
+//! Entries from ring 3 are checked to have landed at that stack's top.
+//! Blocking syscalls (Read
+//! on an empty console, Wait) are restarted from scratch when the program
+//! is resumed, so they never sleep inside the kernel.
+//!
+//! A program's console is the tty it was started on (inherited across
+//! fork). Ctrl+C on that tty sends SIGINT, which ends every program on it
+//! that hasn't chosen to ignore the signal. There are no signal handlers:
+//! each signal either terminates the program (exit status 128 + signal)
+//! or is ignored.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use crate::arch::x86::gdt::{self, selectors};
+use crate::arch::x86::idt::InterruptFrame;
+use crate::fs::{vfs::VFS, OpenFlags, SeekFrom};
+use crate::mm::paging::{AddressSpace, PTE_USER, PTE_WRITABLE};
+use crate::mm::pmm::{self, PageKind, PAGE_SIZE};
+use crate::mm::vma::{VmaKind, VmaList, USER_STACK_TOP};
+use crate::sched::{Pid, Priority, Task, TaskState, SCHEDULER};
+use crate::syscall::usercopy::{USER_SPACE_END, USER_SPACE_START};
+
+/// Where flat binaries are loaded (and entered)
+pub const FLAT_LOAD_ADDR: u32 = USER_SPACE_START;
+
+/// Largest program file accepted
+pub const MAX_IMAGE_SIZE: usize = 1024 * 1024;
+
+/// Start of a program file read to find its ELF and program headers
+const HEADER_SIZE: usize = 512;
+
+/// Most argv (or envp) entries
+pub const MAX_ARGS: usize = 32;
+
+/// Most bytes of argument and environment strings together
+pub const ARG_MAX: usize = 8 * 1024;
+
+/// Environment of programs started by the kernel
+pub const DEFAULT_ENV: &[&str] = &["PATH=/bin", "HOME=/"];
+
+/// Interrupt from the keyboard (Ctrl+C)
+pub const SIGINT: u32 = 2;
+
+/// Aborted (a kernel assertion failed during one of its syscalls)
+pub const SIGABRT: u32 = 6;
+
+/// Killed by the kernel (OOM policy)
+pub const SIGKILL: u32 = 9;
+
+/// Invalid memory access
+pub const SIGSEGV: u32 = 11;
+
+/// Polite termination request (shutdown)
+pub const SIGTERM: u32 = 15;
+
+/// Highest signal number
+pub const MAX_SIGNAL: u32 = 31;
+
+/// Exit code reported for a program killed by a fault
+pub const EXIT_FAULT: u32 = 128 + SIGSEGV;
+
+/// Exit code reported for a program ended by a failed kernel assertion
+pub const EXIT_ASSERT: u32 = 128 + SIGABRT;
+
+/// Exit code reported for a program whose syscall smashed a kernel stack
+pub const EXIT_STACK_SMASH: u32 = 128 + SIGABRT;
+
+const PAGE: u32 = PAGE_SIZE as u32;
+
+// =============================================================================
+// ELF32
+// =============================================================================
+
+const ELF_MAGIC: &[u8; 4] = b"\x7FELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+const PT_LOAD: u32 = 1;
+const PF_W: u32 = 2;
+const PF_X: u32 = 1;
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// A PT_LOAD program header
+struct Segment {
+    offset: u32,
+    vaddr: u32,
+    filesz: u32,
+    memsz: u32,
+    flags: u32,
+}
+
+/// Entry point and loadable segments of an ELF executable
+///
+/// `data` is the start of a file of `file_size` bytes, which must hold
+/// the program headers.
+fn parse_elf(data: &[u8], file_size: u32) -> Result<(u32, Vec<Segment>), &'static str> {
+    const BAD: &str = "malformed ELF";
+    if data.len() < 52 || data[4] != ELFCLASS32 || data[5] != ELFDATA2LSB {
+        return Err("not a 32-bit little-endian ELF");
+    }
+    if read_u16(data, 16) != Some(ET_EXEC) || read_u16(data, 18) != Some(EM_386) {
+        return Err("not an i386 executable");
+    }
+
+    let entry = read_u32(data, 24).ok_or(BAD)?;
+    let phoff = read_u32(data, 28).ok_or(BAD)? as usize;
+    let phentsize = read_u16(data, 42).ok_or(BAD)? as usize;
+    let phnum = read_u16(data, 44).ok_or(BAD)? as usize;
+    if phentsize < 32 {
+        return Err(BAD);
+    }
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        if read_u32(data, ph).ok_or(BAD)? != PT_LOAD {
+            continue;
+        }
+        let segment = Segment {
+            offset: read_u32(data, ph + 4).ok_or(BAD)?,
+            vaddr: read_u32(data, ph + 8).ok_or(BAD)?,
+            filesz: read_u32(data, ph + 16).ok_or(BAD)?,
+            memsz: read_u32(data, ph + 20).ok_or(BAD)?,
+            flags: read_u32(data, ph + 24).ok_or(BAD)?,
+        };
+        let file_end = segment.offset.checked_add(segment.filesz).ok_or(BAD)?;
+        if segment.filesz > segment.memsz || file_end > file_size {
+            return Err(BAD);
+        }
+        segments.push(segment);
+    }
+    if segments.is_empty() {
+        return Err("no loadable segments");
+    }
+    Ok((entry, segments))
+}
+
+// =============================================================================
+// Loading
+// =============================================================================
+
+/// Arguments and environment for a new image (strings without NULs)
+#[derive(Default)]
+pub struct Args {
+    pub argv: Vec<Vec<u8>>,
+    pub envp: Vec<Vec<u8>>,
+}
+
+impl Args {
+    /// Build from string slices
+    pub fn new(argv: &[&str], envp: &[&str]) -> Result<Self, &'static str> {
+        let mut args = Self::default();
+        for arg in argv {
+            args.push_arg(arg.as_bytes())?;
+        }
+        for var in envp {
+            args.push_env(var.as_bytes())?;
+        }
+        Ok(args)
+    }
+
+    /// Bytes the strings take on the stack, terminators included
+    fn string_bytes(&self) -> usize {
+        self.argv.iter().chain(&self.envp).map(|s| s.len() + 1).sum()
+    }
+
+    fn push(list: &mut Vec<Vec<u8>>, used: usize, s: &[u8]) -> Result<(), &'static str> {
+        if list.len() >= MAX_ARGS || used + s.len() + 1 > ARG_MAX {
+            return Err("argument list too long");
+        }
+        if s.contains(&0) {
+            return Err("invalid argument");
+        }
+        list.push(Vec::from(s));
+        Ok(())
+    }
+
+    pub fn push_arg(&mut self, s: &[u8]) -> Result<(), &'static str> {
+        let used = self.string_bytes();
+        Self::push(&mut self.argv, used, s)
+    }
+
+    pub fn push_env(&mut self, s: &[u8]) -> Result<(), &'static str> {
+        let used = self.string_bytes();
+        Self::push(&mut self.envp, used, s)
+    }
+}
+
+/// A program ready to run
+pub struct Image {
+    pub space: AddressSpace,
+    pub vmas: VmaList,
+    pub entry: u32,
+    /// Initial stack pointer (at argc)
+    pub stack: u32,
+}
+
+/// A program file being loaded
+struct ImageFile {
+    fd: u32,
+    size: u32,
+}
+
+impl ImageFile {
+    fn open(path: &str) -> Result<Self, &'static str> {
+        let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+        let fd = vfs.open(path, OpenFlags::read_only()).map_err(|e| e.as_str())?;
+        match vfs.seek(fd, 0, SeekFrom::End) {
+            Ok(size) if size as usize <= MAX_IMAGE_SIZE => Ok(Self { fd, size: size as u32 }),
+            result => {
+                let _ = vfs.close(fd);
+                Err(result.map_or_else(|e| e.as_str(), |_| "program too large"))
+            }
+        }
+    }
+
+    /// Fill `buf` from the file at `offset`
+    fn read_at(&self, offset: u32, buf: &mut [u8]) -> Result<(), &'static str> {
+        let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+        vfs.seek(self.fd, offset as i64, SeekFrom::Start).map_err(|e| e.as_str())?;
+        let mut done = 0;
+        while done < buf.len() {
+            match vfs.read(self.fd, &mut buf[done..]) {
+                Ok(0) => return Err("program file truncated"),
+                Ok(n) => done += n,
+                Err(e) => return Err(e.as_str()),
+            }
+        }
+        Ok(())
+    }
+
+    fn close(self) {
+        let _ = unsafe { (*core::ptr::addr_of_mut!(VFS)).close(self.fd) };
+    }
+}
+
+/// Map `[vaddr, vaddr + memsz)` with `filesz` bytes of `file` from
+/// `offset` at its start and zeros after
+fn map_segment(space: &mut AddressSpace, vaddr: u32, file: &ImageFile, offset: u32, filesz: u32,
+               memsz: u32, writable: bool) -> Result<(), &'static str> {
+    let end = vaddr.checked_add(memsz).filter(|&e| e <= USER_SPACE_END).ok_or("segment out of range")?;
+    if vaddr < USER_SPACE_START {
+        return Err("segment below user space");
+    }
+    let flags = if writable { PTE_USER | PTE_WRITABLE } else { PTE_USER };
+
+    let mut page = vaddr & !(PAGE - 1);
+    while page < end {
+        let frame = pmm::alloc_zeroed_page().ok_or("out of memory")?;
+        // Part of the file that lands in this page
+        let from = page.max(vaddr);
+        let to = (page + PAGE).min(vaddr + filesz);
+        let read = if from < to {
+            let dst = unsafe {
+                core::slice::from_raw_parts_mut((frame + (from - page) as usize) as *mut u8, (to - from) as usize)
+            };
+            file.read_at(offset + (from - vaddr), dst)
+        } else {
+            Ok(())
+        };
+        if let Err(e) = read.and_then(|_| space.map(page, frame as u32, flags)) {
+            unsafe { pmm::free_page(frame) };
+            return Err(e);
+        }
+        page += PAGE;
+    }
+    Ok(())
+}
+
+/// Populate an address space from a program file
+fn populate(file: &ImageFile, space: &mut AddressSpace, vmas: &mut VmaList) -> Result<u32, &'static str> {
+    let mut header = [0u8; HEADER_SIZE];
+    let header = &mut header[..(file.size as usize).min(HEADER_SIZE)];
+    file.read_at(0, header)?;
+
+    if header.starts_with(ELF_MAGIC) {
+        let (entry, segments) = parse_elf(header, file.size)?;
+        let mut image_end = 0;
+        for seg in &segments {
+            let writable = seg.flags & PF_W != 0;
+            map_segment(space, seg.vaddr, file, seg.offset, seg.filesz, seg.memsz, writable)?;
+            let kind = if seg.flags & PF_X != 0 { VmaKind::Code } else { VmaKind::Data };
+            vmas.add(seg.vaddr, seg.vaddr + seg.memsz, kind, writable)?;
+            image_end = image_end.max(seg.vaddr + seg.memsz);
+        }
+        vmas.setup_heap_and_stack(image_end)?;
+        Ok(entry)
+    } else {
+        // Flat binary: code and data in one writable region, entered at the start
+        if file.size == 0 {
+            return Err("empty program");
+        }
+        let size = file.size;
+        map_segment(space, FLAT_LOAD_ADDR, file, 0, size, size, true)?;
+        vmas.add(FLAT_LOAD_ADDR, FLAT_LOAD_ADDR + size, VmaKind::Code, true)?;
+        vmas.setup_heap_and_stack(FLAT_LOAD_ADDR + size)?;
+        Ok(FLAT_LOAD_ADDR)
+    }
+}
+
+/// Copy bytes into an address space that isn't active, mapping any
+/// missing pages as user-writable
+fn write_user(space: &mut AddressSpace, addr: u32, bytes: &[u8]) -> Result<(), &'static str> {
+    let mut done = 0;
+    while done < bytes.len() {
+        let virt = addr + done as u32;
+        let page = virt & !(PAGE - 1);
+        if space.translate(page).is_none() {
+            let frame = pmm::alloc_zeroed_page().ok_or("out of memory")?;
+            if let Err(e) = space.map(page, frame as u32, PTE_USER | PTE_WRITABLE) {
+                unsafe { pmm::free_page(frame) };
+                return Err(e);
+            }
+        }
+        let phys = space.translate(virt).ok_or("stack not mapped")?;
+        let len = (bytes.len() - done).min((page + PAGE - virt) as usize);
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes[done..].as_ptr(), phys as *mut u8, len);
+        }
+        done += len;
+    }
+    Ok(())
+}
+
+/// Lay out argc/argv/envp at the top of the user stack
+///
+/// Returns the initial stack pointer, 16-byte aligned.
+fn setup_stack(space: &mut AddressSpace, args: &Args) -> Result<u32, &'static str> {
+    let strings = args.string_bytes();
+    // argc, argv + NULL, envp + NULL, AT_NULL auxv pair
+    let words = 1 + args.argv.len() + 1 + args.envp.len() + 1 + 2;
+    let strings_at = USER_STACK_TOP - ((strings as u32 + 3) & !3);
+    let stack = (strings_at - words as u32 * 4) & !15;
+
+    // Written in place, a word or a string at a time
+    let mut word = stack;
+    let mut put = |space: &mut AddressSpace, value: u32| {
+        word += 4;
+        write_user(space, word - 4, &value.to_le_bytes())
+    };
+    let mut next = strings_at;
+    put(space, args.argv.len() as u32)?;
+    for list in [&args.argv, &args.envp] {
+        for s in list.iter() {
+            put(space, next)?;
+            write_user(space, next, s)?;
+            write_user(space, next + s.len() as u32, &[0])?;
+            next += s.len() as u32 + 1;
+        }
+        put(space, 0)?;
+    }
+    // AT_NULL
+    put(space, 0)?;
+    put(space, 0)?;
+    Ok(stack)
+}
+
+/// Load a program into a new address space
+pub fn load(path: &str, args: &Args) -> Result<Image, &'static str> {
+    let file = ImageFile::open(path)?;
+    let mut space = match AddressSpace::new() {
+        Ok(space) => space,
+        Err(e) => {
+            file.close();
+            return Err(e);
+        }
+    };
+    let mut vmas = VmaList::new();
+    let loaded = populate(&file, &mut space, &mut vmas)
+        .and_then(|entry| Ok((entry, setup_stack(&mut space, args)?)));
+    file.close();
+    match loaded {
+        Ok((entry, stack)) => Ok(Image { space, vmas, entry, stack }),
+        Err(e) => {
+            space.destroy();
+            Err(e)
+        }
+    }
+}
+
+/// Final path component (task name)
+fn program_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+// =============================================================================
+// Processes
+// =============================================================================
+
+/// Maximum user programs (running, blocked, or exited and not yet reaped)
+pub const MAX_PROCESSES: usize = 16;
+
+/// Parent recorded for programs started with `spawn`
+pub const KERNEL_PID: Pid = 0;
+
+/// Timer ticks a program runs before `poll` gets control back
+const TIME_SLICE: u32 = 2;
+
+/// What a blocked program is waiting for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wait {
+    /// Read on the tty with no input ready
+    Stdin,
+    /// Wait syscall for a child (0 = any)
+    Child(Pid),
+    /// Input event for a window
+    #[cfg(feature = "gui")]
+    Window(u32),
+    /// Room in a port's queue, until a deadline
+    PortSend(u32, u32),
+    /// Message on a port, until a deadline
+    PortReceive(u32, u32),
+}
+
+/// A user program
+#[derive(Clone, Copy)]
+struct Process {
+    task: *mut Task,
+    /// Who collects the exit status (None = orphan, freed on exit)
+    parent: Option<Pid>,
+    /// Controlling tty (console for fd 0-2)
+    tty: Option<usize>,
+    /// Ignored signals (bit per signal number)
+    ignored: u32,
+    /// User registers while the program is not running
+    context: InterruptFrame,
+    /// Why the program can't run, if it can't
+    wait: Option<Wait>,
+    /// Exit status once the program has finished
+    exit_code: Option<u32>,
+    /// Base of the program's kernel stack (`KERNEL_STACK_SIZE` bytes)
+    kernel_stack: *mut u8,
+}
+
+impl Process {
+    fn pid(&self) -> Pid {
+        unsafe { (*self.task).pid }
+    }
+
+    /// Torn down by `finish`, so its exit status can be collected (the OOM
+    /// killer sets the status first and leaves the teardown to the main
+    /// loop)
+    fn finished(&self) -> bool {
+        unsafe { (*self.task).state == TaskState::Zombie }
+    }
+}
+
+/// Ring 0 stack per program
+const KERNEL_STACK_SIZE: usize = gdt::RING0_STACK_SIZE;
+
+/// Kernel stacks are `1 << KERNEL_STACK_ORDER` PMM pages
+const KERNEL_STACK_ORDER: u32 = (KERNEL_STACK_SIZE / PAGE_SIZE).trailing_zeros();
+
+/// Allocate a kernel stack, returning its base and (16-byte aligned) top
+fn alloc_kernel_stack() -> Result<(*mut u8, u32), &'static str> {
+    let base = pmm::alloc_pages(KERNEL_STACK_ORDER, PageKind::KernelStack).ok_or("out of memory")?;
+    Ok((base as *mut u8, (base + KERNEL_STACK_SIZE) as u32 & !0xF))
+}
+
+/// Free a stack from `alloc_kernel_stack`
+unsafe fn free_kernel_stack(base: *mut u8) {
+    pmm::free_pages(base as usize, KERNEL_STACK_ORDER);
+}
+
+/// Check that a ring 3 entry switched to the running program's stack
+///
+/// The CPU pushes the frame at ESP0, so a good entry (INT 0x80, or an
+/// IRQ or fault while in ring 3) leaves it ending exactly there, and ESP0
+/// is the running program's own kernel stack. Anything else means the TSS
+/// and the program are out of step: the program is killed, and the
+/// kernel goes back to the stack `exec_resume_user` saved rather than
+/// returning along one it can't trust.
+fn check_entry(frame: &InterruptFrame) {
+    let at = frame as *const InterruptFrame as u32;
+    let esp0 = gdt::kernel_stack();
+    let own = unsafe { CURRENT.and_then(|slot| PROCESSES[slot]).map(|p| (*p.task).kernel_stack) };
+    if !entry_in_step(at, esp0) || own != Some(esp0) {
+        crate::klog::write_fmt(format_args!(
+            "[EXEC] killed: ring 3 entry frame ends at {:08X}, ESP0 is {:08X}, its stack {:08X}\n",
+            at.wrapping_add(core::mem::size_of::<InterruptFrame>() as u32), esp0, own.unwrap_or(0),
+        ));
+        abort_current(EXIT_FAULT);
+    }
+}
+
+/// Whether an entry frame pushed at `frame` ends exactly at `esp0`
+fn entry_in_step(frame: u32, esp0: u32) -> bool {
+    frame.checked_add(core::mem::size_of::<InterruptFrame>() as u32) == Some(esp0)
+}
+
+/// Request made by the running program's syscall, acted on as it returns
+#[derive(Debug, Clone, Copy)]
+enum Pending {
+    /// Exit with a status
+    Exit(u32),
+    /// Exec: start the new image at this entry point and stack
+    Enter(u32, u32),
+    /// Fork: the child in this slot gets a copy of the caller's registers
+    Fork(usize),
+    /// Let the other programs run
+    Yield,
+    /// Sleep until the wait is over, then restart the syscall
+    Block(Wait),
+}
+
+static mut PROCESSES: [Option<Process>; MAX_PROCESSES] = [None; MAX_PROCESSES];
+
+/// Slot of the program in ring 3 (None while the kernel runs)
+static mut CURRENT: Option<usize> = None;
+
+/// Slot `poll` looks at first
+static mut NEXT: usize = 0;
+
+/// Kernel stack pointer saved by `exec_resume_user` (0 = nothing running)
+static mut KERNEL_ESP: u32 = 0;
+
+/// Tick at which the running program is preempted
+static mut SLICE_END: u32 = 0;
+
+static mut PENDING: Option<Pending> = None;
+
+/// Programs that exited since `take_exited` last ran (their windows
+/// still need closing)
+static mut EXITED: Vec<Pid> = Vec::new();
+
+extern "C" {
+    /// Save kernel registers at `*saved_esp` and IRET to ring 3 with `context`
+    fn exec_resume_user(context: *const InterruptFrame, saved_esp: *mut u32) -> u32;
+    /// Restore the registers saved by `exec_resume_user`, returning `code` from it
+    fn exec_return_to_kernel(saved_esp: u32, code: u32) -> !;
+}
+
+core::arch::global_asm!(
+    ".global exec_resume_user",
+    "exec_resume_user:",
+    "    push ebp",
+    "    push ebx",
+    "    push esi",
+    "    push edi",
+    "    pushfd",
+    "    mov esi, [esp + 24]",      // saved user registers
+    "    mov edx, [esp + 28]",      // &saved_esp
+    "    mov [edx], esp",
+    "    mov dx, {udata}",
+    "    mov ds, dx",
+    "    mov es, dx",
+    "    mov fs, dx",
+    "    mov gs, dx",
+    "    push dword ptr [esi + {ss}]",
+    "    push dword ptr [esi + {esp}]",
+    "    push dword ptr [esi + {eflags}]",
+    "    push dword ptr [esi + {cs}]",
+    "    push dword ptr [esi + {eip}]",
+    "    mov edi, [esi + {edi}]",
+    "    mov ebp, [esi + {ebp}]",
+    "    mov ebx, [esi + {ebx}]",
+    "    mov edx, [esi + {edx}]",
+    "    mov ecx, [esi + {ecx}]",
+    "    mov eax, [esi + {eax}]",
+    "    mov esi, [esi + {esi}]",
+    "    iretd",
+
+    ".global exec_return_to_kernel",
+    "exec_return_to_kernel:",
+    "    mov eax, [esp + 8]",       // code
+    "    mov esp, [esp + 4]",       // saved kernel stack
+    "    mov dx, {kdata}",
+    "    mov ds, dx",
+    "    mov es, dx",
+    "    mov fs, dx",
+    "    mov gs, dx",
+    "    popfd",
+    "    pop edi",
+    "    pop esi",
+    "    pop ebx",
+    "    pop ebp",
+    "    ret",
+    udata = const selectors::USER_DATA,
+    kdata = const selectors::KERNEL_DATA,
+    ss = const core::mem::offset_of!(InterruptFrame, user_ss),
+    esp = const core::mem::offset_of!(InterruptFrame, user_esp),
+    eflags = const core::mem::offset_of!(InterruptFrame, eflags),
+    cs = const core::mem::offset_of!(InterruptFrame, cs),
+    eip = const core::mem::offset_of!(InterruptFrame, eip),
+    edi = const core::mem::offset_of!(InterruptFrame, edi),
+    esi = const core::mem::offset_of!(InterruptFrame, esi),
+    ebp = const core::mem::offset_of!(InterruptFrame, ebp),
+    ebx = const core::mem::offset_of!(InterruptFrame, ebx),
+    edx = const core::mem::offset_of!(InterruptFrame, edx),
+    ecx = const core::mem::offset_of!(InterruptFrame, ecx),
+    eax = const core::mem::offset_of!(InterruptFrame, eax),
+);
+
+/// Registers for entering an image at `entry` with ESP at `stack`
+fn initial_context(entry: u32, stack: u32) -> InterruptFrame {
+    InterruptFrame {
+        edi: 0, esi: 0, ebp: 0, esp_dummy: 0,
+        ebx: 0, edx: 0, ecx: 0, eax: 0,
+        interrupt_number: 0,
+        error_code: 0,
+        eip: entry,
+        cs: selectors::USER_CODE as u32,
+        eflags: 0x202, // IF set
+        user_esp: stack,
+        user_ss: selectors::USER_DATA as u32,
+    }
+}
+
+fn free_slot() -> Result<usize, &'static str> {
+    unsafe { PROCESSES.iter().position(|p| p.is_none()).ok_or("too many processes") }
+}
+
+/// Release a process slot and its task
+unsafe fn free(slot: usize) {
+    if let Some(process) = PROCESSES[slot].take() {
+        drop(Box::from_raw(process.task));
+        free_kernel_stack(process.kernel_stack);
+    }
+}
+
+/// Is a user program running right now?
+pub fn is_running() -> bool {
+    unsafe { KERNEL_ESP != 0 }
+}
+
+/// PID of the program in ring 3, if any
+pub fn current_pid() -> Option<Pid> {
+    unsafe { CURRENT.and_then(|slot| PROCESSES[slot]).map(|p| p.pid()) }
+}
+
+/// PIDs of programs that exited since the last call
+pub fn take_exited() -> Vec<Pid> {
+    unsafe { core::mem::take(&mut *core::ptr::addr_of_mut!(EXITED)) }
+}
+
+/// Task of a program that has not exited
+pub fn task_of(pid: Pid) -> Option<&'static mut Task> {
+    unsafe {
+        PROCESSES.iter().flatten()
+            .find(|p| p.pid() == pid && p.exit_code.is_none())
+            .map(|p| &mut *p.task)
+    }
+}
+
+/// Load a program and make it runnable, with `tty` as its console
+///
+/// It runs from `poll`; collect its exit status with `reap`.
+pub fn spawn(path: &str, args: &Args, tty: Option<usize>) -> Result<Pid, &'static str> {
+    let path = unsafe { VFS.resolve(path) }.map_err(|e| e.as_str())?;
+    adopt(program_name(&path), tty, || load(&path, args)).map(|(_, pid)| pid)
+}
+
+/// Put the image `load` makes in a free slot as a runnable child of the
+/// kernel, returning the slot and PID
+fn adopt(name: &str, tty: Option<usize>, load: impl FnOnce() -> Result<Image, &'static str>)
+    -> Result<(usize, Pid), &'static str>
+{
+    let slot = free_slot()?;
+    let (kernel_stack, kernel_stack_top) = alloc_kernel_stack()?;
+    let image = match load() {
+        Ok(image) => image,
+        Err(e) => {
+            unsafe { free_kernel_stack(kernel_stack) };
+            return Err(e);
+        }
+    };
+
+    let task = Box::leak(Box::new(Task::new(name, Priority::Normal)));
+    task.ppid = KERNEL_PID;
+    task.cr3 = image.space.directory();
+    task.vmas = image.vmas;
+    task.eip = image.entry;
+    task.user_stack = image.stack;
+    task.kernel_stack = kernel_stack_top;
+    let _ = task.set_cwd(&unsafe { VFS.cwd() });
+
+    let pid = task.pid;
+    unsafe {
+        PROCESSES[slot] = Some(Process {
+            task,
+            parent: Some(KERNEL_PID),
+            tty,
+            ignored: 0,
+            context: initial_context(image.entry, image.stack),
+            wait: None,
+            exit_code: None,
+            kernel_stack,
+        });
+    }
+    Ok((slot, pid))
+}
+
+/// Exit status of a program started with `spawn`, once it has finished
+///
+/// Frees the process; returns None while it is still alive.
+pub fn reap(pid: Pid) -> Option<u32> {
+    unsafe {
+        let slot = PROCESSES.iter().position(|p| {
+            matches!(p, Some(p) if p.pid() == pid && p.parent == Some(KERNEL_PID))
+        })?;
+        let process = PROCESSES[slot]?;
+        let code = process.exit_code.filter(|_| process.finished())?;
+        free(slot);
+        Some(code)
+    }
+}
+
+/// Children of `parent` matching a Wait pid (0 = any)
+fn children(parent: Pid, pid: Pid) -> impl Iterator<Item = (usize, Process)> {
+    unsafe { PROCESSES }.into_iter().enumerate().filter_map(move |(slot, p)| match p {
+        Some(p) if p.parent == Some(parent) && (pid == 0 || p.pid() == pid) => Some((slot, p)),
+        _ => None,
+    })
+}
+
+/// Can the program in `slot` make progress?
+fn runnable(slot: usize) -> bool {
+    let Some(process) = (unsafe { PROCESSES[slot] }) else {
+        return false;
+    };
+    if process.exit_code.is_some() {
+        return false;
+    }
+    match process.wait {
+        None => true,
+        Some(Wait::Stdin) => process.tty.map_or(true, crate::tty::readable),
+        #[cfg(feature = "gui")]
+        Some(Wait::Window(id)) => crate::gui::server::readable(id),
+        Some(Wait::PortSend(id, deadline)) => crate::ipc::sendable(id, deadline),
+        Some(Wait::PortReceive(id, deadline)) => crate::ipc::receivable(id, deadline),
+        // Ready once a child has exited, or if there are none (Wait then fails)
+        Some(Wait::Child(pid)) => {
+            let mut matching = children(process.pid(), pid).peekable();
+            matching.peek().is_none() || matching.any(|(_, c)| c.finished())
+        }
+    }
+}
+
+/// Is any program ready to run? (the main loop shouldn't halt then)
+pub fn has_runnable() -> bool {
+    (0..MAX_PROCESSES).any(runnable)
+}
+
+/// Give every runnable program a turn
+///
+/// Called from the main loop. Each program runs until it blocks, yields,
+/// exits or uses up its time slice.
+pub fn poll() {
+    if is_running() {
+        return;
+    }
+    crate::mm::oom::poll();
+    for _ in 0..MAX_PROCESSES {
+        let slot = unsafe { NEXT };
+        unsafe { NEXT = (NEXT + 1) % MAX_PROCESSES };
+        if runnable(slot) {
+            resume(slot);
+        }
+    }
+}
+
+/// Run one program until it comes back to the kernel
+fn resume(slot: usize) {
+    unsafe {
+        let Some(process) = PROCESSES[slot].as_mut() else {
+            return;
+        };
+        process.wait = None;
+        let context = process.context;
+        let task = &mut *process.task;
+
+        let previous = SCHEDULER.current();
+        SCHEDULER.set_current(Some(task as *mut Task));
+        task.state = TaskState::Running;
+        crate::mm::paging::activate(task.cr3);
+        gdt::set_kernel_stack(task.kernel_stack);
+        CURRENT = Some(slot);
+        SLICE_END = crate::arch::x86::idt::ticks().wrapping_add(TIME_SLICE);
+
+        exec_resume_user(&context, core::ptr::addr_of_mut!(KERNEL_ESP));
+
+        KERNEL_ESP = 0;
+        CURRENT = None;
+        gdt::reset_kernel_stack();
+        crate::mm::paging::activate(previous.map_or(0, |p| (*p).cr3));
+        SCHEDULER.set_current(previous);
+
+        match PROCESSES[slot] {
+            Some(Process { exit_code: Some(code), .. }) => finish(slot, code),
+            Some(Process { wait: Some(_), .. }) => task.state = TaskState::Blocked,
+            _ => task.state = TaskState::Ready,
+        }
+    }
+}
+
+/// Tear down an exited program, leaving its status for the parent
+unsafe fn finish(slot: usize, code: u32) {
+    let Some(process) = PROCESSES[slot] else {
+        return;
+    };
+    let task = &mut *process.task;
+    crate::syscall::file::release_stdio(task);
+    crate::mm::shm::release_owner(task.pid);
+    crate::ipc::release_owner(task.pid);
+    AddressSpace::from_directory(task.cr3).destroy();
+    task.cr3 = 0;
+    task.state = TaskState::Zombie;
+    (*core::ptr::addr_of_mut!(EXITED)).push(task.pid);
+    crate::klog::write_fmt(format_args!("[EXEC] pid {} exited with {}\n", task.pid, code));
+
+    // Exited children go now; running ones are freed when they exit
+    for (child, c) in children(task.pid, 0) {
+        if c.finished() {
+            free(child);
+        } else if let Some(c) = PROCESSES[child].as_mut() {
+            c.parent = None;
+        }
+    }
+    if process.parent.is_none() {
+        free(slot);
+    }
+}
+
+/// Save the running program's registers and return to `resume`
+unsafe fn switch_out(frame: &InterruptFrame) -> ! {
+    if let Some(process) = CURRENT.and_then(|slot| PROCESSES[slot].as_mut()) {
+        process.context = *frame;
+    }
+    exec_return_to_kernel(KERNEL_ESP, 0)
+}
+
+/// End the running program with an exit status
+unsafe fn leave(code: u32) -> ! {
+    if let Some(process) = CURRENT.and_then(|slot| PROCESSES[slot].as_mut()) {
+        process.exit_code = Some(code);
+    }
+    exec_return_to_kernel(KERNEL_ESP, code)
+}
+
+// =============================================================================
+// Syscall Hooks
+// =============================================================================
+
+/// Exit syscall: leave the running program once the syscall returns
+pub fn exit_current(code: u32) {
+    if is_running() {
+        unsafe { PENDING = Some(Pending::Exit(code)) };
+    }
+}
+
+/// Exec syscall: replace the running program's image
+pub fn exec_current(path: &str, args: &Args) -> Result<(), &'static str> {
+    if !is_running() {
+        return Err("no user program");
+    }
+    let path = unsafe { VFS.resolve(path) }.map_err(|e| e.as_str())?;
+    let image = load(&path, args)?;
+
+    unsafe {
+        let task = &mut *SCHEDULER.current().ok_or("no current task")?;
+        let old = AddressSpace::from_directory(task.cr3);
+        task.cr3 = image.space.directory();
+        task.vmas = image.vmas;
+        task.eip = image.entry;
+        task.user_stack = image.stack;
+        let name = program_name(&path).as_bytes();
+        let len = name.len().min(task.name.len() - 1);
+        task.name = [0; 16];
+        task.name[..len].copy_from_slice(&name[..len]);
+
+        crate::mm::paging::activate(task.cr3);
+        old.destroy();
+        PENDING = Some(Pending::Enter(image.entry, image.stack));
+    }
+    Ok(())
+}
+
+/// Fork syscall from a user program: the child becomes a new process
+///
+/// It resumes from the same syscall with EAX = 0 on its first turn.
+pub fn fork_current() -> Result<Pid, &'static str> {
+    let slot = free_slot()?;
+    unsafe {
+        let parent = &*SCHEDULER.current().ok_or("no current task")?;
+        let this = CURRENT.and_then(|slot| PROCESSES[slot]).ok_or("no user program")?;
+        let (kernel_stack, kernel_stack_top) = alloc_kernel_stack()?;
+        let child = match crate::sched::fork_task(parent) {
+            Ok(child) => child,
+            Err(e) => {
+                free_kernel_stack(kernel_stack);
+                return Err(e);
+            }
+        };
+        child.stdio = crate::syscall::file::inherit_stdio(parent);
+        child.kernel_stack = kernel_stack_top;
+        let pid = child.pid;
+        PROCESSES[slot] = Some(Process {
+            task: child,
+            parent: Some(parent.pid),
+            tty: this.tty,
+            ignored: this.ignored,
+            // Filled in from the parent's registers in `after_syscall`
+            context: initial_context(0, 0),
+            wait: None,
+            exit_code: None,
+            kernel_stack,
+        });
+        PENDING = Some(Pending::Fork(slot));
+        Ok(pid)
+    }
+}
+
+/// Yield syscall: let the other programs run
+///
+/// Returns false outside a user program.
+pub fn yield_current() -> bool {
+    if is_running() {
+        unsafe { PENDING = Some(Pending::Yield) };
+    }
+    is_running()
+}
+
+/// Wait syscall: collect an exited child of the running program
+///
+/// `pid` 0 means any child. Returns None when the caller has been
+/// blocked; the syscall restarts once a child exits.
+pub fn wait_child(pid: Pid) -> Result<Option<(Pid, u32)>, &'static str> {
+    let parent = current_pid().ok_or("no user program")?;
+    let mut alive = false;
+    for (slot, child) in children(parent, pid) {
+        if let Some(code) = child.exit_code.filter(|_| child.finished()) {
+            unsafe { free(slot) };
+            return Ok(Some((child.pid(), code)));
+        }
+        alive = true;
+    }
+    if !alive {
+        return Err("no child processes");
+    }
+    unsafe { PENDING = Some(Pending::Block(Wait::Child(pid))) };
+    Ok(None)
+}
+
+/// Finish a syscall made from ring 3
+///
+/// `number` is the syscall number from the original EAX, used to restart
+/// a blocked syscall.
+pub fn after_syscall(frame: &mut InterruptFrame, number: u32) {
+    if frame.cs & 3 != 3 || !is_running() {
+        return;
+    }
+    check_entry(frame);
+    unsafe {
+        match PENDING.take() {
+            None => {}
+            Some(Pending::Exit(code)) => leave(code),
+            Some(Pending::Enter(entry, stack)) => *frame = initial_context(entry, stack),
+            Some(Pending::Fork(child)) => {
+                if let Some(child) = PROCESSES[child].as_mut() {
+                    child.context = *frame;
+                    child.context.eax = 0;
+                }
+            }
+            Some(Pending::Yield) => switch_out(frame),
+            Some(Pending::Block(wait)) => {
+                // Back up over `int 0x80` so the syscall runs again on resume
+                frame.eax = number;
+                frame.eip = frame.eip.wrapping_sub(2);
+                if let Some(process) = CURRENT.and_then(|slot| PROCESSES[slot].as_mut()) {
+                    process.wait = Some(wait);
+                }
+                switch_out(frame);
+            }
+        }
+    }
+}
+
+/// Timer tick: take the CPU back from a program whose slice is used up
+pub fn preempt(frame: &InterruptFrame) {
+    if frame.cs & 3 != 3 || !is_running() {
+        return;
+    }
+    check_entry(frame);
+    unsafe {
+        if crate::arch::x86::idt::ticks().wrapping_sub(SLICE_END) as i32 >= 0 {
+            switch_out(frame);
+        }
+    }
+}
+
+/// Kill the running program after a fault in ring 3
+///
+/// Returns only if the fault did not come from a user program.
+pub fn fault_exit(frame: &InterruptFrame, reason: &str) {
+    if frame.cs & 3 != 3 || !is_running() {
+        return;
+    }
+    crate::klog::write_fmt(format_args!("[EXEC] killed: {} at {:08X}\n", reason, frame.eip));
+    unsafe {
+        PENDING = None;
+        leave(EXIT_FAULT);
+    }
+}
+
+/// End the running program from kernel code that can't go back to it
+/// (the stack its syscall was running on is no longer trustworthy)
+///
+/// Returns only if no user program is running.
+pub fn abort_current(code: u32) {
+    if !is_running() {
+        return;
+    }
+    unsafe {
+        PENDING = None;
+        leave(code);
+    }
+}
+
+// =============================================================================
+// Signals
+// =============================================================================
+
+/// Signal syscall: ignore a signal (`ignore`) or restore the default
+/// action of terminating the program
+///
+/// Returns whether the signal was ignored before.
+pub fn set_signal(signal: u32, ignore: bool) -> Result<bool, &'static str> {
+    if signal == 0 || signal > MAX_SIGNAL {
+        return Err("invalid signal");
+    }
+    let process = unsafe { CURRENT.and_then(|slot| PROCESSES[slot].as_mut()) }
+        .ok_or("no user program")?;
+    let bit = 1 << signal;
+    let was = process.ignored & bit != 0;
+    if ignore {
+        process.ignored |= bit;
+    } else {
+        process.ignored &= !bit;
+    }
+    Ok(was)
+}
+
+/// Deliver a signal to every program on a tty
+///
+/// Programs not ignoring it are terminated. Called from the main loop,
+/// so none of them is in ring 3.
+pub fn signal_tty(tty: usize, signal: u32) {
+    if is_running() {
+        return;
+    }
+    for slot in 0..MAX_PROCESSES {
+        let Some(process) = (unsafe { PROCESSES[slot] }) else {
+            continue;
+        };
+        if process.tty == Some(tty) && process.exit_code.is_none()
+            && process.ignored & (1 << signal) == 0
+        {
+            unsafe {
+                if let Some(p) = PROCESSES[slot].as_mut() {
+                    p.exit_code = Some(128 + signal);
+                }
+                finish(slot, 128 + signal);
+            }
+        }
+    }
+}
+
+/// Deliver a signal to every program (shutdown)
+///
+/// Returns how many are still running because they ignore it. Called
+/// from the main loop, so none of them is in ring 3.
+pub fn signal_all(signal: u32) -> usize {
+    if is_running() {
+        return 0;
+    }
+    let mut ignored = 0;
+    for slot in 0..MAX_PROCESSES {
+        let Some(process) = (unsafe { PROCESSES[slot] }) else {
+            continue;
+        };
+        if process.exit_code.is_some() {
+            continue;
+        }
+        if process.ignored & (1 << signal) != 0 {
+            ignored += 1;
+            continue;
+        }
+        unsafe {
+            if let Some(p) = PROCESSES[slot].as_mut() {
+                p.exit_code = Some(128 + signal);
+            }
+            finish(slot, 128 + signal);
+        }
+    }
+    ignored
+}
+
+/// End every remaining program with SIGKILL, returning how many there were
+pub fn kill_all() -> usize {
+    if is_running() {
+        return 0;
+    }
+    let mut killed = 0;
+    for slot in 0..MAX_PROCESSES {
+        if unsafe { PROCESSES[slot] }.is_some_and(|p| p.exit_code.is_none()) {
+            unsafe {
+                if let Some(p) = PROCESSES[slot].as_mut() {
+                    p.exit_code = Some(128 + SIGKILL);
+                }
+                finish(slot, 128 + SIGKILL);
+            }
+            killed += 1;
+        }
+    }
+    killed
+}
+
+/// OOM killer: mark the largest program that is neither essential nor
+/// the one running as killed, returning its PID
+///
+/// Size is the number of user pages mapped. Called from allocators, so
+/// the program only stops being scheduled; `finish_killed` tears it down.
+pub fn oom_kill() -> Option<Pid> {
+    unsafe {
+        let (slot, process) = (0..MAX_PROCESSES)
+            .filter(|&slot| Some(slot) != CURRENT)
+            .filter_map(|slot| PROCESSES[slot].map(|p| (slot, p)))
+            .filter(|(_, p)| p.exit_code.is_none() && !(*p.task).essential)
+            .max_by_key(|(_, p)| AddressSpace::from_directory((*p.task).cr3).user_pages())?;
+        if let Some(p) = PROCESSES[slot].as_mut() {
+            p.exit_code = Some(128 + SIGKILL);
+        }
+        Some(process.pid())
+    }
+}
+
+/// Tear down the programs `oom_kill` marked (from the main loop)
+pub fn finish_killed() {
+    if is_running() {
+        return;
+    }
+    for slot in 0..MAX_PROCESSES {
+        let Some(process) = (unsafe { PROCESSES[slot] }) else {
+            continue;
+        };
+        if let Some(code) = process.exit_code.filter(|_| !process.finished()) {
+            unsafe { finish(slot, code) };
+        }
+    }
+}
+
+// =============================================================================
+// Console
+// =============================================================================
+
+/// Controlling tty of the running program
+pub fn current_tty() -> Option<usize> {
+    unsafe { CURRENT.and_then(|slot| PROCESSES[slot]).and_then(|p| p.tty) }
+}
+
+/// Read console input for the running program
+///
+/// With nothing ready the program blocks, and its Read restarts once
+/// input arrives. Outside a user program (or without a tty) this
+/// returns 0.
+pub fn console_read(buf: &mut [u8]) -> usize {
+    let Some(tty) = current_tty() else {
+        return 0;
+    };
+    match crate::tty::read(tty, buf) {
+        Some(n) => n,
+        None => {
+            unsafe { PENDING = Some(Pending::Block(Wait::Stdin)) };
+            0
+        }
+    }
+}
+
+/// Block the running program until a window has an input event
+///
+/// The syscall restarts once one is queued (or the window is gone).
+#[cfg(feature = "gui")]
+pub fn wait_window_event(window_id: u32) {
+    if is_running() {
+        unsafe { PENDING = Some(Pending::Block(Wait::Window(window_id))) };
+    }
+}
+
+/// Block the running program on a port until it is ready or the
+/// deadline passes; the syscall then restarts
+pub fn wait_port(port: u32, deadline: u32, send: bool) {
+    if is_running() {
+        let wait = if send { Wait::PortSend(port, deadline) } else { Wait::PortReceive(port, deadline) };
+        unsafe { PENDING = Some(Pending::Block(wait)) };
+    }
+}
+
+/// Console output from the running program
+///
+/// Returns false when no program with a tty is running, so the caller
+/// writes to the screen as usual.
+pub fn console_write(bytes: &[u8]) -> bool {
+    match current_tty() {
+        Some(tty) => {
+            crate::tty::write(tty, bytes);
+            true
+        }
+        None => false,
+    }
+}
+
+// =============================================================================
+// Self-Tests (run by `selftest`)
+// =============================================================================
+
+use crate::selftest::check;
+
+/// Entry frames are accepted only when they end at this CPU's ESP0
+pub(crate) fn test_entry_frame() -> Result<(), &'static str> {
+    let size = core::mem::size_of::<InterruptFrame>() as u32;
+    let esp0 = gdt::kernel_stack();
+    check(esp0 > size, "no kernel stack in the TSS")?;
+    check(entry_in_step(esp0 - size, esp0), "frame ending at ESP0 refused")?;
+    // A ring 0 frame has no user ESP and SS, so it ends 8 bytes short
+    check(!entry_in_step(esp0 - size + 8, esp0), "frame without user ESP/SS accepted")?;
+    check(!entry_in_step(esp0 - size - 4, esp0), "frame below ESP0 accepted")?;
+    check(!entry_in_step(esp0 - size + 4, esp0), "frame past ESP0 accepted")?;
+    check(!entry_in_step(u32::MAX - size + 1, 0), "frame wrapping round accepted")
+}
+
+/// Registers the ring transition stub keeps (EBX, ECX, EDX, ESI, EDI)
+const STUB_VALUES: [u32; 5] = [0x1111_1111, 0x2222_2222, 0x3333_3333, 0x4444_4444, 0x5555_5555];
+
+/// Exit status of the stub when it finds a register changed
+const STUB_CORRUPTED: u32 = 1;
+
+/// Timer preemptions each stub program goes through
+const STUB_ROUNDS: usize = 3;
+
+extern "C" {
+    static exec_selftest_stub: u8;
+    static exec_selftest_stub_end: u8;
+}
+
+// Ring 3 program for `test_ring_transitions`, copied into a user page:
+// loads `STUB_VALUES`, saves ESP in EBP, then loops on GetPid through
+// INT 0x80 for ever, checking after each call that the registers, ESP and
+// SS are as it left them. The timer preempts it anywhere in the loop. On
+// a mismatch it exits with `STUB_CORRUPTED`. Only relative jumps, so it
+// runs wherever it is copied.
+core::arch::global_asm!(
+    ".global exec_selftest_stub",
+    ".global exec_selftest_stub_end",
+    "exec_selftest_stub:",
+    "    mov ebx, {b}",
+    "    mov ecx, {c}",
+    "    mov edx, {d}",
+    "    mov esi, {si}",
+    "    mov edi, {di}",
+    "    mov ebp, esp",
+    "2:",
+    "    mov eax, {getpid}",
+    "    int 0x80",
+    "    cmp ebx, {b}",
+    "    jne 3f",
+    "    cmp ecx, {c}",
+    "    jne 3f",
+    "    cmp edx, {d}",
+    "    jne 3f",
+    "    cmp esi, {si}",
+    "    jne 3f",
+    "    cmp edi, {di}",
+    "    jne 3f",
+    "    cmp ebp, esp",
+    "    jne 3f",
+    "    mov ax, ss",
+    "    cmp ax, {udata}",
+    "    je 2b",
+    "3:",
+    "    mov eax, {exit}",
+    "    mov ebx, {corrupted}",
+    "    int 0x80",
+    "    jmp 3b",
+    "exec_selftest_stub_end:",
+    b = const STUB_VALUES[0],
+    c = const STUB_VALUES[1],
+    d = const STUB_VALUES[2],
+    si = const STUB_VALUES[3],
+    di = const STUB_VALUES[4],
+    getpid = const crate::syscall::SyscallNumber::GetPid as u32,
+    exit = const crate::syscall::SyscallNumber::Exit as u32,
+    udata = const selectors::USER_DATA,
+    corrupted = const STUB_CORRUPTED,
+);
+
+/// Address space holding the stub as a flat program
+fn stub_image() -> Result<Image, &'static str> {
+    let code = unsafe {
+        let start = core::ptr::addr_of!(exec_selftest_stub);
+        let end = core::ptr::addr_of!(exec_selftest_stub_end);
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    };
+    let mut space = AddressSpace::new()?;
+    let mut vmas = VmaList::new();
+    let size = code.len() as u32;
+    let built = write_user(&mut space, FLAT_LOAD_ADDR, code)
+        .and_then(|_| vmas.add(FLAT_LOAD_ADDR, FLAT_LOAD_ADDR + size, VmaKind::Code, true))
+        .and_then(|_| vmas.setup_heap_and_stack(FLAT_LOAD_ADDR + size))
+        .and_then(|_| setup_stack(&mut space, &Args::default()));
+    match built {
+        Ok(stack) => Ok(Image { space, vmas, entry: FLAT_LOAD_ADDR, stack }),
+        Err(e) => {
+            space.destroy();
+            Err(e)
+        }
+    }
+}
+
+/// INT 0x80 and timer interrupts from ring 3 keep a program's registers,
+/// ESP and SS, and each entry lands on the running program's own kernel
+/// stack
+///
+/// Two copies of the stub take turns, so ESP0 has to follow each switch
+/// (`check_entry` kills a program whose entry doesn't).
+pub(crate) fn test_ring_transitions() -> Result<(), &'static str> {
+    check(!is_running(), "run from inside a user program")?;
+    check(crate::arch::interrupts_enabled(), "the timer can't preempt with interrupts off")?;
+    let kernel_esp0 = gdt::kernel_stack();
+
+    let first = adopt("ringtest", None, stub_image)?;
+    let second = match adopt("ringtest", None, stub_image) {
+        Ok(second) => second,
+        Err(e) => {
+            unsafe { end_stub(first.0) };
+            return Err(e);
+        }
+    };
+
+    let result = (|| {
+        for _ in 0..STUB_ROUNDS {
+            for (slot, _) in [first, second] {
+                // Runs until its time slice is up (or it exits)
+                resume(slot);
+                check(gdt::kernel_stack() == kernel_esp0, "ESP0 not given back to the kernel")?;
+
+                let process = unsafe { PROCESSES[slot] }.ok_or("stub program vanished")?;
+                match process.exit_code {
+                    None => {}
+                    Some(STUB_CORRUPTED) => return Err("registers changed across INT 0x80"),
+                    Some(EXIT_FAULT) => return Err("entry frame not at the program's ESP0"),
+                    Some(_) => return Err("stub program ended"),
+                }
+                // Saved by the timer interrupt that preempted it
+                let c = process.context;
+                check([c.ebx, c.ecx, c.edx, c.esi, c.edi] == STUB_VALUES, "registers changed across an IRQ")?;
+                check(c.user_esp == c.ebp, "ESP changed across an IRQ")?;
+                check(c.user_ss == selectors::USER_DATA as u32, "SS changed across an IRQ")?;
+                check(c.cs == selectors::USER_CODE as u32, "preempted outside ring 3")?;
+            }
+        }
+        Ok(())
+    })();
+
+    unsafe {
+        end_stub(first.0);
+        end_stub(second.0);
+    }
+    result
+}
+
+/// Kill and free a stub program from `test_ring_transitions`
+unsafe fn end_stub(slot: usize) {
+    let Some(process) = PROCESSES[slot].as_mut() else {
+        return;
+    };
+    if process.finished() {
+        free(slot);
+        return;
+    }
+    // An orphan is freed as it finishes
+    process.parent = None;
+    let code = *process.exit_code.get_or_insert(128 + SIGKILL);
+    finish(slot, code);
+}
+
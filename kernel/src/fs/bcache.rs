@@ -0,0 +1,527 @@
+//! Block Buffer Cache
+//!
+//! LRU cache of device blocks shared by every filesystem. Filesystems read
+//! and write whole blocks through `BCACHE` instead of touching a
+//! `BlockDevice` directly, so directory walks and FAT lookups are served
+//! from memory after the first access.
+//!
+//! - The cache is sized at boot from the free heap; `configure` changes it.
+//! - A run of sequential misses on a device triggers read-ahead: the next
+//!   `READAHEAD_BLOCKS` blocks are fetched in a single `read_blocks` call.
+//! - Writes only mark the buffer dirty. Dirty buffers reach the device when
+//!   they are evicted, on `sync` (the Sync syscall), or from
+//!   `periodic_flush` once they are older than `DIRTY_EXPIRE_MS`.
+//! - `barrier` orders writes to a device. Blocks dirtied after a barrier
+//!   never reach the device before every block dirtied ahead of it has
+//!   been written and the device has flushed its own write cache, however
+//!   the write-back is triggered (sync, eviction or age).
+//!
+//! Resizing the cache writes back dirty blocks, and the old buffers go
+//! back to the heap once the new ones are in place.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ptr::NonNull;
+use super::block::{BlockDevice, DeviceId};
+use super::{FsError, FsResult};
+
+/// Devices that can be registered at once
+pub const MAX_DEVICES: usize = 8;
+
+/// Cache size limits (in buffers)
+pub const MIN_BUFFERS: usize = 16;
+pub const MAX_BUFFERS: usize = 1024;
+
+/// Fraction of the free heap given to the cache at boot (1/N)
+const HEAP_SHARE: usize = 8;
+
+/// Block size assumed when sizing from the heap
+const SIZING_BLOCK_SIZE: usize = 512;
+
+/// Blocks fetched ahead of a sequential reader
+pub const READAHEAD_BLOCKS: u32 = 8;
+
+/// Consecutive sequential accesses before read-ahead kicks in
+const SEQUENTIAL_TRIGGER: u32 = 2;
+
+/// Dirty buffers older than this are written by the periodic flusher
+pub const DIRTY_EXPIRE_MS: u32 = 5000;
+
+/// How often the periodic flusher scans for expired buffers
+pub const FLUSH_INTERVAL_MS: u32 = 1000;
+
+/// One cached block
+struct Buffer {
+    dev: DeviceId,
+    lba: u64,
+    valid: bool,
+    dirty: bool,
+    /// Uptime when the buffer first became dirty
+    dirty_since: u32,
+    /// Access stamp for LRU replacement
+    last_use: u32,
+    /// Device epoch the block was dirtied in (see `barrier`)
+    epoch: u32,
+    /// Block contents (allocated on first use)
+    data: Vec<u8>,
+}
+
+impl Buffer {
+    const fn empty() -> Self {
+        Self {
+            dev: DeviceId(0),
+            lba: 0,
+            valid: false,
+            dirty: false,
+            dirty_since: 0,
+            last_use: 0,
+            epoch: 0,
+            data: Vec::new(),
+        }
+    }
+}
+
+/// A registered device and its access pattern
+struct DeviceSlot {
+    dev: &'static mut dyn BlockDevice,
+    /// Block a sequential reader would ask for next
+    next_lba: u64,
+    /// Length of the current sequential run
+    run: u32,
+    /// Epoch new writes are tagged with; `barrier` starts the next one
+    epoch: u32,
+}
+
+/// Cache counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BcacheStats {
+    pub hits: u32,
+    pub misses: u32,
+    /// Blocks brought in by read-ahead
+    pub readahead: u32,
+    pub writebacks: u32,
+    /// Barriers that separated dirty blocks
+    pub barriers: u32,
+    /// Device cache flushes issued
+    pub flushes: u32,
+}
+
+/// Shared block cache
+pub struct BufferCache {
+    buffers: Vec<Buffer>,
+    devices: [Option<DeviceSlot>; MAX_DEVICES],
+    /// Blocks to read ahead (0 disables read-ahead)
+    readahead: u32,
+    clock: u32,
+    last_flush_ms: u32,
+    stats: BcacheStats,
+}
+
+impl BufferCache {
+    pub const fn new() -> Self {
+        const NONE: Option<DeviceSlot> = None;
+        Self {
+            buffers: Vec::new(),
+            devices: [NONE; MAX_DEVICES],
+            readahead: READAHEAD_BLOCKS,
+            clock: 0,
+            last_flush_ms: 0,
+            stats: BcacheStats { hits: 0, misses: 0, readahead: 0, writebacks: 0, barriers: 0, flushes: 0 },
+        }
+    }
+
+    /// Resize the cache to `count` buffers, writing back dirty blocks first
+    pub fn configure(&mut self, count: usize) -> FsResult<()> {
+        self.sync()?;
+        let count = count.clamp(MIN_BUFFERS, MAX_BUFFERS);
+        let mut buffers = Vec::new();
+        buffers.try_reserve_exact(count).map_err(|_| FsError::NoSpace)?;
+        for _ in 0..count {
+            buffers.push(Buffer::empty());
+        }
+        self.buffers = buffers;
+        Ok(())
+    }
+
+    /// Number of buffers
+    pub fn capacity(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Set how many blocks are read ahead (0 disables)
+    pub fn set_readahead(&mut self, blocks: u32) {
+        self.readahead = blocks;
+    }
+
+    pub fn stats(&self) -> BcacheStats {
+        self.stats
+    }
+
+    /// Hand a device to the cache
+    pub fn register(&mut self, dev: &'static mut dyn BlockDevice) -> FsResult<DeviceId> {
+        let index = self.devices.iter().position(|d| d.is_none()).ok_or(FsError::NoSpace)?;
+        self.devices[index] = Some(DeviceSlot { dev, next_lba: 0, run: 0, epoch: 0 });
+        Ok(DeviceId(index as u8))
+    }
+
+    /// Write back and drop everything cached for a device, then release it
+    pub fn unregister(&mut self, id: DeviceId) -> FsResult<()> {
+        self.sync_device(id)?;
+        for buf in self.buffers.iter_mut().filter(|b| b.dev == id) {
+            buf.valid = false;
+        }
+        self.devices[id.0 as usize] = None;
+        Ok(())
+    }
+
+    /// Block size of a registered device
+    pub fn block_size(&self, id: DeviceId) -> FsResult<usize> {
+        self.devices.get(id.0 as usize)
+            .and_then(|d| d.as_ref())
+            .map(|d| d.dev.block_size())
+            .ok_or(FsError::NotMounted)
+    }
+
+    /// Direct access to a device, bypassing the cache
+    ///
+    /// Used by partition devices to reach the disk they sit on. The caller
+    /// must not use it for blocks the cache may hold for this device.
+    pub fn raw_device(&mut self, id: DeviceId) -> FsResult<NonNull<dyn BlockDevice>> {
+        let slot = self.device(id)?;
+        Ok(NonNull::from(&mut *slot.dev))
+    }
+
+    fn device(&mut self, id: DeviceId) -> FsResult<&mut DeviceSlot> {
+        self.devices.get_mut(id.0 as usize)
+            .and_then(|d| d.as_mut())
+            .ok_or(FsError::NotMounted)
+    }
+
+    fn find(&self, id: DeviceId, lba: u64) -> Option<usize> {
+        self.buffers.iter().position(|b| b.valid && b.dev == id && b.lba == lba)
+    }
+
+    /// Write one buffer to its device if dirty
+    fn write_out(&mut self, index: usize) -> FsResult<()> {
+        let buf = &mut self.buffers[index];
+        if !buf.valid || !buf.dirty {
+            return Ok(());
+        }
+        let slot = self.devices[buf.dev.0 as usize].as_mut().ok_or(FsError::NotMounted)?;
+        slot.dev.write_blocks(buf.lba, &buf.data)?;
+        buf.dirty = false;
+        self.stats.writebacks += 1;
+        Ok(())
+    }
+
+    /// Free a buffer for reuse (empty first, else least recently used)
+    fn evict(&mut self) -> FsResult<usize> {
+        if self.buffers.is_empty() {
+            return Err(FsError::NoSpace);
+        }
+        let clock = self.clock;
+        let victim = self.buffers.iter().position(|b| !b.valid).unwrap_or_else(|| {
+            self.buffers.iter().enumerate()
+                .max_by_key(|(_, b)| clock.wrapping_sub(b.last_use))
+                .map(|(i, _)| i)
+                .unwrap_or(0)
+        });
+        self.write_ordered(victim)?;
+        self.buffers[victim].valid = false;
+        Ok(victim)
+    }
+
+    /// Claim a buffer for (id, lba) without reading it
+    fn install(&mut self, id: DeviceId, lba: u64, block_size: usize) -> FsResult<usize> {
+        let i = self.evict()?;
+        let buf = &mut self.buffers[i];
+        buf.data.resize(block_size, 0);
+        buf.dev = id;
+        buf.lba = lba;
+        buf.valid = true;
+        buf.dirty = false;
+        buf.last_use = self.clock;
+        Ok(i)
+    }
+
+    /// Track sequential access; returns how many blocks to fetch on a miss
+    fn fetch_count(&mut self, id: DeviceId, lba: u64) -> FsResult<u32> {
+        let readahead = self.readahead;
+        let slot = self.device(id)?;
+        slot.run = if lba == slot.next_lba { slot.run.saturating_add(1) } else { 1 };
+        slot.next_lba = lba + 1;
+
+        let remaining = slot.dev.block_count().saturating_sub(lba);
+        if remaining == 0 {
+            return Err(FsError::IoError);
+        }
+        let wanted = if slot.run >= SEQUENTIAL_TRIGGER { 1 + readahead } else { 1 };
+        Ok((wanted as u64).min(remaining) as u32)
+    }
+
+    /// Read `count` blocks from `lba` in one request and cache them
+    fn fill(&mut self, id: DeviceId, lba: u64, count: u32, block_size: usize) -> FsResult<usize> {
+        if count <= 1 {
+            let i = self.install(id, lba, block_size)?;
+            let buf = &mut self.buffers[i];
+            let slot = self.devices[id.0 as usize].as_mut().ok_or(FsError::NotMounted)?;
+            if let Err(e) = slot.dev.read_blocks(lba, &mut buf.data) {
+                buf.valid = false;
+                return Err(e);
+            }
+            return Ok(i);
+        }
+
+        let mut batch = Vec::new();
+        batch.try_reserve_exact(count as usize * block_size).map_err(|_| FsError::NoSpace)?;
+        batch.resize(count as usize * block_size, 0);
+        self.device(id)?.dev.read_blocks(lba, &mut batch)?;
+
+        let mut first = 0;
+        for n in 0..count as u64 {
+            // Never clobber a cached (possibly dirty) copy
+            if let Some(i) = self.find(id, lba + n) {
+                if n == 0 {
+                    first = i;
+                }
+                continue;
+            }
+            let i = self.install(id, lba + n, block_size)?;
+            let start = n as usize * block_size;
+            self.buffers[i].data.copy_from_slice(&batch[start..start + block_size]);
+            if n == 0 {
+                first = i;
+            } else {
+                self.stats.readahead += 1;
+            }
+        }
+        Ok(first)
+    }
+
+    /// Read one block into `out` (which must be exactly one block long)
+    pub fn read(&mut self, id: DeviceId, lba: u64, out: &mut [u8]) -> FsResult<()> {
+        let block_size = self.block_size(id)?;
+        if out.len() != block_size {
+            return Err(FsError::IoError);
+        }
+        self.clock = self.clock.wrapping_add(1);
+
+        let i = match self.find(id, lba) {
+            Some(i) => {
+                self.stats.hits += 1;
+                let slot = self.device(id)?;
+                slot.run = if lba == slot.next_lba { slot.run.saturating_add(1) } else { 1 };
+                slot.next_lba = lba + 1;
+                i
+            }
+            None => {
+                self.stats.misses += 1;
+                let count = self.fetch_count(id, lba)?;
+                self.fill(id, lba, count, block_size)?
+            }
+        };
+
+        let buf = &mut self.buffers[i];
+        buf.last_use = self.clock;
+        out.copy_from_slice(&buf.data);
+        Ok(())
+    }
+
+    /// Replace one block (written back later)
+    pub fn write(&mut self, id: DeviceId, lba: u64, data: &[u8]) -> FsResult<()> {
+        let block_size = self.block_size(id)?;
+        if data.len() != block_size {
+            return Err(FsError::IoError);
+        }
+        if lba >= self.device(id)?.dev.block_count() {
+            return Err(FsError::IoError);
+        }
+        self.clock = self.clock.wrapping_add(1);
+
+        // A whole-block write never needs the old contents
+        let i = match self.find(id, lba) {
+            Some(i) => i,
+            None => self.install(id, lba, block_size)?,
+        };
+        // The old contents belong before the barrier; settle them first
+        let epoch = self.device(id)?.epoch;
+        if self.buffers[i].dirty && self.buffers[i].epoch != epoch {
+            self.settle(id, epoch)?;
+        }
+        let buf = &mut self.buffers[i];
+        buf.data.copy_from_slice(data);
+        if !buf.dirty {
+            buf.dirty = true;
+            buf.dirty_since = crate::arch::x86::pit::uptime_ms();
+            buf.epoch = epoch;
+        }
+        buf.last_use = self.clock;
+        Ok(())
+    }
+
+    // =========================================================================
+    // Write ordering
+    // =========================================================================
+
+    /// Order writes to a device
+    ///
+    /// Everything written to the device so far reaches it, and is flushed
+    /// from its write cache, before anything written after this call.
+    /// Costs nothing until a later block is written back; a barrier with
+    /// no dirty blocks ahead of it is dropped.
+    pub fn barrier(&mut self, id: DeviceId) -> FsResult<()> {
+        let epoch = self.device(id)?.epoch;
+        let pending = self.buffers.iter().any(|b| b.valid && b.dirty && b.dev == id && b.epoch == epoch);
+        if pending {
+            self.device(id)?.epoch = epoch.wrapping_add(1);
+            self.stats.barriers += 1;
+        }
+        Ok(())
+    }
+
+    /// Write back a device's dirty blocks from epochs before `before`
+    ///
+    /// Epoch by epoch, in LBA order within each, with a device flush after
+    /// each epoch so the next can't overtake it in the drive's cache.
+    fn settle(&mut self, id: DeviceId, before: u32) -> FsResult<()> {
+        let current = self.device(id)?.epoch;
+        // Epochs count up from the device's current one, wrapping
+        let age = |epoch: u32| current.wrapping_sub(epoch);
+        let limit = age(before);
+        let mut dirty: Vec<usize> = (0..self.buffers.len())
+            .filter(|&i| {
+                let b = &self.buffers[i];
+                b.valid && b.dirty && b.dev == id && age(b.epoch) > limit
+            })
+            .collect();
+        dirty.sort_unstable_by_key(|&i| (core::cmp::Reverse(age(self.buffers[i].epoch)), self.buffers[i].lba));
+
+        let mut last = None;
+        for i in dirty {
+            let epoch = self.buffers[i].epoch;
+            if last.is_some_and(|e| e != epoch) {
+                self.flush_device(id)?;
+            }
+            self.write_out(i)?;
+            last = Some(epoch);
+        }
+        if last.is_some() {
+            self.flush_device(id)?;
+        }
+        Ok(())
+    }
+
+    /// Write back one buffer, after anything its device must see first
+    fn write_ordered(&mut self, index: usize) -> FsResult<()> {
+        let buf = &self.buffers[index];
+        if !buf.valid || !buf.dirty {
+            return Ok(());
+        }
+        let (id, epoch) = (buf.dev, buf.epoch);
+        self.settle(id, epoch)?;
+        self.write_out(index)
+    }
+
+    /// Flush the device's own write cache (ATA FLUSH CACHE and the like)
+    fn flush_device(&mut self, id: DeviceId) -> FsResult<()> {
+        self.stats.flushes += 1;
+        self.device(id)?.dev.flush()
+    }
+
+    /// Write back a device's dirty blocks and flush it
+    ///
+    /// Blocks go out in barrier order, each epoch in LBA order. Stops at
+    /// the first failed write: carrying on could put later epochs on the
+    /// device without the earlier ones they depend on.
+    pub fn sync_device(&mut self, id: DeviceId) -> FsResult<()> {
+        let slot = self.device(id)?;
+        slot.epoch = slot.epoch.wrapping_add(1);
+        let epoch = slot.epoch;
+        self.settle(id, epoch)?;
+        self.flush_device(id)
+    }
+
+    /// Write back every device
+    pub fn sync(&mut self) -> FsResult<()> {
+        let mut result = Ok(());
+        for index in 0..MAX_DEVICES {
+            if self.devices[index].is_some() {
+                if let Err(e) = self.sync_device(DeviceId(index as u8)) {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Write back buffers that have been dirty longer than `DIRTY_EXPIRE_MS`
+    pub fn flush_expired(&mut self, now_ms: u32) -> FsResult<()> {
+        let mut result = Ok(());
+        for i in 0..self.buffers.len() {
+            let b = &self.buffers[i];
+            if b.valid && b.dirty && now_ms.wrapping_sub(b.dirty_since) >= DIRTY_EXPIRE_MS {
+                if let Err(e) = self.write_ordered(i) {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Number of dirty buffers
+    pub fn dirty_count(&self) -> usize {
+        self.buffers.iter().filter(|b| b.valid && b.dirty).count()
+    }
+}
+
+/// Global buffer cache
+pub static mut BCACHE: BufferCache = BufferCache::new();
+
+/// Size the cache from the free heap
+pub fn init() -> FsResult<()> {
+    let free = crate::mm::heap::stats().free;
+    let count = free / HEAP_SHARE / (SIZING_BLOCK_SIZE + core::mem::size_of::<Buffer>());
+    unsafe { BCACHE.configure(count) }
+}
+
+/// Write back all dirty blocks (Sync syscall)
+pub fn sync_all() -> FsResult<()> {
+    unsafe { BCACHE.sync() }
+}
+
+/// Background write-back, called regularly from the main loop
+///
+/// Stands in for a flusher task: there are no kernel threads yet, so the
+/// GUI loop calls this every frame and it rate-limits itself.
+pub fn periodic_flush(now_ms: u32) {
+    let cache = unsafe { &mut BCACHE };
+    if now_ms.wrapping_sub(cache.last_flush_ms) < FLUSH_INTERVAL_MS {
+        return;
+    }
+    cache.last_flush_ms = now_ms;
+    let _ = cache.flush_expired(now_ms);
+}
+
+/// Format cache state as the text of `/proc/bcache`
+pub fn report(out: &mut String) {
+    let cache = unsafe { &BCACHE };
+    let s = cache.stats;
+    let lookups = s.hits + s.misses;
+    let hit_pct = if lookups > 0 { s.hits as u64 * 100 / lookups as u64 } else { 0 };
+
+    let _ = writeln!(out, "buffers    {}", cache.capacity());
+    let _ = writeln!(out, "dirty      {}", cache.dirty_count());
+    let _ = writeln!(out, "hits       {} ({}%)", s.hits, hit_pct);
+    let _ = writeln!(out, "misses     {}", s.misses);
+    let _ = writeln!(out, "readahead  {} (window {})", s.readahead, cache.readahead);
+    let _ = writeln!(out, "writebacks {}", s.writebacks);
+    let _ = writeln!(out, "barriers   {}", s.barriers);
+    let _ = writeln!(out, "flushes    {}", s.flushes);
+    for (i, slot) in cache.devices.iter().enumerate() {
+        if let Some(slot) = slot {
+            let _ = writeln!(out, "dev{} {} {}x{}", i, slot.dev.name(),
+                slot.dev.block_count(), slot.dev.block_size());
+        }
+    }
+}
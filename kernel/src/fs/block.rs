@@ -0,0 +1,52 @@
+//! Block Device Layer
+//!
+//! Storage drivers expose whole-block access through `BlockDevice`.
+//! Filesystems don't call devices directly; they go through the shared
+//! buffer cache (`fs::bcache`), which owns every registered device.
+//!
+//! Storage drivers announce disks with `add_disk`; the storage stage of
+//! boot (`fs::init`) collects them, splits them into partitions, and hands
+//! everything to the cache.
+
+use alloc::vec::Vec;
+use super::FsResult;
+
+/// Handle for a device registered with the buffer cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId(pub u8);
+
+/// Block-addressed storage
+pub trait BlockDevice {
+    /// Short device name (for /proc/bcache)
+    fn name(&self) -> &'static str;
+
+    /// Bytes per block
+    fn block_size(&self) -> usize;
+
+    /// Total number of blocks
+    fn block_count(&self) -> u64;
+
+    /// Read `buf.len() / block_size()` consecutive blocks starting at `lba`
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> FsResult<()>;
+
+    /// Write `buf.len() / block_size()` consecutive blocks starting at `lba`
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> FsResult<()>;
+
+    /// Flush any volatile write cache in the device itself
+    fn flush(&mut self) -> FsResult<()> {
+        Ok(())
+    }
+}
+
+/// Disks announced by drivers and not yet scanned
+static mut PENDING_DISKS: Vec<&'static mut dyn BlockDevice> = Vec::new();
+
+/// Announce a disk found by a storage driver
+pub fn add_disk(disk: &'static mut dyn BlockDevice) {
+    unsafe { PENDING_DISKS.push(disk) }
+}
+
+/// Take every disk announced so far
+pub fn take_disks() -> Vec<&'static mut dyn BlockDevice> {
+    unsafe { core::mem::take(&mut PENDING_DISKS) }
+}
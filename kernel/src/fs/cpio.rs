@@ -0,0 +1,116 @@
+//! Initrd Archives
+//!
+//! Whole-file lookup in a cpio "newc" archive on a volume (what
+//! `find . | cpio -o -H newc` writes), for boot-time data passed with
+//! `make run RAMDISK=initrd.cpio`. There is no mountable driver: callers
+//! fetch files by name with `read_file`.
+//!
+//! Each member is a 110-byte ASCII header (magic `070701`, then thirteen
+//! 8-digit hex fields), its name with a terminating NUL, and its data;
+//! the name and the data are each padded to a multiple of 4 bytes. A
+//! member named `TRAILER!!!` ends the archive.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use super::bcache::BCACHE;
+use super::block::DeviceId;
+use super::{FsError, FsResult};
+
+/// Header size in bytes
+const HEADER_LEN: usize = 110;
+
+/// Magic at the start of every header
+const MAGIC: &[u8] = b"070701";
+
+/// Name of the member ending the archive
+const TRAILER: &[u8] = b"TRAILER!!!";
+
+/// Largest member `read_file` returns
+pub const MAX_FILE_SIZE: usize = 1 << 20;
+
+/// Header fields used (index of the 8-digit field after the magic)
+const FIELD_FILESIZE: usize = 6;
+const FIELD_NAMESIZE: usize = 11;
+
+/// Reads byte ranges of a volume through the buffer cache
+struct VolumeReader {
+    id: DeviceId,
+    block: Vec<u8>,
+    /// Block held in `block`
+    cached: Option<u64>,
+}
+
+impl VolumeReader {
+    fn new(id: DeviceId) -> FsResult<Self> {
+        let block_size = unsafe { (*core::ptr::addr_of!(BCACHE)).block_size(id)? };
+        Ok(Self { id, block: vec![0u8; block_size], cached: None })
+    }
+
+    fn read(&mut self, mut offset: u64, out: &mut [u8]) -> FsResult<()> {
+        let block_size = self.block.len() as u64;
+        let mut done = 0;
+        while done < out.len() {
+            let lba = offset / block_size;
+            if self.cached != Some(lba) {
+                unsafe { (*core::ptr::addr_of_mut!(BCACHE)).read(self.id, lba, &mut self.block)? };
+                self.cached = Some(lba);
+            }
+            let start = (offset % block_size) as usize;
+            let len = (self.block.len() - start).min(out.len() - done);
+            out[done..done + len].copy_from_slice(&self.block[start..start + len]);
+            done += len;
+            offset += len as u64;
+        }
+        Ok(())
+    }
+}
+
+/// One 8-digit hex header field
+fn field(header: &[u8], index: usize) -> FsResult<usize> {
+    let start = MAGIC.len() + index * 8;
+    let digits = core::str::from_utf8(&header[start..start + 8]).map_err(|_| FsError::InvalidFs)?;
+    usize::from_str_radix(digits, 16).map_err(|_| FsError::InvalidFs)
+}
+
+/// Names compare without a leading "./" or "/"
+fn strip(name: &[u8]) -> &[u8] {
+    let name = name.strip_prefix(b"./").unwrap_or(name);
+    name.strip_prefix(b"/").unwrap_or(name)
+}
+
+/// Contents of the member called `name` in the archive on volume `id`
+pub fn read_file(id: DeviceId, name: &str) -> FsResult<Vec<u8>> {
+    let mut reader = VolumeReader::new(id)?;
+    let wanted = strip(name.as_bytes());
+    let mut header = [0u8; HEADER_LEN];
+    let mut offset = 0u64;
+    loop {
+        reader.read(offset, &mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(FsError::InvalidFs);
+        }
+        let file_size = field(&header, FIELD_FILESIZE)?;
+        let name_size = field(&header, FIELD_NAMESIZE)?;
+        if name_size == 0 || name_size > super::MAX_PATH + 1 {
+            return Err(FsError::InvalidFs);
+        }
+
+        let mut member = vec![0u8; name_size];
+        reader.read(offset + HEADER_LEN as u64, &mut member)?;
+        let member = &member[..name_size - 1];
+        let data_offset = offset + (HEADER_LEN + name_size).next_multiple_of(4) as u64;
+
+        if member == TRAILER {
+            return Err(FsError::NotFound);
+        }
+        if strip(member) == wanted {
+            if file_size > MAX_FILE_SIZE {
+                return Err(FsError::NoSpace);
+            }
+            let mut data = vec![0u8; file_size];
+            reader.read(data_offset, &mut data)?;
+            return Ok(data);
+        }
+        offset = data_offset + file_size.next_multiple_of(4) as u64;
+    }
+}
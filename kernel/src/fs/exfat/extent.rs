@@ -0,0 +1,130 @@
+//! Cluster Run (Extent) Cache
+//!
+//! Per-open-file map from file cluster index to disk cluster, stored as runs
+//! of consecutive clusters. Sequential reads of a fragmented file then only
+//! touch the FAT when crossing into a run that has not been seen yet, and
+//! seeks resume the chain walk from the nearest known run rather than from
+//! the first cluster.
+//!
+//! Files flagged `NoFatChain` are a single contiguous run and never consult
+//! the FAT at all.
+
+/// Runs remembered per open file
+pub const MAX_EXTENTS: usize = 8;
+
+/// A run of consecutive clusters
+#[derive(Debug, Clone, Copy)]
+pub struct Extent {
+    /// Index of the first cluster within the file
+    pub file_cluster: u32,
+    /// Disk cluster it maps to
+    pub disk_cluster: u32,
+    /// Number of clusters in the run
+    pub len: u32,
+}
+
+impl Extent {
+    const EMPTY: Self = Self { file_cluster: 0, disk_cluster: 0, len: 0 };
+
+    fn contains(&self, file_cluster: u32) -> bool {
+        file_cluster >= self.file_cluster && file_cluster - self.file_cluster < self.len
+    }
+}
+
+/// Extent cache for one file
+#[derive(Debug, Clone, Copy)]
+pub struct ExtentCache {
+    extents: [Extent; MAX_EXTENTS],
+    count: usize,
+    /// Next slot to replace once full (slot 0 keeps the file start)
+    replace: usize,
+    /// Whole file is one contiguous run (NoFatChain)
+    contiguous: bool,
+}
+
+impl ExtentCache {
+    pub const fn empty() -> Self {
+        Self {
+            extents: [Extent::EMPTY; MAX_EXTENTS],
+            count: 0,
+            replace: 1,
+            contiguous: false,
+        }
+    }
+
+    /// Start tracking a file
+    ///
+    /// `contiguous` is the stream entry's NoFatChain flag; `clusters` is
+    /// the file's allocated length in clusters (used only when contiguous).
+    pub fn reset(&mut self, first_cluster: u32, contiguous: bool, clusters: u32) {
+        *self = Self::empty();
+        self.contiguous = contiguous;
+        if first_cluster >= super::cluster::FIRST_VALID {
+            let len = if contiguous { clusters.max(1) } else { 1 };
+            self.extents[0] = Extent { file_cluster: 0, disk_cluster: first_cluster, len };
+            self.count = 1;
+        }
+    }
+
+    pub fn is_contiguous(&self) -> bool {
+        self.contiguous
+    }
+
+    /// Disk cluster for a file cluster index, if a cached run covers it
+    pub fn lookup(&self, file_cluster: u32) -> Option<u32> {
+        self.extents[..self.count].iter()
+            .find(|e| e.contains(file_cluster))
+            .map(|e| e.disk_cluster + (file_cluster - e.file_cluster))
+    }
+
+    /// Closest known mapping at or before `file_cluster` (start of a walk)
+    pub fn nearest_before(&self, file_cluster: u32) -> Option<(u32, u32)> {
+        self.extents[..self.count].iter()
+            .filter(|e| e.file_cluster <= file_cluster)
+            .max_by_key(|e| e.file_cluster)
+            .map(|e| {
+                let last = (e.len - 1).min(file_cluster - e.file_cluster);
+                (e.file_cluster + last, e.disk_cluster + last)
+            })
+    }
+
+    /// Record that `file_cluster` lives at `disk_cluster`
+    pub fn insert(&mut self, file_cluster: u32, disk_cluster: u32) {
+        if self.lookup(file_cluster).is_some() {
+            return;
+        }
+
+        // Extend a run that ends right before this cluster
+        for e in self.extents[..self.count].iter_mut() {
+            if e.file_cluster + e.len == file_cluster && e.disk_cluster + e.len == disk_cluster {
+                e.len += 1;
+                return;
+            }
+        }
+
+        let extent = Extent { file_cluster, disk_cluster, len: 1 };
+        if self.count < MAX_EXTENTS {
+            self.extents[self.count] = extent;
+            self.count += 1;
+        } else {
+            self.extents[self.replace] = extent;
+            self.replace = if self.replace + 1 >= MAX_EXTENTS { 1 } else { self.replace + 1 };
+        }
+    }
+
+    /// Forget mappings at or beyond `clusters` (after truncation)
+    pub fn truncate(&mut self, clusters: u32) {
+        let mut kept = 0;
+        for i in 0..self.count {
+            let mut e = self.extents[i];
+            if e.file_cluster >= clusters {
+                continue;
+            }
+            e.len = e.len.min(clusters - e.file_cluster);
+            self.extents[kept] = e;
+            kept += 1;
+        }
+        self.count = kept;
+        self.replace = 1;
+    }
+}
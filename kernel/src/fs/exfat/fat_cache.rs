@@ -0,0 +1,231 @@
+//! FAT Sector Cache
+//!
+//! Keeps recently used FAT sectors in memory so following a cluster chain
+//! costs one disk read per FAT sector instead of one per cluster. Slots are
+//! replaced least-recently-used. With `WritePolicy::WriteBack`, modified
+//! sectors stay dirty until `flush` (called on unmount); `WriteThrough`
+//! writes every update immediately.
+//!
+//! Only the first FAT is cached. On volumes with two FATs the second is
+//! kept in sync whenever a dirty sector is written out.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use super::super::{FsError, FsResult};
+use super::cluster;
+
+/// Number of cached FAT sectors
+pub const FAT_CACHE_SLOTS: usize = 16;
+
+/// Bytes per FAT entry
+const ENTRY_SIZE: usize = 4;
+
+/// When modified FAT sectors reach the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Write each update immediately
+    WriteThrough,
+    /// Hold dirty sectors until flush/unmount
+    WriteBack,
+}
+
+/// Sector-granular access to the underlying volume
+pub trait SectorIo {
+    /// Read one sector at a volume-relative LBA
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> FsResult<()>;
+    /// Write one sector at a volume-relative LBA
+    fn write_sector(&mut self, lba: u64, buf: &[u8]) -> FsResult<()>;
+}
+
+/// One cached FAT sector
+struct FatSlot {
+    /// Sector index within the FAT
+    sector: u32,
+    valid: bool,
+    dirty: bool,
+    /// Access stamp for LRU replacement
+    last_use: u32,
+    data: Vec<u8>,
+}
+
+/// Cache counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FatCacheStats {
+    pub hits: u32,
+    pub misses: u32,
+    pub writebacks: u32,
+}
+
+/// In-memory FAT cache
+pub struct FatCache {
+    slots: Vec<FatSlot>,
+    policy: WritePolicy,
+    /// FAT start (sectors from volume start)
+    fat_offset: u32,
+    /// FAT length in sectors
+    fat_length: u32,
+    /// Number of FATs on the volume
+    fat_count: u8,
+    sector_size: usize,
+    clock: u32,
+    stats: FatCacheStats,
+}
+
+impl FatCache {
+    pub const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            policy: WritePolicy::WriteBack,
+            fat_offset: 0,
+            fat_length: 0,
+            fat_count: 1,
+            sector_size: 512,
+            clock: 0,
+            stats: FatCacheStats { hits: 0, misses: 0, writebacks: 0 },
+        }
+    }
+
+    /// Set up for a freshly mounted volume, dropping any previous contents
+    pub fn configure(&mut self, fat_offset: u32, fat_length: u32, fat_count: u8, sector_size: usize) {
+        self.fat_offset = fat_offset;
+        self.fat_length = fat_length;
+        self.fat_count = fat_count.max(1);
+        self.sector_size = sector_size;
+        self.slots.clear();
+        for _ in 0..FAT_CACHE_SLOTS {
+            self.slots.push(FatSlot {
+                sector: 0,
+                valid: false,
+                dirty: false,
+                last_use: 0,
+                data: vec![0; sector_size],
+            });
+        }
+        self.stats = FatCacheStats::default();
+    }
+
+    pub fn policy(&self) -> WritePolicy {
+        self.policy
+    }
+
+    /// Change the write policy (switching to write-through flushes first)
+    pub fn set_policy(&mut self, io: &mut dyn SectorIo, policy: WritePolicy) -> FsResult<()> {
+        if policy == WritePolicy::WriteThrough {
+            self.flush(io)?;
+        }
+        self.policy = policy;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> FatCacheStats {
+        self.stats
+    }
+
+    /// Locate the FAT sector and byte offset holding a cluster's entry
+    fn locate(&self, cluster: u32) -> FsResult<(u32, usize)> {
+        let byte = cluster as usize * ENTRY_SIZE;
+        let sector = (byte / self.sector_size) as u32;
+        if self.slots.is_empty() || sector >= self.fat_length {
+            return Err(FsError::InvalidFs);
+        }
+        Ok((sector, byte % self.sector_size))
+    }
+
+    /// Get the slot holding a FAT sector, reading it on a miss
+    fn slot(&mut self, io: &mut dyn SectorIo, sector: u32) -> FsResult<usize> {
+        self.clock = self.clock.wrapping_add(1);
+
+        if let Some(i) = self.slots.iter().position(|s| s.valid && s.sector == sector) {
+            self.stats.hits += 1;
+            self.slots[i].last_use = self.clock;
+            return Ok(i);
+        }
+        self.stats.misses += 1;
+
+        // Prefer an empty slot, otherwise evict the least recently used
+        let clock = self.clock;
+        let victim = self.slots.iter().position(|s| !s.valid).unwrap_or_else(|| {
+            self.slots.iter().enumerate()
+                .max_by_key(|(_, s)| clock.wrapping_sub(s.last_use))
+                .map(|(i, _)| i)
+                .unwrap_or(0)
+        });
+        self.write_out(io, victim)?;
+
+        let lba = self.fat_offset as u64 + sector as u64;
+        let slot = &mut self.slots[victim];
+        slot.valid = false;
+        io.read_sector(lba, &mut slot.data)?;
+        slot.sector = sector;
+        slot.valid = true;
+        slot.dirty = false;
+        slot.last_use = self.clock;
+        Ok(victim)
+    }
+
+    /// Write a dirty slot to every FAT copy
+    fn write_out(&mut self, io: &mut dyn SectorIo, index: usize) -> FsResult<()> {
+        let slot = &mut self.slots[index];
+        if !slot.valid || !slot.dirty {
+            return Ok(());
+        }
+        for fat in 0..self.fat_count as u64 {
+            let lba = self.fat_offset as u64 + fat * self.fat_length as u64 + slot.sector as u64;
+            io.write_sector(lba, &slot.data)?;
+        }
+        slot.dirty = false;
+        self.stats.writebacks += 1;
+        Ok(())
+    }
+
+    /// Read the FAT entry for a cluster
+    pub fn get_entry(&mut self, io: &mut dyn SectorIo, cluster: u32) -> FsResult<u32> {
+        let (sector, offset) = self.locate(cluster)?;
+        let i = self.slot(io, sector)?;
+        let data = &self.slots[i].data;
+        Ok(u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]))
+    }
+
+    /// Update the FAT entry for a cluster
+    pub fn set_entry(&mut self, io: &mut dyn SectorIo, cluster: u32, value: u32) -> FsResult<()> {
+        let (sector, offset) = self.locate(cluster)?;
+        let i = self.slot(io, sector)?;
+        self.slots[i].data[offset..offset + ENTRY_SIZE].copy_from_slice(&value.to_le_bytes());
+        self.slots[i].dirty = true;
+        if self.policy == WritePolicy::WriteThrough {
+            self.write_out(io, i)?;
+        }
+        Ok(())
+    }
+
+    /// Follow the chain one step; `None` at end of chain
+    pub fn next_cluster(&mut self, io: &mut dyn SectorIo, cluster: u32) -> FsResult<Option<u32>> {
+        match self.get_entry(io, cluster)? {
+            cluster::END => Ok(None),
+            cluster::BAD | cluster::FREE => Err(FsError::InvalidFs),
+            next if next < cluster::FIRST_VALID => Err(FsError::InvalidFs),
+            next => Ok(Some(next)),
+        }
+    }
+
+    /// Write every dirty sector
+    pub fn flush(&mut self, io: &mut dyn SectorIo) -> FsResult<()> {
+        for i in 0..self.slots.len() {
+            self.write_out(io, i)?;
+        }
+        Ok(())
+    }
+
+    /// Drop all cached sectors (dirty data is discarded; flush first)
+    pub fn invalidate(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.valid = false;
+            slot.dirty = false;
+        }
+    }
+
+    /// Any unwritten updates?
+    pub fn is_dirty(&self) -> bool {
+        self.slots.iter().any(|s| s.valid && s.dirty)
+    }
+}
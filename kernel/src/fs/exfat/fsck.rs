@@ -0,0 +1,380 @@
+//! Consistency Check
+//!
+//! Walks everything reachable from the root directory and checks it
+//! against the structures that describe it:
+//!
+//! - every cluster chain stays inside the heap, ends, and is long enough
+//!   for its stream's data length
+//! - no cluster belongs to two chains (cross-linked)
+//! - the allocation bitmap marks exactly the clusters reached; the rest
+//!   are orphaned (allocated, but nothing refers to them)
+//! - every file's entry set checksum matches its entries
+//!
+//! With `repair`, the simple cases are fixed: orphaned clusters are freed,
+//! clusters in use are marked in the bitmap, a valid data length past the
+//! end of a stream is clamped, and bad set checksums are recomputed.
+//! Cross-links and broken chains lose data whichever way they are
+//! resolved, so they are only reported.
+//!
+//! A volume marked dirty has the flag cleared once nothing is left
+//! unrepaired, which also makes it writable unless it was mounted
+//! read-only on purpose.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::{Filesystem, FsError, FsResult};
+use super::{
+    attrs, cluster, name, stream_flags, volume_flags, EntryType, ExfatFilesystem,
+    FileEntry, FileNameEntry, StreamEntry, DIR_ENTRY_SIZE,
+};
+
+/// Problems described line by line; the rest are only counted
+const MAX_REPORTED: u32 = 32;
+
+/// Directory bytes read for checking (the rest of a larger one is skipped)
+const MAX_DIR_BYTES: usize = 1 << 20;
+
+/// Offset of the valid data length in an entry set (in the stream entry)
+const VALID_LENGTH_OFFSET: usize = DIR_ENTRY_SIZE + 8;
+
+/// Checksum of a file's entry set (skips the checksum field itself)
+pub fn entry_set_checksum(entries: &[u8]) -> u16 {
+    entries.iter().enumerate()
+        .filter(|&(i, _)| i != 2 && i != 3)
+        .fold(0u16, |sum, (_, &byte)| sum.rotate_right(1).wrapping_add(byte as u16))
+}
+
+fn read_entry<T>(raw: &[u8]) -> T {
+    debug_assert!(raw.len() >= core::mem::size_of::<T>());
+    unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const T) }
+}
+
+/// One bit per heap cluster, laid out like the allocation bitmap
+struct ClusterMap {
+    bits: Vec<u8>,
+}
+
+impl ClusterMap {
+    fn new(clusters: u32) -> FsResult<Self> {
+        let len = (clusters as usize).div_ceil(8);
+        let mut bits = Vec::new();
+        bits.try_reserve_exact(len).map_err(|_| FsError::NoSpace)?;
+        bits.resize(len, 0);
+        Ok(Self { bits })
+    }
+
+    fn get(&self, index: u32) -> bool {
+        self.bits[index as usize / 8] & (1 << (index % 8)) != 0
+    }
+
+    /// Set a bit, returning whether it was already set
+    fn set(&mut self, index: u32) -> bool {
+        let was = self.get(index);
+        self.bits[index as usize / 8] |= 1 << (index % 8);
+        was
+    }
+
+    fn clear(&mut self, index: u32) {
+        self.bits[index as usize / 8] &= !(1 << (index % 8));
+    }
+}
+
+/// State of one check
+struct Checker<'a> {
+    fs: &'a mut ExfatFilesystem,
+    out: &'a mut String,
+    repair: bool,
+    /// Clusters reached so far
+    used: ClusterMap,
+    problems: u32,
+    repaired: u32,
+}
+
+impl Checker<'_> {
+    /// Count a problem, describing it while under `MAX_REPORTED`
+    fn report(&mut self, repaired: bool, args: core::fmt::Arguments) {
+        if self.problems < MAX_REPORTED {
+            let _ = self.out.write_fmt(args);
+            let _ = writeln!(self.out, "{}", if repaired { " (repaired)" } else { "" });
+        }
+        self.problems += 1;
+        if repaired {
+            self.repaired += 1;
+        }
+    }
+
+    fn in_heap(&self, c: u32) -> bool {
+        c >= cluster::FIRST_VALID && c - cluster::FIRST_VALID < self.fs.cluster_count
+    }
+
+    /// Mark a stream's clusters used, collecting them into `clusters`
+    ///
+    /// Returns false if the chain is broken, cross-linked or too short
+    /// (the problem has been reported).
+    fn claim(&mut self, what: &str, first: u32, bytes: u64, contiguous: bool,
+             mut clusters: Option<&mut Vec<u32>>) -> FsResult<bool> {
+        let needed = bytes.div_ceil(self.fs.cluster_size() as u64);
+        if first == cluster::FREE {
+            if bytes != 0 {
+                self.report(false, format_args!("{}: {} bytes but no clusters", what, bytes));
+                return Ok(false);
+            }
+            return Ok(true);
+        }
+
+        let mut at = first;
+        let mut length = 0u64;
+        loop {
+            if !self.in_heap(at) {
+                self.report(false, format_args!("{}: chain leaves the heap at cluster {}", what, at));
+                return Ok(false);
+            }
+            // Also what stops a looping chain
+            if self.used.set(at - cluster::FIRST_VALID) {
+                self.report(false, format_args!("{}: cluster {} is cross-linked", what, at));
+                return Ok(false);
+            }
+            if let Some(list) = clusters.as_deref_mut() {
+                list.try_reserve(1).map_err(|_| FsError::NoSpace)?;
+                list.push(at);
+            }
+            length += 1;
+
+            let next = if contiguous {
+                if length >= needed { None } else { Some(at + 1) }
+            } else {
+                match self.fs.get_next_cluster(at) {
+                    Ok(next) => next,
+                    Err(FsError::InvalidFs) => {
+                        self.report(false, format_args!("{}: bad FAT entry for cluster {}", what, at));
+                        return Ok(false);
+                    }
+                    Err(e) => return Err(e),
+                }
+            };
+            match next {
+                Some(next) => at = next,
+                None => break,
+            }
+        }
+
+        if length < needed {
+            self.report(false, format_args!("{}: {} clusters, {} bytes need {}", what, length, bytes, needed));
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Read clusters back to back
+    fn read_clusters(&mut self, clusters: &[u32]) -> FsResult<Vec<u8>> {
+        let size = self.fs.cluster_size();
+        let mut data = Vec::new();
+        data.try_reserve_exact(clusters.len() * size).map_err(|_| FsError::NoSpace)?;
+        data.resize(clusters.len() * size, 0);
+        for (chunk, &c) in data.chunks_mut(size).zip(clusters) {
+            self.fs.read_cluster(c, chunk)?;
+        }
+        Ok(data)
+    }
+
+    /// Write back the clusters of `data` flagged in `dirty`
+    fn write_clusters(&mut self, clusters: &[u32], data: &[u8], dirty: &[bool]) -> FsResult<()> {
+        let size = self.fs.cluster_size();
+        for (i, _) in dirty.iter().enumerate().filter(|&(_, &d)| d) {
+            self.fs.write_cluster(clusters[i], &data[i * size..(i + 1) * size])?;
+        }
+        Ok(())
+    }
+
+    /// Check every entry set in a directory, queueing its subdirectories
+    fn scan_dir(&mut self, path: &str, clusters: &[u32], pending: &mut Vec<(String, Vec<u32>)>) -> FsResult<()> {
+        let size = self.fs.cluster_size();
+        let clusters = &clusters[..clusters.len().min((MAX_DIR_BYTES / size).max(1))];
+        let mut data = self.read_clusters(clusters)?;
+        let mut dirty = vec![false; clusters.len()];
+
+        let mut offset = 0;
+        while offset + DIR_ENTRY_SIZE <= data.len() {
+            let kind = data[offset];
+            if kind == EntryType::EndOfDirectory as u8 {
+                break;
+            }
+            // Bitmap, up-case, label and deleted entries were handled (or
+            // don't matter); a stray secondary is as harmless as a deleted one
+            if kind != EntryType::File as u8 {
+                offset += DIR_ENTRY_SIZE;
+                continue;
+            }
+
+            let file: FileEntry = read_entry(&data[offset..]);
+            let end = offset + (file.secondary_count as usize + 1) * DIR_ENTRY_SIZE;
+            if file.secondary_count < 2 || end > data.len() {
+                self.report(false, format_args!("{}: truncated entry set at byte {}", path, offset));
+                offset += DIR_ENTRY_SIZE;
+                continue;
+            }
+            let stream: StreamEntry = read_entry(&data[offset + DIR_ENTRY_SIZE..]);
+            let names: Vec<FileNameEntry> = data[offset + 2 * DIR_ENTRY_SIZE..end]
+                .chunks_exact(DIR_ENTRY_SIZE)
+                .take_while(|e| e[0] == EntryType::FileNameExtension as u8)
+                .map(read_entry)
+                .collect();
+            let child = match name::from_entries(&names, stream.name_length) {
+                Ok(units) => format!("{}/{}", path, name::to_utf8(&units)),
+                Err(_) => format!("{}/<entry at byte {}>", path, offset),
+            };
+            if stream.entry_type != EntryType::StreamExtension as u8 {
+                self.report(false, format_args!("{}: no stream extension entry", child));
+                offset = end;
+                continue;
+            }
+
+            // Fix fields first so the checksum is computed over the result
+            let before = entry_set_checksum(&data[offset..end]);
+            let (valid, length) = (stream.valid_data_length, stream.data_length);
+            let mut rewritten = false;
+            if valid > length {
+                if self.repair {
+                    let at = offset + VALID_LENGTH_OFFSET;
+                    data[at..at + 8].copy_from_slice(&length.to_le_bytes());
+                    rewritten = true;
+                }
+                self.report(self.repair, format_args!("{}: valid data length {} past the end ({})", child, valid, length));
+            }
+            let stored = file.set_checksum;
+            if stored != before {
+                self.report(self.repair, format_args!("{}: entry set checksum {:04X}, expected {:04X}", child, stored, before));
+            }
+            if self.repair && (rewritten || stored != before) {
+                let checksum = entry_set_checksum(&data[offset..end]);
+                data[offset + 2..offset + 4].copy_from_slice(&checksum.to_le_bytes());
+                // The set may straddle two clusters
+                dirty[offset / size] = true;
+                dirty[(end - 1) / size] = true;
+            }
+
+            let contiguous = stream.general_flags & stream_flags::NO_FAT_CHAIN != 0;
+            let first = stream.first_cluster;
+            if file.file_attributes & attrs::DIRECTORY != 0 {
+                let mut list = Vec::new();
+                if self.claim(&child, first, length, contiguous, Some(&mut list))? && !list.is_empty() {
+                    pending.push((child, list));
+                }
+            } else {
+                self.claim(&child, first, length, contiguous, None)?;
+            }
+            offset = end;
+        }
+
+        self.write_clusters(clusters, &data, &dirty)
+    }
+
+    /// Compare the allocation bitmap with the clusters reached
+    fn compare_bitmap(&mut self, clusters: &[u32]) -> FsResult<()> {
+        let mut on_disk = ClusterMap { bits: self.read_clusters(clusters)? };
+        let count = self.fs.cluster_count;
+        let mut changed = false;
+
+        let mut index = 0;
+        while index < count {
+            // Skip whole bytes that agree
+            let byte = index as usize / 8;
+            if index % 8 == 0 && index + 8 <= count && on_disk.bits[byte] == self.used.bits[byte] {
+                index += 8;
+                continue;
+            }
+            let used = self.used.get(index);
+            if on_disk.get(index) != used {
+                let c = index + cluster::FIRST_VALID;
+                if used {
+                    self.report(self.repair, format_args!("cluster {}: in use but free in the bitmap", c));
+                    on_disk.set(index);
+                } else {
+                    self.report(self.repair, format_args!("cluster {}: allocated but unreachable (orphaned)", c));
+                    on_disk.clear(index);
+                }
+                changed = true;
+            }
+            index += 1;
+        }
+
+        if changed && self.repair {
+            let dirty = vec![true; clusters.len()];
+            self.write_clusters(clusters, &on_disk.bits, &dirty)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check the volume, repairing what can be repaired if asked
+///
+/// Returns the number of problems found (repaired or not).
+pub fn check(fs: &mut ExfatFilesystem, repair: bool, out: &mut String) -> FsResult<u32> {
+    let _ = writeln!(out, "volume: {}{}", if fs.is_dirty() { "dirty" } else { "clean" },
+        if fs.volume_flags & volume_flags::MEDIA_FAILURE != 0 { ", media failure reported" } else { "" });
+
+    let used = ClusterMap::new(fs.cluster_count)?;
+    let root_cluster = fs.root_cluster;
+    let mut checker = Checker { fs, out, repair, used, problems: 0, repaired: 0 };
+
+    let mut root = Vec::new();
+    checker.claim("/", root_cluster, 0, false, Some(&mut root))?;
+
+    let mut bitmap = Vec::new();
+    let bitmap_ok = match checker.fs.find_bitmap_entry()? {
+        Some(entry) => {
+            let (first, length) = (entry.first_cluster, entry.data_length);
+            let needed = (checker.fs.cluster_count as u64).div_ceil(8);
+            if length < needed {
+                checker.report(false, format_args!("allocation bitmap: {} bytes, needs {}", length, needed));
+            }
+            checker.claim("allocation bitmap", first, length, false, Some(&mut bitmap))? && length >= needed
+        }
+        None => {
+            checker.report(false, format_args!("allocation bitmap: no entry in the root directory"));
+            false
+        }
+    };
+
+    match checker.fs.find_upcase_entry()? {
+        Some(entry) => {
+            let (first, length) = (entry.first_cluster, entry.data_length);
+            checker.claim("up-case table", first, length, false, None)?;
+            if !checker.fs.upcase.is_from_volume() {
+                checker.report(false, format_args!("up-case table: bad size or checksum"));
+            }
+        }
+        None => checker.report(false, format_args!("up-case table: no entry in the root directory")),
+    }
+
+    let mut pending = vec![(String::new(), root)];
+    while let Some((path, clusters)) = pending.pop() {
+        checker.scan_dir(&path, &clusters, &mut pending)?;
+    }
+
+    // Without a sound bitmap there is nothing to compare against
+    if bitmap_ok {
+        checker.compare_bitmap(&bitmap)?;
+    }
+
+    let (problems, repaired) = (checker.problems, checker.repaired);
+    if problems > MAX_REPORTED {
+        let _ = writeln!(out, "({} more not shown)", problems - MAX_REPORTED);
+    }
+    if repaired > 0 {
+        fs.sync()?;
+    }
+    if fs.is_dirty() && repair {
+        if problems == repaired {
+            fs.write_volume_flags(fs.volume_flags & !volume_flags::VOLUME_DIRTY)?;
+            fs.read_only = fs.options.read_only;
+            let _ = writeln!(out, "dirty flag cleared{}", if fs.read_only { "" } else { ", volume writable" });
+        } else {
+            let _ = writeln!(out, "dirty flag kept: {} problem(s) not repaired", problems - repaired);
+        }
+    }
+    Ok(problems)
+}
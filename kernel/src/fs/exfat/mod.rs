@@ -6,14 +6,56 @@
 //! # Features
 //!
 //! - Supports files up to 16 EB (exabytes)
-//! - Long filename support (up to 255 characters)
+//! - Long filename support (up to 255 UTF-16 code units, see `name`)
+//! - Case-insensitive names through the volume's up-case table (`upcase`)
 //! - No journaling (simpler, but less crash-resilient)
 //! - Widely compatible with Windows, macOS, Linux
+//!
+//! # Write ordering
+//!
+//! With no journal, a power cut leaves whatever subset of writes reached
+//! the disk. Updates are ordered so that subset is always readable, at
+//! worst leaking clusters:
+//!
+//! 1. file data (cluster heap)
+//! 2. allocation: FAT entries
+//! 3. the directory entry set (size, first cluster, checksum)
+//!
+//! A buffer cache barrier separates each step, so a directory entry never
+//! points at clusters whose contents or chain are not on the disk yet.
+//!
+//! # Dirty volumes
+//!
+//! The boot sector's VolumeDirty flag is set while another system has
+//! unflushed changes on the volume. Such a volume is mounted read-only
+//! (unless `MountOptions::force`) until `fsck` scans it and clears the
+//! flag.
+//!
+//! # Volume identity
+//!
+//! A Volume GUID entry in the root directory names the volume across
+//! boots (`Filesystem::volume_id`). Formatters often leave it out;
+//! `set_volume_id` adds one in a free root directory slot.
+
+pub mod fat_cache;
+pub mod extent;
+pub mod fsck;
+pub mod name;
+pub mod upcase;
 
 use super::{
     Filesystem, Metadata, FileType, OpenFlags, SeekFrom,
-    FsResult, FsError, DirEntry, ReadDir, Permissions,
+    FsResult, FsError, DirEntry, Permissions, MountOptions,
 };
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use super::bcache::BCACHE;
+use super::block::DeviceId;
+use crate::util::{SlotKey, SlotMap, SlotStats, Uuid};
+use extent::ExtentCache;
+use fat_cache::{FatCache, FatCacheStats, SectorIo, WritePolicy};
+use upcase::UpcaseTable;
 
 /// exFAT boot sector
 #[derive(Debug, Clone, Copy)]
@@ -87,6 +129,61 @@ pub enum EntryType {
     DeletedFile = 0x05,
 }
 
+/// Size of every directory entry
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+/// exFAT up-case table directory entry (in the root directory)
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct UpcaseEntry {
+    /// Entry type (0x82)
+    pub entry_type: u8,
+    /// Reserved
+    pub reserved1: [u8; 3],
+    /// Checksum of the table's bytes
+    pub table_checksum: u32,
+    /// Reserved
+    pub reserved2: [u8; 12],
+    /// First cluster of the table
+    pub first_cluster: u32,
+    /// Table size in bytes
+    pub data_length: u64,
+}
+
+/// exFAT allocation bitmap directory entry (in the root directory)
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct BitmapEntry {
+    /// Entry type (0x81)
+    pub entry_type: u8,
+    /// Bit 0: which FAT the bitmap belongs to
+    pub bitmap_flags: u8,
+    /// Reserved
+    pub reserved: [u8; 18],
+    /// First cluster of the bitmap
+    pub first_cluster: u32,
+    /// Bitmap size in bytes
+    pub data_length: u64,
+}
+
+/// exFAT volume GUID directory entry (in the root directory)
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct VolumeGuidEntry {
+    /// Entry type (0xA0)
+    pub entry_type: u8,
+    /// Secondary count (always 0)
+    pub secondary_count: u8,
+    /// Checksum of the entry (a set of one)
+    pub set_checksum: u16,
+    /// General primary flags
+    pub general_flags: u16,
+    /// GUID (first three fields little-endian)
+    pub volume_guid: [u8; 16],
+    /// Reserved
+    pub reserved: [u8; 10],
+}
+
 /// exFAT file directory entry
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
@@ -121,6 +218,33 @@ pub struct FileEntry {
     pub reserved2: [u8; 7],
 }
 
+impl FileEntry {
+    /// Creation time (Unix seconds)
+    pub fn created(&self) -> u32 {
+        crate::time::from_exfat(self.create_timestamp, self.create_10ms, self.create_utc_offset)
+    }
+
+    /// Last modification time (Unix seconds)
+    pub fn modified(&self) -> u32 {
+        crate::time::from_exfat(self.modified_timestamp, self.modified_10ms, self.modified_utc_offset)
+    }
+
+    /// Last access time (Unix seconds; exFAT keeps no sub-second part)
+    pub fn accessed(&self) -> u32 {
+        crate::time::from_exfat(self.accessed_timestamp, 0, self.accessed_utc_offset)
+    }
+
+    /// Stamp the modification and access times with `unix`
+    pub fn touch(&mut self, unix: u32) {
+        let (timestamp, ten_ms, offset) = crate::time::to_exfat(unix);
+        self.modified_timestamp = timestamp;
+        self.modified_10ms = ten_ms;
+        self.modified_utc_offset = offset;
+        self.accessed_timestamp = timestamp;
+        self.accessed_utc_offset = offset;
+    }
+}
+
 /// exFAT stream extension entry
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
@@ -168,6 +292,27 @@ pub mod attrs {
     pub const ARCHIVE: u16 = 0x20;
 }
 
+/// Stream extension general secondary flags
+pub mod stream_flags {
+    /// Clusters have been allocated
+    pub const ALLOCATION_POSSIBLE: u8 = 0x01;
+    /// File occupies one contiguous run; its FAT entries are not valid
+    pub const NO_FAT_CHAIN: u8 = 0x02;
+}
+
+/// Boot sector volume flags
+pub mod volume_flags {
+    /// Second FAT and bitmap are the active ones
+    pub const ACTIVE_FAT: u16 = 0x01;
+    /// Volume may be inconsistent (not cleanly unmounted)
+    pub const VOLUME_DIRTY: u16 = 0x02;
+    /// A read or write hit a media error
+    pub const MEDIA_FAILURE: u16 = 0x04;
+}
+
+/// Byte offset of `volume_flags` in the boot sector
+const VOLUME_FLAGS_OFFSET: usize = 106;
+
 /// exFAT cluster values
 pub mod cluster {
     /// Free cluster
@@ -183,10 +328,14 @@ pub mod cluster {
 /// Maximum open files
 const MAX_OPEN_FILES: usize = 32;
 
+/// Root directory clusters searched for the up-case table entry
+const MAX_ROOT_SCAN: u32 = 16;
+
+/// Largest up-case table accepted (the uncompressed table)
+const MAX_UPCASE_BYTES: u64 = 0x10000 * 2;
+
 /// Open file handle
 struct OpenFile {
-    /// Is this slot in use?
-    in_use: bool,
     /// First cluster
     first_cluster: u32,
     /// Current cluster
@@ -197,21 +346,48 @@ struct OpenFile {
     size: u64,
     /// Open flags
     flags: OpenFlags,
+    /// Known cluster runs
+    extents: ExtentCache,
 }
 
 impl OpenFile {
     const fn empty() -> Self {
         Self {
-            in_use: false,
             first_cluster: 0,
             current_cluster: 0,
             position: 0,
             size: 0,
             flags: OpenFlags::read_only(),
+            extents: ExtentCache::empty(),
         }
     }
 }
 
+/// Does this sector hold an exFAT boot record?
+pub fn probe(sector: &[u8]) -> bool {
+    sector.len() >= 512
+        && &sector[3..11] == b"EXFAT   "
+        && u16::from_le_bytes([sector[510], sector[511]]) == 0xAA55
+}
+
+/// Sector access to the volume, through the shared buffer cache
+struct SectorDevice {
+    /// Backing device (None until attached)
+    id: Option<DeviceId>,
+}
+
+impl SectorIo for SectorDevice {
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> FsResult<()> {
+        let id = self.id.ok_or(FsError::IoError)?;
+        unsafe { BCACHE.read(id, lba, buf) }
+    }
+
+    fn write_sector(&mut self, lba: u64, buf: &[u8]) -> FsResult<()> {
+        let id = self.id.ok_or(FsError::IoError)?;
+        unsafe { BCACHE.write(id, lba, buf) }
+    }
+}
+
 /// exFAT filesystem driver
 pub struct ExfatFilesystem {
     /// Is mounted?
@@ -223,14 +399,29 @@ pub struct ExfatFilesystem {
     root_cluster: u32,
     cluster_count: u32,
     fat_offset: u32,
-    /// Open files
-    open_files: [OpenFile; MAX_OPEN_FILES],
+    fat_length: u32,
+    number_of_fats: u8,
+    /// Underlying volume
+    dev: SectorDevice,
+    /// Cached FAT sectors
+    fat: FatCache,
+    /// Open files (handles are their slot keys)
+    open_files: SlotMap<OpenFile, MAX_OPEN_FILES>,
+    /// Case mapping for name comparison and hashing
+    upcase: UpcaseTable,
+    /// Options for the next mount
+    options: MountOptions,
+    /// Boot sector volume flags as of mount (or the last `fsck`)
+    volume_flags: u16,
+    /// Writes are refused
+    read_only: bool,
+    /// From the Volume GUID entry, as of mount
+    volume_guid: Option<Uuid>,
 }
 
 impl ExfatFilesystem {
     /// Create a new exFAT filesystem instance
     pub const fn new() -> Self {
-        const EMPTY: OpenFile = OpenFile::empty();
         Self {
             mounted: false,
             bytes_per_sector: 512,
@@ -239,7 +430,16 @@ impl ExfatFilesystem {
             root_cluster: 0,
             cluster_count: 0,
             fat_offset: 0,
-            open_files: [EMPTY; MAX_OPEN_FILES],
+            fat_length: 0,
+            number_of_fats: 1,
+            dev: SectorDevice { id: None },
+            fat: FatCache::new(),
+            open_files: SlotMap::new(),
+            upcase: UpcaseTable::ascii(),
+            options: MountOptions::new(),
+            volume_flags: 0,
+            read_only: false,
+            volume_guid: None,
         }
     }
     
@@ -249,46 +449,336 @@ impl ExfatFilesystem {
         (self.cluster_heap_offset as u64) + (cluster_offset * self.sectors_per_cluster as u64)
     }
     
-    /// Read a cluster from disk
-    fn read_cluster(&self, _cluster: u32, _buf: &mut [u8]) -> FsResult<()> {
-        // TODO: Implement actual disk I/O
-        Err(FsError::IoError)
+    /// Bytes per cluster
+    fn cluster_size(&self) -> usize {
+        (self.bytes_per_sector * self.sectors_per_cluster) as usize
     }
     
-    /// Write a cluster to disk
-    fn write_cluster(&mut self, _cluster: u32, _buf: &[u8]) -> FsResult<()> {
-        // TODO: Implement actual disk I/O
-        Err(FsError::IoError)
+    /// Read a cluster from disk (`buf` is one cluster long)
+    fn read_cluster(&mut self, cluster: u32, buf: &mut [u8]) -> FsResult<()> {
+        if cluster < cluster::FIRST_VALID || cluster - cluster::FIRST_VALID >= self.cluster_count {
+            return Err(FsError::InvalidFs);
+        }
+        let first = self.cluster_to_sector(cluster);
+        let sectors = buf.chunks_mut(self.bytes_per_sector as usize).take(self.sectors_per_cluster as usize);
+        for (i, sector) in sectors.enumerate() {
+            self.dev.read_sector(first + i as u64, sector)?;
+        }
+        Ok(())
     }
     
-    /// Get next cluster in chain from FAT
-    fn get_next_cluster(&self, _cluster: u32) -> FsResult<u32> {
-        // TODO: Read from FAT
-        Err(FsError::IoError)
+    /// Write a cluster to disk (`buf` is one cluster long)
+    fn write_cluster(&mut self, cluster: u32, buf: &[u8]) -> FsResult<()> {
+        if cluster < cluster::FIRST_VALID || cluster - cluster::FIRST_VALID >= self.cluster_count {
+            return Err(FsError::InvalidFs);
+        }
+        let first = self.cluster_to_sector(cluster);
+        let sectors = buf.chunks(self.bytes_per_sector as usize).take(self.sectors_per_cluster as usize);
+        for (i, sector) in sectors.enumerate() {
+            self.dev.write_sector(first + i as u64, sector)?;
+        }
+        Ok(())
     }
     
-    /// Allocate a file handle
-    fn alloc_handle(&mut self) -> FsResult<u64> {
-        for (i, file) in self.open_files.iter_mut().enumerate() {
-            if !file.in_use {
-                file.in_use = true;
-                return Ok(i as u64);
+    /// Get next cluster in chain from FAT (None at end of chain)
+    fn get_next_cluster(&mut self, cluster: u32) -> FsResult<Option<u32>> {
+        self.fat.next_cluster(&mut self.dev, cluster)
+    }
+    
+    /// Disk cluster holding the `index`th cluster of an open file
+    ///
+    /// Served from the file's extent cache when possible; otherwise walks
+    /// the FAT from the nearest known run, caching what it finds.
+    fn file_cluster(&mut self, handle: u64, index: u32) -> FsResult<u32> {
+        let extents = self.get_file(handle)?.extents;
+        if let Some(cluster) = extents.lookup(index) {
+            return Ok(cluster);
+        }
+        if extents.is_contiguous() {
+            // Past the end of a NoFatChain file
+            return Err(FsError::InvalidFs);
+        }
+        
+        let (mut at, mut cluster) = extents.nearest_before(index).ok_or(FsError::InvalidFs)?;
+        while at < index {
+            cluster = self.get_next_cluster(cluster)?.ok_or(FsError::InvalidFs)?;
+            at += 1;
+            self.get_file(handle)?.extents.insert(at, cluster);
+        }
+        Ok(cluster)
+    }
+    
+    /// Choose when FAT updates reach the disk
+    pub fn set_write_policy(&mut self, policy: WritePolicy) -> FsResult<()> {
+        self.fat.set_policy(&mut self.dev, policy)
+    }
+    
+    /// Current FAT write policy
+    pub fn write_policy(&self) -> WritePolicy {
+        self.fat.policy()
+    }
+    
+    /// FAT cache counters
+    pub fn fat_cache_stats(&self) -> FatCacheStats {
+        self.fat.stats()
+    }
+    
+    /// Read and validate the boot sector, taking the volume geometry from it
+    fn load_boot_sector(&mut self, id: DeviceId) -> FsResult<()> {
+        let block_size = unsafe { BCACHE.block_size(id)? };
+        let mut sector = vec![0u8; block_size];
+        self.dev.read_sector(0, &mut sector)?;
+        if !probe(&sector) {
+            return Err(FsError::InvalidFs);
+        }
+        
+        let boot = unsafe { core::ptr::read_unaligned(sector.as_ptr() as *const ExfatBootSector) };
+        if boot.bytes_per_sector_shift > 12 || 1usize << boot.bytes_per_sector_shift != block_size {
+            return Err(FsError::InvalidFs);
+        }
+        if boot.number_of_fats == 0 || boot.number_of_fats > 2 || boot.sectors_per_cluster_shift > 25 {
+            return Err(FsError::InvalidFs);
+        }
+        
+        self.bytes_per_sector = block_size as u32;
+        self.sectors_per_cluster = 1 << boot.sectors_per_cluster_shift;
+        self.cluster_heap_offset = boot.cluster_heap_offset;
+        self.root_cluster = boot.root_directory_cluster;
+        self.cluster_count = boot.cluster_count;
+        self.fat_offset = boot.fat_offset;
+        self.fat_length = boot.fat_length;
+        self.number_of_fats = boot.number_of_fats;
+        self.volume_flags = boot.volume_flags;
+        Ok(())
+    }
+    
+    /// Rewrite the volume flags in the boot sector
+    ///
+    /// The boot checksum skips this field, so nothing else changes. The
+    /// backup boot sector keeps its flags as formatted.
+    fn write_volume_flags(&mut self, flags: u16) -> FsResult<()> {
+        let id = self.dev.id.ok_or(FsError::NotMounted)?;
+        let mut sector = vec![0u8; self.bytes_per_sector as usize];
+        self.dev.read_sector(0, &mut sector)?;
+        sector[VOLUME_FLAGS_OFFSET..VOLUME_FLAGS_OFFSET + 2].copy_from_slice(&flags.to_le_bytes());
+        self.dev.write_sector(0, &sector)?;
+        unsafe { BCACHE.sync_device(id)? };
+        self.volume_flags = flags;
+        Ok(())
+    }
+    
+    /// Was the volume marked dirty?
+    pub fn is_dirty(&self) -> bool {
+        self.volume_flags & volume_flags::VOLUME_DIRTY != 0
+    }
+    
+    /// Find the first entry of a type in the root directory
+    fn find_root_entry(&mut self, kind: EntryType) -> FsResult<Option<[u8; DIR_ENTRY_SIZE]>> {
+        Ok(self.find_root_slot(|t| t == kind as u8)?.map(|(_, _, entry)| entry))
+    }
+    
+    /// Find the first root directory entry whose type byte matches
+    ///
+    /// Returns its cluster, byte offset in the cluster, and contents. The
+    /// end-of-directory entry stops the scan; `matches` sees it first.
+    fn find_root_slot(&mut self, matches: impl Fn(u8) -> bool)
+        -> FsResult<Option<(u32, usize, [u8; DIR_ENTRY_SIZE])>>
+    {
+        let mut buf = vec![0u8; self.cluster_size()];
+        let mut cluster = self.root_cluster;
+        for _ in 0..MAX_ROOT_SCAN {
+            self.read_cluster(cluster, &mut buf)?;
+            for (i, raw) in buf.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+                if matches(raw[0]) {
+                    let mut entry = [0u8; DIR_ENTRY_SIZE];
+                    entry.copy_from_slice(raw);
+                    return Ok(Some((cluster, i * DIR_ENTRY_SIZE, entry)));
+                }
+                if raw[0] == EntryType::EndOfDirectory as u8 {
+                    return Ok(None);
+                }
+            }
+            match self.get_next_cluster(cluster)? {
+                Some(next) => cluster = next,
+                None => break,
             }
         }
-        Err(FsError::TooManyOpenFiles)
+        Ok(None)
     }
     
-    /// Get open file by handle
-    fn get_file(&mut self, handle: u64) -> FsResult<&mut OpenFile> {
-        let idx = handle as usize;
-        if idx >= MAX_OPEN_FILES {
-            return Err(FsError::IoError);
+    /// Overwrite one root directory entry
+    fn write_root_slot(&mut self, cluster: u32, offset: usize, entry: &[u8; DIR_ENTRY_SIZE]) -> FsResult<()> {
+        let mut buf = vec![0u8; self.cluster_size()];
+        self.read_cluster(cluster, &mut buf)?;
+        buf[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(entry);
+        self.write_cluster(cluster, &buf)
+    }
+    
+    /// Read the Volume GUID entry
+    ///
+    /// An entry with a bad checksum or a nil GUID counts as no identity.
+    fn load_volume_guid(&mut self) -> FsResult<()> {
+        self.volume_guid = None;
+        let Some(raw) = self.find_root_entry(EntryType::VolumeGuid)? else {
+            return Ok(());
+        };
+        let entry = unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const VolumeGuidEntry) };
+        let checksum = entry.set_checksum;
+        if fsck::entry_set_checksum(&raw) != checksum {
+            crate::klog::write_str("[exFAT] bad volume GUID checksum, ignoring it\n");
+            return Ok(());
         }
-        let file = &mut self.open_files[idx];
-        if !file.in_use {
-            return Err(FsError::IoError);
+        let guid = Uuid::from_guid_bytes(entry.volume_guid);
+        self.volume_guid = Some(guid).filter(|g| !g.is_nil());
+        Ok(())
+    }
+    
+    /// Write (or with None, delete) the Volume GUID entry
+    ///
+    /// An existing entry is rewritten in place. Otherwise the first unused
+    /// root slot takes it, or the end-of-directory slot when another one
+    /// follows it in the same cluster to keep ending the directory.
+    fn store_volume_guid(&mut self, guid: Option<Uuid>) -> FsResult<()> {
+        let entries_per_cluster = self.cluster_size() / DIR_ENTRY_SIZE;
+        let existing = self.find_root_slot(|t| t == EntryType::VolumeGuid as u8)?;
+        let slot = match (existing, guid) {
+            (Some(slot), _) => slot,
+            (None, None) => return Ok(()),
+            // In-use bit clear: deleted or never used
+            (None, Some(_)) => self.find_root_slot(|t| t & 0x80 == 0)?
+                .filter(|&(_, offset, raw)| raw[0] != EntryType::EndOfDirectory as u8
+                    || offset / DIR_ENTRY_SIZE + 1 < entries_per_cluster)
+                .ok_or(FsError::NoSpace)?,
+        };
+        let (cluster, offset, _) = slot;
+        
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        match guid {
+            Some(guid) => {
+                let entry = VolumeGuidEntry {
+                    entry_type: EntryType::VolumeGuid as u8,
+                    secondary_count: 0,
+                    set_checksum: 0,
+                    general_flags: 0,
+                    volume_guid: guid.to_guid_bytes(),
+                    reserved: [0; 10],
+                };
+                unsafe { core::ptr::write_unaligned(raw.as_mut_ptr() as *mut VolumeGuidEntry, entry) };
+                let checksum = fsck::entry_set_checksum(&raw);
+                raw[2..4].copy_from_slice(&checksum.to_le_bytes());
+            }
+            // Clearing the in-use bit leaves an unused, not an end, entry
+            None => raw[0] = EntryType::VolumeGuid as u8 & !0x80,
         }
-        Ok(file)
+        self.write_root_slot(cluster, offset, &raw)?;
+        let id = self.dev.id.ok_or(FsError::NotMounted)?;
+        unsafe { (*core::ptr::addr_of_mut!(BCACHE)).sync_device(id)? };
+        self.volume_guid = guid;
+        Ok(())
+    }
+    
+    /// Find the up-case table entry in the root directory
+    fn find_upcase_entry(&mut self) -> FsResult<Option<UpcaseEntry>> {
+        let raw = self.find_root_entry(EntryType::UpcaseTable)?;
+        Ok(raw.map(|raw| unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const UpcaseEntry) }))
+    }
+    
+    /// Find the allocation bitmap entry in the root directory
+    fn find_bitmap_entry(&mut self) -> FsResult<Option<BitmapEntry>> {
+        let raw = self.find_root_entry(EntryType::AllocationBitmap)?;
+        Ok(raw.map(|raw| unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const BitmapEntry) }))
+    }
+    
+    /// Load the volume's up-case table
+    ///
+    /// A missing or corrupt table leaves the ASCII mapping in place so the
+    /// volume still mounts; names differing only in non-ASCII case then
+    /// compare unequal.
+    fn load_upcase(&mut self) -> FsResult<()> {
+        let Some(entry) = self.find_upcase_entry()? else {
+            crate::klog::write_str("[exFAT] no up-case table, using ASCII case folding\n");
+            return Ok(());
+        };
+        let length = entry.data_length;
+        if length == 0 || length > MAX_UPCASE_BYTES || length % 2 != 0 {
+            crate::klog::write_str("[exFAT] bad up-case table size, using ASCII case folding\n");
+            return Ok(());
+        }
+        
+        let mut data = vec![0u8; length as usize];
+        let mut buf = vec![0u8; self.cluster_size()];
+        let mut cluster = entry.first_cluster;
+        let mut chunks = data.chunks_mut(buf.len()).peekable();
+        while let Some(chunk) = chunks.next() {
+            self.read_cluster(cluster, &mut buf)?;
+            chunk.copy_from_slice(&buf[..chunk.len()]);
+            if chunks.peek().is_some() {
+                cluster = self.get_next_cluster(cluster)?.ok_or(FsError::InvalidFs)?;
+            }
+        }
+        
+        let checksum = entry.table_checksum;
+        match UpcaseTable::parse(&data) {
+            Some(table) if UpcaseTable::checksum(&data) == checksum => self.upcase = table,
+            _ => crate::klog::write_str("[exFAT] bad up-case table, using ASCII case folding\n"),
+        }
+        Ok(())
+    }
+    
+    /// Encode a name for this volume, with its name hash
+    pub fn encode_name(&self, name: &str) -> FsResult<(Vec<u16>, u16)> {
+        let units = name::to_utf16(name)?;
+        let hash = self.upcase.name_hash(&units);
+        Ok((units, hash))
+    }
+    
+    /// Do two names refer to the same file on this volume?
+    pub fn same_name(&self, a: &str, b: &str) -> bool {
+        match (name::to_utf16(a), name::to_utf16(b)) {
+            (Ok(a), Ok(b)) => self.upcase.names_equal(&a, &b),
+            _ => false,
+        }
+    }
+    
+    /// Up-case table in use
+    pub fn upcase_table(&self) -> &UpcaseTable {
+        &self.upcase
+    }
+    
+    /// Use a device registered with the buffer cache (before mounting)
+    pub fn attach(&mut self, id: DeviceId) -> FsResult<()> {
+        if self.mounted {
+            return Err(FsError::AlreadyExists);
+        }
+        self.dev.id = Some(id);
+        Ok(())
+    }
+    
+    /// Set how the next mount treats the volume (before mounting)
+    pub fn set_options(&mut self, options: MountOptions) -> FsResult<()> {
+        if self.mounted {
+            return Err(FsError::AlreadyExists);
+        }
+        self.options = options;
+        Ok(())
+    }
+    
+    /// Refuse writes while mounted read-only
+    fn check_writable(&self) -> FsResult<()> {
+        if self.read_only { Err(FsError::ReadOnly) } else { Ok(()) }
+    }
+    
+    /// Allocate a file handle
+    fn alloc_handle(&mut self) -> FsResult<u64> {
+        let key = self.open_files.insert(OpenFile::empty()).ok_or(FsError::TooManyOpenFiles)?;
+        Ok(key.to_u64())
+    }
+    
+    /// Get open file by handle (a closed handle no longer resolves)
+    fn get_file(&mut self, handle: u64) -> FsResult<&mut OpenFile> {
+        SlotKey::from_u64(handle)
+            .and_then(|key| self.open_files.get_key(key))
+            .ok_or(FsError::IoError)
     }
 }
 
@@ -302,9 +792,28 @@ impl Filesystem for ExfatFilesystem {
             return Ok(());
         }
         
-        // TODO: Read boot sector and validate
-        // For now, just mark as mounted with defaults
+        // Without a device the defaults are kept (nothing can be read anyway)
+        if let Some(id) = self.dev.id {
+            self.load_boot_sector(id)?;
+        }
+        
+        self.fat.configure(
+            self.fat_offset,
+            self.fat_length,
+            self.number_of_fats,
+            self.bytes_per_sector as usize,
+        );
+        if self.dev.id.is_some() {
+            self.load_upcase()?;
+            self.load_volume_guid()?;
+        }
         
+        self.read_only = self.options.read_only;
+        if self.is_dirty() && !self.options.force && !self.read_only {
+            crate::klog::write_str("[exFAT] volume dirty, mounting read-only (run fsck to clear)\n");
+            crate::gui::notify::warn("Disk not cleanly unmounted: read-only");
+            self.read_only = true;
+        }
         self.mounted = true;
         Ok(())
     }
@@ -314,13 +823,18 @@ impl Filesystem for ExfatFilesystem {
             return Err(FsError::NotMounted);
         }
         
+        // Write back the FAT and the volume's blocks before dropping the cache
+        let flushed = self.sync();
+        self.fat.invalidate();
+        
         // Close all open files
-        for file in &mut self.open_files {
-            file.in_use = false;
-        }
+        self.open_files.clear();
+        self.upcase = UpcaseTable::ascii();
+        self.read_only = false;
+        self.volume_guid = None;
         
         self.mounted = false;
-        Ok(())
+        flushed
     }
     
     fn open(&mut self, path: &str, flags: OpenFlags) -> FsResult<u64> {
@@ -328,6 +842,10 @@ impl Filesystem for ExfatFilesystem {
             return Err(FsError::NotMounted);
         }
         
+        if flags.write || flags.create || flags.truncate {
+            self.check_writable()?;
+        }
+        
         // TODO: Implement path lookup and file opening
         // For now, return a dummy handle
         
@@ -338,13 +856,14 @@ impl Filesystem for ExfatFilesystem {
         file.size = 0;
         file.first_cluster = 0;
         file.current_cluster = 0;
+        file.extents.reset(0, false, 0);
         
         Ok(handle)
     }
     
     fn close(&mut self, handle: u64) -> FsResult<()> {
-        let file = self.get_file(handle)?;
-        file.in_use = false;
+        let key = SlotKey::from_u64(handle).ok_or(FsError::IoError)?;
+        self.open_files.remove_key(key).ok_or(FsError::IoError)?;
         Ok(())
     }
     
@@ -397,13 +916,14 @@ impl Filesystem for ExfatFilesystem {
         Err(FsError::NotFound)
     }
     
-    fn readdir(&mut self, _path: &str) -> FsResult<ReadDir> {
+    fn read_dir_entry(&mut self, _path: &str, _cookie: u64) -> FsResult<Option<(DirEntry, u64)>> {
         if !self.mounted {
             return Err(FsError::NotMounted);
         }
         
-        // TODO: Implement directory reading
-        Ok(ReadDir::empty())
+        // TODO: Implement directory reading (cookie = byte offset of the
+        // next entry set in the directory's clusters)
+        Ok(None)
     }
     
     fn mkdir(&mut self, _path: &str) -> FsResult<()> {
@@ -411,6 +931,8 @@ impl Filesystem for ExfatFilesystem {
             return Err(FsError::NotMounted);
         }
         
+        self.check_writable()?;
+        
         // TODO: Implement directory creation
         Err(FsError::IoError)
     }
@@ -420,6 +942,8 @@ impl Filesystem for ExfatFilesystem {
             return Err(FsError::NotMounted);
         }
         
+        self.check_writable()?;
+        
         // TODO: Implement file removal
         Err(FsError::IoError)
     }
@@ -429,6 +953,8 @@ impl Filesystem for ExfatFilesystem {
             return Err(FsError::NotMounted);
         }
         
+        self.check_writable()?;
+        
         // TODO: Implement directory removal
         Err(FsError::IoError)
     }
@@ -438,9 +964,59 @@ impl Filesystem for ExfatFilesystem {
             return Err(FsError::NotMounted);
         }
         
+        self.check_writable()?;
+        
         // TODO: Implement rename
         Err(FsError::IoError)
     }
+    
+    fn emulates_symlinks(&self) -> bool {
+        true
+    }
+    
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+    
+    fn check(&mut self, repair: bool, out: &mut String) -> FsResult<u32> {
+        if !self.mounted {
+            return Err(FsError::NotMounted);
+        }
+        fsck::check(self, repair, out)
+    }
+    
+    fn sync(&mut self) -> FsResult<()> {
+        let Some(id) = self.dev.id else {
+            return self.fat.flush(&mut self.dev);
+        };
+        // Data already in the cache goes first, then the chains holding it
+        unsafe { BCACHE.barrier(id)? };
+        self.fat.flush(&mut self.dev)?;
+        unsafe { BCACHE.barrier(id)? };
+        unsafe { BCACHE.sync_device(id) }
+    }
+    
+    fn fsync(&mut self, handle: u64) -> FsResult<()> {
+        // Dirty state isn't tracked per file; check the handle, sync it all
+        self.get_file(handle)?;
+        self.sync()
+    }
+    
+    fn handle_stats(&self) -> Option<SlotStats> {
+        Some(self.open_files.stats())
+    }
+    
+    fn volume_id(&self) -> Option<Uuid> {
+        self.volume_guid
+    }
+    
+    fn set_volume_id(&mut self, id: Option<Uuid>) -> FsResult<()> {
+        if !self.mounted {
+            return Err(FsError::NotMounted);
+        }
+        self.check_writable()?;
+        self.store_volume_guid(id)
+    }
 }
 
 impl Default for ExfatFilesystem {
@@ -0,0 +1,75 @@
+//! File Names
+//!
+//! exFAT stores names as UTF-16LE code units, 15 per file name entry and
+//! at most 255 per name. The kernel's paths are UTF-8. Characters outside
+//! the Basic Multilingual Plane take a surrogate pair (two units) on disk
+//! and four bytes in UTF-8, so lengths differ between the two and both
+//! limits are checked on the UTF-16 side.
+//!
+//! Names written by other systems may hold unpaired surrogates; they are
+//! shown as U+FFFD rather than rejected, so the file can still be listed.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::fs::{FsError, FsResult};
+use super::FileNameEntry;
+
+/// Code units in a name
+pub const MAX_NAME_UNITS: usize = 255;
+
+/// Code units per file name entry
+pub const UNITS_PER_ENTRY: usize = 15;
+
+/// Characters exFAT does not allow in names (besides controls)
+const FORBIDDEN: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// Encode a path component for the volume
+pub fn to_utf16(name: &str) -> FsResult<Vec<u16>> {
+    if name.is_empty() || name.chars().any(|c| (c as u32) < 0x20 || FORBIDDEN.contains(&c)) {
+        return Err(FsError::InvalidPath);
+    }
+    let units: Vec<u16> = name.encode_utf16().collect();
+    if units.len() > MAX_NAME_UNITS {
+        return Err(FsError::NameTooLong);
+    }
+    Ok(units)
+}
+
+/// Decode a name from the volume
+pub fn to_utf8(units: &[u16]) -> String {
+    char::decode_utf16(units.iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Gather `name_length` code units from a file's name entries
+///
+/// A surrogate pair may straddle two entries; it is joined here like any
+/// other pair of units.
+pub fn from_entries(entries: &[FileNameEntry], name_length: u8) -> FsResult<Vec<u16>> {
+    let len = name_length as usize;
+    if len == 0 || len > entries.len() * UNITS_PER_ENTRY {
+        return Err(FsError::InvalidFs);
+    }
+    let mut units = Vec::with_capacity(len);
+    for entry in entries {
+        // Copy out of the packed entry before iterating
+        let chunk = entry.file_name;
+        units.extend(chunk.iter().map(|&u| u16::from_le(u)));
+    }
+    units.truncate(len);
+    Ok(units)
+}
+
+/// Split a name into file name entries (unused units zeroed)
+pub fn to_entries(units: &[u16]) -> Vec<FileNameEntry> {
+    units.chunks(UNITS_PER_ENTRY)
+        .map(|chunk| {
+            let mut file_name = [0u16; UNITS_PER_ENTRY];
+            for (slot, &unit) in file_name.iter_mut().zip(chunk) {
+                *slot = unit.to_le();
+            }
+            FileNameEntry { entry_type: super::EntryType::FileNameExtension as u8, general_flags: 0, file_name }
+        })
+        .collect()
+}
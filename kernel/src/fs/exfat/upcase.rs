@@ -0,0 +1,110 @@
+//! Up-case Table
+//!
+//! exFAT compares file names case-insensitively through a per-volume
+//! table mapping each UTF-16 code unit to its upper-case form. The table
+//! is stored in a cluster chain named by an entry in the root directory,
+//! compressed: `0xFFFF, n` stands for `n` code units that map to
+//! themselves. Only the units that change case are kept here, sorted for
+//! binary search, which is a few kilobytes instead of 128KB.
+//!
+//! The same table feeds the name hash in each file's stream extension
+//! entry, so a lookup can skip entries whose hash differs before
+//! comparing names.
+
+use alloc::vec::Vec;
+
+/// Code units in the full table
+const TABLE_UNITS: usize = 0x10000;
+
+/// Marks an identity run in the compressed table
+const RUN_MARKER: u16 = 0xFFFF;
+
+/// Case mapping for one volume
+pub struct UpcaseTable {
+    /// (unit, upper case) for every unit that changes, sorted by unit
+    changes: Vec<(u16, u16)>,
+    /// Loaded from the volume (false: ASCII fallback)
+    from_volume: bool,
+}
+
+impl UpcaseTable {
+    /// ASCII-only mapping, used until a volume's table is loaded
+    pub const fn ascii() -> Self {
+        Self { changes: Vec::new(), from_volume: false }
+    }
+
+    /// Decode a compressed table; None if it is malformed
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut changes = Vec::new();
+        let mut unit = 0usize;
+        let mut words = data.chunks_exact(2).map(|w| u16::from_le_bytes([w[0], w[1]]));
+        while let Some(word) = words.next() {
+            if unit >= TABLE_UNITS {
+                break;
+            }
+            if word == RUN_MARKER {
+                // A run marker needs its length; a trailing one is malformed
+                unit += words.next()? as usize;
+                continue;
+            }
+            if word != unit as u16 {
+                changes.push((unit as u16, word));
+            }
+            unit += 1;
+        }
+        Some(Self { changes, from_volume: true })
+    }
+
+    /// Table checksum as stored in the up-case table directory entry
+    pub fn checksum(data: &[u8]) -> u32 {
+        data.iter().fold(0u32, |sum, &byte| sum.rotate_right(1).wrapping_add(byte as u32))
+    }
+
+    /// Was the table read from the volume?
+    pub fn is_from_volume(&self) -> bool {
+        self.from_volume
+    }
+
+    /// Code units with a case mapping
+    pub fn mappings(&self) -> usize {
+        if self.from_volume { self.changes.len() } else { 26 }
+    }
+
+    /// Upper-case form of a code unit
+    pub fn upcase(&self, unit: u16) -> u16 {
+        if !self.from_volume {
+            return match unit {
+                0x61..=0x7A => unit - 0x20,
+                _ => unit,
+            };
+        }
+        match self.changes.binary_search_by_key(&unit, |&(from, _)| from) {
+            Ok(i) => self.changes[i].1,
+            Err(_) => unit,
+        }
+    }
+
+    /// Name hash of the stream extension entry (over the up-cased name)
+    pub fn name_hash(&self, name: &[u16]) -> u16 {
+        let mut hash = 0u16;
+        for &unit in name {
+            for byte in self.upcase(unit).to_le_bytes() {
+                hash = hash.rotate_right(1).wrapping_add(byte as u16);
+            }
+        }
+        hash
+    }
+
+    /// Do two names match, ignoring case?
+    pub fn names_equal(&self, a: &[u16], b: &[u16]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(&x, &y)| x == y || self.upcase(x) == self.upcase(y))
+    }
+
+    /// Does a directory entry name `wanted`?
+    ///
+    /// `entry_hash` is the entry's stored hash; most non-matching entries
+    /// are rejected on it without comparing names.
+    pub fn matches(&self, wanted: &[u16], wanted_hash: u16, entry: &[u16], entry_hash: u16) -> bool {
+        wanted_hash == entry_hash && self.names_equal(wanted, entry)
+    }
+}
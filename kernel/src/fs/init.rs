@@ -0,0 +1,278 @@
+//! Storage Initialization EventChain
+//!
+//! Runs after driver init: sizes the buffer cache, collects the disks that
+//! storage drivers announced, splits them into partitions, probes each
+//! volume for a filesystem, and mounts the first usable one as root, or
+//! the one whose identity was asked for (`root=UUID=...`) when it is there.
+//!
+//! The chain is BestEffort. A machine with no disk (or no readable
+//! filesystem) boots normally without a root; the failures just show up in
+//! the boot report.
+
+use alloc::vec::Vec;
+use crate::util::Uuid;
+use crate::event_chains::{
+    ChainableEvent, EventChain, EventContext, FaultToleranceMode,
+    result::{ErrorMessage, EventResult},
+    middleware::LoggingMiddleware,
+};
+use super::bcache::{self, BCACHE};
+use super::block::{self, DeviceId};
+use super::partition;
+use super::MountOptions;
+use super::vfs::{self, VolumeKind, VFS};
+
+// =============================================================================
+// Context Keys
+// =============================================================================
+
+pub mod context_keys {
+    pub const BCACHE_BUFFERS: &str = "bc_buffers";
+    pub const DISK_COUNT: &str = "disk_count";
+    pub const VOLUME_COUNT: &str = "vol_count";
+    pub const ROOT_MOUNTED: &str = "root_mounted";
+    pub const ROOT_DEVICE: &str = "root_dev";
+    pub const MOUNT_READ_ONLY: &str = "mount_ro";
+    pub const MOUNT_FORCE: &str = "mount_force";
+}
+
+/// A volume found during the storage stage
+#[derive(Debug, Clone, Copy)]
+pub struct VolumeRecord {
+    pub id: DeviceId,
+    /// Filesystem found by probing (None = unrecognized)
+    pub kind: Option<VolumeKind>,
+    /// Volume identity as of probing
+    pub uuid: Option<Uuid>,
+    /// Mounted as root
+    pub mounted: bool,
+}
+
+/// Volumes seen at boot, in probe order
+static mut VOLUMES: Vec<VolumeRecord> = Vec::new();
+
+/// Volume to mount as root, if one was asked for
+static mut WANTED_ROOT: Option<Uuid> = None;
+
+/// Volumes seen at boot
+pub fn volumes() -> &'static [VolumeRecord] {
+    unsafe { &*core::ptr::addr_of!(VOLUMES) }
+}
+
+// =============================================================================
+// Storage Events
+// =============================================================================
+
+/// Buffer Cache Init Event
+pub struct BcacheInitEvent;
+
+impl ChainableEvent for BcacheInitEvent {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        match bcache::init() {
+            Ok(()) => {
+                let buffers = unsafe { BCACHE.capacity() };
+                context.set_u32(context_keys::BCACHE_BUFFERS, buffers as u32);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "bcache_init"
+    }
+}
+
+/// Disk Scan Event
+///
+/// Registers every announced disk and its partitions with the cache.
+pub struct DiskScanEvent;
+
+impl ChainableEvent for DiskScanEvent {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let disks = block::take_disks();
+        context.set_u32(context_keys::DISK_COUNT, disks.len() as u32);
+        if disks.is_empty() {
+            return EventResult::failure("No block devices");
+        }
+
+        let records = unsafe { &mut VOLUMES };
+        let mut last_error = None;
+        for disk in disks {
+            match partition::register_disk(disk) {
+                Ok(ids) => {
+                    for id in ids {
+                        records.push(VolumeRecord { id, kind: None, uuid: None, mounted: false });
+                    }
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        context.set_u32(context_keys::VOLUME_COUNT, records.len() as u32);
+
+        match (records.is_empty(), last_error) {
+            (true, Some(e)) => EventResult::failure(e.as_str()),
+            _ => EventResult::success(()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "disk_scan"
+    }
+}
+
+/// Filesystem Probe Event
+pub struct FsProbeEvent;
+
+impl ChainableEvent for FsProbeEvent {
+    fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
+        let records = unsafe { &mut VOLUMES };
+        let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+        for record in records.iter_mut() {
+            record.kind = vfs::probe(record.id);
+            if record.kind.is_some() {
+                record.uuid = vfs.volume_id(record.id);
+            }
+        }
+        if records.iter().all(|r| r.kind.is_none()) {
+            return EventResult::failure("No recognized filesystem");
+        }
+        EventResult::success(())
+    }
+
+    fn name(&self) -> &'static str {
+        "fs_probe"
+    }
+
+    fn should_run(&self, context: &EventContext) -> bool {
+        context.get_u32(context_keys::VOLUME_COUNT).unwrap_or(0) > 0
+    }
+}
+
+/// Root Mount Event
+///
+/// Mounts the first volume with a filesystem there is a driver for. A
+/// wanted root is tried first; if no volume has that identity, the first
+/// usable one is mounted instead so the machine still comes up.
+pub struct RootMountEvent;
+
+impl ChainableEvent for RootMountEvent {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let records = unsafe { &mut VOLUMES };
+        let vfs = unsafe { &mut VFS };
+        let mut last_error = "No mountable volume";
+        let options = MountOptions {
+            read_only: context.get_bool(context_keys::MOUNT_READ_ONLY).unwrap_or(false),
+            force: context.get_bool(context_keys::MOUNT_FORCE).unwrap_or(false),
+        };
+
+        let mut order: Vec<usize> = (0..records.len()).filter(|&i| records[i].kind.is_some()).collect();
+        if let Some(wanted) = unsafe { *core::ptr::addr_of!(WANTED_ROOT) } {
+            match order.iter().position(|&i| records[i].uuid == Some(wanted)) {
+                Some(at) => {
+                    let index = order.remove(at);
+                    order.insert(0, index);
+                }
+                None => crate::klog!(Warning, "fs", "no volume {}, mounting the first usable one", wanted),
+            }
+        }
+
+        for index in order {
+            let record = &mut records[index];
+            match vfs.mount_volume(record.id, options) {
+                Ok(_) => {
+                    record.mounted = true;
+                    context.set_bool(context_keys::ROOT_MOUNTED, true);
+                    context.set_u32(context_keys::ROOT_DEVICE, record.id.0 as u32);
+                    return EventResult::success(());
+                }
+                Err(e) => last_error = e.as_str(),
+            }
+        }
+        EventResult::failure(last_error)
+    }
+
+    fn name(&self) -> &'static str {
+        "root_mount"
+    }
+
+    fn should_run(&self, context: &EventContext) -> bool {
+        context.get_u32(context_keys::VOLUME_COUNT).unwrap_or(0) > 0
+            && unsafe { !VFS.is_mounted() }
+    }
+}
+
+// =============================================================================
+// Global Event Instances
+// =============================================================================
+
+static BCACHE_INIT: BcacheInitEvent = BcacheInitEvent;
+static DISK_SCAN: DiskScanEvent = DiskScanEvent;
+static FS_PROBE: FsProbeEvent = FsProbeEvent;
+static ROOT_MOUNT: RootMountEvent = RootMountEvent;
+
+static LOGGING_MW: LoggingMiddleware = LoggingMiddleware::new();
+
+// =============================================================================
+// Public API
+// =============================================================================
+
+/// Result of the storage stage
+pub struct StorageInitResult {
+    /// Buffer cache size (0 if it could not be allocated)
+    pub cache_buffers: u32,
+    pub disk_count: u32,
+    pub volume_count: u32,
+    /// Root volume, if one was mounted
+    pub root: Option<DeviceId>,
+    pub failures: [Option<(&'static str, ErrorMessage)>; 4],
+    pub failure_count: usize,
+}
+
+impl StorageInitResult {
+    /// Filesystem on the root volume
+    pub fn root_kind(&self) -> Option<VolumeKind> {
+        let root = self.root?;
+        volumes().iter().find(|v| v.id == root).and_then(|v| v.kind)
+    }
+}
+
+/// Bring up storage and mount the root filesystem with `options`,
+/// preferring the volume identified by `root` if there is one
+pub fn init_storage(options: MountOptions, root: Option<Uuid>) -> StorageInitResult {
+    unsafe { WANTED_ROOT = root };
+    let mut context = EventContext::new();
+    context.set_bool(context_keys::MOUNT_READ_ONLY, options.read_only);
+    context.set_bool(context_keys::MOUNT_FORCE, options.force);
+
+    let chain = EventChain::new()
+        .middleware(&LOGGING_MW)
+        .event(&BCACHE_INIT)         // Size the shared block cache
+        .event(&DISK_SCAN)           // Register disks and partitions
+        .event(&FS_PROBE)            // Identify filesystems
+        .event(&ROOT_MOUNT)          // Mount the first usable volume
+        .with_fault_tolerance(FaultToleranceMode::BestEffort);
+
+    let result = chain.execute(&mut context);
+
+    let mut failures = [None; 4];
+    let mut failure_count = 0;
+    for failure in result.failures() {
+        if failure_count < failures.len() {
+            failures[failure_count] = Some((failure.event_name, failure.error));
+            failure_count += 1;
+        }
+    }
+
+    let mounted = context.get_bool(context_keys::ROOT_MOUNTED).unwrap_or(false);
+    StorageInitResult {
+        cache_buffers: context.get_u32(context_keys::BCACHE_BUFFERS).unwrap_or(0),
+        disk_count: context.get_u32(context_keys::DISK_COUNT).unwrap_or(0),
+        volume_count: context.get_u32(context_keys::VOLUME_COUNT).unwrap_or(0),
+        root: context.get_u32(context_keys::ROOT_DEVICE)
+            .filter(|_| mounted)
+            .map(|id| DeviceId(id as u8)),
+        failures,
+        failure_count,
+    }
+}
@@ -0,0 +1,232 @@
+//! Loopback Disks
+//!
+//! Block devices backed by something other than a disk controller, so
+//! filesystem code can be exercised under QEMU without emulated storage:
+//!
+//! - `MemoryDisk` serves blocks from a region of RAM. The bootloader can
+//!   provide one: `make run RAMDISK=fs.img` has QEMU load the image into
+//!   memory and stage 2 passes its address and size in the boot info. It
+//!   is announced as a disk like any other, so an exFAT image is mounted
+//!   as root.
+//! - `FileDisk` serves blocks from a file opened through the VFS, attached
+//!   at run time with `losetup`. The file must be on a filesystem that
+//!   doesn't use the buffer cache (ramfs): reading a block would otherwise
+//!   re-enter the cache it is being read into.
+//!
+//! Both use 512-byte blocks; a trailing partial block is ignored. Devices
+//! stay attached until reboot.
+
+use alloc::boxed::Box;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::arch::PhysAddr;
+use super::block::{self, BlockDevice};
+use super::vfs::VFS;
+use super::{FsError, FsResult, OpenFlags, SeekFrom};
+
+/// Bytes per block
+pub const BLOCK_SIZE: usize = 512;
+
+/// Lowest address a boot image may start at (past the kernel, heap and
+/// crash dump area)
+const BOOT_IMAGE_MIN_ADDR: PhysAddr = PhysAddr::new(0x0200_0000);
+
+/// Byte range of `len` bytes of whole blocks from `lba`, if inside `blocks`
+fn block_range(blocks: u64, lba: u64, len: usize) -> FsResult<Range<u64>> {
+    if len % BLOCK_SIZE != 0 {
+        return Err(FsError::IoError);
+    }
+    let end = lba.checked_add((len / BLOCK_SIZE) as u64).ok_or(FsError::IoError)?;
+    if end > blocks {
+        return Err(FsError::IoError);
+    }
+    Ok(lba * BLOCK_SIZE as u64..end * BLOCK_SIZE as u64)
+}
+
+// =============================================================================
+// Memory Disk
+// =============================================================================
+
+/// Disk image held in memory
+pub struct MemoryDisk {
+    data: &'static mut [u8],
+}
+
+impl MemoryDisk {
+    /// Serve blocks from `len` bytes at `addr`
+    ///
+    /// # Safety
+    ///
+    /// The region must be mapped and used by nothing else for as long as
+    /// the kernel runs.
+    pub unsafe fn new(addr: usize, len: usize) -> Self {
+        Self { data: core::slice::from_raw_parts_mut(addr as *mut u8, len) }
+    }
+}
+
+impl BlockDevice for MemoryDisk {
+    fn name(&self) -> &'static str {
+        "ramdisk"
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.len() / BLOCK_SIZE) as u64
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> FsResult<()> {
+        let range = block_range(self.block_count(), lba, buf.len())?;
+        buf.copy_from_slice(&self.data[range.start as usize..range.end as usize]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> FsResult<()> {
+        let range = block_range(self.block_count(), lba, buf.len())?;
+        self.data[range.start as usize..range.end as usize].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+// =============================================================================
+// File Disk
+// =============================================================================
+
+/// Disk image stored in a file
+pub struct FileDisk {
+    /// VFS descriptor held open for the device's lifetime
+    fd: u32,
+    blocks: u64,
+    /// Opened for writing (false if the file is read-only)
+    writable: bool,
+}
+
+impl FileDisk {
+    /// Open an image file, read-write if its filesystem allows
+    pub fn open(path: &str) -> FsResult<Self> {
+        let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+        let (fd, writable) = match vfs.open(path, OpenFlags::read_write()) {
+            Ok(fd) => (fd, true),
+            Err(FsError::ReadOnly | FsError::PermissionDenied) => (vfs.open(path, OpenFlags::read_only())?, false),
+            Err(e) => return Err(e),
+        };
+        let size = match vfs.seek(fd, 0, SeekFrom::End) {
+            Ok(size) if size >= BLOCK_SIZE as u64 => Ok(size),
+            Ok(_) => Err(FsError::InvalidFs),
+            Err(e) => Err(e),
+        };
+        match size {
+            Ok(size) => Ok(Self { fd, blocks: size / BLOCK_SIZE as u64, writable }),
+            Err(e) => {
+                let _ = vfs.close(fd);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+}
+
+impl BlockDevice for FileDisk {
+    fn name(&self) -> &'static str {
+        "loop"
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> FsResult<()> {
+        let range = block_range(self.blocks, lba, buf.len())?;
+        let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+        vfs.seek(self.fd, range.start as i64, SeekFrom::Start)?;
+        let mut done = 0;
+        while done < buf.len() {
+            match vfs.read(self.fd, &mut buf[done..])? {
+                0 => return Err(FsError::IoError),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> FsResult<()> {
+        if !self.writable {
+            return Err(FsError::ReadOnly);
+        }
+        let range = block_range(self.blocks, lba, buf.len())?;
+        let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+        vfs.seek(self.fd, range.start as i64, SeekFrom::Start)?;
+        let mut done = 0;
+        while done < buf.len() {
+            match vfs.write(self.fd, &buf[done..])? {
+                0 => return Err(FsError::IoError),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> FsResult<()> {
+        unsafe { (*core::ptr::addr_of_mut!(VFS)).fsync(self.fd) }
+    }
+}
+
+// =============================================================================
+// Boot Image
+// =============================================================================
+
+/// Image the bootloader left in memory (0 = none)
+static BOOT_IMAGE_ADDR: AtomicUsize = AtomicUsize::new(0);
+static BOOT_IMAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Record the bootloader's disk image
+///
+/// Called before memory management starts, so the physical memory
+/// manager leaves the image's pages alone (`is_reserved_page`).
+pub fn set_boot_image(addr: PhysAddr, size: usize) -> Result<(), &'static str> {
+    if addr.is_null() || size == 0 {
+        return Ok(());
+    }
+    if size < BLOCK_SIZE {
+        return Err("Disk image smaller than one block");
+    }
+    let end = crate::mm::paging::KERNEL_SPACE_END as usize;
+    if addr < BOOT_IMAGE_MIN_ADDR || addr.checked_add(size).map_or(true, |e| e.as_usize() > end) {
+        return Err("Disk image outside usable memory");
+    }
+    BOOT_IMAGE_ADDR.store(addr.as_usize(), Ordering::Relaxed);
+    BOOT_IMAGE_SIZE.store(size, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Is this physical page part of the boot image?
+pub fn is_reserved_page(page_idx: usize) -> bool {
+    let addr = BOOT_IMAGE_ADDR.load(Ordering::Relaxed);
+    let size = BOOT_IMAGE_SIZE.load(Ordering::Relaxed);
+    let page = page_idx * 4096;
+    size != 0 && page + 4096 > addr && page < addr + size
+}
+
+/// Announce the boot image as a disk; returns its size in blocks
+///
+/// Needs the heap; call before the storage stage scans for disks.
+pub fn add_boot_image() -> Option<u64> {
+    let addr = BOOT_IMAGE_ADDR.load(Ordering::Relaxed);
+    let size = BOOT_IMAGE_SIZE.load(Ordering::Relaxed);
+    if size == 0 {
+        return None;
+    }
+    let disk: &'static mut MemoryDisk = Box::leak(Box::new(unsafe { MemoryDisk::new(addr, size) }));
+    let blocks = disk.block_count();
+    block::add_disk(disk);
+    Some(blocks)
+}
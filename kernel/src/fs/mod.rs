@@ -3,9 +3,22 @@
 //! Rustacean OS filesystem support with Plan 9-style "everything is a file" philosophy.
 //! Primary filesystem is exFAT for USB compatibility.
 
+pub mod bcache;
+pub mod block;
+pub mod cpio;
 pub mod exfat;
+pub mod init;
+pub mod loopback;
+pub mod partition;
+pub mod path;
+pub mod procfs;
+pub mod ramfs;
+pub mod symlink;
+pub mod vfs;
 
-/// Maximum path length
+use alloc::string::String;
+
+/// Maximum length of a canonical path in bytes (no terminator)
 pub const MAX_PATH: usize = 256;
 
 /// Maximum filename length
@@ -30,6 +43,27 @@ pub enum FileType {
     Socket,
 }
 
+/// Options for mounting a volume
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MountOptions {
+    /// Refuse every write
+    pub read_only: bool,
+    /// Mount read-write even if the volume is marked dirty
+    pub force: bool,
+}
+
+impl MountOptions {
+    /// Read-write, but read-only if the volume is dirty
+    pub const fn new() -> Self {
+        Self { read_only: false, force: false }
+    }
+    
+    /// Never write
+    pub const fn read_only() -> Self {
+        Self { read_only: true, force: false }
+    }
+}
+
 /// File open flags
 #[derive(Debug, Clone, Copy)]
 pub struct OpenFlags {
@@ -89,6 +123,40 @@ impl OpenFlags {
         self.truncate = true;
         self
     }
+    
+    /// POSIX access mode: read-only
+    pub const O_RDONLY: u32 = 0x0000;
+    /// POSIX access mode: write-only
+    pub const O_WRONLY: u32 = 0x0001;
+    /// POSIX access mode: read-write
+    pub const O_RDWR: u32 = 0x0002;
+    /// Mask for the access mode bits
+    pub const O_ACCMODE: u32 = 0x0003;
+    /// Create file if it doesn't exist
+    pub const O_CREAT: u32 = 0x0040;
+    /// Fail if O_CREAT and the file exists
+    pub const O_EXCL: u32 = 0x0080;
+    /// Truncate file on open
+    pub const O_TRUNC: u32 = 0x0200;
+    /// Writes always append
+    pub const O_APPEND: u32 = 0x0400;
+    
+    /// Translate POSIX-style open flags (as passed to the open syscall)
+    ///
+    /// Returns None for an invalid access mode.
+    pub const fn from_posix(bits: u32) -> Option<Self> {
+        let mut flags = match bits & Self::O_ACCMODE {
+            Self::O_RDONLY => Self::read_only(),
+            Self::O_WRONLY => Self::write_only(),
+            Self::O_RDWR => Self::read_write(),
+            _ => return None,
+        };
+        flags.create = bits & Self::O_CREAT != 0;
+        flags.exclusive = bits & Self::O_EXCL != 0;
+        flags.truncate = bits & Self::O_TRUNC != 0;
+        flags.append = bits & Self::O_APPEND != 0;
+        Some(flags)
+    }
 }
 
 /// File permissions (Unix-style)
@@ -193,6 +261,35 @@ pub enum FsError {
     InvalidFs,
     /// Read-only filesystem
     ReadOnly,
+    /// Operation not supported by this filesystem
+    NotSupported,
+    /// Too many symbolic links while resolving a path
+    SymlinkLoop,
+    /// Path longer than `MAX_PATH` or a component longer than `MAX_FILENAME`
+    NameTooLong,
+}
+
+impl FsError {
+    /// Short description (used as the syscall failure message)
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotFound => "not found",
+            Self::PermissionDenied => "permission denied",
+            Self::AlreadyExists => "already exists",
+            Self::NotDirectory => "not a directory",
+            Self::IsDirectory => "is a directory",
+            Self::InvalidPath => "invalid path",
+            Self::NoSpace => "no space left",
+            Self::TooManyOpenFiles => "too many open files",
+            Self::IoError => "I/O error",
+            Self::NotMounted => "not mounted",
+            Self::InvalidFs => "invalid filesystem",
+            Self::ReadOnly => "read-only filesystem",
+            Self::NotSupported => "not supported",
+            Self::SymlinkLoop => "too many levels of symbolic links",
+            Self::NameTooLong => "file name too long",
+        }
+    }
 }
 
 /// Filesystem result type
@@ -226,11 +323,37 @@ pub trait Filesystem {
     /// Seek in file
     fn seek(&mut self, handle: u64, offset: i64, whence: SeekFrom) -> FsResult<u64>;
     
+    /// Read at a byte offset
+    ///
+    /// The VFS owns file positions and only uses positioned I/O. The
+    /// default goes through the handle's own seek pointer.
+    fn read_at(&mut self, handle: u64, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        self.seek(handle, offset as i64, SeekFrom::Start)?;
+        self.read(handle, buf)
+    }
+    
+    /// Write at a byte offset
+    fn write_at(&mut self, handle: u64, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        self.seek(handle, offset as i64, SeekFrom::Start)?;
+        self.write(handle, buf)
+    }
+    
+    /// Current size of an open file
+    fn size(&mut self, handle: u64) -> FsResult<u64> {
+        self.seek(handle, 0, SeekFrom::End)
+    }
+    
     /// Get file metadata
     fn stat(&self, path: &str) -> FsResult<Metadata>;
     
-    /// Read directory entries
-    fn readdir(&mut self, path: &str) -> FsResult<ReadDir>;
+    /// Read one directory entry
+    ///
+    /// `cookie` says where to continue: 0 for the first entry, otherwise
+    /// the token returned with the previous one. Returns the entry and the
+    /// token for the one after it, or None at the end of the directory.
+    /// Tokens are opaque to callers; a filesystem picks whatever lets it
+    /// resume cheaply (an index, a byte offset into the directory).
+    fn read_dir_entry(&mut self, path: &str, cookie: u64) -> FsResult<Option<(DirEntry, u64)>>;
     
     /// Create a directory
     fn mkdir(&mut self, path: &str) -> FsResult<()>;
@@ -243,6 +366,62 @@ pub trait Filesystem {
     
     /// Rename/move a file
     fn rename(&mut self, from: &str, to: &str) -> FsResult<()>;
+    
+    /// Push cached metadata down to the buffer cache
+    fn sync(&mut self) -> FsResult<()> {
+        Ok(())
+    }
+    
+    /// Make one open file durable: its data, then the metadata naming it
+    ///
+    /// Returns once the device reports the writes stable. Filesystems
+    /// that don't track dirty state per file sync the whole volume.
+    fn fsync(&mut self, _handle: u64) -> FsResult<()> {
+        self.sync()
+    }
+    
+    /// Create a symbolic link at `link` pointing to `target`
+    fn symlink(&mut self, _target: &str, _link: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+    
+    /// Read a symbolic link's target
+    fn readlink(&mut self, _path: &str) -> FsResult<alloc::string::String> {
+        Err(FsError::NotSupported)
+    }
+    
+    /// Store links as marker files (see `symlink`) instead of natively
+    fn emulates_symlinks(&self) -> bool {
+        false
+    }
+    
+    /// Are writes refused (mounted read-only)?
+    fn is_read_only(&self) -> bool {
+        false
+    }
+    
+    /// Scan for inconsistencies, describing each in `out`
+    ///
+    /// Returns how many problems were found. With `repair`, fixes what
+    /// the filesystem knows how to fix.
+    fn check(&mut self, _repair: bool, _out: &mut alloc::string::String) -> FsResult<u32> {
+        Err(FsError::NotSupported)
+    }
+    
+    /// Occupancy of the open handle table (/proc/slots)
+    fn handle_stats(&self) -> Option<crate::util::SlotStats> {
+        None
+    }
+    
+    /// Identity of the volume, stable across boots (None if it has none)
+    fn volume_id(&self) -> Option<crate::util::Uuid> {
+        None
+    }
+    
+    /// Give the volume a new identity (None removes it)
+    fn set_volume_id(&mut self, _id: Option<crate::util::Uuid>) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
 }
 
 /// Seek origin
@@ -257,48 +436,58 @@ pub enum SeekFrom {
 }
 
 /// Directory iterator
-pub struct ReadDir {
-    /// Entries (fixed size for no_std)
-    entries: [Option<DirEntry>; 64],
-    /// Number of entries
-    count: usize,
-    /// Current index
-    index: usize,
+///
+/// Pulls entries from the filesystem one at a time, so a directory of any
+/// size lists completely with a single entry buffered. The first entry is
+/// read up front, so a bad path fails when the listing is opened; an
+/// error after that ends the iteration and is kept in `error`.
+pub struct ReadDir<'a> {
+    fs: &'a mut dyn Filesystem,
+    path: String,
+    /// Token for `next` (where the listing resumes)
+    cookie: u64,
+    /// Token for the entry after `next`
+    after: u64,
+    /// Entry read ahead
+    next: Option<DirEntry>,
+    error: Option<FsError>,
 }
 
-impl ReadDir {
-    /// Create empty directory listing
-    pub const fn empty() -> Self {
-        const NONE: Option<DirEntry> = None;
-        Self {
-            entries: [NONE; 64],
-            count: 0,
-            index: 0,
-        }
+impl<'a> ReadDir<'a> {
+    /// Start listing `path` at `cookie` (0 for the beginning)
+    pub fn new(fs: &'a mut dyn Filesystem, path: &str, cookie: u64) -> FsResult<Self> {
+        let (next, after) = match fs.read_dir_entry(path, cookie)? {
+            Some((entry, after)) => (Some(entry), after),
+            None => (None, cookie),
+        };
+        Ok(Self { fs, path: String::from(path), cookie, after, next, error: None })
     }
     
-    /// Add an entry
-    pub fn add(&mut self, entry: DirEntry) -> bool {
-        if self.count < 64 {
-            self.entries[self.count] = Some(entry);
-            self.count += 1;
-            true
-        } else {
-            false
-        }
+    /// Token to resume the listing at the next entry not yet returned
+    pub fn cookie(&self) -> u64 {
+        self.cookie
+    }
+    
+    /// Error that cut the listing short, if any
+    pub fn error(&self) -> Option<FsError> {
+        self.error
     }
 }
 
-impl Iterator for ReadDir {
+impl Iterator for ReadDir<'_> {
     type Item = DirEntry;
     
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index < self.count {
-            let entry = self.entries[self.index].take();
-            self.index += 1;
-            entry
-        } else {
-            None
+        let entry = self.next.take()?;
+        self.cookie = self.after;
+        match self.fs.read_dir_entry(&self.path, self.cookie) {
+            Ok(Some((next, after))) => {
+                self.next = Some(next);
+                self.after = after;
+            }
+            Ok(None) => {}
+            Err(e) => self.error = Some(e),
         }
+        Some(entry)
     }
 }
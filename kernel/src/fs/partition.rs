@@ -0,0 +1,291 @@
+//! Partition Tables
+//!
+//! Parses MBR and GPT partition tables and exposes each partition as its
+//! own `BlockDevice` that translates partition-relative LBAs to the disk.
+//! A disk whose first sector is a filesystem boot record (a "superfloppy",
+//! common on USB sticks) has no table and is used as a single volume.
+//!
+//! Extended MBR partitions (logical drives) are not followed.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+use crate::util::crc32::crc32;
+use super::bcache::BCACHE;
+use super::block::{BlockDevice, DeviceId};
+use super::{FsError, FsResult};
+
+/// MBR boot signature at offset 510
+const MBR_SIGNATURE: u16 = 0xAA55;
+
+/// Offset of the four primary MBR entries
+const MBR_TABLE_OFFSET: usize = 446;
+
+/// Size of one MBR entry
+const MBR_ENTRY_SIZE: usize = 16;
+
+/// MBR type of the protective entry covering a GPT disk
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+/// MBR types of extended partitions (not followed)
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+/// GPT header signature
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// Smallest GPT entry size allowed by the spec
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+
+/// Upper bound on GPT entries scanned
+const GPT_MAX_ENTRIES: u32 = 128;
+
+/// Partition table format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Mbr,
+    Gpt,
+}
+
+/// Partition type identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    /// MBR system ID byte
+    Mbr(u8),
+    /// GPT partition type GUID (on-disk byte order)
+    Gpt([u8; 16]),
+}
+
+/// One partition table entry
+#[derive(Debug, Clone, Copy)]
+pub struct PartitionInfo {
+    /// 1-based position in the table
+    pub number: u32,
+    pub kind: PartitionType,
+    /// First block on the disk
+    pub start_lba: u64,
+    /// Length in blocks
+    pub block_count: u64,
+}
+
+impl PartitionInfo {
+    pub fn scheme(&self) -> Scheme {
+        match self.kind {
+            PartitionType::Mbr(_) => Scheme::Mbr,
+            PartitionType::Gpt(_) => Scheme::Gpt,
+        }
+    }
+}
+
+fn le16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+fn le64(b: &[u8], at: usize) -> u64 {
+    le32(b, at) as u64 | (le32(b, at + 4) as u64) << 32
+}
+
+/// Does this sector look like a filesystem boot record rather than an MBR?
+fn is_volume_boot_record(sector: &[u8]) -> bool {
+    &sector[3..11] == b"EXFAT   "
+        || &sector[82..90] == b"FAT32   "
+        || &sector[54..59] == b"FAT12"
+        || &sector[54..59] == b"FAT16"
+}
+
+/// Read the partition table of a disk
+///
+/// Returns an empty list for an unpartitioned disk.
+pub fn scan(disk: &mut dyn BlockDevice) -> FsResult<Vec<PartitionInfo>> {
+    let block_size = disk.block_size();
+    if block_size < 512 {
+        return Err(FsError::InvalidFs);
+    }
+    let mut sector = vec![0u8; block_size];
+    disk.read_blocks(0, &mut sector)?;
+
+    if le16(&sector, 510) != MBR_SIGNATURE || is_volume_boot_record(&sector) {
+        return Ok(Vec::new());
+    }
+
+    let mut found = Vec::new();
+    for i in 0..4 {
+        let e = &sector[MBR_TABLE_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        let (status, kind) = (e[0], e[4]);
+        if status & 0x7F != 0 {
+            // Not a partition table after all
+            return Ok(Vec::new());
+        }
+        if kind == MBR_TYPE_GPT_PROTECTIVE {
+            return scan_gpt(disk, block_size);
+        }
+        let (start, count) = (le32(e, 8) as u64, le32(e, 12) as u64);
+        if kind == 0 || count == 0 || MBR_TYPE_EXTENDED.contains(&kind) {
+            continue;
+        }
+        found.push(PartitionInfo {
+            number: i as u32 + 1,
+            kind: PartitionType::Mbr(kind),
+            start_lba: start,
+            block_count: count,
+        });
+    }
+    Ok(clip(found, disk.block_count()))
+}
+
+/// Parse the GPT header at LBA 1 and its entry array
+fn scan_gpt(disk: &mut dyn BlockDevice, block_size: usize) -> FsResult<Vec<PartitionInfo>> {
+    let mut header = vec![0u8; block_size];
+    disk.read_blocks(1, &mut header)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err(FsError::InvalidFs);
+    }
+
+    let header_size = le32(&header, 12) as usize;
+    if header_size < 92 || header_size > block_size {
+        return Err(FsError::InvalidFs);
+    }
+    let stored_crc = le32(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != stored_crc {
+        return Err(FsError::InvalidFs);
+    }
+
+    let entries_lba = le64(&header, 72);
+    let entry_count = le32(&header, 80).min(GPT_MAX_ENTRIES) as usize;
+    let entry_size = le32(&header, 84) as usize;
+    if entry_size < GPT_MIN_ENTRY_SIZE || !entry_size.is_power_of_two() {
+        return Err(FsError::InvalidFs);
+    }
+
+    let bytes = entry_count * entry_size;
+    let blocks = bytes.div_ceil(block_size);
+    let mut table = vec![0u8; blocks * block_size];
+    disk.read_blocks(entries_lba, &mut table)?;
+    if entry_count == le32(&header, 80) as usize && crc32(&table[..bytes]) != le32(&header, 88) {
+        return Err(FsError::InvalidFs);
+    }
+
+    let mut found = Vec::new();
+    for i in 0..entry_count {
+        let e = &table[i * entry_size..][..entry_size];
+        let mut guid = [0u8; 16];
+        guid.copy_from_slice(&e[0..16]);
+        if guid == [0; 16] {
+            continue;
+        }
+        let (first, last) = (le64(e, 32), le64(e, 40));
+        if last < first {
+            continue;
+        }
+        found.push(PartitionInfo {
+            number: i as u32 + 1,
+            kind: PartitionType::Gpt(guid),
+            start_lba: first,
+            block_count: last - first + 1,
+        });
+    }
+    Ok(clip(found, disk.block_count()))
+}
+
+/// Drop or shorten entries that run past the end of the disk
+fn clip(mut parts: Vec<PartitionInfo>, disk_blocks: u64) -> Vec<PartitionInfo> {
+    parts.retain(|p| p.start_lba > 0 && p.start_lba < disk_blocks);
+    for p in parts.iter_mut() {
+        p.block_count = p.block_count.min(disk_blocks - p.start_lba);
+    }
+    parts
+}
+
+/// Hand a disk and each of its partitions to the buffer cache
+///
+/// Returns the volumes to probe: one per partition, or the whole disk if
+/// it has no partition table.
+pub fn register_disk(disk: &'static mut dyn BlockDevice) -> FsResult<Vec<DeviceId>> {
+    let parts = scan(disk)?;
+    let cache = unsafe { &mut BCACHE };
+    let disk_id = cache.register(disk)?;
+    if parts.is_empty() {
+        return Ok(vec![disk_id]);
+    }
+
+    let raw = cache.raw_device(disk_id)?;
+    let mut volumes = Vec::new();
+    for info in parts {
+        let part: &'static mut Partition = Box::leak(Box::new(Partition::new(raw, info)));
+        match cache.register(part) {
+            Ok(id) => volumes.push(id),
+            // Out of device slots; later partitions stay invisible
+            Err(_) => break,
+        }
+    }
+    Ok(volumes)
+}
+
+// =============================================================================
+// Partition Device
+// =============================================================================
+
+/// A window onto part of a disk
+///
+/// Reads and writes go straight to the disk device; the buffer cache sees
+/// only the partition, so its blocks are never cached twice.
+pub struct Partition {
+    disk: NonNull<dyn BlockDevice>,
+    info: PartitionInfo,
+}
+
+impl Partition {
+    /// Wrap a partition of `disk` (which must outlive the partition)
+    pub fn new(disk: NonNull<dyn BlockDevice>, info: PartitionInfo) -> Self {
+        Self { disk, info }
+    }
+
+    pub fn info(&self) -> &PartitionInfo {
+        &self.info
+    }
+
+    /// Translate a partition-relative request, rejecting overruns
+    fn translate(&self, lba: u64, len: usize) -> FsResult<u64> {
+        let blocks = (len / self.block_size()) as u64;
+        match lba.checked_add(blocks) {
+            Some(end) if end <= self.info.block_count => Ok(self.info.start_lba + lba),
+            _ => Err(FsError::IoError),
+        }
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &'static str {
+        match self.info.scheme() {
+            Scheme::Mbr => "mbr-part",
+            Scheme::Gpt => "gpt-part",
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        unsafe { self.disk.as_ref().block_size() }
+    }
+
+    fn block_count(&self) -> u64 {
+        self.info.block_count
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> FsResult<()> {
+        let lba = self.translate(lba, buf.len())?;
+        unsafe { self.disk.as_mut().read_blocks(lba, buf) }
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> FsResult<()> {
+        let lba = self.translate(lba, buf.len())?;
+        unsafe { self.disk.as_mut().write_blocks(lba, buf) }
+    }
+
+    fn flush(&mut self) -> FsResult<()> {
+        unsafe { self.disk.as_mut().flush() }
+    }
+}
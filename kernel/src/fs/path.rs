@@ -0,0 +1,136 @@
+//! Path Resolution
+//!
+//! Turns user-supplied paths into the canonical absolute form filesystems
+//! expect: relative paths are joined to a working directory, `.` and empty
+//! components are dropped, `..` removes the previous component (and stops
+//! at the root), and the result never ends in a slash except for `/`.
+//!
+//! Resolution is purely lexical; symbolic links are not consulted.
+//!
+//! Limits apply to the canonical result, after joining and `..`: a path
+//! over `MAX_PATH` bytes or with a component over `MAX_FILENAME` fails
+//! with `NameTooLong`. Inputs may be longer than that (a long relative
+//! path that climbs back up) as long as the joined path fits in
+//! `2 * MAX_PATH` bytes, which a working directory joined with a
+//! syscall's path always does.
+//!
+//! Paths are held in `PathBuf`s, fixed buffers that live on the stack,
+//! so resolving one (on every path syscall) doesn't touch the heap.
+
+use core::fmt;
+use core::ops::Deref;
+use super::{FsError, FsResult, MAX_FILENAME, MAX_PATH};
+
+/// Room for a working directory joined with a path, before `..` and `.`
+/// shorten it
+const JOINED_MAX: usize = 2 * MAX_PATH;
+
+/// A path of up to `MAX_PATH` bytes, held inline
+#[derive(Clone, Copy)]
+pub struct PathBuf {
+    bytes: [u8; MAX_PATH],
+    len: usize,
+}
+
+impl PathBuf {
+    /// The root, `/`
+    pub const fn root() -> Self {
+        let mut bytes = [0; MAX_PATH];
+        bytes[0] = b'/';
+        Self { bytes, len: 1 }
+    }
+
+    /// Copy `path`, which must fit in `MAX_PATH` bytes
+    pub fn new(path: &str) -> FsResult<Self> {
+        if path.len() > MAX_PATH {
+            return Err(FsError::NameTooLong);
+        }
+        let mut bytes = [0; MAX_PATH];
+        bytes[..path.len()].copy_from_slice(path.as_bytes());
+        Ok(Self { bytes, len: path.len() })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever filled from whole `str`s and ASCII slashes
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("/")
+    }
+}
+
+impl Deref for PathBuf {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for PathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for PathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Canonicalize `path`, taking relative paths from `cwd` (itself absolute)
+pub fn resolve(cwd: &str, path: &str) -> FsResult<PathBuf> {
+    if path.is_empty() {
+        return Err(FsError::InvalidPath);
+    }
+
+    let mut out = Joined { bytes: [0; JOINED_MAX], len: 0 };
+    if !path.starts_with('/') {
+        out.push_components(cwd)?;
+    }
+    out.push_components(path)?;
+
+    if out.len == 0 {
+        return Ok(PathBuf::root());
+    }
+    let joined = core::str::from_utf8(&out.bytes[..out.len]).map_err(|_| FsError::InvalidPath)?;
+    PathBuf::new(joined)
+}
+
+/// Canonicalize an absolute path
+pub fn normalize(path: &str) -> FsResult<PathBuf> {
+    if !path.starts_with('/') {
+        return Err(FsError::InvalidPath);
+    }
+    resolve("/", path)
+}
+
+/// Path being resolved, stored without the root slash when empty (so it
+/// is either "" or "/a/b")
+struct Joined {
+    bytes: [u8; JOINED_MAX],
+    len: usize,
+}
+
+impl Joined {
+    /// Append the components of `path`
+    fn push_components(&mut self, path: &str) -> FsResult<()> {
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    self.len = self.bytes[..self.len].iter().rposition(|&b| b == b'/').unwrap_or(0);
+                }
+                name if name.len() > MAX_FILENAME => return Err(FsError::NameTooLong),
+                name => {
+                    let end = self.len + 1 + name.len();
+                    if end > JOINED_MAX {
+                        return Err(FsError::NameTooLong);
+                    }
+                    self.bytes[self.len] = b'/';
+                    self.bytes[self.len + 1..end].copy_from_slice(name.as_bytes());
+                    self.len = end;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+//! Process Filesystem
+//!
+//! Read-only kernel status files under `/proc`. Each file is generated into
+//! a heap string when it is opened, so a reader sees one consistent snapshot
+//! no matter how small its reads are. The VFS routes any path beneath
+//! `/proc` here, independent of what is mounted at the root.
+
+use alloc::string::String;
+use crate::util::{SlotMap, SlotStats};
+use super::{DirEntry, FileType, Filesystem, FsError, FsResult, Metadata, OpenFlags,
+            Permissions, SeekFrom, MAX_FILENAME};
+
+/// Mount point
+pub const PROC_ROOT: &str = "/proc";
+
+/// Maximum simultaneously open proc files
+const MAX_HANDLES: usize = 8;
+
+/// A generated file
+pub struct ProcEntry {
+    /// File name under /proc
+    pub name: &'static str,
+    /// Writes the file contents
+    pub generate: fn(&mut String),
+}
+
+/// Files served under /proc
+static ENTRIES: &[ProcEntry] = &[
+    ProcEntry { name: "asserts", generate: crate::kassert::report },
+    ProcEntry { name: "bcache", generate: super::bcache::report },
+    ProcEntry { name: "cpuinfo", generate: crate::arch::x86::cpu::report },
+    ProcEntry { name: "cpus", generate: crate::arch::x86::smp::report },
+    ProcEntry { name: "drivers", generate: crate::drivers::report::report },
+    ProcEntry { name: "features", generate: crate::subsys::report },
+    ProcEntry { name: "interrupts", generate: crate::arch::x86::idt::report },
+    ProcEntry { name: "ioports", generate: crate::arch::x86::ioport::report },
+    ProcEntry { name: "limits", generate: crate::sched::rlimit::report },
+    ProcEntry { name: "maps", generate: crate::mm::vma::report },
+    ProcEntry { name: "mce", generate: crate::arch::x86::mce::report },
+    ProcEntry { name: "meminfo", generate: crate::mm::pmm::report },
+    ProcEntry { name: "mounts", generate: super::vfs::report },
+    ProcEntry { name: "mtrr", generate: crate::arch::x86::mtrr::report },
+    ProcEntry { name: "ports", generate: crate::ipc::report },
+    ProcEntry { name: "profile", generate: crate::profile::report },
+    ProcEntry { name: "random", generate: crate::rng::report },
+    ProcEntry { name: "sched", generate: crate::sched::stats::report },
+    ProcEntry { name: "shm", generate: crate::mm::shm::report },
+    ProcEntry { name: "slots", generate: crate::util::slotmap::report },
+    ProcEntry { name: "softirqs", generate: crate::softirq::report },
+    ProcEntry { name: "timers", generate: crate::sched::timer::report },
+    ProcEntry { name: "trace", generate: crate::trace::report },
+];
+
+/// Is this path /proc or something beneath it?
+pub fn is_proc_path(path: &str) -> bool {
+    path == PROC_ROOT
+        || (path.starts_with(PROC_ROOT) && path.as_bytes().get(PROC_ROOT.len()) == Some(&b'/'))
+}
+
+/// Find the entry for an absolute /proc path
+fn lookup(path: &str) -> FsResult<&'static ProcEntry> {
+    let name = path.strip_prefix(PROC_ROOT)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .ok_or(FsError::NotFound)?;
+    ENTRIES.iter().find(|e| e.name == name).ok_or(FsError::NotFound)
+}
+
+/// Open file snapshot
+struct ProcHandle {
+    data: String,
+    pos: usize,
+}
+
+/// The /proc filesystem
+pub struct ProcFs {
+    handles: SlotMap<ProcHandle, MAX_HANDLES>,
+}
+
+impl ProcFs {
+    pub const fn new() -> Self {
+        Self { handles: SlotMap::new() }
+    }
+
+    fn handle(&mut self, handle: u64) -> FsResult<&mut ProcHandle> {
+        self.handles.get_mut(handle as usize).ok_or(FsError::InvalidPath)
+    }
+
+    fn metadata(file_type: FileType, size: u64) -> Metadata {
+        let permissions = match file_type {
+            FileType::Directory => Permissions::default_dir(),
+            _ => Permissions::default_file(),
+        };
+        Metadata { file_type, size, permissions, created: 0, modified: 0, accessed: 0 }
+    }
+}
+
+impl Filesystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn mount(&mut self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn unmount(&mut self) -> FsResult<()> {
+        self.handles.clear();
+        Ok(())
+    }
+
+    fn open(&mut self, path: &str, flags: OpenFlags) -> FsResult<u64> {
+        if flags.write || flags.create || flags.truncate || flags.append {
+            return Err(FsError::ReadOnly);
+        }
+        if path == PROC_ROOT {
+            return Err(FsError::IsDirectory);
+        }
+        let entry = lookup(path)?;
+
+        let mut data = String::new();
+        (entry.generate)(&mut data);
+        let key = self.handles.insert(ProcHandle { data, pos: 0 }).ok_or(FsError::TooManyOpenFiles)?;
+        Ok(key.index() as u64)
+    }
+
+    fn close(&mut self, handle: u64) -> FsResult<()> {
+        self.handles.remove(handle as usize).ok_or(FsError::InvalidPath)?;
+        Ok(())
+    }
+
+    fn read(&mut self, handle: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let h = self.handle(handle)?;
+        let bytes = h.data.as_bytes();
+        let n = buf.len().min(bytes.len().saturating_sub(h.pos));
+        buf[..n].copy_from_slice(&bytes[h.pos..h.pos + n]);
+        h.pos += n;
+        Ok(n)
+    }
+
+    fn write(&mut self, _handle: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn seek(&mut self, handle: u64, offset: i64, whence: SeekFrom) -> FsResult<u64> {
+        let h = self.handle(handle)?;
+        let base = match whence {
+            SeekFrom::Start => 0,
+            SeekFrom::Current => h.pos as i64,
+            SeekFrom::End => h.data.len() as i64,
+        };
+        let pos = base.checked_add(offset).filter(|&p| p >= 0).ok_or(FsError::InvalidPath)?;
+        h.pos = (pos as usize).min(h.data.len());
+        Ok(h.pos as u64)
+    }
+
+    fn stat(&self, path: &str) -> FsResult<Metadata> {
+        if path == PROC_ROOT {
+            return Ok(Self::metadata(FileType::Directory, 0));
+        }
+        // Size is unknown until generated; report zero like other procfs
+        lookup(path)?;
+        Ok(Self::metadata(FileType::Regular, 0))
+    }
+
+    fn read_dir_entry(&mut self, path: &str, cookie: u64) -> FsResult<Option<(DirEntry, u64)>> {
+        if path != PROC_ROOT {
+            lookup(path)?;
+            return Err(FsError::NotDirectory);
+        }
+        // Cookie: index into ENTRIES
+        let Some(entry) = ENTRIES.get(cookie as usize) else {
+            return Ok(None);
+        };
+        let mut name = [0u8; MAX_FILENAME];
+        let len = entry.name.len().min(MAX_FILENAME);
+        name[..len].copy_from_slice(&entry.name.as_bytes()[..len]);
+        let dirent = DirEntry { name, name_len: len, file_type: FileType::Regular, inode: cookie + 1 };
+        Ok(Some((dirent, cookie + 1)))
+    }
+
+    fn mkdir(&mut self, _path: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn remove(&mut self, _path: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rmdir(&mut self, _path: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&mut self, _from: &str, _to: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn handle_stats(&self) -> Option<SlotStats> {
+        Some(self.handles.stats())
+    }
+}
+
+/// Global /proc instance (routed to by the VFS)
+pub static mut PROCFS: ProcFs = ProcFs::new();
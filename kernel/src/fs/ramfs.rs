@@ -0,0 +1,355 @@
+//! RAM Filesystem
+//!
+//! Heap-backed filesystem with directories, regular files and native
+//! symbolic links. Nodes live in a flat table and point at their parent;
+//! directory listings scan the table, which is fine for the handful of
+//! files a ramfs holds. Paths arrive canonical and with links already
+//! resolved by the VFS, so lookup never follows links itself.
+//!
+//! Removed nodes leave a hole in the table; file data is returned to the
+//! heap.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use super::{DirEntry, FileType, Filesystem, FsError, FsResult, Metadata, OpenFlags,
+            Permissions, SeekFrom, MAX_FILENAME};
+
+/// Maximum simultaneously open files
+const MAX_HANDLES: usize = 16;
+
+/// Index of the root directory
+const ROOT: usize = 0;
+
+/// What a node is
+enum NodeKind {
+    Directory,
+    File(Vec<u8>),
+    Symlink(String),
+}
+
+/// A file, directory or link
+struct Node {
+    name: String,
+    parent: usize,
+    kind: NodeKind,
+}
+
+impl Node {
+    fn file_type(&self) -> FileType {
+        match self.kind {
+            NodeKind::Directory => FileType::Directory,
+            NodeKind::File(_) => FileType::Regular,
+            NodeKind::Symlink(_) => FileType::Symlink,
+        }
+    }
+}
+
+/// Open file state
+#[derive(Clone, Copy)]
+struct RamHandle {
+    node: usize,
+    pos: u64,
+    flags: OpenFlags,
+}
+
+/// RAM filesystem instance
+pub struct RamFs {
+    nodes: Vec<Option<Node>>,
+    handles: [Option<RamHandle>; MAX_HANDLES],
+}
+
+impl RamFs {
+    /// Create an empty filesystem (the root appears on mount)
+    pub const fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            handles: [None; MAX_HANDLES],
+        }
+    }
+
+    fn node(&self, index: usize) -> FsResult<&Node> {
+        self.nodes.get(index).and_then(|n| n.as_ref()).ok_or(FsError::NotFound)
+    }
+
+    fn node_mut(&mut self, index: usize) -> FsResult<&mut Node> {
+        self.nodes.get_mut(index).and_then(|n| n.as_mut()).ok_or(FsError::NotFound)
+    }
+
+    /// Child of `dir` called `name`
+    fn child(&self, dir: usize, name: &str) -> Option<usize> {
+        self.nodes.iter().enumerate().skip(1).find_map(|(i, n)| match n {
+            Some(n) if n.parent == dir && n.name == name => Some(i),
+            _ => None,
+        })
+    }
+
+    /// Node at a canonical absolute path
+    fn lookup(&self, path: &str) -> FsResult<usize> {
+        if self.nodes.is_empty() {
+            return Err(FsError::NotMounted);
+        }
+        let mut current = ROOT;
+        for name in path.split('/').filter(|c| !c.is_empty()) {
+            if !matches!(self.node(current)?.kind, NodeKind::Directory) {
+                return Err(FsError::NotDirectory);
+            }
+            current = self.child(current, name).ok_or(FsError::NotFound)?;
+        }
+        Ok(current)
+    }
+
+    /// Parent directory and final name of a path
+    fn split<'a>(&self, path: &'a str) -> FsResult<(usize, &'a str)> {
+        let cut = path.rfind('/').ok_or(FsError::InvalidPath)?;
+        let name = &path[cut + 1..];
+        if name.is_empty() || name.len() > MAX_FILENAME {
+            return Err(FsError::InvalidPath);
+        }
+        let parent = self.lookup(if cut == 0 { "/" } else { &path[..cut] })?;
+        if !matches!(self.node(parent)?.kind, NodeKind::Directory) {
+            return Err(FsError::NotDirectory);
+        }
+        Ok((parent, name))
+    }
+
+    /// Add a node under a path that must not exist yet
+    fn create(&mut self, path: &str, kind: NodeKind) -> FsResult<usize> {
+        let (parent, name) = self.split(path)?;
+        if self.child(parent, name).is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let node = Node { name: String::from(name), parent, kind };
+        match self.nodes.iter().position(|n| n.is_none()) {
+            Some(i) => {
+                self.nodes[i] = Some(node);
+                Ok(i)
+            }
+            None => {
+                self.nodes.push(Some(node));
+                Ok(self.nodes.len() - 1)
+            }
+        }
+    }
+
+    fn is_open(&self, node: usize) -> bool {
+        self.handles.iter().flatten().any(|h| h.node == node)
+    }
+
+    fn handle(&mut self, handle: u64) -> FsResult<&mut RamHandle> {
+        self.handles.get_mut(handle as usize)
+            .and_then(|h| h.as_mut())
+            .ok_or(FsError::InvalidPath)
+    }
+
+    fn file_data(&mut self, node: usize) -> FsResult<&mut Vec<u8>> {
+        match &mut self.node_mut(node)?.kind {
+            NodeKind::File(data) => Ok(data),
+            NodeKind::Directory => Err(FsError::IsDirectory),
+            NodeKind::Symlink(_) => Err(FsError::InvalidPath),
+        }
+    }
+}
+
+impl Filesystem for RamFs {
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn mount(&mut self) -> FsResult<()> {
+        if self.nodes.is_empty() {
+            self.nodes.push(Some(Node { name: String::new(), parent: ROOT, kind: NodeKind::Directory }));
+        }
+        Ok(())
+    }
+
+    fn unmount(&mut self) -> FsResult<()> {
+        // Contents survive so the volume can be mounted again
+        self.handles = [None; MAX_HANDLES];
+        Ok(())
+    }
+
+    fn open(&mut self, path: &str, flags: OpenFlags) -> FsResult<u64> {
+        let slot = self.handles.iter().position(|h| h.is_none())
+            .ok_or(FsError::TooManyOpenFiles)?;
+
+        let node = match self.lookup(path) {
+            Ok(_) if flags.create && flags.exclusive => return Err(FsError::AlreadyExists),
+            Ok(node) => node,
+            Err(FsError::NotFound) if flags.create => self.create(path, NodeKind::File(Vec::new()))?,
+            Err(e) => return Err(e),
+        };
+
+        let data = self.file_data(node)?;
+        if flags.truncate && flags.write {
+            data.clear();
+        }
+        self.handles[slot] = Some(RamHandle { node, pos: 0, flags });
+        Ok(slot as u64)
+    }
+
+    fn close(&mut self, handle: u64) -> FsResult<()> {
+        self.handle(handle)?;
+        self.handles[handle as usize] = None;
+        Ok(())
+    }
+
+    fn read(&mut self, handle: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let h = *self.handle(handle)?;
+        let n = self.read_at(handle, h.pos, buf)?;
+        self.handle(handle)?.pos += n as u64;
+        Ok(n)
+    }
+
+    fn write(&mut self, handle: u64, buf: &[u8]) -> FsResult<usize> {
+        let h = *self.handle(handle)?;
+        let n = self.write_at(handle, h.pos, buf)?;
+        self.handle(handle)?.pos += n as u64;
+        Ok(n)
+    }
+
+    fn read_at(&mut self, handle: u64, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let h = *self.handle(handle)?;
+        let data = self.file_data(h.node)?;
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn write_at(&mut self, handle: u64, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        let h = *self.handle(handle)?;
+        if !h.flags.write {
+            return Err(FsError::PermissionDenied);
+        }
+        let data = self.file_data(h.node)?;
+        let start = offset as usize;
+        let end = start.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+        if end > data.len() {
+            data.try_reserve(end - data.len()).map_err(|_| FsError::NoSpace)?;
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn size(&mut self, handle: u64) -> FsResult<u64> {
+        let h = *self.handle(handle)?;
+        Ok(self.file_data(h.node)?.len() as u64)
+    }
+
+    fn seek(&mut self, handle: u64, offset: i64, whence: SeekFrom) -> FsResult<u64> {
+        let h = *self.handle(handle)?;
+        let base = match whence {
+            SeekFrom::Start => 0,
+            SeekFrom::Current => h.pos as i64,
+            SeekFrom::End => self.file_data(h.node)?.len() as i64,
+        };
+        let pos = base.checked_add(offset).filter(|&p| p >= 0).ok_or(FsError::InvalidPath)?;
+        self.handle(handle)?.pos = pos as u64;
+        Ok(pos as u64)
+    }
+
+    fn stat(&self, path: &str) -> FsResult<Metadata> {
+        let node = self.node(self.lookup(path)?)?;
+        let (size, permissions) = match &node.kind {
+            NodeKind::Directory => (0, Permissions::default_dir()),
+            NodeKind::File(data) => (data.len() as u64, Permissions::default_file()),
+            NodeKind::Symlink(target) => (target.len() as u64, Permissions::default_file()),
+        };
+        Ok(Metadata {
+            file_type: node.file_type(),
+            size,
+            permissions,
+            created: 0,
+            modified: 0,
+            accessed: 0,
+        })
+    }
+
+    fn read_dir_entry(&mut self, path: &str, cookie: u64) -> FsResult<Option<(DirEntry, u64)>> {
+        let dir = self.lookup(path)?;
+        if !matches!(self.node(dir)?.kind, NodeKind::Directory) {
+            return Err(FsError::NotDirectory);
+        }
+        // Cookie: node index to continue the scan from (node 0 is the root)
+        let start = (cookie as usize).max(1);
+        for (i, node) in self.nodes.iter().enumerate().skip(start) {
+            let Some(node) = node else { continue };
+            if node.parent != dir {
+                continue;
+            }
+            let mut name = [0u8; MAX_FILENAME];
+            let len = node.name.len().min(MAX_FILENAME);
+            name[..len].copy_from_slice(&node.name.as_bytes()[..len]);
+            let entry = DirEntry { name, name_len: len, file_type: node.file_type(), inode: i as u64 };
+            return Ok(Some((entry, i as u64 + 1)));
+        }
+        Ok(None)
+    }
+
+    fn mkdir(&mut self, path: &str) -> FsResult<()> {
+        self.create(path, NodeKind::Directory).map(|_| ())
+    }
+
+    fn remove(&mut self, path: &str) -> FsResult<()> {
+        let node = self.lookup(path)?;
+        if matches!(self.node(node)?.kind, NodeKind::Directory) {
+            return Err(FsError::IsDirectory);
+        }
+        if self.is_open(node) {
+            return Err(FsError::PermissionDenied);
+        }
+        self.nodes[node] = None;
+        Ok(())
+    }
+
+    fn rmdir(&mut self, path: &str) -> FsResult<()> {
+        let dir = self.lookup(path)?;
+        if dir == ROOT {
+            return Err(FsError::PermissionDenied);
+        }
+        if !matches!(self.node(dir)?.kind, NodeKind::Directory) {
+            return Err(FsError::NotDirectory);
+        }
+        if self.nodes.iter().flatten().any(|n| n.parent == dir) {
+            return Err(FsError::AlreadyExists);
+        }
+        self.nodes[dir] = None;
+        Ok(())
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> FsResult<()> {
+        let node = self.lookup(from)?;
+        if node == ROOT {
+            return Err(FsError::PermissionDenied);
+        }
+        let (parent, name) = self.split(to)?;
+        if self.child(parent, name).is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        // Refuse to move a directory underneath itself
+        let mut ancestor = parent;
+        while ancestor != ROOT {
+            if ancestor == node {
+                return Err(FsError::InvalidPath);
+            }
+            ancestor = self.node(ancestor)?.parent;
+        }
+        let name = String::from(name);
+        let n = self.node_mut(node)?;
+        n.parent = parent;
+        n.name = name;
+        Ok(())
+    }
+
+    fn symlink(&mut self, target: &str, link: &str) -> FsResult<()> {
+        self.create(link, NodeKind::Symlink(String::from(target))).map(|_| ())
+    }
+
+    fn readlink(&mut self, path: &str) -> FsResult<String> {
+        match &self.node(self.lookup(path)?)?.kind {
+            NodeKind::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidPath),
+        }
+    }
+}
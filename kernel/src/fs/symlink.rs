@@ -0,0 +1,87 @@
+//! Symbolic Link Emulation
+//!
+//! exFAT has no symlinks. On filesystems that report
+//! `emulates_symlinks()`, a link is stored as a small regular file that
+//! starts with `SYMLINK_MAGIC` followed by the UTF-8 target, the same
+//! scheme Cygwin uses on FAT volumes. The VFS recognizes these files during
+//! path walks and treats them exactly like native links. Other systems see
+//! an ordinary file holding the target path.
+
+use alloc::string::String;
+use alloc::vec;
+use super::{Filesystem, FsError, FsResult, Metadata, FileType, OpenFlags, MAX_PATH};
+
+/// Marker at the start of an emulated link file
+pub const SYMLINK_MAGIC: &[u8] = b"!<symlink>";
+
+/// Largest file that can be an emulated link
+pub const MAX_EMULATED_SIZE: u64 = (SYMLINK_MAGIC.len() + MAX_PATH) as u64;
+
+/// Links followed while resolving one path before giving up
+pub const MAX_SYMLINK_HOPS: u32 = 8;
+
+/// Flags for writing a new link file
+const CREATE_FLAGS: OpenFlags = OpenFlags {
+    read: false,
+    write: true,
+    append: false,
+    create: true,
+    truncate: false,
+    exclusive: true,
+};
+
+/// Check a link target before storing it
+pub fn validate_target(target: &str) -> FsResult<()> {
+    if target.is_empty() || target.contains('\0') {
+        return Err(FsError::InvalidPath);
+    }
+    if target.len() > MAX_PATH {
+        return Err(FsError::NameTooLong);
+    }
+    Ok(())
+}
+
+/// Store a link as a marker file
+pub fn create_emulated(fs: &mut dyn Filesystem, target: &str, link: &str) -> FsResult<()> {
+    validate_target(target)?;
+    let handle = fs.open(link, CREATE_FLAGS)?;
+
+    let mut data = vec![0u8; SYMLINK_MAGIC.len() + target.len()];
+    data[..SYMLINK_MAGIC.len()].copy_from_slice(SYMLINK_MAGIC);
+    data[SYMLINK_MAGIC.len()..].copy_from_slice(target.as_bytes());
+    let written = fs.write_at(handle, 0, &data);
+    let closed = fs.close(handle);
+
+    match written {
+        Ok(n) if n == data.len() => closed,
+        Ok(_) => Err(FsError::NoSpace),
+        Err(e) => Err(e),
+    }
+}
+
+/// Target of an emulated link, or None if the file is not one
+///
+/// `meta` is the file's metadata from `stat`, used to skip anything that
+/// cannot be a link without opening it.
+pub fn read_emulated(fs: &mut dyn Filesystem, path: &str, meta: &Metadata) -> FsResult<Option<String>> {
+    if meta.file_type != FileType::Regular
+        || meta.size < SYMLINK_MAGIC.len() as u64
+        || meta.size > MAX_EMULATED_SIZE
+    {
+        return Ok(None);
+    }
+
+    let handle = fs.open(path, OpenFlags::read_only())?;
+    let mut data = vec![0u8; meta.size as usize];
+    let read = fs.read_at(handle, 0, &mut data);
+    let _ = fs.close(handle);
+    let n = read?;
+
+    let Some(target) = data[..n].strip_prefix(SYMLINK_MAGIC) else {
+        return Ok(None);
+    };
+    match core::str::from_utf8(target) {
+        Ok(target) if !target.is_empty() => Ok(Some(String::from(target))),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,633 @@
+//! Virtual Filesystem Switch
+//!
+//! Routes path and descriptor operations to the mounted root filesystem.
+//! File descriptors 0-2 are reserved for the console (stdin/stdout/stderr);
+//! descriptors from 3 upward refer to open file descriptions on the root
+//! filesystem, or on procfs for paths beneath `/proc`.
+//!
+//! An open file description holds the file position and access mode, and is
+//! shared by every descriptor duplicated from the same `open` (Dup/Dup2).
+//! Filesystems only see positioned reads and writes on their handle; the
+//! handle is closed when the last descriptor referring to it goes away.
+//!
+//! Paths may be relative. They are resolved against the current task's
+//! working directory (or the kernel's own, outside task context) and
+//! canonicalized before a filesystem sees them. Symbolic links in any
+//! component are then followed (at most `MAX_SYMLINK_HOPS` in total), so
+//! filesystems only ever see link-free paths. Filesystems without native
+//! links get them emulated as marker files (see `symlink`).
+//!
+//! `mount_disk` takes a raw disk, splits it into partitions, probes each
+//! for a filesystem it has a driver for, and mounts the first as root.
+//! A root mounted read-only (by request, or because the volume was left
+//! dirty) refuses opens for writing and link creation here, before the
+//! filesystem sees them.
+//!
+//! Volumes with an identity (`Filesystem::volume_id`, the exFAT Volume
+//! GUID) can be told apart whatever order the disks come up in:
+//! `volume_id` reads it from any registered volume, and the storage chain
+//! uses it for `root=UUID=...`.
+
+use super::{Filesystem, FsError, FsResult, Metadata, MountOptions, OpenFlags, ReadDir, SeekFrom};
+use super::procfs::{self, PROCFS};
+use super::bcache::BCACHE;
+use super::block::{BlockDevice, DeviceId};
+use super::exfat::{self, ExfatFilesystem};
+use super::partition;
+use super::path::{self, PathBuf};
+use super::symlink::{self, MAX_SYMLINK_HOPS};
+use crate::util::slotmap::{self, SlotMap};
+use crate::util::Uuid;
+use super::FileType;
+use alloc::string::String;
+use alloc::boxed::Box;
+use core::fmt::Write;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Maximum number of open file descriptors
+pub const MAX_FDS: usize = 32;
+
+/// Maximum number of open file descriptions
+pub const MAX_OPEN_FILES: usize = MAX_FDS;
+
+/// First descriptor handed out by open (0-2 are the console)
+pub const FIRST_FD: u32 = 3;
+
+/// State of one `open`, shared by all descriptors dup'd from it
+#[derive(Clone, Copy)]
+pub struct OpenFileDescription {
+    /// Filesystem handle
+    handle: u64,
+    /// Flags the file was opened with
+    flags: OpenFlags,
+    /// Handle belongs to procfs rather than the root filesystem
+    proc: bool,
+    /// Byte offset of the next read or write
+    position: u64,
+    /// Descriptors referring to this description
+    refs: u32,
+}
+
+/// Filesystem found on a volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeKind {
+    Exfat,
+    /// Recognized, but there is no driver yet
+    Fat32,
+    /// cpio "newc" initrd archive (not mountable; see `cpio`)
+    Initrd,
+}
+
+impl VolumeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Exfat => "exFAT",
+            Self::Fat32 => "FAT32",
+            Self::Initrd => "initrd",
+        }
+    }
+}
+
+/// Identify the filesystem on a cached volume from its first block
+pub fn probe(id: DeviceId) -> Option<VolumeKind> {
+    let cache = unsafe { &mut BCACHE };
+    let mut sector = vec![0u8; cache.block_size(id).ok()?];
+    cache.read(id, 0, &mut sector).ok()?;
+    if sector.len() < 512 {
+        None
+    } else if exfat::probe(&sector) {
+        Some(VolumeKind::Exfat)
+    } else if &sector[82..90] == b"FAT32   " {
+        Some(VolumeKind::Fat32)
+    } else if &sector[0..6] == b"070701" {
+        Some(VolumeKind::Initrd)
+    } else {
+        None
+    }
+}
+
+/// Virtual filesystem state
+pub struct Vfs {
+    /// Root filesystem
+    root: Option<&'static mut dyn Filesystem>,
+    /// Volume the root was mounted from (None for an in-memory root)
+    root_device: Option<DeviceId>,
+    /// Open file descriptions
+    files: SlotMap<OpenFileDescription, MAX_OPEN_FILES>,
+    /// Descriptor table (index = fd - FIRST_FD, value = index into `files`)
+    fds: [Option<u8>; MAX_FDS],
+    /// Working directory used outside task context
+    kernel_cwd: PathBuf,
+}
+
+impl Vfs {
+    /// Create an empty VFS with nothing mounted
+    pub const fn new() -> Self {
+        Self {
+            root: None,
+            root_device: None,
+            files: SlotMap::new(),
+            fds: [None; MAX_FDS],
+            kernel_cwd: PathBuf::root(),
+        }
+    }
+    
+    /// Mount a filesystem at the root
+    pub fn mount_root(&mut self, fs: &'static mut dyn Filesystem) -> FsResult<()> {
+        if self.root.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        fs.mount()?;
+        self.root = Some(fs);
+        Ok(())
+    }
+    
+    /// Unmount the root filesystem, dropping its descriptors
+    pub fn unmount_root(&mut self) -> FsResult<()> {
+        let fs = self.root.take().ok_or(FsError::NotMounted)?;
+        self.root_device = None;
+        for fd in self.fds.iter_mut() {
+            if matches!(*fd, Some(i) if !self.files.get(i as usize).map_or(false, |f| f.proc)) {
+                *fd = None;
+            }
+        }
+        self.files.retain(|f| f.proc);
+        fs.unmount()
+    }
+    
+    /// Register a disk and its partitions with the buffer cache, then
+    /// mount the first volume with a supported filesystem as root
+    ///
+    /// Returns the volumes that were registered (the whole disk if it has
+    /// no partition table), whether or not one could be mounted.
+    pub fn mount_disk(&mut self, disk: &'static mut dyn BlockDevice) -> FsResult<Vec<DeviceId>> {
+        let volumes = partition::register_disk(disk)?;
+        for &id in &volumes {
+            if self.root.is_some() {
+                break;
+            }
+            // A corrupt volume shouldn't hide the ones after it
+            let _ = self.mount_volume(id, MountOptions::new());
+        }
+        Ok(volumes)
+    }
+    
+    /// Probe a registered volume and mount it as root
+    pub fn mount_volume(&mut self, id: DeviceId, options: MountOptions) -> FsResult<VolumeKind> {
+        match probe(id) {
+            Some(VolumeKind::Exfat) => {
+                let fs: &'static mut ExfatFilesystem = Box::leak(Box::new(ExfatFilesystem::new()));
+                fs.attach(id)?;
+                fs.set_options(options)?;
+                self.mount_root(fs)?;
+                self.root_device = Some(id);
+                Ok(VolumeKind::Exfat)
+            }
+            Some(_) | None => Err(FsError::InvalidFs),
+        }
+    }
+    
+    /// Is a root filesystem mounted?
+    pub fn is_mounted(&self) -> bool {
+        self.root.is_some()
+    }
+    
+    /// Is the root filesystem refusing writes?
+    pub fn is_read_only(&self) -> bool {
+        self.root.as_ref().is_some_and(|fs| fs.is_read_only())
+    }
+    
+    /// Check the root filesystem (see `Filesystem::check`)
+    pub fn check_root(&mut self, repair: bool, out: &mut String) -> FsResult<u32> {
+        self.root()?.check(repair, out)
+    }
+    
+    /// Check a registered volume
+    ///
+    /// A volume that isn't mounted gets a private mount for the check
+    /// (read-only unless repairing); the root volume is checked in place.
+    pub fn check_volume(&mut self, id: DeviceId, repair: bool, out: &mut String) -> FsResult<u32> {
+        if self.root_device == Some(id) {
+            return self.check_root(repair, out);
+        }
+        match probe(id) {
+            Some(VolumeKind::Exfat) => {
+                let mut fs = Box::new(ExfatFilesystem::new());
+                fs.attach(id)?;
+                fs.set_options(MountOptions { read_only: !repair, force: true })?;
+                fs.mount()?;
+                let result = fs.check(repair, out);
+                let unmounted = fs.unmount();
+                let problems = result?;
+                unmounted.map(|_| problems)
+            }
+            Some(_) => Err(FsError::NotSupported),
+            None => Err(FsError::InvalidFs),
+        }
+    }
+    
+    /// Identity of a registered volume
+    ///
+    /// A volume that isn't mounted gets a private read-only mount to read
+    /// it.
+    pub fn volume_id(&mut self, id: DeviceId) -> Option<Uuid> {
+        if self.root_device == Some(id) {
+            return self.root_volume_id();
+        }
+        match probe(id)? {
+            VolumeKind::Exfat => {
+                let mut fs = Box::new(ExfatFilesystem::new());
+                fs.attach(id).ok()?;
+                fs.set_options(MountOptions::read_only()).ok()?;
+                fs.mount().ok()?;
+                let uuid = fs.volume_id();
+                let _ = fs.unmount();
+                uuid
+            }
+            _ => None,
+        }
+    }
+    
+    /// Identity of the root volume
+    pub fn root_volume_id(&self) -> Option<Uuid> {
+        self.root.as_ref().and_then(|fs| fs.volume_id())
+    }
+    
+    /// Give the root volume a new identity (None removes it)
+    pub fn set_root_volume_id(&mut self, id: Option<Uuid>) -> FsResult<()> {
+        self.root()?.set_volume_id(id)
+    }
+    
+    /// Get the root filesystem
+    fn root(&mut self) -> FsResult<&mut dyn Filesystem> {
+        match self.root.as_mut() {
+            Some(fs) => Ok(&mut **fs),
+            None => Err(FsError::NotMounted),
+        }
+    }
+    
+    /// Filesystem serving a path
+    fn fs_for_path(&mut self, path: &str) -> FsResult<&mut dyn Filesystem> {
+        if procfs::is_proc_path(path) {
+            Ok(unsafe { &mut PROCFS })
+        } else {
+            self.root()
+        }
+    }
+    
+    /// Filesystem serving a path that is about to be modified
+    fn fs_for_write(&mut self, path: &str) -> FsResult<&mut dyn Filesystem> {
+        let fs = self.fs_for_path(path)?;
+        if fs.is_read_only() {
+            return Err(FsError::ReadOnly);
+        }
+        Ok(fs)
+    }
+    
+    /// Filesystem owning an open file description
+    fn fs_for_file(&mut self, proc: bool) -> FsResult<&mut dyn Filesystem> {
+        if proc {
+            Ok(unsafe { &mut PROCFS })
+        } else {
+            self.root()
+        }
+    }
+    
+    /// Index of the open file description behind a descriptor
+    fn file_index(&self, fd: u32) -> FsResult<usize> {
+        let idx = fd.checked_sub(FIRST_FD).ok_or(FsError::InvalidPath)? as usize;
+        self.fds.get(idx).copied().flatten().map(usize::from).ok_or(FsError::InvalidPath)
+    }
+    
+    /// Open file description behind a descriptor
+    fn file(&self, fd: u32) -> FsResult<OpenFileDescription> {
+        let idx = self.file_index(fd)?;
+        self.files.get(idx).copied().ok_or(FsError::InvalidPath)
+    }
+    
+    /// Lowest free descriptor slot
+    fn free_fd(&self) -> FsResult<usize> {
+        self.fds.iter().position(|e| e.is_none()).ok_or(FsError::TooManyOpenFiles)
+    }
+    
+    /// Drop one reference to a description, closing the handle on the last
+    fn release(&mut self, idx: usize) -> FsResult<()> {
+        let file = self.files.get_mut(idx).ok_or(FsError::InvalidPath)?;
+        file.refs -= 1;
+        if file.refs > 0 {
+            return Ok(());
+        }
+        let (handle, proc) = (file.handle, file.proc);
+        self.files.remove(idx);
+        self.fs_for_file(proc)?.close(handle)
+    }
+    
+    /// Current working directory of the caller
+    pub fn cwd(&self) -> PathBuf {
+        match unsafe { crate::sched::SCHEDULER.current() } {
+            Some(task) => PathBuf::new(unsafe { (*task).cwd() }).unwrap_or(PathBuf::root()),
+            None => self.kernel_cwd,
+        }
+    }
+    
+    /// Canonical absolute form of a path, relative to the caller's cwd
+    pub fn resolve(&self, path: &str) -> FsResult<PathBuf> {
+        path::resolve(&self.cwd(), path)
+    }
+    
+    /// Resolve a path and follow symbolic links in it
+    ///
+    /// With `follow_last` false a link in the final component is left in
+    /// place (for lstat, readlink, and creating links).
+    fn lookup(&mut self, path: &str, follow_last: bool) -> FsResult<PathBuf> {
+        let mut path = self.resolve(path)?;
+        let mut hops = 0;
+        
+        'restart: loop {
+            let mut start = 1;
+            while start <= path.len() {
+                let end = path[start..].find('/').map_or(path.len(), |i| start + i);
+                if end < path.len() || follow_last {
+                    if let Some(target) = self.link_target(&path[..end])? {
+                        hops += 1;
+                        if hops > MAX_SYMLINK_HOPS {
+                            return Err(FsError::SymlinkLoop);
+                        }
+                        // Relative targets are taken from the link's directory
+                        let parent = match path[..end].rfind('/') {
+                            Some(0) | None => "/",
+                            Some(cut) => &path[..cut],
+                        };
+                        // Then the rest of the path is taken from the target
+                        let next = path::resolve(parent, &target)?;
+                        path = match &path[end..] {
+                            "" => next,
+                            rest => path::resolve(&next, &rest[1..])?,
+                        };
+                        continue 'restart;
+                    }
+                }
+                start = end + 1;
+            }
+            return Ok(path);
+        }
+    }
+    
+    /// Target of the link at a link-free canonical path, if it is one
+    fn link_target(&mut self, path: &str) -> FsResult<Option<String>> {
+        let fs = self.fs_for_path(path)?;
+        // Missing components are reported by the operation itself
+        let Ok(meta) = fs.stat(path) else {
+            return Ok(None);
+        };
+        match meta.file_type {
+            FileType::Symlink => fs.readlink(path).map(Some),
+            FileType::Regular if fs.emulates_symlinks() => symlink::read_emulated(fs, path, &meta),
+            _ => Ok(None),
+        }
+    }
+    
+    /// Create a symbolic link at `link` pointing to `target`
+    ///
+    /// The target is stored as given; relative targets are resolved from
+    /// the link's directory when the link is followed.
+    pub fn symlink(&mut self, target: &str, link: &str) -> FsResult<()> {
+        symlink::validate_target(target)?;
+        let link = &self.lookup(link, false)?;
+        if self.link_target(link)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let fs = self.fs_for_write(link)?;
+        if fs.emulates_symlinks() {
+            symlink::create_emulated(fs, target, link)
+        } else {
+            fs.symlink(target, link)
+        }
+    }
+    
+    /// Read the target of a symbolic link
+    pub fn readlink(&mut self, path: &str) -> FsResult<String> {
+        let path = &self.lookup(path, false)?;
+        self.link_target(path)?.ok_or(FsError::InvalidPath)
+    }
+    
+    /// Get metadata without following a final symbolic link
+    pub fn lstat(&mut self, path: &str) -> FsResult<Metadata> {
+        let path = &self.lookup(path, false)?;
+        let fs = self.fs_for_path(path)?;
+        let mut meta = fs.stat(path)?;
+        if meta.file_type == FileType::Regular && fs.emulates_symlinks() {
+            if let Some(target) = symlink::read_emulated(fs, path, &meta)? {
+                meta.file_type = FileType::Symlink;
+                meta.size = target.len() as u64;
+            }
+        }
+        Ok(meta)
+    }
+    
+    /// Change the caller's working directory
+    pub fn chdir(&mut self, path: &str) -> FsResult<()> {
+        let path = self.lookup(path, true)?;
+        if self.stat(&path)?.file_type != FileType::Directory {
+            return Err(FsError::NotDirectory);
+        }
+        match unsafe { crate::sched::SCHEDULER.current() } {
+            Some(task) => unsafe { (*task).set_cwd(&path).map_err(|_| FsError::InvalidPath) },
+            None => {
+                self.kernel_cwd = path;
+                Ok(())
+            }
+        }
+    }
+    
+    /// Open a file, returning a descriptor
+    pub fn open(&mut self, path: &str, flags: OpenFlags) -> FsResult<u32> {
+        let path = &self.lookup(path, true)?;
+        let fd = self.free_fd()?;
+        let fs = if flags.write || flags.create || flags.truncate {
+            self.fs_for_write(path)?
+        } else {
+            self.fs_for_path(path)?
+        };
+        let handle = fs.open(path, flags)?;
+        let proc = procfs::is_proc_path(path);
+        
+        let description = OpenFileDescription { handle, flags, proc, position: 0, refs: 1 };
+        let Some(key) = self.files.insert(description) else {
+            // Don't leave the filesystem holding a handle nobody can close
+            let _ = self.fs_for_file(proc)?.close(handle);
+            return Err(FsError::TooManyOpenFiles);
+        };
+        self.fds[fd] = Some(key.index() as u8);
+        Ok(fd as u32 + FIRST_FD)
+    }
+    
+    /// Close a descriptor
+    pub fn close(&mut self, fd: u32) -> FsResult<()> {
+        let idx = self.file_index(fd)?;
+        self.fds[(fd - FIRST_FD) as usize] = None;
+        self.release(idx)
+    }
+    
+    /// Read from a descriptor at its file position
+    pub fn read(&mut self, fd: u32, buf: &mut [u8]) -> FsResult<usize> {
+        let idx = self.file_index(fd)?;
+        let file = self.file(fd)?;
+        if !file.flags.read {
+            return Err(FsError::PermissionDenied);
+        }
+        let n = self.fs_for_file(file.proc)?.read_at(file.handle, file.position, buf)?;
+        if let Some(f) = self.files.get_mut(idx) {
+            f.position += n as u64;
+        }
+        Ok(n)
+    }
+    
+    /// Write to a descriptor at its file position (or the end, if appending)
+    pub fn write(&mut self, fd: u32, buf: &[u8]) -> FsResult<usize> {
+        let idx = self.file_index(fd)?;
+        let file = self.file(fd)?;
+        if !file.flags.write {
+            return Err(FsError::PermissionDenied);
+        }
+        let fs = self.fs_for_file(file.proc)?;
+        let position = if file.flags.append { fs.size(file.handle)? } else { file.position };
+        let n = fs.write_at(file.handle, position, buf)?;
+        if let Some(f) = self.files.get_mut(idx) {
+            f.position = position + n as u64;
+        }
+        Ok(n)
+    }
+    
+    /// Move a descriptor's file position
+    pub fn seek(&mut self, fd: u32, offset: i64, whence: SeekFrom) -> FsResult<u64> {
+        let idx = self.file_index(fd)?;
+        let file = self.file(fd)?;
+        let base = match whence {
+            SeekFrom::Start => 0,
+            SeekFrom::Current => file.position,
+            SeekFrom::End => self.fs_for_file(file.proc)?.size(file.handle)?,
+        };
+        let position = (base as i64).checked_add(offset)
+            .filter(|&p| p >= 0)
+            .ok_or(FsError::InvalidPath)? as u64;
+        if let Some(f) = self.files.get_mut(idx) {
+            f.position = position;
+        }
+        Ok(position)
+    }
+    
+    /// Duplicate a descriptor onto the lowest free descriptor
+    pub fn dup(&mut self, fd: u32) -> FsResult<u32> {
+        let idx = self.file_index(fd)?;
+        let new = self.free_fd()?;
+        self.share(idx, new);
+        Ok(new as u32 + FIRST_FD)
+    }
+    
+    /// Make `new_fd` refer to the same description as `fd`, closing
+    /// whatever `new_fd` referred to before
+    pub fn dup2(&mut self, fd: u32, new_fd: u32) -> FsResult<u32> {
+        let idx = self.file_index(fd)?;
+        let slot = new_fd.checked_sub(FIRST_FD)
+            .filter(|&s| (s as usize) < MAX_FDS)
+            .ok_or(FsError::InvalidPath)? as usize;
+        if new_fd == fd {
+            return Ok(new_fd);
+        }
+        // As with POSIX dup2, an error closing the old file is not reported
+        if let Some(old) = self.fds[slot].take() {
+            let _ = self.release(old as usize);
+        }
+        self.share(idx, slot);
+        Ok(new_fd)
+    }
+    
+    /// Point descriptor slot `slot` at description `idx`
+    fn share(&mut self, idx: usize, slot: usize) {
+        if let Some(f) = self.files.get_mut(idx) {
+            f.refs += 1;
+        }
+        self.fds[slot] = Some(idx as u8);
+    }
+    
+    /// Get metadata for a path
+    pub fn stat(&mut self, path: &str) -> FsResult<Metadata> {
+        let path = &self.lookup(path, true)?;
+        self.fs_for_path(path)?.stat(path)
+    }
+    
+    /// List a directory
+    pub fn readdir(&mut self, path: &str) -> FsResult<ReadDir<'_>> {
+        self.readdir_from(path, 0)
+    }
+    
+    /// Continue listing a directory from a `ReadDir::cookie`
+    pub fn readdir_from(&mut self, path: &str, cookie: u64) -> FsResult<ReadDir<'_>> {
+        let path = &self.lookup(path, true)?;
+        ReadDir::new(self.fs_for_path(path)?, path, cookie)
+    }
+    
+    /// Make a descriptor's file durable on its device
+    pub fn fsync(&mut self, fd: u32) -> FsResult<()> {
+        let file = self.file(fd)?;
+        self.fs_for_file(file.proc)?.fsync(file.handle)
+    }
+    
+    /// Flush filesystem metadata and every dirty cached block
+    pub fn sync(&mut self) -> FsResult<()> {
+        let fs_result = match self.root.as_mut() {
+            Some(fs) => fs.sync(),
+            None => Ok(()),
+        };
+        super::bcache::sync_all()?;
+        fs_result
+    }
+    
+    /// Open file and handle table rows for /proc/slots
+    ///
+    /// A description is leaked if its reference count disagrees with the
+    /// descriptors pointing at it; a filesystem handle is leaked if no
+    /// description holds it.
+    pub fn slot_report(&self, out: &mut String) {
+        let leaked_files = self.files.iter()
+            .filter(|&(idx, f)| {
+                let fds = self.fds.iter().filter(|&&fd| fd == Some(idx as u8)).count();
+                fds != f.refs as usize
+            })
+            .count();
+        slotmap::write_row(out, "vfs-files", self.files.stats(), leaked_files);
+        
+        let proc_files = self.files.values().filter(|f| f.proc).count();
+        let root_files = self.files.len() - proc_files;
+        if let Some(fs) = self.root.as_ref() {
+            if let Some(stats) = fs.handle_stats() {
+                slotmap::write_row(out, fs.name(), stats, stats.len.saturating_sub(root_files));
+            }
+        }
+        if let Some(stats) = unsafe { (*core::ptr::addr_of!(PROCFS)).handle_stats() } {
+            slotmap::write_row(out, "proc", stats, stats.len.saturating_sub(proc_files));
+        }
+    }
+}
+
+/// Global VFS instance
+pub static mut VFS: Vfs = Vfs::new();
+
+/// Mounted filesystems (/proc/mounts)
+pub fn report(out: &mut String) {
+    let vfs = unsafe { &*core::ptr::addr_of!(VFS) };
+    if let Some(fs) = vfs.root.as_ref() {
+        let _ = match vfs.root_device {
+            Some(dev) => write!(out, "dev{}", dev.0),
+            None => write!(out, "none"),
+        };
+        let _ = write!(out, " / {} {}", fs.name(), if fs.is_read_only() { "ro" } else { "rw" });
+        if let Some(uuid) = fs.volume_id() {
+            let _ = write!(out, " uuid={}", uuid);
+        }
+        let _ = writeln!(out);
+    }
+    let _ = writeln!(out, "proc /proc proc rw");
+}
@@ -0,0 +1,107 @@
+//! BMP Image Decoder
+//!
+//! Decodes uncompressed Windows bitmaps (BITMAPINFOHEADER or later) with
+//! 24 or 32 bits per pixel, bottom-up or top-down, into a heap surface.
+
+use alloc::vec::Vec;
+
+use super::Color;
+
+/// "BM" signature
+const BMP_MAGIC: u16 = 0x4D42;
+
+/// Size of BITMAPFILEHEADER
+const FILE_HEADER_SIZE: usize = 14;
+
+/// Minimum info header size (BITMAPINFOHEADER)
+const INFO_HEADER_MIN: u32 = 40;
+
+/// Uncompressed
+const BI_RGB: u32 = 0;
+/// Uncompressed with channel masks (accepted for standard BGRA layouts)
+const BI_BITFIELDS: u32 = 3;
+
+/// Largest image we are willing to decode (16MB of pixels at most)
+const MAX_DIMENSION: u32 = 2048;
+
+/// Decoded image, row-major, top row first
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Bitmap {
+    /// Get a pixel (coordinates must be in range)
+    #[inline]
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let b = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Decode a BMP file held in memory
+pub fn decode(data: &[u8]) -> Result<Bitmap, &'static str> {
+    const TRUNCATED: &str = "BMP truncated";
+
+    if read_u16(data, 0).ok_or(TRUNCATED)? != BMP_MAGIC {
+        return Err("Not a BMP file");
+    }
+
+    let pixel_offset = read_u32(data, 10).ok_or(TRUNCATED)? as usize;
+    let header_size = read_u32(data, FILE_HEADER_SIZE).ok_or(TRUNCATED)?;
+    if header_size < INFO_HEADER_MIN {
+        return Err("Unsupported BMP header");
+    }
+
+    let width = read_u32(data, 18).ok_or(TRUNCATED)? as i32;
+    let raw_height = read_u32(data, 22).ok_or(TRUNCATED)? as i32;
+    let bpp = read_u16(data, 28).ok_or(TRUNCATED)?;
+    let compression = read_u32(data, 30).ok_or(TRUNCATED)?;
+
+    if compression != BI_RGB && !(compression == BI_BITFIELDS && bpp == 32) {
+        return Err("Compressed BMP not supported");
+    }
+    if bpp != 24 && bpp != 32 {
+        return Err("Only 24/32-bit BMP supported");
+    }
+
+    // Negative height means rows are stored top-down
+    let top_down = raw_height < 0;
+    let height = raw_height.unsigned_abs();
+    if width <= 0 || height == 0 || width as u32 > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err("Bad BMP dimensions");
+    }
+    let width = width as u32;
+
+    let bytes_pp = (bpp / 8) as usize;
+    let stride = (width as usize * bytes_pp + 3) & !3;
+    // The offset comes from the file: a huge one mustn't wrap the sum
+    let needed = stride.checked_mul(height as usize)
+        .and_then(|size| size.checked_add(pixel_offset))
+        .ok_or("Bad BMP pixel offset")?;
+    if data.len() < needed {
+        return Err(TRUNCATED);
+    }
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        let src_row = if top_down { y } else { height - 1 - y };
+        let row = &data[pixel_offset + src_row as usize * stride..];
+        for x in 0..width as usize {
+            let p = &row[x * bytes_pp..];
+            pixels.push(Color::rgb(p[2], p[1], p[0]));
+        }
+    }
+
+    Ok(Bitmap { width, height, pixels })
+}
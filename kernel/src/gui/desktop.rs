@@ -16,17 +16,100 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use core::fmt::Write;
 
-use crate::gui::wm_events::{WmEventDispatcher, z_order};
-use super::{Window, Framebuffer, Color, Rect, Point, theme, MouseButton};
+use crate::gui::wm_events::{self, WmEventDispatcher, z_order};
+use super::notify::{self, Notification};
+use super::profiler::FrameProfiler;
+use super::server::{event_kind, modifier, WinEvent};
+use super::screensaver::Screensaver;
+use super::scroll_view::ScrollView;
+use super::test_pattern::{Pattern, TestPattern};
+use super::wallpaper::{Wallpaper, WallpaperMode};
+use super::{Window, Framebuffer, Color, Rect, Point, theme, GuiEvent, InputEvent, MouseButton};
+use crate::drivers::keyboard::KeyCode;
+use crate::util::slotmap::{self, SlotMap};
+
+pub use super::WindowKind;
 
 /// Maximum number of windows
 const MAX_WINDOWS: usize = 32;
 
+/// Maximum toasts on screen at once
+const MAX_TOASTS: usize = 4;
+
+/// Toast window size and spacing
+const TOAST_WIDTH: u32 = 280;
+const TOAST_HEIGHT: u32 = 56;
+const TOAST_MARGIN: i32 = 8;
+
+/// Renders slower than this are logged (at debug level)
+const SLOW_RENDER_MS: u32 = 50;
+
+/// A notification currently shown as a toast window
+#[derive(Clone, Copy)]
+struct Toast {
+    /// Toast window ID
+    window_id: u32,
+    /// Notification being shown
+    notification: Notification,
+    /// Uptime (ms) at which the toast is dismissed
+    expires_at: u32,
+}
+
 // =============================================================================
 // Terminal Application (Heap Allocated)
 // =============================================================================
 
+/// Maximum input line length
+const TERM_INPUT_MAX: usize = 40;
+
+/// Maximum commands kept in history
+const TERM_HISTORY_MAX: usize = 16;
+
+/// Longest output line kept (the window scrolls sideways for wider ones)
+const TERM_LINE_MAX: usize = 80;
+
+/// Output lines kept for scrolling back
+const TERM_SCROLLBACK: usize = 200;
+
+/// Lines paging commands print at a time (about a window's worth)
+const TERM_PAGE_LINES: usize = 24;
+
+/// Terminal text line height and margin (pixels)
+const TERM_LINE_HEIGHT: u32 = 16;
+const TERM_MARGIN: u32 = 8;
+
+/// Shell started in the terminal when it exists
+const SHELL_PATH: &str = "/bin/sh";
+
+/// Line editing operations for the terminal input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEdit {
+    /// Move cursor one character left
+    Left,
+    /// Move cursor one character right
+    Right,
+    /// Move cursor to start of line
+    Home,
+    /// Move cursor to end of line
+    End,
+    /// Delete character under the cursor
+    Delete,
+    /// Recall previous history entry
+    HistoryPrev,
+    /// Recall next history entry
+    HistoryNext,
+    /// Kill from start of line to cursor (Ctrl+U)
+    KillLine,
+    /// Kill word before cursor (Ctrl+W)
+    KillWord,
+}
+
 /// Terminal state - lives on the HEAP via Box
+///
+/// While a user program is in the foreground (normally `/bin/sh`) the
+/// terminal is the master side of a tty: keys go to the line discipline
+/// and the tty's output (including echo) is shown. Otherwise the
+/// built-in commands run with the terminal's own line editor.
 pub struct Terminal {
     /// Output lines
     lines: Vec<String>,
@@ -34,15 +117,42 @@ pub struct Terminal {
     max_lines: usize,
     /// Current input buffer
     input: String,
+    /// Cursor position within input (bytes; input is ASCII)
+    cursor: usize,
+    /// Previously entered commands, oldest first
+    history: Vec<String>,
+    /// Index into history while browsing (None = editing a new line)
+    history_pos: Option<usize>,
+    /// Line being edited before history browsing started
+    draft: String,
+    /// User program reading the input, if any
+    foreground: Option<crate::sched::Pid>,
+    /// Tty the terminal's programs run on
+    tty: Option<usize>,
+    /// Program output not yet ended by a newline (e.g. a prompt)
+    partial: String,
+    /// Scrollback position (follows new output unless scrolled up)
+    view: ScrollView,
+    /// Test pattern the `testpattern` command asked the desktop to show
+    pattern_request: Option<Pattern>,
 }
 
 impl Terminal {
     /// Create a new terminal
     pub fn new() -> Box<Self> {
         let mut term = Box::new(Self {
-            lines: Vec::with_capacity(8),
-            max_lines: 8,
+            lines: Vec::with_capacity(TERM_SCROLLBACK),
+            max_lines: TERM_SCROLLBACK,
             input: String::with_capacity(48),
+            cursor: 0,
+            history: Vec::with_capacity(TERM_HISTORY_MAX),
+            history_pos: None,
+            draft: String::new(),
+            foreground: None,
+            tty: crate::tty::open(),
+            partial: String::new(),
+            view: ScrollView::new(TERM_LINE_HEIGHT).following_end(),
+            pattern_request: None,
         });
 
         // Welcome message
@@ -53,6 +163,66 @@ impl Terminal {
         term
     }
 
+    /// Start the user shell, keeping the built-in commands if there is none
+    pub fn start_shell(&mut self) {
+        let Ok(args) = crate::exec::Args::new(&["sh"], crate::exec::DEFAULT_ENV) else {
+            return;
+        };
+        if let Ok(pid) = crate::exec::spawn(SHELL_PATH, &args, self.tty) {
+            // Losing the shell would leave the terminal dead; OOM kills its children instead
+            if let Some(task) = crate::exec::task_of(pid) {
+                task.essential = true;
+            }
+            self.foreground = Some(pid);
+        }
+    }
+
+    /// Pick up output from user programs and notice the foreground one exiting
+    ///
+    /// Returns true if anything changed.
+    pub fn poll(&mut self) -> bool {
+        let output = self.tty.map(crate::tty::master_read).unwrap_or_default();
+        self.write_output(&output);
+
+        let Some(pid) = self.foreground else {
+            return !output.is_empty();
+        };
+        let Some(code) = crate::exec::reap(pid) else {
+            return !output.is_empty();
+        };
+        if !self.partial.is_empty() {
+            let line = core::mem::take(&mut self.partial);
+            self.print(&line);
+        }
+        self.foreground = None;
+        if let Some(tty) = self.tty {
+            crate::tty::flush_input(tty);
+        }
+        let mut buf = String::new();
+        let _ = write!(buf, "[exit {}]", code);
+        self.print(&buf);
+        true
+    }
+
+    /// Append program output, breaking it into lines
+    fn write_output(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            match byte {
+                b'\n' => {
+                    let line = core::mem::take(&mut self.partial);
+                    self.print(&line);
+                }
+                b'\r' => {}
+                0x08 => {
+                    self.partial.pop();
+                }
+                _ if self.partial.len() >= TERM_LINE_MAX => {}
+                0x20..=0x7E => self.partial.push(byte as char),
+                _ => self.partial.push('?'),
+            }
+        }
+    }
+
     /// Print a line to the terminal
     pub fn print(&mut self, text: &str) {
         if self.lines.len() >= self.max_lines {
@@ -61,20 +231,188 @@ impl Terminal {
         self.lines.push(String::from(text));
     }
 
-    /// Handle a character input
+    /// Scroll state sized to the current output and input line
+    pub fn view(&self) -> ScrollView {
+        let mut view = self.view;
+        let width = (self.lines.iter().map(String::len).max().unwrap_or(0))
+            .max(self.prompt().len() + self.input.len() + 1) as u32 * super::font::FONT_WIDTH as u32;
+        view.set_content_size(width + TERM_MARGIN * 2, (self.lines.len() as u32 + 1) * TERM_LINE_HEIGHT + TERM_MARGIN * 2);
+        view
+    }
+
+    /// Apply a scrolling operation to the scrollback; true if it moved
+    pub fn scroll(&mut self, op: impl FnOnce(&mut ScrollView) -> bool) -> bool {
+        let mut view = self.view();
+        let moved = op(&mut view);
+        self.view = view;
+        moved
+    }
+
+    /// Tty receiving keystrokes, while a program is in the foreground
+    fn active_tty(&self) -> Option<usize> {
+        self.foreground.and(self.tty)
+    }
+
+    /// Handle a character input (inserted at the cursor)
     pub fn key_input(&mut self, c: char) {
-        if self.input.len() < 40 {
-            self.input.push(c);
+        if let Some(tty) = self.active_tty() {
+            if c.is_ascii() {
+                crate::tty::master_write(tty, &[c as u8]);
+            }
+            return;
+        }
+        if self.input.len() < TERM_INPUT_MAX && c.is_ascii() {
+            self.input.insert(self.cursor, c);
+            self.cursor += 1;
         }
     }
 
-    /// Handle backspace
+    /// Handle backspace (delete character before the cursor)
     pub fn backspace(&mut self) {
-        self.input.pop();
+        if let Some(tty) = self.active_tty() {
+            crate::tty::master_write(tty, &[0x7F]);
+            return;
+        }
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.input.remove(self.cursor);
+        }
     }
 
-    /// Handle enter - execute command
+    /// Handle Ctrl+letter
+    ///
+    /// On a tty this sends the control character (Ctrl+C interrupts,
+    /// Ctrl+D ends input); the built-in prompt just abandons the line.
+    pub fn control(&mut self, letter: char) {
+        if let Some(tty) = self.active_tty() {
+            if letter.is_ascii_alphabetic() {
+                crate::tty::master_write(tty, &[letter.to_ascii_uppercase() as u8 & 0x1F]);
+            }
+            return;
+        }
+        if letter.eq_ignore_ascii_case(&'c') {
+            let mut echo = String::from("> ");
+            echo.push_str(&self.input);
+            echo.push_str("^C");
+            self.print(&echo);
+            self.set_input("");
+            self.history_pos = None;
+        }
+    }
+
+    /// Escape sequence a raw-mode program receives for an editing key
+    fn edit_sequence(op: LineEdit) -> &'static [u8] {
+        match op {
+            LineEdit::HistoryPrev => b"\x1b[A",
+            LineEdit::HistoryNext => b"\x1b[B",
+            LineEdit::Right => b"\x1b[C",
+            LineEdit::Left => b"\x1b[D",
+            LineEdit::Home => b"\x1b[H",
+            LineEdit::End => b"\x1b[F",
+            LineEdit::Delete => b"\x1b[3~",
+            LineEdit::KillLine => &[0x15],
+            LineEdit::KillWord => &[0x17],
+        }
+    }
+
+    /// Apply a line editing operation
+    pub fn edit(&mut self, op: LineEdit) {
+        if let Some(tty) = self.active_tty() {
+            // Canonical mode edits only at the end of the line: pass on
+            // the kill keys and drop cursor movement
+            let kill = matches!(op, LineEdit::KillLine | LineEdit::KillWord);
+            if kill || crate::tty::is_raw(tty) {
+                crate::tty::master_write(tty, Self::edit_sequence(op));
+            }
+            return;
+        }
+        match op {
+            LineEdit::Left => self.cursor = self.cursor.saturating_sub(1),
+            LineEdit::Right => self.cursor = (self.cursor + 1).min(self.input.len()),
+            LineEdit::Home => self.cursor = 0,
+            LineEdit::End => self.cursor = self.input.len(),
+            LineEdit::Delete => {
+                if self.cursor < self.input.len() {
+                    self.input.remove(self.cursor);
+                }
+            }
+            LineEdit::HistoryPrev => self.history_prev(),
+            LineEdit::HistoryNext => self.history_next(),
+            LineEdit::KillLine => {
+                self.input.replace_range(..self.cursor, "");
+                self.cursor = 0;
+            }
+            LineEdit::KillWord => {
+                let bytes = self.input.as_bytes();
+                let mut start = self.cursor;
+                while start > 0 && bytes[start - 1] == b' ' {
+                    start -= 1;
+                }
+                while start > 0 && bytes[start - 1] != b' ' {
+                    start -= 1;
+                }
+                self.input.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+        }
+    }
+
+    /// Step back through history
+    fn history_prev(&mut self) {
+        let pos = match self.history_pos {
+            Some(0) => return,
+            Some(pos) => pos - 1,
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.input.clone();
+                self.history.len() - 1
+            }
+        };
+        self.history_pos = Some(pos);
+        self.set_input(&self.history[pos].clone());
+    }
+
+    /// Step forward through history, returning to the draft at the end
+    fn history_next(&mut self) {
+        let pos = match self.history_pos {
+            Some(pos) => pos + 1,
+            None => return,
+        };
+        if pos < self.history.len() {
+            self.history_pos = Some(pos);
+            self.set_input(&self.history[pos].clone());
+        } else {
+            self.history_pos = None;
+            let draft = core::mem::take(&mut self.draft);
+            self.set_input(&draft);
+        }
+    }
+
+    /// Replace the input line, moving the cursor to the end
+    fn set_input(&mut self, text: &str) {
+        self.input.clear();
+        self.input.push_str(text);
+        self.cursor = self.input.len();
+    }
+
+    /// Record a command in history (skips blanks and repeats)
+    fn push_history(&mut self, cmd: &str) {
+        if cmd.is_empty() || self.history.last().map(|s| s.as_str()) == Some(cmd) {
+            return;
+        }
+        if self.history.len() >= TERM_HISTORY_MAX {
+            self.history.remove(0);
+        }
+        self.history.push(String::from(cmd));
+    }
+
+    /// Handle enter - execute command, or pass the line to the foreground program
     pub fn enter(&mut self) {
+        if let Some(tty) = self.active_tty() {
+            crate::tty::master_write(tty, b"\n");
+            return;
+        }
+
         // Echo command
         let mut echo = String::from("> ");
         echo.push_str(&self.input);
@@ -82,21 +420,52 @@ impl Terminal {
 
         // Execute
         let cmd: String = self.input.trim().chars().collect();
+        self.push_history(&cmd);
         self.execute(&cmd);
 
         // Clear input
         self.input.clear();
+        self.cursor = 0;
+        self.history_pos = None;
+        self.draft.clear();
+    }
+
+    /// Handle a key press: editing keys, Ctrl shortcuts, then text
+    pub fn handle_key(&mut self, keycode: KeyCode, ascii: Option<char>, ctrl: bool) {
+        match keycode {
+            KeyCode::Enter | KeyCode::KeypadEnter => self.enter(),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Up => self.edit(LineEdit::HistoryPrev),
+            KeyCode::Down => self.edit(LineEdit::HistoryNext),
+            KeyCode::Left => self.edit(LineEdit::Left),
+            KeyCode::Right => self.edit(LineEdit::Right),
+            KeyCode::Home => self.edit(LineEdit::Home),
+            KeyCode::End => self.edit(LineEdit::End),
+            KeyCode::Delete => self.edit(LineEdit::Delete),
+            KeyCode::U if ctrl => self.edit(LineEdit::KillLine),
+            KeyCode::W if ctrl => self.edit(LineEdit::KillWord),
+            KeyCode::C if ctrl => self.control('c'),
+            KeyCode::D if ctrl => self.control('d'),
+            _ => {
+                if let Some(c) = ascii {
+                    self.key_input(c);
+                }
+            }
+        }
     }
 
     /// Execute a command
+    ///
+    /// Terminal-only commands are handled here; the rest go to the
+    /// kernel command registry.
     fn execute(&mut self, cmd: &str) {
         match cmd {
             "help" => {
-                self.print("Commands: help ls clear info heap");
-            }
-            "ls" => {
-                self.print("Documents/ Projects/ Downloads/");
-                self.print("notes.txt main.rs Cargo.toml");
+                self.print("Commands: help clear info");
+                self.print("theme <plan9|dark|light>");
+                self.print("run <program> [args]");
+                self.print("testpattern [gradients|bars|grid]");
+                crate::commands::help(self);
             }
             "clear" => {
                 self.lines.clear();
@@ -106,31 +475,92 @@ impl Terminal {
                 self.print("RAM: 256 MB");
                 self.print("GPU: ATI Rage Mobility P");
             }
-            "heap" => {
-                let stats = crate::mm::heap::stats();
-                let mut buf = String::new();
-                let _ = write!(buf, "Used: {} bytes", stats.used);
-                self.print(&buf);
-                buf.clear();
-                let _ = write!(buf, "Free: {} bytes", stats.free);
-                self.print(&buf);
-            }
             "" => {}
+            _ if cmd.starts_with("testpattern") => {
+                let name = cmd["testpattern".len()..].trim();
+                match if name.is_empty() { Some(Pattern::Gradients) } else { Pattern::from_name(name) } {
+                    Some(pattern) => self.pattern_request = Some(pattern),
+                    None => self.print("usage: testpattern [gradients|bars|grid]"),
+                }
+            }
+            _ if cmd.starts_with("run ") => {
+                self.run(cmd["run ".len()..].trim());
+            }
+            _ if cmd.starts_with("theme") => {
+                let name = cmd["theme".len()..].trim();
+                if name.is_empty() {
+                    let current = crate::settings::get().theme;
+                    let mut buf = String::from("Theme: ");
+                    buf.push_str(current);
+                    self.print(&buf);
+                } else {
+                    match crate::settings::set_theme(name) {
+                        Ok(()) => self.print("Theme saved"),
+                        Err(e) => self.print(e),
+                    }
+                }
+            }
             _ => {
-                self.print("Unknown cmd. Try 'help'");
+                if !crate::commands::execute(cmd, self) {
+                    self.print("Unknown cmd. Try 'help'");
+                }
             }
         }
     }
 
+    /// Run a user program in the foreground (`run <program> [args...]`)
+    fn run(&mut self, command: &str) {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let Some(&path) = words.first() else {
+            self.print("usage: run <program> [args...]");
+            return;
+        };
+        let spawned = crate::exec::Args::new(&words, crate::exec::DEFAULT_ENV)
+            .and_then(|args| crate::exec::spawn(path, &args, self.tty));
+        match spawned {
+            Ok(pid) => self.foreground = Some(pid),
+            Err(e) => self.print(e),
+        }
+    }
+
+    /// Test pattern to show, if a command asked for one
+    pub fn take_pattern_request(&mut self) -> Option<Pattern> {
+        self.pattern_request.take()
+    }
+
     /// Get lines for rendering
     pub fn lines(&self) -> &[String] {
         &self.lines
     }
 
+    /// Prompt shown before the input (the foreground program's own, if any)
+    pub fn prompt(&self) -> &str {
+        if self.foreground.is_some() { &self.partial } else { "> " }
+    }
+
     /// Get current input
     pub fn input(&self) -> &str {
         &self.input
     }
+
+    /// Get cursor position within the input
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+}
+
+impl crate::commands::Output for Terminal {
+    fn print(&mut self, line: &str) {
+        Terminal::print(self, line);
+    }
+
+    fn columns(&self) -> usize {
+        TERM_INPUT_MAX
+    }
+
+    fn rows(&self) -> usize {
+        TERM_PAGE_LINES
+    }
 }
 
 // =============================================================================
@@ -182,7 +612,7 @@ static CURSOR_MASK: [u16; 16] = [
 /// Desktop state
 pub struct Desktop {
     /// All windows
-    windows: [Option<Window>; MAX_WINDOWS],
+    windows: SlotMap<Window, MAX_WINDOWS>,
     /// Window Z-order (indices into windows array, front to back)
     z_order: [usize; MAX_WINDOWS],
     /// Number of windows in z_order
@@ -210,6 +640,8 @@ pub struct Desktop {
     dirty: bool,
     /// Using hardware cursor (skip software cursor drawing)
     hw_cursor: bool,
+    /// Wait for vblank before presenting a frame
+    vsync: bool,
     /// Saved pixels under cursor (from front buffer)
     cursor_save: [Color; 256], // 16x16
     cursor_save_x: i32,
@@ -218,15 +650,25 @@ pub struct Desktop {
     terminal: Option<Box<Terminal>>,
     /// Terminal window ID
     term_window_id: Option<u32>,
+    /// Visible toasts, oldest first (stacked up from the bottom-right corner)
+    toasts: [Option<Toast>; MAX_TOASTS],
+    /// Idle screensaver
+    screensaver: Screensaver,
+    /// Background image (theme color when None)
+    wallpaper: Option<Wallpaper>,
+    /// Frame time / blit counters and their overlay
+    profiler: FrameProfiler,
+    /// Local minute shown by the taskbar clock
+    clock_minute: u32,
+    /// Test pattern / color picker window state
+    test_pattern: TestPattern,
 }
 
 impl Desktop {
     /// Create a new desktop
     pub fn new(screen_width: u32, screen_height: u32) -> Self {
-        const NONE_WINDOW: Option<Window> = None;
-
         Self {
-            windows: [NONE_WINDOW; MAX_WINDOWS],
+            windows: SlotMap::new(),
             z_order: [0; MAX_WINDOWS],
             window_count: 0,
             focused: None,
@@ -242,11 +684,18 @@ impl Desktop {
             next_id: 1,
             dirty: true,
             hw_cursor: false,
+            vsync: false,
             cursor_save: [Color::BLACK; 256],
             cursor_save_x: -1,
             cursor_save_y: -1,
             terminal: None,
             term_window_id: None,
+            toasts: [None; MAX_TOASTS],
+            screensaver: Screensaver::new(),
+            wallpaper: None,
+            profiler: FrameProfiler::new(),
+            clock_minute: u32::MAX,
+            test_pattern: TestPattern::new(),
         }
     }
 
@@ -258,11 +707,29 @@ impl Desktop {
         self.hw_cursor = enabled;
     }
 
+    /// Enable or disable vblank-paced presents
+    pub fn set_vsync(&mut self, enabled: bool) {
+        self.vsync = enabled;
+    }
+
+    /// Show or hide the frame profiler overlay
+    pub fn toggle_profiler(&mut self) {
+        let enabled = !self.profiler.is_enabled();
+        self.profiler.set_enabled(enabled, crate::time::now_ns());
+        // Repaint to draw (or erase) the overlay
+        self.dirty = true;
+    }
+
+    /// Frame profiler state
+    pub fn profiler(&self) -> &FrameProfiler {
+        &self.profiler
+    }
+
     /// Find window at screen coordinates (front to back)
     pub fn window_at(&self, x: i32, y: i32) -> Option<usize> {
         for i in 0..self.window_count {
             let slot = self.z_order[i];
-            if let Some(ref window) = self.windows[slot] {
+            if let Some(window) = self.windows.get(slot) {
                 if window.flags.visible && window.contains(x, y) {
                     return Some(slot);
                 }
@@ -272,29 +739,151 @@ impl Desktop {
     }
 
     /// Handle mouse movement (direct - hot path)
-    pub fn handle_mouse_move(&mut self, x: i32, y: i32) {
+    pub fn handle_mouse_move(&mut self, x: i32, y: i32, time_ms: u32) {
         self.mouse_x = x.max(0).min(self.screen_width as i32 - 1);
         self.mouse_y = y.max(0).min(self.screen_height as i32 - 1);
 
         // Handle window dragging only
         if let Some(slot) = self.dragging {
-            if let Some(ref mut window) = self.windows[slot] {
-                let new_x = self.mouse_x - self.drag_offset.x;
-                let new_y = self.mouse_y - self.drag_offset.y;
+            if let Some(window) = self.windows.get_mut(slot) {
+                // Same policy the move event applies on release
+                let (new_x, new_y) = wm_events::constrain_position(
+                    self.mouse_x - self.drag_offset.x,
+                    self.mouse_y - self.drag_offset.y,
+                    window.bounds.width, self.screen_width, self.screen_height,
+                );
                 window.move_to(new_x, new_y);
                 self.dirty = true;
             }
+        } else {
+            let event = GuiEvent::MouseMove { x: self.mouse_x, y: self.mouse_y };
+            self.post_pointer(InputEvent::new(event, time_ms));
         }
         // Note: Sketch drawing only happens on click, not drag
         // This keeps the mouse driver interaction simple and safe
     }
 
-    /// Handle keyboard input
-    pub fn handle_key(&mut self, _key: char, _pressed: bool) {
-        // Forward to focused window
-        if let Some(_slot) = self.focused {
-            // In a full implementation, dispatch to window's event handler
+    /// Focused program window with the pointer in its content area
+    fn client_pointer(&self) -> Option<usize> {
+        let slot = self.focused?;
+        let window = self.windows.get(slot)?;
+        if !super::server::is_client(window.id) {
+            return None;
+        }
+        window.content_rect_abs().contains(self.mouse_x, self.mouse_y).then_some(slot)
+    }
+
+    /// Does the focused window take keys? (the terminal or a program's)
+    fn focused_takes_keys(&self) -> bool {
+        self.focused
+            .and_then(|slot| self.windows.get(slot))
+            .is_some_and(|w| Some(w.id) == self.term_window_id || super::server::is_client(w.id))
+    }
+
+    /// Queue a key event on the focused window
+    ///
+    /// Returns false if no window that takes keys has focus; the caller
+    /// then uses the key for navigation.
+    pub fn post_key(&mut self, event: InputEvent) -> bool {
+        if !self.focused_takes_keys() {
+            return false;
+        }
+        if let Some(window) = self.focused.and_then(|slot| self.windows.get_mut(slot)) {
+            window.post_event(event);
+        }
+        true
+    }
+
+    /// Wheel: scroll the terminal or pass it to the program window under
+    /// the pointer (focused or not)
+    pub fn handle_wheel(&mut self, input: InputEvent) {
+        let GuiEvent::Wheel { x, y, delta } = input.event else {
+            return;
+        };
+        let Some(slot) = self.window_at(x, y) else {
+            return;
+        };
+        let Some(window) = self.windows.get_mut(slot) else {
+            return;
+        };
+        if Some(window.id) == self.term_window_id {
+            let area = window.content_rect_abs();
+            if let Some(term) = self.terminal.as_mut() {
+                self.dirty |= term.scroll(|v| v.handle_wheel(area, delta));
+            }
+        } else if super::server::is_client(window.id) {
+            window.post_event(input);
+        }
+    }
+
+    /// Queue a pointer event on the focused program window under the pointer
+    fn post_pointer(&mut self, event: InputEvent) {
+        if let Some(slot) = self.client_pointer() {
+            if let Some(window) = self.windows.get_mut(slot) {
+                window.post_event(event);
+            }
+        }
+    }
+
+    /// Hand every window's queued events to its content handler
+    pub fn pump_events(&mut self) {
+        for slot in 0..MAX_WINDOWS {
+            while let Some((id, event)) = self.windows.get_mut(slot)
+                .and_then(|w| Some((w.id, w.take_event()?)))
+            {
+                self.deliver(slot, id, event);
+            }
+        }
+    }
+
+    /// Content handlers: the terminal edits its line, program windows
+    /// forward to the window server (waking a program blocked on them)
+    fn deliver(&mut self, slot: usize, id: u32, InputEvent { event, .. }: InputEvent) {
+        if Some(id) == self.term_window_id {
+            let area = self.windows.get(slot).map(|w| w.content_rect_abs());
+            if let (GuiEvent::KeyDown { keycode, ascii, ctrl, .. }, Some(term), Some(area)) = (event, self.terminal.as_mut(), area) {
+                // Page Up/Down scroll back; anything else edits and returns to the bottom
+                if !matches!(keycode, KeyCode::PageUp | KeyCode::PageDown) || !term.scroll(|v| v.handle_key(area, keycode)) {
+                    term.handle_key(keycode, ascii, ctrl);
+                    term.scroll(|v| v.scroll_to_end(area));
+                }
+                if let Some(pattern) = term.take_pattern_request() {
+                    self.show_test_pattern(pattern);
+                }
+                self.dirty = true;
+            }
+            return;
+        }
+        if !super::server::is_client(id) {
+            return;
         }
+        let Some(content) = self.windows.get(slot).map(|w| w.content_rect_abs()) else {
+            return;
+        };
+        let relative = |x: i32, y: i32| ((x - content.x).max(0) as u32, (y - content.y).max(0) as u32);
+        let win_event = match event {
+            GuiEvent::KeyDown { keycode, ascii, ctrl, .. } => WinEvent {
+                kind: event_kind::KEY,
+                a: keycode as u32,
+                b: ascii.map_or(0, |c| c as u32),
+                c: if ctrl { modifier::CTRL } else { 0 },
+            },
+            GuiEvent::MouseMove { x, y } => {
+                let (a, b) = relative(x, y);
+                WinEvent { kind: event_kind::MOUSE_MOVE, a, b, c: 0 }
+            }
+            GuiEvent::MouseDown { x, y, button } | GuiEvent::MouseUp { x, y, button } => {
+                let (a, b) = relative(x, y);
+                let pressed = if matches!(event, GuiEvent::MouseDown { .. }) { 0x100 } else { 0 };
+                WinEvent { kind: event_kind::MOUSE_BUTTON, a, b, c: button_bit(button) as u32 | pressed }
+            }
+            GuiEvent::Wheel { x, y, delta } => {
+                let (a, b) = relative(x, y);
+                WinEvent { kind: event_kind::WHEEL, a, b, c: delta as u32 }
+            }
+            _ => return,
+        };
+        super::server::post(id, win_event);
     }
 
     /// Save pixels under cursor from front buffer
@@ -367,28 +956,71 @@ impl Desktop {
     fn render_to_back_buffer(&self, back_buffer: &mut Framebuffer) {
         let theme = theme::current();
 
-        // Clear background
-        back_buffer.clear(theme.desktop_bg);
+        // Clear background (wallpaper or flat theme color)
+        match self.wallpaper {
+            Some(ref wallpaper) => {
+                let screen = Rect::new(0, 0, self.screen_width, self.screen_height);
+                wallpaper.draw(back_buffer, screen, theme.desktop_bg);
+            }
+            None => back_buffer.clear(theme.desktop_bg),
+        }
 
-        // Draw windows back to front
-        for i in (0..self.window_count).rev() {
-            let slot = self.z_order[i];
-            if let Some(ref window) = self.windows[slot] {
-                if window.flags.visible {
-                    window.draw(back_buffer);
+        // Draw windows back to front (toasts last, so they stay on top)
+        for toast_pass in [false, true] {
+            for i in (0..self.window_count).rev() {
+                let slot = self.z_order[i];
+                if let Some(window) = self.windows.get(slot) {
+                    if window.flags.visible && self.toast_for(window.id).is_some() == toast_pass {
+                        window.draw(back_buffer);
 
-                    // Draw window content based on title
-                    self.draw_window_content(back_buffer, window);
+                        // Draw window content based on title
+                        self.draw_window_content(back_buffer, window);
+                    }
                 }
             }
         }
+
+        self.draw_clock(back_buffer);
+    }
+
+    /// Taskbar clock: local time in the corner the taskbar setting picks
+    fn draw_clock(&self, fb: &mut Framebuffer) {
+        let settings = crate::settings::get();
+        if !settings.taskbar_visible {
+            return;
+        }
+        let theme = theme::current();
+        let now = crate::time::local_now();
+        let mut text = String::new();
+        let _ = write!(text, "{:02}:{:02}", now.hour, now.minute);
+
+        let pad = 4;
+        let width = (text.len() * super::font::FONT_WIDTH) as u32 + pad * 2;
+        let height = super::font::FONT_HEIGHT as u32 + pad * 2;
+        let x = self.screen_width.saturating_sub(width) as i32;
+        let y = match settings.taskbar_position {
+            crate::settings::TaskbarPosition::Top => 0,
+            crate::settings::TaskbarPosition::Bottom => self.screen_height.saturating_sub(height) as i32,
+        };
+        fb.fill_rect(x, y, width, height, theme.title_active);
+        fb.draw_string(x + pad as i32, y + pad as i32, &text, theme.title_text_active, None);
     }
 
     /// Draw content for a window based on its type
     fn draw_window_content(&self, fb: &mut Framebuffer, window: &Window) {
+        if let Some(toast) = self.toast_for(window.id) {
+            self.draw_toast_content(fb, window, toast);
+            return;
+        }
+        if super::server::draw(fb, window) {
+            return;
+        }
+
         let title = window.title();
 
-        if title.contains("Welcome") {
+        if title == WindowKind::TestPattern.title() {
+            self.test_pattern.draw(fb, window.content_rect_abs());
+        } else if title.contains("Welcome") {
             self.draw_welcome_content(fb, window);
         } else if title.contains("Terminal") {
             self.draw_terminal_content(fb, window);
@@ -418,17 +1050,29 @@ impl Desktop {
 
         // Render from heap-allocated terminal state
         if let Some(ref term) = self.terminal {
-            for (i, line) in term.lines().iter().enumerate() {
-                window.draw_text_color(fb, 8, 8 + (i as i32 * 16), line, green, bg);
-            }
+            term.view().draw(fb, content, |fb, origin| {
+                let clip = fb.clip();
+                let line_height = TERM_LINE_HEIGHT as i32;
+                let x = origin.x + TERM_MARGIN as i32;
+                let line_y = |i: usize| origin.y + TERM_MARGIN as i32 + i as i32 * line_height;
+
+                // Only the lines in view (the scrollback is long)
+                for (i, line) in term.lines().iter().enumerate() {
+                    let y = line_y(i);
+                    if y + line_height > clip.y && y < clip.bottom() {
+                        fb.draw_string(x, y, line, green, Some(bg));
+                    }
+                }
 
-            let input_y = 8 + (term.lines().len() as i32 * 16);
-            window.draw_text_color(fb, 8, input_y, "> ", prompt_color, bg);
-            window.draw_text_color(fb, 24, input_y, term.input(), green, bg);
+                let input_y = line_y(term.lines().len());
+                let input_x = x + (term.prompt().len() as i32 * 8);
+                fb.draw_string(x, input_y, term.prompt(), prompt_color, Some(bg));
+                fb.draw_string(input_x, input_y, term.input(), green, Some(bg));
 
-            // Blinking cursor
-            let cursor_x = 24 + (term.input().len() as i32 * 8);
-            window.draw_text_color(fb, cursor_x, input_y, "_", green, bg);
+                // Underline cursor at the edit position
+                let cursor_x = input_x + (term.cursor() as i32 * 8);
+                fb.fill_rect(cursor_x, input_y + 14, 8, 2, green);
+            });
         } else {
             // Fallback if terminal not created
             window.draw_text_color(fb, 8, 8, "Terminal not initialized", green, bg);
@@ -451,69 +1095,383 @@ impl Desktop {
         window.draw_text(fb, 8, 148, "[toml] Cargo.toml", file);
     }
 
+    /// Draw toast content: accent bar plus message
+    fn draw_toast_content(&self, fb: &mut Framebuffer, window: &Window, toast: &Toast) {
+        let theme = theme::current();
+        let content = window.content_rect_abs();
+        fb.fill_rect(content.x, content.y, 4, content.height, toast.notification.severity.accent());
+        window.draw_text(fb, 12, 8, toast.notification.message(), theme.text);
+    }
+
+    // =========================================================================
+    // Notifications (toasts via EventChain)
+    // =========================================================================
+
+    /// Find the toast shown in a window
+    fn toast_for(&self, window_id: u32) -> Option<&Toast> {
+        self.toasts.iter().flatten().find(|t| t.window_id == window_id)
+    }
+
+    /// Find a window slot by ID
+    fn slot_of(&self, window_id: u32) -> Option<usize> {
+        self.windows.iter().find(|(_, w)| w.id == window_id).map(|(slot, _)| slot)
+    }
+
+    /// Show pending notifications and dismiss expired toasts
+    ///
+    /// Called once per frame from the main loop. Toast windows are created
+    /// and destroyed through the WM EventChain like any other window.
+    pub fn update_notifications(&mut self, now_ms: u32) {
+        // Dismiss expired toasts
+        let mut changed = false;
+        for i in 0..MAX_TOASTS {
+            if let Some(toast) = self.toasts[i] {
+                if now_ms.wrapping_sub(toast.expires_at) as i32 >= 0 {
+                    changed |= self.dismiss_toast(i);
+                }
+            }
+        }
+
+        // Show as many pending notifications as there are free toast slots
+        while self.toasts.iter().any(|t| t.is_none()) {
+            let notification = match unsafe { notify::NOTIFICATIONS.pop() } {
+                Some(n) => n,
+                None => break,
+            };
+            changed |= self.show_toast(notification, now_ms);
+        }
+
+        if changed {
+            self.restack_toasts();
+        }
+    }
+
+    /// Create a toast window for a notification
+    fn show_toast(&mut self, notification: Notification, now_ms: u32) -> bool {
+        let idx = match self.toasts.iter().position(|t| t.is_none()) {
+            Some(i) => i,
+            None => return false,
+        };
+
+        let x = self.screen_width as i32 - TOAST_WIDTH as i32 - TOAST_MARGIN;
+        let y = self.screen_height as i32 - TOAST_HEIGHT as i32 - TOAST_MARGIN;
+        let window_id = match self.create_window_with_focus(
+            notification.severity.title(), x, y, TOAST_WIDTH, TOAST_HEIGHT, false,
+        ) {
+            Some(id) => id,
+            None => return false,
+        };
+
+        if let Some(window) = self.get_window(window_id) {
+            window.flags.movable = false;
+            window.flags.resizable = false;
+        }
+
+        self.toasts[idx] = Some(Toast {
+            window_id,
+            notification,
+            expires_at: now_ms.wrapping_add(notification.timeout_ms),
+        });
+        true
+    }
+
+    /// Destroy a toast's window and free its slot
+    fn dismiss_toast(&mut self, idx: usize) -> bool {
+        let toast = match self.toasts[idx] {
+            Some(t) => t,
+            None => return false,
+        };
+
+        if let Some(slot) = self.slot_of(toast.window_id) {
+            if !self.destroy_window(slot) {
+                return false;
+            }
+        }
+
+        self.toasts[idx] = None;
+        true
+    }
+
+    /// Stack visible toasts upward from the bottom-right corner, oldest lowest
+    fn restack_toasts(&mut self) {
+        // Compact so the oldest toasts come first
+        let mut packed = [None; MAX_TOASTS];
+        for (dst, toast) in packed.iter_mut().zip(self.toasts.iter().flatten()) {
+            *dst = Some(*toast);
+        }
+        self.toasts = packed;
+
+        let x = self.screen_width as i32 - TOAST_WIDTH as i32 - TOAST_MARGIN;
+        let mut y = self.screen_height as i32 - TOAST_MARGIN;
+        for i in 0..MAX_TOASTS {
+            let window_id = match self.toasts[i] {
+                Some(t) => t.window_id,
+                None => break,
+            };
+            y -= TOAST_HEIGHT as i32;
+            if let Some(window) = self.get_window(window_id) {
+                window.move_to(x, y);
+            }
+            y -= TOAST_MARGIN;
+        }
+        self.dirty = true;
+    }
+
+    /// Dismiss the toast under the cursor, if any
+    fn dismiss_toast_at(&mut self, x: i32, y: i32) -> bool {
+        for i in 0..MAX_TOASTS {
+            let hit = self.toasts[i]
+                .and_then(|t| self.slot_of(t.window_id))
+                .and_then(|slot| self.windows.get(slot))
+                .map(|w| w.contains(x, y))
+                .unwrap_or(false);
+            if hit && self.dismiss_toast(i) {
+                self.restack_toasts();
+                return true;
+            }
+        }
+        false
+    }
+
     // =========================================================================
     // Terminal Application Methods
     // =========================================================================
 
     /// Create terminal window with heap-allocated state
     pub fn create_terminal_window(&mut self, x: i32, y: i32, w: u32, h: u32) -> Option<u32> {
-        let id = self.create_window("Terminal", x, y, w, h)?;
+        let id = self.create_window(WindowKind::Terminal.title(), x, y, w, h)?;
         self.term_window_id = Some(id);
-        self.terminal = Some(Terminal::new());
+        let mut terminal = Terminal::new();
+        terminal.start_shell();
+        self.terminal = Some(terminal);
         Some(id)
     }
 
-    /// Check if terminal is focused
-    pub fn is_terminal_focused(&self) -> bool {
-        if let (Some(term_id), Some(focus_slot)) = (self.term_window_id, self.focused) {
-            if let Some(ref window) = self.windows[focus_slot] {
-                return window.id == term_id;
+    // =========================================================================
+    // Session
+    // =========================================================================
+
+    /// What built-in window this is (None for toasts and program windows)
+    fn kind_of(&self, window: &Window) -> Option<WindowKind> {
+        if self.toast_for(window.id).is_some() || super::server::is_client(window.id) {
+            return None;
+        }
+        if Some(window.id) == self.term_window_id {
+            return Some(WindowKind::Terminal);
+        }
+        [WindowKind::Welcome, WindowKind::Files, WindowKind::TestPattern].into_iter()
+            .find(|k| window.title() == k.title())
+    }
+
+    /// Built-in windows and their geometry, back to front
+    pub fn session(&self) -> Vec<crate::settings::SessionWindow> {
+        self.z_order[..self.window_count].iter().rev()
+            .filter_map(|&slot| self.windows.get(slot))
+            .filter_map(|w| Some(crate::settings::SessionWindow {
+                kind: self.kind_of(w)?,
+                x: w.bounds.x,
+                y: w.bounds.y,
+                width: w.bounds.width,
+                height: w.bounds.height,
+            }))
+            .collect()
+    }
+
+    /// Recreate saved windows (through the WM chain), back to front
+    ///
+    /// Positions are pulled back on screen in case the resolution shrank.
+    /// Returns how many windows were created.
+    pub fn restore_session(&mut self, windows: &[crate::settings::SessionWindow]) -> usize {
+        let mut created = 0;
+        for w in windows {
+            let (x, y) = wm_events::constrain_position(w.x, w.y, w.width, self.screen_width, self.screen_height);
+            let id = match w.kind {
+                // There is only one terminal
+                WindowKind::Terminal if self.terminal.is_some() => None,
+                WindowKind::Terminal => self.create_terminal_window(x, y, w.width, w.height),
+                kind => self.create_window(kind.title(), x, y, w.width, w.height),
+            };
+            created += id.is_some() as usize;
+        }
+        created
+    }
+
+    /// Show a test pattern, opening its window if needed
+    pub fn show_test_pattern(&mut self, pattern: Pattern) {
+        self.test_pattern.show(pattern);
+        let open = self.windows.values()
+            .find(|w| w.title() == WindowKind::TestPattern.title())
+            .map(|w| w.id);
+        match open.and_then(|id| self.slot_of(id)) {
+            Some(slot) => self.focus_window(slot),
+            None => {
+                self.create_window(WindowKind::TestPattern.title(), 120, 80, 360, 260);
             }
         }
-        false
+        self.dirty = true;
     }
 
-    /// Terminal key input
-    pub fn term_key_input(&mut self, c: char) {
-        if let Some(ref mut term) = self.terminal {
-            term.key_input(c);
-            self.dirty = true;
+    /// Close the windows of programs that have exited
+    ///
+    /// Each goes through the WM destroy chain like any other close.
+    pub fn close_orphaned_windows(&mut self) {
+        for pid in crate::exec::take_exited() {
+            for slot in 0..MAX_WINDOWS {
+                if self.windows.get(slot).is_some_and(|w| w.owner == Some(pid)) {
+                    self.destroy_window(slot);
+                }
+            }
         }
     }
 
-    /// Terminal backspace
-    pub fn term_backspace(&mut self) {
+    /// Close every window through the WM chain (shutdown)
+    ///
+    /// Returns how many windows refused to close.
+    pub fn close_all_windows(&mut self) -> usize {
+        (0..MAX_WINDOWS)
+            .filter(|&slot| self.windows.contains(slot) && !self.destroy_window(slot))
+            .count()
+    }
+
+    /// Show output from user programs in the terminal
+    pub fn term_poll(&mut self) {
         if let Some(ref mut term) = self.terminal {
-            term.backspace();
-            self.dirty = true;
+            if term.poll() {
+                self.dirty = true;
+            }
         }
     }
 
-    /// Terminal enter
-    pub fn term_enter(&mut self) {
-        if let Some(ref mut term) = self.terminal {
-            term.enter();
+    // =========================================================================
+    // Wallpaper
+    // =========================================================================
+
+    /// Load a BMP wallpaper from the VFS
+    pub fn load_wallpaper(&mut self, path: &str, mode: WallpaperMode) -> Result<(), &'static str> {
+        let wallpaper = Wallpaper::load(path, mode)?;
+        self.set_wallpaper(Some(wallpaper));
+        Ok(())
+    }
+
+    /// Set or clear the wallpaper
+    pub fn set_wallpaper(&mut self, wallpaper: Option<Wallpaper>) {
+        self.wallpaper = wallpaper;
+        self.dirty = true;
+    }
+
+    /// Change how the current wallpaper is placed
+    pub fn set_wallpaper_mode(&mut self, mode: WallpaperMode) {
+        if let Some(ref mut wallpaper) = self.wallpaper {
+            wallpaper.set_mode(mode);
             self.dirty = true;
         }
     }
 
+    // =========================================================================
+    // Screensaver (via EventChain)
+    // =========================================================================
+
+    /// Get the screensaver for configuration
+    pub fn screensaver(&mut self) -> &mut Screensaver {
+        &mut self.screensaver
+    }
+
+    /// Record user input
+    ///
+    /// Returns true if the input woke the screensaver, in which case the
+    /// caller should swallow it rather than deliver it to a window.
+    pub fn note_input(&mut self, now_ms: u32) -> bool {
+        if self.screensaver.is_active() {
+            let idle = self.screensaver.idle_ms(now_ms);
+            if WmEventDispatcher::dispatch_screensaver(false, idle) {
+                self.screensaver.deactivate(now_ms);
+
+                // Everything on screen is stale: redraw all windows and
+                // don't restore the pixels saved under the old cursor
+                self.cursor_save_x = -1;
+                self.cursor_save_y = -1;
+                self.dirty = true;
+            }
+            return true;
+        }
+
+        self.screensaver.note_input(now_ms);
+        false
+    }
+
+    /// Start the screensaver once the idle timeout has elapsed
+    pub fn update_idle(&mut self, now_ms: u32) {
+        if !self.screensaver.should_activate(now_ms) {
+            return;
+        }
+
+        let idle = self.screensaver.idle_ms(now_ms);
+        if !WmEventDispatcher::dispatch_screensaver(true, idle) {
+            // Vetoed; wait another full timeout before asking again
+            self.screensaver.note_input(now_ms);
+            return;
+        }
+
+        // Abandon any drag in progress so it doesn't resume on wake
+        self.dragging = None;
+        self.screensaver.activate(now_ms, self.screen_width, self.screen_height);
+    }
+
     /// Draw with double buffering for windows, direct draw for cursor
     pub fn draw(&mut self, back_buffer: &mut Framebuffer, front_buffer: &mut Framebuffer) {
+        let now = crate::arch::x86::pit::uptime_ms();
+        let frame_start_ns = crate::time::now_ns();
+
+        // Screensaver takes over the whole screen while active
+        if self.screensaver.is_active() {
+            if self.screensaver.draw_frame(back_buffer, now) {
+                front_buffer.copy_from(back_buffer);
+                self.profiler.note_blit(front_buffer.width, front_buffer.height, front_buffer.bpp);
+            }
+            self.profiler.end_frame(frame_start_ns);
+            return;
+        }
+
         // Step 1: Restore old cursor area on front buffer (software cursor only)
-        if !self.hw_cursor {
+        if !self.hw_cursor && self.cursor_save_x >= 0 {
             self.restore_cursor_area(front_buffer);
+            self.profiler.note_blit(CURSOR_WIDTH, CURSOR_HEIGHT, front_buffer.bpp);
+        }
+
+        // The taskbar clock changes once a minute
+        let minute = crate::time::local() / 60;
+        if minute != self.clock_minute {
+            self.clock_minute = minute;
+            self.dirty = true;
         }
 
         // Step 2: If windows changed, re-render to back buffer and copy
+        let mut presented = false;
         if self.dirty {
             self.render_to_back_buffer(back_buffer);
+            let render_us = crate::time::now_ns().saturating_sub(frame_start_ns) / 1000;
+            if render_us > SLOW_RENDER_MS as u64 * 1000 {
+                crate::klog_limited!(Debug, "gui", "slow render: {}.{:03} ms", render_us / 1000, render_us % 1000);
+            }
+            // Pace presents to the display refresh to avoid tearing
+            if self.vsync {
+                crate::drivers::ati_rage::wait_for_vblank();
+            }
             front_buffer.copy_from(back_buffer);
+            self.profiler.note_blit(front_buffer.width, front_buffer.height, front_buffer.bpp);
             self.dirty = false;
+            presented = true;
+        }
+
+        // Profiler overlay sits on the front buffer, under the cursor
+        if self.profiler.end_frame(frame_start_ns) || (presented && self.profiler.is_enabled()) {
+            self.profiler.draw_overlay(front_buffer);
         }
 
         // Step 3: Draw cursor directly to front buffer (software cursor only)
         if !self.hw_cursor {
             self.draw_cursor(front_buffer);
+            self.profiler.note_blit(CURSOR_WIDTH, CURSOR_HEIGHT, front_buffer.bpp);
         }
     }
 
@@ -522,6 +1480,11 @@ impl Desktop {
         self.dirty = true;
     }
 
+    /// Is a repaint pending?
+    pub fn needs_redraw(&self) -> bool {
+        self.dirty
+    }
+
     /// Get mouse position
     pub fn mouse_pos(&self) -> (i32, i32) {
         (self.mouse_x, self.mouse_y)
@@ -530,20 +1493,13 @@ impl Desktop {
     /// Get focused window ID
     pub fn focused_window(&self) -> Option<u32> {
         self.focused.and_then(|slot| {
-            self.windows[slot].as_ref().map(|w| w.id)
+            self.windows.get(slot).map(|w| w.id)
         })
     }
 
     /// Get a window by ID for content drawing
     pub fn get_window(&mut self, id: u32) -> Option<&mut Window> {
-        for window in self.windows.iter_mut() {
-            if let Some(ref mut w) = window {
-                if w.id == id {
-                    return Some(w);
-                }
-            }
-        }
-        None
+        self.windows.values_mut().find(|w| w.id == id)
     }
 
     /// Get screen dimensions
@@ -559,21 +1515,48 @@ impl Desktop {
     ///
     /// Dispatches through WM EventChain for validation and audit.
     pub fn create_window(&mut self, title: &str, x: i32, y: i32, width: u32, height: u32) -> Option<u32> {
+        self.create_window_with_focus(title, x, y, width, height, true)
+    }
+
+    /// Create a new window, optionally without taking focus
+    ///
+    /// Unfocused windows are placed at the front of the z-order but leave
+    /// keyboard focus where it was (used for toasts).
+    pub fn create_window_with_focus(
+        &mut self,
+        title: &str,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        focus: bool,
+    ) -> Option<u32> {
         // Dispatch through EventChain for validation
         if !WmEventDispatcher::dispatch_create(x, y, width, height) {
             return None;
         }
 
-        // Find free slot
-        let slot = self.windows.iter().position(|w| w.is_none())?;
+        // Windows made on behalf of a user program count against its limit
+        let owner = crate::exec::current_pid();
+        if let Some(task) = owner.and_then(crate::exec::task_of) {
+            crate::sched::rlimit::charge_window(task).ok()?;
+        }
 
         // Generate window ID
         let id = self.next_id;
         self.next_id += 1;
 
         // Create the window
-        let window = Window::new(id, title, x, y, width, height);
-        self.windows[slot] = Some(window);
+        let mut window = Window::new(id, title, x, y, width, height);
+        window.owner = owner;
+        let Some(key) = self.windows.insert(window) else {
+            // No free slot: hand back the charge for a window that never was
+            if let Some(task) = owner.and_then(crate::exec::task_of) {
+                crate::sched::rlimit::release_window(task);
+            }
+            return None;
+        };
+        let slot = key.index();
 
         // Add to z-order
         if self.window_count < MAX_WINDOWS {
@@ -581,14 +1564,18 @@ impl Desktop {
             self.window_count += 1;
         }
 
-        // Dispatch focus change through EventChain
-        let old_focus = self.focused.and_then(|s| {
-            self.windows[s].as_ref().map(|w| w.id)
-        });
-        WmEventDispatcher::dispatch_focus_change(old_focus, Some(id));
+        if focus {
+            // Dispatch focus change through EventChain
+            let old_focus = self.focused.and_then(|s| {
+                self.windows.get(s).map(|w| w.id)
+            });
+            WmEventDispatcher::dispatch_focus_change(old_focus, Some(id));
 
-        // Set as focused (top of z-order)
-        self.focus_window(slot);
+            // Set as focused (top of z-order)
+            self.focus_window(slot);
+        } else {
+            self.bring_to_front(slot);
+        }
 
         self.dirty = true;
         Some(id)
@@ -602,13 +1589,13 @@ impl Desktop {
             return false;
         }
 
-        let window_id = match &self.windows[slot] {
-            Some(w) => w.id,
+        let (window_id, owner) = match self.windows.get(slot) {
+            Some(w) => (w.id, w.owner),
             None => return false,
         };
 
         // Dispatch through EventChain
-        if !WmEventDispatcher::dispatch_destroy(window_id) {
+        if !WmEventDispatcher::dispatch_destroy(window_id, owner) {
             return false;
         }
 
@@ -626,24 +1613,36 @@ impl Desktop {
             self.window_count = self.window_count.saturating_sub(1);
         }
 
-        self.windows[slot] = None;
+        if let Some(task) = self.windows.get(slot)
+            .and_then(|w| w.owner)
+            .and_then(crate::exec::task_of)
+        {
+            crate::sched::rlimit::release_window(task);
+        }
+        super::server::forget(window_id);
+        self.windows.remove(slot);
         self.dirty = true;
         true
     }
 
+    /// Destroy a window by ID
+    pub fn destroy_window_id(&mut self, window_id: u32) -> bool {
+        self.slot_of(window_id).is_some_and(|slot| self.destroy_window(slot))
+    }
+
     // =========================================================================
     // Focus Management (via EventChain)
     // =========================================================================
 
     /// Focus a window by slot index
     fn focus_window(&mut self, slot: usize) {
-        if slot >= MAX_WINDOWS || self.windows[slot].is_none() {
+        if slot >= MAX_WINDOWS || !self.windows.contains(slot) {
             return;
         }
 
-        let new_id = self.windows[slot].as_ref().unwrap().id;
+        let new_id = self.windows.get(slot).unwrap().id;
         let old_id = self.focused.and_then(|s| {
-            self.windows[s].as_ref().map(|w| w.id)
+            self.windows.get(s).map(|w| w.id)
         });
 
         // Dispatch through EventChain (could be blocked by policy)
@@ -653,13 +1652,13 @@ impl Desktop {
 
         // Unfocus old window
         if let Some(old_slot) = self.focused {
-            if let Some(ref mut old_win) = self.windows[old_slot] {
+            if let Some(old_win) = self.windows.get_mut(old_slot) {
                 old_win.flags.focused = false;
             }
         }
 
         // Focus new window
-        if let Some(ref mut win) = self.windows[slot] {
+        if let Some(win) = self.windows.get_mut(slot) {
             win.flags.focused = true;
         }
         self.focused = Some(slot);
@@ -676,13 +1675,13 @@ impl Desktop {
 
     /// Bring a window to the front of the z-order
     fn bring_to_front(&mut self, slot: usize) {
-        let window_id = match &self.windows[slot] {
-            Some(w) => w.id,
+        let (window_id, owner) = match self.windows.get(slot) {
+            Some(w) => (w.id, w.owner),
             None => return,
         };
 
         // Dispatch through EventChain
-        if !WmEventDispatcher::dispatch_z_order_change(window_id, z_order::BRING_TO_FRONT) {
+        if !WmEventDispatcher::dispatch_z_order_change(window_id, owner, z_order::BRING_TO_FRONT) {
             return;
         }
 
@@ -713,13 +1712,48 @@ impl Desktop {
 
     /// Called when a drag operation completes
     fn complete_drag(&mut self, slot: usize, old_x: i32, old_y: i32, new_x: i32, new_y: i32) {
-        let window_id = match &self.windows[slot] {
-            Some(w) => w.id,
+        self.move_window(slot, old_x, old_y, new_x, new_y);
+    }
+
+    /// Move a window through the move event, which may pull it back on
+    /// screen or refuse (putting it back at the old position)
+    fn move_window(&mut self, slot: usize, old_x: i32, old_y: i32, new_x: i32, new_y: i32) {
+        let (window_id, owner, width) = match self.windows.get(slot) {
+            Some(w) => (w.id, w.owner, w.bounds.width),
             None => return,
         };
 
-        // Dispatch move event for audit
-        WmEventDispatcher::dispatch_move(window_id, old_x, old_y, new_x, new_y);
+        let screen = (self.screen_width, self.screen_height);
+        let (x, y) = WmEventDispatcher::dispatch_move(window_id, owner, old_x, old_y, new_x, new_y, width, screen)
+            .unwrap_or((old_x, old_y));
+        if let Some(window) = self.windows.get_mut(slot) {
+            if (window.bounds.x, window.bounds.y) != (x, y) {
+                window.move_to(x, y);
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Bring every window fully on screen (as far as its size allows)
+    ///
+    /// Recovers windows left mostly off-screen, e.g. after a resolution
+    /// change, without needing a mouse. Returns how many moved.
+    pub fn gather_windows(&mut self) -> usize {
+        let (screen_width, screen_height) = (self.screen_width as i32, self.screen_height as i32);
+        let mut moved = 0;
+        for slot in 0..MAX_WINDOWS {
+            let (x, y, width, height) = match self.windows.get(slot) {
+                Some(w) => (w.bounds.x, w.bounds.y, w.bounds.width as i32, w.bounds.height as i32),
+                None => continue,
+            };
+            let new_x = x.min(screen_width - width).max(0);
+            let new_y = y.min(screen_height - height).max(0);
+            if (new_x, new_y) != (x, y) {
+                self.move_window(slot, x, y, new_x, new_y);
+                moved += 1;
+            }
+        }
+        moved
     }
 
     // =========================================================================
@@ -727,24 +1761,32 @@ impl Desktop {
     // =========================================================================
 
     /// Handle mouse button press/release
-    pub fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool) {
-        let bit = match button {
-            MouseButton::Left => 0x01,
-            MouseButton::Right => 0x02,
-            MouseButton::Middle => 0x04,
-        };
+    pub fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool, time_ms: u32) {
+        let bit = button_bit(button);
+        let (x, y) = (self.mouse_x, self.mouse_y);
+
+        // Buttons in a program window's content go to the program too:
+        // releases here, presses below once the window is focused
+        if !pressed {
+            self.post_pointer(InputEvent::new(GuiEvent::MouseUp { x, y, button }, time_ms));
+        }
 
         if pressed {
             self.mouse_buttons |= bit;
 
             if button == MouseButton::Left {
+                // Clicking a toast dismisses it
+                if self.dismiss_toast_at(self.mouse_x, self.mouse_y) {
+                    return;
+                }
+
                 // Check for window click (front to back in z-order)
                 // First, find the clicked window and gather needed data
                 let mut click_info: Option<(usize, bool, i32, i32)> = None;
 
                 for i in 0..self.window_count {
                     let slot = self.z_order[i];
-                    if let Some(ref window) = self.windows[slot] {
+                    if let Some(window) = self.windows.get(slot) {
                         if window.contains(self.mouse_x, self.mouse_y) {
                             let in_title = window.in_title_bar(self.mouse_x, self.mouse_y);
                             click_info = Some((slot, in_title, window.bounds.x, window.bounds.y));
@@ -760,6 +1802,28 @@ impl Desktop {
                         self.focus_window(slot);
                     }
 
+                    self.post_pointer(InputEvent::new(GuiEvent::MouseDown { x, y, button }, time_ms));
+
+                    // Test pattern clicks pick a color or change the pattern
+                    if !in_title {
+                        let pattern_area = self.windows.get(slot)
+                            .filter(|w| w.title() == WindowKind::TestPattern.title())
+                            .map(|w| w.content_rect_abs());
+                        if let Some(area) = pattern_area {
+                            self.dirty |= self.test_pattern.click(area, x, y);
+                        }
+                    }
+
+                    // Terminal scrollbar clicks page through the scrollback
+                    if !in_title && self.windows.get(slot).map(|w| w.id) == self.term_window_id {
+                        let area = self.windows.get(slot).map(|w| w.content_rect_abs());
+                        if let (Some(term), Some(area)) = (self.terminal.as_mut(), area) {
+                            if term.scroll(|v| v.handle_click(area, x, y)) {
+                                self.dirty = true;
+                            }
+                        }
+                    }
+
                     // Check if in title bar for drag
                     if in_title {
                         self.dragging = Some(slot);
@@ -771,6 +1835,8 @@ impl Desktop {
                         );
                     }
                 }
+            } else {
+                self.post_pointer(InputEvent::new(GuiEvent::MouseDown { x, y, button }, time_ms));
             }
         } else {
             self.mouse_buttons &= !bit;
@@ -779,8 +1845,7 @@ impl Desktop {
             if button == MouseButton::Left {
                 if let Some(slot) = self.dragging {
                     // Extract position before mutable borrow
-                    let new_pos = self.windows[slot]
-                        .as_ref()
+                    let new_pos = self.windows.get(slot)
                         .map(|w| (w.bounds.x, w.bounds.y));
 
                     if let Some((new_x, new_y)) = new_pos {
@@ -799,6 +1864,15 @@ impl Desktop {
     }
 }
 
+/// Button bit as in the mouse drivers and `event_kind::MOUSE_BUTTON`
+fn button_bit(button: MouseButton) -> u8 {
+    match button {
+        MouseButton::Left => 0x01,
+        MouseButton::Right => 0x02,
+        MouseButton::Middle => 0x04,
+    }
+}
+
 // =============================================================================
 // Global Instance
 // =============================================================================
@@ -825,3 +1899,18 @@ pub fn init_with_hw_cursor(width: u32, height: u32, hw_cursor: bool) {
 pub fn get() -> Option<&'static mut Desktop> {
     unsafe { DESKTOP.as_mut() }
 }
+
+/// Window table row for /proc/slots
+///
+/// Windows whose owner has exited are leaked until `close_orphaned_windows`
+/// gets to them; so is any slot the z-order has lost track of.
+pub fn slot_report(out: &mut String) {
+    let Some(desktop) = (unsafe { (*core::ptr::addr_of!(DESKTOP)).as_ref() }) else {
+        return;
+    };
+    let orphaned = desktop.windows.values()
+        .filter(|w| w.owner.is_some_and(|pid| crate::exec::task_of(pid).is_none()))
+        .count();
+    let unordered = desktop.windows.len().abs_diff(desktop.window_count);
+    slotmap::write_row(out, "windows", desktop.windows.stats(), orphaned + unordered);
+}
@@ -0,0 +1,166 @@
+//! Color Packing and Dithering
+//!
+//! Converts 24-bit colors to the packed formats used by low-depth
+//! framebuffers:
+//!
+//! - 16bpp: RGB565
+//! - 8bpp: palettized, with a fixed RGB332 palette loaded into the DAC
+//!
+//! Truncating to these depths bands gradients and the theme's pale tints,
+//! so packing can optionally apply a 4x4 ordered (Bayer) dither keyed on
+//! the pixel position. Monochrome mode reduces everything to black and
+//! white for 2-color output. 24/32bpp targets are never dithered.
+//! Undithered packing lives in `pixel`.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use super::Color;
+use super::pixel::PixelFormat;
+
+const RGB565: PixelFormat = PixelFormat::Rgb565;
+const RGB332: PixelFormat = PixelFormat::Indexed8;
+
+/// Dithering applied when packing to 8/16bpp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DitherMode {
+    /// Truncate to the nearest lower representable color
+    None = 0,
+    /// 4x4 ordered dither between the two nearest representable colors
+    Ordered = 1,
+    /// Ordered dither of luminance to pure black/white
+    Monochrome = 2,
+}
+
+impl DitherMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Ordered => "ordered",
+            Self::Monochrome => "mono",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "ordered" => Some(Self::Ordered),
+            "mono" => Some(Self::Monochrome),
+            _ => None,
+        }
+    }
+}
+
+/// Current dithering mode
+static MODE: AtomicU8 = AtomicU8::new(DitherMode::Ordered as u8);
+
+/// Set the dithering mode (caller should redraw)
+pub fn set_mode(mode: DitherMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Get the dithering mode
+pub fn mode() -> DitherMode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => DitherMode::Ordered,
+        2 => DitherMode::Monochrome,
+        _ => DitherMode::None,
+    }
+}
+
+/// 4x4 Bayer threshold matrix (0..16)
+const BAYER_4X4: [[u8; 4]; 4] = [
+    [ 0,  8,  2, 10],
+    [12,  4, 14,  6],
+    [ 3, 11,  1,  9],
+    [15,  7, 13,  5],
+];
+
+/// Threshold for a pixel position, scaled to 0..256
+#[inline]
+fn threshold(x: i32, y: i32) -> u32 {
+    BAYER_4X4[(y & 3) as usize][(x & 3) as usize] as u32 * 16 + 8
+}
+
+/// Quantize one channel to `bits` bits, dithered at threshold `t`
+#[inline]
+fn quantize(value: u8, bits: u32, t: u32) -> u32 {
+    let levels = (1u32 << bits) - 1;
+    // Position between the two nearest levels, in 1/256ths
+    let scaled = value as u32 * levels;
+    let base = scaled / 255;
+    let frac = (scaled % 255) * 256 / 255;
+    if frac > t && base < levels { base + 1 } else { base }
+}
+
+/// Reduce a color to black or white by dithered luminance
+#[inline]
+fn monochrome(color: Color, t: u32) -> Color {
+    let luma = (color.r as u32 * 77 + color.g as u32 * 150 + color.b as u32 * 29) >> 8;
+    if luma > t.min(255) { Color::WHITE } else { Color::BLACK }
+}
+
+// =============================================================================
+// 16bpp (RGB565)
+// =============================================================================
+
+/// Pack a color as RGB565, dithered at (x, y) per the current mode
+#[inline]
+pub fn pack_rgb565(color: Color, x: i32, y: i32) -> u16 {
+    match mode() {
+        DitherMode::None => RGB565.pack(color) as u16,
+        DitherMode::Ordered => {
+            let t = threshold(x, y);
+            let r = quantize(color.r, 5, t) as u16;
+            let g = quantize(color.g, 6, t) as u16;
+            let b = quantize(color.b, 5, t) as u16;
+            (r << 11) | (g << 5) | b
+        }
+        DitherMode::Monochrome => RGB565.pack(monochrome(color, threshold(x, y))) as u16,
+    }
+}
+
+// =============================================================================
+// 8bpp (RGB332 palette)
+// =============================================================================
+
+/// Pack a color as an RGB332 palette index, dithered at (x, y)
+#[inline]
+pub fn pack_rgb332(color: Color, x: i32, y: i32) -> u8 {
+    match mode() {
+        DitherMode::None => RGB332.pack(color) as u8,
+        DitherMode::Ordered => {
+            let t = threshold(x, y);
+            let r = quantize(color.r, 3, t) as u8;
+            let g = quantize(color.g, 3, t) as u8;
+            let b = quantize(color.b, 2, t) as u8;
+            (r << 5) | (g << 2) | b
+        }
+        DitherMode::Monochrome => RGB332.pack(monochrome(color, threshold(x, y))) as u8,
+    }
+}
+
+
+/// Load the RGB332 palette into the display DAC
+///
+/// Uses the ATI DAC when the native driver is active, otherwise the
+/// standard VGA DAC ports (6 bits per channel).
+pub fn load_palette() {
+    if let Some(gpu) = crate::drivers::ati_rage::get() {
+        gpu.set_palette(0, &|i| {
+            let c = RGB332.unpack(i as u32);
+            (c.r, c.g, c.b)
+        }, 256);
+        return;
+    }
+
+    unsafe {
+        use crate::arch::x86::io::outb;
+        outb(0x3C8, 0);
+        for i in 0..=255u8 {
+            let c = RGB332.unpack(i as u32);
+            outb(0x3C9, c.r >> 2);
+            outb(0x3C9, c.g >> 2);
+            outb(0x3C9, c.b >> 2);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! GUI Event Loop
+//!
+//! Turns raw input into `GuiEvent`s and routes them:
+//!
+//! - Pointer events go to the window manager (focus, drags, toasts),
+//!   which also queues them on a program window under the pointer; the
+//!   wheel scrolls whatever window is under the pointer
+//! - Key events are queued on the focused window if it takes keys (the
+//!   terminal or a program's window); otherwise they drive navigation
+//!   mode, a keyboard-steered pointer
+//! - `Desktop::pump_events` then hands each window's queue to its content
+//!   handler. Program windows forward to the window server, whose queue
+//!   wakes a program blocked in the WinEvent syscall.
+//!
+//! F11 gathers every window back on screen and F12 toggles the frame
+//! profiler, whatever has focus.
+//!
+//! Any input first passes the screensaver, which swallows the input that
+//! wakes it. Events carry the time their input arrived (`InputEvent`):
+//! keys from the keyboard buffer, pointer samples from the driver's last
+//! packet.
+//!
+//! Key, pointer and wheel input is also handed to the input recorder
+//! (`recorder`), and `replay` feeds a recording back in the same way.
+
+use crate::drivers::keyboard::{BufferedKey, KeyCode};
+use super::recorder::{self, Input};
+use super::{Desktop, GuiEvent, InputEvent, MouseButton};
+
+/// Navigation mode pointer step (pixels per key)
+const NAV_STEP: i32 = 8;
+
+/// Input state carried between frames
+pub struct EventLoop {
+    /// Last pointer sample
+    pointer_x: i32,
+    pointer_y: i32,
+    buttons: u8,
+    /// Keyboard-steered pointer (navigation mode)
+    nav_x: i32,
+    nav_y: i32,
+}
+
+impl EventLoop {
+    /// Start with the pointer at (x, y)
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { pointer_x: x, pointer_y: y, buttons: 0, nav_x: x, nav_y: y }
+    }
+
+    /// Handle a key from the keyboard buffer
+    pub fn key(&mut self, desktop: &mut Desktop, key: &BufferedKey) {
+        recorder::capture(Input::from_key(key), key.time_ms);
+        // Any key wakes the screensaver (and is swallowed)
+        if desktop.note_input(key.time_ms) {
+            return;
+        }
+        self.dispatch(desktop, InputEvent::from_key(key));
+    }
+
+    /// Handle wheel notches turned at `time_ms`
+    pub fn wheel(&mut self, desktop: &mut Desktop, delta: i32, time_ms: u32) {
+        recorder::capture(Input::Wheel { delta }, time_ms);
+        if desktop.note_input(time_ms) {
+            return;
+        }
+        let (x, y) = (self.pointer_x, self.pointer_y);
+        self.dispatch(desktop, InputEvent::new(GuiEvent::Wheel { x, y, delta }, time_ms));
+    }
+
+    /// Handle a pointer sample (position and button bits) taken at `time_ms`
+    ///
+    /// Returns true if the pointer moved, so the caller can move a
+    /// hardware cursor.
+    pub fn pointer(&mut self, desktop: &mut Desktop, x: i32, y: i32, buttons: u8, time_ms: u32) -> bool {
+        let moved = x != self.pointer_x || y != self.pointer_y;
+        let changed = buttons ^ self.buttons;
+        if !moved && changed == 0 {
+            return false;
+        }
+        recorder::capture(Input::Pointer { x, y, buttons }, time_ms);
+
+        // Input that only dismissed the screensaver
+        if desktop.note_input(time_ms) {
+            self.pointer_x = x;
+            self.pointer_y = y;
+            self.buttons = buttons;
+            return false;
+        }
+
+        if moved {
+            self.dispatch(desktop, InputEvent::new(GuiEvent::MouseMove { x, y }, time_ms));
+            self.pointer_x = x;
+            self.pointer_y = y;
+        }
+        for (bit, button) in [(0x01, MouseButton::Left), (0x02, MouseButton::Right), (0x04, MouseButton::Middle)] {
+            if changed & bit == 0 {
+                continue;
+            }
+            let event = if buttons & bit != 0 {
+                GuiEvent::MouseDown { x, y, button }
+            } else {
+                GuiEvent::MouseUp { x, y, button }
+            };
+            self.dispatch(desktop, InputEvent::new(event, time_ms));
+        }
+        self.buttons = buttons;
+        moved
+    }
+
+    /// Feed in a recorded input stamped `time_ms`
+    ///
+    /// Returns true if the pointer moved, as `pointer` does.
+    pub fn replay(&mut self, desktop: &mut Desktop, input: Input, time_ms: u32) -> bool {
+        match input {
+            Input::Key { keycode, ascii, pressed, ctrl, alt } => {
+                self.key(desktop, &BufferedKey { keycode, ascii, pressed, ctrl, alt, time_ms });
+                false
+            }
+            Input::Pointer { x, y, buttons } => self.pointer(desktop, x, y, buttons, time_ms),
+            Input::Wheel { delta } => {
+                self.wheel(desktop, delta, time_ms);
+                false
+            }
+        }
+    }
+
+    /// Route one event
+    pub fn dispatch(&mut self, desktop: &mut Desktop, input: InputEvent) {
+        let time_ms = input.time_ms;
+        match input.event {
+            GuiEvent::MouseMove { x, y } => {
+                desktop.handle_mouse_move(x, y, time_ms);
+                self.nav_x = x;
+                self.nav_y = y;
+            }
+            GuiEvent::MouseDown { button, .. } => desktop.handle_mouse_button(button, true, time_ms),
+            GuiEvent::MouseUp { button, .. } => desktop.handle_mouse_button(button, false, time_ms),
+            GuiEvent::Wheel { .. } => desktop.handle_wheel(input),
+            // F11 recovers off-screen windows from any mode
+            GuiEvent::KeyDown { keycode: KeyCode::F11, .. } => {
+                desktop.gather_windows();
+            }
+            // F12 toggles the frame profiler overlay from any mode
+            GuiEvent::KeyDown { keycode: KeyCode::F12, .. } => desktop.toggle_profiler(),
+            GuiEvent::KeyDown { keycode, .. } => {
+                if !desktop.post_key(input) {
+                    self.navigate(desktop, keycode, time_ms);
+                }
+            }
+            GuiEvent::KeyUp { .. } => {
+                desktop.post_key(input);
+            }
+            GuiEvent::Redraw => desktop.mark_dirty(),
+            GuiEvent::Tick => {
+                desktop.update_idle(time_ms);
+                desktop.update_notifications(time_ms);
+            }
+        }
+    }
+
+    /// Navigation mode: arrows/WASD move the pointer, Enter clicks,
+    /// Space presses
+    fn navigate(&mut self, desktop: &mut Desktop, keycode: KeyCode, time_ms: u32) {
+        let (width, height) = desktop.screen_size();
+        let (x, y) = match keycode {
+            KeyCode::Up | KeyCode::W => (self.nav_x, (self.nav_y - NAV_STEP).max(0)),
+            KeyCode::Down | KeyCode::S => (self.nav_x, (self.nav_y + NAV_STEP).min(height as i32 - 1)),
+            KeyCode::Left | KeyCode::A => ((self.nav_x - NAV_STEP).max(0), self.nav_y),
+            KeyCode::Right | KeyCode::D => ((self.nav_x + NAV_STEP).min(width as i32 - 1), self.nav_y),
+            KeyCode::Enter | KeyCode::KeypadEnter => {
+                let (x, y) = (self.nav_x, self.nav_y);
+                self.dispatch(desktop, InputEvent::new(GuiEvent::MouseDown { x, y, button: MouseButton::Left }, time_ms));
+                self.dispatch(desktop, InputEvent::new(GuiEvent::MouseUp { x, y, button: MouseButton::Left }, time_ms));
+                return;
+            }
+            KeyCode::Space => {
+                let (x, y) = (self.nav_x, self.nav_y);
+                self.dispatch(desktop, InputEvent::new(GuiEvent::MouseDown { x, y, button: MouseButton::Left }, time_ms));
+                return;
+            }
+            _ => return,
+        };
+        self.dispatch(desktop, InputEvent::new(GuiEvent::MouseMove { x, y }, time_ms));
+    }
+}
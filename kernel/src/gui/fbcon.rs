@@ -0,0 +1,194 @@
+//! Framebuffer Console
+//!
+//! Text console drawn into a linear framebuffer with the 8x16 GUI font.
+//! `vga::Writer` delegates to it in graphics mode (boot messages, panics).
+//! Cells take a foreground/background pair from the 16-color VGA palette,
+//! so text-mode color attributes carry over. Scrolling moves the screen up
+//! with the ATI 2D engine when it drives this framebuffer, otherwise with
+//! 32-bit word copies.
+
+use super::{font, Color, Framebuffer};
+
+/// Standard VGA text palette, indexed by attribute nibble
+pub const VGA_PALETTE: [Color; 16] = [
+    Color::rgb(0x00, 0x00, 0x00), // Black
+    Color::rgb(0x00, 0x00, 0xAA), // Blue
+    Color::rgb(0x00, 0xAA, 0x00), // Green
+    Color::rgb(0x00, 0xAA, 0xAA), // Cyan
+    Color::rgb(0xAA, 0x00, 0x00), // Red
+    Color::rgb(0xAA, 0x00, 0xAA), // Magenta
+    Color::rgb(0xAA, 0x55, 0x00), // Brown
+    Color::rgb(0xAA, 0xAA, 0xAA), // Light gray
+    Color::rgb(0x55, 0x55, 0x55), // Dark gray
+    Color::rgb(0x55, 0x55, 0xFF), // Light blue
+    Color::rgb(0x55, 0xFF, 0x55), // Light green
+    Color::rgb(0x55, 0xFF, 0xFF), // Light cyan
+    Color::rgb(0xFF, 0x55, 0x55), // Light red
+    Color::rgb(0xFF, 0x55, 0xFF), // Pink
+    Color::rgb(0xFF, 0xFF, 0x55), // Yellow
+    Color::rgb(0xFF, 0xFF, 0xFF), // White
+];
+
+/// Glyph cell size
+const CELL_WIDTH: u32 = font::FONT_WIDTH as u32;
+const CELL_HEIGHT: u32 = font::FONT_HEIGHT as u32;
+
+/// Framebuffer text console
+pub struct FbCon {
+    fb: Framebuffer,
+    /// Framebuffer physical address (to match the GPU's)
+    addr: u32,
+    /// Size in cells
+    cols: u32,
+    rows: u32,
+    /// Cursor cell
+    col: u32,
+    row: u32,
+    fg: Color,
+    bg: Color,
+}
+
+impl FbCon {
+    /// Create a console over a framebuffer at physical `addr` (`bpp` in
+    /// bytes per pixel)
+    ///
+    /// # Safety
+    /// The framebuffer must match the given geometry
+    pub unsafe fn new(addr: u32, width: u32, height: u32, bpp: u32, pitch: u32) -> Self {
+        // Before paging this is the physical address; `remap` fixes it up
+        let virt = crate::mm::iomap_framebuffer(addr, pitch * height).unwrap_or(addr);
+        Self {
+            fb: Framebuffer::new(virt as *mut u8, width, height, bpp, pitch),
+            addr,
+            cols: (width / CELL_WIDTH).max(1),
+            rows: (height / CELL_HEIGHT).max(1),
+            col: 0,
+            row: 0,
+            fg: VGA_PALETTE[7],
+            bg: VGA_PALETTE[0],
+        }
+    }
+
+    /// Move to a device window mapping once paging is on
+    pub fn remap(&mut self) -> Result<(), &'static str> {
+        let fb = &self.fb;
+        let virt = crate::mm::iomap_framebuffer(self.addr, fb.pitch * fb.height)?;
+        self.fb = unsafe { Framebuffer::new(virt as *mut u8, fb.width, fb.height, fb.bpp, fb.pitch) };
+        Ok(())
+    }
+
+    /// Size in cells (columns, rows)
+    pub fn size(&self) -> (u32, u32) {
+        (self.cols, self.rows)
+    }
+
+    /// Is the cursor at the start of a line?
+    pub fn at_line_start(&self) -> bool {
+        self.col == 0
+    }
+
+    /// Set colors from a VGA attribute byte (background << 4 | foreground)
+    pub fn set_attribute(&mut self, attribute: u8) {
+        self.fg = VGA_PALETTE[(attribute & 0x0F) as usize];
+        self.bg = VGA_PALETTE[(attribute >> 4) as usize];
+    }
+
+    /// Clear to the background color and home the cursor
+    pub fn clear(&mut self) {
+        self.fb.clear(self.bg);
+        self.col = 0;
+        self.row = 0;
+    }
+
+    /// Write a byte, interpreting newline, carriage return and tab
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.col = 0,
+            b'\t' => {
+                let spaces = 8 - (self.col % 8);
+                for _ in 0..spaces {
+                    self.write_byte(b' ');
+                }
+            }
+            byte => {
+                if self.col >= self.cols {
+                    self.new_line();
+                }
+                self.draw_cell(self.col, self.row, byte);
+                self.col += 1;
+            }
+        }
+    }
+
+    /// Erase the character before the cursor (stops at the line start)
+    pub fn backspace(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+            self.draw_cell(self.col, self.row, b' ');
+        }
+    }
+
+    fn draw_cell(&mut self, col: u32, row: u32, byte: u8) {
+        let x = (col * CELL_WIDTH) as i32;
+        let y = (row * CELL_HEIGHT) as i32;
+        self.fb.draw_char(x, y, byte as char, self.fg, Some(self.bg));
+    }
+
+    fn new_line(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    /// Move everything up one text row and blank the last
+    fn scroll(&mut self) {
+        let kept = (self.rows - 1) * CELL_HEIGHT;
+
+        match self.engine() {
+            Some(gpu) => {
+                gpu.copy_rect(0, CELL_HEIGHT, 0, 0, self.fb.width, kept);
+                gpu.wait_for_idle();
+            }
+            None => unsafe {
+                let base = self.fb.buffer();
+                let row_bytes = (self.fb.pitch * CELL_HEIGHT) as usize;
+                memcpy32(base, base.add(row_bytes), (self.fb.pitch * kept) as usize);
+            },
+        }
+
+        self.fb.fill_rect(0, kept as i32, self.fb.width, CELL_HEIGHT, self.bg);
+    }
+
+    /// The ATI 2D engine, if it is driving this exact framebuffer
+    fn engine(&self) -> Option<&'static mut crate::drivers::ati_rage::AtiRage> {
+        crate::drivers::ati_rage::get().filter(|gpu| {
+            gpu.is_initialized()
+                && gpu.framebuffer_addr() == self.addr
+                && gpu.width() == self.fb.width
+                && gpu.pitch() == self.fb.pitch
+        })
+    }
+}
+
+/// Forward copy in 32-bit words (any tail bytes singly)
+///
+/// Framebuffer memory is much slower to access per byte than per word.
+/// Safe for overlapping ranges when `dst` is below `src`.
+///
+/// # Safety
+/// Both ranges must be valid for `bytes` bytes
+pub(crate) unsafe fn memcpy32(dst: *mut u8, src: *const u8, bytes: usize) {
+    let words = bytes / 4;
+    let dst_words = dst as *mut u32;
+    let src_words = src as *const u32;
+    for i in 0..words {
+        dst_words.add(i).write_volatile(src_words.add(i).read_volatile());
+    }
+    for i in words * 4..bytes {
+        dst.add(i).write_volatile(src.add(i).read_volatile());
+    }
+}
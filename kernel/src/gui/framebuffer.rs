@@ -2,7 +2,8 @@
 //!
 //! Low-level graphics primitives for the linear framebuffer.
 
-use super::{Color, Rect, Point, font};
+use super::{Color, Rect, Point, font, glyph_cache};
+use super::pixel::PixelFormat;
 
 /// Framebuffer for direct pixel manipulation
 pub struct Framebuffer {
@@ -12,10 +13,13 @@ pub struct Framebuffer {
     pub width: u32,
     /// Height in pixels
     pub height: u32,
-    /// Bytes per pixel (2, 3, or 4)
+    /// Bytes per pixel (see `PixelFormat::from_bytes`)
     pub bpp: u32,
     /// Bytes per scanline
     pub pitch: u32,
+    /// Drawing outside this rectangle is discarded (the whole screen
+    /// unless a scroll view or window narrows it)
+    clip: Rect,
 }
 
 impl Framebuffer {
@@ -30,44 +34,38 @@ impl Framebuffer {
             height,
             bpp,
             pitch,
+            clip: Rect::new(0, 0, width, height),
         }
     }
     
+    /// Start of pixel memory
+    pub fn buffer(&self) -> *mut u8 {
+        self.buffer
+    }
+
+    /// Restrict drawing to `rect` (within the screen); returns the old clip
+    /// so the caller can put it back
+    pub fn set_clip(&mut self, rect: Rect) -> Rect {
+        let screen = Rect::new(0, 0, self.width, self.height);
+        core::mem::replace(&mut self.clip, rect.intersect(&screen))
+    }
+
+    /// Current clip rectangle
+    pub fn clip(&self) -> Rect {
+        self.clip
+    }
+
     /// Set a single pixel
     #[inline]
     pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
-        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+        if !self.clip.contains(x, y) {
             return;
         }
         
         let offset = (y as u32 * self.pitch + x as u32 * self.bpp) as usize;
         
         unsafe {
-            let pixel = self.buffer.add(offset);
-            match self.bpp {
-                4 => {
-                    // 32-bit BGRA
-                    *pixel = color.b;
-                    *pixel.add(1) = color.g;
-                    *pixel.add(2) = color.r;
-                    *pixel.add(3) = 0xFF;
-                }
-                3 => {
-                    // 24-bit BGR
-                    *pixel = color.b;
-                    *pixel.add(1) = color.g;
-                    *pixel.add(2) = color.r;
-                }
-                2 => {
-                    // 16-bit RGB565
-                    let r = (color.r >> 3) as u16;
-                    let g = (color.g >> 2) as u16;
-                    let b = (color.b >> 3) as u16;
-                    let rgb565 = (r << 11) | (g << 5) | b;
-                    *(pixel as *mut u16) = rgb565;
-                }
-                _ => {}
-            }
+            store_pixel(self.buffer.add(offset), self.bpp, color, x, y);
         }
     }
     
@@ -79,21 +77,11 @@ impl Framebuffer {
         
         let offset = (y as u32 * self.pitch + x as u32 * self.bpp) as usize;
         
-        unsafe {
-            let pixel = self.buffer.add(offset);
-            let color = match self.bpp {
-                4 | 3 => Color::rgb(*pixel.add(2), *pixel.add(1), *pixel),
-                2 => {
-                    let rgb565 = *(pixel as *const u16);
-                    let r = ((rgb565 >> 11) & 0x1F) as u8;
-                    let g = ((rgb565 >> 5) & 0x3F) as u8;
-                    let b = (rgb565 & 0x1F) as u8;
-                    Color::rgb(r << 3, g << 2, b << 3)
-                }
-                _ => Color::BLACK,
-            };
-            Some(color)
-        }
+        let color = match PixelFormat::from_bytes(self.bpp) {
+            Some(format) => unsafe { format.load(self.buffer.add(offset)) },
+            None => Color::BLACK,
+        };
+        Some(color)
     }
     
     /// Fill entire screen with a color
@@ -103,19 +91,11 @@ impl Framebuffer {
     
     /// Fill a rectangle with a solid color
     pub fn fill_rect(&mut self, x: i32, y: i32, width: u32, height: u32, color: Color) {
-        // Clip to screen bounds
-        let x0 = x.max(0) as u32;
-        let y0 = y.max(0) as u32;
-        let x1 = ((x + width as i32) as u32).min(self.width);
-        let y1 = ((y + height as i32) as u32).min(self.height);
-        
-        if x0 >= x1 || y0 >= y1 {
-            return;
-        }
+        let area = Rect::new(x, y, width, height).intersect(&self.clip);
         
-        for py in y0..y1 {
-            for px in x0..x1 {
-                self.set_pixel(px as i32, py as i32, color);
+        for py in area.y..area.bottom() {
+            for px in area.x..area.right() {
+                self.set_pixel(px, py, color);
             }
         }
     }
@@ -178,7 +158,55 @@ impl Framebuffer {
     
     /// Draw a single character at position
     pub fn draw_char(&mut self, x: i32, y: i32, c: char, fg: Color, bg: Option<Color>) {
+        let fully_visible = x >= self.clip.x && y >= self.clip.y
+            && x + font::FONT_WIDTH as i32 <= self.clip.right()
+            && y + font::FONT_HEIGHT as i32 <= self.clip.bottom();
+
+        // Fast path: copy a pre-rendered tile row by row
+        if let (true, Some(bg_color)) = (fully_visible, bg) {
+            if let Some(tile) = glyph_cache::lookup(c as u8, fg, bg_color, self.bpp) {
+                let row_bytes = font::FONT_WIDTH * self.bpp as usize;
+                for row in 0..font::FONT_HEIGHT {
+                    let dst = ((y as u32 + row as u32) * self.pitch + x as u32 * self.bpp) as usize;
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            tile.as_ptr().add(row * glyph_cache::TILE_PITCH),
+                            self.buffer.add(dst),
+                            row_bytes,
+                        );
+                    }
+                }
+                return;
+            }
+        }
+
         let glyph = font::get_char(c as u8);
+
+        // Transparent text: pack the foreground once and skip per-pixel clipping
+        if fully_visible && bg.is_none() && glyph_cache::cacheable(self.bpp) {
+            let mut packed = [0u8; 4];
+            unsafe { store_pixel(packed.as_mut_ptr(), self.bpp, fg, 0, 0); }
+            let bpp = self.bpp as usize;
+
+            for (row_idx, &row) in glyph.iter().enumerate() {
+                if row == 0 {
+                    continue;
+                }
+                let line = ((y as u32 + row_idx as u32) * self.pitch + x as u32 * self.bpp) as usize;
+                for col_idx in 0..font::FONT_WIDTH {
+                    if (row >> (7 - col_idx)) & 1 != 0 {
+                        unsafe {
+                            core::ptr::copy_nonoverlapping(
+                                packed.as_ptr(),
+                                self.buffer.add(line + col_idx * bpp),
+                                bpp,
+                            );
+                        }
+                    }
+                }
+            }
+            return;
+        }
         
         for (row_idx, &row) in glyph.iter().enumerate() {
             for col_idx in 0..8 {
@@ -335,6 +363,19 @@ impl Framebuffer {
     }
 }
 
+/// Write one pixel in the format for `bpp` bytes per pixel
+///
+/// `x`/`y` only select the dither threshold at 8/16bpp.
+///
+/// # Safety
+/// `pixel` must be valid for `bpp` bytes of writes
+#[inline]
+pub(crate) unsafe fn store_pixel(pixel: *mut u8, bpp: u32, color: Color, x: i32, y: i32) {
+    if let Some(format) = PixelFormat::from_bytes(bpp) {
+        format.store(pixel, color, x, y);
+    }
+}
+
 // Global framebuffer instance
 static mut FRAMEBUFFER: Option<Framebuffer> = None;
 
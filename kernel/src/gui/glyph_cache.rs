@@ -0,0 +1,174 @@
+//! Glyph Cache
+//!
+//! Pre-rendered 8x16 glyph tiles keyed by (glyph, fg, bg), stored in the
+//! target pixel format so opaque text can be drawn with one row copy per
+//! scanline instead of a `set_pixel` call per font bit.
+//!
+//! The cache is direct-mapped: a colliding glyph simply evicts the previous
+//! occupant. Terminal and label text uses a handful of color pairs, so a few
+//! hundred slots cover the working set.
+//!
+//! Tiles are only valid when pixel packing is position-independent. Ordered
+//! dithering at 8/16bpp depends on screen coordinates, so those depths fall
+//! back to the per-pixel path unless dithering is disabled.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use super::{Color, font, dither};
+use super::dither::DitherMode;
+
+// =============================================================================
+// Configuration
+// =============================================================================
+
+/// Number of cache slots (power of two)
+pub const CACHE_SLOTS: usize = 256;
+
+/// Largest supported pixel size in bytes
+const MAX_BPP: usize = 4;
+
+/// Bytes in one tile row at the largest pixel size
+pub const TILE_PITCH: usize = font::FONT_WIDTH * MAX_BPP;
+
+/// Bytes in one tile
+const TILE_BYTES: usize = TILE_PITCH * font::FONT_HEIGHT;
+
+// =============================================================================
+// Tile Storage
+// =============================================================================
+
+/// A pre-rendered glyph in the target pixel format
+struct GlyphTile {
+    /// Packed key (0 = empty slot)
+    key: u64,
+    /// Rows of packed pixels, `TILE_PITCH` bytes apart
+    pixels: [u8; TILE_BYTES],
+}
+
+/// Hit/miss counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u32,
+    pub misses: u32,
+    pub evictions: u32,
+}
+
+static mut TILES: Option<Box<[GlyphTile]>> = None;
+static mut STATS: CacheStats = CacheStats { hits: 0, misses: 0, evictions: 0 };
+
+/// Build the cache key for a glyph/color/depth combination
+///
+/// Bit 63 is always set so a valid key is never zero.
+#[inline]
+fn make_key(c: u8, fg: Color, bg: Color, bpp: u32) -> u64 {
+    (1u64 << 63)
+        | ((bpp as u64 & 0x7) << 56)
+        | ((c as u64) << 48)
+        | ((fg.to_u32() as u64) << 24)
+        | bg.to_u32() as u64
+}
+
+/// Pick a slot for a key
+#[inline]
+fn slot_for(key: u64) -> usize {
+    // Fibonacci hashing spreads consecutive characters across the table
+    let h = key.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    (h >> 56) as usize & (CACHE_SLOTS - 1)
+}
+
+/// Whether tiles can be used at this pixel depth right now
+pub fn cacheable(bpp: u32) -> bool {
+    match bpp {
+        3 | 4 => true,
+        1 | 2 => dither::mode() == DitherMode::None,
+        _ => false,
+    }
+}
+
+/// Get (rendering on a miss) the tile for a glyph
+///
+/// Returns rows of packed pixels `TILE_PITCH` bytes apart, or `None` if the
+/// depth cannot be cached or the heap is exhausted.
+pub fn lookup(c: u8, fg: Color, bg: Color, bpp: u32) -> Option<&'static [u8]> {
+    if !cacheable(bpp) {
+        return None;
+    }
+
+    unsafe {
+        if TILES.is_none() {
+            TILES = Some(alloc_tiles()?);
+        }
+        let tiles = TILES.as_mut()?;
+
+        let key = make_key(c, fg, bg, bpp);
+        let tile = &mut tiles[slot_for(key)];
+
+        if tile.key == key {
+            STATS.hits = STATS.hits.wrapping_add(1);
+        } else {
+            if tile.key != 0 {
+                STATS.evictions = STATS.evictions.wrapping_add(1);
+            }
+            STATS.misses = STATS.misses.wrapping_add(1);
+            render(tile, c, fg, bg, bpp);
+            tile.key = key;
+        }
+
+        Some(&tile.pixels[..])
+    }
+}
+
+/// Allocate the tile table on first use
+fn alloc_tiles() -> Option<Box<[GlyphTile]>> {
+    let mut tiles = Vec::new();
+    tiles.try_reserve_exact(CACHE_SLOTS).ok()?;
+    for _ in 0..CACHE_SLOTS {
+        tiles.push(GlyphTile { key: 0, pixels: [0; TILE_BYTES] });
+    }
+    Some(tiles.into_boxed_slice())
+}
+
+/// Render a glyph into a tile
+fn render(tile: &mut GlyphTile, c: u8, fg: Color, bg: Color, bpp: u32) {
+    let glyph = font::get_char(c);
+    let bpp = bpp as usize;
+
+    for (row_idx, &row) in glyph.iter().enumerate() {
+        for col_idx in 0..font::FONT_WIDTH {
+            let color = if (row >> (7 - col_idx)) & 1 != 0 { fg } else { bg };
+            let offset = row_idx * TILE_PITCH + col_idx * bpp;
+            unsafe {
+                super::framebuffer::store_pixel(
+                    tile.pixels.as_mut_ptr().add(offset),
+                    bpp as u32,
+                    color,
+                    col_idx as i32,
+                    row_idx as i32,
+                );
+            }
+        }
+    }
+}
+
+// =============================================================================
+// Maintenance
+// =============================================================================
+
+/// Drop every cached tile
+///
+/// Call after anything that changes how colors are packed (palette reload,
+/// dither mode change). The table itself stays allocated.
+pub fn clear() {
+    unsafe {
+        if let Some(tiles) = TILES.as_mut() {
+            for tile in tiles.iter_mut() {
+                tile.key = 0;
+            }
+        }
+    }
+}
+
+/// Cache counters since boot
+pub fn stats() -> CacheStats {
+    unsafe { STATS }
+}
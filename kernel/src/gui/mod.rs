@@ -11,20 +11,61 @@
 //! Continuous events (mouse tracking, frame rendering) are handled directly
 //! for performance reasons.
 
+pub mod color;
 pub mod font;
+pub mod fbcon;
+pub mod glyph_cache;
 pub mod framebuffer;
+pub mod dither;
+pub mod pixel;
+pub mod notify;
+pub mod bmp;
+pub mod theme;
+pub mod test_pattern;
+pub mod recorder;
+
+// The window system itself; without it the console, framebuffer and
+// notification queue above still serve the text-mode shell
+#[cfg(feature = "gui")]
 pub mod window;
+#[cfg(feature = "gui")]
 pub mod desktop;
-pub mod theme;
+#[cfg(feature = "gui")]
 pub mod wm_events;
-
+#[cfg(feature = "gui")]
+pub mod screensaver;
+#[cfg(feature = "gui")]
+pub mod profiler;
+#[cfg(feature = "gui")]
+pub mod wallpaper;
+#[cfg(feature = "gui")]
+pub mod server;
+#[cfg(feature = "gui")]
+pub mod events;
+#[cfg(feature = "gui")]
+pub mod scroll_view;
+
+pub use color::Color;
 pub use framebuffer::Framebuffer;
+pub use theme::Theme;
+#[cfg(feature = "gui")]
 pub use window::Window;
+#[cfg(feature = "gui")]
 pub use desktop::Desktop;
-pub use theme::Theme;
+#[cfg(feature = "gui")]
 pub use wm_events::WmEventDispatcher;
+#[cfg(feature = "gui")]
+pub use events::EventLoop;
+#[cfg(feature = "gui")]
+pub use scroll_view::ScrollView;
+
+use crate::drivers::keyboard::{BufferedKey, KeyCode};
 
 /// GUI Event types
+///
+/// Built from raw input by `events::EventLoop` and queued on the window
+/// they are for (as `InputEvent`s); pointer positions are in screen
+/// coordinates.
 #[derive(Debug, Clone, Copy)]
 pub enum GuiEvent {
     /// Mouse moved to position
@@ -33,16 +74,96 @@ pub enum GuiEvent {
     MouseDown { x: i32, y: i32, button: MouseButton },
     /// Mouse button released
     MouseUp { x: i32, y: i32, button: MouseButton },
+    /// Wheel turned with the pointer at (x, y); positive is towards the
+    /// user (scroll down)
+    Wheel { x: i32, y: i32, delta: i32 },
     /// Key pressed
-    KeyDown { key: char, scancode: u8 },
+    KeyDown { keycode: KeyCode, ascii: Option<char>, ctrl: bool, alt: bool },
     /// Key released
-    KeyUp { key: char, scancode: u8 },
+    KeyUp { keycode: KeyCode, ascii: Option<char>, ctrl: bool, alt: bool },
     /// Window needs redraw
     Redraw,
     /// Timer tick
     Tick,
 }
 
+impl GuiEvent {
+    /// Event for a key from the keyboard buffer
+    pub fn from_key(key: &BufferedKey) -> Self {
+        let (keycode, ascii, ctrl, alt) = (key.keycode, key.ascii, key.ctrl, key.alt);
+        if key.pressed {
+            Self::KeyDown { keycode, ascii, ctrl, alt }
+        } else {
+            Self::KeyUp { keycode, ascii, ctrl, alt }
+        }
+    }
+}
+
+/// A GUI event and when its input arrived
+///
+/// The time is `pit::uptime_ms` when the key or first packet byte came
+/// in, the same clock WM events record, so double clicks and repeats can
+/// be timed against each other.
+#[derive(Debug, Clone, Copy)]
+pub struct InputEvent {
+    pub event: GuiEvent,
+    pub time_ms: u32,
+}
+
+impl InputEvent {
+    pub const fn new(event: GuiEvent, time_ms: u32) -> Self {
+        Self { event, time_ms }
+    }
+
+    /// Event for a key from the keyboard buffer, stamped with its arrival
+    pub fn from_key(key: &BufferedKey) -> Self {
+        Self::new(GuiEvent::from_key(key), key.time_ms)
+    }
+}
+
+/// Built-in desktop windows (program windows belong to their programs)
+///
+/// Outside `desktop` so saved sessions round-trip through the settings
+/// file in builds without the window system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowKind {
+    Welcome,
+    Terminal,
+    Files,
+    TestPattern,
+}
+
+impl WindowKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Welcome => "welcome",
+            Self::Terminal => "terminal",
+            Self::Files => "files",
+            Self::TestPattern => "testpattern",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "welcome" => Some(Self::Welcome),
+            "terminal" => Some(Self::Terminal),
+            "files" => Some(Self::Files),
+            "testpattern" => Some(Self::TestPattern),
+            _ => None,
+        }
+    }
+
+    /// Title the window is created with (and recognized by)
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Welcome => "Welcome to Rustacean OS!",
+            Self::Terminal => "Terminal",
+            Self::Files => "Files",
+            Self::TestPattern => "Test Pattern",
+        }
+    }
+}
+
 /// Mouse buttons
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MouseButton {
@@ -77,6 +198,15 @@ impl Rect {
     pub fn bottom(&self) -> i32 {
         self.y + self.height as i32
     }
+
+    /// Overlap of two rectangles (empty if they don't meet)
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        Rect::new(x, y, (right - x).max(0) as u32, (bottom - y).max(0) as u32)
+    }
 }
 
 /// Point structure
@@ -91,42 +221,3 @@ impl Point {
         Self { x, y }
     }
 }
-
-/// RGB Color
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct Color {
-    pub r: u8,
-    pub g: u8,
-    pub b: u8,
-}
-
-impl Color {
-    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
-        Self { r, g, b }
-    }
-
-    pub const fn from_u32(val: u32) -> Self {
-        Self {
-            r: ((val >> 16) & 0xFF) as u8,
-            g: ((val >> 8) & 0xFF) as u8,
-            b: (val & 0xFF) as u8,
-        }
-    }
-
-    pub const fn to_u32(&self) -> u32 {
-        ((self.r as u32) << 16) | ((self.g as u32) << 8) | (self.b as u32)
-    }
-
-    // Plan 9 inspired colors
-    pub const BLACK: Color = Color::rgb(0, 0, 0);
-    pub const WHITE: Color = Color::rgb(255, 255, 255);
-    pub const PALEYELLOW: Color = Color::rgb(255, 255, 224);
-    pub const PALEBLUE: Color = Color::rgb(224, 224, 255);
-    pub const PALEGREEN: Color = Color::rgb(224, 255, 224);
-    pub const MEDBLUE: Color = Color::rgb(0, 0, 153);
-    pub const GREYBLUE: Color = Color::rgb(102, 153, 153);
-    pub const PALEGREYBLUE: Color = Color::rgb(156, 182, 182);
-    pub const DARKGREY: Color = Color::rgb(102, 102, 102);
-    pub const LIGHTGREY: Color = Color::rgb(192, 192, 192);
-    pub const BORDER: Color = Color::rgb(153, 153, 153);
-}
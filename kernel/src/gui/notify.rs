@@ -0,0 +1,163 @@
+//! Notification Service
+//!
+//! Kernel subsystems and applications post short status messages here
+//! (driver failure, battery low, task crashed). The desktop drains the
+//! queue each frame and shows every message as a transient toast window.
+//!
+//! Posting only touches a fixed-size queue, never the window manager,
+//! so it is safe from driver init code before the desktop exists.
+
+use super::Color;
+
+/// Maximum message length in bytes (longer messages are truncated)
+pub const MAX_MESSAGE: usize = 48;
+
+/// Default time a toast stays on screen
+pub const DEFAULT_TIMEOUT_MS: u32 = 4000;
+
+/// Pending notification queue size
+const QUEUE_SIZE: usize = 8;
+
+/// Notification severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Toast window title
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Info => "Notice",
+            Self::Warning => "Warning",
+            Self::Error => "Error",
+        }
+    }
+
+    /// Accent color for the toast
+    pub fn accent(self) -> Color {
+        match self {
+            Self::Info => Color::rgb(80, 160, 255),
+            Self::Warning => Color::rgb(255, 190, 40),
+            Self::Error => Color::rgb(230, 60, 60),
+        }
+    }
+}
+
+/// A posted notification
+#[derive(Debug, Clone, Copy)]
+pub struct Notification {
+    pub severity: Severity,
+    /// How long the toast stays visible
+    pub timeout_ms: u32,
+    message: [u8; MAX_MESSAGE],
+    len: usize,
+}
+
+impl Notification {
+    /// Create a notification (message truncated to MAX_MESSAGE bytes)
+    pub fn new(severity: Severity, message: &str, timeout_ms: u32) -> Self {
+        let mut len = message.len().min(MAX_MESSAGE);
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut buf = [0u8; MAX_MESSAGE];
+        buf[..len].copy_from_slice(&message.as_bytes()[..len]);
+
+        Self {
+            severity,
+            timeout_ms,
+            message: buf,
+            len,
+        }
+    }
+
+    /// Get the message text
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(&self.message[..self.len]).unwrap_or("???")
+    }
+}
+
+/// Pending notifications waiting for the desktop
+pub struct NotificationQueue {
+    buffer: [Option<Notification>; QUEUE_SIZE],
+    read_idx: usize,
+    count: usize,
+    /// Notifications dropped because the queue was full
+    dropped: u32,
+}
+
+impl NotificationQueue {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [None; QUEUE_SIZE],
+            read_idx: 0,
+            count: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Queue a notification, returns false if the queue is full
+    pub fn push(&mut self, notification: Notification) -> bool {
+        if self.count >= QUEUE_SIZE {
+            self.dropped = self.dropped.wrapping_add(1);
+            return false;
+        }
+        let idx = (self.read_idx + self.count) % QUEUE_SIZE;
+        self.buffer[idx] = Some(notification);
+        self.count += 1;
+        true
+    }
+
+    /// Take the oldest pending notification
+    pub fn pop(&mut self) -> Option<Notification> {
+        if self.count == 0 {
+            return None;
+        }
+        let notification = self.buffer[self.read_idx].take();
+        self.read_idx = (self.read_idx + 1) % QUEUE_SIZE;
+        self.count -= 1;
+        notification
+    }
+
+    /// Number of pending notifications
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Number of notifications dropped due to overflow
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+/// Global notification queue
+pub static mut NOTIFICATIONS: NotificationQueue = NotificationQueue::new();
+
+/// Post a notification with the default timeout
+pub fn post(severity: Severity, message: &str) -> bool {
+    post_with_timeout(severity, message, DEFAULT_TIMEOUT_MS)
+}
+
+/// Post a notification with a custom timeout
+pub fn post_with_timeout(severity: Severity, message: &str, timeout_ms: u32) -> bool {
+    unsafe { NOTIFICATIONS.push(Notification::new(severity, message, timeout_ms)) }
+}
+
+/// Post an informational notification
+pub fn info(message: &str) -> bool {
+    post(Severity::Info, message)
+}
+
+/// Post a warning notification
+pub fn warn(message: &str) -> bool {
+    post(Severity::Warning, message)
+}
+
+/// Post an error notification
+pub fn error(message: &str) -> bool {
+    post(Severity::Error, message)
+}
@@ -0,0 +1,307 @@
+//! Pixel Formats
+//!
+//! The one place that knows how a `Color` is laid out in video memory.
+//! Framebuffer drawing, the glyph cache and the ATI 2D engine all pack
+//! through `PixelFormat`, so every path agrees on channel order:
+//!
+//! | Format   | Bytes | In memory (lowest address first)        |
+//! |----------|-------|-----------------------------------------|
+//! | Xrgb8888 | 4     | B, G, R, X (0xFF) - little-endian u32   |
+//! | Bgr888   | 3     | B, G, R                                 |
+//! | Rgb565   | 2     | little-endian u16, red in the top bits  |
+//! | Xrgb1555 | 2     | little-endian u16, top bit unused       |
+//! | Indexed8 | 1     | RGB332 index into the palette we load   |
+//!
+//! `pack`/`unpack` are exact and undithered; `store` dithers 8/16bpp
+//! through `dither`. Round trips are checked at compile time below, and
+//! the tests at the end run on the host (`cargo test` in `kernel/hosttest`).
+
+use super::{dither, Color};
+
+/// Layout of one pixel in a framebuffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Xrgb8888,
+    Bgr888,
+    Rgb565,
+    Xrgb1555,
+    Indexed8,
+}
+
+impl PixelFormat {
+    /// Format for a framebuffer's bytes per pixel
+    ///
+    /// 2 bytes is assumed to be RGB565, as VBE and BGA set it up.
+    pub const fn from_bytes(bytes: u32) -> Option<Self> {
+        match bytes {
+            4 => Some(Self::Xrgb8888),
+            3 => Some(Self::Bgr888),
+            2 => Some(Self::Rgb565),
+            1 => Some(Self::Indexed8),
+            _ => None,
+        }
+    }
+
+    /// Format for a color depth in bits (as the ATI CRTC reports it)
+    pub const fn from_depth(bits: u32) -> Option<Self> {
+        match bits {
+            32 => Some(Self::Xrgb8888),
+            24 => Some(Self::Bgr888),
+            16 => Some(Self::Rgb565),
+            15 => Some(Self::Xrgb1555),
+            8 => Some(Self::Indexed8),
+            _ => None,
+        }
+    }
+
+    /// Bytes per pixel
+    pub const fn bytes(self) -> u32 {
+        match self {
+            Self::Xrgb8888 => 4,
+            Self::Bgr888 => 3,
+            Self::Rgb565 | Self::Xrgb1555 => 2,
+            Self::Indexed8 => 1,
+        }
+    }
+
+    /// Pack a color without dithering (low bits of the result)
+    pub const fn pack(self, color: Color) -> u32 {
+        let (r, g, b) = (color.r as u32, color.g as u32, color.b as u32);
+        match self {
+            Self::Xrgb8888 => 0xFF00_0000 | (r << 16) | (g << 8) | b,
+            Self::Bgr888 => (r << 16) | (g << 8) | b,
+            Self::Rgb565 => ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3),
+            Self::Xrgb1555 => ((r >> 3) << 10) | ((g >> 3) << 5) | (b >> 3),
+            Self::Indexed8 => (r & 0xE0) | ((g >> 5) << 2) | (b >> 6),
+        }
+    }
+
+    /// Unpack a pixel value produced by `pack`
+    ///
+    /// Low-depth channels replicate their high bits so white stays white.
+    pub const fn unpack(self, pixel: u32) -> Color {
+        match self {
+            Self::Xrgb8888 | Self::Bgr888 => Color::from_u32(pixel),
+            Self::Rgb565 => Color::rgb(
+                expand5((pixel >> 11) & 0x1F),
+                expand6((pixel >> 5) & 0x3F),
+                expand5(pixel & 0x1F),
+            ),
+            Self::Xrgb1555 => Color::rgb(
+                expand5((pixel >> 10) & 0x1F),
+                expand5((pixel >> 5) & 0x1F),
+                expand5(pixel & 0x1F),
+            ),
+            Self::Indexed8 => {
+                let r = (pixel >> 5) & 0x07;
+                let g = (pixel >> 2) & 0x07;
+                let b = pixel & 0x03;
+                Color::rgb(
+                    ((r << 5) | (r << 2) | (r >> 1)) as u8,
+                    ((g << 5) | (g << 2) | (g >> 1)) as u8,
+                    ((b << 6) | (b << 4) | (b << 2) | b) as u8,
+                )
+            }
+        }
+    }
+
+    /// Write one pixel, dithered at (x, y) for the 8/16bpp formats
+    ///
+    /// # Safety
+    /// `pixel` must be valid for `self.bytes()` bytes of writes
+    #[inline]
+    pub unsafe fn store(self, pixel: *mut u8, color: Color, x: i32, y: i32) {
+        match self {
+            Self::Xrgb8888 => (pixel as *mut u32).write_unaligned(self.pack(color).to_le()),
+            Self::Bgr888 => {
+                *pixel = color.b;
+                *pixel.add(1) = color.g;
+                *pixel.add(2) = color.r;
+            }
+            Self::Rgb565 => (pixel as *mut u16).write_unaligned(dither::pack_rgb565(color, x, y).to_le()),
+            Self::Xrgb1555 => (pixel as *mut u16).write_unaligned((self.pack(color) as u16).to_le()),
+            Self::Indexed8 => *pixel = dither::pack_rgb332(color, x, y),
+        }
+    }
+
+    /// Read one pixel back
+    ///
+    /// # Safety
+    /// `pixel` must be valid for `self.bytes()` bytes of reads
+    #[inline]
+    pub unsafe fn load(self, pixel: *const u8) -> Color {
+        let value = match self {
+            Self::Xrgb8888 => u32::from_le((pixel as *const u32).read_unaligned()),
+            Self::Bgr888 => (*pixel.add(2) as u32) << 16 | (*pixel.add(1) as u32) << 8 | *pixel as u32,
+            Self::Rgb565 | Self::Xrgb1555 => u16::from_le((pixel as *const u16).read_unaligned()) as u32,
+            Self::Indexed8 => *pixel as u32,
+        };
+        self.unpack(value)
+    }
+}
+
+/// Widen a 5-bit channel to 8 bits
+const fn expand5(v: u32) -> u8 {
+    ((v << 3) | (v >> 2)) as u8
+}
+
+/// Widen a 6-bit channel to 8 bits
+const fn expand6(v: u32) -> u8 {
+    ((v << 2) | (v >> 4)) as u8
+}
+
+// =============================================================================
+// Compile-time checks
+// =============================================================================
+
+const fn same(a: Color, b: Color) -> bool {
+    a.r == b.r && a.g == b.g && a.b == b.b
+}
+
+const fn round_trips(format: PixelFormat, color: Color) -> bool {
+    same(format.unpack(format.pack(color)), color)
+}
+
+const ALL: [PixelFormat; 5] = [
+    PixelFormat::Xrgb8888,
+    PixelFormat::Bgr888,
+    PixelFormat::Rgb565,
+    PixelFormat::Xrgb1555,
+    PixelFormat::Indexed8,
+];
+
+// Black, white and the primaries survive every format
+const _: () = {
+    let colors = [
+        Color::BLACK,
+        Color::WHITE,
+        Color::rgb(255, 0, 0),
+        Color::rgb(0, 255, 0),
+        Color::rgb(0, 0, 255),
+    ];
+    let mut f = 0;
+    while f < ALL.len() {
+        let mut c = 0;
+        while c < colors.len() {
+            assert!(round_trips(ALL[f], colors[c]));
+            c += 1;
+        }
+        // Packed values fit the pixel size
+        assert!(ALL[f].bytes() == 4 || ALL[f].pack(Color::WHITE) < 1 << (ALL[f].bytes() * 8));
+        f += 1;
+    }
+};
+
+// Channel order
+const _: () = {
+    let c = Color::rgb(0x12, 0x34, 0x56);
+    assert!(PixelFormat::Xrgb8888.pack(c) == 0xFF12_3456);
+    assert!(PixelFormat::Bgr888.pack(c) == 0x12_3456);
+    assert!(PixelFormat::Rgb565.pack(Color::rgb(255, 0, 0)) == 0xF800);
+    assert!(PixelFormat::Rgb565.pack(Color::rgb(0, 255, 0)) == 0x07E0);
+    assert!(PixelFormat::Xrgb1555.pack(Color::rgb(255, 0, 0)) == 0x7C00);
+    assert!(PixelFormat::Indexed8.pack(Color::rgb(255, 0, 0)) == 0xE0);
+    assert!(PixelFormat::Indexed8.pack(Color::rgb(0, 0, 255)) == 0x03);
+};
+
+// Every RGB332 palette index maps back to itself
+const _: () = {
+    let mut i = 0;
+    while i < 256 {
+        let f = PixelFormat::Indexed8;
+        assert!(f.pack(f.unpack(i)) == i);
+        i += 1;
+    }
+};
+
+// Every RGB565 value round trips through Color
+const _: () = {
+    let mut i = 0;
+    while i < 0x10000 {
+        let f = PixelFormat::Rgb565;
+        assert!(f.pack(f.unpack(i)) == i);
+        i += 1;
+    }
+};
+
+// =============================================================================
+// Tests
+// =============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Store `color` in a buffer laid out as `format` and return its bytes
+    fn stored(format: PixelFormat, color: Color) -> [u8; 4] {
+        let mut bytes = [0xAA; 4];
+        unsafe { format.store(bytes.as_mut_ptr(), color, 0, 0) };
+        bytes
+    }
+
+    #[test]
+    fn formats_by_size() {
+        assert_eq!(PixelFormat::from_bytes(4), Some(PixelFormat::Xrgb8888));
+        assert_eq!(PixelFormat::from_bytes(3), Some(PixelFormat::Bgr888));
+        assert_eq!(PixelFormat::from_bytes(2), Some(PixelFormat::Rgb565));
+        assert_eq!(PixelFormat::from_bytes(1), Some(PixelFormat::Indexed8));
+        assert_eq!(PixelFormat::from_bytes(5), None);
+
+        assert_eq!(PixelFormat::from_depth(15), Some(PixelFormat::Xrgb1555));
+        assert_eq!(PixelFormat::from_depth(12), None);
+        for format in ALL {
+            assert_eq!(PixelFormat::from_depth(format.bytes() * 8).map(PixelFormat::bytes), Some(format.bytes()));
+        }
+    }
+
+    #[test]
+    fn memory_order() {
+        let c = Color::rgb(0x12, 0x34, 0x56);
+        assert_eq!(stored(PixelFormat::Xrgb8888, c), [0x56, 0x34, 0x12, 0xFF]);
+        // Only three bytes are written
+        assert_eq!(stored(PixelFormat::Bgr888, c), [0x56, 0x34, 0x12, 0xAA]);
+        // 0x7C00: red in bits 10-14, little-endian
+        assert_eq!(stored(PixelFormat::Xrgb1555, Color::rgb(255, 0, 0)), [0x00, 0x7C, 0xAA, 0xAA]);
+        assert_eq!(stored(PixelFormat::Xrgb1555, Color::rgb(0, 0, 255)), [0x1F, 0x00, 0xAA, 0xAA]);
+    }
+
+    #[test]
+    fn store_then_load() {
+        let colors = [Color::BLACK, Color::WHITE, Color::PALEGREYBLUE, Color::rgb(0x12, 0x34, 0x56)];
+        for color in colors {
+            for format in [PixelFormat::Xrgb8888, PixelFormat::Bgr888] {
+                let bytes = stored(format, color);
+                assert_eq!(unsafe { format.load(bytes.as_ptr()) }, color, "{:?}", format);
+            }
+            // 15bpp keeps the top five bits of each channel
+            let bytes = stored(PixelFormat::Xrgb1555, color);
+            let back = unsafe { PixelFormat::Xrgb1555.load(bytes.as_ptr()) };
+            assert_eq!((back.r >> 3, back.g >> 3, back.b >> 3), (color.r >> 3, color.g >> 3, color.b >> 3));
+        }
+    }
+
+    #[test]
+    fn load_ignores_unused_bits() {
+        // The X byte of Xrgb8888 and the top bit of Xrgb1555 aren't color
+        let xrgb = [0x56, 0x34, 0x12, 0x00];
+        assert_eq!(unsafe { PixelFormat::Xrgb8888.load(xrgb.as_ptr()) }, Color::rgb(0x12, 0x34, 0x56));
+        let x1555 = [0x00, 0xFC];
+        assert_eq!(unsafe { PixelFormat::Xrgb1555.load(x1555.as_ptr()) }, Color::rgb(255, 0, 0));
+    }
+
+    #[test]
+    fn every_15bpp_value_round_trips() {
+        let format = PixelFormat::Xrgb1555;
+        for value in 0..0x8000 {
+            assert_eq!(format.pack(format.unpack(value)), value);
+        }
+    }
+
+    #[test]
+    fn low_depths_keep_the_extremes() {
+        for format in ALL {
+            assert_eq!(format.unpack(format.pack(Color::WHITE)), Color::WHITE, "{:?}", format);
+            assert_eq!(format.unpack(format.pack(Color::BLACK)), Color::BLACK, "{:?}", format);
+        }
+    }
+}
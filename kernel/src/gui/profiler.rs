@@ -0,0 +1,212 @@
+//! Frame Profiler
+//!
+//! Per-frame counters sampled in the desktop draw path: frame time, dirty
+//! rectangles presented, bytes blitted to the front buffer, and heap usage.
+//! Samples are folded into a report every `REPORT_INTERVAL_MS` and shown in
+//! a small overlay in the top-right corner (toggled with F12).
+//!
+//! Frame times come from `time::now_ns` (the TSC where it is stable), so
+//! the worst frame is measured to the microsecond rather than to a PIT
+//! tick. The idle figure is the share of timer ticks that found the GUI
+//! loop halted (`arch::x86::idle`).
+
+use core::fmt::Write;
+use alloc::string::String;
+use crate::arch::x86::idle;
+use super::{Color, Framebuffer, font};
+
+/// How often the overlay figures are refreshed
+pub const REPORT_INTERVAL_MS: u32 = 500;
+
+/// Overlay lines and width in characters
+const OVERLAY_LINES: usize = 6;
+const OVERLAY_COLS: usize = 22;
+
+/// Overlay inset from the screen corner
+const OVERLAY_MARGIN: i32 = 4;
+
+/// Figures for one report window
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameReport {
+    /// Frames drawn in the window
+    pub frames: u32,
+    /// Average frame time in microseconds
+    pub avg_frame_us: u32,
+    /// Longest single frame in microseconds
+    pub worst_frame_us: u32,
+    /// Dirty rectangles presented per frame (average)
+    pub dirty_rects: u32,
+    /// Bytes written to the front buffer per frame (average)
+    pub blit_bytes: u32,
+    /// Heap bytes in use at the end of the window
+    pub heap_used: usize,
+    /// Heap bytes still available
+    pub heap_free: usize,
+    /// Time the CPU spent halted (percent)
+    pub idle_pct: u32,
+}
+
+impl FrameReport {
+    /// Frames per second over the window (tenths)
+    pub fn fps_x10(&self) -> u32 {
+        if self.avg_frame_us == 0 {
+            0
+        } else {
+            10_000_000 / self.avg_frame_us
+        }
+    }
+}
+
+/// Accumulates per-frame samples
+pub struct FrameProfiler {
+    enabled: bool,
+    window_start_ns: u64,
+    last_frame_ns: u64,
+    frames: u32,
+    worst_frame_us: u32,
+    dirty_rects: u32,
+    blit_bytes: u32,
+    /// Idle sample at the start of the window
+    idle_start: (u32, u32),
+    report: FrameReport,
+}
+
+impl FrameProfiler {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            window_start_ns: 0,
+            last_frame_ns: 0,
+            frames: 0,
+            worst_frame_us: 0,
+            dirty_rects: 0,
+            blit_bytes: 0,
+            idle_start: (0, 0),
+            report: FrameReport {
+                frames: 0,
+                avg_frame_us: 0,
+                worst_frame_us: 0,
+                dirty_rects: 0,
+                blit_bytes: 0,
+                heap_used: 0,
+                heap_free: 0,
+                idle_pct: 0,
+            },
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turn sampling and the overlay on or off
+    ///
+    /// Counters restart on enable so the first report is not skewed by the
+    /// time spent disabled.
+    pub fn set_enabled(&mut self, enabled: bool, now_ns: u64) {
+        self.enabled = enabled;
+        if enabled {
+            self.restart(now_ns);
+            self.report = FrameReport::default();
+        }
+    }
+
+    fn restart(&mut self, now_ns: u64) {
+        self.window_start_ns = now_ns;
+        self.last_frame_ns = now_ns;
+        self.frames = 0;
+        self.worst_frame_us = 0;
+        self.dirty_rects = 0;
+        self.blit_bytes = 0;
+        self.idle_start = idle::sample();
+    }
+
+    /// Record one rectangle copied to the front buffer
+    #[inline]
+    pub fn note_blit(&mut self, width: u32, height: u32, bpp: u32) {
+        if self.enabled {
+            self.dirty_rects += 1;
+            self.blit_bytes = self.blit_bytes.saturating_add(width * height * bpp);
+        }
+    }
+
+    /// Close out the current frame
+    ///
+    /// Returns true when a new report was published (overlay needs redraw).
+    pub fn end_frame(&mut self, now_ns: u64) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        self.frames += 1;
+        let frame_us = (now_ns.saturating_sub(self.last_frame_ns) / 1000).min(u32::MAX as u64) as u32;
+        self.worst_frame_us = self.worst_frame_us.max(frame_us);
+        self.last_frame_ns = now_ns;
+
+        let elapsed_us = now_ns.saturating_sub(self.window_start_ns) / 1000;
+        if elapsed_us < REPORT_INTERVAL_MS as u64 * 1000 {
+            return false;
+        }
+
+        let heap = crate::mm::heap::stats();
+        self.report = FrameReport {
+            frames: self.frames,
+            avg_frame_us: (elapsed_us / self.frames as u64) as u32,
+            worst_frame_us: self.worst_frame_us,
+            dirty_rects: self.dirty_rects / self.frames,
+            blit_bytes: self.blit_bytes / self.frames,
+            heap_used: heap.used,
+            heap_free: heap.free,
+            idle_pct: idle::percent(self.idle_start, idle::sample()),
+        };
+        self.restart(now_ns);
+        true
+    }
+
+    /// Most recent report
+    pub fn report(&self) -> FrameReport {
+        self.report
+    }
+
+    /// Screen area covered by the overlay
+    pub fn overlay_rect(&self, screen_width: u32) -> (i32, i32, u32, u32) {
+        let w = (OVERLAY_COLS * font::FONT_WIDTH) as u32 + 8;
+        let h = (OVERLAY_LINES * font::FONT_HEIGHT) as u32 + 8;
+        let x = screen_width as i32 - w as i32 - OVERLAY_MARGIN;
+        (x, OVERLAY_MARGIN, w, h)
+    }
+
+    /// Draw the overlay with the latest report
+    pub fn draw_overlay(&self, fb: &mut Framebuffer) {
+        let (x, y, w, h) = self.overlay_rect(fb.width);
+        let r = &self.report;
+
+        fb.fill_rect(x, y, w, h, Color::BLACK);
+        fb.draw_rect(x, y, w, h, Color::PALEGREEN);
+
+        let mut line = String::new();
+        let mut ty = y + 4;
+        let mut emit = |fb: &mut Framebuffer, line: &mut String| {
+            fb.draw_string(x + 4, ty, line, Color::PALEGREEN, Some(Color::BLACK));
+            ty += font::FONT_HEIGHT as i32;
+            line.clear();
+        };
+
+        let fps = r.fps_x10();
+        let _ = write!(line, "fps   {}.{}", fps / 10, fps % 10);
+        emit(fb, &mut line);
+        let _ = write!(line, "frame {}.{}ms max {}.{}",
+            r.avg_frame_us / 1000, (r.avg_frame_us % 1000) / 100,
+            r.worst_frame_us / 1000, (r.worst_frame_us % 1000) / 100);
+        emit(fb, &mut line);
+        let _ = write!(line, "dirty {} rects", r.dirty_rects);
+        emit(fb, &mut line);
+        let _ = write!(line, "blit  {} KB/frame", r.blit_bytes / 1024);
+        emit(fb, &mut line);
+        let _ = write!(line, "heap  {}K/{}K",
+            r.heap_used / 1024, (r.heap_used + r.heap_free) / 1024);
+        emit(fb, &mut line);
+        let _ = write!(line, "idle  {}%", r.idle_pct);
+        emit(fb, &mut line);
+    }
+}
@@ -0,0 +1,315 @@
+//! Input Recording
+//!
+//! Records the raw input the GUI event loop is handed (keys, pointer
+//! samples, wheel notches) with the time since recording began, and plays
+//! a recording back through the same entry points, so a session (window
+//! drags, focus changes, typing in the terminal) can be re-run under QEMU
+//! without a human. `record start` and `record stop [file]` capture one;
+//! `record play <file>`, or `replay=<name>` on the command line, plays one
+//! back. A boot replay looks for `<name>` in an initrd volume first
+//! (`make run RAMDISK=initrd.cpio`), then as a path.
+//!
+//! Playback is deterministic: each input is delivered stamped with its
+//! recorded offset from the start of playback, not the time the frame
+//! happened to handle it, so double clicks and key repeats time out the
+//! same way. Live keys and pointer samples are ignored until it ends. The
+//! end is logged ("replay finished") for a harness watching the serial
+//! log.
+//!
+//! Recordings are text, one input per line, so tests can be written by
+//! hand:
+//!
+//! ```text
+//! 0 ptr 400 300 0
+//! 120 ptr 400 300 1
+//! 250 key 1e down 61
+//! 260 key 1e up 61
+//! 900 wheel 1
+//! ```
+//!
+//! `ptr` is a position and the button bits (1 left, 2 right, 4 middle);
+//! `key` is the `KeyCode` (hex), `down` or `up`, the character typed (hex,
+//! or `-`), then `ctrl` and/or `alt` if held. `#` starts a comment.
+//!
+//! The recorder itself doesn't need the window system; without it a
+//! recording just has nothing to play into.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::drivers::keyboard::{BufferedKey, KeyCode};
+use crate::fs::{OpenFlags, vfs::VFS};
+
+/// Longest recording kept (in steps)
+pub const MAX_STEPS: usize = 16384;
+
+/// Largest recording file read
+const MAX_FILE_SIZE: usize = 1 << 20;
+
+/// One recorded input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Key { keycode: KeyCode, ascii: Option<char>, pressed: bool, ctrl: bool, alt: bool },
+    Pointer { x: i32, y: i32, buttons: u8 },
+    Wheel { delta: i32 },
+}
+
+impl Input {
+    pub fn from_key(key: &BufferedKey) -> Self {
+        Self::Key { keycode: key.keycode, ascii: key.ascii, pressed: key.pressed, ctrl: key.ctrl, alt: key.alt }
+    }
+}
+
+/// An input and when it came, in ms from the start of the recording
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    pub offset_ms: u32,
+    pub input: Input,
+}
+
+impl Step {
+    /// Write as one line of a recording
+    pub fn format(&self, out: &mut String) {
+        let _ = write!(out, "{} ", self.offset_ms);
+        let _ = match self.input {
+            Input::Key { keycode, ascii, pressed, ctrl, alt } => {
+                let _ = write!(out, "key {:02x} {} ", keycode as u8, if pressed { "down" } else { "up" });
+                let _ = match ascii {
+                    Some(c) => write!(out, "{:x}", c as u32),
+                    None => write!(out, "-"),
+                };
+                write!(out, "{}{}", if ctrl { " ctrl" } else { "" }, if alt { " alt" } else { "" })
+            }
+            Input::Pointer { x, y, buttons } => write!(out, "ptr {} {} {}", x, y, buttons),
+            Input::Wheel { delta } => write!(out, "wheel {}", delta),
+        };
+        out.push('\n');
+    }
+
+    /// Read one line of a recording (None for a malformed line)
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_ascii_whitespace();
+        let offset_ms = words.next()?.parse().ok()?;
+        let input = match words.next()? {
+            "key" => {
+                let code = u8::from_str_radix(words.next()?, 16).ok()?;
+                let pressed = match words.next()? {
+                    "down" => true,
+                    "up" => false,
+                    _ => return None,
+                };
+                let ascii = match words.next()? {
+                    "-" => None,
+                    hex => Some(char::from_u32(u32::from_str_radix(hex, 16).ok()?)?),
+                };
+                let (mut ctrl, mut alt) = (false, false);
+                for word in words.by_ref() {
+                    match word {
+                        "ctrl" => ctrl = true,
+                        "alt" => alt = true,
+                        _ => return None,
+                    }
+                }
+                Input::Key { keycode: KeyCode::from_code(code), ascii, pressed, ctrl, alt }
+            }
+            "ptr" => Input::Pointer {
+                x: words.next()?.parse().ok()?,
+                y: words.next()?.parse().ok()?,
+                buttons: words.next()?.parse().ok()?,
+            },
+            "wheel" => Input::Wheel { delta: words.next()?.parse().ok()? },
+            _ => return None,
+        };
+        words.next().is_none().then_some(Self { offset_ms, input })
+    }
+}
+
+/// Parse a whole recording; steps must be in time order
+pub fn parse(text: &str) -> Result<Vec<Step>, &'static str> {
+    let mut steps = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let step = Step::parse(line).ok_or("Bad recording line")?;
+        if steps.last().is_some_and(|last: &Step| last.offset_ms > step.offset_ms) {
+            return Err("Recording out of time order");
+        }
+        if steps.len() == MAX_STEPS {
+            return Err("Recording too long");
+        }
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+// =============================================================================
+// Recorder State
+// =============================================================================
+
+struct Recording {
+    start_ms: u32,
+    steps: Vec<Step>,
+}
+
+struct Playback {
+    steps: Vec<Step>,
+    next: usize,
+    /// Time the first step is due from (set by the first `next_due`)
+    start_ms: Option<u32>,
+}
+
+static mut RECORDING: Option<Recording> = None;
+static mut PLAYBACK: Option<Playback> = None;
+
+fn recording() -> &'static mut Option<Recording> {
+    unsafe { &mut *core::ptr::addr_of_mut!(RECORDING) }
+}
+
+fn playback() -> &'static mut Option<Playback> {
+    unsafe { &mut *core::ptr::addr_of_mut!(PLAYBACK) }
+}
+
+pub fn is_recording() -> bool {
+    recording().is_some()
+}
+
+pub fn is_playing() -> bool {
+    playback().is_some()
+}
+
+/// Start capturing input (dropping a recording in progress)
+pub fn start_recording(now_ms: u32) -> Result<(), &'static str> {
+    if is_playing() {
+        return Err("Replay in progress");
+    }
+    *recording() = Some(Recording { start_ms: now_ms, steps: Vec::new() });
+    Ok(())
+}
+
+/// Stop capturing; returns what was captured
+pub fn stop_recording() -> Option<Vec<Step>> {
+    recording().take().map(|r| r.steps)
+}
+
+/// Input handed to the event loop at `time_ms` (while recording; input
+/// being played back isn't captured again)
+pub fn capture(input: Input, time_ms: u32) {
+    if is_playing() {
+        return;
+    }
+    let Some(rec) = recording().as_mut() else {
+        return;
+    };
+    if rec.steps.len() < MAX_STEPS {
+        // Stamps can trail the start by a frame (packets arrive before
+        // the command runs); keep the order anyway
+        let offset_ms = time_ms.saturating_sub(rec.start_ms)
+            .max(rec.steps.last().map_or(0, |s| s.offset_ms));
+        rec.steps.push(Step { offset_ms, input });
+    }
+}
+
+/// Text form of a recording
+pub fn format(steps: &[Step]) -> String {
+    let mut text = String::new();
+    for step in steps {
+        step.format(&mut text);
+    }
+    text
+}
+
+/// Play `steps` back, starting with the next frame
+pub fn play(steps: Vec<Step>) -> Result<usize, &'static str> {
+    if is_recording() {
+        return Err("Recording in progress");
+    }
+    let count = steps.len();
+    *playback() = Some(Playback { steps, next: 0, start_ms: None });
+    Ok(count)
+}
+
+/// Stop a replay early
+pub fn stop_playback() -> bool {
+    playback().take().is_some()
+}
+
+/// Next recorded input due by `now_ms`, with the time to stamp it with
+///
+/// Ends playback (and logs it) after the last step.
+pub fn next_due(now_ms: u32) -> Option<(Input, u32)> {
+    let play = playback().as_mut()?;
+    let start_ms = *play.start_ms.get_or_insert(now_ms);
+    let due = match play.steps.get(play.next) {
+        Some(step) if now_ms.wrapping_sub(start_ms) >= step.offset_ms => {
+            play.next += 1;
+            Some((step.input, start_ms.wrapping_add(step.offset_ms)))
+        }
+        Some(_) => return None,
+        None => None,
+    };
+    if play.next == play.steps.len() {
+        crate::klog!(Info, "input", "replay finished: {} inputs", play.steps.len());
+        *playback() = None;
+    }
+    due
+}
+
+// =============================================================================
+// Files
+// =============================================================================
+
+/// Load a recording from the initrd (if there is one) or a path, and play it
+pub fn play_source(name: &str) -> Result<usize, &'static str> {
+    let data = read_initrd(name).map_or_else(|| read_file(name), Ok)?;
+    let text = core::str::from_utf8(&data).map_err(|_| "Recording not text")?;
+    play(parse(text)?)
+}
+
+/// `name` from the first initrd volume that has it
+fn read_initrd(name: &str) -> Option<Vec<u8>> {
+    use crate::fs::vfs::VolumeKind;
+    crate::fs::init::volumes().iter()
+        .filter(|v| v.kind == Some(VolumeKind::Initrd))
+        .find_map(|v| crate::fs::cpio::read_file(v.id, name).ok())
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, &'static str> {
+    let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+    let fd = vfs.open(path, OpenFlags::read_only()).map_err(|e| e.as_str())?;
+    let mut data = Vec::new();
+    let mut buf = [0u8; 512];
+    let result = loop {
+        match vfs.read(fd, &mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(_) if data.len() >= MAX_FILE_SIZE => break Err("Recording too large"),
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) => break Err(e.as_str()),
+        }
+    };
+    let _ = vfs.close(fd);
+    result.map(|_| data)
+}
+
+/// Write a recording to `path`
+pub fn save(path: &str, steps: &[Step]) -> Result<(), &'static str> {
+    let text = format(steps);
+    let bytes = text.as_bytes();
+    let vfs = unsafe { &mut *core::ptr::addr_of_mut!(VFS) };
+    let flags = OpenFlags::write_only().with_create().with_truncate();
+    let fd = vfs.open(path, flags).map_err(|e| e.as_str())?;
+    let mut written = 0;
+    let result = loop {
+        if written == bytes.len() {
+            break Ok(());
+        }
+        match vfs.write(fd, &bytes[written..]) {
+            Ok(0) => break Err("Short write"),
+            Ok(n) => written += n,
+            Err(e) => break Err(e.as_str()),
+        }
+    };
+    let _ = vfs.close(fd);
+    result
+}
@@ -0,0 +1,222 @@
+//! Screensaver
+//!
+//! Idle detection plus a full-screen animation drawn with Framebuffer
+//! primitives. The desktop owns a `Screensaver`, feeds it input activity,
+//! and hands it the back buffer while it is active. Starting and stopping
+//! go through the WM EventChain (see `WmEventDispatcher::dispatch_screensaver`).
+
+use super::{Color, Framebuffer};
+
+/// Default idle time before the screensaver starts (5 minutes)
+pub const DEFAULT_IDLE_TIMEOUT_MS: u32 = 5 * 60 * 1000;
+
+/// Minimum time between animation frames (~30 fps)
+const FRAME_INTERVAL_MS: u32 = 33;
+
+/// Number of stars in the starfield
+const NUM_STARS: usize = 96;
+
+/// Star depth range (fixed-point z)
+const STAR_MAX_Z: i32 = 1024;
+
+/// Text shown by the bouncing logo
+const LOGO_TEXT: &str = "Rustacean OS";
+
+/// Animation style
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreensaverMode {
+    /// Stars flying toward the viewer
+    Starfield,
+    /// Logo bouncing off the screen edges
+    BouncingLogo,
+}
+
+#[derive(Clone, Copy)]
+struct Star {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+/// Screensaver state
+pub struct Screensaver {
+    mode: ScreensaverMode,
+    /// Idle time before activation (0 = disabled)
+    timeout_ms: u32,
+    active: bool,
+    last_input_ms: u32,
+    last_frame_ms: u32,
+    rng: u32,
+    stars: [Star; NUM_STARS],
+    logo_x: i32,
+    logo_y: i32,
+    logo_dx: i32,
+    logo_dy: i32,
+    logo_color: Color,
+}
+
+impl Screensaver {
+    pub const fn new() -> Self {
+        Self {
+            mode: ScreensaverMode::Starfield,
+            timeout_ms: DEFAULT_IDLE_TIMEOUT_MS,
+            active: false,
+            last_input_ms: 0,
+            last_frame_ms: 0,
+            rng: 0x1234_5678,
+            stars: [Star { x: 0, y: 0, z: 0 }; NUM_STARS],
+            logo_x: 0,
+            logo_y: 0,
+            logo_dx: 3,
+            logo_dy: 2,
+            logo_color: Color::WHITE,
+        }
+    }
+
+    /// Set the animation style
+    pub fn set_mode(&mut self, mode: ScreensaverMode) {
+        self.mode = mode;
+    }
+
+    /// Set idle timeout in milliseconds (0 disables the screensaver)
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Is the screensaver currently showing?
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Record user input
+    pub fn note_input(&mut self, now_ms: u32) {
+        self.last_input_ms = now_ms;
+    }
+
+    /// Milliseconds since the last input
+    pub fn idle_ms(&self, now_ms: u32) -> u32 {
+        now_ms.wrapping_sub(self.last_input_ms)
+    }
+
+    /// Has the idle timeout elapsed?
+    pub fn should_activate(&self, now_ms: u32) -> bool {
+        !self.active && self.timeout_ms != 0 && self.idle_ms(now_ms) >= self.timeout_ms
+    }
+
+    /// Start the animation
+    pub fn activate(&mut self, now_ms: u32, width: u32, height: u32) {
+        self.active = true;
+        self.rng ^= now_ms | 1;
+        self.last_frame_ms = now_ms.wrapping_sub(FRAME_INTERVAL_MS);
+
+        for i in 0..NUM_STARS {
+            self.stars[i] = self.random_star();
+        }
+
+        self.logo_x = (width / 3) as i32;
+        self.logo_y = (height / 3) as i32;
+    }
+
+    /// Stop the animation
+    pub fn deactivate(&mut self, now_ms: u32) {
+        self.active = false;
+        self.last_input_ms = now_ms;
+    }
+
+    /// Render the next frame if one is due
+    ///
+    /// Returns true if the buffer was redrawn.
+    pub fn draw_frame(&mut self, fb: &mut Framebuffer, now_ms: u32) -> bool {
+        if !self.active || now_ms.wrapping_sub(self.last_frame_ms) < FRAME_INTERVAL_MS {
+            return false;
+        }
+        self.last_frame_ms = now_ms;
+
+        fb.clear(Color::BLACK);
+        match self.mode {
+            ScreensaverMode::Starfield => self.draw_starfield(fb),
+            ScreensaverMode::BouncingLogo => self.draw_logo(fb),
+        }
+        true
+    }
+
+    /// xorshift32
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x
+    }
+
+    fn random_star(&mut self) -> Star {
+        Star {
+            x: (self.next_random() % 2048) as i32 - 1024,
+            y: (self.next_random() % 2048) as i32 - 1024,
+            z: (self.next_random() % STAR_MAX_Z as u32) as i32 + 1,
+        }
+    }
+
+    fn draw_starfield(&mut self, fb: &mut Framebuffer) {
+        let cx = (fb.width / 2) as i32;
+        let cy = (fb.height / 2) as i32;
+
+        for i in 0..NUM_STARS {
+            let mut star = self.stars[i];
+            star.z -= 8;
+
+            // Project onto the screen; respawn stars that pass the viewer
+            // or leave the visible area
+            let (sx, sy) = if star.z > 0 {
+                (cx + star.x * 128 / star.z, cy + star.y * 128 / star.z)
+            } else {
+                (-1, -1)
+            };
+            if star.z <= 0 || sx < 0 || sy < 0 || sx >= fb.width as i32 || sy >= fb.height as i32 {
+                star = self.random_star();
+                star.z = STAR_MAX_Z;
+                self.stars[i] = star;
+                continue;
+            }
+            self.stars[i] = star;
+
+            // Closer stars are brighter and larger
+            let brightness = (255 - star.z * 200 / STAR_MAX_Z) as u8;
+            let size = if star.z < STAR_MAX_Z / 4 { 2 } else { 1 };
+            fb.fill_rect(sx, sy, size, size, Color::rgb(brightness, brightness, brightness));
+        }
+    }
+
+    fn draw_logo(&mut self, fb: &mut Framebuffer) {
+        let w = fb.measure_string(LOGO_TEXT) as i32;
+        let h = 16;
+        let max_x = fb.width as i32 - w;
+        let max_y = fb.height as i32 - h;
+
+        self.logo_x += self.logo_dx;
+        self.logo_y += self.logo_dy;
+
+        let mut bounced = false;
+        if self.logo_x <= 0 || self.logo_x >= max_x {
+            self.logo_dx = -self.logo_dx;
+            self.logo_x = self.logo_x.clamp(0, max_x.max(0));
+            bounced = true;
+        }
+        if self.logo_y <= 0 || self.logo_y >= max_y {
+            self.logo_dy = -self.logo_dy;
+            self.logo_y = self.logo_y.clamp(0, max_y.max(0));
+            bounced = true;
+        }
+        if bounced {
+            let c = self.next_random();
+            self.logo_color = Color::rgb(
+                (c & 0x7F) as u8 + 128,
+                ((c >> 8) & 0x7F) as u8 + 128,
+                ((c >> 16) & 0x7F) as u8 + 128,
+            );
+        }
+
+        fb.draw_string(self.logo_x, self.logo_y, LOGO_TEXT, self.logo_color, None);
+    }
+}
@@ -0,0 +1,263 @@
+//! Scroll View
+//!
+//! A viewport onto content bigger than the area it is shown in. The owner
+//! says how big its content is and draws it through `draw`, which clips
+//! to the viewport and shifts by the scroll position; the view draws the
+//! scrollbars (only on an axis that overflows) and handles wheel, key and
+//! scrollbar click scrolling.
+//!
+//! The view keeps no geometry of its own: every call takes the area it
+//! occupies (screen coordinates), so it follows its window around. With
+//! `follow_end` it stays pinned to the bottom as content grows, like a
+//! terminal, until the user scrolls up.
+
+use crate::drivers::keyboard::KeyCode;
+use super::{theme, Framebuffer, Point, Rect};
+
+/// Scrollbar thickness
+pub const SCROLLBAR_SIZE: u32 = 12;
+
+/// Shortest scrollbar thumb
+const MIN_THUMB: u32 = 16;
+
+/// Lines scrolled per wheel notch
+const WHEEL_LINES: i32 = 3;
+
+/// Scroll state for one piece of content
+#[derive(Debug, Clone, Copy)]
+pub struct ScrollView {
+    content_width: u32,
+    content_height: u32,
+    /// Requested offset (clamped when used)
+    x: u32,
+    y: u32,
+    /// Arrow key / wheel step
+    line_height: u32,
+    /// Stick to the bottom as content grows
+    follow_end: bool,
+    /// Currently stuck to the bottom
+    pinned: bool,
+}
+
+impl ScrollView {
+    /// Empty view scrolling `line_height` pixels per line
+    pub const fn new(line_height: u32) -> Self {
+        Self {
+            content_width: 0,
+            content_height: 0,
+            x: 0,
+            y: 0,
+            line_height,
+            follow_end: false,
+            pinned: false,
+        }
+    }
+
+    /// Stay at the bottom as content grows (until scrolled up)
+    pub const fn following_end(mut self) -> Self {
+        self.follow_end = true;
+        self.pinned = true;
+        self
+    }
+
+    /// Size of the content in pixels
+    pub fn set_content_size(&mut self, width: u32, height: u32) {
+        self.content_width = width;
+        self.content_height = height;
+    }
+
+    // =========================================================================
+    // Geometry
+    // =========================================================================
+
+    /// Which scrollbars `area` needs: (horizontal, vertical)
+    fn bars(&self, area: Rect) -> (bool, bool) {
+        let mut horizontal = self.content_width > area.width;
+        let vertical = self.content_height > area.height.saturating_sub(if horizontal { SCROLLBAR_SIZE } else { 0 });
+        // A vertical bar narrows the view and may force a horizontal one
+        if vertical && !horizontal {
+            horizontal = self.content_width > area.width.saturating_sub(SCROLLBAR_SIZE);
+        }
+        (horizontal, vertical)
+    }
+
+    /// Part of `area` the content shows in (without the scrollbars)
+    pub fn viewport(&self, area: Rect) -> Rect {
+        let (horizontal, vertical) = self.bars(area);
+        Rect::new(
+            area.x,
+            area.y,
+            area.width.saturating_sub(if vertical { SCROLLBAR_SIZE } else { 0 }),
+            area.height.saturating_sub(if horizontal { SCROLLBAR_SIZE } else { 0 }),
+        )
+    }
+
+    /// Largest offsets
+    fn max_offset(&self, area: Rect) -> (u32, u32) {
+        let view = self.viewport(area);
+        (
+            self.content_width.saturating_sub(view.width),
+            self.content_height.saturating_sub(view.height),
+        )
+    }
+
+    /// Scroll position: content pixel at the viewport's top left
+    pub fn offset(&self, area: Rect) -> (u32, u32) {
+        let (max_x, max_y) = self.max_offset(area);
+        let y = if self.pinned { max_y } else { self.y.min(max_y) };
+        (self.x.min(max_x), y)
+    }
+
+    // =========================================================================
+    // Scrolling
+    // =========================================================================
+
+    /// Scroll to a position (clamped); true if the view moved
+    pub fn scroll_to(&mut self, area: Rect, x: i64, y: i64) -> bool {
+        let before = self.offset(area);
+        let (max_x, max_y) = self.max_offset(area);
+        self.x = x.clamp(0, max_x as i64) as u32;
+        self.y = y.clamp(0, max_y as i64) as u32;
+        self.pinned = self.follow_end && self.y == max_y;
+        self.offset(area) != before
+    }
+
+    /// Scroll by a distance in pixels; true if the view moved
+    pub fn scroll_by(&mut self, area: Rect, dx: i32, dy: i32) -> bool {
+        let (x, y) = self.offset(area);
+        self.scroll_to(area, x as i64 + dx as i64, y as i64 + dy as i64)
+    }
+
+    /// Scroll to the bottom (and stay there if following the end)
+    pub fn scroll_to_end(&mut self, area: Rect) -> bool {
+        let x = self.offset(area).0;
+        self.scroll_to(area, x as i64, i64::MAX)
+    }
+
+    /// Scroll just enough to show `rect` (content coordinates), e.g. a
+    /// text cursor
+    pub fn ensure_visible(&mut self, area: Rect, rect: Rect) -> bool {
+        let view = self.viewport(area);
+        let (mut x, mut y) = self.offset(area);
+        if rect.right() > (x + view.width) as i32 {
+            x = (rect.right() - view.width as i32).max(0) as u32;
+        }
+        if rect.x < x as i32 {
+            x = rect.x.max(0) as u32;
+        }
+        if rect.bottom() > (y + view.height) as i32 {
+            y = (rect.bottom() - view.height as i32).max(0) as u32;
+        }
+        if rect.y < y as i32 {
+            y = rect.y.max(0) as u32;
+        }
+        self.scroll_to(area, x as i64, y as i64)
+    }
+
+    /// Wheel notches (positive scrolls down); true if the view moved
+    pub fn handle_wheel(&mut self, area: Rect, delta: i32) -> bool {
+        self.scroll_by(area, 0, delta * WHEEL_LINES * self.line_height as i32)
+    }
+
+    /// Arrows scroll a line, Page Up/Down a screen, Home/End to the ends
+    ///
+    /// Returns true if the key is a scrolling key (whether or not the view
+    /// could move), so the caller doesn't use it for anything else.
+    pub fn handle_key(&mut self, area: Rect, keycode: KeyCode) -> bool {
+        let line = self.line_height as i32;
+        let page = (self.viewport(area).height as i32 - line).max(line);
+        match keycode {
+            KeyCode::Up => self.scroll_by(area, 0, -line),
+            KeyCode::Down => self.scroll_by(area, 0, line),
+            KeyCode::Left => self.scroll_by(area, -line, 0),
+            KeyCode::Right => self.scroll_by(area, line, 0),
+            KeyCode::PageUp => self.scroll_by(area, 0, -page),
+            KeyCode::PageDown => self.scroll_by(area, 0, page),
+            KeyCode::Home => self.scroll_to(area, 0, 0),
+            KeyCode::End => self.scroll_to_end(area),
+            _ => return false,
+        };
+        true
+    }
+
+    /// A click in a scrollbar track pages towards it
+    ///
+    /// Returns true if (x, y) was on a scrollbar.
+    pub fn handle_click(&mut self, area: Rect, x: i32, y: i32) -> bool {
+        let view = self.viewport(area);
+        if let Some((track, thumb)) = self.vertical_bar(area) {
+            if track.contains(x, y) {
+                let page = view.height as i32;
+                self.scroll_by(area, 0, if y < thumb.y { -page } else if y >= thumb.bottom() { page } else { 0 });
+                return true;
+            }
+        }
+        if let Some((track, thumb)) = self.horizontal_bar(area) {
+            if track.contains(x, y) {
+                let page = view.width as i32;
+                self.scroll_by(area, if x < thumb.x { -page } else if x >= thumb.right() { page } else { 0 }, 0);
+                return true;
+            }
+        }
+        false
+    }
+
+    // =========================================================================
+    // Drawing
+    // =========================================================================
+
+    /// Thumb position and length along a track
+    fn thumb(track: u32, view: u32, content: u32, offset: u32) -> (u32, u32) {
+        let length = ((track as u64 * view as u64 / content.max(1) as u64) as u32)
+            .clamp(MIN_THUMB.min(track), track);
+        let travel = track - length;
+        let range = content.saturating_sub(view).max(1);
+        (((travel as u64 * offset as u64) / range as u64) as u32, length)
+    }
+
+    /// Vertical scrollbar (track, thumb), if shown
+    fn vertical_bar(&self, area: Rect) -> Option<(Rect, Rect)> {
+        if !self.bars(area).1 {
+            return None;
+        }
+        let view = self.viewport(area);
+        let track = Rect::new(view.right(), area.y, SCROLLBAR_SIZE, view.height);
+        let (pos, length) = Self::thumb(track.height, view.height, self.content_height, self.offset(area).1);
+        Some((track, Rect::new(track.x, track.y + pos as i32, track.width, length)))
+    }
+
+    /// Horizontal scrollbar (track, thumb), if shown
+    fn horizontal_bar(&self, area: Rect) -> Option<(Rect, Rect)> {
+        if !self.bars(area).0 {
+            return None;
+        }
+        let view = self.viewport(area);
+        let track = Rect::new(area.x, view.bottom(), view.width, SCROLLBAR_SIZE);
+        let (pos, length) = Self::thumb(track.width, view.width, self.content_width, self.offset(area).0);
+        Some((track, Rect::new(track.x + pos as i32, track.y, length, track.height)))
+    }
+
+    /// Draw content and scrollbars into `area`
+    ///
+    /// `draw_content` gets the framebuffer clipped to the viewport and the
+    /// screen position of the content's top left corner.
+    pub fn draw(&self, fb: &mut Framebuffer, area: Rect, draw_content: impl FnOnce(&mut Framebuffer, Point)) {
+        let view = self.viewport(area);
+        let (x, y) = self.offset(area);
+
+        let outer = fb.set_clip(view.intersect(&fb.clip()));
+        draw_content(fb, Point::new(view.x - x as i32, view.y - y as i32));
+        fb.set_clip(outer);
+
+        let theme = theme::current();
+        for (track, thumb) in self.vertical_bar(area).into_iter().chain(self.horizontal_bar(area)) {
+            fb.fill_rect(track.x, track.y, track.width, track.height, theme.window_bg);
+            fb.fill_rect(thumb.x, thumb.y, thumb.width, thumb.height, theme.scrollbar);
+            fb.draw_3d_rect(thumb.x, thumb.y, thumb.width, thumb.height, true);
+        }
+        // Corner between two bars
+        if let (Some((v, _)), Some((h, _))) = (self.vertical_bar(area), self.horizontal_bar(area)) {
+            fb.fill_rect(v.x, h.y, SCROLLBAR_SIZE, SCROLLBAR_SIZE, theme.window_bg);
+        }
+    }
+}
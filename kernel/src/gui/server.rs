@@ -0,0 +1,367 @@
+//! Window Server
+//!
+//! Lets user programs own desktop windows through the window syscalls
+//! (see `syscall::gui`). A program creates a window, fills its content
+//! area by presenting a pixel buffer, and reads the input events the
+//! desktop queues for it. Instead of copying each frame in, a program
+//! can attach a shared memory object (`mm::shm`) as the window's buffer;
+//! the desktop then draws straight from its frames and presenting only
+//! asks for a redraw. These are ordinary desktop windows owned by
+//! the program, so the WM chain's permission check and exit cleanup
+//! apply to them as to any other.
+//!
+//! Pixels are 0x00RRGGBB, row-major, exactly filling the content area.
+//!
+//! A window's own buffer is PMM pages, like a shared memory object's, so
+//! it goes back when the window closes; a program's buffers together may
+//! take at most `MAX_SURFACE_MEMORY`.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use super::window::{BORDER_WIDTH, TITLE_HEIGHT};
+use super::{theme, Color, Framebuffer, Window};
+use crate::mm::pmm::{self, PAGE_SIZE};
+use crate::mm::shm;
+use crate::sched::Pid;
+
+/// Most program windows at once
+pub const MAX_SURFACES: usize = 16;
+
+/// Largest content area a program may ask for
+pub const MAX_SURFACE_SIZE: u32 = 1024;
+
+/// Most bytes of window buffers one program may own (shared memory
+/// buffers are counted by `mm::shm` instead)
+pub const MAX_SURFACE_MEMORY: usize = 2 * 1024 * 1024;
+
+/// Window buffer limit hit
+pub const ESURFACE_MEMORY: &str = "window memory limit exceeded";
+
+/// Events kept per window before new ones are dropped
+const EVENT_QUEUE_LIMIT: usize = 64;
+
+/// Window event kinds
+pub mod event_kind {
+    /// a = key code (set 1 scancode), b = character (0 if none), c = modifiers
+    pub const KEY: u32 = 1;
+    /// a = x, b = y (content coordinates)
+    pub const MOUSE_MOVE: u32 = 2;
+    /// a = x, b = y, c = button bit (1 left, 2 right, 4 middle) | 0x100 if pressed
+    pub const MOUSE_BUTTON: u32 = 3;
+    /// The window should close (the system is shutting down)
+    pub const CLOSE: u32 = 4;
+    /// a = x, b = y, c = wheel notches (i32, positive = scroll down)
+    pub const WHEEL: u32 = 5;
+}
+
+/// Modifier bits in a KEY event
+pub mod modifier {
+    pub const CTRL: u32 = 0x01;
+}
+
+/// Event record copied to user space
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct WinEvent {
+    pub kind: u32,
+    pub a: u32,
+    pub b: u32,
+    pub c: u32,
+}
+
+/// Where a window's pixels live (frames in pixel order)
+enum Buffer {
+    /// Pages of the window's own, filled by present
+    Owned(Vec<u32>),
+    /// Frames of a shared memory object (one reference held per frame)
+    Shared(Vec<u32>),
+}
+
+impl Buffer {
+    /// `width` x `height` pages of the window's own, cleared to the
+    /// theme's background
+    fn alloc(width: u32, height: u32) -> Result<Self, &'static str> {
+        let pages = ((width * height * 4) as usize).div_ceil(PAGE_SIZE);
+        let mut frames = Vec::with_capacity(pages);
+        for _ in 0..pages {
+            let Some(frame) = pmm::alloc_page() else {
+                Self::Owned(frames).release();
+                return Err(crate::mm::oom::ENOMEM);
+            };
+            frames.push(frame as u32);
+        }
+        let buffer = Self::Owned(frames);
+        buffer.clear();
+        Ok(buffer)
+    }
+
+    /// Fill with the theme's background
+    fn clear(&self) {
+        let background = theme::current().window_bg.to_u32();
+        for &frame in self.frames() {
+            let words = unsafe { core::slice::from_raw_parts_mut(frame as *mut u32, PAGE_SIZE / 4) };
+            words.fill(background);
+        }
+    }
+
+    fn frames(&self) -> &[u32] {
+        match self {
+            Self::Owned(frames) | Self::Shared(frames) => frames,
+        }
+    }
+
+    fn pixel(&self, index: usize) -> u32 {
+        let offset = index * 4;
+        let frame = self.frames()[offset / PAGE_SIZE] as usize;
+        unsafe { core::ptr::read_volatile((frame + offset % PAGE_SIZE) as *const u32) }
+    }
+
+    /// Bytes of the window's own pages (none for a shared buffer)
+    fn owned_bytes(&self) -> usize {
+        match self {
+            Self::Owned(frames) => frames.len() * PAGE_SIZE,
+            Self::Shared(_) => 0,
+        }
+    }
+
+    fn release(&self) {
+        match self {
+            Self::Owned(frames) => {
+                for &frame in frames {
+                    unsafe { pmm::free_page(frame as usize) };
+                }
+            }
+            Self::Shared(frames) => shm::put_frames(frames),
+        }
+    }
+}
+
+/// A program's window content and input queue
+struct Surface {
+    window_id: u32,
+    owner: Pid,
+    width: u32,
+    height: u32,
+    buffer: Buffer,
+    events: VecDeque<WinEvent>,
+}
+
+static mut SURFACES: [Option<Surface>; MAX_SURFACES] = [const { None }; MAX_SURFACES];
+
+fn surfaces() -> &'static mut [Option<Surface>; MAX_SURFACES] {
+    unsafe { &mut *core::ptr::addr_of_mut!(SURFACES) }
+}
+
+fn find(window_id: u32) -> Option<&'static mut Surface> {
+    surfaces().iter_mut().flatten().find(|s| s.window_id == window_id)
+}
+
+/// Bytes of window buffers `owner` has
+fn owner_bytes(owner: Pid) -> usize {
+    surfaces().iter().flatten().filter(|s| s.owner == owner).map(|s| s.buffer.owned_bytes()).sum()
+}
+
+/// Check that `owner` may have `bytes` more of window buffers
+fn check_memory(owner: Pid, bytes: usize) -> Result<(), &'static str> {
+    if owner_bytes(owner) + bytes.next_multiple_of(PAGE_SIZE) > MAX_SURFACE_MEMORY {
+        return Err(ESURFACE_MEMORY);
+    }
+    Ok(())
+}
+
+/// Surface of a window, if the caller owns it
+fn owned(window_id: u32, caller: Pid) -> Result<&'static mut Surface, &'static str> {
+    let surface = find(window_id).ok_or("no such window")?;
+    if surface.owner != caller {
+        return Err("owned by another process");
+    }
+    Ok(surface)
+}
+
+// =============================================================================
+// Program Side (syscalls)
+// =============================================================================
+
+/// Create a window with a `width` x `height` content area for `owner`
+///
+/// Returns the window ID.
+pub fn create(owner: Pid, title: &str, width: u32, height: u32) -> Result<u32, &'static str> {
+    if width == 0 || height == 0 || width > MAX_SURFACE_SIZE || height > MAX_SURFACE_SIZE {
+        return Err("bad window size");
+    }
+    let slot = surfaces().iter().position(|s| s.is_none()).ok_or("too many windows")?;
+    // Checked up front so the program sees the limit's own error
+    if let Some(task) = crate::exec::task_of(owner) {
+        if task.windows >= task.limits.windows {
+            return Err(crate::sched::rlimit::EWINDOWS);
+        }
+    }
+    check_memory(owner, (width * height * 4) as usize)?;
+    let desktop = super::desktop::get().ok_or("no desktop")?;
+    let buffer = Buffer::alloc(width, height)?;
+
+    // Cascade new windows so they don't stack exactly
+    let offset = 40 + 24 * slot as i32;
+    let Some(id) = desktop.create_window(title, offset, offset,
+        width + BORDER_WIDTH * 2, height + TITLE_HEIGHT + BORDER_WIDTH)
+    else {
+        buffer.release();
+        return Err("window refused");
+    };
+
+    surfaces()[slot] = Some(Surface {
+        window_id: id,
+        owner,
+        width,
+        height,
+        buffer,
+        events: VecDeque::new(),
+    });
+    Ok(id)
+}
+
+/// Destroy a window (through the WM chain, which checks ownership)
+pub fn destroy(window_id: u32) -> Result<(), &'static str> {
+    find(window_id).ok_or("no such window")?;
+    let desktop = super::desktop::get().ok_or("no desktop")?;
+    if !desktop.destroy_window_id(window_id) {
+        return Err("owned by another process");
+    }
+    Ok(())
+}
+
+/// Content size of a caller's window
+pub fn size(window_id: u32, caller: Pid) -> Result<(u32, u32), &'static str> {
+    let surface = owned(window_id, caller)?;
+    Ok((surface.width, surface.height))
+}
+
+/// Replace a window's content with `fill`, which is handed the pixel
+/// buffer's bytes a page at a time, in order
+pub fn present(
+    window_id: u32,
+    caller: Pid,
+    mut fill: impl FnMut(&mut [u8]) -> Result<(), &'static str>,
+) -> Result<(), &'static str> {
+    let surface = owned(window_id, caller)?;
+    let Buffer::Owned(frames) = &surface.buffer else {
+        return Err("window uses shared memory");
+    };
+    let mut left = (surface.width * surface.height * 4) as usize;
+    for &frame in frames {
+        let len = left.min(PAGE_SIZE);
+        fill(unsafe { core::slice::from_raw_parts_mut(frame as *mut u8, len) })?;
+        left -= len;
+    }
+    redraw();
+    Ok(())
+}
+
+/// Redraw a window after its shared buffer changed
+pub fn damage(window_id: u32, caller: Pid) -> Result<(), &'static str> {
+    owned(window_id, caller)?;
+    redraw();
+    Ok(())
+}
+
+/// Use a caller's shared memory object as a window's buffer
+///
+/// Object ID 0 goes back to the window's own buffer (cleared to the
+/// background).
+pub fn attach(window_id: u32, caller: Pid, shm_id: u32) -> Result<(), &'static str> {
+    let surface = owned(window_id, caller)?;
+    let buffer = if shm_id == 0 {
+        if let Buffer::Owned(_) = surface.buffer {
+            surface.buffer.clear();
+            redraw();
+            return Ok(());
+        }
+        check_memory(caller, (surface.width * surface.height * 4) as usize)?;
+        Buffer::alloc(surface.width, surface.height)?
+    } else {
+        if shm::size(shm_id, caller)? < surface.width * surface.height * 4 {
+            return Err("object smaller than window");
+        }
+        Buffer::Shared(shm::get_frames(shm_id, caller)?)
+    };
+    core::mem::replace(&mut surface.buffer, buffer).release();
+    redraw();
+    Ok(())
+}
+
+fn redraw() {
+    if let Some(desktop) = super::desktop::get() {
+        desktop.mark_dirty();
+    }
+}
+
+/// Take the next input event for a caller's window
+pub fn next_event(window_id: u32, caller: Pid) -> Result<Option<WinEvent>, &'static str> {
+    Ok(owned(window_id, caller)?.events.pop_front())
+}
+
+/// Would reading an event of this window return now (or fail)?
+pub fn readable(window_id: u32) -> bool {
+    find(window_id).map_or(true, |s| !s.events.is_empty())
+}
+
+// =============================================================================
+// Desktop Side
+// =============================================================================
+
+/// Is this window a program's?
+pub fn is_client(window_id: u32) -> bool {
+    find(window_id).is_some()
+}
+
+/// Queue an input event for a program window
+pub fn post(window_id: u32, event: WinEvent) {
+    if let Some(surface) = find(window_id) {
+        if surface.events.len() < EVENT_QUEUE_LIMIT {
+            surface.events.push_back(event);
+        }
+    }
+}
+
+/// Ask every program window to close, returning how many there are
+pub fn request_close_all() -> usize {
+    let ids: alloc::vec::Vec<u32> = surfaces().iter().flatten().map(|s| s.window_id).collect();
+    for &id in &ids {
+        post(id, WinEvent { kind: event_kind::CLOSE, ..WinEvent::default() });
+    }
+    ids.len()
+}
+
+/// Number of program windows open
+pub fn client_count() -> usize {
+    surfaces().iter().flatten().count()
+}
+
+/// The desktop destroyed a window; drop its surface
+pub fn forget(window_id: u32) {
+    for slot in surfaces().iter_mut() {
+        if slot.as_ref().is_some_and(|s| s.window_id == window_id) {
+            if let Some(surface) = slot.take() {
+                surface.buffer.release();
+            }
+        }
+    }
+}
+
+/// Draw a program window's content; false if it isn't one
+pub fn draw(fb: &mut Framebuffer, window: &Window) -> bool {
+    let Some(surface) = find(window.id) else {
+        return false;
+    };
+    let content = window.content_rect_abs();
+    let width = surface.width.min(content.width);
+    let height = surface.height.min(content.height);
+    for y in 0..height {
+        let row = (y * surface.width) as usize;
+        for x in 0..width {
+            let pixel = surface.buffer.pixel(row + x as usize);
+            fb.set_pixel(content.x + x as i32, content.y + y as i32, Color::from_u32(pixel));
+        }
+    }
+    true
+}
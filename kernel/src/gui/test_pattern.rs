@@ -0,0 +1,179 @@
+//! Display Test Patterns
+//!
+//! A utility window for checking the display path on real hardware:
+//!
+//! - Gradients: gray and per-channel ramps; banding shows the pixel
+//!   format's depth and what the dither mode does about it
+//! - Color bars: 75% bars plus full white and black, for channel order
+//!   and levels
+//! - Grid: one-pixel checkerboard and stripes, which LCD panel expansion
+//!   visibly blurs
+//!
+//! Clicking the pattern picks the color under the pointer. It is read
+//! back from the rendered frame with `get_pixel`, so the status line
+//! shows what the framebuffer actually stored next to what was drawn.
+//! Clicking the status line cycles through the patterns.
+
+use alloc::string::String;
+use core::fmt::Write;
+use super::{font, theme, Color, Framebuffer, Point, Rect};
+
+/// Status line height (below the pattern)
+const STATUS_HEIGHT: u32 = font::FONT_HEIGHT as u32 + 4;
+
+/// 75% color bars, left to right
+const BARS: [Color; 7] = [
+    Color::rgb(191, 191, 191),
+    Color::rgb(191, 191, 0),
+    Color::rgb(0, 191, 191),
+    Color::rgb(0, 191, 0),
+    Color::rgb(191, 0, 191),
+    Color::rgb(191, 0, 0),
+    Color::rgb(0, 0, 191),
+];
+
+/// Which pattern is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Gradients,
+    ColorBars,
+    Grid,
+}
+
+impl Pattern {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gradients => "gradients",
+            Self::ColorBars => "bars",
+            Self::Grid => "grid",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gradients" => Some(Self::Gradients),
+            "bars" => Some(Self::ColorBars),
+            "grid" => Some(Self::Grid),
+            _ => None,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Self::Gradients => Self::ColorBars,
+            Self::ColorBars => Self::Grid,
+            Self::Grid => Self::Gradients,
+        }
+    }
+
+    /// Color at (x, y) of a `width` x `height` pattern
+    fn color_at(self, x: u32, y: u32, width: u32, height: u32) -> Color {
+        let (width, height) = (width.max(1), height.max(1));
+        match self {
+            Self::Gradients => {
+                let level = (x * 255 / (width - 1).max(1)) as u8;
+                match y * 4 / height {
+                    0 => Color::rgb(level, level, level),
+                    1 => Color::rgb(level, 0, 0),
+                    2 => Color::rgb(0, level, 0),
+                    _ => Color::rgb(0, 0, level),
+                }
+            }
+            Self::ColorBars => {
+                if y < height * 3 / 4 {
+                    BARS[(x * BARS.len() as u32 / width) as usize]
+                } else if x < width / 2 {
+                    Color::WHITE
+                } else {
+                    Color::BLACK
+                }
+            }
+            Self::Grid => {
+                let on = if y < height / 2 {
+                    (x ^ y) & 1 != 0
+                } else if x < width / 2 {
+                    x & 1 != 0
+                } else {
+                    y & 1 != 0
+                };
+                if on { Color::WHITE } else { Color::BLACK }
+            }
+        }
+    }
+}
+
+/// Test pattern window state
+#[derive(Debug, Clone, Copy)]
+pub struct TestPattern {
+    pub pattern: Pattern,
+    /// Picked point, relative to the pattern's top left
+    picked: Option<Point>,
+}
+
+impl TestPattern {
+    pub const fn new() -> Self {
+        Self { pattern: Pattern::Gradients, picked: None }
+    }
+
+    /// Pattern part of the window content
+    fn pattern_rect(area: Rect) -> Rect {
+        Rect::new(area.x, area.y, area.width, area.height.saturating_sub(STATUS_HEIGHT))
+    }
+
+    /// Handle a click at screen (x, y) in a window whose content is `area`
+    ///
+    /// Returns true if anything changed.
+    pub fn click(&mut self, area: Rect, x: i32, y: i32) -> bool {
+        let pattern = Self::pattern_rect(area);
+        if pattern.contains(x, y) {
+            self.picked = Some(Point::new(x - pattern.x, y - pattern.y));
+        } else if area.contains(x, y) {
+            self.pattern = self.pattern.next();
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Show a pattern (clears the picked color)
+    pub fn show(&mut self, pattern: Pattern) {
+        self.pattern = pattern;
+        self.picked = None;
+    }
+
+    /// Draw into `area` (the window content)
+    pub fn draw(&self, fb: &mut Framebuffer, area: Rect) {
+        let rect = Self::pattern_rect(area);
+        for y in 0..rect.height {
+            for x in 0..rect.width {
+                let color = self.pattern.color_at(x, y, rect.width, rect.height);
+                fb.set_pixel(rect.x + x as i32, rect.y + y as i32, color);
+            }
+        }
+
+        let theme = theme::current();
+        let status_y = rect.bottom();
+        fb.fill_rect(area.x, status_y, area.width, STATUS_HEIGHT, theme.window_bg);
+
+        let mut text = String::new();
+        let _ = write!(text, "[{}]", self.pattern.as_str());
+        if let Some(p) = self.picked.filter(|p| rect.contains(rect.x + p.x, rect.y + p.y)) {
+            let (sx, sy) = (rect.x + p.x, rect.y + p.y);
+            let drawn = self.pattern.color_at(p.x as u32, p.y as u32, rect.width, rect.height);
+            // Read back before the marker covers it
+            let stored = fb.get_pixel(sx, sy).unwrap_or(Color::BLACK);
+            let _ = write!(text, " {},{} #{:06X} read #{:06X}", p.x, p.y, drawn.to_u32(), stored.to_u32());
+
+            let marker = Color::from_u32(!stored.to_u32() & 0xFF_FFFF);
+            for d in 2..5 {
+                fb.set_pixel(sx - d, sy, marker);
+                fb.set_pixel(sx + d, sy, marker);
+                fb.set_pixel(sx, sy - d, marker);
+                fb.set_pixel(sx, sy + d, marker);
+            }
+        }
+        let outer = fb.set_clip(area.intersect(&fb.clip()));
+        fb.draw_string(area.x + 4, status_y + 2, &text, theme.text, Some(theme.window_bg));
+        fb.set_clip(outer);
+    }
+}
@@ -83,6 +83,21 @@ impl Theme {
     }
 }
 
+impl Theme {
+    /// Names accepted by `by_name`
+    pub const NAMES: [&'static str; 3] = ["plan9", "dark", "light"];
+
+    /// Look up a built-in theme by name
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "plan9" => Some(Self::plan9()),
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            _ => None,
+        }
+    }
+}
+
 impl Default for Theme {
     fn default() -> Self {
         Self::plan9()
@@ -0,0 +1,118 @@
+//! Desktop Wallpaper
+//!
+//! A decoded image drawn behind all windows in place of the flat theme
+//! background. The decoded bitmap is cached on the heap; placement is
+//! computed per pixel when compositing so changing the mode is free.
+
+use alloc::vec;
+
+use super::{bmp::{self, Bitmap}, Color, Framebuffer, Rect};
+use crate::fs::{OpenFlags, vfs::VFS};
+
+/// How the image is placed on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallpaperMode {
+    /// Repeat from the top-left corner
+    Tile,
+    /// Centered at native size, theme background around it
+    Center,
+    /// Scaled to fill the screen (nearest neighbour)
+    Stretch,
+}
+
+impl WallpaperMode {
+    /// Parse a mode name ("tile", "center", "stretch")
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tile" => Some(Self::Tile),
+            "center" => Some(Self::Center),
+            "stretch" => Some(Self::Stretch),
+            _ => None,
+        }
+    }
+}
+
+/// Loaded wallpaper
+pub struct Wallpaper {
+    image: Bitmap,
+    mode: WallpaperMode,
+}
+
+impl Wallpaper {
+    /// Wrap an already decoded image
+    pub fn new(image: Bitmap, mode: WallpaperMode) -> Self {
+        Self { image, mode }
+    }
+
+    /// Load and decode a BMP file from the VFS
+    pub fn load(path: &str, mode: WallpaperMode) -> Result<Self, &'static str> {
+        let vfs = unsafe { &mut VFS };
+
+        let size = vfs.stat(path).map_err(|e| e.as_str())?.size as usize;
+        let fd = vfs.open(path, OpenFlags::read_only()).map_err(|e| e.as_str())?;
+
+        let mut data = vec![0u8; size];
+        let mut read = 0;
+        let result = loop {
+            if read == size {
+                break Ok(());
+            }
+            match vfs.read(fd, &mut data[read..]) {
+                Ok(0) => break Err("Unexpected end of file"),
+                Ok(n) => read += n,
+                Err(e) => break Err(e.as_str()),
+            }
+        };
+        let _ = vfs.close(fd);
+        result?;
+
+        let image = bmp::decode(&data)?;
+        Ok(Self::new(image, mode))
+    }
+
+    /// Get the placement mode
+    pub fn mode(&self) -> WallpaperMode {
+        self.mode
+    }
+
+    /// Change the placement mode
+    pub fn set_mode(&mut self, mode: WallpaperMode) {
+        self.mode = mode;
+    }
+
+    /// Composite the wallpaper into `area` of the framebuffer
+    ///
+    /// Pixels not covered by the image (Center mode) get `background`.
+    pub fn draw(&self, fb: &mut Framebuffer, area: Rect, background: Color) {
+        let (sw, sh) = (fb.width, fb.height);
+        let (iw, ih) = (self.image.width, self.image.height);
+
+        let x0 = area.x.max(0) as u32;
+        let y0 = area.y.max(0) as u32;
+        let x1 = ((area.x + area.width as i32).max(0) as u32).min(sw);
+        let y1 = ((area.y + area.height as i32).max(0) as u32).min(sh);
+
+        // Top-left of the image in Center mode (may be negative)
+        let off_x = (sw as i32 - iw as i32) / 2;
+        let off_y = (sh as i32 - ih as i32) / 2;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let color = match self.mode {
+                    WallpaperMode::Tile => self.image.pixel(x % iw, y % ih),
+                    WallpaperMode::Stretch => self.image.pixel(x * iw / sw, y * ih / sh),
+                    WallpaperMode::Center => {
+                        let ix = x as i32 - off_x;
+                        let iy = y as i32 - off_y;
+                        if ix >= 0 && iy >= 0 && (ix as u32) < iw && (iy as u32) < ih {
+                            self.image.pixel(ix as u32, iy as u32)
+                        } else {
+                            background
+                        }
+                    }
+                };
+                fb.set_pixel(x as i32, y as i32, color);
+            }
+        }
+    }
+}
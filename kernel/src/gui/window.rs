@@ -2,13 +2,17 @@
 //!
 //! Plan 9 rio-style windows with minimal chrome.
 
-use super::{Rect, Color, Framebuffer, theme};
+use alloc::collections::VecDeque;
+use super::{Rect, Color, Framebuffer, InputEvent, theme};
 
 /// Window title bar height
 pub const TITLE_HEIGHT: u32 = 20;
 /// Window border width
 pub const BORDER_WIDTH: u32 = 3;
 
+/// Events kept per window before new ones are dropped
+const EVENT_QUEUE_LIMIT: usize = 32;
+
 /// Window flags
 #[derive(Debug, Clone, Copy)]
 pub struct WindowFlags {
@@ -47,6 +51,10 @@ pub struct Window {
     content_height: u32,
     /// Dirty flag (needs redraw)
     dirty: bool,
+    /// User program charged for the window (None = the kernel)
+    pub owner: Option<u32>,
+    /// Input waiting for the window's content handler
+    events: VecDeque<InputEvent>,
 }
 
 impl Window {
@@ -70,6 +78,8 @@ impl Window {
             content_width: content_w,
             content_height: content_h,
             dirty: true,
+            owner: None,
+            events: VecDeque::new(),
         }
     }
 
@@ -229,4 +239,23 @@ impl Window {
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
+
+    /// Queue an event for the content handler; false if the queue is full
+    pub fn post_event(&mut self, event: InputEvent) -> bool {
+        if self.events.len() >= EVENT_QUEUE_LIMIT {
+            return false;
+        }
+        self.events.push_back(event);
+        true
+    }
+
+    /// Take the oldest queued event
+    pub fn take_event(&mut self) -> Option<InputEvent> {
+        self.events.pop_front()
+    }
+
+    /// Are events waiting?
+    pub fn has_events(&self) -> bool {
+        !self.events.is_empty()
+    }
 }
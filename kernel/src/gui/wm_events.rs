@@ -4,7 +4,12 @@
 //! - Window creation/destruction
 //! - Focus changes
 //! - Z-order changes (bring to front/send to back)
-//! - Window move/resize completion
+//! - Window move/resize completion (moves keep the title bar on screen)
+//! - Screensaver start/stop
+//!
+//! Events on an existing window carry its owner, and the permission
+//! middleware stops a user program from touching another program's (or
+//! the kernel's) windows. The kernel itself may act on any window.
 //!
 //! NOTE: Continuous events (mouse tracking, frame rendering) stay outside
 //! EventChains for performance reasons. Only discrete, state-changing
@@ -14,7 +19,7 @@ use crate::event_chains::{
     ChainableEvent, EventChain, EventContext, EventMiddleware,
     FaultToleranceMode,
     result::EventResult,
-    middleware::{LoggingMiddleware, NextHandler},
+    middleware::{self, LoggingMiddleware, NextHandler, PermissionMiddleware},
 };
 
 // =============================================================================
@@ -29,6 +34,8 @@ pub mod event_type {
     pub const Z_ORDER_CHANGE: u32 = 4;
     pub const WINDOW_MOVE: u32 = 5;
     pub const WINDOW_RESIZE: u32 = 6;
+    pub const SCREENSAVER_START: u32 = 7;
+    pub const SCREENSAVER_STOP: u32 = 8;
 }
 
 /// Z-order change directions
@@ -47,6 +54,8 @@ pub mod context_keys {
     // Event identification
     pub const EVENT_TYPE: &str = "wm_event";
     pub const WINDOW_ID: &str = "wm_win_id";
+    /// When the event was dispatched (`pit::uptime_ms`, the input clock)
+    pub const TIMESTAMP_MS: &str = "wm_time_ms";
     
     // Window creation
     pub const WIN_TITLE_LEN: &str = "wm_title_len";
@@ -73,12 +82,37 @@ pub mod context_keys {
     pub const OLD_HEIGHT: &str = "wm_old_h";
     pub const NEW_WIDTH: &str = "wm_new_w";
     pub const NEW_HEIGHT: &str = "wm_new_h";
+    pub const SCREEN_WIDTH: &str = "wm_screen_w";
+    pub const SCREEN_HEIGHT: &str = "wm_screen_h";
+    
+    // Screensaver
+    pub const IDLE_MS: &str = "wm_idle_ms";
     
     // Result
     pub const SUCCESS: &str = "wm_success";
     pub const RESULT_WINDOW_ID: &str = "wm_result_id";
 }
 
+// =============================================================================
+// Geometry Policy
+// =============================================================================
+
+/// Title bar width that must stay on screen, so a window can always be
+/// grabbed again
+pub const MIN_TITLE_VISIBLE: i32 = 48;
+
+/// Nearest position to (x, y) that keeps a `width` wide window's title
+/// bar on a `screen_width` x `screen_height` screen
+///
+/// The whole title bar height stays visible; horizontally, at least
+/// `MIN_TITLE_VISIBLE` pixels of it (or all of a narrower window).
+pub fn constrain_position(x: i32, y: i32, width: u32, screen_width: u32, screen_height: u32) -> (i32, i32) {
+    let visible = MIN_TITLE_VISIBLE.min(width as i32);
+    let x = x.max(visible - width as i32).min(screen_width as i32 - visible);
+    let y = y.min(screen_height as i32 - super::window::TITLE_HEIGHT as i32).max(0);
+    (x, y)
+}
+
 // =============================================================================
 // Middleware: Focus Policy
 // =============================================================================
@@ -156,6 +190,7 @@ impl EventMiddleware for WmAuditMiddleware {
         // Log before execution
         let _event_type = context.get_u32(context_keys::EVENT_TYPE).unwrap_or(0);
         let _window_id = context.get_u32(context_keys::WINDOW_ID).unwrap_or(0);
+        let _timestamp = context.get_u32(context_keys::TIMESTAMP_MS).unwrap_or(0);
         
         // In a real implementation:
         // audit_log.push(AuditEntry { event_type, window_id, timestamp });
@@ -299,6 +334,8 @@ impl ChainableEvent for ZOrderChangeEvent {
 /// 
 /// Called when a window move operation completes (drag released).
 /// NOT called during dragging - that's handled directly for performance.
+/// Given the window width and screen size, pulls the new position back
+/// far enough to keep the title bar visible (`constrain_position`).
 pub struct WindowMoveEvent;
 
 impl ChainableEvent for WindowMoveEvent {
@@ -308,14 +345,20 @@ impl ChainableEvent for WindowMoveEvent {
             None => return EventResult::failure("No window ID specified"),
         };
         
-        let new_x = context.get_u32(context_keys::NEW_X);
-        let new_y = context.get_u32(context_keys::NEW_Y);
+        let (new_x, new_y) = match (context.get_u32(context_keys::NEW_X), context.get_u32(context_keys::NEW_Y)) {
+            (Some(x), Some(y)) => (x as i32, y as i32),
+            _ => return EventResult::failure("No new position specified"),
+        };
         
-        if new_x.is_none() || new_y.is_none() {
-            return EventResult::failure("No new position specified");
+        let width = context.get_u32(context_keys::WIN_WIDTH);
+        let screen_width = context.get_u32(context_keys::SCREEN_WIDTH);
+        let screen_height = context.get_u32(context_keys::SCREEN_HEIGHT);
+        if let (Some(width), Some(screen_width), Some(screen_height)) = (width, screen_width, screen_height) {
+            let (x, y) = constrain_position(new_x, new_y, width, screen_width, screen_height);
+            context.set_u32(context_keys::NEW_X, x as u32);
+            context.set_u32(context_keys::NEW_Y, y as u32);
         }
         
-        // Could validate that window stays on screen
         context.set_bool(context_keys::SUCCESS, true);
         
         EventResult::success(())
@@ -356,6 +399,30 @@ impl ChainableEvent for WindowResizeEvent {
     }
 }
 
+/// Screensaver Event
+/// 
+/// Called when the idle screensaver is about to start or stop. Starting
+/// can be vetoed by middleware; the desktop suspends window rendering
+/// while active and fully redraws on stop.
+pub struct ScreensaverEvent;
+
+impl ChainableEvent for ScreensaverEvent {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        match context.get_u32(context_keys::EVENT_TYPE) {
+            Some(event_type::SCREENSAVER_START) | Some(event_type::SCREENSAVER_STOP) => {}
+            _ => return EventResult::failure("Invalid screensaver event"),
+        }
+        
+        context.set_bool(context_keys::SUCCESS, true);
+        
+        EventResult::success(())
+    }
+    
+    fn name(&self) -> &'static str {
+        "wm_screensaver"
+    }
+}
+
 // =============================================================================
 // Global Instances
 // =============================================================================
@@ -366,15 +433,65 @@ static FOCUS_CHANGE: FocusChangeEvent = FocusChangeEvent;
 static Z_ORDER_CHANGE: ZOrderChangeEvent = ZOrderChangeEvent;
 static WINDOW_MOVE: WindowMoveEvent = WindowMoveEvent;
 static WINDOW_RESIZE: WindowResizeEvent = WindowResizeEvent;
+static SCREENSAVER: ScreensaverEvent = ScreensaverEvent;
 
 static LOGGING_MW: LoggingMiddleware = LoggingMiddleware::new();
+static PERMISSION_MW: PermissionMiddleware = PermissionMiddleware::user_allowed();
 static FOCUS_POLICY_MW: FocusPolicyMiddleware = FocusPolicyMiddleware::new();
 static AUDIT_MW: WmAuditMiddleware = WmAuditMiddleware::new();
 
+// =============================================================================
+// Prebuilt Chains
+// =============================================================================
+
+/// Build the standard WM chain (logging + permission + audit) around a
+/// single event
+const fn wm_chain(event: &'static dyn ChainableEvent) -> EventChain<'static> {
+    EventChain::new()
+        .middleware(&LOGGING_MW)
+        .middleware(&PERMISSION_MW)
+        .middleware(&AUDIT_MW)
+        .event(event)
+        .with_fault_tolerance(FaultToleranceMode::Strict)
+}
+
+static CREATE_CHAIN: EventChain<'static> = wm_chain(&WINDOW_CREATE);
+static DESTROY_CHAIN: EventChain<'static> = wm_chain(&WINDOW_DESTROY);
+static Z_ORDER_CHAIN: EventChain<'static> = wm_chain(&Z_ORDER_CHANGE);
+static MOVE_CHAIN: EventChain<'static> = wm_chain(&WINDOW_MOVE);
+static RESIZE_CHAIN: EventChain<'static> = wm_chain(&WINDOW_RESIZE);
+static SCREENSAVER_CHAIN: EventChain<'static> = wm_chain(&SCREENSAVER);
+
+/// Focus changes additionally pass through the focus policy middleware
+static FOCUS_CHAIN: EventChain<'static> = EventChain::new()
+    .middleware(&LOGGING_MW)
+    .middleware(&FOCUS_POLICY_MW)
+    .middleware(&AUDIT_MW)
+    .event(&FOCUS_CHANGE)
+    .with_fault_tolerance(FaultToleranceMode::Strict);
+
 // =============================================================================
 // Public API
 // =============================================================================
 
+/// Record who is asking and who owns the window, for the permission check
+fn set_ownership(context: &mut EventContext, owner: Option<u32>) {
+    if let Some(caller) = crate::exec::current_pid() {
+        context.set_u32(middleware::CALLER_PID, caller);
+    }
+    if let Some(owner) = owner {
+        context.set_u32(middleware::OWNER_PID, owner);
+    }
+}
+
+/// Context for a WM event of `kind`, stamped with the current time
+fn new_context(kind: u32) -> EventContext {
+    let mut context = EventContext::new();
+    context.set_u32(context_keys::EVENT_TYPE, kind);
+    context.set_u32(context_keys::TIMESTAMP_MS, crate::arch::x86::pit::uptime_ms());
+    context
+}
+
 /// Window Manager EventChain handler
 /// 
 /// Call these methods from Desktop to dispatch events through the chain.
@@ -384,45 +501,31 @@ impl WmEventDispatcher {
     /// Dispatch a window creation event
     /// Returns true if creation should proceed
     pub fn dispatch_create(x: i32, y: i32, width: u32, height: u32) -> bool {
-        let mut context = EventContext::new();
-        context.set_u32(context_keys::EVENT_TYPE, event_type::WINDOW_CREATE);
+        let mut context = new_context(event_type::WINDOW_CREATE);
         context.set_u32(context_keys::WIN_X, x as u32);
         context.set_u32(context_keys::WIN_Y, y as u32);
         context.set_u32(context_keys::WIN_WIDTH, width);
         context.set_u32(context_keys::WIN_HEIGHT, height);
         
-        let chain = EventChain::new()
-            .middleware(&LOGGING_MW)
-            .middleware(&AUDIT_MW)
-            .event(&WINDOW_CREATE)
-            .with_fault_tolerance(FaultToleranceMode::Strict);
-        
-        let result = chain.execute(&mut context);
+        let result = CREATE_CHAIN.execute(&mut context);
         result.success
     }
     
     /// Dispatch a window destruction event
     /// Returns true if destruction should proceed
-    pub fn dispatch_destroy(window_id: u32) -> bool {
-        let mut context = EventContext::new();
-        context.set_u32(context_keys::EVENT_TYPE, event_type::WINDOW_DESTROY);
+    pub fn dispatch_destroy(window_id: u32, owner: Option<u32>) -> bool {
+        let mut context = new_context(event_type::WINDOW_DESTROY);
         context.set_u32(context_keys::WINDOW_ID, window_id);
+        set_ownership(&mut context, owner);
         
-        let chain = EventChain::new()
-            .middleware(&LOGGING_MW)
-            .middleware(&AUDIT_MW)
-            .event(&WINDOW_DESTROY)
-            .with_fault_tolerance(FaultToleranceMode::Strict);
-        
-        let result = chain.execute(&mut context);
+        let result = DESTROY_CHAIN.execute(&mut context);
         result.success
     }
     
     /// Dispatch a focus change event
     /// Returns true if focus change should proceed
     pub fn dispatch_focus_change(old_focus: Option<u32>, new_focus: Option<u32>) -> bool {
-        let mut context = EventContext::new();
-        context.set_u32(context_keys::EVENT_TYPE, event_type::FOCUS_CHANGE);
+        let mut context = new_context(event_type::FOCUS_CHANGE);
         
         if let Some(old) = old_focus {
             context.set_u32(context_keys::OLD_FOCUS, old);
@@ -431,78 +534,82 @@ impl WmEventDispatcher {
             context.set_u32(context_keys::NEW_FOCUS, new);
         }
         
-        let chain = EventChain::new()
-            .middleware(&LOGGING_MW)
-            .middleware(&FOCUS_POLICY_MW)
-            .middleware(&AUDIT_MW)
-            .event(&FOCUS_CHANGE)
-            .with_fault_tolerance(FaultToleranceMode::Strict);
-        
-        let result = chain.execute(&mut context);
+        let result = FOCUS_CHAIN.execute(&mut context);
         result.success
     }
     
     /// Dispatch a z-order change event
     /// Returns true if z-order change should proceed
-    pub fn dispatch_z_order_change(window_id: u32, direction: u32) -> bool {
-        let mut context = EventContext::new();
-        context.set_u32(context_keys::EVENT_TYPE, event_type::Z_ORDER_CHANGE);
+    pub fn dispatch_z_order_change(window_id: u32, owner: Option<u32>, direction: u32) -> bool {
+        let mut context = new_context(event_type::Z_ORDER_CHANGE);
         context.set_u32(context_keys::WINDOW_ID, window_id);
+        set_ownership(&mut context, owner);
         context.set_u32(context_keys::Z_DIRECTION, direction);
         
-        let chain = EventChain::new()
-            .middleware(&LOGGING_MW)
-            .middleware(&AUDIT_MW)
-            .event(&Z_ORDER_CHANGE)
-            .with_fault_tolerance(FaultToleranceMode::Strict);
-        
-        let result = chain.execute(&mut context);
+        let result = Z_ORDER_CHAIN.execute(&mut context);
         result.success
     }
     
     /// Dispatch a window move completion event
-    /// Returns true if the move is valid
-    pub fn dispatch_move(window_id: u32, old_x: i32, old_y: i32, new_x: i32, new_y: i32) -> bool {
-        let mut context = EventContext::new();
-        context.set_u32(context_keys::EVENT_TYPE, event_type::WINDOW_MOVE);
+    /// Returns where the window may go (the requested position, pulled
+    /// back on screen if needed), or None if the move is refused
+    pub fn dispatch_move(
+        window_id: u32, owner: Option<u32>,
+        old_x: i32, old_y: i32, new_x: i32, new_y: i32,
+        width: u32, screen: (u32, u32)
+    ) -> Option<(i32, i32)> {
+        let mut context = new_context(event_type::WINDOW_MOVE);
         context.set_u32(context_keys::WINDOW_ID, window_id);
+        set_ownership(&mut context, owner);
         context.set_u32(context_keys::OLD_X, old_x as u32);
         context.set_u32(context_keys::OLD_Y, old_y as u32);
         context.set_u32(context_keys::NEW_X, new_x as u32);
         context.set_u32(context_keys::NEW_Y, new_y as u32);
+        context.set_u32(context_keys::WIN_WIDTH, width);
+        context.set_u32(context_keys::SCREEN_WIDTH, screen.0);
+        context.set_u32(context_keys::SCREEN_HEIGHT, screen.1);
         
-        let chain = EventChain::new()
-            .middleware(&LOGGING_MW)
-            .middleware(&AUDIT_MW)
-            .event(&WINDOW_MOVE)
-            .with_fault_tolerance(FaultToleranceMode::Strict);
-        
-        let result = chain.execute(&mut context);
-        result.success
+        let result = MOVE_CHAIN.execute(&mut context);
+        if !result.success {
+            return None;
+        }
+        Some((
+            context.get_u32(context_keys::NEW_X).unwrap_or(new_x as u32) as i32,
+            context.get_u32(context_keys::NEW_Y).unwrap_or(new_y as u32) as i32,
+        ))
     }
     
     /// Dispatch a window resize completion event
     /// Returns true if the resize is valid
     pub fn dispatch_resize(
-        window_id: u32, 
+        window_id: u32, owner: Option<u32>,
         old_w: u32, old_h: u32, 
         new_w: u32, new_h: u32
     ) -> bool {
-        let mut context = EventContext::new();
-        context.set_u32(context_keys::EVENT_TYPE, event_type::WINDOW_RESIZE);
+        let mut context = new_context(event_type::WINDOW_RESIZE);
         context.set_u32(context_keys::WINDOW_ID, window_id);
+        set_ownership(&mut context, owner);
         context.set_u32(context_keys::OLD_WIDTH, old_w);
         context.set_u32(context_keys::OLD_HEIGHT, old_h);
         context.set_u32(context_keys::NEW_WIDTH, new_w);
         context.set_u32(context_keys::NEW_HEIGHT, new_h);
         
-        let chain = EventChain::new()
-            .middleware(&LOGGING_MW)
-            .middleware(&AUDIT_MW)
-            .event(&WINDOW_RESIZE)
-            .with_fault_tolerance(FaultToleranceMode::Strict);
+        let result = RESIZE_CHAIN.execute(&mut context);
+        result.success
+    }
+    
+    /// Dispatch a screensaver start/stop event
+    /// Returns true if the transition should proceed
+    pub fn dispatch_screensaver(start: bool, idle_ms: u32) -> bool {
+        let kind = if start {
+            event_type::SCREENSAVER_START
+        } else {
+            event_type::SCREENSAVER_STOP
+        };
+        let mut context = new_context(kind);
+        context.set_u32(context_keys::IDLE_MS, idle_ms);
         
-        let result = chain.execute(&mut context);
+        let result = SCREENSAVER_CHAIN.execute(&mut context);
         result.success
     }
 }
@@ -0,0 +1,202 @@
+//! Message Ports
+//!
+//! A port is a named queue of fixed-size messages. The program that
+//! creates a port owns it and is the only one that can receive from it;
+//! any program that looks the name up can send to it.
+//! The kernel stamps every message with the sender's PID, so a receiver
+//! knows who it is talking to without trusting the payload.
+//!
+//! Send blocks while the queue is full and receive while it is empty,
+//! each until an absolute deadline in uptime milliseconds: `NO_WAIT`
+//! fails at once and `FOREVER` never times out. Deadlines (rather than
+//! timeouts) keep the restarted syscall from waiting afresh each time.
+//!
+//! Ports go away when their owner destroys them or exits; blocked
+//! senders then fail with "no such port".
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use core::fmt::Write;
+use crate::sched::Pid;
+
+/// Most ports at once (system wide)
+pub const MAX_PORTS: usize = 32;
+
+/// Longest port name
+pub const MAX_NAME: usize = 31;
+
+/// Payload bytes per message
+pub const MSG_SIZE: usize = 64;
+
+/// Messages queued per port before senders block
+pub const QUEUE_LEN: usize = 16;
+
+/// Deadline that fails immediately instead of blocking
+pub const NO_WAIT: u32 = 0;
+
+/// Deadline that never passes
+pub const FOREVER: u32 = u32::MAX;
+
+/// Deadline passed before the port was ready
+pub const ETIMEDOUT: &str = "timed out";
+
+/// A message as copied to the receiver
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Message {
+    /// Sending program, filled in by the kernel
+    pub sender: Pid,
+    /// Payload bytes used
+    pub len: u32,
+    pub data: [u8; MSG_SIZE],
+}
+
+struct Port {
+    id: u32,
+    name: [u8; MAX_NAME],
+    name_len: usize,
+    owner: Pid,
+    queue: VecDeque<Message>,
+    /// Messages delivered since creation
+    delivered: u32,
+}
+
+impl Port {
+    fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("?")
+    }
+}
+
+static mut PORTS: [Option<Port>; MAX_PORTS] = [const { None }; MAX_PORTS];
+static mut NEXT_ID: u32 = 1;
+
+fn ports() -> &'static mut [Option<Port>; MAX_PORTS] {
+    unsafe { &mut *core::ptr::addr_of_mut!(PORTS) }
+}
+
+fn find(id: u32) -> Option<&'static mut Port> {
+    ports().iter_mut().flatten().find(|p| p.id == id)
+}
+
+/// Has a deadline passed?
+fn expired(deadline: u32) -> bool {
+    deadline != FOREVER && crate::arch::x86::pit::uptime_ms() >= deadline
+}
+
+// =============================================================================
+// Port Management
+// =============================================================================
+
+/// Create a port named `name` owned by `owner`, returning its ID
+pub fn create(owner: Pid, name: &str) -> Result<u32, &'static str> {
+    if name.is_empty() || name.len() > MAX_NAME {
+        return Err("bad port name");
+    }
+    if lookup(name).is_some() {
+        return Err("port name in use");
+    }
+    let slot = ports().iter().position(|p| p.is_none()).ok_or("too many ports")?;
+
+    let mut buf = [0u8; MAX_NAME];
+    buf[..name.len()].copy_from_slice(name.as_bytes());
+    let id = unsafe {
+        let id = NEXT_ID;
+        NEXT_ID += 1;
+        id
+    };
+    ports()[slot] = Some(Port {
+        id,
+        name: buf,
+        name_len: name.len(),
+        owner,
+        queue: VecDeque::with_capacity(QUEUE_LEN),
+        delivered: 0,
+    });
+    Ok(id)
+}
+
+/// ID of the port with this name
+pub fn lookup(name: &str) -> Option<u32> {
+    ports().iter().flatten().find(|p| p.name() == name).map(|p| p.id)
+}
+
+/// Destroy a port (owner only); queued messages are dropped
+pub fn destroy(id: u32, caller: Pid) -> Result<(), &'static str> {
+    let port = find(id).ok_or("no such port")?;
+    if port.owner != caller {
+        return Err("owned by another process");
+    }
+    for slot in ports().iter_mut() {
+        if slot.as_ref().is_some_and(|p| p.id == id) {
+            *slot = None;
+        }
+    }
+    Ok(())
+}
+
+/// Destroy every port a program owns (it exited)
+pub fn release_owner(owner: Pid) {
+    for slot in ports().iter_mut() {
+        if slot.as_ref().is_some_and(|p| p.owner == owner) {
+            *slot = None;
+        }
+    }
+}
+
+// =============================================================================
+// Messaging
+// =============================================================================
+
+/// Queue a message from `sender`
+///
+/// Returns Ok(false) if the queue is full and the deadline is still
+/// ahead; the caller blocks (see `sendable`) and tries again.
+pub fn send(id: u32, sender: Pid, payload: &[u8], deadline: u32) -> Result<bool, &'static str> {
+    if payload.len() > MSG_SIZE {
+        return Err("message too long");
+    }
+    let port = find(id).ok_or("no such port")?;
+    if port.queue.len() >= QUEUE_LEN {
+        return if expired(deadline) { Err(ETIMEDOUT) } else { Ok(false) };
+    }
+    let mut message = Message { sender, len: payload.len() as u32, data: [0; MSG_SIZE] };
+    message.data[..payload.len()].copy_from_slice(payload);
+    port.queue.push_back(message);
+    port.delivered = port.delivered.wrapping_add(1);
+    Ok(true)
+}
+
+/// Take the next message from a caller's port
+///
+/// Returns Ok(None) if the queue is empty and the deadline is still
+/// ahead; the caller blocks (see `receivable`) and tries again.
+pub fn receive(id: u32, caller: Pid, deadline: u32) -> Result<Option<Message>, &'static str> {
+    let port = find(id).ok_or("no such port")?;
+    if port.owner != caller {
+        return Err("owned by another process");
+    }
+    match port.queue.pop_front() {
+        Some(message) => Ok(Some(message)),
+        None if expired(deadline) => Err(ETIMEDOUT),
+        None => Ok(None),
+    }
+}
+
+/// Would a blocked send to this port return now?
+pub fn sendable(id: u32, deadline: u32) -> bool {
+    find(id).map_or(true, |p| p.queue.len() < QUEUE_LEN) || expired(deadline)
+}
+
+/// Would a blocked receive on this port return now?
+pub fn receivable(id: u32, deadline: u32) -> bool {
+    find(id).map_or(true, |p| !p.queue.is_empty()) || expired(deadline)
+}
+
+/// /proc/ports: ports in use
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "id    owner  queued  delivered  name");
+    for port in ports().iter().flatten() {
+        let _ = writeln!(out, "{:<5} {:<6} {:<7} {:<10} {}",
+            port.id, port.owner, port.queue.len(), port.delivered, port.name());
+    }
+}
@@ -0,0 +1,249 @@
+//! Kernel Assertions
+//!
+//! `kassert!` checks an invariant in every build; `debug_assert_kernel!`
+//! only in debug builds or with the `debug` feature. In a debug build a
+//! failure panics like `assert!`. In a release build one broken invariant
+//! shouldn't cost the user their desktop, so a failure is reported instead:
+//!
+//! - logged at critical level with its source location,
+//! - recorded as a mini-dump (message, registers and a window of stack)
+//!   kept for /proc/asserts and streamed over COM1,
+//! - if a user program is running, so the check failed in a syscall made
+//!   on its behalf, the program is ended once the syscall returns (unless
+//!   turned off with `asserts kill off`).
+//!
+//! Both macros evaluate to whether the condition held, so the caller can
+//! back out of an operation it can no longer trust:
+//!
+//! ```ignore
+//! if !kassert!(len <= buf.len(), "length {} past buffer", len) {
+//!     return Err(FsError::IoError);
+//! }
+//! ```
+//!
+//! Each location is recorded once; later failures there only bump its
+//! count.
+
+use alloc::string::String;
+use core::fmt::{self, Write};
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Failure locations remembered
+const MAX_RECORDS: usize = 8;
+
+/// Captured message size
+const MESSAGE_SIZE: usize = 96;
+
+/// Captured stack window size
+const STACK_SIZE: usize = 64;
+
+/// A failed assertion
+#[derive(Clone, Copy)]
+pub struct Failure {
+    pub location: &'static Location<'static>,
+    message: [u8; MESSAGE_SIZE],
+    message_len: usize,
+    /// Times this location has failed
+    pub count: u32,
+    pub first_ms: u32,
+    pub last_ms: u32,
+    /// Program running at the first failure (0 = none)
+    pub pid: u32,
+    pub esp: u32,
+    pub ebp: u32,
+    stack: [u8; STACK_SIZE],
+}
+
+impl Failure {
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(&self.message[..self.message_len]).unwrap_or("???")
+    }
+}
+
+/// Fixed-buffer writer for the message (cut at a char boundary)
+struct MessageWriter<'a> {
+    buf: &'a mut [u8; MESSAGE_SIZE],
+    len: usize,
+}
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let mut utf8 = [0u8; 4];
+            let bytes = c.encode_utf8(&mut utf8).as_bytes();
+            if self.len + bytes.len() > MESSAGE_SIZE {
+                break;
+            }
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+        Ok(())
+    }
+}
+
+/// Recorded failures (only changed with interrupts off)
+static mut FAILURES: [Option<Failure>; MAX_RECORDS] = [None; MAX_RECORDS];
+
+/// Failures at every location, recorded or not
+static TOTAL: AtomicU32 = AtomicU32::new(0);
+
+/// Failures at new locations once the records were full
+static UNRECORDED: AtomicU32 = AtomicU32::new(0);
+
+/// End the running program on failure
+static KILL_TASK: AtomicBool = AtomicBool::new(true);
+
+/// Set while a failure is being reported (an assertion in the reporting
+/// path must not recurse)
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+/// Should failures panic? (debug builds)
+pub const PANIC_ON_FAILURE: bool = cfg!(debug_assertions);
+
+pub fn set_kill_task(kill: bool) {
+    KILL_TASK.store(kill, Ordering::Relaxed);
+}
+
+pub fn kill_task() -> bool {
+    KILL_TASK.load(Ordering::Relaxed)
+}
+
+/// Report a failed assertion (called by the macros)
+#[track_caller]
+#[cold]
+pub fn fail(args: fmt::Arguments) {
+    let location = Location::caller();
+    if PANIC_ON_FAILURE {
+        panic!("assertion failed: {}", args);
+    }
+    TOTAL.fetch_add(1, Ordering::Relaxed);
+    if REPORTING.swap(true, Ordering::Acquire) {
+        return;
+    }
+
+    let (esp, ebp): (u32, u32);
+    unsafe {
+        core::arch::asm!("mov {:e}, esp", out(reg) esp);
+        core::arch::asm!("mov {:e}, ebp", out(reg) ebp);
+    }
+    let now = crate::time::monotonic_ms();
+    let pid = crate::exec::current_pid().unwrap_or(0);
+
+    let new = crate::arch::without_interrupts(|| {
+        let records = unsafe { &mut *core::ptr::addr_of_mut!(FAILURES) };
+        if let Some(failure) = records.iter_mut().flatten().find(|f| f.location == location) {
+            failure.count += 1;
+            failure.last_ms = now;
+            return None;
+        }
+        let Some(slot) = records.iter_mut().find(|f| f.is_none()) else {
+            UNRECORDED.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        let mut failure = Failure {
+            location,
+            message: [0; MESSAGE_SIZE],
+            message_len: 0,
+            count: 1,
+            first_ms: now,
+            last_ms: now,
+            pid,
+            esp,
+            ebp,
+            stack: [0; STACK_SIZE],
+        };
+        let mut msg = MessageWriter { buf: &mut failure.message, len: 0 };
+        let _ = write!(msg, "{}", args);
+        failure.message_len = msg.len;
+        for (i, byte) in failure.stack.iter_mut().enumerate() {
+            *byte = unsafe { ((esp as usize + i) as *const u8).read_volatile() };
+        }
+        *slot = Some(failure);
+        Some(failure)
+    });
+
+    crate::klog_limited!(Crit, "assert", "{} at {}:{}", args, location.file(), location.line());
+    if let Some(failure) = new {
+        stream(&failure);
+        crate::gui::notify::error("Kernel assertion failed (see /proc/asserts)");
+    }
+    if pid != 0 && kill_task() {
+        crate::klog!(Err, "assert", "ending pid {}", pid);
+        crate::exec::exit_current(crate::exec::EXIT_ASSERT);
+    }
+
+    REPORTING.store(false, Ordering::Release);
+}
+
+/// Stream a new failure's mini-dump over COM1
+fn stream(failure: &Failure) {
+    let serial = unsafe { &mut *core::ptr::addr_of_mut!(crate::drivers::serial::SERIAL) };
+    if !serial.is_present() {
+        return;
+    }
+    let _ = writeln!(serial, "\n=== ASSERT {}:{} pid {} at {} ms ===",
+        failure.location.file(), failure.location.line(), failure.pid, failure.first_ms);
+    let _ = writeln!(serial, "{}", failure.message());
+    let _ = writeln!(serial, "esp {:08x} ebp {:08x}", failure.esp, failure.ebp);
+    for line in failure.stack.chunks(32) {
+        for b in line {
+            let _ = write!(serial, "{:02x}", b);
+        }
+        let _ = writeln!(serial);
+    }
+    let _ = writeln!(serial, "=== ASSERT END ===");
+}
+
+/// Forget recorded failures
+pub fn clear() {
+    crate::arch::without_interrupts(|| unsafe {
+        *core::ptr::addr_of_mut!(FAILURES) = [None; MAX_RECORDS];
+    });
+    UNRECORDED.store(0, Ordering::Relaxed);
+}
+
+/// Recorded failures (/proc/asserts)
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "total: {}  unrecorded: {}  kill: {}",
+        TOTAL.load(Ordering::Relaxed), UNRECORDED.load(Ordering::Relaxed),
+        if kill_task() { "on" } else { "off" });
+    let records = unsafe { &*core::ptr::addr_of!(FAILURES) };
+    for failure in records.iter().flatten() {
+        let _ = writeln!(out, "{}:{} x{} first {} ms last {} ms pid {}",
+            failure.location.file(), failure.location.line(), failure.count,
+            failure.first_ms, failure.last_ms, failure.pid);
+        let _ = writeln!(out, "  {}", failure.message());
+        let _ = writeln!(out, "  esp {:08X} ebp {:08X}", failure.esp, failure.ebp);
+    }
+}
+
+/// Check an invariant in every build; evaluates to whether it held
+///
+/// `kassert!(cond)` or `kassert!(cond, "format", args...)`
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr $(,)?) => {
+        $crate::kassert!($cond, "{}", stringify!($cond))
+    };
+    ($cond:expr, $($arg:tt)+) => {{
+        let held: bool = $cond;
+        if !held {
+            $crate::kassert::fail(format_args!($($arg)+));
+        }
+        held
+    }};
+}
+
+/// Check an invariant in debug builds (or with the `debug` feature);
+/// always true otherwise, without evaluating the condition
+#[macro_export]
+macro_rules! debug_assert_kernel {
+    ($($arg:tt)+) => {
+        if cfg!(any(debug_assertions, feature = "debug")) {
+            $crate::kassert!($($arg)+)
+        } else {
+            true
+        }
+    };
+}
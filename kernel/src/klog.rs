@@ -0,0 +1,361 @@
+//! Kernel Log
+//!
+//! Fixed-size ring buffer holding the most recent console output. Everything
+//! written through the VGA `Writer` is mirrored here, so the tail of the log
+//! is available to crash dumps and diagnostics even after the screen has been
+//! taken over by the GUI. Under an emulator the log is also copied to the
+//! debug console (`drivers::debugcon`) as it is written.
+//!
+//! Subsystems log through the `klog!` macro with a syslog-style level and a
+//! module name:
+//!
+//! ```ignore
+//! klog!(Debug, "gui", "frame took {} ms", ms);
+//! ```
+//!
+//! A message is kept if its level is at or above the module's threshold:
+//! the default (info) unless overridden at runtime with `loglevel gui
+//! debug`. Levels above `MAX_LEVEL` are compiled out, so release builds
+//! carry no debug messages unless built with the `debug` feature.
+//!
+//! Sources that can fire on every interrupt or frame (input, the render
+//! loop) use `klog_limited!`, which keeps a burst of messages per call
+//! site and then counts what it drops until the window passes.
+
+use alloc::string::String;
+use core::fmt;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+/// Ring buffer size in bytes
+pub const KLOG_SIZE: usize = 4096;
+
+/// Kernel log ring buffer
+pub struct KernelLog {
+    buffer: [u8; KLOG_SIZE],
+    /// Next write position
+    head: usize,
+    /// Total bytes ever written (saturating at usize::MAX)
+    written: usize,
+}
+
+impl KernelLog {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; KLOG_SIZE],
+            head: 0,
+            written: 0,
+        }
+    }
+
+    /// Append bytes, overwriting the oldest data when full
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.buffer[self.head] = b;
+            self.head = (self.head + 1) % KLOG_SIZE;
+        }
+        self.written = self.written.saturating_add(bytes.len());
+    }
+
+    /// Total bytes ever written (a cursor for following the log)
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Number of bytes currently held
+    pub fn len(&self) -> usize {
+        self.written.min(KLOG_SIZE)
+    }
+
+    /// Copy the most recent `out.len()` bytes (or fewer) into `out`
+    ///
+    /// Returns the number of bytes copied, oldest first.
+    pub fn tail(&self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.len());
+        let start = (self.head + KLOG_SIZE - n) % KLOG_SIZE;
+        for (i, slot) in out[..n].iter_mut().enumerate() {
+            *slot = self.buffer[(start + i) % KLOG_SIZE];
+        }
+        n
+    }
+}
+
+/// Global kernel log
+pub static mut KLOG: KernelLog = KernelLog::new();
+
+/// Append a string to the kernel log
+pub fn write_str(s: &str) {
+    unsafe { KLOG.write_bytes(s.as_bytes()) }
+    crate::drivers::debugcon::write_str(s);
+}
+
+/// Append formatted text to the kernel log
+pub fn write_fmt(args: fmt::Arguments) {
+    struct Sink;
+
+    impl fmt::Write for Sink {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            write_str(s);
+            Ok(())
+        }
+    }
+
+    let _ = fmt::Write::write_fmt(&mut Sink, args);
+}
+
+/// Copy the log tail into `out`, returning bytes copied
+pub fn tail(out: &mut [u8]) -> usize {
+    unsafe { KLOG.tail(out) }
+}
+
+/// Total bytes ever written to the log
+pub fn written() -> usize {
+    unsafe { KLOG.written() }
+}
+
+// =============================================================================
+// Levels
+// =============================================================================
+
+/// Message severity, most severe first (syslog numbering)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Emerg = 0,
+    Alert = 1,
+    Crit = 2,
+    Err = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+    Debug = 7,
+}
+
+impl Level {
+    const ALL: [Level; 8] = [
+        Level::Emerg, Level::Alert, Level::Crit, Level::Err,
+        Level::Warning, Level::Notice, Level::Info, Level::Debug,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Emerg => "emerg",
+            Level::Alert => "alert",
+            Level::Crit => "crit",
+            Level::Err => "err",
+            Level::Warning => "warning",
+            Level::Notice => "notice",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    /// Parse a level name or number ("debug", "7")
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.parse::<u8>() {
+            Ok(n) => Self::ALL.get(n as usize).copied(),
+            Err(_) => Self::ALL.iter().copied().find(|l| l.as_str() == text),
+        }
+    }
+
+    fn from_u8(n: u8) -> Self {
+        Self::ALL[(n as usize).min(7)]
+    }
+}
+
+/// Most verbose level compiled in
+pub const MAX_LEVEL: Level = if cfg!(any(debug_assertions, feature = "debug")) {
+    Level::Debug
+} else {
+    Level::Info
+};
+
+/// Threshold for modules without an override
+static DEFAULT_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Most verbose threshold in effect anywhere, so most filtered messages
+/// are rejected without searching the overrides
+static MOST_VERBOSE: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Messages dropped by rate limiting
+static SUPPRESSED: AtomicU32 = AtomicU32::new(0);
+
+// =============================================================================
+// Module Overrides
+// =============================================================================
+
+/// Modules that can have their own threshold
+const MAX_OVERRIDES: usize = 16;
+
+/// Longest module name
+const MODULE_NAME_MAX: usize = 12;
+
+#[derive(Clone, Copy)]
+struct Override {
+    name: [u8; MODULE_NAME_MAX],
+    len: u8,
+    level: Level,
+}
+
+impl Override {
+    fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.len as usize]).unwrap_or("")
+    }
+}
+
+/// Per-module thresholds (only changed with interrupts off)
+static mut OVERRIDES: [Option<Override>; MAX_OVERRIDES] = [None; MAX_OVERRIDES];
+
+fn overrides() -> &'static [Option<Override>; MAX_OVERRIDES] {
+    unsafe { &*core::ptr::addr_of!(OVERRIDES) }
+}
+
+/// Threshold for a module
+pub fn level_for(module: &str) -> Level {
+    overrides().iter().flatten()
+        .find(|o| o.name() == module)
+        .map_or(Level::from_u8(DEFAULT_LEVEL.load(Ordering::Relaxed)), |o| o.level)
+}
+
+/// Would a message from `module` at `level` be kept?
+pub fn enabled(module: &str, level: Level) -> bool {
+    if level as u8 > MOST_VERBOSE.load(Ordering::Relaxed) {
+        return false;
+    }
+    level <= level_for(module)
+}
+
+/// Set the default threshold
+pub fn set_default_level(level: Level) {
+    DEFAULT_LEVEL.store(level as u8, Ordering::Relaxed);
+    update_most_verbose();
+}
+
+/// Give a module its own threshold, or follow the default again (None)
+pub fn set_module_level(module: &str, level: Option<Level>) -> Result<(), &'static str> {
+    if module.is_empty() || module.len() > MODULE_NAME_MAX {
+        return Err("Module name too long");
+    }
+    crate::arch::x86::idt::without_interrupts(|| {
+        let table = unsafe { &mut *core::ptr::addr_of_mut!(OVERRIDES) };
+        let existing = table.iter().position(|o| o.is_some_and(|o| o.name() == module));
+        match (existing, level) {
+            (Some(i), Some(level)) => {
+                if let Some(o) = table[i].as_mut() {
+                    o.level = level;
+                }
+            }
+            (Some(i), None) => table[i] = None,
+            (None, Some(level)) => {
+                let slot = table.iter_mut().find(|o| o.is_none()).ok_or("Too many module levels")?;
+                let mut name = [0u8; MODULE_NAME_MAX];
+                name[..module.len()].copy_from_slice(module.as_bytes());
+                *slot = Some(Override { name, len: module.len() as u8, level });
+            }
+            (None, None) => {}
+        }
+        Ok(())
+    })?;
+    update_most_verbose();
+    Ok(())
+}
+
+fn update_most_verbose() {
+    let most = overrides().iter().flatten()
+        .map(|o| o.level as u8)
+        .fold(DEFAULT_LEVEL.load(Ordering::Relaxed), u8::max);
+    MOST_VERBOSE.store(most, Ordering::Relaxed);
+}
+
+/// Thresholds and counters, for the `loglevel` command
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "default: {} (max {})",
+        Level::from_u8(DEFAULT_LEVEL.load(Ordering::Relaxed)).as_str(), MAX_LEVEL.as_str());
+    for o in overrides().iter().flatten() {
+        let _ = writeln!(out, "{}: {}", o.name(), o.level.as_str());
+    }
+    let _ = writeln!(out, "suppressed: {}", SUPPRESSED.load(Ordering::Relaxed));
+}
+
+// =============================================================================
+// Leveled Logging
+// =============================================================================
+
+/// Append one message as "[module] level: text" (level omitted for info
+/// and notice, which are most messages)
+pub fn log(level: Level, module: &str, args: fmt::Arguments) {
+    match level {
+        Level::Info | Level::Notice => write_fmt(format_args!("[{}] {}\n", module, args)),
+        _ => write_fmt(format_args!("[{}] {}: {}\n", module, level.as_str(), args)),
+    }
+}
+
+/// Messages kept per call site in each window
+const RATE_BURST: u32 = 10;
+
+/// Rate limiting window
+const RATE_WINDOW_MS: u32 = 5000;
+
+/// Per-call-site budget for `klog_limited!`
+pub struct RateLimit {
+    window_start: AtomicU32,
+    count: AtomicU32,
+    suppressed: AtomicU32,
+}
+
+impl RateLimit {
+    pub const fn new() -> Self {
+        Self {
+            window_start: AtomicU32::new(0),
+            count: AtomicU32::new(0),
+            suppressed: AtomicU32::new(0),
+        }
+    }
+
+    /// Log if the call site is within its budget
+    ///
+    /// The first message of a new window first reports how many were
+    /// dropped in the last one.
+    pub fn log(&self, level: Level, module: &str, args: fmt::Arguments) {
+        let now = crate::time::monotonic_ms();
+        if now.wrapping_sub(self.window_start.load(Ordering::Relaxed)) >= RATE_WINDOW_MS {
+            self.window_start.store(now, Ordering::Relaxed);
+            self.count.store(0, Ordering::Relaxed);
+            let dropped = self.suppressed.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                log(level, module, format_args!("{} messages suppressed", dropped));
+            }
+        }
+        if self.count.fetch_add(1, Ordering::Relaxed) < RATE_BURST {
+            log(level, module, args);
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            SUPPRESSED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Log a message: `klog!(Warning, "exfat", "bad entry at {}", lba)`
+#[macro_export]
+macro_rules! klog {
+    ($level:ident, $module:expr, $($arg:tt)*) => {
+        if $crate::klog::Level::$level <= $crate::klog::MAX_LEVEL
+            && $crate::klog::enabled($module, $crate::klog::Level::$level)
+        {
+            $crate::klog::log($crate::klog::Level::$level, $module, format_args!($($arg)*));
+        }
+    };
+}
+
+/// Log a message from a noisy source, rate limited per call site
+#[macro_export]
+macro_rules! klog_limited {
+    ($level:ident, $module:expr, $($arg:tt)*) => {
+        if $crate::klog::Level::$level <= $crate::klog::MAX_LEVEL
+            && $crate::klog::enabled($module, $crate::klog::Level::$level)
+        {
+            static LIMIT: $crate::klog::RateLimit = $crate::klog::RateLimit::new();
+            LIMIT.log($crate::klog::Level::$level, $module, format_args!($($arg)*));
+        }
+    };
+}
@@ -0,0 +1,182 @@
+//! Kernel Symbols
+//!
+//! Function names for kernel addresses, so crash output and the profiler
+//! show `rustacean_kernel::gui::desktop::Desktop::draw+0x1c` instead of
+//! `0x0010A3F4`.
+//!
+//! The table lives in the `.ksyms` section (linker.ld), filled in after
+//! linking: the build has the linker write a map (build.rs),
+//! `tools/ksyms.py` turns the text symbols in it into a table, and objcopy
+//! writes that over the section. The section is sized to the table by
+//! relinking with `KSYMS_SIZE` set to what ksyms.py measured; it follows
+//! the code, so its size doesn't move any symbol in it. A kernel linked
+//! without that step has an empty table and shows bare addresses.
+//!
+//! Format (little-endian):
+//!
+//! ```text
+//! "KSYM" u32 count u32 first-address
+//! per symbol, in address order:
+//!   ULEB128 distance from the previous symbol (0 for the first)
+//!   u8 bytes shared with the previous name, u8 suffix length, suffix
+//! ```
+//!
+//! Neighbouring functions mostly share a module path, so the shared
+//! prefix takes out most of each name. A lookup decodes from the start:
+//! fine for crash reports and /proc output, not for hot paths.
+
+use core::fmt;
+
+/// Longest name kept (the table stores at most this much of each)
+pub const MAX_NAME: usize = 255;
+
+/// Start of the table
+const MAGIC: &[u8; 4] = b"KSYM";
+
+/// Header: magic, count, first address
+const HEADER_LEN: usize = 12;
+
+/// Return addresses listed in a stack trace
+const MAX_TRACE: usize = 16;
+
+extern "C" {
+    /// Table bounds and kernel text bounds (linker.ld)
+    static __ksyms_start: u8;
+    static __ksyms_end: u8;
+    static __text_start: u8;
+    static __text_end: u8;
+}
+
+/// The section as written by objcopy
+fn section() -> &'static [u8] {
+    unsafe {
+        let start = core::ptr::addr_of!(__ksyms_start);
+        let len = core::ptr::addr_of!(__ksyms_end) as usize - start as usize;
+        core::slice::from_raw_parts(start, len)
+    }
+}
+
+/// Is `addr` in kernel code?
+pub fn is_kernel_text(addr: u32) -> bool {
+    let start = core::ptr::addr_of!(__text_start) as u32;
+    let end = core::ptr::addr_of!(__text_end) as u32;
+    (start..end).contains(&addr)
+}
+
+/// Symbol count, first address and the entries (None if the table was
+/// never filled in)
+fn table() -> Option<(u32, u32, &'static [u8])> {
+    let data = section();
+    if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+        return None;
+    }
+    let word = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    Some((word(4), word(8), &data[HEADER_LEN..]))
+}
+
+/// Symbols in the table (0 without one)
+pub fn count() -> u32 {
+    table().map_or(0, |(count, _, _)| count)
+}
+
+/// The function containing an address
+pub struct Symbol {
+    name: [u8; MAX_NAME],
+    len: usize,
+    /// Where the function starts
+    pub addr: u32,
+    /// How far into it the address was
+    pub offset: u32,
+}
+
+impl Symbol {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.len]).unwrap_or("?")
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+0x{:x}", self.name(), self.offset)
+    }
+}
+
+/// Name the kernel function `eip` is in
+pub fn symbolize(eip: u32) -> Option<Symbol> {
+    if !is_kernel_text(eip) {
+        return None;
+    }
+    let (count, first, entries) = table()?;
+    let mut symbol = Symbol { name: [0; MAX_NAME], len: 0, addr: first, offset: 0 };
+    let mut found = false;
+    let mut pos = 0;
+    let mut addr = first;
+    for _ in 0..count {
+        // Distance from the previous symbol
+        let mut delta = 0u32;
+        let mut shift = 0;
+        loop {
+            let byte = *entries.get(pos)?;
+            pos += 1;
+            delta |= ((byte & 0x7F) as u32) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 || shift >= 32 {
+                break;
+            }
+        }
+        addr = addr.wrapping_add(delta);
+        if addr > eip {
+            break;
+        }
+
+        // Rebuild the name on top of the previous one
+        let shared = (*entries.get(pos)? as usize).min(symbol.len);
+        let suffix_len = *entries.get(pos + 1)? as usize;
+        let suffix = entries.get(pos + 2..pos + 2 + suffix_len)?;
+        pos += 2 + suffix_len;
+        let len = (shared + suffix_len).min(MAX_NAME);
+        symbol.name[shared..len].copy_from_slice(&suffix[..len - shared]);
+        symbol.len = len;
+        symbol.addr = addr;
+        found = true;
+    }
+    if !found {
+        return None;
+    }
+    symbol.offset = eip - symbol.addr;
+    Some(symbol)
+}
+
+/// An address, with its symbol where there is one: `0x0010A3F4 name+0x1c`
+pub struct Addr(pub u32);
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:08X}", self.0)?;
+        match symbolize(self.0) {
+            Some(symbol) => write!(f, " {}", symbol),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Words in a captured stack that point into kernel code, innermost
+/// first
+///
+/// The kernel isn't built with frame pointers, so this is a scan rather
+/// than a walk: most hits are return addresses, but stale ones from
+/// earlier calls show up too.
+pub fn return_addresses(stack: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    stack.chunks_exact(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+        .filter(|&addr| is_kernel_text(addr))
+        .take(MAX_TRACE)
+}
+
+/// Write the likely call trace from a captured stack
+pub fn write_trace(out: &mut dyn fmt::Write, stack: &[u8]) {
+    let _ = writeln!(out, "Call trace (scanned from the stack):");
+    for addr in return_addresses(stack) {
+        let _ = writeln!(out, "  ? {}", Addr(addr));
+    }
+}
@@ -35,6 +35,29 @@ mod syscall;
 mod drivers;
 mod fs;
 mod gui;
+mod settings;
+mod klog;
+mod kassert;
+mod trace;
+mod bench;
+mod selftest;
+mod profile;
+mod ksyms;
+mod stackprot;
+mod crashdump;
+mod exec;
+mod tty;
+mod ipc;
+mod time;
+mod rng;
+mod shutdown;
+mod commands;
+mod text_shell;
+mod vt;
+mod watchdog;
+mod softirq;
+mod subsys;
+mod util;
 
 use boot_info::BootInfo;
 use drivers::vga;
@@ -46,10 +69,19 @@ use core::arch::global_asm;
 // Panic handler - required for no_std
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    // Try to print panic info if we have a console
+    // Try to print panic info if we have a console (the log copies it
+    // to the debug console); before that, the debug console alone
     if let Some(writer) = unsafe { drivers::vga::WRITER.as_mut() } {
         let _ = writeln!(writer, "\n!!! KERNEL PANIC !!!");
         let _ = writeln!(writer, "{}", info);
+    } else {
+        let _ = writeln!(drivers::debugcon::Debugcon, "\n!!! KERNEL PANIC !!!\n{}", info);
+    }
+
+    // Save a crash dump for analysis after reboot
+    crashdump::record_panic(info);
+    if let (Some(writer), Some(dump)) = (unsafe { (*core::ptr::addr_of_mut!(drivers::vga::WRITER)).as_mut() }, crashdump::last()) {
+        ksyms::write_trace(writer, dump.stack());
     }
 
     // Halt the CPU
@@ -84,6 +116,10 @@ global_asm!(
 /// Called from _start with boot_info pointer on stack
 #[no_mangle]
 extern "C" fn kernel_main(boot_info_ptr: u32) -> ! {
+    // Emulator debug console: the one output that needs no setup
+    let debugcon_ok = drivers::debugcon::init();
+    drivers::debugcon::write_str("[BOOT] kernel_main\n");
+
     // Debug: Write '2' to VGA - we made it into Rust!
     unsafe {
         let vga = 0xB8006 as *mut u8;
@@ -108,6 +144,7 @@ extern "C" fn kernel_main(boot_info_ptr: u32) -> ! {
 
     // Verify boot magic
     if !boot_info.verify_magic() {
+        drivers::debugcon::write_str("[BOOT] Bad boot info magic, halting\n");
         unsafe {
             let vga = 0xB800A as *mut u8;
             vga.write_volatile(b'X');
@@ -125,11 +162,14 @@ extern "C" fn kernel_main(boot_info_ptr: u32) -> ! {
         vga.add(1).write_volatile(0x2F);
     }
 
+    // Legacy platform ports belong to the kernel; drivers claim the rest
+    arch::x86::ioport::init();
+
     // Initialize VGA/VESA display for boot messages
     unsafe {
         if boot_info.vesa_enabled {
             vga::init_framebuffer(
-                boot_info.framebuffer_addr,
+                boot_info.framebuffer_addr.as_u32(),
                 boot_info.screen_width,
                 boot_info.screen_height,
                 boot_info.bits_per_pixel,
@@ -140,6 +180,9 @@ extern "C" fn kernel_main(boot_info_ptr: u32) -> ! {
         }
     }
 
+    // Serial console for crash dumps (optional hardware)
+    let serial_ok = drivers::serial::init();
+
     // Now we can print!
     let writer = unsafe { vga::WRITER.as_mut().unwrap() };
 
@@ -150,6 +193,17 @@ extern "C" fn kernel_main(boot_info_ptr: u32) -> ! {
     let _ = writeln!(writer, "========================================");
     let _ = writeln!(writer, "");
 
+    let _ = writeln!(writer, "[BOOT] Serial COM1: {}", if serial_ok { "yes" } else { "no" });
+    if debugcon_ok {
+        let _ = writeln!(writer, "[BOOT] Debug console: port 0x{:02X}", drivers::debugcon::PORT);
+    }
+    let _ = writeln!(writer, "[BOOT] Subsystems: {}", subsys::built_names());
+    let _ = writeln!(writer, "[BOOT] Kernel symbols: {}", ksyms::count());
+    if let Some(dump) = crashdump::last() {
+        let _ = writeln!(writer, "[BOOT] Previous crash: {} (EIP {})",
+                         dump.message(), ksyms::Addr(dump.regs.eip));
+    }
+
     // Display boot info
     let _ = writeln!(writer, "[BOOT] Display: {}x{} @ {}bpp",
                      boot_info.screen_width,
@@ -157,10 +211,26 @@ extern "C" fn kernel_main(boot_info_ptr: u32) -> ! {
                      boot_info.bits_per_pixel
     );
     let _ = writeln!(writer, "[BOOT] Framebuffer: 0x{:08X}", boot_info.framebuffer_addr);
+    if !boot_info.cmdline().is_empty() {
+        let _ = writeln!(writer, "[BOOT] Command line: {}", boot_info.cmdline());
+    }
+    // Before memory init, so the PMM keeps its pages
+    match fs::loopback::set_boot_image(boot_info.ramdisk_addr, boot_info.ramdisk_size) {
+        Ok(()) if boot_info.ramdisk_size != 0 => {
+            let _ = writeln!(writer, "[BOOT] Disk image: {} KB at 0x{:08X}",
+                             boot_info.ramdisk_size / 1024, boot_info.ramdisk_addr);
+        }
+        Ok(()) => {}
+        Err(e) => { let _ = writeln!(writer, "[BOOT] Disk image ignored: {}", e); }
+    }
+    if boot_info.safe_mode() {
+        let _ = writeln!(writer, "[BOOT] Safe mode: experimental drivers disabled");
+    }
 
     // Initialize GDT
     let _ = write!(writer, "[INIT] Loading GDT...");
     gdt::init();
+    gdt::init_tss();
     let _ = writeln!(writer, " OK");
 
     // Initialize IDT
@@ -168,32 +238,104 @@ extern "C" fn kernel_main(boot_info_ptr: u32) -> ! {
     idt::init();
     let _ = writeln!(writer, " OK");
 
+    // Machine checks report through #MC, so only after the IDT is up
+    match arch::x86::mce::init() {
+        Ok(banks) => { let _ = writeln!(writer, "[INIT] Machine checks enabled, {} banks", banks); }
+        Err(e) => { let _ = writeln!(writer, "[INIT] Machine checks: {}", e); }
+    }
+
+    // PAT must be set before any framebuffer is mapped
+    match arch::x86::mtrr::init() {
+        Ok(method) => { let _ = writeln!(writer, "[INIT] Write-combining via {}", method.name()); }
+        Err(e) => { let _ = writeln!(writer, "[INIT] Write-combining: {}", e); }
+    }
+
+    if serial_ok {
+        if let Err(e) = drivers::serial::enable_receive() {
+            let _ = writeln!(writer, "[SER ] Receive disabled: {}", e);
+        }
+    }
+
     // Parse E820 memory map and initialize memory manager
     let _ = write!(writer, "[INIT] Parsing E820 memory map...");
     let mem_info = mm::init(boot_info.e820_map_addr);
+    // The console framebuffer may lie outside the identity map
+    let console_mapped = writer.remap_framebuffer();
     let _ = writeln!(writer, " OK");
+    if let Err(e) = console_mapped {
+        let _ = writeln!(writer, "[MEM ] Console framebuffer: {}", e);
+    }
     let _ = writeln!(writer, "[MEM ] Total: {} KB, Usable: {} KB",
                      mem_info.total_kb,
                      mem_info.usable_kb
     );
+    let _ = writeln!(writer, "[MEM ] Paging: {}",
+                     if mem_info.paging { "enabled" } else { "FAILED" });
+    if mem_info.paging {
+        match mm::paging::enforce_wx() {
+            Ok(()) => { let _ = writeln!(writer, "[MEM ] W^X: kernel code read-only, data not executable"); }
+            Err(e) => { let _ = writeln!(writer, "[MEM ] W^X: {}", e); }
+        }
+    }
+
+    // Program the PIT for the system tick
+    let _ = write!(writer, "[INIT] Starting PIT timer...");
+    arch::x86::pit::init();
+    sched::timer::init();
+    let _ = writeln!(writer, " OK");
+
+    // Wall clock from the CMOS RTC (UTC)
+    match time::init() {
+        Some(now) => { let _ = writeln!(writer, "[INIT] RTC: {} UTC", now); }
+        None => { let _ = writeln!(writer, "[INIT] RTC: unreadable, clock starts at 1970"); }
+    }
 
     // Enable interrupts
     let _ = write!(writer, "[INIT] Enabling interrupts...");
     unsafe { core::arch::asm!("sti"); }
     let _ = writeln!(writer, " OK");
 
-    // If we have VESA graphics, start the GUI
-    if boot_info.vesa_enabled && boot_info.screen_width > 0 {
+    // TSC rate for high-resolution timestamps (waits on the PIT)
+    match arch::x86::tsc::init() {
+        Ok(khz) => { let _ = writeln!(writer, "[INIT] TSC: {}.{:03} MHz", khz / 1000, khz % 1000); }
+        Err(e) => { let _ = writeln!(writer, "[INIT] TSC: {}, timing from the PIT", e); }
+    }
+    rng::init();
+    stackprot::init();
+
+    // Other CPUs, found through the MADT or MP table
+    let start_aps = !boot_info.safe_mode() && !boot_info.has_option("nosmp");
+    match arch::x86::smp::init(start_aps) {
+        Ok(_) => {
+            let _ = writeln!(writer, "[SMP ] {} CPU(s) online{}", arch::x86::smp::online_count(),
+                             if start_aps { "" } else { " (APs not started)" });
+        }
+        Err(e) => { let _ = writeln!(writer, "[SMP ] Single CPU: {}", e); }
+    }
+
+    // Headless runs draw into RAM instead (`vfb` or `vfb=<w>x<h>`)
+    let virtual_fb = if boot_info.has_option("vfb") {
+        Some((drivers::vfb::DEFAULT_WIDTH, drivers::vfb::DEFAULT_HEIGHT))
+    } else {
+        boot_info.option_value("vfb").and_then(drivers::vfb::parse_size)
+    };
+    drivers::vfb::set_screenshots(boot_info.has_option("vfbshot"));
+
+    // If we have VESA graphics (or a virtual display), start the GUI
+    if (boot_info.vesa_enabled && boot_info.screen_width > 0) || virtual_fb.is_some() {
         let _ = writeln!(writer, "");
         let _ = writeln!(writer, "[DRV ] Initializing drivers via EventChain...");
 
         // Use Driver EventChain for fault-tolerant initialization
         let drv_result = drivers::init_all_drivers(
-            boot_info.framebuffer_addr,
+            boot_info.framebuffer_addr.as_u32(),
             boot_info.screen_width,
             boot_info.screen_height,
             boot_info.bits_per_pixel / 8,
             boot_info.pitch,
+            boot_info.safe_mode(),
+            boot_info.has_option("noapic"),
+            virtual_fb,
         );
 
         // Report driver initialization results
@@ -201,6 +343,19 @@ extern "C" fn kernel_main(boot_info_ptr: u32) -> ! {
         let _ = writeln!(writer, "[DRV ] Input: {}", drv_result.input_type_str());
         let _ = writeln!(writer, "[DRV ] Hardware cursor: {}",
                          if drv_result.hw_cursor { "yes" } else { "no" });
+        let _ = writeln!(writer, "[DRV ] Write-combining: {}",
+                         if drv_result.write_combining { "yes" } else { "no" });
+        let _ = writeln!(writer, "[DRV ] Interrupts: {}",
+                         if drv_result.apic { "IO-APIC" } else { "8259 PIC" });
+        if drv_result.agp_aperture > 0 {
+            let _ = writeln!(writer, "[DRV ] AGP aperture: {} MB", drv_result.agp_aperture >> 20);
+        }
+        if drv_result.safe_mode {
+            for name in drv_result.skipped.iter().flatten() {
+                let _ = writeln!(writer, "[DRV ] Skipped (safe mode): {}", name);
+            }
+            gui::notify::warn("Safe mode: experimental drivers disabled");
+        }
 
         // Report any failures (non-fatal in BestEffort mode)
         if drv_result.failure_count > 0 {
@@ -208,40 +363,129 @@ extern "C" fn kernel_main(boot_info_ptr: u32) -> ! {
             for i in 0..drv_result.failure_count {
                 if let Some(name) = drv_result.failures[i] {
                     let _ = writeln!(writer, "[DRV ]   - {}", name);
+
+                    let mut msg = alloc::string::String::new();
+                    let _ = write!(msg, "Driver failed: {}", name);
+                    gui::notify::warn(&msg);
+                }
+            }
+        }
+
+        // Storage: block cache, disks, partitions, root filesystem
+        if let Some(blocks) = fs::loopback::add_boot_image() {
+            let _ = writeln!(writer, "[FS  ] Boot disk image: {} blocks", blocks);
+        }
+        let _ = writeln!(writer, "[FS  ] Initializing storage via EventChain...");
+        let mount_options = fs::MountOptions {
+            read_only: boot_info.has_option("ro"),
+            force: boot_info.has_option("fsforce"),
+        };
+        let root_uuid = boot_info.option_value("root")
+            .and_then(|root| root.strip_prefix("UUID="))
+            .and_then(util::Uuid::parse);
+        let fs_result = fs::init::init_storage(mount_options, root_uuid);
+        let _ = writeln!(writer, "[FS  ] Block cache: {} buffers", fs_result.cache_buffers);
+        let _ = writeln!(writer, "[FS  ] Disks: {}, volumes: {}",
+                         fs_result.disk_count, fs_result.volume_count);
+        match (fs_result.root, fs_result.root_kind()) {
+            (Some(dev), Some(kind)) => {
+                let vfs = unsafe { &*core::ptr::addr_of!(fs::vfs::VFS) };
+                let _ = writeln!(writer, "[FS  ] Root: {} on dev{}{}", kind.as_str(), dev.0,
+                                 if vfs.is_read_only() { " (read-only)" } else { "" });
+                if let Some(uuid) = vfs.root_volume_id() {
+                    let _ = writeln!(writer, "[FS  ] Root UUID: {}", uuid);
                 }
             }
+            _ => {
+                let _ = writeln!(writer, "[FS  ] Root: none");
+            }
+        }
+        if fs_result.failure_count > 0 {
+            let _ = writeln!(writer, "[FS  ] Failures (non-fatal):");
+            for (name, error) in fs_result.failures.iter().flatten() {
+                let _ = writeln!(writer, "[FS  ]   - {}: {}", name, error);
+            }
+        }
+
+        // Input to play into the GUI once it starts (replay=<name>)
+        if let Some(name) = boot_info.option_value("replay") {
+            match gui::recorder::play_source(name) {
+                Ok(count) => {
+                    let _ = writeln!(writer, "[GUI  ] Replaying {} inputs from {}", count, name);
+                }
+                Err(e) => crate::klog!(Err, "input", "replay {}: {}", name, e),
+            }
+        }
+
+        // Restore persisted settings (defaults if none are stored)
+        let cfg_status = settings::init();
+        let _ = writeln!(writer, "[CFG ] Settings: {:?}", cfg_status);
+
+        // Self-tests (selftest), also reported on COM1 for headless runs
+        if boot_info.has_option("selftest") {
+            selftest::run_at_boot(writer);
         }
 
         let _ = writeln!(writer, "");
         let _ = writeln!(writer, "[READY] Rustacean OS kernel initialized!");
-        let _ = writeln!(writer, "[READY] EventChains: Driver, Kernel, WindowManager");
-        let _ = writeln!(writer, "[GUI  ] Starting graphical interface...");
+        #[cfg(feature = "gui")]
+        {
+            let _ = writeln!(writer, "[READY] EventChains: Driver, Kernel, WindowManager");
+            let _ = writeln!(writer, "[GUI  ] Starting graphical interface...");
+
+            // Small delay to show messages
+            for _ in 0..50000000u32 {
+                unsafe { core::arch::asm!("nop"); }
+            }
 
-        // Small delay to show messages
-        for _ in 0..50000000u32 {
-            unsafe { core::arch::asm!("nop"); }
+            // Without a framebuffer (or one too big for the back buffer) the
+            // GUI can't run; leave the graphics mode for a text console
+            if let Some(reason) = gui_unavailable(&drv_result) {
+                text_shell::run(true, reason);
+            }
+            run_gui(drv_result);
+        }
+        #[cfg(not(feature = "gui"))]
+        {
+            let _ = writeln!(writer, "[READY] EventChains: Driver, Kernel");
+            text_shell::run(true, "Built without the GUI");
         }
-
-        run_gui(drv_result);
     } else {
         let _ = writeln!(writer, "[TEXT] Running in text mode - no GUI available");
-        loop {
-            unsafe { core::arch::asm!("hlt"); }
-        }
+        text_shell::run(false, "No VESA mode from the bootloader");
     }
 }
 
+/// Why the GUI can't start with these drivers, if it can't
+#[cfg(feature = "gui")]
+fn gui_unavailable(drv: &drivers::DriverInitResult) -> Option<&'static str> {
+    if gui::framebuffer::get().is_none() {
+        return Some("GUI failed: no framebuffer (driver chain failed)");
+    }
+    let size = drv.height as usize * drv.pitch as usize;
+    if size > unsafe { BACK_BUFFER_DATA.len() } {
+        return Some("GUI failed: mode too large for the back buffer");
+    }
+    None
+}
+
 // =============================================================================
 // Back buffer for double buffering (in BSS section - regular RAM)
 // =============================================================================
+#[cfg(feature = "gui")]
 static mut BACK_BUFFER_DATA: [u8; 800 * 600 * 4] = [0u8; 800 * 600 * 4];
 
+/// Keep the GUI loop spinning this long after PS/2 input before halting
+#[cfg(feature = "gui")]
+const INPUT_GRACE_MS: u32 = 50;
+
 /// Run the graphical user interface
 ///
 /// Uses:
 /// - Driver EventChain results for display/input configuration
 /// - Window Manager EventChain for discrete window events
 /// - Direct calls for hot path (mouse tracking, rendering)
+#[cfg(feature = "gui")]
 fn run_gui(drv: drivers::DriverInitResult) -> ! {
     // Create back buffer for double buffering
     let mut back_buffer = unsafe {
@@ -260,182 +504,188 @@ fn run_gui(drv: drivers::DriverInitResult) -> ! {
     let desktop = gui::desktop::get().expect("Desktop not initialized");
     let fb = gui::framebuffer::get().expect("Framebuffer not initialized");
 
-    // Create demo windows (goes through WM EventChain)
-    desktop.create_window("Welcome to Rustacean OS!", 50, 50, 450, 220);
-    desktop.create_terminal_window(100, 280, 400, 180);  // Heap-allocated terminal!
-    desktop.create_window("Files", 470, 50, 300, 220);
+    // Pace presents to vblank when the GPU can interrupt us for it
+    desktop.set_vsync(drv.vblank_irq);
+
+    // Bring back the last session's windows, or the demo windows on a
+    // first boot (either way through the WM EventChain)
+    let session: alloc::vec::Vec<_> = settings::get().session.iter().flatten().copied().collect();
+    if desktop.restore_session(&session) == 0 {
+        desktop.create_window(gui::desktop::WindowKind::Welcome.title(), 50, 50, 450, 220);
+        desktop.create_terminal_window(100, 280, 400, 180);  // Heap-allocated terminal!
+        desktop.create_window(gui::desktop::WindowKind::Files.title(), 470, 50, 300, 220);
+    }
 
     desktop.mark_dirty();
 
+    // Headless runs report the first frame (and the end of a replay)
+    let checkpoints = drivers::vfb::is_active();
+    if checkpoints {
+        let _ = drivers::vfb::request_checkpoint("desktop");
+    }
+
     // =========================================================================
     // Main GUI event loop (Polling Mode)
     // =========================================================================
 
     // Disable keyboard (IRQ1) and mouse (IRQ12) interrupts - we'll poll instead
     // This avoids race conditions between IRQ handlers and our polling loop
-    unsafe {
-        // Disable IRQ1 (keyboard) on master PIC
-        let mask = crate::arch::x86::io::inb(0x21);
-        crate::arch::x86::io::outb(0x21, mask | 0x02);  // Set bit 1
+    crate::arch::x86::irqchip::disable_irq(1);
+    crate::arch::x86::irqchip::disable_irq(12);
 
-        // Disable IRQ12 (mouse) on slave PIC
-        let mask = crate::arch::x86::io::inb(0xA1);
-        crate::arch::x86::io::outb(0xA1, mask | 0x10);  // Set bit 4
-    }
-
-    let mut last_mouse_x = (drv.width / 2) as i32;
-    let mut last_mouse_y = (drv.height / 2) as i32;
-    let mut last_buttons = 0u8;
+    // Raw input becomes GuiEvents, queued on the windows they are for
+    let mut input = gui::EventLoop::new((drv.width / 2) as i32, (drv.height / 2) as i32);
 
-    // Keyboard-controlled cursor (fallback)
-    let mut kb_cursor_x = last_mouse_x;
-    let mut kb_cursor_y = last_mouse_y;
-    let cursor_speed = 8i32;
+    // Last time the PS/2 controller had data (see the idle halt below)
+    let mut last_input_ms = 0u32;
 
     let using_synaptics = drv.is_synaptics();
     let using_ati_rage = drv.is_ati_rage();
 
+    // Catch polling-loop deadlocks: the loop pets the watchdog every frame
+    watchdog::enable(watchdog::DEFAULT_TIMEOUT_MS, false);
+
     loop {
+        if let Some(stalled_ms) = watchdog::pet() {
+            let mut msg = alloc::string::String::new();
+            let _ = write!(msg, "GUI stalled for {} ms", stalled_ms);
+            gui::notify::warn(&msg);
+        }
+
+        let now_ms = arch::x86::pit::uptime_ms();
+
         // =====================================================================
         // Poll PS/2 controller - route keyboard and mouse data to drivers
         // =====================================================================
-        unsafe {
+        // Interrupts off: the IRQ handlers queue into the same buffers
+        arch::x86::idt::without_interrupts(|| unsafe {
             let status = crate::arch::x86::io::inb(0x64);
 
             // Check if output buffer has data (bit 0)
             if status & 0x01 != 0 {
                 let data = crate::arch::x86::io::inb(0x60);
+                last_input_ms = now_ms;
 
                 // Bit 5 tells us if it's from auxiliary device (mouse/touchpad)
                 if status & 0x20 == 0 {
-                    // Keyboard data - process through keyboard driver
-                    drivers::keyboard::KEYBOARD.process_scancode(data);
+                    // Keyboard data - queue for the keyboard driver
+                    drivers::keyboard::queue_scancode(data);
                 } else {
                     // Mouse/touchpad data - route to appropriate driver
                     if using_synaptics {
-                        drivers::synaptics::handle_irq_byte(data);
+                        drivers::synaptics::queue_byte(data);
                     } else {
-                        drivers::mouse::MOUSE.process_byte(data);
+                        drivers::mouse::queue_byte(data);
                     }
                 }
             }
+        });
+
+        // Deferred work the IRQ exits left over, and anything just polled
+        softirq::run();
+
+        // A replay (gui::recorder) stands in for live keys and pointer
+        // samples until it ends; they are still drained
+        let replaying = gui::recorder::is_playing();
+        while let Some((recorded, time_ms)) = gui::recorder::next_due(now_ms) {
+            if input.replay(desktop, recorded, time_ms) && using_ati_rage {
+                if let (Some(gpu), gui::recorder::Input::Pointer { x, y, .. }) = (drivers::ati_rage::get(), recorded) {
+                    gpu.set_cursor_pos(x, y);
+                }
+            }
+        }
+        if checkpoints && replaying && !gui::recorder::is_playing() {
+            let _ = drivers::vfb::request_checkpoint("replay");
         }
 
         // =====================================================================
         // Handle keyboard input - poll driver buffer
         // =====================================================================
         while let Some(key) = drivers::keyboard::get_key() {
-            use drivers::keyboard::KeyCode;
-
-            if desktop.is_terminal_focused() {
-                // Terminal input mode
-                match key.keycode {
-                    KeyCode::Enter => desktop.term_enter(),
-                    KeyCode::Backspace => desktop.term_backspace(),
-                    KeyCode::Up => {
-                        kb_cursor_y = (kb_cursor_y - cursor_speed).max(0);
-                        desktop.handle_mouse_move(kb_cursor_x, kb_cursor_y);
-                    }
-                    KeyCode::Down => {
-                        kb_cursor_y = (kb_cursor_y + cursor_speed).min(drv.height as i32 - 1);
-                        desktop.handle_mouse_move(kb_cursor_x, kb_cursor_y);
-                    }
-                    KeyCode::Left => {
-                        kb_cursor_x = (kb_cursor_x - cursor_speed).max(0);
-                        desktop.handle_mouse_move(kb_cursor_x, kb_cursor_y);
-                    }
-                    KeyCode::Right => {
-                        kb_cursor_x = (kb_cursor_x + cursor_speed).min(drv.width as i32 - 1);
-                        desktop.handle_mouse_move(kb_cursor_x, kb_cursor_y);
-                    }
-                    _ => {
-                        // Send printable characters to terminal
-                        if let Some(c) = key.ascii {
-                            desktop.term_key_input(c);
-                        }
+            // Ctrl+Alt+F1 / F7 switch virtual consoles
+            if let Some(console) = vt::hotkey(&key) {
+                match vt::switch(console) {
+                    Ok(true) if console == vt::Console::Gui => desktop.mark_dirty(),
+                    Ok(_) => {}
+                    Err(e) => {
+                        gui::notify::warn(e);
                     }
                 }
-            } else {
-                // Window navigation mode
-                match key.keycode {
-                    KeyCode::Up | KeyCode::W => {
-                        kb_cursor_y = (kb_cursor_y - cursor_speed).max(0);
-                        desktop.handle_mouse_move(kb_cursor_x, kb_cursor_y);
-                    }
-                    KeyCode::Down | KeyCode::S => {
-                        kb_cursor_y = (kb_cursor_y + cursor_speed).min(drv.height as i32 - 1);
-                        desktop.handle_mouse_move(kb_cursor_x, kb_cursor_y);
-                    }
-                    KeyCode::Left | KeyCode::A => {
-                        kb_cursor_x = (kb_cursor_x - cursor_speed).max(0);
-                        desktop.handle_mouse_move(kb_cursor_x, kb_cursor_y);
-                    }
-                    KeyCode::Right | KeyCode::D => {
-                        kb_cursor_x = (kb_cursor_x + cursor_speed).min(drv.width as i32 - 1);
-                        desktop.handle_mouse_move(kb_cursor_x, kb_cursor_y);
-                    }
-                    KeyCode::Enter => unsafe {
-                        desktop.handle_mouse_button(gui::MouseButton::Left, true);
-                        for _ in 0..100000u32 { core::arch::asm!("nop"); }
-                        desktop.handle_mouse_button(gui::MouseButton::Left, false);
-                    }
-                    KeyCode::Space => {
-                        desktop.handle_mouse_button(gui::MouseButton::Left, true);
-                    }
-                    _ => {}
-                }
+                continue;
+            }
+
+            // The text console takes the keyboard while it is shown
+            if vt::active() == vt::Console::Text {
+                vt::key(key);
+                continue;
+            }
+
+            if !replaying {
+                input.key(desktop, &key);
             }
         }
 
         // =====================================================================
         // Handle pointing device input (direct - hot path)
         // =====================================================================
-        let (mouse_x, mouse_y, buttons) = if using_synaptics {
+        let (mouse_x, mouse_y, buttons, packet_ms) = if using_synaptics {
             let (x, y) = drivers::synaptics::get_position();
             let btns = drivers::synaptics::get_buttons();
-            (x, y, btns)
+            (x, y, btns, drivers::synaptics::last_packet_ms())
         } else {
             let (x, y) = drivers::mouse::get_position();
             let btns = drivers::mouse::get_buttons();
-            (x, y, btns)
+            (x, y, btns, drivers::mouse::last_packet_ms())
         };
 
-        if mouse_x != last_mouse_x || mouse_y != last_mouse_y {
-            desktop.handle_mouse_move(mouse_x, mouse_y);
-            kb_cursor_x = mouse_x;
-            kb_cursor_y = mouse_y;
-
-            if using_ati_rage {
-                if let Some(gpu) = drivers::ati_rage::get() {
-                    gpu.set_cursor_pos(mouse_x, mouse_y);
-                }
+        if !replaying && input.pointer(desktop, mouse_x, mouse_y, buttons, packet_ms) && using_ati_rage {
+            if let Some(gpu) = drivers::ati_rage::get() {
+                gpu.set_cursor_pos(mouse_x, mouse_y);
             }
-
-            last_mouse_x = mouse_x;
-            last_mouse_y = mouse_y;
         }
-
-        if buttons != last_buttons {
-            if (buttons & 0x01) != (last_buttons & 0x01) {
-                desktop.handle_mouse_button(gui::MouseButton::Left, buttons & 0x01 != 0);
+        if !using_synaptics {
+            let wheel = drivers::mouse::take_wheel();
+            if wheel != 0 && !replaying {
+                input.wheel(desktop, wheel, packet_ms);
             }
-            if (buttons & 0x02) != (last_buttons & 0x02) {
-                desktop.handle_mouse_button(gui::MouseButton::Right, buttons & 0x02 != 0);
-            }
-            if (buttons & 0x04) != (last_buttons & 0x04) {
-                desktop.handle_mouse_button(gui::MouseButton::Middle, buttons & 0x04 != 0);
-            }
-            last_buttons = buttons;
         }
 
+        // Window content handlers consume their queued events
+        desktop.pump_events();
+
         // =====================================================================
         // Draw the desktop (direct - hot path, double buffered)
         // =====================================================================
-        desktop.draw(&mut back_buffer, fb);
+        exec::poll();
+        shutdown::poll();
+        drivers::accessibility::poll();
+
+        // The text console owns the screen: keep programs running, don't draw
+        if vt::active() == vt::Console::Text {
+            vt::poll();
+            desktop.close_orphaned_windows();
+            desktop.term_poll();
+            fs::bcache::periodic_flush(now_ms);
+            arch::x86::idle::halt();
+            continue;
+        }
 
-        // Small yield
-        for _ in 0..10000u32 {
-            unsafe { core::arch::asm!("nop"); }
+        desktop.close_orphaned_windows();
+        desktop.term_poll();
+        input.dispatch(desktop, gui::InputEvent::new(gui::GuiEvent::Tick, now_ms));
+        fs::bcache::periodic_flush(now_ms);
+        desktop.draw(&mut back_buffer, fb);
+        drivers::vfb::poll();
+
+        // Nothing to do until the next interrupt: halt rather than spin.
+        // IRQ1/IRQ12 are masked, so the timer is what wakes us; keep
+        // polling for a moment after input, while a mouse packet or key
+        // repeat is likely still arriving byte by byte.
+        let idle = now_ms.wrapping_sub(last_input_ms) >= INPUT_GRACE_MS
+            && !desktop.needs_redraw()
+            && !exec::has_runnable();
+        if idle {
+            arch::x86::idle::halt();
         }
     }
 }
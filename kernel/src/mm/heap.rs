@@ -1,11 +1,29 @@
-//! Ultra-Simple Bump Allocator for Rustacean OS
+//! Kernel Heap
 //!
-//! No atomics, no frills - just bumps a pointer forward.
-//! NOT thread-safe, but we're single-threaded anyway.
+//! A first-fit free-list allocator over a fixed 4MB region. Free blocks
+//! are kept in address order, each holding its size and the next free
+//! block in its first bytes; a freed block is merged with the free blocks
+//! on either side, so memory given back is reused.
+//!
+//! Every block is a multiple of `UNIT` (a free block's header) and starts
+//! on one, so what is left over when a block is split can always hold a
+//! header. Allocations aren't prefixed with a header of their own:
+//! `dealloc` is handed the layout, which gives the size back.
+//!
+//! When free memory drops below `HEAP_RESERVE` the OOM policy is told
+//! (see `mm::oom`), and acts on it from the main loop. An allocation that
+//! finds no block big enough fails, and has the policy mark a program to
+//! be killed from the main loop, whose memory later allocations get.
+//!
+//! `stats` counts allocations and frees per power-of-two size class, live
+//! bytes and their peak, and the bytes lost to rounding up to `UNIT`.
+//! Live bytes are also kept per `CHUNK_SIZE` chunk for `map`, which the
+//! `heapmap` command draws.
 
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr;
 use core::cell::UnsafeCell;
+use crate::sched::spinlock::RawSpinLock;
 
 // =============================================================================
 // Heap Configuration - 16MB mark, 4MB size
@@ -14,62 +32,266 @@ use core::cell::UnsafeCell;
 const HEAP_START: usize = 0x0100_0000;  // 16MB
 const HEAP_SIZE: usize = 0x0040_0000;   // 4MB
 const HEAP_END: usize = HEAP_START + HEAP_SIZE;
+const HEAP_RESERVE: usize = 0x0004_0000; // 256KB
+
+/// Granularity of the live-byte map
+pub const CHUNK_SIZE: usize = 0x4000; // 16KB
+/// Chunks in the heap
+pub const CHUNKS: usize = HEAP_SIZE / CHUNK_SIZE;
+
+/// Size classes: up to 16 bytes, 32, 64, ... 64KB, and bigger
+pub const SIZE_CLASSES: usize = 14;
+const SMALLEST_CLASS_SHIFT: u32 = 4;
+
+/// A free block's header, kept in the block itself
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// Block size and alignment granule
+const UNIT: usize = core::mem::size_of::<FreeBlock>();
+
+const _: () = assert!(HEAP_START % UNIT == 0 && HEAP_SIZE % UNIT == 0);
+
+// =============================================================================
+// Accounting
+// =============================================================================
+
+/// Counters updated by every alloc and dealloc
+struct Counters {
+    allocs: [u32; SIZE_CLASSES],
+    frees: [u32; SIZE_CLASSES],
+    live: usize,
+    peak_live: usize,
+    padding: usize,
+    failed: u32,
+    /// Live bytes per chunk
+    chunks: [u32; CHUNKS],
+}
+
+static mut COUNTERS: Counters = Counters {
+    allocs: [0; SIZE_CLASSES],
+    frees: [0; SIZE_CLASSES],
+    live: 0,
+    peak_live: 0,
+    padding: 0,
+    failed: 0,
+    chunks: [0; CHUNKS],
+};
+
+/// Size class of an allocation
+fn size_class(size: usize) -> usize {
+    let bits = usize::BITS - size.saturating_sub(1).leading_zeros();
+    (bits.saturating_sub(SMALLEST_CLASS_SHIFT) as usize).min(SIZE_CLASSES - 1)
+}
+
+/// Upper bound of a size class in bytes (None for the last, open class)
+pub fn class_limit(class: usize) -> Option<usize> {
+    (class < SIZE_CLASSES - 1).then(|| 1 << (class as u32 + SMALLEST_CLASS_SHIFT))
+}
+
+/// Add (or take) live bytes to the chunks under [start, start + size)
+unsafe fn account_chunks(counters: &mut Counters, start: usize, size: usize, add: bool) {
+    let mut addr = start;
+    let end = start + size;
+    while addr < end {
+        let chunk = (addr - HEAP_START) / CHUNK_SIZE;
+        let chunk_end = HEAP_START + (chunk + 1) * CHUNK_SIZE;
+        let bytes = (end.min(chunk_end) - addr) as u32;
+        let live = &mut counters.chunks[chunk];
+        *live = if add { *live + bytes } else { live.saturating_sub(bytes) };
+        addr = chunk_end;
+    }
+}
 
 // =============================================================================
-// Simple Bump Allocator (no atomics)
+// Free-List Allocator
 // =============================================================================
 
-pub struct SimpleBumpAllocator {
-    next: UnsafeCell<usize>,
+/// Free list and the bookkeeping it needs
+struct Heap {
+    /// Lowest free block
+    head: *mut FreeBlock,
+    /// Bytes in free blocks
+    free: usize,
+    /// Highest address ever handed out (the map shows nothing past it)
+    top: usize,
+    ready: bool,
+}
+
+pub struct FreeListAllocator {
+    heap: UnsafeCell<Heap>,
+    lock: RawSpinLock,
 }
 
-// We're single-threaded, so this is safe
-unsafe impl Sync for SimpleBumpAllocator {}
+// Only touched with `lock` held
+unsafe impl Sync for FreeListAllocator {}
 
-impl SimpleBumpAllocator {
+impl FreeListAllocator {
     pub const fn new() -> Self {
         Self {
-            next: UnsafeCell::new(HEAP_START),
+            heap: UnsafeCell::new(Heap { head: ptr::null_mut(), free: 0, top: HEAP_START, ready: false }),
+            lock: RawSpinLock::new(),
         }
     }
 
+    /// Make the whole region one free block
     pub unsafe fn init(&self) {
-        *self.next.get() = HEAP_START;
+        self.lock.with(|| (*self.heap.get()).reset());
     }
 
-    /// Align address up
-    fn align_up(addr: usize, align: usize) -> usize {
-        (addr + align - 1) & !(align - 1)
+    fn with_heap<R>(&self, f: impl FnOnce(&mut Heap) -> R) -> R {
+        self.lock.with(|| {
+            let heap = unsafe { &mut *self.heap.get() };
+            if !heap.ready {
+                unsafe { heap.reset() };
+            }
+            f(heap)
+        })
     }
 }
 
-unsafe impl GlobalAlloc for SimpleBumpAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let next_ptr = self.next.get();
-        let current = *next_ptr;
+/// Block size for a request
+fn block_size(layout: &Layout) -> usize {
+    layout.size().max(1).next_multiple_of(UNIT)
+}
 
-        // Align
-        let alloc_start = Self::align_up(current, layout.align());
-        let alloc_end = alloc_start + layout.size();
+impl Heap {
+    unsafe fn reset(&mut self) {
+        let block = HEAP_START as *mut FreeBlock;
+        (*block).size = HEAP_SIZE;
+        (*block).next = ptr::null_mut();
+        self.head = block;
+        self.free = HEAP_SIZE;
+        self.top = HEAP_START;
+        self.ready = true;
+    }
+
+    /// Carve `size` bytes aligned to `align` out of the first free block
+    /// that has room
+    unsafe fn take(&mut self, size: usize, align: usize) -> Option<usize> {
+        let mut link: *mut *mut FreeBlock = &mut self.head;
+        while !(*link).is_null() {
+            let block = *link;
+            let start = block as usize;
+            let end = start + (*block).size;
+            let at = start.next_multiple_of(align);
+            if at + size <= end {
+                // What is left after the allocation stays free
+                let mut rest = (*block).next;
+                if at + size < end {
+                    let tail = (at + size) as *mut FreeBlock;
+                    (*tail).size = end - (at + size);
+                    (*tail).next = rest;
+                    rest = tail;
+                }
+                // And so does what alignment skipped in front of it
+                if at > start {
+                    (*block).size = at - start;
+                    (*block).next = rest;
+                } else {
+                    *link = rest;
+                }
+                self.free -= size;
+                self.top = self.top.max(at + size);
+                return Some(at);
+            }
+            link = &mut (*block).next;
+        }
+        None
+    }
 
-        // Bounds check
-        if alloc_end > HEAP_END {
-            return ptr::null_mut();
+    /// Return a block, merging it with free neighbours
+    unsafe fn give(&mut self, start: usize, size: usize) {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
         }
 
-        // Bump
-        *next_ptr = alloc_end;
+        let block = start as *mut FreeBlock;
+        (*block).size = size;
+        (*block).next = next;
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+        self.free += size;
+    }
+
+    /// Largest free block
+    fn largest_free(&self) -> usize {
+        let mut largest = 0;
+        let mut block = self.head;
+        while !block.is_null() {
+            unsafe {
+                largest = largest.max((*block).size);
+                block = (*block).next;
+            }
+        }
+        largest
+    }
+}
+
+unsafe impl GlobalAlloc for FreeListAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = block_size(&layout);
+        let align = layout.align().max(UNIT);
+        let taken = self.with_heap(|heap| {
+            let was_low = heap.free < HEAP_RESERVE;
+            let Some(at) = heap.take(size, align) else {
+                (*ptr::addr_of_mut!(COUNTERS)).failed += 1;
+                return None;
+            };
 
-        alloc_start as *mut u8
+            let counters = &mut *ptr::addr_of_mut!(COUNTERS);
+            counters.allocs[size_class(layout.size())] += 1;
+            counters.padding += size - layout.size();
+            counters.live += layout.size();
+            counters.peak_live = counters.peak_live.max(counters.live);
+            account_chunks(counters, at, layout.size(), true);
+            Some((at, !was_low && heap.free < HEAP_RESERVE))
+        });
+        match taken {
+            Some((at, low)) => {
+                if low {
+                    crate::mm::oom::heap_pressure();
+                }
+                at as *mut u8
+            }
+            None => {
+                crate::mm::oom::reclaim();
+                ptr::null_mut()
+            }
+        }
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        // Bump allocator doesn't free
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with_heap(|heap| {
+            heap.give(ptr as usize, block_size(&layout));
+
+            let counters = &mut *ptr::addr_of_mut!(COUNTERS);
+            counters.frees[size_class(layout.size())] += 1;
+            counters.padding -= block_size(&layout) - layout.size();
+            counters.live = counters.live.saturating_sub(layout.size());
+            account_chunks(counters, ptr as usize, layout.size(), false);
+        });
     }
 }
 
 #[global_allocator]
-static ALLOCATOR: SimpleBumpAllocator = SimpleBumpAllocator::new();
+static ALLOCATOR: FreeListAllocator = FreeListAllocator::new();
 
 pub unsafe fn init() {
     ALLOCATOR.init();
@@ -77,14 +299,69 @@ pub unsafe fn init() {
 
 /// Heap stats
 pub struct HeapStats {
+    /// Bytes in allocated blocks (live bytes plus rounding)
     pub used: usize,
+    /// Bytes in free blocks
     pub free: usize,
+    /// Largest free block (the biggest allocation that can succeed)
+    pub largest_free: usize,
+    /// Bytes in allocations not yet freed
+    pub live: usize,
+    /// Most bytes ever live at once
+    pub peak_live: usize,
+    /// Bytes lost to rounding live allocations up to whole units
+    pub padding: usize,
+    /// Allocations refused
+    pub failed: u32,
+    /// Allocations per size class (see `class_limit`)
+    pub allocs: [u32; SIZE_CLASSES],
+    /// Frees per size class
+    pub frees: [u32; SIZE_CLASSES],
+}
+
+impl HeapStats {
+    /// Share of the free memory outside the largest free block, in percent
+    pub fn fragmentation_percent(&self) -> usize {
+        (self.free - self.largest_free.min(self.free)) * 100 / self.free.max(1)
+    }
 }
 
 pub fn stats() -> HeapStats {
-    let used = unsafe { *ALLOCATOR.next.get() } - HEAP_START;
+    let (free, largest_free) = ALLOCATOR.with_heap(|heap| (heap.free, heap.largest_free()));
+    let counters = unsafe { &*ptr::addr_of!(COUNTERS) };
     HeapStats {
-        used,
-        free: HEAP_SIZE - used,
+        used: HEAP_SIZE - free,
+        free,
+        largest_free,
+        live: counters.live,
+        peak_live: counters.peak_live,
+        padding: counters.padding,
+        failed: counters.failed,
+        allocs: counters.allocs,
+        frees: counters.frees,
+    }
+}
+
+/// State of one heap chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunk {
+    /// Never handed out
+    Unused,
+    /// Handed out at some point, with this many live bytes now
+    Used(u32),
+}
+
+/// Address of the first chunk
+pub const fn start() -> usize {
+    HEAP_START
+}
+
+/// Visit every chunk in address order
+pub fn map(mut f: impl FnMut(Chunk)) {
+    let top = ALLOCATOR.with_heap(|heap| heap.top);
+    let chunks = unsafe { (*ptr::addr_of!(COUNTERS)).chunks };
+    for (i, &live) in chunks.iter().enumerate() {
+        let base = HEAP_START + i * CHUNK_SIZE;
+        f(if base < top { Chunk::Used(live) } else { Chunk::Unused });
     }
 }
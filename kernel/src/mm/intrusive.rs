@@ -89,6 +89,8 @@ where
     tail: Option<NonNull<IntrusiveNode>>,
     len: usize,
     node_offset: N,
+    /// Byte offset of the node within `T` (learned on first insert)
+    node_delta: usize,
     _marker: PhantomData<T>,
 }
 
@@ -105,6 +107,7 @@ where
             tail: None,
             len: 0,
             node_offset,
+            node_delta: 0,
             _marker: PhantomData,
         }
     }
@@ -128,8 +131,12 @@ where
     pub unsafe fn push_front(&mut self, item: &T) {
         let node = (self.node_offset)(item);
         let node_ptr = NonNull::new_unchecked(node as *const _ as *mut IntrusiveNode);
+        self.node_delta = node as *const _ as usize - item as *const T as usize;
         
-        debug_assert!(!node.is_linked(), "Node already linked");
+        // Relinking would corrupt both lists; leave it where it is
+        if !crate::debug_assert_kernel!(!node.is_linked(), "Node already linked") {
+            return;
+        }
         
         // Get mutable access to the node
         let node_mut = node_ptr.as_ptr();
@@ -156,8 +163,12 @@ where
     pub unsafe fn push_back(&mut self, item: &T) {
         let node = (self.node_offset)(item);
         let node_ptr = NonNull::new_unchecked(node as *const _ as *mut IntrusiveNode);
+        self.node_delta = node as *const _ as usize - item as *const T as usize;
         
-        debug_assert!(!node.is_linked(), "Node already linked");
+        // Relinking would corrupt both lists; leave it where it is
+        if !crate::debug_assert_kernel!(!node.is_linked(), "Node already linked") {
+            return;
+        }
         
         let node_mut = node_ptr.as_ptr();
         
@@ -261,14 +272,26 @@ where
         self.tail.map(|t| unsafe { self.node_to_container(t) })
     }
     
+    /// Visit every element from front to back
+    ///
+    /// # Safety
+    ///
+    /// The list must not be modified while iterating.
+    pub unsafe fn for_each<F: FnMut(NonNull<T>)>(&self, mut f: F) {
+        let mut cursor = self.head;
+        while let Some(node) = cursor {
+            cursor = (*node.as_ptr()).next;
+            f(self.node_to_container(node));
+        }
+    }
+    
     /// Convert a node pointer back to its container
     ///
-    /// This requires knowing the offset of the node within the container,
-    /// which we compute by using the node_offset function on a reference.
+    /// The node's offset within the container is recorded on insert, so
+    /// containers with several nodes (run queue + wait queue) resolve
+    /// correctly through any of them.
     unsafe fn node_to_container(&self, node: NonNull<IntrusiveNode>) -> NonNull<T> {
-        // This is a simplified version - in production you'd use offset_of!
-        // For now, we assume the node is at the start of T (offset 0)
-        NonNull::new_unchecked(node.as_ptr() as *mut T)
+        NonNull::new_unchecked((node.as_ptr() as *mut u8).sub(self.node_delta) as *mut T)
     }
 }
 
@@ -334,6 +357,15 @@ where
     pub unsafe fn pop(&mut self) -> Option<NonNull<T>> {
         self.list.pop_front()
     }
+    
+    /// Remove an item from anywhere in the stack
+    ///
+    /// # Safety
+    ///
+    /// `item` must be on this stack.
+    pub unsafe fn remove(&mut self, item: &T) {
+        self.list.remove(item)
+    }
 }
 
 // FIFO queue using intrusive list (for run queues)
@@ -380,4 +412,22 @@ where
     pub fn peek(&self) -> Option<NonNull<T>> {
         self.list.front()
     }
+    
+    /// Remove an item from anywhere in the queue
+    ///
+    /// # Safety
+    ///
+    /// `item` must be in this queue.
+    pub unsafe fn remove(&mut self, item: &T) {
+        self.list.remove(item)
+    }
+    
+    /// Visit every queued item from front to back
+    ///
+    /// # Safety
+    ///
+    /// The queue must not be modified while iterating.
+    pub unsafe fn for_each<F: FnMut(NonNull<T>)>(&self, f: F) {
+        self.list.for_each(f)
+    }
 }
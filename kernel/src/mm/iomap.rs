@@ -0,0 +1,127 @@
+//! Device Memory Mapping
+//!
+//! Drivers reach MMIO registers and video memory through these mappings
+//! instead of dereferencing physical addresses, which only works while
+//! the device happens to sit in the identity map (and then cached, which
+//! is wrong for registers). Mappings are made with 4KB pages in the
+//! kernel's device window (`paging::IOMAP_BASE`), which every address
+//! space shares:
+//!
+//! - `iomap`: uncached (PCD + PWT), for registers
+//! - `iomap_framebuffer`: write-combining through PAT where the CPU has it
+//!   (see `arch::x86::mtrr`), otherwise uncached-minus (PCD only), which a
+//!   WC MTRR upgrades
+//!
+//! Drivers map once at init and keep their mappings until reboot; code
+//! that only reads something briefly (ACPI tables) gives it back with
+//! `iounmap`. Asking again for a range that is already mapped the same
+//! way returns the existing mapping, so the GPU driver and the
+//! framebuffer share one; a shared mapping stays until each user has
+//! unmapped it. Before paging is on, physical addresses are returned
+//! unchanged.
+
+use super::paging::{self, IOMAP_BASE, IOMAP_END, PTE_CACHE_DISABLE, PTE_WRITE_THROUGH};
+use super::pmm::PAGE_SIZE;
+
+/// A kernel virtual address
+pub type VirtAddr = u32;
+
+/// Mappings remembered for reuse
+const MAX_MAPPINGS: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Mapping {
+    phys: u32,
+    len: u32,
+    virt: VirtAddr,
+    flags: u32,
+    /// `iomap` calls that returned this mapping and haven't unmapped it
+    users: u32,
+}
+
+static mut MAPPINGS: [Option<Mapping>; MAX_MAPPINGS] = [None; MAX_MAPPINGS];
+
+/// Next free address in the window
+static mut NEXT: VirtAddr = IOMAP_BASE;
+
+/// Map device registers uncached
+pub fn iomap(phys: u32, len: u32) -> Result<VirtAddr, &'static str> {
+    map(phys, len, PTE_CACHE_DISABLE | PTE_WRITE_THROUGH)
+}
+
+/// Map video memory write-combining (PAT) or uncached-minus (MTRR)
+pub fn iomap_framebuffer(phys: u32, len: u32) -> Result<VirtAddr, &'static str> {
+    if crate::arch::x86::mtrr::pat_write_combining() {
+        map(phys, len, PTE_WRITE_THROUGH)
+    } else {
+        map(phys, len, PTE_CACHE_DISABLE)
+    }
+}
+
+/// Give back a mapping from `iomap` (`virt` is any address in it)
+///
+/// The pages are unmapped once the last user gives it back; addresses
+/// not in a mapping (as returned before paging was on) are ignored.
+pub fn iounmap(virt: VirtAddr) {
+    crate::arch::x86::idt::without_interrupts(|| unsafe {
+        let mappings = &mut *core::ptr::addr_of_mut!(MAPPINGS);
+        let Some(slot) = mappings.iter_mut()
+            .find(|m| matches!(m, Some(m) if m.virt <= virt && virt - m.virt < m.len)) else {
+            return;
+        };
+        let Some(mut m) = *slot else {
+            return;
+        };
+        m.users -= 1;
+        if m.users > 0 {
+            *slot = Some(m);
+            return;
+        }
+        *slot = None;
+        for offset in (0..m.len).step_by(PAGE_SIZE) {
+            paging::unmap_device_page(m.virt + offset);
+        }
+        // The window is handed out in order, so only the last mapping's
+        // addresses can be reused
+        if m.virt + m.len == NEXT {
+            NEXT = m.virt;
+        }
+    })
+}
+
+fn map(phys: u32, len: u32, flags: u32) -> Result<VirtAddr, &'static str> {
+    if len == 0 {
+        return Err("Empty device mapping");
+    }
+    if !paging::is_enabled() {
+        return Ok(phys);
+    }
+
+    let page = PAGE_SIZE as u32;
+    let start = phys & !(page - 1);
+    let end = phys.checked_add(len).and_then(|e| e.checked_next_multiple_of(page))
+        .ok_or("Device range wraps")?;
+
+    crate::arch::x86::idt::without_interrupts(|| unsafe {
+        let mappings = &mut *core::ptr::addr_of_mut!(MAPPINGS);
+        let existing = mappings.iter_mut().flatten()
+            .find(|m| m.flags == flags && m.phys <= start && end - m.phys <= m.len);
+        if let Some(m) = existing {
+            m.users += 1;
+            return Ok(m.virt + (phys - m.phys));
+        }
+
+        let slot = mappings.iter_mut().find(|m| m.is_none()).ok_or("Too many device mappings")?;
+        let size = end - start;
+        if IOMAP_END - NEXT < size {
+            return Err("Device window full");
+        }
+        let virt = NEXT;
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            paging::map_device_page(virt + offset, start + offset, flags)?;
+        }
+        NEXT += size;
+        *slot = Some(Mapping { phys: start, len: size, virt, flags, users: 1 });
+        Ok(virt + (phys - start))
+    })
+}
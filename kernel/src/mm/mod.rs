@@ -5,9 +5,16 @@
 
 pub mod intrusive;
 pub mod pmm;
+pub mod paging;
+pub mod vma;
+pub mod oom;
+pub mod shm;
+pub mod iomap;
 
 pub mod heap;
 
+pub use iomap::{iomap, iomap_framebuffer, VirtAddr};
+
 use crate::boot_info::{E820Map, E820Type};
 
 /// Memory information returned by init
@@ -19,10 +26,12 @@ pub struct MemoryInfo {
     pub usable_kb: u64,
     /// Number of E820 entries
     pub e820_entries: usize,
+    /// Paging came up
+    pub paging: bool,
 }
 
 /// Initialize memory management
-pub fn init(e820_map_addr: u32) -> MemoryInfo {
+pub fn init(e820_map_addr: crate::arch::PhysAddr) -> MemoryInfo {
     // Parse E820 memory map
     let e820_map = unsafe { E820Map::from_addr(e820_map_addr) };
     
@@ -43,9 +52,13 @@ pub fn init(e820_map_addr: u32) -> MemoryInfo {
     // Initialize physical memory manager
     pmm::init(&e820_map);
     
+    // Switch on paging (identity mapped, so nothing moves)
+    let paging = paging::init().is_ok();
+    
     MemoryInfo {
         total_kb: total_memory / 1024,
         usable_kb: usable_memory / 1024,
         e820_entries: e820_map.len(),
+        paging,
     }
 }
@@ -0,0 +1,61 @@
+//! Out-of-Memory Policy
+//!
+//! When physical pages or the kernel heap run out, the largest
+//! non-essential user program is killed to get memory back. Size is the
+//! number of user pages mapped in a program's address space.
+//!
+//! An allocator that comes up empty can be anywhere in the kernel, in the
+//! middle of changing a filesystem, the shm or ipc tables or the window
+//! server, so it doesn't tear the program down itself (that closes its
+//! files and releases its shared memory, ports and pages): it only marks
+//! the program killed, and its own allocation fails. The main loop
+//! finishes the program from `poll`, where nothing is half changed.
+//!
+//! The heap also keeps a reserve, and the first allocation that dips into
+//! it flags pressure, acted on from the main loop the same way, so a
+//! program is usually killed before the heap actually runs out.
+
+use crate::sched::Pid;
+
+/// Allocation failed for lack of memory
+pub const ENOMEM: &str = "out of memory";
+
+/// Heap reserve entered, nothing killed for it yet
+static mut HEAP_PRESSURE: bool = false;
+
+/// Program marked killed and not yet torn down by `poll`
+static mut VICTIM: Option<Pid> = None;
+
+/// An allocation found no memory: mark a program to be killed
+///
+/// Safe to call from any allocator, as it changes nothing but the
+/// victim's exit status. Only one program is marked until `poll` has
+/// freed it, so a burst of failures doesn't kill several.
+pub fn reclaim() {
+    unsafe {
+        let victim = &mut *core::ptr::addr_of_mut!(VICTIM);
+        if victim.is_none() {
+            *victim = crate::exec::oom_kill();
+        }
+    }
+}
+
+/// The heap has started using its reserve
+pub fn heap_pressure() {
+    unsafe { HEAP_PRESSURE = true };
+}
+
+/// Act on heap pressure and tear down a marked program (called from the
+/// main loop, with no user program running)
+pub fn poll() {
+    unsafe {
+        if core::mem::take(&mut *core::ptr::addr_of_mut!(HEAP_PRESSURE)) {
+            crate::klog::write_str("[OOM] kernel heap in reserve\n");
+            reclaim();
+        }
+        if let Some(pid) = (*core::ptr::addr_of_mut!(VICTIM)).take() {
+            crate::klog::write_fmt(format_args!("[OOM] killed pid {} to free memory\n", pid));
+            crate::exec::finish_killed();
+        }
+    }
+}
@@ -0,0 +1,569 @@
+//! Paging
+//!
+//! Two-level x86 page tables. Every address space shares the kernel part:
+//! physical RAM below `KERNEL_SPACE_END` and everything from `IOMAP_END`
+//! up are identity mapped with 4MB supervisor pages, and the device window
+//! `[IOMAP_BASE, IOMAP_END)` uses 4KB page tables shared by every
+//! directory, so a device mapped once (`mm::iomap`) is visible everywhere.
+//! In between, each address space has its own page tables built from 4KB
+//! PMM frames.
+//!
+//! Page tables live in RAM the PMM hands out, which is always inside the
+//! identity map, so the kernel edits any address space without switching
+//! to it.
+//!
+//! Fork is copy-on-write: the child gets new page tables pointing at the
+//! parent's frames, and every writable page in both becomes read-only with
+//! `PTE_COW` set. The first write to such a page faults, and
+//! `handle_fault` gives the writer its own copy of that one page (or just
+//! makes it writable again if nobody else still shares it). CR0.WP is set
+//! so kernel writes into user pages (copy_to_user) break sharing too.
+//!
+//! The slots holding the kernel image are split into 4KB pages instead,
+//! so its code and read-only data can be mapped read-only (W^X). These
+//! tables have no no-execute bit (that needs PAE, and the CPUs this runs
+//! on have no NX either), so `enforce_wx` does that part with
+//! segmentation: the kernel code segment ends where the kernel's code
+//! does, and jumping into data, the heap or a stack raises #GP. A boot
+//! self-test checks that writing to the kernel's code faults.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use super::pmm::{self, PageKind, PAGE_SIZE};
+use crate::syscall::usercopy::USER_SPACE_END;
+
+// =============================================================================
+// Entry Flags
+// =============================================================================
+
+pub const PTE_PRESENT: u32 = 1 << 0;
+pub const PTE_WRITABLE: u32 = 1 << 1;
+pub const PTE_USER: u32 = 1 << 2;
+pub const PTE_WRITE_THROUGH: u32 = 1 << 3;
+pub const PTE_CACHE_DISABLE: u32 = 1 << 4;
+pub const PTE_ACCESSED: u32 = 1 << 5;
+pub const PTE_DIRTY: u32 = 1 << 6;
+/// 4MB page (directory entries only)
+pub const PTE_LARGE: u32 = 1 << 7;
+/// Copy-on-write (software bit, ignored by the CPU)
+pub const PTE_COW: u32 = 1 << 9;
+
+/// Shared memory page: stays shared and writable across fork (software bit)
+pub const PTE_SHARED: u32 = 1 << 10;
+
+/// Frame address part of an entry
+const FRAME_MASK: u32 = 0xFFFF_F000;
+
+/// Entries per directory or table
+const ENTRIES: usize = 1024;
+
+/// Bytes covered by one directory entry
+const PDE_SPAN: u32 = 4 * 1024 * 1024;
+
+/// End of the identity-mapped RAM (matches the PMM's frame limit)
+pub const KERNEL_SPACE_END: u32 = 0x1000_0000; // 256MB
+
+/// Kernel window for device memory (see `mm::iomap`)
+pub const IOMAP_BASE: u32 = 0xB800_0000;
+pub const IOMAP_END: u32 = 0xC000_0000;
+
+// The window is whole directory slots at the top of user space
+const _: () = assert!(IOMAP_BASE % PDE_SPAN == 0 && IOMAP_END % PDE_SPAN == 0);
+
+/// Page-fault error code bits
+const FAULT_PRESENT: u32 = 1 << 0;
+const FAULT_WRITE: u32 = 1 << 1;
+
+// CR0/CR4 bits
+const CR0_WP: u32 = 1 << 16;
+const CR0_PG: u32 = 1 << 31;
+const CR4_PSE: u32 = 1 << 4;
+
+extern "C" {
+    /// Kernel image bounds (linker.ld)
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_end: u8;
+}
+
+/// Fault expected by `write_faults`: the address, and where to resume
+/// (0 when none is)
+static PROBE_ADDR: AtomicU32 = AtomicU32::new(0);
+static PROBE_RESUME: AtomicU32 = AtomicU32::new(0);
+
+/// Directory every address space copies its kernel entries from
+static mut KERNEL_DIRECTORY: u32 = 0;
+
+/// Copy-on-write counters
+static mut COW_STATS: CowStats = CowStats { faults: 0, copies: 0 };
+
+/// Copy-on-write statistics
+#[derive(Debug, Clone, Copy)]
+pub struct CowStats {
+    /// Write faults resolved on COW pages
+    pub faults: u32,
+    /// Faults that had to copy (the rest were the last sharer)
+    pub copies: u32,
+}
+
+/// Get copy-on-write statistics
+pub fn cow_stats() -> CowStats {
+    unsafe { COW_STATS }
+}
+
+// =============================================================================
+// Helpers
+// =============================================================================
+
+/// Is this directory slot part of the shared kernel mapping?
+fn is_kernel_slot(index: usize) -> bool {
+    let base = index as u32 * PDE_SPAN;
+    base < KERNEL_SPACE_END || base >= USER_SPACE_END
+}
+
+/// Is this directory slot part of the device window?
+fn is_iomap_slot(index: usize) -> bool {
+    let base = index as u32 * PDE_SPAN;
+    (IOMAP_BASE..IOMAP_END).contains(&base)
+}
+
+/// Identity-mapped 4MB entry for a kernel slot
+fn kernel_entry(index: usize) -> u32 {
+    (index as u32 * PDE_SPAN) | PTE_PRESENT | PTE_WRITABLE | PTE_LARGE
+}
+
+/// Page-aligned range of the kernel's code and read-only data
+fn kernel_read_only() -> (u32, u32) {
+    let start = core::ptr::addr_of!(__text_start) as u32 & FRAME_MASK;
+    let end = (core::ptr::addr_of!(__rodata_end) as u32 + PAGE_SIZE as u32 - 1) & FRAME_MASK;
+    (start, end)
+}
+
+/// Page table for a kernel slot holding part of the image: identity
+/// mapped like `kernel_entry`, with code and read-only data not writable
+fn kernel_table(index: usize) -> Result<u32, &'static str> {
+    let phys = alloc_table()?;
+    let (ro_start, ro_end) = kernel_read_only();
+    let base = index as u32 * PDE_SPAN;
+    for (i, entry) in unsafe { table(phys) }.iter_mut().enumerate() {
+        let addr = base + i as u32 * PAGE_SIZE as u32;
+        let writable = if (ro_start..ro_end).contains(&addr) { 0 } else { PTE_WRITABLE };
+        *entry = addr | PTE_PRESENT | writable;
+    }
+    Ok(phys | PTE_PRESENT | PTE_WRITABLE)
+}
+
+/// Does this kernel slot hold part of the read-only image?
+fn holds_kernel_image(index: usize) -> bool {
+    let (ro_start, ro_end) = kernel_read_only();
+    let base = index as u32 * PDE_SPAN;
+    base < ro_end && ro_start < base + PDE_SPAN
+}
+
+/// Access a directory or page table through the identity map
+unsafe fn table(phys: u32) -> &'static mut [u32; ENTRIES] {
+    &mut *(phys as *mut [u32; ENTRIES])
+}
+
+/// Allocate a zeroed frame for a directory or table
+fn alloc_table() -> Result<u32, &'static str> {
+    let phys = pmm::alloc_zeroed_page_as(PageKind::PageTable).ok_or("out of memory for page tables")?;
+    Ok(phys as u32)
+}
+
+fn read_cr3() -> u32 {
+    let cr3: u32;
+    unsafe { core::arch::asm!("mov {}, cr3", out(reg) cr3) };
+    cr3
+}
+
+unsafe fn invalidate(virt: u32) {
+    core::arch::asm!("invlpg [{}]", in(reg) virt, options(nostack));
+}
+
+/// Is paging on?
+pub fn is_enabled() -> bool {
+    unsafe { KERNEL_DIRECTORY != 0 }
+}
+
+// =============================================================================
+// Address Spaces
+// =============================================================================
+
+/// A page directory and the user mappings under it
+///
+/// This is a handle, not an owner: dropping it leaves the mappings in
+/// place. Call `destroy` to release a dead process's memory.
+pub struct AddressSpace {
+    directory: u32,
+}
+
+impl AddressSpace {
+    /// The kernel's own address space
+    pub fn kernel() -> Self {
+        Self { directory: unsafe { KERNEL_DIRECTORY } }
+    }
+
+    /// Address space for a saved CR3 value (0 means the kernel's)
+    pub fn from_directory(directory: u32) -> Self {
+        if directory == 0 {
+            Self::kernel()
+        } else {
+            Self { directory }
+        }
+    }
+
+    /// Empty user address space
+    pub fn new() -> Result<Self, &'static str> {
+        if !is_enabled() {
+            return Err("paging not enabled");
+        }
+        let directory = alloc_table()?;
+        let dir = unsafe { table(directory) };
+        let kernel = unsafe { table(KERNEL_DIRECTORY) };
+        for (i, entry) in dir.iter_mut().enumerate() {
+            if is_kernel_slot(i) {
+                *entry = kernel[i];
+            }
+        }
+        Ok(Self { directory })
+    }
+
+    /// Physical address of the page directory (the CR3 value)
+    pub fn directory(&self) -> u32 {
+        self.directory
+    }
+
+    fn is_active(&self) -> bool {
+        read_cr3() & FRAME_MASK == self.directory
+    }
+
+    /// Page table entry for a user address, if its table exists
+    fn entry(&self, virt: u32) -> Option<&'static mut u32> {
+        let slot = (virt >> 22) as usize;
+        if is_kernel_slot(slot) {
+            return None;
+        }
+        let pde = unsafe { table(self.directory)[slot] };
+        if pde & PTE_PRESENT == 0 {
+            return None;
+        }
+        Some(unsafe { &mut table(pde & FRAME_MASK)[((virt >> 12) & 0x3FF) as usize] })
+    }
+
+    /// Map one user page to a frame
+    ///
+    /// The frame reference passes to the address space; `unmap` and
+    /// `destroy` drop it.
+    pub fn map(&mut self, virt: u32, phys: u32, flags: u32) -> Result<(), &'static str> {
+        let slot = (virt >> 22) as usize;
+        if is_kernel_slot(slot) {
+            return Err("address outside user space");
+        }
+
+        let dir = unsafe { table(self.directory) };
+        if dir[slot] & PTE_PRESENT == 0 {
+            // Permissions are enforced per page; the table itself allows all
+            dir[slot] = alloc_table()? | PTE_PRESENT | PTE_WRITABLE | PTE_USER;
+        }
+
+        let entry = self.entry(virt).ok_or("page table missing")?;
+        if *entry & PTE_PRESENT != 0 {
+            return Err("page already mapped");
+        }
+        *entry = (phys & FRAME_MASK) | (flags & !FRAME_MASK) | PTE_PRESENT;
+        if self.is_active() {
+            unsafe { invalidate(virt) };
+        }
+        Ok(())
+    }
+
+    /// Unmap one user page, dropping its frame reference
+    ///
+    /// Returns the frame that was mapped there.
+    pub fn unmap(&mut self, virt: u32) -> Option<u32> {
+        let entry = self.entry(virt)?;
+        if *entry & PTE_PRESENT == 0 {
+            return None;
+        }
+        let phys = *entry & FRAME_MASK;
+        *entry = 0;
+        if self.is_active() {
+            unsafe { invalidate(virt) };
+        }
+        unsafe { pmm::free_page(phys as usize) };
+        Some(phys)
+    }
+
+    /// Physical address a user address maps to
+    pub fn translate(&self, virt: u32) -> Option<u32> {
+        let entry = *self.entry(virt)?;
+        if entry & PTE_PRESENT == 0 {
+            return None;
+        }
+        Some((entry & FRAME_MASK) | (virt & 0xFFF))
+    }
+
+    /// Number of user pages mapped
+    pub fn user_pages(&self) -> usize {
+        let dir = unsafe { table(self.directory) };
+        (0..ENTRIES)
+            .filter(|&i| !is_kernel_slot(i) && dir[i] & PTE_PRESENT != 0)
+            .map(|i| unsafe { table(dir[i] & FRAME_MASK) }.iter().filter(|&&pte| pte & PTE_PRESENT != 0).count())
+            .sum()
+    }
+
+    /// Copy-on-write clone for fork
+    ///
+    /// Writable pages become read-only + COW in both spaces. Read-only
+    /// pages are simply shared. Shared memory pages and frames the PMM
+    /// doesn't track (device memory mapped into a process) are shared
+    /// writable, as they must be.
+    pub fn fork(&mut self) -> Result<AddressSpace, &'static str> {
+        let child = AddressSpace::new()?;
+        let parent_dir = unsafe { table(self.directory) };
+        let child_dir = unsafe { table(child.directory) };
+
+        for slot in (0..ENTRIES).filter(|&i| !is_kernel_slot(i)) {
+            let pde = parent_dir[slot];
+            if pde & PTE_PRESENT == 0 {
+                continue;
+            }
+            let child_table = match alloc_table() {
+                Ok(t) => t,
+                Err(e) => {
+                    child.destroy();
+                    return Err(e);
+                }
+            };
+            child_dir[slot] = child_table | (pde & !FRAME_MASK);
+
+            let src = unsafe { table(pde & FRAME_MASK) };
+            let dst = unsafe { table(child_table) };
+            for (parent_pte, child_pte) in src.iter_mut().zip(dst.iter_mut()) {
+                if *parent_pte & PTE_PRESENT == 0 {
+                    continue;
+                }
+                let frame = (*parent_pte & FRAME_MASK) as usize;
+                if pmm::ref_page(frame) && *parent_pte & (PTE_WRITABLE | PTE_SHARED) == PTE_WRITABLE {
+                    *parent_pte = (*parent_pte & !PTE_WRITABLE) | PTE_COW;
+                }
+                *child_pte = *parent_pte;
+            }
+        }
+
+        // Parent pages just lost write access
+        if self.is_active() {
+            unsafe { core::arch::asm!("mov cr3, {}", in(reg) self.directory) };
+        }
+        Ok(child)
+    }
+
+    /// Release every user page, the page tables and the directory
+    ///
+    /// Must not be the active address space.
+    pub fn destroy(self) {
+        if self.directory == unsafe { KERNEL_DIRECTORY } || self.is_active() {
+            return;
+        }
+        let dir = unsafe { table(self.directory) };
+        for slot in (0..ENTRIES).filter(|&i| !is_kernel_slot(i)) {
+            let pde = dir[slot];
+            if pde & PTE_PRESENT == 0 {
+                continue;
+            }
+            for &pte in unsafe { table(pde & FRAME_MASK) }.iter() {
+                if pte & PTE_PRESENT != 0 {
+                    unsafe { pmm::free_page((pte & FRAME_MASK) as usize) };
+                }
+            }
+            unsafe { pmm::free_page((pde & FRAME_MASK) as usize) };
+        }
+        unsafe { pmm::free_page(self.directory as usize) };
+    }
+}
+
+// =============================================================================
+// Public API
+// =============================================================================
+
+/// Build the kernel directory and turn paging on
+pub fn init() -> Result<(), &'static str> {
+    let directory = alloc_table()?;
+    let dir = unsafe { table(directory) };
+    for (i, entry) in dir.iter_mut().enumerate() {
+        if is_iomap_slot(i) {
+            *entry = alloc_table()? | PTE_PRESENT | PTE_WRITABLE;
+        } else if holds_kernel_image(i) {
+            *entry = kernel_table(i)?;
+        } else if is_kernel_slot(i) {
+            *entry = kernel_entry(i);
+        }
+    }
+
+    unsafe {
+        core::arch::asm!(
+            "mov {tmp}, cr4",
+            "or {tmp}, {pse}",
+            "mov cr4, {tmp}",
+            "mov cr3, {dir}",
+            "mov {tmp}, cr0",
+            "or {tmp}, {on}",
+            "mov cr0, {tmp}",
+            tmp = out(reg) _,
+            pse = const CR4_PSE,
+            dir = in(reg) directory,
+            on = const CR0_PG | CR0_WP,
+        );
+        KERNEL_DIRECTORY = directory;
+    }
+    Ok(())
+}
+
+/// Finish W^X for the kernel (after `init`, with the IDT up): end the
+/// kernel code segment at the end of its code, then check that a write
+/// to the code faults
+pub fn enforce_wx() -> Result<(), &'static str> {
+    if !is_enabled() {
+        return Err("paging not enabled");
+    }
+    let text_end = (core::ptr::addr_of!(__text_end) as u32 + PAGE_SIZE as u32 - 1) & FRAME_MASK;
+    crate::arch::x86::gdt::limit_kernel_code(text_end);
+
+    if !write_faults(core::ptr::addr_of!(__text_start) as u32) {
+        return Err("kernel code is writable");
+    }
+    Ok(())
+}
+
+/// Does a kernel write to `addr` fault? (If not, it rewrote the byte that
+/// was there.)
+fn write_faults(addr: u32) -> bool {
+    let faulted: u32;
+    PROBE_ADDR.store(addr, Ordering::Relaxed);
+    unsafe {
+        core::arch::asm!(
+            "lea {tmp:e}, [2f]",
+            "mov dword ptr [{resume}], {tmp:e}",
+            "xor {faulted:e}, {faulted:e}",
+            "mov {tmp:l}, byte ptr [{addr}]",
+            "mov byte ptr [{addr}], {tmp:l}",
+            "jmp 3f",
+            // The page fault handler resumes here
+            "2:",
+            "mov {faulted:e}, 1",
+            "3:",
+            addr = in(reg) addr as usize,
+            resume = in(reg) PROBE_RESUME.as_ptr(),
+            tmp = out(reg_abcd) _,
+            faulted = out(reg) faulted,
+        );
+    }
+    PROBE_RESUME.store(0, Ordering::Relaxed);
+    faulted != 0
+}
+
+/// Where to resume after a fault at `addr`, if `write_faults` expects it
+pub fn probe_fault(addr: u32) -> Option<u32> {
+    let resume = PROBE_RESUME.swap(0, Ordering::Relaxed);
+    if resume != 0 && PROBE_ADDR.load(Ordering::Relaxed) == addr {
+        Some(resume)
+    } else {
+        PROBE_RESUME.store(resume, Ordering::Relaxed);
+        None
+    }
+}
+
+/// Map one page of the device window to `phys` (`flags` adds cache bits)
+pub fn map_device_page(virt: u32, phys: u32, flags: u32) -> Result<(), &'static str> {
+    if !is_enabled() {
+        return Err("paging not enabled");
+    }
+    let slot = (virt >> 22) as usize;
+    if !is_iomap_slot(slot) {
+        return Err("address outside the device window");
+    }
+    unsafe {
+        let pde = table(KERNEL_DIRECTORY)[slot];
+        let entry = &mut table(pde & FRAME_MASK)[((virt >> 12) & 0x3FF) as usize];
+        *entry = (phys & FRAME_MASK) | (flags & !FRAME_MASK) | PTE_PRESENT | PTE_WRITABLE;
+        invalidate(virt);
+    }
+    Ok(())
+}
+
+/// Remove one page of the device window mapped by `map_device_page`
+///
+/// Only this CPU's TLB is flushed, so the page must not have been used
+/// from another CPU.
+pub fn unmap_device_page(virt: u32) {
+    let slot = (virt >> 22) as usize;
+    if !is_enabled() || !is_iomap_slot(slot) {
+        return;
+    }
+    unsafe {
+        let pde = table(KERNEL_DIRECTORY)[slot];
+        table(pde & FRAME_MASK)[((virt >> 12) & 0x3FF) as usize] = 0;
+        invalidate(virt);
+    }
+}
+
+/// Switch to a task's address space (0 = kernel)
+///
+/// # Safety
+///
+/// The directory must be a live address space.
+pub unsafe fn activate(directory: u32) {
+    if !is_enabled() {
+        return;
+    }
+    let directory = if directory == 0 { KERNEL_DIRECTORY } else { directory };
+    if read_cr3() & FRAME_MASK != directory {
+        core::arch::asm!("mov cr3, {}", in(reg) directory);
+    }
+}
+
+/// Try to resolve a page fault
+///
+/// Returns true if the faulting access can be retried.
+pub fn handle_fault(addr: u32, error_code: u32) -> bool {
+    if !is_enabled() {
+        return false;
+    }
+    let write = error_code & FAULT_WRITE != 0;
+    if error_code & FAULT_PRESENT == 0 {
+        // Lazily allocated heap/stack page, or stack growth
+        return super::vma::handle_fault(addr, write);
+    }
+    if !write {
+        return false;
+    }
+    let space = AddressSpace::from_directory(read_cr3() & FRAME_MASK);
+    match space.entry(addr) {
+        Some(entry) if *entry & PTE_COW != 0 => resolve_cow(entry, addr),
+        _ => false,
+    }
+}
+
+/// Give the writer its own copy of a shared page
+fn resolve_cow(entry: &mut u32, addr: u32) -> bool {
+    let old = (*entry & FRAME_MASK) as usize;
+    let flags = (*entry & !FRAME_MASK & !PTE_COW) | PTE_WRITABLE;
+
+    unsafe {
+        COW_STATS.faults += 1;
+        if pmm::page_refs(old) <= 1 {
+            // Everyone else already copied or exited
+            *entry = old as u32 | flags;
+        } else {
+            let Some(new) = pmm::alloc_page() else {
+                return false;
+            };
+            core::ptr::copy_nonoverlapping(old as *const u8, new as *mut u8, PAGE_SIZE);
+            *entry = new as u32 | flags;
+            pmm::free_page(old);
+            COW_STATS.copies += 1;
+        }
+        invalidate(addr);
+    }
+    true
+}
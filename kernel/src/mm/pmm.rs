@@ -2,7 +2,14 @@
 //!
 //! Manages physical memory pages using a pooled intrusive free list.
 //! This is performance-critical code - no EventChains overhead here.
+//!
+//! Pages can be tagged with what owns them (`PageKind`): the tag is kept
+//! in the frame's flags, counted in the stats, and named when a page is
+//! freed twice. Runs of `1 << order` pages come from `alloc_pages`, which
+//! scans the frame table (there is no buddy allocator yet).
 
+use alloc::string::String;
+use core::fmt::Write;
 use crate::boot_info::{E820Map, E820Type};
 use crate::mm::intrusive::{IntrusiveNode, IntrusiveStack};
 use core::ptr::NonNull;
@@ -49,10 +56,26 @@ impl PageFrame {
     }
     
     /// Mark page as free
+    ///
+    /// The kind tag stays until the page is allocated again, so a double
+    /// free can say what the page was.
     pub fn free(&mut self) {
         self.flags.insert(PageFlags::FREE);
         self.ref_count = 0;
     }
+
+    /// What the page is (or was last) used for
+    pub fn kind(&self) -> PageKind {
+        PageKind::of(self.flags)
+    }
+
+    /// Replace the kind tag
+    fn set_kind(&mut self, kind: PageKind) {
+        for other in PageKind::ALL {
+            self.flags.remove(other.flag());
+        }
+        self.flags.insert(kind.flag());
+    }
 }
 
 bitflags::bitflags! {
@@ -67,9 +90,59 @@ bitflags::bitflags! {
         const KERNEL = 1 << 2;
         /// Page is in use by DMA
         const DMA = 1 << 3;
+        /// Page is a kernel stack
+        const KERNEL_STACK = 1 << 4;
+        /// Page is a page directory or table
+        const PAGE_TABLE = 1 << 5;
+    }
+}
+
+/// What an allocated page is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    /// Untagged (user memory, shared memory, buffers)
+    General = 0,
+    KernelStack = 1,
+    PageTable = 2,
+    Dma = 3,
+}
+
+impl PageKind {
+    /// Number of kinds
+    pub const COUNT: usize = 4;
+
+    pub const ALL: [PageKind; Self::COUNT] =
+        [PageKind::General, PageKind::KernelStack, PageKind::PageTable, PageKind::Dma];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PageKind::General => "general",
+            PageKind::KernelStack => "kernel-stack",
+            PageKind::PageTable => "page-table",
+            PageKind::Dma => "dma",
+        }
+    }
+
+    fn flag(self) -> PageFlags {
+        match self {
+            PageKind::General => PageFlags::empty(),
+            PageKind::KernelStack => PageFlags::KERNEL_STACK,
+            PageKind::PageTable => PageFlags::PAGE_TABLE,
+            PageKind::Dma => PageFlags::DMA,
+        }
+    }
+
+    /// The kind tagged in a frame's flags
+    fn of(flags: PageFlags) -> Self {
+        Self::ALL[1..].iter().copied()
+            .find(|kind| flags.contains(kind.flag()))
+            .unwrap_or(PageKind::General)
     }
 }
 
+/// Largest run `alloc_pages` hands out (4MB)
+pub const MAX_ORDER: u32 = 10;
+
 // We need a simple bitflags implementation since we're no_std
 mod bitflags {
     #[macro_export]
@@ -143,6 +216,8 @@ static mut STATS: PmmStats = PmmStats {
     free_pages: 0,
     reserved_pages: 0,
     kernel_pages: 0,
+    kind_pages: [0; PageKind::COUNT],
+    double_frees: 0,
 };
 
 /// PMM Statistics
@@ -152,6 +227,10 @@ pub struct PmmStats {
     pub free_pages: usize,
     pub reserved_pages: usize,
     pub kernel_pages: usize,
+    /// Allocated pages by kind (indexed by `PageKind`)
+    pub kind_pages: [usize; PageKind::COUNT],
+    /// Frees of pages that were already free
+    pub double_frees: usize,
 }
 
 /// Initialize the physical memory manager
@@ -204,6 +283,18 @@ pub fn init(e820_map: &E820Map) {
                     continue;
                 }
                 
+                // Keep the crash dump area intact across boots
+                if crate::crashdump::is_reserved_page(page_idx) {
+                    STATS.reserved_pages += 1;
+                    continue;
+                }
+                
+                // The bootloader's disk image stays where it was loaded
+                if crate::fs::loopback::is_reserved_page(page_idx) {
+                    STATS.reserved_pages += 1;
+                    continue;
+                }
+                
                 // Mark as free and add to free list
                 PAGE_FRAMES[page_idx].flags = PageFlags::FREE;
                 
@@ -221,15 +312,45 @@ pub fn init(e820_map: &E820Map) {
 
 /// Allocate a physical page
 ///
-/// Returns the physical address of the allocated page, or None if out of memory.
+/// With no free page the OOM policy marks a program to be killed from the
+/// main loop. Returns the physical address of the allocated page, or None
+/// if memory is exhausted.
 pub fn alloc_page() -> Option<usize> {
+    alloc_page_as(PageKind::General)
+}
+
+/// Allocate a page tagged with its use (contents uninitialized)
+pub fn alloc_page_as(kind: PageKind) -> Option<usize> {
+    let page = take_page(kind);
+    if page.is_none() {
+        crate::mm::oom::reclaim();
+    }
+    page
+}
+
+/// Allocate a page filled with zeroes
+pub fn alloc_zeroed_page() -> Option<usize> {
+    alloc_zeroed_page_as(PageKind::General)
+}
+
+/// Allocate a zeroed page tagged with its use
+pub fn alloc_zeroed_page_as(kind: PageKind) -> Option<usize> {
+    let phys = alloc_page_as(kind)?;
+    unsafe { core::ptr::write_bytes(phys as *mut u8, 0, PAGE_SIZE) };
+    Some(phys)
+}
+
+/// Pop a page off the free list
+fn take_page(kind: PageKind) -> Option<usize> {
     unsafe {
         let list = FREE_LIST.as_mut()?;
         let frame_ptr = list.pop()?;
         let frame = frame_ptr.as_ptr();
         
         (*frame).allocate();
+        (*frame).set_kind(kind);
         STATS.free_pages -= 1;
+        STATS.kind_pages[kind as usize] += 1;
         
         // Calculate physical address from frame index
         let frame_idx = frame_index(frame);
@@ -237,11 +358,93 @@ pub fn alloc_page() -> Option<usize> {
     }
 }
 
-/// Free a physical page
+/// Allocate `1 << order` contiguous pages, aligned to their size
+///
+/// Contents are uninitialized; release them with `free_pages`.
+pub fn alloc_pages(order: u32, kind: PageKind) -> Option<usize> {
+    if order > MAX_ORDER {
+        return None;
+    }
+    let pages = 1 << order;
+    let phys = take_run(pages, pages, kind);
+    if phys.is_none() {
+        crate::mm::oom::reclaim();
+    }
+    phys
+}
+
+/// Free a run from `alloc_pages`
+///
+/// # Safety
+///
+/// `phys_addr` and `order` must describe one `alloc_pages` run that is no
+/// longer in use.
+pub unsafe fn free_pages(phys_addr: usize, order: u32) {
+    for i in 0..1 << order {
+        free_page(phys_addr + i * PAGE_SIZE);
+    }
+}
+
+/// Allocate `pages` physically contiguous pages (for device DMA)
+///
+/// The run starts on a multiple of `align` pages. Pages are zeroed and
+/// tagged DMA; release them with `free_contiguous`. This scans the frame
+/// table, so keep it out of hot paths.
+pub fn alloc_contiguous(pages: usize, align: usize) -> Option<usize> {
+    let phys = take_run(pages, align, PageKind::Dma)?;
+    unsafe { core::ptr::write_bytes(phys as *mut u8, 0, pages * PAGE_SIZE) };
+    Some(phys)
+}
+
+/// Take the first free run of `pages` pages starting on a multiple of
+/// `align` pages off the free list
+fn take_run(pages: usize, align: usize, kind: PageKind) -> Option<usize> {
+    if pages == 0 {
+        return None;
+    }
+    let align = align.max(1);
+    unsafe {
+        let list = FREE_LIST.as_mut()?;
+        let mut start = 0;
+        while start + pages <= MAX_PAGE_FRAMES {
+            match (start..start + pages).find(|&i| !PAGE_FRAMES[i].is_free()) {
+                // Skip past the page that broke the run
+                Some(used) => start = (used + 1).next_multiple_of(align),
+                None => {
+                    for i in start..start + pages {
+                        let frame = &mut PAGE_FRAMES[i];
+                        list.remove(frame);
+                        frame.allocate();
+                        frame.set_kind(kind);
+                    }
+                    STATS.free_pages -= pages;
+                    STATS.kind_pages[kind as usize] += pages;
+                    return Some(start * PAGE_SIZE);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Free a run from `alloc_contiguous`
 ///
 /// # Safety
 ///
-/// The address must have been allocated by alloc_page() and not already freed.
+/// `phys_addr` and `pages` must describe one `alloc_contiguous` run that
+/// no device is still accessing.
+pub unsafe fn free_contiguous(phys_addr: usize, pages: usize) {
+    for i in 0..pages {
+        free_page(phys_addr + i * PAGE_SIZE);
+    }
+}
+
+/// Drop a reference to a physical page, freeing it with the last one
+///
+/// # Safety
+///
+/// The address must have been allocated by alloc_page() and the caller
+/// must own one of its references.
 pub unsafe fn free_page(phys_addr: usize) {
     let page_idx = phys_addr / PAGE_SIZE;
     
@@ -252,11 +455,23 @@ pub unsafe fn free_page(phys_addr: usize) {
     let frame = &mut PAGE_FRAMES[page_idx];
     
     if frame.is_free() {
-        // Double free - panic or log
+        STATS.double_frees += 1;
+        crate::klog::write_fmt(format_args!("[PMM] double free of {} page {:08X}\n",
+            frame.kind().name(), phys_addr));
+        return;
+    }
+    if frame.ref_count == 0 {
+        // A reserved/kernel page that was never allocated
+        return;
+    }
+    
+    if frame.ref_count > 1 {
+        frame.ref_count -= 1;
         return;
     }
     
     frame.free();
+    STATS.kind_pages[frame.kind() as usize] -= 1;
     
     if let Some(ref mut list) = FREE_LIST {
         list.push(frame);
@@ -265,6 +480,33 @@ pub unsafe fn free_page(phys_addr: usize) {
     STATS.free_pages += 1;
 }
 
+/// Take another reference to an allocated page (for shared mappings)
+///
+/// Returns false if the page is not allocated or the count would overflow.
+pub fn ref_page(phys_addr: usize) -> bool {
+    let page_idx = phys_addr / PAGE_SIZE;
+    if page_idx >= MAX_PAGE_FRAMES {
+        return false;
+    }
+    unsafe {
+        let frame = &mut PAGE_FRAMES[page_idx];
+        if frame.is_free() || frame.ref_count == 0 || frame.ref_count == u16::MAX {
+            return false;
+        }
+        frame.ref_count += 1;
+    }
+    true
+}
+
+/// Number of references to a page (0 if free or not tracked)
+pub fn page_refs(phys_addr: usize) -> u16 {
+    let page_idx = phys_addr / PAGE_SIZE;
+    if page_idx >= MAX_PAGE_FRAMES {
+        return 0;
+    }
+    unsafe { PAGE_FRAMES[page_idx].ref_count }
+}
+
 /// Get the frame index from a frame pointer
 fn frame_index(frame: *const PageFrame) -> usize {
     unsafe {
@@ -278,6 +520,19 @@ pub fn stats() -> PmmStats {
     unsafe { STATS }
 }
 
+/// Page counts for /proc/meminfo
+pub fn report(out: &mut String) {
+    let stats = stats();
+    let _ = writeln!(out, "{:<13} {} pages ({} KB)", "total", stats.total_pages, stats.total_pages * PAGE_SIZE / 1024);
+    let _ = writeln!(out, "{:<13} {} pages ({} KB)", "free", stats.free_pages, stats.free_pages * PAGE_SIZE / 1024);
+    let _ = writeln!(out, "{:<13} {} pages", "kernel", stats.kernel_pages);
+    let _ = writeln!(out, "{:<13} {} pages", "reserved", stats.reserved_pages);
+    for kind in PageKind::ALL {
+        let _ = writeln!(out, "{:<13} {} pages", kind.name(), stats.kind_pages[kind as usize]);
+    }
+    let _ = writeln!(out, "{:<13} {}", "double frees", stats.double_frees);
+}
+
 /// Get free page count
 pub fn free_page_count() -> usize {
     unsafe { STATS.free_pages }
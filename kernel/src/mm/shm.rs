@@ -0,0 +1,180 @@
+//! Shared Memory Objects
+//!
+//! A shared memory object is a set of physical frames, allocated and
+//! zeroed up front, that can be mapped into a program's address space
+//! and handed to the window server as a window's pixel buffer. The
+//! desktop then reads the frames in place instead of having the program
+//! copy a full frame through a syscall.
+//!
+//! The object holds one reference to each frame; every mapping and every
+//! window using it takes its own. Destroying the object (or the exit of
+//! the program that created it) only drops the object's references, so
+//! existing mappings stay valid until they are unmapped.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use super::paging::{AddressSpace, PTE_SHARED, PTE_USER, PTE_WRITABLE};
+use super::pmm::{self, PAGE_SIZE};
+use super::vma::VmaKind;
+use crate::sched::Pid;
+
+/// Most objects at once (system wide)
+pub const MAX_OBJECTS: usize = 16;
+
+/// Largest object in bytes (a 1024x1024 window at 32bpp)
+pub const MAX_SIZE: u32 = 4 * 1024 * 1024;
+
+const PAGE: u32 = PAGE_SIZE as u32;
+
+struct ShmObject {
+    id: u32,
+    owner: Pid,
+    frames: Vec<u32>,
+}
+
+static mut OBJECTS: [Option<ShmObject>; MAX_OBJECTS] = [const { None }; MAX_OBJECTS];
+static mut NEXT_ID: u32 = 1;
+
+fn objects() -> &'static mut [Option<ShmObject>; MAX_OBJECTS] {
+    unsafe { &mut *core::ptr::addr_of_mut!(OBJECTS) }
+}
+
+/// Object with this ID, if the caller created it
+fn owned(id: u32, caller: Pid) -> Result<&'static mut ShmObject, &'static str> {
+    let object = objects().iter_mut().flatten().find(|o| o.id == id).ok_or("no such object")?;
+    if object.owner != caller {
+        return Err("owned by another process");
+    }
+    Ok(object)
+}
+
+fn release_frames(frames: &[u32]) {
+    for &frame in frames {
+        unsafe { pmm::free_page(frame as usize) };
+    }
+}
+
+/// Create an object of at least `size` bytes for `owner`, returning its ID
+pub fn create(owner: Pid, size: u32) -> Result<u32, &'static str> {
+    if size == 0 || size > MAX_SIZE {
+        return Err("bad object size");
+    }
+    let slot = objects().iter().position(|o| o.is_none()).ok_or("too many objects")?;
+
+    let pages = size.div_ceil(PAGE) as usize;
+    let mut frames = Vec::with_capacity(pages);
+    for _ in 0..pages {
+        let Some(frame) = pmm::alloc_zeroed_page() else {
+            release_frames(&frames);
+            return Err(super::oom::ENOMEM);
+        };
+        frames.push(frame as u32);
+    }
+
+    let id = unsafe {
+        let id = NEXT_ID;
+        NEXT_ID += 1;
+        id
+    };
+    objects()[slot] = Some(ShmObject { id, owner, frames });
+    Ok(id)
+}
+
+/// Drop an object; mappings and windows using it keep their frames
+pub fn destroy(id: u32, caller: Pid) -> Result<(), &'static str> {
+    owned(id, caller)?;
+    for slot in objects().iter_mut() {
+        if slot.as_ref().is_some_and(|o| o.id == id) {
+            if let Some(object) = slot.take() {
+                release_frames(&object.frames);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Drop every object a program created (it exited)
+pub fn release_owner(owner: Pid) {
+    for slot in objects().iter_mut() {
+        if slot.as_ref().is_some_and(|o| o.owner == owner) {
+            if let Some(object) = slot.take() {
+                release_frames(&object.frames);
+            }
+        }
+    }
+}
+
+/// Size of a caller's object in bytes
+pub fn size(id: u32, caller: Pid) -> Result<u32, &'static str> {
+    Ok(owned(id, caller)?.frames.len() as u32 * PAGE)
+}
+
+/// Map a caller's object into the current task, returning the address
+///
+/// The mapping is writable and stays shared (not copy-on-write) across
+/// fork, so a forked child can render into its parent's buffer.
+pub fn map(id: u32, caller: Pid) -> Result<u32, &'static str> {
+    let frames = &owned(id, caller)?.frames;
+    let task = unsafe { &mut *crate::sched::SCHEDULER.current().ok_or("no current task")? };
+    let mut space = AddressSpace::from_directory(task.cr3);
+
+    let len = frames.len() as u32 * PAGE;
+    let start = task.vmas.find_gap(len).ok_or("no room for mapping")?;
+    task.vmas.add(start, start + len, VmaKind::Shared, true)?;
+    for (i, &frame) in frames.iter().enumerate() {
+        let virt = start + i as u32 * PAGE;
+        pmm::ref_page(frame as usize);
+        if let Err(e) = space.map(virt, frame, PTE_USER | PTE_WRITABLE | PTE_SHARED) {
+            unsafe { pmm::free_page(frame as usize) };
+            unmap_range(&mut space, start, virt);
+            task.vmas.remove(start);
+            return Err(e);
+        }
+    }
+    Ok(start)
+}
+
+/// Remove a shared mapping from the current task
+pub fn unmap(addr: u32) -> Result<(), &'static str> {
+    let task = unsafe { &mut *crate::sched::SCHEDULER.current().ok_or("no current task")? };
+    let vma = *task.vmas.find(addr).ok_or("not mapped")?;
+    if vma.kind != VmaKind::Shared || vma.start != addr {
+        return Err("not a shared mapping");
+    }
+    unmap_range(&mut AddressSpace::from_directory(task.cr3), vma.start, vma.end);
+    task.vmas.remove(vma.start);
+    Ok(())
+}
+
+fn unmap_range(space: &mut AddressSpace, start: u32, end: u32) {
+    let mut page = start;
+    while page < end {
+        space.unmap(page);
+        page += PAGE;
+    }
+}
+
+/// Take references to a caller's object frames (for a window buffer)
+///
+/// Give them back with `put_frames`.
+pub fn get_frames(id: u32, caller: Pid) -> Result<Vec<u32>, &'static str> {
+    let frames = owned(id, caller)?.frames.clone();
+    for &frame in &frames {
+        pmm::ref_page(frame as usize);
+    }
+    Ok(frames)
+}
+
+/// Drop references taken with `get_frames`
+pub fn put_frames(frames: &[u32]) {
+    release_frames(frames);
+}
+
+/// /proc/shm: objects in use
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "id    owner  pages");
+    for object in objects().iter().flatten() {
+        let _ = writeln!(out, "{:<5} {:<6} {}", object.id, object.owner, object.frames.len());
+    }
+}
@@ -0,0 +1,284 @@
+//! Virtual Memory Areas
+//!
+//! Each task describes its user address space as a short list of regions
+//! (code, data, heap, stack, shared memory). Pages inside a region are
+//! allocated lazily: the first touch faults, and `handle_fault` maps a
+//! zeroed frame with the region's permissions. Shared memory regions are
+//! mapped in full when created (see `mm::shm`) and never fault in pages.
+//!
+//! Layout, bottom to top:
+//!
+//! ```text
+//! USER_SPACE_START  code, data (from the program image)
+//!                   heap       grows up with brk/sbrk
+//!                   ...        free
+//!                   shared     shared memory, placed top down
+//!                   ...        free, at least STACK_GUARD_GAP
+//! USER_STACK_TOP    stack      grows down on faults, up to STACK_LIMIT
+//! ```
+
+use core::fmt::Write;
+use alloc::string::String;
+use super::paging::{AddressSpace, PTE_USER, PTE_WRITABLE};
+use super::pmm::{self, PAGE_SIZE};
+use crate::syscall::usercopy::{USER_SPACE_END, USER_SPACE_START};
+
+/// Maximum regions per task
+pub const MAX_VMAS: usize = 12;
+
+/// Top of the user stack (exclusive)
+pub const USER_STACK_TOP: u32 = USER_SPACE_END;
+
+/// Stack reserved when a program starts
+pub const INITIAL_STACK_SIZE: u32 = 16 * 1024;
+
+/// Furthest the stack may grow
+pub const STACK_LIMIT: u32 = 8 * 1024 * 1024;
+
+/// Unmapped space kept between the heap and the lowest possible stack
+pub const STACK_GUARD_GAP: u32 = 64 * 1024;
+
+const PAGE: u32 = PAGE_SIZE as u32;
+
+fn page_down(addr: u32) -> u32 {
+    addr & !(PAGE - 1)
+}
+
+fn page_up(addr: u32) -> Option<u32> {
+    addr.checked_add(PAGE - 1).map(page_down)
+}
+
+/// What a region holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    Code,
+    Data,
+    Heap,
+    Stack,
+    Shared,
+}
+
+impl VmaKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Code => "code",
+            Self::Data => "data",
+            Self::Heap => "heap",
+            Self::Stack => "stack",
+            Self::Shared => "shm",
+        }
+    }
+}
+
+/// A page-aligned user region `[start, end)`
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    pub start: u32,
+    pub end: u32,
+    pub kind: VmaKind,
+    pub writable: bool,
+}
+
+impl Vma {
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    /// Page table flags for pages in this region
+    fn pte_flags(&self) -> u32 {
+        if self.writable { PTE_USER | PTE_WRITABLE } else { PTE_USER }
+    }
+}
+
+/// A task's regions plus the current program break
+#[derive(Debug, Clone, Copy)]
+pub struct VmaList {
+    areas: [Option<Vma>; MAX_VMAS],
+    /// Program break (end of the heap, byte granular)
+    brk: u32,
+}
+
+impl VmaList {
+    /// No regions (kernel tasks)
+    pub const fn new() -> Self {
+        Self { areas: [None; MAX_VMAS], brk: 0 }
+    }
+
+    /// Regions in address order
+    pub fn sorted(&self) -> [Option<Vma>; MAX_VMAS] {
+        let mut sorted = self.areas;
+        sorted.sort_unstable_by_key(|a| a.map_or(u32::MAX, |a| a.start));
+        sorted
+    }
+
+    /// Region containing an address
+    pub fn find(&self, addr: u32) -> Option<&Vma> {
+        self.areas.iter().flatten().find(|a| a.contains(addr))
+    }
+
+    fn find_kind(&mut self, kind: VmaKind) -> Option<&mut Vma> {
+        self.areas.iter_mut().flatten().find(|a| a.kind == kind)
+    }
+
+    /// Add a region (rounded out to whole pages; must not overlap)
+    pub fn add(&mut self, start: u32, end: u32, kind: VmaKind, writable: bool) -> Result<(), &'static str> {
+        let start = page_down(start);
+        let end = page_up(end).ok_or("region wraps")?;
+        if start >= end || start < USER_SPACE_START || end > USER_SPACE_END {
+            return Err("region outside user space");
+        }
+        if self.areas.iter().flatten().any(|a| start < a.end && a.start < end) {
+            return Err("region overlaps");
+        }
+        let slot = self.areas.iter_mut().find(|a| a.is_none()).ok_or("too many regions")?;
+        *slot = Some(Vma { start, end, kind, writable });
+        Ok(())
+    }
+
+    /// Remove the region starting at `start`
+    pub fn remove(&mut self, start: u32) {
+        for slot in self.areas.iter_mut() {
+            if slot.is_some_and(|a| a.start == start) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Highest free page-aligned range of `len` bytes between the heap
+    /// and the stack's growth limit
+    pub fn find_gap(&self, len: u32) -> Option<u32> {
+        let len = page_up(len)?;
+        let floor = page_up(self.brk.max(USER_SPACE_START))?;
+        let mut top = USER_STACK_TOP - STACK_LIMIT - STACK_GUARD_GAP;
+        let mut areas = self.areas;
+        areas.sort_unstable_by_key(|a| core::cmp::Reverse(a.map_or(0, |a| a.end)));
+        for vma in areas.iter().flatten().filter(|a| a.kind != VmaKind::Stack) {
+            if vma.end <= top && top - vma.end >= len {
+                break;
+            }
+            top = top.min(vma.start);
+        }
+        let start = top.checked_sub(len)?;
+        (start >= floor).then_some(start)
+    }
+
+    /// Create the heap and stack for a program image ending at `image_end`
+    pub fn setup_heap_and_stack(&mut self, image_end: u32) -> Result<(), &'static str> {
+        let heap = page_up(image_end).ok_or("image too large")?;
+        self.add(USER_STACK_TOP - INITIAL_STACK_SIZE, USER_STACK_TOP, VmaKind::Stack, true)?;
+        // Empty heap: tracked by brk, given pages as it grows
+        let slot = self.areas.iter_mut().find(|a| a.is_none()).ok_or("too many regions")?;
+        *slot = Some(Vma { start: heap, end: heap, kind: VmaKind::Heap, writable: true });
+        self.brk = heap;
+        Ok(())
+    }
+
+    /// First address of the heap
+    pub fn heap_start(&self) -> Option<u32> {
+        self.areas.iter().flatten().find(|a| a.kind == VmaKind::Heap).map(|a| a.start)
+    }
+
+    /// Current program break (0 if the task has no heap)
+    pub fn brk(&self) -> u32 {
+        self.brk
+    }
+
+    /// Move the program break
+    ///
+    /// Growing only extends the heap region (pages arrive on first
+    /// touch); shrinking unmaps the pages past the new end.
+    pub fn set_brk(&mut self, space: &mut AddressSpace, new_brk: u32) -> Result<u32, &'static str> {
+        let heap = *self.find_kind(VmaKind::Heap).ok_or("no heap")?;
+        if new_brk < heap.start {
+            return Err("break below heap start");
+        }
+        let new_end = page_up(new_brk).ok_or("break out of range")?;
+        let ceiling = USER_STACK_TOP - STACK_LIMIT - STACK_GUARD_GAP;
+        if new_end > ceiling {
+            return Err("heap would collide with stack");
+        }
+        if self.areas.iter().flatten()
+            .any(|a| a.kind != VmaKind::Heap && heap.start < a.end && a.start < new_end)
+        {
+            return Err("heap would overlap a region");
+        }
+
+        let mut page = new_end;
+        while page < heap.end {
+            space.unmap(page);
+            page += PAGE;
+        }
+        if let Some(vma) = self.find_kind(VmaKind::Heap) {
+            vma.end = new_end;
+        }
+        self.brk = new_brk;
+        Ok(new_brk)
+    }
+
+    /// Extend the stack down to cover `addr`, if within the limit
+    fn grow_stack(&mut self, addr: u32) -> Option<Vma> {
+        let lowest = USER_STACK_TOP - STACK_LIMIT;
+        let below = self.areas.iter().flatten()
+            .filter(|a| a.kind != VmaKind::Stack)
+            .map(|a| a.end)
+            .max()
+            .unwrap_or(0);
+        let stack = self.find_kind(VmaKind::Stack)?;
+        if addr >= stack.start || addr < lowest || page_down(addr) < below.saturating_add(STACK_GUARD_GAP) {
+            return None;
+        }
+        stack.start = page_down(addr);
+        Some(*stack)
+    }
+
+    /// Resolve a fault on an unmapped page in this task's regions
+    fn fault(&mut self, space: &mut AddressSpace, addr: u32, write: bool) -> bool {
+        let vma = match self.find(addr) {
+            Some(vma) => *vma,
+            None => match self.grow_stack(addr) {
+                Some(vma) => vma,
+                None => return false,
+            },
+        };
+        if (write && !vma.writable) || vma.kind == VmaKind::Shared {
+            return false;
+        }
+        let Some(frame) = pmm::alloc_zeroed_page() else {
+            return false;
+        };
+        if space.map(page_down(addr), frame as u32, vma.pte_flags()).is_err() {
+            unsafe { pmm::free_page(frame) };
+            return false;
+        }
+        true
+    }
+}
+
+/// Demand-fault handler for not-present user pages of the current task
+pub fn handle_fault(addr: u32, write: bool) -> bool {
+    unsafe {
+        let Some(task) = crate::sched::SCHEDULER.current() else {
+            return false;
+        };
+        let task = &mut *task;
+        let mut space = AddressSpace::from_directory(task.cr3);
+        task.vmas.fault(&mut space, addr, write)
+    }
+}
+
+/// /proc/maps: regions of the current task
+pub fn report(out: &mut String) {
+    let Some(task) = (unsafe { crate::sched::SCHEDULER.current() }) else {
+        return;
+    };
+    let vmas = unsafe { &(*task).vmas };
+    for vma in vmas.sorted().iter().flatten() {
+        let _ = writeln!(out, "{:08x}-{:08x} r{} {}",
+            vma.start, vma.end,
+            if vma.writable { 'w' } else { '-' },
+            vma.kind.as_str());
+    }
+    if vmas.brk() != 0 {
+        let _ = writeln!(out, "brk {:08x}", vmas.brk());
+    }
+}
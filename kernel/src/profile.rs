@@ -0,0 +1,204 @@
+//! Sampling Profiler
+//!
+//! Statistical answer to "where does kernel time go": while running, every
+//! timer tick records the interrupted EIP against the task that was
+//! running (pid 0 is the kernel's own main loop). Kernel addresses are
+//! counted in `BUCKET_SIZE`-byte buckets over the kernel's text; ring 3
+//! samples are only counted per task.
+//!
+//! Buckets are reported as address ranges, named after the function the
+//! range starts in (`ksyms`); without a symbol table, `nm -n` on the
+//! kernel ELF turns them into functions. The histograms are allocated by `start` so
+//! the tick only increments counters; up to `MAX_TASKS` tasks are tracked
+//! and samples from others are counted as dropped.
+//!
+//! Controlled with the `prof` command; the report is /proc/profile.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crate::arch::x86::idt::{without_interrupts, InterruptFrame};
+use crate::sched::Pid;
+
+/// Bytes of kernel text per histogram bucket
+pub const BUCKET_SIZE: u32 = 256;
+
+/// Tasks profiled at once
+const MAX_TASKS: usize = 8;
+
+/// Buckets listed per task in the report
+const TOP_BUCKETS: usize = 12;
+
+extern "C" {
+    /// Kernel text bounds (linker.ld)
+    static __text_start: u8;
+    static __text_end: u8;
+}
+
+fn text_range() -> (u32, u32) {
+    (core::ptr::addr_of!(__text_start) as u32, core::ptr::addr_of!(__text_end) as u32)
+}
+
+/// One task's samples
+struct TaskProfile {
+    /// None while the slot is unused
+    pid: Option<Pid>,
+    name: [u8; 16],
+    /// Every sample taken while the task ran
+    samples: u32,
+    /// Samples in ring 3
+    user: u32,
+    /// Kernel samples by text bucket
+    buckets: Vec<u32>,
+}
+
+impl TaskProfile {
+    fn name(&self) -> &str {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+}
+
+/// Histograms (allocated while there is a profile to keep)
+static mut PROFILES: Vec<TaskProfile> = Vec::new();
+
+/// Sampling on every tick
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Samples lost because every task slot was taken
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Is the profiler sampling?
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// =============================================================================
+// Control
+// =============================================================================
+
+/// Start sampling (keeps samples from an earlier run)
+pub fn start() -> Result<(), &'static str> {
+    let (start, end) = text_range();
+    let buckets = (end.saturating_sub(start)).div_ceil(BUCKET_SIZE) as usize;
+    if buckets == 0 {
+        return Err("No kernel text bounds");
+    }
+
+    without_interrupts(|| unsafe {
+        let profiles = &mut *core::ptr::addr_of_mut!(PROFILES);
+        if profiles.is_empty() {
+            let mut fresh = Vec::new();
+            fresh.try_reserve_exact(MAX_TASKS).map_err(|_| "Out of memory")?;
+            for _ in 0..MAX_TASKS {
+                fresh.push(TaskProfile { pid: None, name: [0; 16], samples: 0, user: 0, buckets: vec![0; buckets] });
+            }
+            *profiles = fresh;
+        }
+        ENABLED.store(true, Ordering::Relaxed);
+        Ok(())
+    })
+}
+
+/// Stop sampling (the samples stay for the report)
+pub fn stop() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Stop and free the histograms
+pub fn clear() {
+    let old = without_interrupts(|| unsafe {
+        ENABLED.store(false, Ordering::Relaxed);
+        DROPPED.store(0, Ordering::Relaxed);
+        core::mem::take(&mut *core::ptr::addr_of_mut!(PROFILES))
+    });
+    drop(old);
+}
+
+// =============================================================================
+// Sampling
+// =============================================================================
+
+/// Timer tick: attribute the interrupted EIP to the running task
+pub fn sample(frame: &InterruptFrame) {
+    if !is_enabled() {
+        return;
+    }
+    let (pid, name) = match unsafe { crate::sched::SCHEDULER.current() } {
+        Some(task) => unsafe { ((*task).pid, (*task).name) },
+        None => (0, *b"kernel\0\0\0\0\0\0\0\0\0\0"),
+    };
+
+    let profiles = unsafe { &mut *core::ptr::addr_of_mut!(PROFILES) };
+    let slot = match profiles.iter().position(|p| p.pid == Some(pid)) {
+        Some(slot) => slot,
+        None => match profiles.iter().position(|p| p.pid.is_none()) {
+            Some(slot) => {
+                profiles[slot].pid = Some(pid);
+                profiles[slot].name = name;
+                slot
+            }
+            None => {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        },
+    };
+
+    let profile = &mut profiles[slot];
+    profile.samples += 1;
+    if frame.cs & 3 == 3 {
+        profile.user += 1;
+        return;
+    }
+    let (start, _) = text_range();
+    let bucket = (frame.eip.wrapping_sub(start) / BUCKET_SIZE) as usize;
+    if let Some(count) = profile.buckets.get_mut(bucket) {
+        *count += 1;
+    }
+}
+
+// =============================================================================
+// Reporting
+// =============================================================================
+
+/// Busiest buckets of every profiled task (/proc/profile)
+pub fn report(out: &mut String) {
+    let (start, _) = text_range();
+    let _ = writeln!(out, "# profiling {}, {} byte buckets, {} samples dropped",
+        if is_enabled() { "on" } else { "off" }, BUCKET_SIZE, DROPPED.load(Ordering::Relaxed));
+
+    // Top buckets found with interrupts off; formatting happens after
+    let mut tasks: Vec<(Pid, String, u32, u32, Vec<(usize, u32)>)> = Vec::new();
+    without_interrupts(|| unsafe {
+        for profile in (*core::ptr::addr_of!(PROFILES)).iter() {
+            let Some(pid) = profile.pid else { continue };
+            let mut top: Vec<(usize, u32)> = Vec::new();
+            for (bucket, &count) in profile.buckets.iter().enumerate().filter(|(_, &c)| c != 0) {
+                let at = top.iter().position(|&(_, c)| c < count).unwrap_or(top.len());
+                if at < TOP_BUCKETS {
+                    top.insert(at, (bucket, count));
+                    top.truncate(TOP_BUCKETS);
+                }
+            }
+            tasks.push((pid, String::from(profile.name()), profile.samples, profile.user, top));
+        }
+    });
+    tasks.sort_by(|a, b| b.2.cmp(&a.2));
+
+    for (pid, name, samples, user, top) in &tasks {
+        let _ = writeln!(out, "pid {} {}: {} samples, {} user, {} kernel",
+            pid, name, samples, user, samples - user);
+        for &(bucket, count) in top {
+            let addr = start + bucket as u32 * BUCKET_SIZE;
+            let _ = write!(out, "  {:08X}-{:08X} {:>6} {:>3}%",
+                addr, addr + BUCKET_SIZE - 1, count, count as u64 * 100 / (*samples).max(1) as u64);
+            match crate::ksyms::symbolize(addr) {
+                Some(symbol) => { let _ = writeln!(out, "  {}", symbol.name()); }
+                None => { let _ = writeln!(out); }
+            }
+        }
+    }
+}
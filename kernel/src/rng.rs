@@ -0,0 +1,248 @@
+//! Random Numbers
+//!
+//! The Armada's CPU has no RDRAND, so randomness is gathered from timing:
+//!
+//! - the TSC at boot, with the RTC time and the PIT phase,
+//! - timer jitter: how many TSC cycles each PIT tick took,
+//! - input: when each keyboard scancode and mouse byte arrived, and its value.
+//!
+//! Samples are stirred into a 512-bit pool. Output comes from ChaCha20
+//! keyed from the pool; the key is folded together with the pool again
+//! once enough new samples have arrived, and replaced with fresh generator
+//! output after every request so earlier output can't be recovered from
+//! a later key. The entropy count is an estimate for /proc/random only:
+//! `rand_bytes` never blocks, since the boot seed is all most callers get
+//! before they need an ID.
+//!
+//! Kernel code calls `rand_bytes` or `rand_u32`; programs use the
+//! GetRandom syscall.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crate::arch::x86::{cpu, pit, tsc};
+use crate::sched::spinlock::RawSpinLock;
+
+/// Pool size in 32-bit words
+const POOL_WORDS: usize = 16;
+
+/// Most entropy the estimate goes up to (the pool size)
+const MAX_ENTROPY_BITS: u32 = POOL_WORDS as u32 * 32;
+
+/// Samples mixed in before the generator key takes the pool in again
+const RESEED_SAMPLES: u32 = 64;
+
+/// Where a sample came from
+#[derive(Clone, Copy)]
+enum Source {
+    Boot = 0,
+    Timer = 1,
+    Input = 2,
+}
+
+const SOURCE_NAMES: [&str; 3] = ["boot", "timer", "input"];
+
+/// Pool, generator and their bookkeeping
+struct Rng {
+    pool: [u32; POOL_WORDS],
+    /// Next pool word to stir into
+    pos: usize,
+    key: [u32; 8],
+    /// Samples since the key last took in the pool
+    fresh: u32,
+    /// Last timer sample and the cycles the tick before it took
+    last_tick: u32,
+    last_delta: u32,
+}
+
+static mut RNG: Rng = Rng {
+    pool: [0; POOL_WORDS],
+    pos: 0,
+    key: [0; 8],
+    fresh: 0,
+    last_tick: 0,
+    last_delta: 0,
+};
+
+static LOCK: RawSpinLock = RawSpinLock::new();
+
+/// The TSC can be read (otherwise the PIT phase stands in)
+static HAVE_TSC: AtomicBool = AtomicBool::new(false);
+
+/// Estimated entropy in the pool, in bits
+static ENTROPY_BITS: AtomicU32 = AtomicU32::new(0);
+
+/// Samples taken, per source
+static SAMPLES: [AtomicU32; 3] = [const { AtomicU32::new(0) }; 3];
+
+/// Bytes handed out
+static BYTES_OUT: AtomicU32 = AtomicU32::new(0);
+
+/// Fast-moving counter for timing samples
+fn cycles() -> u32 {
+    if HAVE_TSC.load(Ordering::Relaxed) {
+        tsc::read() as u32
+    } else {
+        pit::uptime_us() as u32
+    }
+}
+
+/// Run with the generator locked (interrupts off)
+fn with_rng<R>(f: impl FnOnce(&mut Rng) -> R) -> R {
+    LOCK.with(|| f(unsafe { &mut *core::ptr::addr_of_mut!(RNG) }))
+}
+
+impl Rng {
+    /// Stir one word into the pool
+    fn mix(&mut self, value: u32) {
+        let i = self.pos;
+        let word = value.rotate_left((i as u32 * 7) & 31) ^ self.pool[(i + 1) % POOL_WORDS];
+        self.pool[i] = self.pool[i].wrapping_add(word).rotate_left(11) ^ self.pool[(i + 9) % POOL_WORDS];
+        self.pos = (i + 1) % POOL_WORDS;
+        self.fresh += 1;
+    }
+
+    /// Fold the pool into the key
+    fn reseed(&mut self) {
+        for (i, word) in self.key.iter_mut().enumerate() {
+            *word ^= self.pool[i] ^ self.pool[i + 8].rotate_left(16);
+        }
+        let mut block = [0u32; 16];
+        chacha20_block(&self.key, u64::MAX, &mut block);
+        self.key.copy_from_slice(&block[..8]);
+        self.fresh = 0;
+    }
+
+    /// Fill `buf`, then move to a new key
+    fn fill(&mut self, buf: &mut [u8]) {
+        if self.fresh >= RESEED_SAMPLES {
+            self.reseed();
+        }
+        let mut block = [0u32; 16];
+        let mut counter = 0u64;
+        for chunk in buf.chunks_mut(64) {
+            chacha20_block(&self.key, counter, &mut block);
+            counter += 1;
+            for (bytes, word) in chunk.chunks_mut(4).zip(block.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+            }
+        }
+        chacha20_block(&self.key, counter, &mut block);
+        self.key.copy_from_slice(&block[..8]);
+    }
+}
+
+/// Seed the pool (after `tsc::init` and `time::init`)
+pub fn init() {
+    HAVE_TSC.store(cpu::has(cpu::features::TSC), Ordering::Relaxed);
+    let tsc = if HAVE_TSC.load(Ordering::Relaxed) { tsc::read() } else { 0 };
+    let samples = [
+        tsc as u32,
+        (tsc >> 32) as u32,
+        crate::time::realtime(),
+        pit::uptime_us() as u32,
+        cpu::cpuid(1).0,
+        tsc::khz(),
+    ];
+    with_rng(|rng| {
+        for sample in samples {
+            rng.mix(sample);
+        }
+        rng.reseed();
+    });
+    SAMPLES[Source::Boot as usize].fetch_add(samples.len() as u32, Ordering::Relaxed);
+    // The TSC's low bits at boot are hard to predict; the rest is not
+    credit(8);
+}
+
+fn credit(bits: u32) {
+    let _ = ENTROPY_BITS.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+        |e| Some((e + bits).min(MAX_ENTROPY_BITS)));
+}
+
+/// Timer tick: mix in how long the tick took (timer IRQ)
+pub fn add_timer_sample() {
+    let now = cycles();
+    let jittered = with_rng(|rng| {
+        let delta = now.wrapping_sub(rng.last_tick);
+        let jittered = delta != rng.last_delta;
+        rng.mix(now ^ delta.rotate_left(16));
+        rng.last_tick = now;
+        rng.last_delta = delta;
+        jittered
+    });
+    SAMPLES[Source::Timer as usize].fetch_add(1, Ordering::Relaxed);
+    if jittered {
+        credit(1);
+    }
+}
+
+/// Input byte arrived: mix in its value and when (keyboard and mouse IRQs)
+pub fn add_input(value: u32) {
+    let now = cycles();
+    with_rng(|rng| rng.mix(now ^ value.rotate_left(24)));
+    SAMPLES[Source::Input as usize].fetch_add(1, Ordering::Relaxed);
+    credit(2);
+}
+
+/// Fill `buf` with random bytes
+pub fn rand_bytes(buf: &mut [u8]) {
+    with_rng(|rng| rng.fill(buf));
+    BYTES_OUT.fetch_add(buf.len() as u32, Ordering::Relaxed);
+}
+
+/// A random u32
+pub fn rand_u32() -> u32 {
+    let mut bytes = [0u8; 4];
+    rand_bytes(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+/// Estimated entropy taken in, in bits (capped at the pool size)
+pub fn entropy_bits() -> u32 {
+    ENTROPY_BITS.load(Ordering::Relaxed)
+}
+
+/// Pool state (/proc/random)
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "entropy: {} of {} bits (estimate)", entropy_bits(), MAX_ENTROPY_BITS);
+    let _ = writeln!(out, "timing: {}", if HAVE_TSC.load(Ordering::Relaxed) { "tsc" } else { "pit" });
+    for (name, count) in SOURCE_NAMES.iter().zip(SAMPLES.iter()) {
+        let _ = writeln!(out, "{:<6} {} samples", name, count.load(Ordering::Relaxed));
+    }
+    let _ = writeln!(out, "output: {} bytes", BYTES_OUT.load(Ordering::Relaxed));
+}
+
+// =============================================================================
+// ChaCha20
+// =============================================================================
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// One 64-byte ChaCha20 block (RFC 8439, 64-bit counter, zero nonce)
+fn chacha20_block(key: &[u32; 8], counter: u64, out: &mut [u32; 16]) {
+    let mut state = [
+        0x6170_7865, 0x3320_646E, 0x7962_2D32, 0x6B20_6574,
+        key[0], key[1], key[2], key[3], key[4], key[5], key[6], key[7],
+        counter as u32, (counter >> 32) as u32, 0, 0,
+    ];
+    let input = state;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (o, (s, i)) in out.iter_mut().zip(state.iter().zip(input.iter())) {
+        *o = s.wrapping_add(*i);
+    }
+}
@@ -2,9 +2,42 @@
 //!
 //! Uses intrusive linked lists for run queues (no EventChains here - raw performance).
 //! The scheduler is preemptive with priority-based round-robin.
+//!
+//! Two per-task flags change that:
+//! - `no_preempt` (run to completion): the timer doesn't take the CPU away
+//!   when the slice runs out, for short driver work that shouldn't be cut
+//!   in half. The task still gives it up by blocking, yielding or exiting;
+//!   one that overruns by `MAX_OVERRUN_TICKS` is preempted anyway.
+//! - `affinity`: the CPUs a task may run on; masks must include an
+//!   online CPU.
+//!
+//! Each CPU has its own run queues (see `arch::x86::smp`). A task is queued
+//! on the CPU it last ran on; a CPU with nothing of its own to run steals
+//! from the busiest other one. User programs stay on CPU 0, and only CPU 0
+//! gets timer ticks, so tasks elsewhere run until they block or yield.
+//! Queue changes happen under `SCHED_LOCK`.
+//!
+//! Deferred and forced preemptions and steals are counted in /proc/sched.
 
+pub mod stats;
+pub mod mutex;
+pub mod rlimit;
+pub mod spinlock;
+pub mod timer;
+pub mod wait;
+
+use alloc::boxed::Box;
 use crate::mm::intrusive::{IntrusiveNode, IntrusiveQueue};
+use crate::mm::paging::AddressSpace;
+use crate::mm::vma::VmaList;
+use core::ptr::NonNull;
 use core::sync::atomic::{AtomicU32, Ordering};
+use mutex::RawMutex;
+use rlimit::Limits;
+use spinlock::RawSpinLock;
+use stats::SchedStats;
+
+pub use crate::arch::x86::smp::MAX_CPUS;
 
 /// Process ID type
 pub type Pid = u32;
@@ -53,7 +86,27 @@ impl Default for Priority {
 }
 
 /// Number of priority levels
-const NUM_PRIORITIES: usize = 5;
+pub const NUM_PRIORITIES: usize = 5;
+
+/// Ticks a `no_preempt` task may run past its slice before it is
+/// preempted regardless
+pub const MAX_OVERRUN_TICKS: u32 = 20;
+
+/// Set of CPUs, bit per CPU
+pub type CpuMask = u32;
+
+/// Every CPU (the default affinity)
+pub const ALL_CPUS: CpuMask = !0;
+
+/// CPU we are running on
+pub fn current_cpu() -> u32 {
+    crate::arch::x86::smp::cpu_index() as u32
+}
+
+/// CPUs that are up
+pub fn online_cpus() -> CpuMask {
+    crate::arch::x86::smp::online_mask()
+}
 
 /// Task Control Block
 ///
@@ -114,6 +167,54 @@ pub struct Task {
     pub kernel_stack: u32,
     /// User stack pointer
     pub user_stack: u32,
+    /// Lowest user-accessible address (inclusive)
+    pub user_base: u32,
+    /// Highest user-accessible address (exclusive)
+    pub user_limit: u32,
+    
+    // Statistics (kept after the saved context so the asm offsets hold)
+    /// Tick at which the task last became Ready
+    pub ready_since: AtomicU32,
+    
+    // Priority inheritance (see `mutex`)
+    /// Priority assigned at creation; `priority` may be boosted above it
+    pub base_priority: Priority,
+    /// Mutex this task is blocked waiting for
+    pub blocked_on: Option<NonNull<RawMutex>>,
+    /// Most recently acquired mutex still held (links through the mutexes)
+    pub held_locks: Option<NonNull<RawMutex>>,
+    
+    // Address space layout
+    /// User regions and program break
+    pub vmas: VmaList,
+    
+    // Filesystem context
+    /// Current working directory (canonical absolute path)
+    cwd: [u8; crate::fs::MAX_PATH],
+    /// Length of `cwd` in bytes
+    cwd_len: usize,
+    /// VFS descriptors standing in for stdin/stdout/stderr (None = console)
+    pub stdio: [Option<u32>; 3],
+    
+    // Resource limits (see `rlimit`)
+    /// Caps on heap, open files and windows
+    pub limits: Limits,
+    /// VFS descriptors opened by this task (bit per descriptor)
+    pub open_fds: u32,
+    /// Windows created by this task
+    pub windows: u32,
+    /// Never picked by the OOM killer
+    pub essential: bool,
+    
+    // Scheduling flags
+    /// Run to completion: not preempted when the slice runs out
+    pub no_preempt: bool,
+    /// Ticks this run has gone past its slice (with `no_preempt`)
+    pub overrun: u32,
+    /// CPUs the task may run on
+    pub affinity: CpuMask,
+    /// CPU the task last ran or was queued on
+    pub cpu: AtomicU32,
 }
 
 impl Task {
@@ -135,7 +236,26 @@ impl Task {
             cr3: 0,
             kernel_stack: 0,
             user_stack: 0,
+            user_base: crate::syscall::usercopy::USER_SPACE_START,
+            user_limit: crate::syscall::usercopy::USER_SPACE_END,
+            ready_since: AtomicU32::new(0),
+            base_priority: priority,
+            blocked_on: None,
+            held_locks: None,
+            vmas: VmaList::new(),
+            cwd: [0; crate::fs::MAX_PATH],
+            cwd_len: 1,
+            stdio: [None; 3],
+            limits: Limits::new(),
+            open_fds: 0,
+            windows: 0,
+            essential: false,
+            no_preempt: false,
+            overrun: 0,
+            affinity: ALL_CPUS,
+            cpu: AtomicU32::new(0),
         };
+        task.cwd[0] = b'/';
         
         // Copy name
         let name_bytes = name.as_bytes();
@@ -150,30 +270,57 @@ impl Task {
         let len = self.name.iter().position(|&c| c == 0).unwrap_or(16);
         core::str::from_utf8(&self.name[..len]).unwrap_or("???")
     }
+    
+    /// Current working directory
+    pub fn cwd(&self) -> &str {
+        core::str::from_utf8(&self.cwd[..self.cwd_len]).unwrap_or("/")
+    }
+    
+    /// Set the working directory (must already be canonical)
+    pub fn set_cwd(&mut self, path: &str) -> Result<(), &'static str> {
+        if !path.starts_with('/') || path.len() > self.cwd.len() {
+            return Err("invalid cwd");
+        }
+        self.cwd[..path.len()].copy_from_slice(path.as_bytes());
+        self.cwd_len = path.len();
+        Ok(())
+    }
+    
+    /// May the task run on `cpu`?
+    pub fn runs_on(&self, cpu: u32) -> bool {
+        cpu < CpuMask::BITS && self.affinity & (1 << cpu) != 0
+    }
+    
+    /// Restrict the task to `mask`, which must include an online CPU
+    pub fn set_affinity(&mut self, mask: CpuMask) -> Result<(), &'static str> {
+        if mask & online_cpus() == 0 {
+            return Err("No online CPU in affinity mask");
+        }
+        self.affinity = mask;
+        Ok(())
+    }
 }
 
-/// Multi-level feedback queue scheduler
-pub struct Scheduler {
+/// Guards the run queues of every CPU
+static SCHED_LOCK: RawSpinLock = RawSpinLock::new();
+
+/// One CPU's share of the scheduler
+struct CpuQueue {
     /// Run queues for each priority level
     run_queues: [IntrusiveQueue<Task, fn(&Task) -> &IntrusiveNode>; NUM_PRIORITIES],
     /// Currently running task
     current: Option<*mut Task>,
     /// Idle task
     idle_task: Option<*mut Task>,
+    /// Task being switched away from; not picked again until
+    /// `finish_switch` has seen its registers saved
+    prev: Option<*mut Task>,
     /// Number of ready tasks
     ready_count: usize,
-    /// Total context switches
-    context_switches: u64,
 }
 
-/// Node accessor for run queue
-fn run_queue_node(task: &Task) -> &IntrusiveNode {
-    &task.run_queue_node
-}
-
-impl Scheduler {
-    /// Create a new scheduler
-    pub const fn new() -> Self {
+impl CpuQueue {
+    const fn new() -> Self {
         Self {
             run_queues: [
                 IntrusiveQueue::new(run_queue_node),
@@ -184,8 +331,59 @@ impl Scheduler {
             ],
             current: None,
             idle_task: None,
+            prev: None,
             ready_count: 0,
+        }
+    }
+}
+
+/// Multi-level feedback queue scheduler
+pub struct Scheduler {
+    /// Queues of each CPU
+    cpus: [CpuQueue; MAX_CPUS],
+    /// Total context switches
+    context_switches: u64,
+    /// Tasks taken from another CPU's queues
+    steals: u64,
+    /// Queue length, latency and switch-rate counters
+    stats: SchedStats,
+}
+
+/// Node accessor for run queue
+fn run_queue_node(task: &Task) -> &IntrusiveNode {
+    &task.run_queue_node
+}
+
+/// CPU whose queues a task goes on
+fn home_cpu(task: &Task) -> usize {
+    // User programs stay on the boot CPU: exec and the syscall paths
+    // aren't SMP-safe yet
+    if task.cr3 != 0 {
+        return 0;
+    }
+    let allowed = task.affinity & online_cpus();
+    let last = task.cpu.load(Ordering::Relaxed);
+    let here = current_cpu();
+    if last < CpuMask::BITS && allowed & (1 << last) != 0 {
+        last as usize
+    } else if allowed & (1 << here) != 0 {
+        here as usize
+    } else if allowed != 0 {
+        allowed.trailing_zeros() as usize
+    } else {
+        0
+    }
+}
+
+impl Scheduler {
+    /// Create a new scheduler
+    pub const fn new() -> Self {
+        const EMPTY: CpuQueue = CpuQueue::new();
+        Self {
+            cpus: [EMPTY; MAX_CPUS],
             context_switches: 0,
+            steals: 0,
+            stats: SchedStats::new(),
         }
     }
     
@@ -195,62 +393,170 @@ impl Scheduler {
     ///
     /// Task must remain valid and at a stable address while in the queue.
     pub unsafe fn enqueue(&mut self, task: &Task) {
+        let cpu = SCHED_LOCK.with(|| self.enqueue_locked(task));
+        crate::arch::x86::smp::send_reschedule(cpu);
+    }
+
+    /// Queue a task on its home CPU (lock held); returns the CPU
+    unsafe fn enqueue_locked(&mut self, task: &Task) -> usize {
+        let cpu = home_cpu(task);
         let priority = task.priority as usize;
-        self.run_queues[priority].enqueue(task);
-        self.ready_count += 1;
+        let now = crate::arch::x86::pit::ticks();
+        task.ready_since.store(now, Ordering::Relaxed);
+        task.cpu.store(cpu as u32, Ordering::Relaxed);
+        self.cpus[cpu].run_queues[priority].enqueue(task);
+        self.cpus[cpu].ready_count += 1;
+        self.stats.queue_changed(priority, 1, now);
+        cpu
     }
-    
-    /// Pick the next task to run
-    ///
-    /// Returns the highest priority ready task.
-    pub unsafe fn pick_next(&mut self) -> Option<*mut Task> {
-        // Check queues from highest to lowest priority
-        for priority in (0..NUM_PRIORITIES).rev() {
-            if let Some(task) = self.run_queues[priority].dequeue() {
-                self.ready_count -= 1;
+
+    /// Is a task still being switched away from on some CPU?
+    fn is_switching(&self, task: *mut Task) -> bool {
+        self.cpus.iter().any(|c| c.prev == Some(task))
+    }
+
+    /// Take the highest priority task at `min` or above from `cpu`'s
+    /// queues that may run on `for_cpu` (lock held)
+    unsafe fn take_from(&mut self, cpu: usize, for_cpu: u32, min: usize) -> Option<*mut Task> {
+        let stealing = cpu as u32 != for_cpu;
+        for priority in (min..NUM_PRIORITIES).rev() {
+            let mut eligible = None;
+            self.cpus[cpu].run_queues[priority].for_each(|task| {
+                let t = task.as_ref();
+                if eligible.is_none() && t.runs_on(for_cpu) && !self.is_switching(task.as_ptr())
+                    && (!stealing || t.cr3 == 0) {
+                    eligible = Some(task);
+                }
+            });
+            if let Some(task) = eligible {
+                self.cpus[cpu].run_queues[priority].remove(task.as_ref());
+                let now = crate::arch::x86::pit::ticks();
+                self.cpus[cpu].ready_count -= 1;
+                self.stats.queue_changed(priority, -1, now);
+                self.stats.record_latency(
+                    now.wrapping_sub(task.as_ref().ready_since.load(Ordering::Relaxed)));
+                task.as_ref().cpu.store(for_cpu, Ordering::Relaxed);
                 return Some(task.as_ptr());
             }
         }
-        
-        // No ready tasks, return idle task
-        self.idle_task
+        None
     }
     
-    /// Get the currently running task
+    /// Pick the next task to run at `min` priority or above (lock held)
+    ///
+    /// Returns the highest priority ready task in this CPU's queues, or
+    /// failing that one stolen from the CPU with the most waiting.
+    unsafe fn pick_next(&mut self, min: Priority) -> Option<*mut Task> {
+        let cpu = current_cpu();
+        if let Some(task) = self.take_from(cpu as usize, cpu, min as usize) {
+            return Some(task);
+        }
+        let online = online_cpus();
+        let busiest = (0..MAX_CPUS)
+            .filter(|&c| c != cpu as usize && online & (1 << c) != 0 && self.cpus[c].ready_count > 0)
+            .max_by_key(|&c| self.cpus[c].ready_count)?;
+        let task = self.take_from(busiest, cpu, min as usize)?;
+        self.steals += 1;
+        Some(task)
+    }
+    
+    /// Change a task's effective priority, moving it between run queues if
+    /// it is waiting to run
+    ///
+    /// # Safety
+    ///
+    /// Must be called with interrupts disabled; `task` must be valid.
+    pub unsafe fn set_effective_priority(&mut self, task: *mut Task, priority: Priority) {
+        SCHED_LOCK.with(|| {
+            let t = &mut *task;
+            if t.priority == priority {
+                return;
+            }
+            if t.state == TaskState::Ready && t.run_queue_node.is_linked() {
+                let old = t.priority as usize;
+                let cpu = t.cpu.load(Ordering::Relaxed) as usize;
+                self.cpus[cpu].run_queues[old].remove(t);
+                self.cpus[cpu].ready_count -= 1;
+                self.stats.queue_changed(old, -1, crate::arch::x86::pit::ticks());
+                // Keep the original wait time so latency stats stay honest
+                let since = t.ready_since.load(Ordering::Relaxed);
+                t.priority = priority;
+                self.enqueue_locked(t);
+                t.ready_since.store(since, Ordering::Relaxed);
+            } else {
+                t.priority = priority;
+            }
+        });
+    }
+    
+    /// Visit every task waiting in the run queues, highest priority first
+    /// (CPU by CPU)
+    ///
+    /// Holds `SCHED_LOCK` throughout, so `f` must not enqueue or dequeue
+    /// tasks.
+    pub fn for_each_ready<F: FnMut(&Task)>(&self, mut f: F) {
+        SCHED_LOCK.with(|| unsafe {
+            for cpu in &self.cpus {
+                for priority in (0..NUM_PRIORITIES).rev() {
+                    cpu.run_queues[priority].for_each(|task| f(task.as_ref()));
+                }
+            }
+        })
+    }
+    
+    /// Get the task running on this CPU
     pub fn current(&self) -> Option<*mut Task> {
-        self.current
+        self.cpus[current_cpu() as usize].current
     }
     
-    /// Set the current task
+    /// Set the task running on this CPU
     pub fn set_current(&mut self, task: Option<*mut Task>) {
-        self.current = task;
+        self.cpus[current_cpu() as usize].current = task;
     }
     
-    /// Set the idle task
+    /// Set this CPU's idle task
     pub fn set_idle(&mut self, task: *mut Task) {
-        self.idle_task = Some(task);
+        self.cpus[current_cpu() as usize].idle_task = Some(task);
     }
     
-    /// Get the number of ready tasks
+    /// Get the number of ready tasks on every CPU
     pub fn ready_count(&self) -> usize {
-        self.ready_count
+        self.cpus.iter().map(|c| c.ready_count).sum()
+    }
+
+    /// Get the number of ready tasks queued on one CPU
+    pub fn ready_on(&self, cpu: usize) -> usize {
+        self.cpus.get(cpu).map_or(0, |c| c.ready_count)
     }
     
     /// Get total context switches
     pub fn context_switches(&self) -> u64 {
         self.context_switches
     }
+
+    /// Get tasks taken from another CPU's queues
+    pub fn steals(&self) -> u64 {
+        self.steals
+    }
+    
+    /// Get run-queue statistics
+    pub fn stats(&self) -> &SchedStats {
+        &self.stats
+    }
     
     /// Increment context switch counter
     pub fn record_context_switch(&mut self) {
         self.context_switches += 1;
+        self.stats.record_switch(crate::arch::x86::pit::ticks());
     }
     
     /// Called on timer tick
     ///
     /// Decrements current task's time slice and triggers reschedule if needed.
+    /// A `no_preempt` task keeps the CPU past its slice, up to
+    /// `MAX_OVERRUN_TICKS`.
     pub unsafe fn timer_tick(&mut self) -> bool {
-        if let Some(task) = self.current {
+        if let Some(task) = self.current() {
             let task = &mut *task;
             task.cpu_time += 1;
             
@@ -260,6 +566,14 @@ impl Scheduler {
             
             // Need reschedule if time slice expired
             if task.time_slice == 0 {
+                if task.no_preempt {
+                    task.overrun += 1;
+                    if task.overrun < MAX_OVERRUN_TICKS {
+                        self.stats.record_preempt_deferred();
+                        return false;
+                    }
+                    self.stats.record_preempt_forced();
+                }
                 return true;
             }
         }
@@ -338,32 +652,140 @@ pub fn timer_tick() {
 }
 
 /// Trigger a reschedule
+///
+/// A task still able to run keeps the CPU unless one of at least its
+/// priority is waiting; it is requeued once the switch away from it is
+/// done (`finish_switch`).
 pub fn schedule() {
-    unsafe {
-        let old = SCHEDULER.current();
-        
-        // Put current task back in run queue if it's still runnable
-        if let Some(old_ptr) = old {
-            let old_task = &*old_ptr;
-            if old_task.state == TaskState::Running {
-                // Reset time slice and re-enqueue
-                (*old_ptr).state = TaskState::Ready;
+    crate::arch::without_interrupts(|| unsafe {
+        let sched = &mut *core::ptr::addr_of_mut!(SCHEDULER);
+        SCHED_LOCK.lock();
+        let cpu = current_cpu() as usize;
+        let old = sched.cpus[cpu].current;
+        let runnable = old.filter(|&t| (*t).state == TaskState::Running);
+        let min = runnable.map_or(Priority::Idle, |t| (*t).priority);
+
+        let next = match sched.pick_next(min) {
+            Some(task) => Some(task),
+            None if runnable.is_none() => sched.cpus[cpu].idle_task,
+            None => None,
+        };
+        let Some(new_ptr) = next.filter(|&t| Some(t) != old) else {
+            // Carry on with the current task
+            if let Some(old_ptr) = runnable {
                 (*old_ptr).time_slice = 10;
-                SCHEDULER.enqueue(&*old_ptr);
             }
+            SCHED_LOCK.unlock();
+            return;
+        };
+
+        if let Some(old_ptr) = runnable {
+            // Reset time slice; requeued by `finish_switch`
+            (*old_ptr).state = TaskState::Ready;
+            (*old_ptr).time_slice = 10;
         }
-        
-        // Pick next task
-        if let Some(new_ptr) = SCHEDULER.pick_next() {
-            (*new_ptr).state = TaskState::Running;
-            SCHEDULER.set_current(Some(new_ptr));
-            SCHEDULER.record_context_switch();
-            
-            if let Some(old_ptr) = old {
-                if old_ptr != new_ptr {
-                    Scheduler::context_switch(old_ptr, new_ptr);
-                }
-            }
+        (*new_ptr).state = TaskState::Running;
+        (*new_ptr).overrun = 0;
+        sched.cpus[cpu].current = Some(new_ptr);
+        sched.cpus[cpu].prev = old;
+        sched.record_context_switch();
+        SCHED_LOCK.unlock();
+
+        if let Some(old_ptr) = old {
+            crate::trace!(ContextSwitch,
+                ((*old_ptr).pid & 0xFFFF) << 16 | ((*new_ptr).pid & 0xFFFF));
+            crate::mm::paging::activate((*new_ptr).cr3);
+            Scheduler::context_switch(old_ptr, new_ptr);
+            finish_switch();
+        }
+    })
+}
+
+/// Requeue the task this CPU just switched away from, if it can still run
+///
+/// Runs in the task switched to, once the old task's registers are saved,
+/// so no other CPU can pick it up half-saved. A new task's entry point
+/// must call it before anything else.
+pub fn finish_switch() {
+    SCHED_LOCK.with(|| unsafe {
+        let sched = &mut *core::ptr::addr_of_mut!(SCHEDULER);
+        let cpu = current_cpu() as usize;
+        let Some(prev) = sched.cpus[cpu].prev.take() else {
+            return;
+        };
+        let task = &*prev;
+        if Some(prev) == sched.cpus[cpu].idle_task || task.state != TaskState::Ready
+            || task.run_queue_node.is_linked() {
+            return;
         }
+        let target = sched.enqueue_locked(task);
+        if target != cpu {
+            crate::arch::x86::smp::send_reschedule(target);
+        }
+    });
+}
+
+/// Start scheduling on another CPU, with `idle` as the task it is running
+///
+/// # Safety
+///
+/// `idle` must stay valid for as long as the kernel runs.
+pub unsafe fn start_cpu(cpu: usize, idle: *mut Task) {
+    SCHED_LOCK.with(|| {
+        let sched = &mut *core::ptr::addr_of_mut!(SCHEDULER);
+        (*idle).state = TaskState::Running;
+        (*idle).cpu.store(cpu as u32, Ordering::Relaxed);
+        sched.cpus[cpu].idle_task = Some(idle);
+        sched.cpus[cpu].current = Some(idle);
+    });
+}
+
+/// A CPU's idle task (null if it has none)
+pub fn idle_task(cpu: usize) -> *mut Task {
+    unsafe {
+        let sched = &*core::ptr::addr_of!(SCHEDULER);
+        sched.cpus.get(cpu).and_then(|c| c.idle_task).unwrap_or(core::ptr::null_mut())
     }
 }
+
+/// Fork the current task
+///
+/// The child gets a copy-on-write clone of the parent's address space
+/// and region list, the parent's saved context (with EAX = 0 so it sees fork return 0),
+/// and its working directory. Returns the child's PID.
+pub fn fork() -> Result<Pid, &'static str> {
+    unsafe {
+        let parent = &*SCHEDULER.current().ok_or("no current task")?;
+        let child = fork_task(parent)?;
+        SCHEDULER.enqueue(child);
+        Ok(child.pid)
+    }
+}
+
+/// Clone a task for fork without making it runnable
+pub fn fork_task(parent: &Task) -> Result<&'static mut Task, &'static str> {
+    let space = AddressSpace::from_directory(parent.cr3).fork()?;
+
+    let child = Box::leak(Box::new(Task::new(parent.name_str(), parent.base_priority)));
+    child.ppid = parent.pid;
+    child.cr3 = space.directory();
+    child.eax = 0;
+    child.ebx = parent.ebx;
+    child.ecx = parent.ecx;
+    child.edx = parent.edx;
+    child.esi = parent.esi;
+    child.edi = parent.edi;
+    child.ebp = parent.ebp;
+    child.esp = parent.esp;
+    child.eip = parent.eip;
+    child.eflags = parent.eflags;
+    child.kernel_stack = parent.kernel_stack;
+    child.user_stack = parent.user_stack;
+    child.user_base = parent.user_base;
+    child.user_limit = parent.user_limit;
+    child.vmas = parent.vmas;
+    child.limits = parent.limits;
+    child.affinity = parent.affinity;
+    child.set_cwd(parent.cwd())?;
+    Ok(child)
+}
@@ -0,0 +1,508 @@
+//! Kernel Mutex with Priority Inheritance
+//!
+//! A sleeping lock for task context. Contended lockers block on an intrusive
+//! wait queue (through `Task::wait_queue_node`) instead of spinning, and the
+//! holder is boosted to the priority of its highest waiter so a Low task
+//! holding a lock cannot starve a Realtime task behind a Normal one.
+//!
+//! # Inheritance rules
+//!
+//! - Blocking on a mutex boosts its owner to the waiter's priority. If the
+//!   owner is itself blocked on another mutex the boost follows the chain
+//!   (bounded by `MAX_CHAIN_DEPTH`).
+//! - Ownership is handed directly to the highest-priority waiter on unlock
+//!   (FIFO among equals), so a woken task never has to race for the lock.
+//! - On unlock the releasing task drops to the highest of its base priority
+//!   and the top waiter of every mutex it still holds, which keeps nested
+//!   locks boosted until the last contended one is released.
+//!
+//! Outside task context (early boot, the GUI polling loop) there is no task
+//! to block, so a contended lock spins with interrupts enabled instead.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use crate::mm::intrusive::{IntrusiveList, IntrusiveNode};
+use super::spinlock::RawSpinLock;
+use super::{Priority, Task, TaskState, SCHEDULER};
+
+/// Longest owner chain followed when propagating a boost
+const MAX_CHAIN_DEPTH: usize = 8;
+
+/// Guards every mutex's state; one lock, since boosts follow chains
+/// across mutexes
+static STATE_LOCK: RawSpinLock = RawSpinLock::new();
+
+/// Run a closure with mutex state locked and interrupts disabled
+fn with_state<R>(f: impl FnOnce() -> R) -> R {
+    STATE_LOCK.with(f)
+}
+
+/// Node accessor for mutex wait queues
+fn wait_queue_node(task: &Task) -> &IntrusiveNode {
+    &task.wait_queue_node
+}
+
+/// Lock state (only touched with interrupts disabled)
+struct MutexState {
+    /// Lock is held
+    locked: bool,
+    /// Holding task (None when taken outside task context)
+    owner: Option<NonNull<Task>>,
+    /// Tasks blocked waiting for the lock
+    waiters: IntrusiveList<Task, fn(&Task) -> &IntrusiveNode>,
+    /// Next mutex in the owner's held list
+    next_held: Option<NonNull<RawMutex>>,
+}
+
+/// Mutex without attached data
+///
+/// Must stay at a stable address while locked (it is linked into the
+/// owner's held list); embed it in statics or heap objects.
+pub struct RawMutex {
+    state: UnsafeCell<MutexState>,
+}
+
+// Safety: all state is accessed under `STATE_LOCK` with interrupts disabled
+unsafe impl Sync for RawMutex {}
+unsafe impl Send for RawMutex {}
+
+/// Result of one locking attempt
+#[derive(Debug, PartialEq, Eq)]
+enum Attempt {
+    Acquired,
+    Blocked,
+    NoTask,
+    /// The caller already holds the lock
+    Recursive(super::Pid),
+}
+
+impl RawMutex {
+    pub const fn new() -> Self {
+        Self {
+            state: UnsafeCell::new(MutexState {
+                locked: false,
+                owner: None,
+                waiters: IntrusiveList::new(wait_queue_node as fn(&Task) -> &IntrusiveNode),
+                next_held: None,
+            }),
+        }
+    }
+
+    #[inline]
+    unsafe fn state(&self) -> &mut MutexState {
+        &mut *self.state.get()
+    }
+
+    /// Is the lock currently held?
+    pub fn is_locked(&self) -> bool {
+        with_state(|| unsafe { self.state().locked })
+    }
+
+    /// Take the lock if it is free
+    pub fn try_lock(&self) -> bool {
+        with_state(|| unsafe {
+            if self.state().locked {
+                return false;
+            }
+            self.take(SCHEDULER.current().and_then(NonNull::new));
+            true
+        })
+    }
+
+    /// Acquire the lock, blocking (and boosting the owner) if it is held
+    pub fn lock(&self) {
+        loop {
+            match with_state(|| unsafe { self.attempt() }) {
+                Attempt::Acquired => return,
+                Attempt::Blocked => {
+                    super::schedule();
+                    // Ownership is handed over on unlock; confirm we got it
+                    let current = unsafe { SCHEDULER.current().and_then(NonNull::new) };
+                    if with_state(|| unsafe { self.state().owner == current }) {
+                        return;
+                    }
+                }
+                Attempt::NoTask => core::hint::spin_loop(),
+                Attempt::Recursive(pid) => panic!("mutex: recursive lock by pid {}", pid),
+            }
+        }
+    }
+
+    /// One locking attempt (interrupts disabled)
+    unsafe fn attempt(&self) -> Attempt {
+        let current = SCHEDULER.current().and_then(NonNull::new);
+
+        if !self.state().locked {
+            self.take(current);
+            return Attempt::Acquired;
+        }
+
+        let Some(task) = current else {
+            return Attempt::NoTask;
+        };
+        if self.state().owner == Some(task) {
+            return Attempt::Recursive(task.as_ref().pid);
+        }
+
+        self.add_waiter(&mut *task.as_ptr());
+        Attempt::Blocked
+    }
+
+    /// Queue `t` for the lock and boost the owner (interrupts disabled)
+    unsafe fn add_waiter(&self, t: &mut Task) {
+        t.state = TaskState::Blocked;
+        t.blocked_on = Some(NonNull::from(self));
+        self.state().waiters.push_back(t);
+
+        propagate_boost(self, t.priority);
+    }
+
+    /// Mark the lock held by `owner` (interrupts disabled)
+    unsafe fn take(&self, owner: Option<NonNull<Task>>) {
+        let state = self.state();
+        state.locked = true;
+        state.owner = owner;
+        if let Some(task) = owner {
+            let t = &mut *task.as_ptr();
+            state.next_held = t.held_locks;
+            t.held_locks = Some(NonNull::from(self));
+        }
+    }
+
+    /// Release the lock, handing it to the highest-priority waiter
+    pub fn unlock(&self) {
+        let preempt = with_state(|| unsafe { self.release() });
+        if preempt {
+            super::schedule();
+        }
+    }
+
+    /// Release with interrupts disabled; returns true if a waiter now
+    /// outranks the releasing task
+    unsafe fn release(&self) -> bool {
+        if !self.state().locked {
+            return false;
+        }
+
+        let releaser = self.state().owner;
+        if let Some(task) = releaser {
+            unlink_held(&mut *task.as_ptr(), self);
+        }
+
+        let woken = self.top_waiter();
+        match woken {
+            Some(waiter) => {
+                let w = &mut *waiter.as_ptr();
+                self.state().waiters.remove(w);
+                w.blocked_on = None;
+                self.take(Some(waiter));
+                // The new owner inherits from whoever is still queued
+                w.priority = inherited_priority(w);
+                w.state = TaskState::Ready;
+                SCHEDULER.enqueue(w);
+            }
+            None => {
+                let state = self.state();
+                state.locked = false;
+                state.owner = None;
+            }
+        }
+
+        // Drop back to whatever the locks still held justify
+        let Some(task) = releaser else {
+            return false;
+        };
+        let priority = inherited_priority(task.as_ref());
+        SCHEDULER.set_effective_priority(task.as_ptr(), priority);
+
+        woken.map_or(false, |w| w.as_ref().priority > priority)
+    }
+
+    /// Highest-priority waiter, first queued among equals
+    unsafe fn top_waiter(&self) -> Option<NonNull<Task>> {
+        let mut best: Option<NonNull<Task>> = None;
+        self.state().waiters.for_each(|task| {
+            let better = match best {
+                Some(b) => task.as_ref().priority > b.as_ref().priority,
+                None => true,
+            };
+            if better {
+                best = Some(task);
+            }
+        });
+        best
+    }
+
+    /// Owner of the lock, if held from task context
+    pub fn owner_pid(&self) -> Option<super::Pid> {
+        with_state(|| unsafe {
+            self.state().owner.map(|t| t.as_ref().pid)
+        })
+    }
+}
+
+// =============================================================================
+// Inheritance Helpers
+// =============================================================================
+
+/// Boost the owner of `lock` (and anything it is blocked behind)
+unsafe fn propagate_boost(lock: &RawMutex, priority: Priority) {
+    let mut lock = NonNull::from(lock);
+    for _ in 0..MAX_CHAIN_DEPTH {
+        let Some(owner) = lock.as_ref().state().owner else {
+            return;
+        };
+        let o = &mut *owner.as_ptr();
+        if o.priority >= priority {
+            return;
+        }
+        SCHEDULER.set_effective_priority(o, priority);
+        match o.blocked_on {
+            Some(next) => lock = next,
+            None => return,
+        }
+    }
+}
+
+/// Priority a task is entitled to from its base and the locks it holds
+unsafe fn inherited_priority(task: &Task) -> Priority {
+    let mut priority = task.base_priority;
+    let mut cursor = task.held_locks;
+    while let Some(lock) = cursor {
+        let lock = lock.as_ref();
+        if let Some(top) = lock.top_waiter() {
+            priority = priority.max(top.as_ref().priority);
+        }
+        cursor = lock.state().next_held;
+    }
+    priority
+}
+
+/// Remove `lock` from a task's held list
+unsafe fn unlink_held(task: &mut Task, lock: &RawMutex) {
+    let target = NonNull::from(lock);
+    let next = lock.state().next_held.take();
+
+    if task.held_locks == Some(target) {
+        task.held_locks = next;
+        return;
+    }
+    let mut cursor = task.held_locks;
+    while let Some(held) = cursor {
+        let state = held.as_ref().state();
+        if state.next_held == Some(target) {
+            state.next_held = next;
+            return;
+        }
+        cursor = state.next_held;
+    }
+}
+
+// =============================================================================
+// Mutex<T>
+// =============================================================================
+
+/// Data protected by a priority-inheriting kernel mutex
+pub struct Mutex<T> {
+    raw: RawMutex,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            raw: RawMutex::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Acquire the lock, blocking if needed
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.raw.lock();
+        MutexGuard { mutex: self }
+    }
+
+    /// Acquire the lock only if it is free
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.raw.try_lock() {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    /// Underlying lock (for ownership queries)
+    pub fn raw(&self) -> &RawMutex {
+        &self.raw
+    }
+}
+
+/// Releases the mutex when dropped
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.raw.unlock();
+    }
+}
+
+// =============================================================================
+// Self-Tests (run by `selftest`)
+// =============================================================================
+
+use alloc::boxed::Box;
+use crate::selftest::check;
+
+/// A task that is never scheduled, whose lock state the tests drive
+fn detached(name: &str, priority: Priority) -> Box<Task> {
+    let mut task = Box::new(Task::new(name, priority));
+    task.state = TaskState::Running;
+    task
+}
+
+/// Take `lock` for `owner` as its own `lock` call would
+fn take_for(lock: &RawMutex, owner: &mut Task) {
+    with_state(|| unsafe { lock.take(Some(NonNull::from(owner))) });
+}
+
+/// Queue `waiter` for `lock` as its own `lock` call would, boosting the
+/// owner
+fn block(lock: &RawMutex, waiter: &mut Task) {
+    with_state(|| unsafe { lock.add_waiter(waiter) });
+}
+
+/// Take `waiter` back out of the queue (as if it had given up); the
+/// owner's boost is recomputed when it next releases a lock
+fn unblock(lock: &RawMutex, waiter: &mut Task) {
+    with_state(|| unsafe {
+        lock.state().waiters.remove(waiter);
+        waiter.blocked_on = None;
+        waiter.state = TaskState::Running;
+    });
+}
+
+/// Number of locks on a task's held list
+fn held_count(task: &Task) -> usize {
+    with_state(|| unsafe {
+        let mut count = 0;
+        let mut cursor = task.held_locks;
+        while let Some(lock) = cursor {
+            count += 1;
+            cursor = lock.as_ref().state().next_held;
+        }
+        count
+    })
+}
+
+/// Nested locks taken and released in either order by the caller
+pub(crate) fn test_nested() -> Result<(), &'static str> {
+    let (a, b) = (RawMutex::new(), RawMutex::new());
+    a.lock();
+    b.lock();
+    check(a.is_locked() && b.is_locked(), "nested lock not held")?;
+    check(!a.try_lock() && !b.try_lock(), "held lock taken again")?;
+
+    // Out of order: the outer lock first
+    a.unlock();
+    check(!a.is_locked() && b.is_locked(), "unlocking the outer lock released the inner")?;
+    check(a.try_lock(), "released lock can't be retaken")?;
+    b.unlock();
+    a.unlock();
+    check(!a.is_locked() && !b.is_locked(), "nested locks left held")
+}
+
+/// A task taking a lock it already holds is caught, and its state is
+/// left alone
+pub(crate) fn test_reentrant() -> Result<(), &'static str> {
+    let mut owner = detached("mtx-owner", Priority::Normal);
+    let lock = RawMutex::new();
+    take_for(&lock, &mut owner);
+
+    // As the owner: interrupts stay off, so nothing runs as it meanwhile
+    let owner_ptr: *mut Task = &mut *owner;
+    let attempt = with_state(|| unsafe {
+        let sched = &mut *core::ptr::addr_of_mut!(SCHEDULER);
+        let current = sched.current();
+        sched.set_current(Some(owner_ptr));
+        let attempt = lock.attempt();
+        sched.set_current(current);
+        attempt
+    });
+
+    check(attempt == Attempt::Recursive(owner.pid), "recursive lock not detected")?;
+    check(!lock.try_lock(), "held lock taken again")?;
+    check(held_count(&owner) == 1 && lock.owner_pid() == Some(owner.pid), "re-entry changed the owner")?;
+    check(owner.state == TaskState::Running && owner.blocked_on.is_none(), "re-entry blocked the owner")?;
+
+    with_state(|| unsafe { lock.release() });
+    check(held_count(&owner) == 0 && !lock.is_locked(), "lock not released")
+}
+
+/// A task holding two contended locks stays boosted by the other's waiter
+/// until it releases that one too
+pub(crate) fn test_nested_inheritance() -> Result<(), &'static str> {
+    let mut owner = detached("mtx-owner", Priority::Low);
+    let mut normal = detached("mtx-normal", Priority::Normal);
+    let mut high = detached("mtx-high", Priority::High);
+    let (outer, inner) = (RawMutex::new(), RawMutex::new());
+    take_for(&outer, &mut owner);
+    take_for(&inner, &mut owner);
+    check(held_count(&owner) == 2, "held list not updated")?;
+
+    block(&outer, &mut normal);
+    check(owner.priority == Priority::Normal, "owner not boosted by the outer lock's waiter")?;
+    block(&inner, &mut high);
+    check(owner.priority == Priority::High, "owner not boosted by the inner lock's waiter")?;
+
+    unblock(&inner, &mut high);
+    with_state(|| unsafe { inner.release() });
+    check(owner.priority == Priority::Normal, "boost from the outer lock lost")?;
+    check(held_count(&owner) == 1, "inner lock still on the held list")?;
+
+    unblock(&outer, &mut normal);
+    with_state(|| unsafe { outer.release() });
+    check(owner.priority == Priority::Low, "boost kept after the last release")?;
+    check(held_count(&owner) == 0 && !outer.is_locked() && !inner.is_locked(), "locks left held")
+}
+
+/// A boost follows an owner that is itself blocked on another lock
+pub(crate) fn test_chain_boost() -> Result<(), &'static str> {
+    let mut first = detached("mtx-first", Priority::Low);
+    let mut second = detached("mtx-second", Priority::Low);
+    let mut high = detached("mtx-high", Priority::High);
+    let (a, b) = (RawMutex::new(), RawMutex::new());
+    take_for(&a, &mut first);
+    take_for(&b, &mut second);
+    block(&a, &mut second);
+
+    block(&b, &mut high);
+    check(second.priority == Priority::High, "direct owner not boosted")?;
+    check(first.priority == Priority::High, "boost didn't follow the chain")?;
+
+    unblock(&b, &mut high);
+    with_state(|| unsafe { b.release() });
+    check(second.priority == Priority::Low, "blocked owner kept its boost")?;
+    unblock(&a, &mut second);
+    with_state(|| unsafe { a.release() });
+    check(first.priority == Priority::Low, "chain owner kept its boost")
+}
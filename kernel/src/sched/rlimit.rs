@@ -0,0 +1,178 @@
+//! Resource Limits
+//!
+//! Per-task caps on heap size, open files and windows, checked where each
+//! resource is handed out. Every limit fails with its own error so the
+//! syscall layer can return a distinct errno for it. Forked children
+//! inherit the parent's limits; exec keeps them.
+
+use alloc::string::String;
+use core::fmt::Write;
+use super::{Task, SCHEDULER};
+use crate::fs::vfs::{FIRST_FD, MAX_FDS};
+use crate::syscall::usercopy::{USER_SPACE_END, USER_SPACE_START};
+
+/// Heap (program break) limit hit
+pub const EHEAP: &str = "heap limit exceeded";
+
+/// Open file limit hit
+pub const EFILES: &str = "too many open files";
+
+/// Window limit hit
+pub const EWINDOWS: &str = "too many windows";
+
+/// Default heap limit in bytes
+pub const DEFAULT_HEAP: u32 = 64 * 1024 * 1024;
+
+/// Default open file limit
+pub const DEFAULT_FILES: u32 = 16;
+
+/// Default window limit
+pub const DEFAULT_WINDOWS: u32 = 4;
+
+/// Most windows any task may be allowed
+pub const MAX_WINDOWS: u32 = 16;
+
+/// A limited resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Resource {
+    /// Bytes between the start of the heap and the program break
+    Heap = 0,
+    /// VFS descriptors (not counting stdin/stdout/stderr)
+    Files = 1,
+    /// Desktop windows
+    Windows = 2,
+}
+
+impl Resource {
+    pub fn from_u32(n: u32) -> Option<Self> {
+        match n {
+            0 => Some(Self::Heap),
+            1 => Some(Self::Files),
+            2 => Some(Self::Windows),
+            _ => None,
+        }
+    }
+
+    /// Highest value the limit can be raised to
+    pub const fn hard_limit(self) -> u32 {
+        match self {
+            Self::Heap => USER_SPACE_END - USER_SPACE_START,
+            Self::Files => MAX_FDS as u32,
+            Self::Windows => MAX_WINDOWS,
+        }
+    }
+}
+
+/// A task's limits
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub heap: u32,
+    pub files: u32,
+    pub windows: u32,
+}
+
+impl Limits {
+    pub const fn new() -> Self {
+        Self { heap: DEFAULT_HEAP, files: DEFAULT_FILES, windows: DEFAULT_WINDOWS }
+    }
+
+    pub fn get(&self, resource: Resource) -> u32 {
+        match resource {
+            Resource::Heap => self.heap,
+            Resource::Files => self.files,
+            Resource::Windows => self.windows,
+        }
+    }
+
+    /// Change a limit, returning the old one
+    pub fn set(&mut self, resource: Resource, value: u32) -> Result<u32, &'static str> {
+        if value > resource.hard_limit() {
+            return Err("limit above hard limit");
+        }
+        let old = self.get(resource);
+        match resource {
+            Resource::Heap => self.heap = value,
+            Resource::Files => self.files = value,
+            Resource::Windows => self.windows = value,
+        }
+        Ok(old)
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn current() -> Option<&'static mut Task> {
+    unsafe { SCHEDULER.current().map(|t| &mut *t) }
+}
+
+fn fd_bit(fd: u32) -> u32 {
+    match fd.checked_sub(FIRST_FD) {
+        Some(i) if (i as usize) < MAX_FDS => 1 << i,
+        _ => 0,
+    }
+}
+
+// =============================================================================
+// Checks
+// =============================================================================
+
+/// Check a new program break against the task's heap limit
+pub fn check_heap(task: &Task, heap_start: u32, new_brk: u32) -> Result<(), &'static str> {
+    if new_brk.saturating_sub(heap_start) > task.limits.heap {
+        return Err(EHEAP);
+    }
+    Ok(())
+}
+
+/// Can the current task open another file?
+pub fn check_files() -> Result<(), &'static str> {
+    match current() {
+        Some(task) if task.open_fds.count_ones() >= task.limits.files => Err(EFILES),
+        _ => Ok(()),
+    }
+}
+
+/// Count a descriptor against the current task
+pub fn track_fd(fd: u32) {
+    if let Some(task) = current() {
+        task.open_fds |= fd_bit(fd);
+    }
+}
+
+/// Stop counting a descriptor against the current task
+pub fn untrack_fd(fd: u32) {
+    if let Some(task) = current() {
+        task.open_fds &= !fd_bit(fd);
+    }
+}
+
+/// Count a new window against a task
+pub fn charge_window(task: &mut Task) -> Result<(), &'static str> {
+    if task.windows >= task.limits.windows {
+        return Err(EWINDOWS);
+    }
+    task.windows += 1;
+    Ok(())
+}
+
+/// Give back a window charged with `charge_window`
+pub fn release_window(task: &mut Task) {
+    task.windows = task.windows.saturating_sub(1);
+}
+
+/// /proc/limits: limits and usage of the current task
+pub fn report(out: &mut String) {
+    let Some(task) = current() else {
+        return;
+    };
+    let heap = task.vmas.heap_start().map_or(0, |start| task.vmas.brk().saturating_sub(start));
+    let _ = writeln!(out, "resource  limit       used");
+    let _ = writeln!(out, "heap      {:<10}  {}", task.limits.heap, heap);
+    let _ = writeln!(out, "files     {:<10}  {}", task.limits.files, task.open_fds.count_ones());
+    let _ = writeln!(out, "windows   {:<10}  {}", task.limits.windows, task.windows);
+}
@@ -0,0 +1,56 @@
+//! Spinlock
+//!
+//! For scheduler state shared between CPUs, held for a few instructions
+//! at a time. Always taken with interrupts disabled, so an interrupt on
+//! the holding CPU can't try to take it again. Code that may sleep uses
+//! `mutex` instead.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Spinlock without attached data
+pub struct RawSpinLock {
+    locked: AtomicBool,
+}
+
+impl RawSpinLock {
+    pub const fn new() -> Self {
+        Self { locked: AtomicBool::new(false) }
+    }
+
+    /// Spin until the lock is ours (interrupts must be disabled)
+    pub fn lock(&self) {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    pub fn try_lock(&self) -> bool {
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    pub fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Run a closure holding the lock with interrupts disabled
+    pub fn with<R>(&self, f: impl FnOnce() -> R) -> R {
+        crate::arch::without_interrupts(|| {
+            self.lock();
+            let result = f();
+            self.unlock();
+            result
+        })
+    }
+}
+
+impl Default for RawSpinLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
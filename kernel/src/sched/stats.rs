@@ -0,0 +1,236 @@
+//! Scheduler Statistics
+//!
+//! Run-queue instrumentation updated inline by the scheduler: time-weighted
+//! queue lengths per priority, a log2 histogram of scheduling latency (ticks
+//! between a task becoming Ready and being picked), and the context-switch
+//! rate over the last full second, and how often a run-to-completion task
+//! held on to the CPU past its slice. Everything is counted in PIT ticks so
+//! the hot path only does integer adds.
+
+use core::fmt::Write;
+use alloc::string::String;
+use super::NUM_PRIORITIES;
+
+/// Latency histogram buckets: 0, 1, 2-3, 4-7, ... , 64+ ticks
+pub const LATENCY_BUCKETS: usize = 8;
+
+/// Priority names for reports (index = `Priority as usize`)
+const PRIORITY_NAMES: [&str; NUM_PRIORITIES] = ["idle", "low", "normal", "high", "realtime"];
+
+/// Run-queue counters
+pub struct SchedStats {
+    /// Current length of each run queue
+    queue_len: [u32; NUM_PRIORITIES],
+    /// Longest each queue has been
+    queue_max: [u32; NUM_PRIORITIES],
+    /// Sum of length x ticks held (for time-weighted averages)
+    queue_area: [u64; NUM_PRIORITIES],
+    /// Tick of the first queue change (0 = nothing observed yet)
+    first_tick: u32,
+    /// Tick of the most recent queue change
+    last_change: u32,
+
+    /// Latency histogram (see `bucket_for`)
+    latency_hist: [u32; LATENCY_BUCKETS],
+    latency_max: u32,
+    latency_sum: u64,
+    latency_count: u32,
+
+    /// Start of the current switch-rate window
+    window_start: u32,
+    /// Switches counted in the current window
+    window_switches: u32,
+    /// Switches per second over the last full window
+    switch_rate: u32,
+
+    /// Ticks a `no_preempt` task kept the CPU past its slice
+    preempt_deferred: u32,
+    /// `no_preempt` tasks preempted for overrunning
+    preempt_forced: u32,
+}
+
+impl SchedStats {
+    pub const fn new() -> Self {
+        Self {
+            queue_len: [0; NUM_PRIORITIES],
+            queue_max: [0; NUM_PRIORITIES],
+            queue_area: [0; NUM_PRIORITIES],
+            first_tick: 0,
+            last_change: 0,
+            latency_hist: [0; LATENCY_BUCKETS],
+            latency_max: 0,
+            latency_sum: 0,
+            latency_count: 0,
+            window_start: 0,
+            window_switches: 0,
+            switch_rate: 0,
+            preempt_deferred: 0,
+            preempt_forced: 0,
+        }
+    }
+
+    /// Account for the time the queues spent at their current lengths
+    fn accumulate(&mut self, now: u32) {
+        if self.first_tick == 0 {
+            self.first_tick = now.max(1);
+            self.last_change = now;
+            return;
+        }
+        let held = now.wrapping_sub(self.last_change) as u64;
+        if held > 0 {
+            for (area, &len) in self.queue_area.iter_mut().zip(self.queue_len.iter()) {
+                *area += len as u64 * held;
+            }
+        }
+        self.last_change = now;
+    }
+
+    /// A task was added to (+1) or removed from (-1) a run queue
+    pub fn queue_changed(&mut self, priority: usize, delta: i32, now: u32) {
+        self.accumulate(now);
+        let len = &mut self.queue_len[priority];
+        *len = (*len as i32 + delta).max(0) as u32;
+        self.queue_max[priority] = self.queue_max[priority].max(*len);
+    }
+
+    /// Histogram bucket for a latency in ticks
+    fn bucket_for(ticks: u32) -> usize {
+        if ticks == 0 {
+            0
+        } else {
+            ((32 - ticks.leading_zeros()) as usize).min(LATENCY_BUCKETS - 1)
+        }
+    }
+
+    /// Record how long a task waited between Ready and Running
+    pub fn record_latency(&mut self, ticks: u32) {
+        self.latency_hist[Self::bucket_for(ticks)] += 1;
+        self.latency_max = self.latency_max.max(ticks);
+        self.latency_sum += ticks as u64;
+        self.latency_count += 1;
+    }
+
+    /// Count a context switch, rolling the rate window each second
+    pub fn record_switch(&mut self, now: u32) {
+        let hz = crate::arch::x86::pit::frequency().max(1);
+        let elapsed = now.wrapping_sub(self.window_start);
+        if elapsed >= hz {
+            // Scale in case the window ran long (no switches for a while)
+            self.switch_rate = (self.window_switches as u64 * hz as u64 / elapsed as u64) as u32;
+            self.window_start = now;
+            self.window_switches = 0;
+        }
+        self.window_switches += 1;
+    }
+
+    /// A `no_preempt` task kept the CPU for another tick
+    pub fn record_preempt_deferred(&mut self) {
+        self.preempt_deferred += 1;
+    }
+
+    /// A `no_preempt` task was preempted for overrunning
+    pub fn record_preempt_forced(&mut self) {
+        self.preempt_forced += 1;
+    }
+
+    /// Current length of a run queue
+    pub fn queue_len(&self, priority: usize) -> u32 {
+        self.queue_len[priority]
+    }
+
+    /// Longest a run queue has been
+    pub fn queue_max(&self, priority: usize) -> u32 {
+        self.queue_max[priority]
+    }
+
+    /// Time-weighted average queue length in hundredths
+    pub fn queue_avg_x100(&self, priority: usize, now: u32) -> u32 {
+        if self.first_tick == 0 {
+            return 0;
+        }
+        let span = now.wrapping_sub(self.first_tick) as u64;
+        if span == 0 {
+            return self.queue_len[priority] * 100;
+        }
+        let pending = now.wrapping_sub(self.last_change) as u64 * self.queue_len[priority] as u64;
+        ((self.queue_area[priority] + pending) * 100 / span) as u32
+    }
+
+    /// Worst scheduling latency in ticks
+    pub fn latency_max(&self) -> u32 {
+        self.latency_max
+    }
+
+    /// Mean scheduling latency in hundredths of a tick
+    pub fn latency_avg_x100(&self) -> u32 {
+        if self.latency_count == 0 {
+            0
+        } else {
+            (self.latency_sum * 100 / self.latency_count as u64) as u32
+        }
+    }
+
+    /// Latency histogram counts
+    pub fn latency_histogram(&self) -> &[u32; LATENCY_BUCKETS] {
+        &self.latency_hist
+    }
+
+    /// Context switches per second over the last full window
+    pub fn switch_rate(&self) -> u32 {
+        self.switch_rate
+    }
+
+    /// Ticks of preemption deferred by `no_preempt`
+    pub fn preempt_deferred(&self) -> u32 {
+        self.preempt_deferred
+    }
+
+    /// Preemptions forced on overrunning `no_preempt` tasks
+    pub fn preempt_forced(&self) -> u32 {
+        self.preempt_forced
+    }
+}
+
+/// Format the statistics as the text of `/proc/sched`
+pub fn report(out: &mut String) {
+    let now = crate::arch::x86::pit::ticks();
+    let hz = crate::arch::x86::pit::frequency().max(1);
+
+    unsafe {
+        let sched = &super::SCHEDULER;
+        let stats = sched.stats();
+
+        let _ = writeln!(out, "ready {}", sched.ready_count());
+        let _ = writeln!(out, "switches {} ({}/s)", sched.context_switches(), stats.switch_rate());
+        let _ = writeln!(out, "preempt deferred {} forced {}", stats.preempt_deferred(), stats.preempt_forced());
+
+        let online = super::online_cpus();
+        let _ = write!(out, "steals {}  ready per cpu:", sched.steals());
+        for cpu in (0..super::MAX_CPUS).filter(|&c| online & (1 << c) != 0) {
+            let _ = write!(out, " {}", sched.ready_on(cpu));
+        }
+        let _ = writeln!(out);
+
+        let _ = writeln!(out, "queue     len  max    avg");
+        for p in (0..NUM_PRIORITIES).rev() {
+            let avg = stats.queue_avg_x100(p, now);
+            let _ = writeln!(out, "{:<8} {:>4} {:>4} {:>3}.{:02}",
+                PRIORITY_NAMES[p], stats.queue_len(p), stats.queue_max(p), avg / 100, avg % 100);
+        }
+
+        let ms_per_tick = 1000 / hz;
+        let avg = stats.latency_avg_x100();
+        let _ = writeln!(out, "latency max {} ms avg {}.{:02} ticks ({} ms/tick)",
+            stats.latency_max() * ms_per_tick, avg / 100, avg % 100, ms_per_tick);
+
+        for (i, &count) in stats.latency_histogram().iter().enumerate() {
+            let lo = if i == 0 { 0 } else { 1u32 << (i - 1) };
+            if i == LATENCY_BUCKETS - 1 {
+                let _ = writeln!(out, "  {:>3}+    {}", lo, count);
+            } else {
+                let hi = if i == 0 { 0 } else { (1u32 << i) - 1 };
+                let _ = writeln!(out, "  {:>3}-{:<3} {}", lo, hi, count);
+            }
+        }
+    }
+}
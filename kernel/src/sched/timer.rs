@@ -0,0 +1,374 @@
+//! Timer Wheel
+//!
+//! One-shot kernel timers for timeouts (wait queues, key repeat, double
+//! clicks, frame pacing, retransmits): `add` arms one for a number of PIT
+//! ticks and returns a handle that `cancel` takes back. Both are O(1).
+//!
+//! Timers live in a hierarchical wheel of `LEVELS` levels of `SLOTS`
+//! buckets. Level 0 has a bucket per tick for the next 64 ticks, level 1
+//! a bucket per 64 ticks for the next 4096, and so on; a timer further
+//! out than the wheel reaches waits in the last level and is placed again
+//! when that bucket comes up. Whenever level 0 wraps, the next bucket of
+//! the level above is cascaded: its timers move down to finer buckets.
+//!
+//! The timer IRQ only raises the timer softirq; expired timers run there,
+//! with interrupts enabled, in the order they expire. Callbacks may arm
+//! and cancel timers (including ones due in the same tick).
+//!
+//! Entries come from a fixed pool of `MAX_TIMERS` and link into their
+//! bucket by index, so the wheel never allocates. A handle carries the
+//! entry's generation, which changes when the entry is reused, so a
+//! stale handle can't cancel someone else's timer. Counts are in
+//! /proc/timers.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::arch::x86::idt::without_interrupts;
+use crate::arch::x86::pit;
+
+/// Concurrent timers
+pub const MAX_TIMERS: usize = 4096;
+
+/// Bits of expiry time per level
+const LEVEL_BITS: u32 = 6;
+/// Buckets per level
+const SLOTS: usize = 1 << LEVEL_BITS;
+const SLOT_MASK: u32 = SLOTS as u32 - 1;
+/// Levels (reach: 2^24 ticks, about 46 hours at 100 Hz)
+const LEVELS: usize = 4;
+
+/// List of timers taken from a level 0 bucket and being run
+const FIRING: usize = LEVELS * SLOTS;
+/// Bucket lists plus the firing list
+const LISTS: usize = FIRING + 1;
+
+/// No entry
+const NIL: u16 = u16::MAX;
+
+/// A timer's expiry callback and its argument
+pub type Callback = fn(u32);
+
+/// Identifies an armed timer (stale once it fires or is cancelled)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle {
+    index: u16,
+    generation: u16,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    /// Tick at which the timer fires
+    expires: u32,
+    /// None while the entry is free
+    callback: Option<Callback>,
+    arg: u32,
+    /// List links (entry indices)
+    next: u16,
+    prev: u16,
+    /// List the entry is on (`FIRING` or a bucket)
+    list: u16,
+    /// Bumped each time the entry is freed
+    generation: u16,
+}
+
+impl Entry {
+    const EMPTY: Self = Self {
+        expires: 0,
+        callback: None,
+        arg: 0,
+        next: NIL,
+        prev: NIL,
+        list: NIL,
+        generation: 0,
+    };
+}
+
+struct Wheel {
+    entries: [Entry; MAX_TIMERS],
+    heads: [u16; LISTS],
+    /// Freed entries, linked through `next`
+    free: u16,
+    /// Entries never used yet (`entries[fresh..]`)
+    fresh: u16,
+    /// Next tick to process
+    now: u32,
+    /// Timers on each level
+    level_counts: [u32; LEVELS],
+    max_armed: u32,
+    fired: u32,
+    cancelled: u32,
+    cascaded: u32,
+}
+
+static mut WHEEL: Wheel = Wheel {
+    entries: [Entry::EMPTY; MAX_TIMERS],
+    heads: [NIL; LISTS],
+    free: NIL,
+    fresh: 0,
+    now: 0,
+    level_counts: [0; LEVELS],
+    max_armed: 0,
+    fired: 0,
+    cancelled: 0,
+    cascaded: 0,
+};
+
+/// Timers armed (read by the timer IRQ without touching the wheel)
+static ARMED: AtomicU32 = AtomicU32::new(0);
+
+impl Wheel {
+    // =========================================================================
+    // Lists
+    // =========================================================================
+
+    fn link(&mut self, index: u16, list: usize) {
+        let head = self.heads[list];
+        let entry = &mut self.entries[index as usize];
+        entry.next = head;
+        entry.prev = NIL;
+        entry.list = list as u16;
+        if head != NIL {
+            self.entries[head as usize].prev = index;
+        }
+        self.heads[list] = index;
+        if list < FIRING {
+            self.level_counts[list / SLOTS] += 1;
+        }
+    }
+
+    fn unlink(&mut self, index: u16) {
+        let Entry { next, prev, list, .. } = self.entries[index as usize];
+        match prev {
+            NIL => self.heads[list as usize] = next,
+            prev => self.entries[prev as usize].next = next,
+        }
+        if next != NIL {
+            self.entries[next as usize].prev = prev;
+        }
+        if (list as usize) < FIRING {
+            self.level_counts[list as usize / SLOTS] -= 1;
+        }
+        let entry = &mut self.entries[index as usize];
+        entry.next = NIL;
+        entry.prev = NIL;
+        entry.list = NIL;
+    }
+
+    /// Bucket for a timer expiring at `expires`
+    fn bucket(&self, expires: u32) -> usize {
+        let delta = expires.wrapping_sub(self.now);
+        // Already due (callers lagging behind the wheel): next tick
+        if delta as i32 <= 0 {
+            return (self.now & SLOT_MASK) as usize;
+        }
+        for level in 0..LEVELS {
+            let shift = LEVEL_BITS * level as u32;
+            if delta < 1 << (shift + LEVEL_BITS) {
+                return level * SLOTS + ((expires >> shift) & SLOT_MASK) as usize;
+            }
+        }
+        // Beyond the wheel: the furthest bucket, placed again from there
+        let shift = LEVEL_BITS * (LEVELS as u32 - 1);
+        let furthest = self.now.wrapping_add((1 << (shift + LEVEL_BITS)) - 1);
+        (LEVELS - 1) * SLOTS + ((furthest >> shift) & SLOT_MASK) as usize
+    }
+
+    // =========================================================================
+    // Arming
+    // =========================================================================
+
+    fn alloc(&mut self) -> Option<u16> {
+        if self.free != NIL {
+            let index = self.free;
+            self.free = self.entries[index as usize].next;
+            return Some(index);
+        }
+        if (self.fresh as usize) < MAX_TIMERS {
+            self.fresh += 1;
+            return Some(self.fresh - 1);
+        }
+        None
+    }
+
+    fn release(&mut self, index: u16) {
+        let entry = &mut self.entries[index as usize];
+        entry.callback = None;
+        entry.generation = entry.generation.wrapping_add(1);
+        entry.next = self.free;
+        self.free = index;
+        ARMED.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn add(&mut self, expires: u32, callback: Callback, arg: u32) -> Option<TimerHandle> {
+        let index = self.alloc()?;
+        let entry = &mut self.entries[index as usize];
+        entry.expires = expires;
+        entry.callback = Some(callback);
+        entry.arg = arg;
+        let generation = entry.generation;
+        self.link(index, self.bucket(expires));
+
+        let armed = ARMED.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_armed = self.max_armed.max(armed);
+        Some(TimerHandle { index, generation })
+    }
+
+    /// The entry a handle refers to, if that timer is still armed
+    fn lookup(&self, handle: TimerHandle) -> Option<u16> {
+        let entry = self.entries.get(handle.index as usize)?;
+        (entry.callback.is_some() && entry.generation == handle.generation).then_some(handle.index)
+    }
+
+    fn cancel(&mut self, handle: TimerHandle) -> bool {
+        match self.lookup(handle) {
+            Some(index) => {
+                self.unlink(index);
+                self.release(index);
+                self.cancelled += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // =========================================================================
+    // Expiry
+    // =========================================================================
+
+    /// Move a bucket's timers down to where they now belong
+    fn cascade(&mut self, list: usize) {
+        let mut index = self.heads[list];
+        while index != NIL {
+            let next = self.entries[index as usize].next;
+            self.unlink(index);
+            self.link(index, self.bucket(self.entries[index as usize].expires));
+            self.cascaded += 1;
+            index = next;
+        }
+    }
+
+    /// Advance to the next tick: cascade if level 0 wraps and move the
+    /// level 0 bucket for this tick to the firing list
+    fn advance(&mut self) {
+        let slot = (self.now & SLOT_MASK) as usize;
+        if slot == 0 {
+            for level in 1..LEVELS {
+                let index = (self.now >> (LEVEL_BITS * level as u32)) & SLOT_MASK;
+                self.cascade(level * SLOTS + index as usize);
+                if index != 0 {
+                    break;
+                }
+            }
+        }
+
+        let mut index = self.heads[slot];
+        while index != NIL {
+            let next = self.entries[index as usize].next;
+            self.unlink(index);
+            self.link(index, FIRING);
+            index = next;
+        }
+        self.now = self.now.wrapping_add(1);
+    }
+
+    /// Take one timer off the firing list: its callback and argument
+    fn next_expired(&mut self) -> Option<(Callback, u32)> {
+        let index = self.heads[FIRING];
+        if index == NIL {
+            return None;
+        }
+        let entry = self.entries[index as usize];
+        self.unlink(index);
+        self.release(index);
+        self.fired += 1;
+        Some((entry.callback?, entry.arg))
+    }
+}
+
+fn wheel() -> &'static mut Wheel {
+    unsafe { &mut *core::ptr::addr_of_mut!(WHEEL) }
+}
+
+// =============================================================================
+// API
+// =============================================================================
+
+/// Start the wheel at the current tick
+pub fn init() {
+    without_interrupts(|| wheel().now = pit::ticks());
+}
+
+/// Arm a timer to call `callback(arg)` after `ticks` timer ticks (at
+/// least one)
+pub fn add(ticks: u32, callback: Callback, arg: u32) -> Result<TimerHandle, &'static str> {
+    let expires = pit::ticks().wrapping_add(ticks.max(1));
+    without_interrupts(|| wheel().add(expires, callback, arg)).ok_or("Too many timers")
+}
+
+/// Arm a timer in milliseconds (rounded up to whole ticks)
+pub fn add_ms(ms: u32, callback: Callback, arg: u32) -> Result<TimerHandle, &'static str> {
+    let hz = pit::frequency().max(1) as u64;
+    let ticks = (ms as u64 * hz).div_ceil(1000).min(u32::MAX as u64) as u32;
+    add(ticks, callback, arg)
+}
+
+/// Disarm a timer; false if it already fired or was cancelled
+pub fn cancel(handle: TimerHandle) -> bool {
+    without_interrupts(|| wheel().cancel(handle))
+}
+
+/// Is the timer still waiting to fire?
+pub fn is_pending(handle: TimerHandle) -> bool {
+    without_interrupts(|| wheel().lookup(handle).is_some())
+}
+
+/// Timer IRQ: raise the timer softirq if anything is armed
+pub fn tick() {
+    if ARMED.load(Ordering::Relaxed) != 0 {
+        crate::softirq::raise(crate::softirq::Softirq::Timer);
+    }
+}
+
+/// Catch the wheel up with the clock and run what expired (timer softirq)
+pub fn run() {
+    let target = pit::ticks();
+    loop {
+        // One tick at a time so callbacks see timers in expiry order
+        let more = without_interrupts(|| {
+            let wheel = wheel();
+            if ARMED.load(Ordering::Relaxed) == 0 {
+                wheel.now = target.wrapping_add(1);
+                return false;
+            }
+            if wheel.now.wrapping_sub(target) as i32 > 0 {
+                return false;
+            }
+            wheel.advance();
+            true
+        });
+        while let Some((callback, arg)) = without_interrupts(|| wheel().next_expired()) {
+            callback(arg);
+        }
+        if !more {
+            break;
+        }
+    }
+}
+
+// =============================================================================
+// Reporting
+// =============================================================================
+
+/// Counts for /proc/timers
+pub fn report(out: &mut String) {
+    let (levels, max_armed, fired, cancelled, cascaded) = without_interrupts(|| {
+        let w = wheel();
+        (w.level_counts, w.max_armed, w.fired, w.cancelled, w.cascaded)
+    });
+    let _ = writeln!(out, "armed {} max {} of {}", ARMED.load(Ordering::Relaxed), max_armed, MAX_TIMERS);
+    let _ = writeln!(out, "fired {} cancelled {} cascaded {}", fired, cancelled, cascaded);
+    for (level, count) in levels.iter().enumerate() {
+        let _ = writeln!(out, "level {} ({} ticks/bucket) {}", level, 1u32 << (LEVEL_BITS * level as u32), count);
+    }
+}
@@ -0,0 +1,235 @@
+//! Cooperative Waiting
+//!
+//! For driver code that has to wait on hardware (a PS/2 byte, a PLL
+//! locking) without spinning the CPU. `yield_until` checks a condition
+//! until it holds or a `Deadline` passes; `WaitQueue::wait_until` does the
+//! same but sleeps until `wake_all` instead of checking every tick.
+//!
+//! What happens between checks depends on the caller:
+//!
+//! - for the first `SPIN_US` it spins, since most devices answer in
+//!   microseconds and a tick is 10 ms;
+//! - a kernel task blocks, with a timer-wheel timer to wake it for the
+//!   next check (or the deadline), so other tasks run;
+//! - outside task context (driver init runs from `kernel_main`) the CPU
+//!   halts until the next interrupt, so IRQs and softirqs carry on;
+//! - with interrupts disabled nothing can end a halt and the PIT doesn't
+//!   advance, so it polls with `io_wait` (about 1 µs) between checks and
+//!   gives up after one poll per microsecond of the timeout.
+//!
+//! User programs never block here: their syscalls return to `exec`,
+//! which has its own blocking, so they are treated as outside task
+//! context.
+
+use core::cell::UnsafeCell;
+use crate::arch::x86::{io, pit};
+use crate::mm::intrusive::{IntrusiveList, IntrusiveNode};
+use super::spinlock::RawSpinLock;
+use super::{timer, Task, TaskState, SCHEDULER};
+
+/// Spin this long before yielding
+const SPIN_US: u64 = 100;
+
+/// Serializes wakeups, so a timer and `wake_all` racing to wake the same
+/// task queue it once
+static WAKE_LOCK: RawSpinLock = RawSpinLock::new();
+
+/// When a wait gives up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline {
+    /// `time::now_ns` to give up at (u64::MAX = never)
+    at_ns: u64,
+}
+
+impl Deadline {
+    /// Wait as long as it takes
+    pub const NEVER: Self = Self { at_ns: u64::MAX };
+
+    pub fn after_us(us: u64) -> Self {
+        Self { at_ns: crate::time::now_ns().saturating_add(us.saturating_mul(1000)) }
+    }
+
+    pub fn after_ms(ms: u32) -> Self {
+        Self::after_us(ms as u64 * 1000)
+    }
+
+    pub fn has_passed(&self) -> bool {
+        crate::time::now_ns() >= self.at_ns
+    }
+
+    /// Microseconds left (0 once passed)
+    pub fn remaining_us(&self) -> u64 {
+        self.at_ns.saturating_sub(crate::time::now_ns()) / 1000
+    }
+
+    /// Whole timer ticks until the deadline (at least one)
+    fn remaining_ticks(&self) -> u32 {
+        let hz = pit::frequency().max(1) as u64;
+        (self.remaining_us().saturating_mul(hz).div_ceil(1_000_000)).clamp(1, u32::MAX as u64) as u32
+    }
+}
+
+/// Wait until `done` returns true or `deadline` passes; true if `done` did
+///
+/// `done` is called at least once, and again after each pause.
+pub fn yield_until(deadline: Deadline, done: impl FnMut() -> bool) -> bool {
+    wait(None, deadline, done)
+}
+
+/// Wait for `us` microseconds, letting other work run
+pub fn sleep_us(us: u64) {
+    yield_until(Deadline::after_us(us), || false);
+}
+
+/// Node accessor for wait queues
+fn wait_queue_node(task: &Task) -> &IntrusiveNode {
+    &task.wait_queue_node
+}
+
+/// Tasks sleeping until an event (`wake_all`)
+///
+/// Must stay at a stable address while tasks wait on it; embed it in
+/// statics or heap objects.
+pub struct WaitQueue {
+    lock: RawSpinLock,
+    waiters: UnsafeCell<IntrusiveList<Task, fn(&Task) -> &IntrusiveNode>>,
+}
+
+// Safety: `waiters` is only touched under `lock` with interrupts disabled
+unsafe impl Sync for WaitQueue {}
+unsafe impl Send for WaitQueue {}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            lock: RawSpinLock::new(),
+            waiters: UnsafeCell::new(IntrusiveList::new(wait_queue_node as fn(&Task) -> &IntrusiveNode)),
+        }
+    }
+
+    /// Wait until `done` returns true or `deadline` passes, sleeping
+    /// between checks until `wake_all`; true if `done` did
+    ///
+    /// Outside task context this halts between checks instead, so the
+    /// waker must be an interrupt (or the deadline).
+    pub fn wait_until(&self, deadline: Deadline, done: impl FnMut() -> bool) -> bool {
+        wait(Some(self), deadline, done)
+    }
+
+    /// Wake every task waiting (they check their condition again)
+    pub fn wake_all(&self) {
+        self.lock.with(|| unsafe {
+            let waiters = &mut *self.waiters.get();
+            while let Some(task) = waiters.pop_front() {
+                wake_task(task.as_ptr());
+            }
+        });
+    }
+
+    /// Are tasks waiting?
+    pub fn has_waiters(&self) -> bool {
+        self.lock.with(|| unsafe { !(*self.waiters.get()).is_empty() })
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shared loop of `yield_until` and `WaitQueue::wait_until`
+fn wait(queue: Option<&WaitQueue>, deadline: Deadline, mut done: impl FnMut() -> bool) -> bool {
+    let spin_until = Deadline::after_us(SPIN_US).min(deadline);
+    let mut polls = deadline.remaining_us();
+    loop {
+        if done() {
+            return true;
+        }
+        if deadline.has_passed() {
+            return false;
+        }
+        if !crate::arch::interrupts_enabled() {
+            if polls == 0 {
+                return false;
+            }
+            polls -= 1;
+            unsafe { io::io_wait() };
+        } else if !spin_until.has_passed() {
+            core::hint::spin_loop();
+        } else if let Some(task) = blockable_task() {
+            // Without a queue nothing else wakes us: check every tick
+            let ticks = match queue {
+                None => 1,
+                Some(_) if deadline == Deadline::NEVER => 0,
+                Some(_) => deadline.remaining_ticks(),
+            };
+            block(task, queue, ticks);
+        } else {
+            crate::arch::wait_for_interrupt();
+        }
+    }
+}
+
+/// Running kernel task that may block (not idle, not a user program)
+fn blockable_task() -> Option<*mut Task> {
+    let task = unsafe { (*core::ptr::addr_of!(SCHEDULER)).current()? };
+    let idle = super::idle_task(super::current_cpu() as usize);
+    (task != idle && !crate::exec::is_running()).then_some(task)
+}
+
+/// Block `task` (the current one) until woken from `queue` or, unless
+/// `ticks` is 0, by a timer after `ticks`
+fn block(task: *mut Task, queue: Option<&WaitQueue>, ticks: u32) {
+    // Interrupts stay off until the switch, so the timer can't fire
+    // before the task is off the CPU
+    crate::arch::without_interrupts(|| unsafe {
+        let t = &mut *task;
+        t.state = TaskState::Blocked;
+        if let Some(queue) = queue {
+            queue.lock.with(|| (*queue.waiters.get()).push_back(t));
+        }
+        let timer = match ticks {
+            0 => None,
+            ticks => timer::add(ticks, wake_timer, task as u32).ok(),
+        };
+        if ticks != 0 && timer.is_none() {
+            // Out of timers: poll again without sleeping
+            t.state = TaskState::Running;
+        } else {
+            super::schedule();
+        }
+
+        if let Some(timer) = timer {
+            timer::cancel(timer);
+        }
+        if let Some(queue) = queue {
+            queue.lock.with(|| {
+                if t.wait_queue_node.is_linked() {
+                    (*queue.waiters.get()).remove(t);
+                }
+            });
+        }
+        t.state = TaskState::Running;
+    });
+}
+
+/// Timer callback: wake the task whose pointer is `arg`
+fn wake_timer(arg: u32) {
+    unsafe { wake_task(arg as *mut Task) };
+}
+
+/// Make a blocked task runnable (no-op if something woke it already)
+unsafe fn wake_task(task: *mut Task) {
+    let woken = WAKE_LOCK.with(|| {
+        let t = &mut *task;
+        if t.state != TaskState::Blocked {
+            return false;
+        }
+        t.state = TaskState::Ready;
+        true
+    });
+    if woken {
+        (*core::ptr::addr_of_mut!(SCHEDULER)).enqueue(&*task);
+    }
+}
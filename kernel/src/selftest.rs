@@ -0,0 +1,188 @@
+//! Kernel Self-Tests
+//!
+//! Checks of kernel code that host `cargo test` can't reach (it needs the
+//! kernel's allocator, its locks, or the CPU in protected mode). Each test
+//! returns what went wrong, and `run` reports every result:
+//!
+//! - `selftest [name]` in a terminal runs them all, or those whose name
+//!   starts with `name`
+//! - the `selftest` boot option runs them all at the end of boot and
+//!   prints the results to COM1 as well, so
+//!   `make run-headless CMDLINE=selftest` is a test run
+//!
+//! Tests leave the kernel as they found it: they work on their own
+//! filesystem instances, locks and buffers, never on the mounted ones.
+
+use alloc::string::String;
+use core::fmt::Write;
+use crate::fs::{self, path, Filesystem, FsError, OpenFlags, MAX_FILENAME, MAX_PATH};
+use crate::sched::mutex;
+
+/// A test's verdict: Err says what went wrong
+type Outcome = Result<(), &'static str>;
+
+/// One self-test
+pub struct Test {
+    pub name: &'static str,
+    run: fn() -> Outcome,
+}
+
+/// Registered tests
+static TESTS: &[Test] = &[
+    Test { name: "path_max_filename", run: path_max_filename },
+    Test { name: "path_max_path", run: path_max_path },
+    Test { name: "path_climb_back", run: path_climb_back },
+    Test { name: "ramfs_max_names", run: ramfs_max_names },
+    Test { name: "mutex_nested", run: mutex::test_nested },
+    Test { name: "mutex_reentrant", run: mutex::test_reentrant },
+    Test { name: "mutex_nested_inheritance", run: mutex::test_nested_inheritance },
+    Test { name: "mutex_chain_boost", run: mutex::test_chain_boost },
+    Test { name: "exec_entry_frame", run: crate::exec::test_entry_frame },
+    Test { name: "exec_ring_transitions", run: crate::exec::test_ring_transitions },
+];
+
+/// Run the tests whose name starts with `filter` (all for ""), reporting
+/// each; returns (passed, failed)
+pub fn run(filter: &str, mut report: impl FnMut(&str, Outcome)) -> (usize, usize) {
+    let (mut passed, mut failed) = (0, 0);
+    for test in TESTS.iter().filter(|t| t.name.starts_with(filter)) {
+        let outcome = (test.run)();
+        match outcome {
+            Ok(()) => passed += 1,
+            Err(_) => failed += 1,
+        }
+        report(test.name, outcome);
+    }
+    (passed, failed)
+}
+
+/// Run every test at boot, printing the results to `writer` and COM1
+pub fn run_at_boot(writer: &mut dyn Write) {
+    let serial = unsafe { &mut *core::ptr::addr_of_mut!(crate::drivers::serial::SERIAL) };
+    let mut line = String::new();
+    let mut print = |line: &str| {
+        let _ = writeln!(writer, "{}", line);
+        if serial.is_present() {
+            let _ = writeln!(serial, "{}", line);
+        }
+    };
+    let (passed, failed) = run("", |name, outcome| {
+        line.clear();
+        let _ = match outcome {
+            Ok(()) => write!(line, "[TEST] {} ok", name),
+            Err(e) => write!(line, "[TEST] {} FAILED: {}", name, e),
+        };
+        print(&line);
+    });
+    line.clear();
+    let _ = write!(line, "[TEST] {} passed, {} failed", passed, failed);
+    print(&line);
+}
+
+/// Fail with `message` unless `condition` holds
+pub(crate) fn check(condition: bool, message: &'static str) -> Outcome {
+    if condition { Ok(()) } else { Err(message) }
+}
+
+/// A name of `len` bytes, distinct per `tag`
+fn name(tag: char, len: usize) -> String {
+    core::iter::repeat(tag).take(len).collect()
+}
+
+/// An absolute path of exactly `len` bytes, several components deep
+fn deep_path(len: usize) -> String {
+    let mut out = String::new();
+    let mut tag = b'a';
+    while out.len() < len {
+        let room = len - out.len() - 1;
+        // Never leave a single byte, which couldn't hold "/x"
+        let take = match room.min(MAX_PATH / 4 - 1) {
+            n if room - n == 1 => n - 1,
+            n => n,
+        };
+        out.push('/');
+        out.push_str(&name(tag as char, take));
+        tag += 1;
+    }
+    out
+}
+
+// =============================================================================
+// Path Resolution
+// =============================================================================
+
+fn path_max_filename() -> Outcome {
+    let longest = alloc::format!("/{}", name('n', MAX_FILENAME));
+    let resolved = path::resolve("/", &longest).map_err(|_| "MAX_FILENAME name refused")?;
+    check(*resolved == *longest, "MAX_FILENAME name changed")?;
+
+    let relative = path::resolve("/", &name('n', MAX_FILENAME)).map_err(|_| "relative name refused")?;
+    check(*relative == *longest, "relative name joined wrongly")?;
+
+    let over = alloc::format!("/{}", name('n', MAX_FILENAME + 1));
+    check(matches!(path::resolve("/", &over), Err(FsError::NameTooLong)), "overlong name accepted")
+}
+
+fn path_max_path() -> Outcome {
+    let longest = deep_path(MAX_PATH);
+    let resolved = path::resolve("/", &longest).map_err(|_| "MAX_PATH path refused")?;
+    check(*resolved == *longest, "MAX_PATH path changed")?;
+
+    // The limit is on the joined result
+    let (cwd, rest) = longest.split_at(longest[1..].find('/').ok_or("test path too shallow")? + 1);
+    let joined = path::resolve(cwd, &rest[1..]).map_err(|_| "MAX_PATH path refused relative to cwd")?;
+    check(*joined == *longest, "MAX_PATH path joined wrongly")?;
+
+    let over = deep_path(MAX_PATH + 1);
+    check(matches!(path::resolve("/", &over), Err(FsError::NameTooLong)), "overlong path accepted")?;
+    check(matches!(path::resolve(&longest, "x"), Err(FsError::NameTooLong)), "overlong join accepted")
+}
+
+fn path_climb_back() -> Outcome {
+    // A cwd at the limit and a relative path that climbs back out of it:
+    // joined they are twice MAX_PATH, but only the result has to fit
+    let cwd = deep_path(MAX_PATH);
+    let climb = "../".repeat(cwd.matches('/').count());
+    let target = name('z', MAX_PATH - climb.len());
+    let relative = alloc::format!("{}{}", climb, target);
+
+    let resolved = path::resolve(&cwd, &relative).map_err(|_| "climbing path refused")?;
+    check(resolved.len() == 1 + target.len() && resolved.starts_with("/z"), "climbing path resolved wrongly")?;
+
+    let root = path::resolve(&cwd, &climb).map_err(|_| "climb to / refused")?;
+    check(&*root == "/", ".. went past the root")
+}
+
+// =============================================================================
+// Filesystems
+// =============================================================================
+
+fn ramfs_max_names() -> Outcome {
+    let mut ramfs = fs::ramfs::RamFs::new();
+    ramfs.mount().map_err(|_| "mount failed")?;
+
+    // Directories down to a path one name short of MAX_PATH...
+    let dir = deep_path(MAX_PATH - 2);
+    let parents = dir.match_indices('/').skip(1).map(|(i, _)| i);
+    for cut in parents.chain(core::iter::once(dir.len())) {
+        ramfs.mkdir(&dir[..cut]).map_err(|_| "mkdir of a long name failed")?;
+    }
+
+    // ...a file that takes it to the limit...
+    let file = alloc::format!("{}/f", dir);
+    check(file.len() == MAX_PATH, "test path isn't MAX_PATH long")?;
+    let handle = ramfs.open(&file, OpenFlags::read_write().with_create()).map_err(|_| "create at MAX_PATH failed")?;
+    let written = ramfs.write(handle, b"deep").map_err(|_| "write at MAX_PATH failed")?;
+    ramfs.close(handle).map_err(|_| "close failed")?;
+    check(written == 4, "short write at MAX_PATH")?;
+    check(matches!(ramfs.stat(&file), Ok(meta) if meta.size == 4), "file at MAX_PATH not found")?;
+
+    // ...and a file whose name is MAX_FILENAME long
+    let longest = alloc::format!("/{}", name('n', MAX_FILENAME));
+    let handle = ramfs.open(&longest, OpenFlags::read_write().with_create()).map_err(|_| "MAX_FILENAME create failed")?;
+    ramfs.close(handle).map_err(|_| "close failed")?;
+    check(ramfs.stat(&longest).is_ok(), "MAX_FILENAME file not found")?;
+
+    let over = alloc::format!("/{}", name('n', MAX_FILENAME + 1));
+    check(ramfs.open(&over, OpenFlags::read_write().with_create()).is_err(), "overlong name created")
+}
@@ -0,0 +1,518 @@
+//! System Settings
+//!
+//! Persists user preferences (theme, keymap, mouse sensitivity, display
+//! resolution, LCD panel scaling, dithering, taskbar, UTC offset, keyboard
+//! accessibility) to `/boot/settings.cfg`
+//! as simple `key=value` lines. Settings are written whenever they change and restored at boot.
+//!
+//! The file also holds the desktop session: the built-in windows open at
+//! the last shutdown, one `window=` line each from back to front, which
+//! the desktop recreates at the next boot.
+//!
+//! The file carries a format version and a trailing checksum line. A
+//! missing, newer-versioned, or corrupted file falls back to defaults;
+//! unknown keys and individually invalid values are ignored.
+
+use alloc::string::String;
+use alloc::vec;
+use core::fmt::Write;
+
+use crate::drivers::accessibility;
+use crate::drivers::ati_rage::PanelScaling;
+use crate::fs::{OpenFlags, vfs::VFS};
+use crate::gui::WindowKind;
+use crate::gui::dither::{self, DitherMode};
+use crate::gui::theme::{self, Theme};
+
+/// Settings file location
+pub const SETTINGS_PATH: &str = "/boot/settings.cfg";
+
+/// Current settings format version
+pub const SETTINGS_VERSION: u32 = 1;
+
+/// Largest settings file we will read
+const MAX_SETTINGS_SIZE: usize = 4096;
+
+/// Supported keyboard layouts
+pub const KEYMAPS: [&str; 1] = ["us"];
+
+/// Mouse sensitivity range, in half steps of the pointer's own movement
+/// (2 = 1:1, 4 = twice as fast) for the PS/2 mouse and the touchpad alike
+pub const MIN_SENSITIVITY: u32 = 1;
+pub const MAX_SENSITIVITY: u32 = 10;
+
+/// Windows a saved session can hold
+pub const MAX_SESSION_WINDOWS: usize = 8;
+
+/// A window to recreate at boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionWindow {
+    pub kind: WindowKind,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SessionWindow {
+    /// `kind,x,y,width,height` as in a `window=` line
+    fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.split(',').map(str::trim);
+        let kind = WindowKind::from_name(fields.next()?)?;
+        let x = fields.next()?.parse().ok()?;
+        let y = fields.next()?.parse().ok()?;
+        let width = fields.next()?.parse().ok()?;
+        let height = fields.next()?.parse().ok()?;
+        if fields.next().is_some() || width == 0 || height == 0 {
+            return None;
+        }
+        Some(Self { kind, x, y, width, height })
+    }
+}
+
+/// Taskbar placement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskbarPosition {
+    Top,
+    Bottom,
+}
+
+impl TaskbarPosition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Top => "top",
+            Self::Bottom => "bottom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "top" => Some(Self::Top),
+            "bottom" => Some(Self::Bottom),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of loading the settings file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStatus {
+    /// File read and applied
+    Loaded,
+    /// No file (or no filesystem); defaults in use
+    Missing,
+    /// File failed validation; defaults in use
+    Corrupt,
+    /// File written by a newer format; defaults in use
+    UnsupportedVersion,
+}
+
+/// System settings
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// Theme name (one of `Theme::NAMES`)
+    pub theme: &'static str,
+    /// Keyboard layout (one of `KEYMAPS`)
+    pub keymap: &'static str,
+    /// Pointer speed (MIN_SENSITIVITY..=MAX_SENSITIVITY, 2 = 1:1)
+    pub mouse_sensitivity: u32,
+    /// Preferred display resolution (applied by the bootloader)
+    pub resolution: (u32, u32),
+    /// Show the taskbar
+    pub taskbar_visible: bool,
+    /// Taskbar placement
+    pub taskbar_position: TaskbarPosition,
+    /// Internal LCD expansion and centering
+    pub panel_scaling: PanelScaling,
+    /// Dithering for 8/16bpp displays
+    pub dither: DitherMode,
+    /// Local time zone, in minutes east of UTC
+    pub utc_offset: i32,
+    /// Sticky modifier keys
+    pub sticky_keys: bool,
+    /// Slow keys on
+    pub slow_keys: bool,
+    /// Slow keys hold time in ms
+    pub slow_keys_ms: u32,
+    /// Windows open at the last shutdown, back to front
+    pub session: [Option<SessionWindow>; MAX_SESSION_WINDOWS],
+}
+
+impl Settings {
+    /// Built-in defaults
+    pub const fn defaults() -> Self {
+        Self {
+            theme: "plan9",
+            keymap: "us",
+            mouse_sensitivity: 2,
+            resolution: (800, 600),
+            taskbar_visible: true,
+            taskbar_position: TaskbarPosition::Bottom,
+            panel_scaling: PanelScaling::DEFAULT,
+            dither: DitherMode::Ordered,
+            utc_offset: 0,
+            sticky_keys: false,
+            slow_keys: false,
+            slow_keys_ms: accessibility::DEFAULT_SLOW_KEYS_MS,
+            session: [None; MAX_SESSION_WINDOWS],
+        }
+    }
+
+    /// Serialize to the on-disk format (including checksum line)
+    pub fn serialize(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# Rustacean OS settings");
+        let _ = writeln!(out, "version={}", SETTINGS_VERSION);
+        let _ = writeln!(out, "theme={}", self.theme);
+        let _ = writeln!(out, "keymap={}", self.keymap);
+        let _ = writeln!(out, "mouse_sensitivity={}", self.mouse_sensitivity);
+        let _ = writeln!(out, "resolution={}x{}", self.resolution.0, self.resolution.1);
+        let _ = writeln!(out, "taskbar_visible={}", self.taskbar_visible);
+        let _ = writeln!(out, "taskbar_position={}", self.taskbar_position.as_str());
+        let _ = writeln!(out, "panel_expand={}", self.panel_scaling.expand_name());
+        let _ = writeln!(out, "panel_center={}", self.panel_scaling.center);
+        let _ = writeln!(out, "dither={}", self.dither.as_str());
+        let _ = writeln!(out, "utc_offset={}", crate::time::format_utc_offset(self.utc_offset));
+        let _ = writeln!(out, "sticky_keys={}", self.sticky_keys);
+        let _ = writeln!(out, "slow_keys={}", self.slow_keys);
+        let _ = writeln!(out, "slow_keys_ms={}", self.slow_keys_ms);
+        for w in self.session.iter().flatten() {
+            let _ = writeln!(out, "window={},{},{},{},{}", w.kind.as_str(), w.x, w.y, w.width, w.height);
+        }
+
+        let sum = checksum(out.as_bytes());
+        let _ = writeln!(out, "checksum={:08x}", sum);
+        out
+    }
+
+    /// Parse the on-disk format
+    pub fn parse(text: &str) -> Result<Self, LoadStatus> {
+        // The checksum line must be last and cover everything before it
+        let body_end = text.rfind("checksum=").ok_or(LoadStatus::Corrupt)?;
+        let stored = u32::from_str_radix(text[body_end + 9..].trim(), 16)
+            .map_err(|_| LoadStatus::Corrupt)?;
+        if checksum(&text.as_bytes()[..body_end]) != stored {
+            return Err(LoadStatus::Corrupt);
+        }
+
+        let mut settings = Self::defaults();
+        let mut version = None;
+        let mut windows = 0;
+
+        for line in text[..body_end].lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => return Err(LoadStatus::Corrupt),
+            };
+
+            match key {
+                "version" => version = value.parse::<u32>().ok(),
+                "theme" => {
+                    if let Some(name) = Theme::NAMES.iter().find(|&&n| n == value) {
+                        settings.theme = name;
+                    }
+                }
+                "keymap" => {
+                    if let Some(name) = KEYMAPS.iter().find(|&&n| n == value) {
+                        settings.keymap = name;
+                    }
+                }
+                "mouse_sensitivity" => {
+                    if let Ok(v) = value.parse::<u32>() {
+                        if (MIN_SENSITIVITY..=MAX_SENSITIVITY).contains(&v) {
+                            settings.mouse_sensitivity = v;
+                        }
+                    }
+                }
+                "resolution" => {
+                    if let Some((w, h)) = value.split_once('x') {
+                        if let (Ok(w), Ok(h)) = (w.parse::<u32>(), h.parse::<u32>()) {
+                            if w > 0 && h > 0 {
+                                settings.resolution = (w, h);
+                            }
+                        }
+                    }
+                }
+                "taskbar_visible" => {
+                    if let Ok(v) = value.parse::<bool>() {
+                        settings.taskbar_visible = v;
+                    }
+                }
+                "taskbar_position" => {
+                    if let Some(pos) = TaskbarPosition::from_name(value) {
+                        settings.taskbar_position = pos;
+                    }
+                }
+                "panel_expand" => {
+                    let center = settings.panel_scaling.center;
+                    if let Some(scaling) = PanelScaling::from_expand_name(value, center) {
+                        settings.panel_scaling = scaling;
+                    }
+                }
+                "panel_center" => {
+                    if let Ok(v) = value.parse::<bool>() {
+                        settings.panel_scaling.center = v;
+                    }
+                }
+                "dither" => {
+                    if let Some(mode) = DitherMode::from_name(value) {
+                        settings.dither = mode;
+                    }
+                }
+                "utc_offset" => {
+                    if let Some(offset) = crate::time::parse_utc_offset(value) {
+                        settings.utc_offset = offset;
+                    }
+                }
+                "sticky_keys" => {
+                    if let Ok(v) = value.parse::<bool>() {
+                        settings.sticky_keys = v;
+                    }
+                }
+                "slow_keys" => {
+                    if let Ok(v) = value.parse::<bool>() {
+                        settings.slow_keys = v;
+                    }
+                }
+                "slow_keys_ms" => {
+                    if let Ok(v) = value.parse::<u32>() {
+                        if (accessibility::MIN_SLOW_KEYS_MS..=accessibility::MAX_SLOW_KEYS_MS).contains(&v) {
+                            settings.slow_keys_ms = v;
+                        }
+                    }
+                }
+                "window" => {
+                    if let (Some(w), true) = (SessionWindow::parse(value), windows < MAX_SESSION_WINDOWS) {
+                        settings.session[windows] = Some(w);
+                        windows += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match version {
+            Some(v) if v <= SETTINGS_VERSION => Ok(settings),
+            Some(_) => Err(LoadStatus::UnsupportedVersion),
+            None => Err(LoadStatus::Corrupt),
+        }
+    }
+
+    /// Apply settings to the running system
+    pub fn apply(&self) {
+        if let Some(t) = Theme::by_name(self.theme) {
+            theme::set(t);
+        }
+
+        let sensitivity = self.mouse_sensitivity as i32;
+        unsafe {
+            crate::drivers::mouse::MOUSE.set_sensitivity(sensitivity);
+            crate::drivers::synaptics::TOUCHPAD.set_sensitivity(sensitivity);
+        }
+
+        dither::set_mode(self.dither);
+
+        accessibility::set_sticky_keys(self.sticky_keys);
+        accessibility::set_slow_keys(if self.slow_keys { self.slow_keys_ms } else { 0 });
+
+        if let Some(gpu) = crate::drivers::ati_rage::get() {
+            if gpu.panel_info().is_some() {
+                let _ = gpu.set_panel_scaling(self.panel_scaling);
+            }
+        }
+
+        // Keymap, resolution and taskbar are recorded for the components
+        // that consume them (only the US layout exists; resolution is
+        // picked by the bootloader).
+    }
+}
+
+/// FNV-1a hash used as the file checksum
+fn checksum(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811C_9DC5;
+    for &b in data {
+        hash ^= b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+// =============================================================================
+// Global Instance
+// =============================================================================
+
+static mut SETTINGS: Settings = Settings::defaults();
+
+/// Get the current settings
+pub fn get() -> &'static Settings {
+    unsafe { &SETTINGS }
+}
+
+/// Load settings from disk and apply them
+///
+/// Called once at boot, after storage setup has mounted a root filesystem
+/// if it found one. Anything other than `Loaded` (including no root, or
+/// no settings file on it) leaves (and applies) the defaults.
+pub fn init() -> LoadStatus {
+    let status = match read_file() {
+        Ok(text) => match Settings::parse(&text) {
+            Ok(settings) => {
+                unsafe { SETTINGS = settings; }
+                LoadStatus::Loaded
+            }
+            Err(status) => status,
+        },
+        Err(_) => LoadStatus::Missing,
+    };
+
+    if status == LoadStatus::Corrupt || status == LoadStatus::UnsupportedVersion {
+        crate::gui::notify::warn("Settings unreadable, using defaults");
+    }
+
+    get().apply();
+    status
+}
+
+/// Write the current settings to disk
+pub fn save() -> Result<(), &'static str> {
+    let text = get().serialize();
+    let vfs = unsafe { &mut VFS };
+
+    let flags = OpenFlags::write_only().with_create().with_truncate();
+    let fd = vfs.open(SETTINGS_PATH, flags).map_err(|e| e.as_str())?;
+
+    let bytes = text.as_bytes();
+    let mut written = 0;
+    let result = loop {
+        if written == bytes.len() {
+            break Ok(());
+        }
+        match vfs.write(fd, &bytes[written..]) {
+            Ok(0) => break Err("Short write"),
+            Ok(n) => written += n,
+            Err(e) => break Err(e.as_str()),
+        }
+    };
+    let _ = vfs.close(fd);
+    result
+}
+
+/// Read the settings file into a string
+fn read_file() -> Result<String, &'static str> {
+    let vfs = unsafe { &mut VFS };
+
+    let size = vfs.stat(SETTINGS_PATH).map_err(|e| e.as_str())?.size as usize;
+    if size > MAX_SETTINGS_SIZE {
+        return Err("Settings file too large");
+    }
+
+    let fd = vfs.open(SETTINGS_PATH, OpenFlags::read_only()).map_err(|e| e.as_str())?;
+    let mut data = vec![0u8; size];
+    let mut read = 0;
+    while read < size {
+        match vfs.read(fd, &mut data[read..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => read += n,
+        }
+    }
+    let _ = vfs.close(fd);
+
+    data.truncate(read);
+    String::from_utf8(data).map_err(|_| "Settings not UTF-8")
+}
+
+/// Update settings, apply them, and persist
+fn update(f: impl FnOnce(&mut Settings)) -> Result<(), &'static str> {
+    unsafe { f(&mut SETTINGS); }
+    get().apply();
+    save()
+}
+
+/// Change the theme by name
+pub fn set_theme(name: &str) -> Result<(), &'static str> {
+    let name = *Theme::NAMES.iter().find(|&&n| n == name).ok_or("Unknown theme")?;
+    update(|s| s.theme = name)
+}
+
+/// Change the keyboard layout
+pub fn set_keymap(name: &str) -> Result<(), &'static str> {
+    let name = *KEYMAPS.iter().find(|&&n| n == name).ok_or("Unknown keymap")?;
+    update(|s| s.keymap = name)
+}
+
+/// Change pointer sensitivity
+pub fn set_mouse_sensitivity(value: u32) -> Result<(), &'static str> {
+    if !(MIN_SENSITIVITY..=MAX_SENSITIVITY).contains(&value) {
+        return Err("Sensitivity out of range");
+    }
+    update(|s| s.mouse_sensitivity = value)
+}
+
+/// Change the preferred resolution (takes effect next boot)
+pub fn set_resolution(width: u32, height: u32) -> Result<(), &'static str> {
+    if width == 0 || height == 0 {
+        return Err("Invalid resolution");
+    }
+    update(|s| s.resolution = (width, height))
+}
+
+/// Change internal LCD expansion and centering
+pub fn set_panel_scaling(scaling: PanelScaling) -> Result<(), &'static str> {
+    update(|s| s.panel_scaling = scaling)
+}
+
+/// Change the dithering mode for 8/16bpp displays
+pub fn set_dither(mode: DitherMode) -> Result<(), &'static str> {
+    update(|s| s.dither = mode)
+}
+
+/// Change taskbar preferences
+pub fn set_taskbar(visible: bool, position: TaskbarPosition) -> Result<(), &'static str> {
+    update(|s| {
+        s.taskbar_visible = visible;
+        s.taskbar_position = position;
+    })
+}
+
+/// Change the local time zone (minutes east of UTC)
+pub fn set_utc_offset(minutes: i32) -> Result<(), &'static str> {
+    if !(crate::time::MIN_UTC_OFFSET..=crate::time::MAX_UTC_OFFSET).contains(&minutes) {
+        return Err("UTC offset out of range");
+    }
+    update(|s| s.utc_offset = minutes)
+}
+
+/// Record the open windows (back to front) for the next boot
+///
+/// Windows past `MAX_SESSION_WINDOWS` are dropped from the back.
+pub fn save_session(windows: &[SessionWindow]) -> Result<(), &'static str> {
+    let skip = windows.len().saturating_sub(MAX_SESSION_WINDOWS);
+    update(|s| {
+        s.session = [None; MAX_SESSION_WINDOWS];
+        for (slot, &w) in s.session.iter_mut().zip(&windows[skip..]) {
+            *slot = Some(w);
+        }
+    })
+}
+
+/// Turn sticky keys on or off
+pub fn set_sticky_keys(on: bool) -> Result<(), &'static str> {
+    update(|s| s.sticky_keys = on)
+}
+
+/// Turn slow keys on or off
+pub fn set_slow_keys(on: bool) -> Result<(), &'static str> {
+    update(|s| s.slow_keys = on)
+}
+
+/// Change how long slow keys wants a key held (ms)
+pub fn set_slow_keys_ms(ms: u32) -> Result<(), &'static str> {
+    if !(accessibility::MIN_SLOW_KEYS_MS..=accessibility::MAX_SLOW_KEYS_MS).contains(&ms) {
+        return Err("Slow keys delay out of range");
+    }
+    update(|s| s.slow_keys_ms = ms)
+}
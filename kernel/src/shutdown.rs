@@ -0,0 +1,228 @@
+//! Shutdown EventChain
+//!
+//! Orderly power-off or reboot. A request (terminal command or the Reboot
+//! syscall) is only recorded; the main loop picks it up between frames,
+//! when no program is in ring 3, and runs the chain:
+//!
+//! 1. Save the desktop session (open windows) to the settings file
+//! 2. Ask program windows to close (WM close events), give their owners
+//!    a moment to exit, then close every remaining window
+//! 3. Stop programs: SIGTERM, then SIGKILL for any that ignore it
+//! 4. Sync filesystem data and the block cache
+//! 5. Unmount the root filesystem
+//! 6. Shut drivers down in reverse init order (input, then GPU)
+//!
+//! The chain is BestEffort: a step that fails or is refused is logged and
+//! the rest still run. The machine is then powered off or reset.
+
+use crate::event_chains::{
+    ChainableEvent, EventChain, EventContext, FaultToleranceMode,
+    result::EventResult,
+    middleware::LoggingMiddleware,
+};
+
+/// How long programs get to close their windows
+const CLOSE_GRACE_MS: u32 = 2000;
+
+/// What to do once the chain has run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    PowerOff,
+    Reboot,
+}
+
+static mut REQUEST: Option<Action> = None;
+
+/// Ask for a shutdown; the main loop carries it out
+pub fn request(action: Action) {
+    unsafe { REQUEST = Some(action) };
+}
+
+/// Main loop hook: run a requested shutdown (never returns if there is one)
+pub fn poll() {
+    if crate::exec::is_running() {
+        return;
+    }
+    if let Some(action) = unsafe { REQUEST.take() } {
+        run(action);
+    }
+}
+
+// =============================================================================
+// Events
+// =============================================================================
+
+/// Record the open windows so the next boot can recreate them
+#[cfg(feature = "gui")]
+struct SaveSessionEvent;
+
+#[cfg(feature = "gui")]
+impl ChainableEvent for SaveSessionEvent {
+    fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
+        let Some(desktop) = crate::gui::desktop::get() else {
+            return EventResult::success(());
+        };
+        match crate::settings::save_session(&desktop.session()) {
+            Ok(()) => EventResult::success(()),
+            Err(e) => EventResult::failure(e),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "save_session"
+    }
+}
+
+/// Send close events to program windows, wait for them, then close the rest
+#[cfg(feature = "gui")]
+struct CloseWindowsEvent;
+
+#[cfg(feature = "gui")]
+impl ChainableEvent for CloseWindowsEvent {
+    fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
+        use crate::gui::{desktop, server};
+
+        if server::request_close_all() > 0 {
+            let start = crate::time::monotonic_ms();
+            while server::client_count() > 0
+                && crate::time::monotonic_ms().wrapping_sub(start) < CLOSE_GRACE_MS
+            {
+                crate::exec::poll();
+                if let Some(desktop) = desktop::get() {
+                    desktop.close_orphaned_windows();
+                }
+                crate::arch::x86::pit::delay_ms(10);
+            }
+        }
+        let ignored = server::client_count();
+
+        let refused = desktop::get().map_or(0, |d| d.close_all_windows());
+        if refused > 0 {
+            return EventResult::failure("windows refused to close");
+        }
+        if ignored > 0 {
+            return EventResult::failure("programs ignored close request");
+        }
+        EventResult::success(())
+    }
+
+    fn name(&self) -> &'static str {
+        "close_windows"
+    }
+}
+
+/// SIGTERM every program, then SIGKILL the ones that ignore it
+struct StopProgramsEvent;
+
+impl ChainableEvent for StopProgramsEvent {
+    fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
+        let ignored = crate::exec::signal_all(crate::exec::SIGTERM);
+        crate::exec::kill_all();
+        if ignored > 0 {
+            return EventResult::failure("programs ignored SIGTERM (killed)");
+        }
+        EventResult::success(())
+    }
+
+    fn name(&self) -> &'static str {
+        "stop_programs"
+    }
+}
+
+/// Write back filesystem data and the block cache
+struct SyncEvent;
+
+impl ChainableEvent for SyncEvent {
+    fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
+        match unsafe { crate::fs::vfs::VFS.sync() } {
+            Ok(()) => EventResult::success(()),
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "sync"
+    }
+}
+
+/// Unmount the root filesystem (no root is fine)
+struct UnmountEvent;
+
+impl ChainableEvent for UnmountEvent {
+    fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
+        use crate::fs::FsError;
+        match unsafe { crate::fs::vfs::VFS.unmount_root() } {
+            Ok(()) | Err(FsError::NotMounted) => EventResult::success(()),
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "unmount"
+    }
+}
+
+/// Driver shutdown hooks, in reverse init order
+struct DriversEvent;
+
+impl ChainableEvent for DriversEvent {
+    fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
+        if crate::drivers::shutdown_all_drivers() > 0 {
+            return EventResult::failure("driver shutdown hook failed");
+        }
+        EventResult::success(())
+    }
+
+    fn name(&self) -> &'static str {
+        "drivers"
+    }
+}
+
+// =============================================================================
+// Global Event Instances
+// =============================================================================
+
+#[cfg(feature = "gui")]
+static SAVE_SESSION: SaveSessionEvent = SaveSessionEvent;
+#[cfg(feature = "gui")]
+static CLOSE_WINDOWS: CloseWindowsEvent = CloseWindowsEvent;
+static STOP_PROGRAMS: StopProgramsEvent = StopProgramsEvent;
+static SYNC: SyncEvent = SyncEvent;
+static UNMOUNT: UnmountEvent = UnmountEvent;
+static DRIVERS: DriversEvent = DriversEvent;
+
+static LOGGING_MW: LoggingMiddleware = LoggingMiddleware::new();
+
+// =============================================================================
+// Public API
+// =============================================================================
+
+/// Run the shutdown chain, then power off or reset
+pub fn run(action: Action) -> ! {
+    crate::klog::write_fmt(format_args!("[SHUT] {:?} requested\n", action));
+    let mut context = EventContext::new();
+
+    let chain = EventChain::new()
+        .middleware(&LOGGING_MW);
+    #[cfg(feature = "gui")]
+    let chain = chain
+        .event(&SAVE_SESSION)        // Before any window closes
+        .event(&CLOSE_WINDOWS);      // Apps first, while the desktop is up
+    let chain = chain
+        .event(&STOP_PROGRAMS)       // Then whatever is still running
+        .event(&SYNC)                // Flush dirty data
+        .event(&UNMOUNT)             // Detach the root volume
+        .event(&DRIVERS)             // Reverse of driver init
+        .with_fault_tolerance(FaultToleranceMode::BestEffort);
+
+    let result = chain.execute(&mut context);
+    for failure in result.failures() {
+        crate::klog::write_fmt(format_args!(
+            "[SHUT] {} failed: {}\n", failure.event_name, failure.error.as_str()));
+    }
+
+    match action {
+        Action::PowerOff => crate::arch::x86::power::power_off(),
+        Action::Reboot => crate::arch::x86::reboot::reset(),
+    }
+}
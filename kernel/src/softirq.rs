@@ -0,0 +1,189 @@
+//! Deferred Work (Softirqs)
+//!
+//! Interrupt handlers do only what can't wait with interrupts off: read
+//! the device, acknowledge it, queue the data. They raise a softirq for
+//! the rest (expiring timers, decoding scancodes, parsing touchpad
+//! packets), which runs with interrupts enabled after the EOI:
+//!
+//! - on the way out of the outermost IRQ (`irq_exit`), for up to
+//!   `MAX_ROUNDS` passes, so a flood of interrupts can't starve the
+//!   interrupted code
+//! - from the main loop (`run`), which picks up what `irq_exit` left and
+//!   work raised by polling
+//!
+//! Softirqs never run nested: an IRQ that arrives while one runs only
+//! marks its work pending. Tasklets are one-off work items (a function
+//! and an argument) queued from any context and run by the tasklet
+//! softirq. Counts are in /proc/softirqs.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crate::arch::x86::idt::without_interrupts;
+use crate::util::RingBuffer;
+
+/// Passes over the pending set per IRQ exit before leaving the rest to
+/// the main loop
+const MAX_ROUNDS: u32 = 4;
+
+/// Queued tasklets (power of two)
+const MAX_TASKLETS: usize = 32;
+
+/// Deferred work classes, run in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Softirq {
+    /// Run expired timers (`sched::timer`)
+    Timer = 0,
+    /// Decode queued scancodes
+    Keyboard = 1,
+    /// Decode queued mouse / touchpad bytes
+    Mouse = 2,
+    /// Run queued tasklets
+    Tasklet = 3,
+}
+
+/// Number of softirqs
+const COUNT: usize = 4;
+
+const ALL: [Softirq; COUNT] = [Softirq::Timer, Softirq::Keyboard, Softirq::Mouse, Softirq::Tasklet];
+
+impl Softirq {
+    pub fn name(self) -> &'static str {
+        match self {
+            Softirq::Timer => "timer",
+            Softirq::Keyboard => "keyboard",
+            Softirq::Mouse => "mouse",
+            Softirq::Tasklet => "tasklet",
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    /// Do the work
+    fn handle(self) {
+        match self {
+            Softirq::Timer => crate::sched::timer::run(),
+            Softirq::Keyboard => crate::drivers::keyboard::drain(),
+            Softirq::Mouse => {
+                if crate::drivers::synaptics::is_initialized() {
+                    crate::drivers::synaptics::drain();
+                } else {
+                    crate::drivers::mouse::poll();
+                }
+            }
+            Softirq::Tasklet => run_tasklets(),
+        }
+    }
+}
+
+/// A tasklet: function and its argument
+type Tasklet = (fn(u32), u32);
+
+/// Raised and not yet run (bit per softirq)
+static PENDING: AtomicU32 = AtomicU32::new(0);
+
+/// Softirqs are running (nested IRQ exits leave the work pending)
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+static TASKLETS: RingBuffer<Tasklet, MAX_TASKLETS> = RingBuffer::new();
+
+static RAISED: [AtomicU32; COUNT] = [const { AtomicU32::new(0) }; COUNT];
+static RUN: [AtomicU32; COUNT] = [const { AtomicU32::new(0) }; COUNT];
+/// IRQ exits that hit `MAX_ROUNDS` with work still pending
+static DEFERRED: AtomicU32 = AtomicU32::new(0);
+
+// =============================================================================
+// Raising
+// =============================================================================
+
+/// Mark work pending (any context)
+pub fn raise(softirq: Softirq) {
+    PENDING.fetch_or(softirq.bit(), Ordering::Release);
+    RAISED[softirq as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Queue `work(arg)` to run in the tasklet softirq (any context)
+///
+/// Returns false if the queue is full and the tasklet was dropped.
+pub fn schedule(work: fn(u32), arg: u32) -> bool {
+    // The ring takes one producer at a time
+    let queued = without_interrupts(|| TASKLETS.push((work, arg)));
+    if queued {
+        raise(Softirq::Tasklet);
+    }
+    queued
+}
+
+fn run_tasklets() {
+    while let Some((work, arg)) = TASKLETS.pop() {
+        work(arg);
+    }
+}
+
+// =============================================================================
+// Running
+// =============================================================================
+
+/// Run everything pending once; false if another run is in progress
+fn run_pending(enable_interrupts: bool) -> bool {
+    if ACTIVE.swap(true, Ordering::Acquire) {
+        return false;
+    }
+    let pending = PENDING.swap(0, Ordering::Acquire);
+    if pending != 0 {
+        if enable_interrupts {
+            unsafe { core::arch::asm!("sti") };
+        }
+        for softirq in ALL.iter().filter(|s| pending & s.bit() != 0) {
+            softirq.handle();
+            RUN[*softirq as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        if enable_interrupts {
+            unsafe { core::arch::asm!("cli") };
+        }
+    }
+    ACTIVE.store(false, Ordering::Release);
+    true
+}
+
+/// Run pending softirqs at the end of an IRQ, after its EOI
+///
+/// Called with interrupts disabled; enables them while the work runs and
+/// returns with them disabled again.
+pub fn irq_exit() {
+    for _ in 0..MAX_ROUNDS {
+        if PENDING.load(Ordering::Acquire) == 0 || !run_pending(true) {
+            return;
+        }
+    }
+    if PENDING.load(Ordering::Acquire) != 0 {
+        DEFERRED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Run pending softirqs from the main loop
+pub fn run() {
+    if PENDING.load(Ordering::Acquire) != 0 {
+        run_pending(false);
+    }
+}
+
+// =============================================================================
+// Reporting
+// =============================================================================
+
+/// Counts for /proc/softirqs
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "softirq   raised      run");
+    for softirq in ALL {
+        let _ = writeln!(out, "{:<9} {:<11} {}", softirq.name(),
+            RAISED[softirq as usize].load(Ordering::Relaxed), RUN[softirq as usize].load(Ordering::Relaxed));
+    }
+    let _ = writeln!(out, "pending {:04b}", PENDING.load(Ordering::Relaxed));
+    let _ = writeln!(out, "deferred to main loop {}", DEFERRED.load(Ordering::Relaxed));
+    let _ = writeln!(out, "tasklets queued {} max {} dropped {}",
+        TASKLETS.len(), TASKLETS.high_water(), TASKLETS.overflows());
+}
@@ -0,0 +1,81 @@
+//! Stack Smashing Protection
+//!
+//! The kernel is built with `-Zstack-protector=strong` (kernel/.cargo/
+//! config.toml): functions with local buffers store a canary (the value
+//! of `__stack_chk_guard`) below their return address and check it before
+//! returning, so an overrun of a parsing buffer (an exFAT name, a line of
+//! a config file) is caught before the function returns into whatever the
+//! overrun wrote there. A changed canary calls `__stack_chk_fail`, which:
+//!
+//! - logs the function the smashed frame belongs to (`ksyms`),
+//! - in a user program's syscall, sends the call trace to COM1 and ends
+//!   the program, abandoning the syscall's stack,
+//! - anywhere else, panics, which keeps a crash dump and halts.
+//!
+//! The guard starts as a fixed value and is replaced with a random one
+//! once the RNG is seeded. Its low byte is always zero, so a runaway
+//! string copy stops at the canary rather than reproducing it.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Guard until `init`: NUL, LF and 0xFF stop most string copies
+const INITIAL_GUARD: u32 = 0x000A_FF00;
+
+/// Stack bytes scanned for the smashed function's return address
+const STACK_WINDOW: usize = 256;
+
+/// Canary value checked by protected functions (read by the compiler's
+/// prologue and epilogue code, not by Rust)
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static mut __stack_chk_guard: u32 = INITIAL_GUARD;
+
+/// Set while a failure is handled (a smash in the handler must not loop)
+static FAILING: AtomicBool = AtomicBool::new(false);
+
+/// Give the guard a random value (after `rng::init`)
+///
+/// Protected functions running now still hold the old canary and would
+/// fail when they return, so this must be called from `kernel_main`,
+/// which never returns, before other tasks or CPUs are started.
+pub fn init() {
+    let guard = crate::rng::rand_u32() & !0xFF;
+    if guard != 0 {
+        unsafe { core::ptr::addr_of_mut!(__stack_chk_guard).write_volatile(guard) };
+    }
+    crate::klog!(Info, "stackprot", "stack guard seeded");
+}
+
+/// Called by a protected function whose canary changed
+#[no_mangle]
+pub extern "C" fn __stack_chk_fail() -> ! {
+    if FAILING.swap(true, Ordering::Acquire) {
+        loop {
+            unsafe { core::arch::asm!("cli; hlt") };
+        }
+    }
+
+    // The first code address on the stack is (almost always) the return
+    // into the function that found its canary changed
+    let esp: u32;
+    unsafe { core::arch::asm!("mov {:e}, esp", out(reg) esp) };
+    let stack = unsafe { core::slice::from_raw_parts(esp as usize as *const u8, STACK_WINDOW) };
+    let culprit = crate::ksyms::return_addresses(stack).next().unwrap_or(0);
+
+    if crate::exec::is_running() {
+        let pid = crate::exec::current_pid().unwrap_or(0);
+        crate::klog!(Crit, "stackprot", "stack smashed in {} (syscall by pid {}), ending it",
+            crate::ksyms::Addr(culprit), pid);
+        let serial = unsafe { &mut *core::ptr::addr_of_mut!(crate::drivers::serial::SERIAL) };
+        if serial.is_present() {
+            let _ = writeln!(serial, "\n=== STACK SMASH pid {} esp {:08x} ===", pid, esp);
+            crate::ksyms::write_trace(serial, stack);
+            let _ = writeln!(serial, "=== STACK SMASH END ===");
+        }
+        crate::gui::notify::error("Kernel stack overrun caught; program ended");
+        FAILING.store(false, Ordering::Release);
+        crate::exec::abort_current(crate::exec::EXIT_STACK_SMASH);
+    }
+    panic!("stack smashing detected in {}", crate::ksyms::Addr(culprit));
+}
@@ -0,0 +1,65 @@
+//! Optional Subsystems
+//!
+//! Cargo features choose which subsystems a kernel is built with; `make
+//! FEATURES=` (none) gives a headless kernel that boots into the text-mode
+//! shell. Everything a subsystem owns is behind its feature, and code
+//! outside it (the syscall table, the shutdown chain, /proc/slots) only
+//! registers the subsystem's events and entries when it is built.
+//!
+//! | feature | subsystem                                     |
+//! |---------|-----------------------------------------------|
+//! | `gui`   | window system, desktop, window server syscalls |
+//! | `net`   | networking                                    |
+//! | `usb`   | USB host controllers                          |
+//! | `audio` | sound output                                  |
+//!
+//! `net`, `usb` and `audio` have no drivers yet; the features are reserved
+//! so their code can land behind them.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+/// A subsystem that can be left out of the build
+pub struct Subsystem {
+    /// Cargo feature name
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Built into this kernel
+    pub built: bool,
+}
+
+/// Every optional subsystem
+pub const SUBSYSTEMS: &[Subsystem] = &[
+    Subsystem { name: "gui", description: "window system", built: cfg!(feature = "gui") },
+    Subsystem { name: "net", description: "networking", built: cfg!(feature = "net") },
+    Subsystem { name: "usb", description: "USB", built: cfg!(feature = "usb") },
+    Subsystem { name: "audio", description: "sound", built: cfg!(feature = "audio") },
+];
+
+/// Is a subsystem built into this kernel?
+pub fn is_built(name: &str) -> bool {
+    SUBSYSTEMS.iter().any(|s| s.name == name && s.built)
+}
+
+/// Names of the subsystems built in, for the boot log ("gui net")
+pub fn built_names() -> String {
+    let mut names = String::new();
+    for subsystem in SUBSYSTEMS.iter().filter(|s| s.built) {
+        if !names.is_empty() {
+            names.push(' ');
+        }
+        names.push_str(subsystem.name);
+    }
+    if names.is_empty() {
+        names.push_str("none");
+    }
+    names
+}
+
+/// Optional subsystems and whether each is built (/proc/features)
+pub fn report(out: &mut String) {
+    for subsystem in SUBSYSTEMS {
+        let _ = writeln!(out, "{:<6} {:<4} {}", subsystem.name,
+            if subsystem.built { "yes" } else { "no" }, subsystem.description);
+    }
+}
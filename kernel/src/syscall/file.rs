@@ -0,0 +1,524 @@
+//! File Syscalls
+//!
+//! Open, Close, Dup, Dup2, Chdir, Getcwd, Stat and Readdir, bridged to
+//! the VFS. Paths and output
+//! buffers are user pointers and always go through `usercopy`.
+//!
+//! VFS descriptors are global, so stdin/stdout/stderr are per task: Dup2
+//! onto 0-2 records a VFS descriptor in the task's `stdio`, and Read,
+//! Write and Close on 0-2 use it instead of the console while it is set.
+//! Other descriptors count against the opening task's file limit.
+
+use crate::event_chains::{ChainableEvent, EventContext, result::EventResult};
+use crate::fs::{self, FileType, Metadata, OpenFlags, PermissionBits};
+use crate::fs::path::PathBuf;
+use crate::fs::vfs::VFS;
+use crate::sched::rlimit;
+use super::usercopy;
+
+// ============================================================================
+// User-visible Structures
+// ============================================================================
+
+/// Stat buffer written by the Stat syscall
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct UserStat {
+    /// File type and permission bits (S_IF* | rwxrwxrwx)
+    pub mode: u32,
+    /// Reserved (keeps the 64-bit fields aligned)
+    pub _pad: u32,
+    /// File size in bytes
+    pub size: u64,
+    /// Creation time (Unix timestamp)
+    pub created: u64,
+    /// Last modification time
+    pub modified: u64,
+    /// Last access time
+    pub accessed: u64,
+}
+
+/// Directory record header written by the Readdir syscall
+///
+/// Each record is a header followed by the NUL-terminated name, padded
+/// so the next record starts on a 4-byte boundary. `reclen` is the
+/// total size of the record including padding.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct UserDirent {
+    /// Inode number (first cluster on exFAT)
+    pub inode: u64,
+    /// Total record length in bytes
+    pub reclen: u16,
+    /// Entry type (DT_* value)
+    pub file_type: u8,
+    /// Name length (excluding NUL)
+    pub name_len: u8,
+}
+
+/// Mode and dirent type constants
+pub mod mode {
+    pub const S_IFIFO: u32 = 0o010000;
+    pub const S_IFCHR: u32 = 0o020000;
+    pub const S_IFDIR: u32 = 0o040000;
+    pub const S_IFBLK: u32 = 0o060000;
+    pub const S_IFREG: u32 = 0o100000;
+    pub const S_IFLNK: u32 = 0o120000;
+    pub const S_IFSOCK: u32 = 0o140000;
+
+    pub const DT_FIFO: u8 = 1;
+    pub const DT_CHR: u8 = 2;
+    pub const DT_DIR: u8 = 4;
+    pub const DT_BLK: u8 = 6;
+    pub const DT_REG: u8 = 8;
+    pub const DT_LNK: u8 = 10;
+    pub const DT_SOCK: u8 = 12;
+}
+
+/// Size of the dirent header in bytes
+const DIRENT_HEADER: usize = core::mem::size_of::<UserDirent>();
+
+fn type_mode(file_type: FileType) -> u32 {
+    match file_type {
+        FileType::Regular => mode::S_IFREG,
+        FileType::Directory => mode::S_IFDIR,
+        FileType::Symlink => mode::S_IFLNK,
+        FileType::BlockDevice => mode::S_IFBLK,
+        FileType::CharDevice => mode::S_IFCHR,
+        FileType::Pipe => mode::S_IFIFO,
+        FileType::Socket => mode::S_IFSOCK,
+    }
+}
+
+fn dirent_type(file_type: FileType) -> u8 {
+    match file_type {
+        FileType::Regular => mode::DT_REG,
+        FileType::Directory => mode::DT_DIR,
+        FileType::Symlink => mode::DT_LNK,
+        FileType::BlockDevice => mode::DT_BLK,
+        FileType::CharDevice => mode::DT_CHR,
+        FileType::Pipe => mode::DT_FIFO,
+        FileType::Socket => mode::DT_SOCK,
+    }
+}
+
+fn permission_bits(bits: &PermissionBits) -> u32 {
+    (bits.read as u32) << 2 | (bits.write as u32) << 1 | bits.execute as u32
+}
+
+impl UserStat {
+    /// Encode VFS metadata
+    pub fn from_metadata(meta: &Metadata) -> Self {
+        let perms = &meta.permissions;
+        Self {
+            mode: type_mode(meta.file_type)
+                | permission_bits(&perms.owner) << 6
+                | permission_bits(&perms.group) << 3
+                | permission_bits(&perms.other),
+            _pad: 0,
+            size: meta.size,
+            created: meta.created,
+            modified: meta.modified,
+            accessed: meta.accessed,
+        }
+    }
+}
+
+/// View a plain `repr(C)` value as bytes for copying to user space
+fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    }
+}
+
+/// Copy a path argument from user space onto the stack
+///
+/// Takes up to `MAX_PATH` bytes; the VFS checks the canonical length.
+pub(super) fn user_path(ptr: u32) -> Result<PathBuf, &'static str> {
+    let mut buf = [0u8; fs::MAX_PATH + 1];
+    let len = usercopy::strncpy_from_user(&mut buf, ptr)
+        .map_err(|e| if e == usercopy::ENAMETOOLONG { fs::FsError::NameTooLong.as_str() } else { e })?;
+    let path = core::str::from_utf8(&buf[..len]).map_err(|_| fs::FsError::InvalidPath.as_str())?;
+    PathBuf::new(path).map_err(|e| e.as_str())
+}
+
+// ============================================================================
+// Standard Descriptors
+// ============================================================================
+
+/// VFS descriptor the current task's fd 0-2 is redirected to
+pub fn stdio_target(fd: u32) -> Option<u32> {
+    let task = unsafe { crate::sched::SCHEDULER.current()? };
+    unsafe { (*task).stdio.get(fd as usize).copied().flatten() }
+}
+
+/// Point standard descriptor `std_fd` at whatever `fd` refers to
+fn redirect_stdio(fd: u32, std_fd: u32) -> fs::FsResult<u32> {
+    let task = unsafe { crate::sched::SCHEDULER.current() }.ok_or(fs::FsError::InvalidPath)?;
+    let task = unsafe { &mut *task };
+    if fd == std_fd {
+        return Ok(std_fd);
+    }
+    // None sends it back to the console
+    let target = match stdio_target(fd) {
+        Some(vfd) => Some(unsafe { VFS.dup(vfd) }?),
+        None if fd < fs::vfs::FIRST_FD => None,
+        None => Some(unsafe { VFS.dup(fd) }?),
+    };
+    if let Some(old) = core::mem::replace(&mut task.stdio[std_fd as usize], target) {
+        let _ = unsafe { VFS.close(old) };
+    }
+    Ok(std_fd)
+}
+
+/// Copies of a task's redirected standard descriptors for a forked child
+pub fn inherit_stdio(task: &crate::sched::Task) -> [Option<u32>; 3] {
+    task.stdio.map(|fd| fd.and_then(|fd| unsafe { VFS.dup(fd) }.ok()))
+}
+
+/// Close a task's redirected standard descriptors
+pub fn release_stdio(task: &mut crate::sched::Task) {
+    for fd in task.stdio.iter_mut() {
+        if let Some(fd) = fd.take() {
+            let _ = unsafe { VFS.close(fd) };
+        }
+    }
+}
+
+// ============================================================================
+// Syscall Events
+// ============================================================================
+
+/// Open syscall event
+///
+/// arg1 = path pointer, arg2 = POSIX open flags. Result is the new fd.
+pub struct SyscallOpen;
+
+impl ChainableEvent for SyscallOpen {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let path_ptr = context.get_u32("arg1").unwrap_or(0);
+        let bits = context.get_u32("arg2").unwrap_or(0);
+        
+        let path = &match user_path(path_ptr) {
+            Ok(path) => path,
+            Err(e) => return EventResult::failure(e),
+        };
+        let flags = match OpenFlags::from_posix(bits) {
+            Some(flags) => flags,
+            None => return EventResult::failure("invalid open flags"),
+        };
+        if let Err(e) = rlimit::check_files() {
+            return EventResult::failure(e);
+        }
+        
+        match unsafe { VFS.open(path, flags) } {
+            Ok(fd) => {
+                rlimit::track_fd(fd);
+                context.set_u32("result", fd);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_open"
+    }
+}
+
+/// Dup syscall event
+///
+/// arg1 = fd. Result is a new fd sharing its file position.
+pub struct SyscallDup;
+
+impl ChainableEvent for SyscallDup {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let fd = context.get_u32("arg1").unwrap_or(0);
+        let fd = stdio_target(fd).unwrap_or(fd);
+        if let Err(e) = rlimit::check_files() {
+            return EventResult::failure(e);
+        }
+        
+        match unsafe { VFS.dup(fd) } {
+            Ok(new_fd) => {
+                rlimit::track_fd(new_fd);
+                context.set_u32("result", new_fd);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_dup"
+    }
+}
+
+/// Dup2 syscall event
+///
+/// arg1 = fd, arg2 = target fd (closed first if open). Result is arg2.
+/// A target of 0-2 redirects the task's standard descriptor.
+pub struct SyscallDup2;
+
+impl ChainableEvent for SyscallDup2 {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let fd = context.get_u32("arg1").unwrap_or(0);
+        let new_fd = context.get_u32("arg2").unwrap_or(0);
+        
+        let result = if new_fd < fs::vfs::FIRST_FD {
+            redirect_stdio(fd, new_fd)
+        } else {
+            if let Err(e) = rlimit::check_files() {
+                return EventResult::failure(e);
+            }
+            unsafe { VFS.dup2(stdio_target(fd).unwrap_or(fd), new_fd) }
+        };
+        match result {
+            Ok(new_fd) => {
+                rlimit::track_fd(new_fd);
+                context.set_u32("result", new_fd);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_dup2"
+    }
+}
+
+/// Chdir syscall event
+///
+/// arg1 = path pointer (absolute or relative to the current directory).
+pub struct SyscallChdir;
+
+impl ChainableEvent for SyscallChdir {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let path_ptr = context.get_u32("arg1").unwrap_or(0);
+        
+        let path = &match user_path(path_ptr) {
+            Ok(path) => path,
+            Err(e) => return EventResult::failure(e),
+        };
+        
+        match unsafe { VFS.chdir(path) } {
+            Ok(()) => {
+                context.set_u32("result", 0);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_chdir"
+    }
+}
+
+/// Getcwd syscall event
+///
+/// arg1 = buffer pointer, arg2 = buffer size. Writes the NUL-terminated
+/// working directory; result is its length excluding the NUL.
+pub struct SyscallGetcwd;
+
+impl ChainableEvent for SyscallGetcwd {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let buf_ptr = context.get_u32("arg1").unwrap_or(0);
+        let size = context.get_u32("arg2").unwrap_or(0) as usize;
+        
+        let cwd = unsafe { VFS.cwd() };
+        if cwd.len() + 1 > size {
+            return EventResult::failure("buffer too small");
+        }
+        let mut terminated = [0u8; fs::MAX_PATH + 1];
+        terminated[..cwd.len()].copy_from_slice(cwd.as_bytes());
+        if let Err(e) = usercopy::copy_to_user(buf_ptr, &terminated[..cwd.len() + 1]) {
+            return EventResult::failure(e);
+        }
+        
+        context.set_u32("result", cwd.len() as u32);
+        EventResult::success(())
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_getcwd"
+    }
+}
+
+/// Close syscall event
+///
+/// arg1 = fd.
+pub struct SyscallClose;
+
+impl ChainableEvent for SyscallClose {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let fd = context.get_u32("arg1").unwrap_or(0);
+        
+        // Console descriptors are permanently open; closing a redirected
+        // one sends it back to the console
+        if fd < fs::vfs::FIRST_FD {
+            if let Some(task) = unsafe { crate::sched::SCHEDULER.current() } {
+                if let Some(vfd) = unsafe { (*task).stdio[fd as usize].take() } {
+                    let _ = unsafe { VFS.close(vfd) };
+                }
+            }
+            context.set_u32("result", 0);
+            return EventResult::success(());
+        }
+        
+        match unsafe { VFS.close(fd) } {
+            Ok(()) => {
+                rlimit::untrack_fd(fd);
+                context.set_u32("result", 0);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_close"
+    }
+}
+
+/// Fsync syscall event
+///
+/// arg1 = fd. Returns once the file's data and the metadata naming it
+/// are on the device. Unredirected console descriptors have nothing to
+/// sync.
+pub struct SyscallFsync;
+
+impl ChainableEvent for SyscallFsync {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let fd = context.get_u32("arg1").unwrap_or(0);
+        let fd = if fd < fs::vfs::FIRST_FD {
+            match stdio_target(fd) {
+                Some(vfd) => vfd,
+                None => {
+                    context.set_u32("result", 0);
+                    return EventResult::success(());
+                }
+            }
+        } else {
+            fd
+        };
+        
+        match unsafe { VFS.fsync(fd) } {
+            Ok(()) => {
+                context.set_u32("result", 0);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_fsync"
+    }
+}
+
+/// Stat syscall event
+///
+/// arg1 = path pointer, arg2 = pointer to a `UserStat`.
+pub struct SyscallStat;
+
+impl ChainableEvent for SyscallStat {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let path_ptr = context.get_u32("arg1").unwrap_or(0);
+        let stat_ptr = context.get_u32("arg2").unwrap_or(0);
+        
+        let path = &match user_path(path_ptr) {
+            Ok(path) => path,
+            Err(e) => return EventResult::failure(e),
+        };
+        
+        let meta = match unsafe { VFS.stat(path) } {
+            Ok(meta) => meta,
+            Err(e) => return EventResult::failure(e.as_str()),
+        };
+        
+        let stat = UserStat::from_metadata(&meta);
+        if let Err(e) = usercopy::copy_to_user(stat_ptr, as_bytes(&stat)) {
+            return EventResult::failure(e);
+        }
+        
+        context.set_u32("result", 0);
+        EventResult::success(())
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_stat"
+    }
+}
+
+/// Readdir syscall event (getdents-style)
+///
+/// arg1 = path pointer, arg2 = buffer, arg3 = buffer size,
+/// arg4 = index of the first entry to return. Fills the buffer with
+/// packed `UserDirent` records and returns the number of bytes written;
+/// 0 means the end of the directory. Callers continue a listing by
+/// advancing arg4 by the number of records consumed; entries before it
+/// are streamed past, never buffered.
+pub struct SyscallReaddir;
+
+impl ChainableEvent for SyscallReaddir {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let path_ptr = context.get_u32("arg1").unwrap_or(0);
+        let buf = context.get_u32("arg2").unwrap_or(0);
+        let size = context.get_u32("arg3").unwrap_or(0);
+        let start = context.get_u32("arg4").unwrap_or(0) as usize;
+        
+        if !usercopy::access_ok(buf, size as usize) {
+            return EventResult::failure(usercopy::EFAULT);
+        }
+        
+        let path = &match user_path(path_ptr) {
+            Ok(path) => path,
+            Err(e) => return EventResult::failure(e),
+        };
+        
+        let entries = match unsafe { VFS.readdir(path) } {
+            Ok(entries) => entries,
+            Err(e) => return EventResult::failure(e.as_str()),
+        };
+        
+        let mut record = [0u8; DIRENT_HEADER + fs::MAX_FILENAME + 4];
+        let mut written = 0u32;
+        
+        for entry in entries.skip(start) {
+            let name_len = entry.name_len.min(u8::MAX as usize);
+            let reclen = (DIRENT_HEADER + name_len + 1 + 3) & !3;
+            
+            if written as usize + reclen > size as usize {
+                if written == 0 {
+                    return EventResult::failure("buffer too small");
+                }
+                break;
+            }
+            
+            let header = UserDirent {
+                inode: entry.inode,
+                reclen: reclen as u16,
+                file_type: dirent_type(entry.file_type),
+                name_len: name_len as u8,
+            };
+            record[..reclen].fill(0);
+            record[..DIRENT_HEADER].copy_from_slice(as_bytes(&header));
+            record[DIRENT_HEADER..DIRENT_HEADER + name_len]
+                .copy_from_slice(&entry.name[..name_len]);
+            
+            if let Err(e) = usercopy::copy_to_user(buf + written, &record[..reclen]) {
+                return EventResult::failure(e);
+            }
+            written += reclen as u32;
+        }
+        
+        context.set_u32("result", written);
+        EventResult::success(())
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_readdir"
+    }
+}
@@ -0,0 +1,166 @@
+//! Window Syscalls
+//!
+//! The program side of the window server (`gui::server`): create a
+//! window, present a pixel buffer into it (or attach a shared memory
+//! object as its buffer), read its input events and destroy it. Windows
+//! belong to the calling program and are closed when it exits.
+
+use crate::event_chains::{ChainableEvent, EventContext, result::EventResult};
+use crate::gui::server::{self, WinEvent};
+use super::usercopy;
+
+/// Longest window title accepted
+const MAX_TITLE: usize = 64;
+
+fn caller() -> Result<crate::sched::Pid, &'static str> {
+    crate::exec::current_pid().ok_or("no user program")
+}
+
+fn finish(context: &mut EventContext, result: Result<u32, &'static str>) -> EventResult<()> {
+    match result {
+        Ok(value) => {
+            context.set_u32("result", value);
+            EventResult::success(())
+        }
+        Err(e) => EventResult::failure(e),
+    }
+}
+
+/// WinCreate syscall event
+///
+/// arg1 = title (NUL-terminated), arg2 = content width, arg3 = content
+/// height. Result is the window ID.
+pub struct SyscallWinCreate;
+
+impl ChainableEvent for SyscallWinCreate {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let title_ptr = context.get_u32("arg1").unwrap_or(0);
+        let width = context.get_u32("arg2").unwrap_or(0);
+        let height = context.get_u32("arg3").unwrap_or(0);
+
+        let result = caller().and_then(|pid| {
+            let mut buf = [0u8; MAX_TITLE];
+            let len = usercopy::strncpy_from_user(&mut buf, title_ptr)?;
+            let title = core::str::from_utf8(&buf[..len]).map_err(|_| "bad title")?;
+            server::create(pid, title, width, height)
+        });
+        finish(context, result)
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_win_create"
+    }
+}
+
+/// WinDestroy syscall event
+///
+/// arg1 = window ID.
+pub struct SyscallWinDestroy;
+
+impl ChainableEvent for SyscallWinDestroy {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let id = context.get_u32("arg1").unwrap_or(0);
+        let result = caller().and_then(|pid| {
+            server::size(id, pid)?;
+            server::destroy(id)
+        });
+        finish(context, result.map(|_| 0))
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_win_destroy"
+    }
+}
+
+/// WinPresent syscall event
+///
+/// arg1 = window ID, arg2 = pixel buffer, arg3 = pixel count, which must
+/// be width * height of the content area. With a shared memory buffer
+/// attached, arg2 is 0 and the window is just redrawn.
+pub struct SyscallWinPresent;
+
+impl ChainableEvent for SyscallWinPresent {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let id = context.get_u32("arg1").unwrap_or(0);
+        let src = context.get_u32("arg2").unwrap_or(0);
+        let count = context.get_u32("arg3").unwrap_or(0);
+
+        let result = caller().and_then(|pid| {
+            if src == 0 {
+                return server::damage(id, pid);
+            }
+            let (width, height) = server::size(id, pid)?;
+            if width * height != count {
+                return Err("pixel count does not match window size");
+            }
+            let mut from = src;
+            server::present(id, pid, |bytes| {
+                usercopy::copy_from_user(bytes, from)?;
+                from = from.checked_add(bytes.len() as u32).ok_or(usercopy::EFAULT)?;
+                Ok(())
+            })
+        });
+        finish(context, result.map(|_| 0))
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_win_present"
+    }
+}
+
+/// WinAttach syscall event
+///
+/// arg1 = window ID, arg2 = shared memory object ID to use as the
+/// window's buffer (at least width * height * 4 bytes), or 0 to detach.
+pub struct SyscallWinAttach;
+
+impl ChainableEvent for SyscallWinAttach {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let id = context.get_u32("arg1").unwrap_or(0);
+        let shm_id = context.get_u32("arg2").unwrap_or(0);
+        let result = caller().and_then(|pid| server::attach(id, pid, shm_id));
+        finish(context, result.map(|_| 0))
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_win_attach"
+    }
+}
+
+/// WinEvent syscall event
+///
+/// arg1 = window ID, arg2 = `WinEvent` buffer, arg3 = 1 to block until
+/// an event arrives. Result is 1 if an event was written, else 0.
+pub struct SyscallWinEvent;
+
+impl ChainableEvent for SyscallWinEvent {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let id = context.get_u32("arg1").unwrap_or(0);
+        let dst = context.get_u32("arg2").unwrap_or(0);
+        let block = context.get_u32("arg3").unwrap_or(0) != 0;
+
+        let result = caller().and_then(|pid| match server::next_event(id, pid)? {
+            Some(event) => {
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(
+                        &event as *const WinEvent as *const u8,
+                        core::mem::size_of::<WinEvent>(),
+                    )
+                };
+                usercopy::copy_to_user(dst, bytes).map(|_| 1)
+            }
+            None => {
+                // Restarted once an event is queued
+                if block {
+                    crate::exec::wait_window_event(id);
+                }
+                Ok(0)
+            }
+        });
+        finish(context, result)
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_win_event"
+    }
+}
@@ -0,0 +1,153 @@
+//! Port Syscalls
+//!
+//! Create, look up and destroy named message ports and send or receive
+//! on them (see `ipc`). Send and receive take an absolute deadline in
+//! uptime milliseconds (`ipc::NO_WAIT`, `ipc::FOREVER`) and block the
+//! caller until the port is ready or the deadline passes.
+
+use crate::event_chains::{ChainableEvent, EventContext, result::EventResult};
+use crate::ipc::{self, Message, MSG_SIZE};
+use super::usercopy;
+
+fn caller() -> Result<crate::sched::Pid, &'static str> {
+    crate::exec::current_pid().ok_or("no user program")
+}
+
+fn finish(context: &mut EventContext, result: Result<u32, &'static str>) -> EventResult<()> {
+    match result {
+        Ok(value) => {
+            context.set_u32("result", value);
+            EventResult::success(())
+        }
+        Err(e) => EventResult::failure(e),
+    }
+}
+
+/// Copy a port name argument from user space
+fn user_name(buf: &mut [u8; ipc::MAX_NAME + 1], ptr: u32) -> Result<&str, &'static str> {
+    let len = usercopy::strncpy_from_user(buf, ptr)?;
+    core::str::from_utf8(&buf[..len]).map_err(|_| "bad port name")
+}
+
+/// PortCreate syscall event
+///
+/// arg1 = name (NUL-terminated). Result is the port ID.
+pub struct SyscallPortCreate;
+
+impl ChainableEvent for SyscallPortCreate {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let name_ptr = context.get_u32("arg1").unwrap_or(0);
+        let result = caller().and_then(|pid| {
+            let mut buf = [0u8; ipc::MAX_NAME + 1];
+            ipc::create(pid, user_name(&mut buf, name_ptr)?)
+        });
+        finish(context, result)
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_port_create"
+    }
+}
+
+/// PortLookup syscall event
+///
+/// arg1 = name (NUL-terminated). Result is the port ID.
+pub struct SyscallPortLookup;
+
+impl ChainableEvent for SyscallPortLookup {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let name_ptr = context.get_u32("arg1").unwrap_or(0);
+        let mut buf = [0u8; ipc::MAX_NAME + 1];
+        let result = user_name(&mut buf, name_ptr)
+            .and_then(|name| ipc::lookup(name).ok_or("no such port"));
+        finish(context, result)
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_port_lookup"
+    }
+}
+
+/// PortDestroy syscall event
+///
+/// arg1 = port ID (must be the caller's).
+pub struct SyscallPortDestroy;
+
+impl ChainableEvent for SyscallPortDestroy {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let id = context.get_u32("arg1").unwrap_or(0);
+        let result = caller().and_then(|pid| ipc::destroy(id, pid));
+        finish(context, result.map(|_| 0))
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_port_destroy"
+    }
+}
+
+/// PortSend syscall event
+///
+/// arg1 = port ID, arg2 = payload, arg3 = payload length (at most
+/// `ipc::MSG_SIZE`), arg4 = deadline.
+pub struct SyscallPortSend;
+
+impl ChainableEvent for SyscallPortSend {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let id = context.get_u32("arg1").unwrap_or(0);
+        let src = context.get_u32("arg2").unwrap_or(0);
+        let len = context.get_u32("arg3").unwrap_or(0) as usize;
+        let deadline = context.get_u32("arg4").unwrap_or(ipc::NO_WAIT);
+
+        let result = caller().and_then(|pid| {
+            let mut payload = [0u8; MSG_SIZE];
+            let payload = payload.get_mut(..len).ok_or("message too long")?;
+            usercopy::copy_from_user(payload, src)?;
+            if !ipc::send(id, pid, payload, deadline)? {
+                // Restarted once the queue has room or the deadline passes
+                crate::exec::wait_port(id, deadline, true);
+            }
+            Ok(0)
+        });
+        finish(context, result)
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_port_send"
+    }
+}
+
+/// PortReceive syscall event
+///
+/// arg1 = port ID (must be the caller's), arg2 = `ipc::Message` buffer,
+/// arg3 = deadline. Result is the payload length.
+pub struct SyscallPortReceive;
+
+impl ChainableEvent for SyscallPortReceive {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let id = context.get_u32("arg1").unwrap_or(0);
+        let dst = context.get_u32("arg2").unwrap_or(0);
+        let deadline = context.get_u32("arg3").unwrap_or(ipc::NO_WAIT);
+
+        let result = caller().and_then(|pid| match ipc::receive(id, pid, deadline)? {
+            Some(message) => {
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(
+                        &message as *const Message as *const u8,
+                        core::mem::size_of::<Message>(),
+                    )
+                };
+                usercopy::copy_to_user(dst, bytes).map(|_| message.len)
+            }
+            None => {
+                // Restarted once a message arrives or the deadline passes
+                crate::exec::wait_port(id, deadline, false);
+                Ok(0)
+            }
+        });
+        finish(context, result)
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_port_receive"
+    }
+}
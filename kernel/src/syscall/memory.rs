@@ -0,0 +1,170 @@
+//! Memory Syscalls
+//!
+//! Brk and Sbrk move the calling task's program break, within the task's
+//! heap limit (see `sched::rlimit`). Heap pages are not allocated here;
+//! they are faulted in on first touch (see `mm::vma`).
+//!
+//! The Shm syscalls create shared memory objects and map them into the
+//! caller (see `mm::shm`), e.g. to render into a window buffer in place.
+
+use crate::event_chains::{ChainableEvent, EventContext, result::EventResult};
+use crate::mm::{paging::AddressSpace, shm};
+use crate::sched::{rlimit, Task, SCHEDULER};
+
+/// Run `f` on the current task and its address space
+fn with_current<T>(f: impl FnOnce(&mut Task, &mut AddressSpace) -> Result<T, &'static str>) -> Result<T, &'static str> {
+    unsafe {
+        let task = &mut *SCHEDULER.current().ok_or("no current task")?;
+        let mut space = AddressSpace::from_directory(task.cr3);
+        f(task, &mut space)
+    }
+}
+
+/// Move the break after checking the heap limit
+fn set_brk(task: &mut Task, space: &mut AddressSpace, new_brk: u32) -> Result<u32, &'static str> {
+    let start = task.vmas.heap_start().ok_or("no heap")?;
+    rlimit::check_heap(task, start, new_brk)?;
+    task.vmas.set_brk(space, new_brk)
+}
+
+/// Brk syscall event
+///
+/// arg1 is the requested break; 0 just queries it. Returns the break in
+/// effect afterwards.
+pub struct SyscallBrk;
+
+impl ChainableEvent for SyscallBrk {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let requested = context.get_u32("arg1").unwrap_or(0);
+
+        let result = with_current(|task, space| {
+            if requested == 0 {
+                return Ok(task.vmas.brk());
+            }
+            set_brk(task, space, requested)
+        });
+        match result {
+            Ok(brk) => {
+                context.set_u32("result", brk);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_brk"
+    }
+}
+
+/// Sbrk syscall event
+///
+/// arg1 is a signed increment. Returns the previous break, so the caller
+/// gets the start of the newly added memory.
+pub struct SyscallSbrk;
+
+impl ChainableEvent for SyscallSbrk {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let increment = context.get_u32("arg1").unwrap_or(0) as i32;
+
+        let result = with_current(|task, space| {
+            let old = task.vmas.brk();
+            if old == 0 {
+                return Err("no heap");
+            }
+            let new = old.checked_add_signed(increment).ok_or("break out of range")?;
+            set_brk(task, space, new)?;
+            Ok(old)
+        });
+        match result {
+            Ok(old) => {
+                context.set_u32("result", old);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_sbrk"
+    }
+}
+
+/// Store a syscall's result or fail the event
+fn finish(context: &mut EventContext, result: Result<u32, &'static str>) -> EventResult<()> {
+    match result {
+        Ok(value) => {
+            context.set_u32("result", value);
+            EventResult::success(())
+        }
+        Err(e) => EventResult::failure(e),
+    }
+}
+
+fn caller() -> Result<crate::sched::Pid, &'static str> {
+    crate::exec::current_pid().ok_or("no user program")
+}
+
+/// ShmCreate syscall event
+///
+/// arg1 = size in bytes (rounded up to pages). Result is the object ID.
+pub struct SyscallShmCreate;
+
+impl ChainableEvent for SyscallShmCreate {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let size = context.get_u32("arg1").unwrap_or(0);
+        finish(context, caller().and_then(|pid| shm::create(pid, size)))
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_shm_create"
+    }
+}
+
+/// ShmMap syscall event
+///
+/// arg1 = object ID. Result is the address it was mapped at.
+pub struct SyscallShmMap;
+
+impl ChainableEvent for SyscallShmMap {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let id = context.get_u32("arg1").unwrap_or(0);
+        finish(context, caller().and_then(|pid| shm::map(id, pid)))
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_shm_map"
+    }
+}
+
+/// ShmUnmap syscall event
+///
+/// arg1 = address returned by ShmMap.
+pub struct SyscallShmUnmap;
+
+impl ChainableEvent for SyscallShmUnmap {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let addr = context.get_u32("arg1").unwrap_or(0);
+        finish(context, shm::unmap(addr).map(|_| 0))
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_shm_unmap"
+    }
+}
+
+/// ShmDestroy syscall event
+///
+/// arg1 = object ID. Existing mappings stay valid.
+pub struct SyscallShmDestroy;
+
+impl ChainableEvent for SyscallShmDestroy {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let id = context.get_u32("arg1").unwrap_or(0);
+        finish(context, caller().and_then(|pid| shm::destroy(id, pid)).map(|_| 0))
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_shm_destroy"
+    }
+}
@@ -5,9 +5,33 @@
 
 use crate::event_chains::{
     ChainableEvent, EventChain, EventContext, FaultToleranceMode,
-    result::EventResult,
+    result::{ChainResult, EventResult},
     middleware::{LoggingMiddleware, PermissionMiddleware, AuditMiddleware},
 };
+use crate::sched::rlimit;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub mod usercopy;
+pub mod file;
+pub mod memory;
+pub mod tty;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod ipc;
+
+use file::{
+    SyscallOpen, SyscallClose, SyscallDup, SyscallDup2, SyscallChdir, SyscallGetcwd,
+    SyscallStat, SyscallReaddir, SyscallFsync,
+};
+use memory::{
+    SyscallBrk, SyscallSbrk, SyscallShmCreate, SyscallShmMap, SyscallShmUnmap, SyscallShmDestroy,
+};
+use tty::SyscallIoctl;
+#[cfg(feature = "gui")]
+use gui::{SyscallWinCreate, SyscallWinDestroy, SyscallWinPresent, SyscallWinEvent, SyscallWinAttach};
+use ipc::{
+    SyscallPortCreate, SyscallPortLookup, SyscallPortDestroy, SyscallPortSend, SyscallPortReceive,
+};
 
 /// System call numbers
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -43,6 +67,64 @@ pub enum SyscallNumber {
     Sleep = 13,
     /// Get current time
     Time = 14,
+    /// Get file metadata
+    Stat = 15,
+    /// Read directory entries
+    Readdir = 16,
+    /// Restart the machine
+    Reboot = 17,
+    /// Write back all cached filesystem data
+    Sync = 18,
+    /// Duplicate a file descriptor
+    Dup = 19,
+    /// Duplicate a file descriptor onto a chosen number
+    Dup2 = 20,
+    /// Change the working directory
+    Chdir = 21,
+    /// Get the working directory
+    Getcwd = 22,
+    /// Set the program break
+    Brk = 23,
+    /// Move the program break by an increment
+    Sbrk = 24,
+    /// Ignore a signal or restore its default action
+    Signal = 25,
+    /// Device control (tty modes)
+    Ioctl = 26,
+    /// Get or set a resource limit
+    Rlimit = 27,
+    /// Create a window owned by the calling program
+    WinCreate = 28,
+    /// Destroy one of the caller's windows
+    WinDestroy = 29,
+    /// Replace a window's content with a pixel buffer
+    WinPresent = 30,
+    /// Read a window's next input event
+    WinEvent = 31,
+    /// Create a shared memory object
+    ShmCreate = 32,
+    /// Map a shared memory object into the caller
+    ShmMap = 33,
+    /// Remove a shared memory mapping
+    ShmUnmap = 34,
+    /// Destroy a shared memory object
+    ShmDestroy = 35,
+    /// Use a shared memory object as a window's buffer
+    WinAttach = 36,
+    /// Create a named message port
+    PortCreate = 37,
+    /// Find a port by name
+    PortLookup = 38,
+    /// Destroy one of the caller's ports
+    PortDestroy = 39,
+    /// Send a message to a port
+    PortSend = 40,
+    /// Receive a message from one of the caller's ports
+    PortReceive = 41,
+    /// Write a file's data and metadata through to its device
+    Fsync = 42,
+    /// Fill a buffer with random bytes
+    GetRandom = 43,
     /// Unknown syscall
     Unknown = 0xFFFFFFFF,
 }
@@ -65,11 +147,96 @@ impl From<u32> for SyscallNumber {
             12 => Self::Yield,
             13 => Self::Sleep,
             14 => Self::Time,
+            15 => Self::Stat,
+            16 => Self::Readdir,
+            17 => Self::Reboot,
+            18 => Self::Sync,
+            19 => Self::Dup,
+            20 => Self::Dup2,
+            21 => Self::Chdir,
+            22 => Self::Getcwd,
+            23 => Self::Brk,
+            24 => Self::Sbrk,
+            25 => Self::Signal,
+            26 => Self::Ioctl,
+            27 => Self::Rlimit,
+            28 => Self::WinCreate,
+            29 => Self::WinDestroy,
+            30 => Self::WinPresent,
+            31 => Self::WinEvent,
+            32 => Self::ShmCreate,
+            33 => Self::ShmMap,
+            34 => Self::ShmUnmap,
+            35 => Self::ShmDestroy,
+            36 => Self::WinAttach,
+            37 => Self::PortCreate,
+            38 => Self::PortLookup,
+            39 => Self::PortDestroy,
+            40 => Self::PortSend,
+            41 => Self::PortReceive,
+            42 => Self::Fsync,
+            43 => Self::GetRandom,
             _ => Self::Unknown,
         }
     }
 }
 
+/// Number of defined syscalls (excluding Unknown)
+pub const NUM_SYSCALLS: usize = 44;
+
+/// Error numbers (returned negated) for failures a program can act on
+///
+/// Any other failure returns -1.
+pub mod errno {
+    /// Failure without a more specific code
+    pub const EFAIL: u32 = 1;
+    /// Out of memory, or the heap limit was hit
+    pub const ENOMEM: u32 = 12;
+    /// Open file limit hit
+    pub const EMFILE: u32 = 24;
+    /// Port send or receive deadline passed
+    pub const ETIMEDOUT: u32 = 110;
+    /// Window limit hit (Rustacean-specific)
+    pub const EMWINDOW: u32 = 200;
+}
+
+/// Return value for a failed chain
+fn error_code(result: &ChainResult) -> u32 {
+    let code = result.failures().find_map(|f| match f.error.as_str() {
+        rlimit::EHEAP | crate::mm::oom::ENOMEM => Some(errno::ENOMEM),
+        #[cfg(feature = "gui")]
+        crate::gui::server::ESURFACE_MEMORY => Some(errno::ENOMEM),
+        rlimit::EFILES => Some(errno::EMFILE),
+        rlimit::EWINDOWS => Some(errno::EMWINDOW),
+        crate::ipc::ETIMEDOUT => Some(errno::ETIMEDOUT),
+        _ => None,
+    });
+    code.unwrap_or(errno::EFAIL).wrapping_neg()
+}
+
+/// How a syscall is dispatched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchPath {
+    /// Direct function call, bypassing middleware (audit is sampled)
+    Fast,
+    /// Full EventChain with logging, permission, and audit middleware
+    Chain,
+}
+
+impl SyscallNumber {
+    /// Default dispatch path for this syscall
+    ///
+    /// Hot syscalls with no side effects on other tasks or the filesystem
+    /// are marked Fast. Anything touching files, memory maps, or process
+    /// images always goes through the chain.
+    pub const fn default_path(self) -> DispatchPath {
+        match self {
+            Self::GetPid | Self::Yield | Self::Time => DispatchPath::Fast,
+            _ => DispatchPath::Chain,
+        }
+    }
+}
+
 /// System call parameters
 #[derive(Debug, Clone, Copy)]
 pub struct SyscallParams {
@@ -106,8 +273,8 @@ impl ChainableEvent for SyscallExit {
     fn execute(&self, context: &mut EventContext) -> EventResult<()> {
         let exit_code = context.get_u32("arg1").unwrap_or(0);
         
-        // Mark current task for termination
-        // In a real implementation, this would interact with the scheduler
+        // The program is left once the syscall returns (see exec::after_syscall)
+        crate::exec::exit_current(exit_code);
         
         context.set_u32("result", 0);
         EventResult::success(())
@@ -127,10 +294,44 @@ impl ChainableEvent for SyscallRead {
         let buf = context.get_u32("arg2").unwrap_or(0);
         let count = context.get_u32("arg3").unwrap_or(0);
         
-        // TODO: Implement file read
-        // For now, just return 0 bytes read
+        if !usercopy::access_ok(buf, count as usize) {
+            return EventResult::failure(usercopy::EFAULT);
+        }
         
-        context.set_u32("result", 0);
+        // Console input (may block the caller until a line is typed)
+        let fd = file::stdio_target(fd).unwrap_or(fd);
+        let mut chunk = [0u8; 256];
+        if fd < crate::fs::vfs::FIRST_FD {
+            let len = (count as usize).min(chunk.len());
+            let n = crate::exec::console_read(&mut chunk[..len]);
+            if let Err(e) = usercopy::copy_to_user(buf, &chunk[..n]) {
+                return EventResult::failure(e);
+            }
+            context.set_u32("result", n as u32);
+            return EventResult::success(());
+        }
+        
+        // Read through a kernel bounce buffer
+        let mut total = 0u32;
+        while total < count {
+            let len = ((count - total) as usize).min(chunk.len());
+            let n = match unsafe { crate::fs::vfs::VFS.read(fd, &mut chunk[..len]) } {
+                Ok(n) => n,
+                Err(e) => return EventResult::failure(e.as_str()),
+            };
+            if n == 0 {
+                break;
+            }
+            if let Err(e) = usercopy::copy_to_user(buf + total, &chunk[..n]) {
+                return EventResult::failure(e);
+            }
+            total += n as u32;
+            if n < len {
+                break;
+            }
+        }
+        
+        context.set_u32("result", total);
         EventResult::success(())
     }
     
@@ -148,21 +349,53 @@ impl ChainableEvent for SyscallWrite {
         let buf = context.get_u32("arg2").unwrap_or(0);
         let count = context.get_u32("arg3").unwrap_or(0);
         
-        // Handle stdout/stderr
+        if !usercopy::access_ok(buf, count as usize) {
+            return EventResult::failure(usercopy::EFAULT);
+        }
+        
+        // Handle stdout/stderr (unless redirected to a file)
+        let fd = file::stdio_target(fd).unwrap_or(fd);
         if fd == 1 || fd == 2 {
-            // Write to console
-            unsafe {
-                let slice = core::slice::from_raw_parts(buf as *const u8, count as usize);
-                if let Some(writer) = crate::drivers::vga::WRITER.as_mut() {
-                    for &byte in slice {
-                        writer.write_byte(byte);
+            // Write to console, copying through a kernel bounce buffer
+            let mut chunk = [0u8; 256];
+            let mut offset = 0u32;
+            while offset < count {
+                let len = ((count - offset) as usize).min(chunk.len());
+                if let Err(e) = usercopy::copy_from_user(&mut chunk[..len], buf + offset) {
+                    return EventResult::failure(e);
+                }
+                if crate::exec::console_write(&chunk[..len]) {
+                    offset += len as u32;
+                    continue;
+                }
+                unsafe {
+                    if let Some(writer) = crate::drivers::vga::WRITER.as_mut() {
+                        for &byte in &chunk[..len] {
+                            writer.write_byte(byte);
+                        }
                     }
                 }
+                offset += len as u32;
             }
             context.set_u32("result", count);
         } else {
-            // TODO: Implement file write
-            context.set_u32("result", 0);
+            let mut chunk = [0u8; 256];
+            let mut total = 0u32;
+            while total < count {
+                let len = ((count - total) as usize).min(chunk.len());
+                if let Err(e) = usercopy::copy_from_user(&mut chunk[..len], buf + total) {
+                    return EventResult::failure(e);
+                }
+                let n = match unsafe { crate::fs::vfs::VFS.write(fd, &chunk[..len]) } {
+                    Ok(n) => n,
+                    Err(e) => return EventResult::failure(e.as_str()),
+                };
+                total += n as u32;
+                if n < len {
+                    break;
+                }
+            }
+            context.set_u32("result", total);
         }
         
         EventResult::success(())
@@ -176,21 +409,207 @@ impl ChainableEvent for SyscallWrite {
 /// GetPid syscall event
 struct SyscallGetPid;
 
+/// Get the current task's PID (0 if no task is running)
+fn sys_getpid() -> u32 {
+    unsafe {
+        match crate::sched::SCHEDULER.current() {
+            Some(task) => (*task).pid,
+            None => 0,
+        }
+    }
+}
+
 impl ChainableEvent for SyscallGetPid {
     fn execute(&self, context: &mut EventContext) -> EventResult<()> {
-        // Get current task's PID
-        unsafe {
-            if let Some(task) = crate::sched::SCHEDULER.current() {
-                context.set_u32("result", (*task).pid);
-            } else {
+        context.set_u32("result", sys_getpid());
+        EventResult::success(())
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_getpid"
+    }
+}
+
+/// Fork syscall event
+///
+/// Returns the child's PID to the parent; the child resumes with 0.
+struct SyscallFork;
+
+impl ChainableEvent for SyscallFork {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let forked = if crate::exec::is_running() {
+            crate::exec::fork_current()
+        } else {
+            crate::sched::fork()
+        };
+        match forked {
+            Ok(pid) => {
+                context.set_u32("result", pid);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_fork"
+    }
+}
+
+/// Exec syscall event
+///
+/// arg1 = path pointer, arg2 = argv, arg3 = envp (NULL-terminated arrays
+/// of string pointers; 0 for an empty list, and an empty argv becomes
+/// just the path). On success the caller's image is replaced and the
+/// syscall never returns to it.
+struct SyscallExec;
+
+/// Copy a NULL-terminated array of user strings into `push`
+fn user_strings(ptr: u32, mut push: impl FnMut(&[u8]) -> Result<(), &'static str>) -> Result<(), &'static str> {
+    if ptr == 0 {
+        return Ok(());
+    }
+    let mut buf = alloc::vec![0u8; crate::exec::ARG_MAX];
+    for i in 0..=crate::exec::MAX_ARGS as u32 {
+        let mut word = [0u8; 4];
+        usercopy::copy_from_user(&mut word, ptr.checked_add(i * 4).ok_or(usercopy::EFAULT)?)?;
+        let string = u32::from_le_bytes(word);
+        if string == 0 {
+            return Ok(());
+        }
+        let len = usercopy::strncpy_from_user(&mut buf, string)?;
+        push(&buf[..len])?;
+    }
+    Err("argument list too long")
+}
+
+impl ChainableEvent for SyscallExec {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let path_ptr = context.get_u32("arg1").unwrap_or(0);
+        let argv_ptr = context.get_u32("arg2").unwrap_or(0);
+        let envp_ptr = context.get_u32("arg3").unwrap_or(0);
+        
+        let path = &match file::user_path(path_ptr) {
+            Ok(path) => path,
+            Err(e) => return EventResult::failure(e),
+        };
+        let mut args = crate::exec::Args::default();
+        let copied = user_strings(argv_ptr, |s| args.push_arg(s))
+            .and_then(|_| user_strings(envp_ptr, |s| args.push_env(s)))
+            .and_then(|_| match args.argv.is_empty() {
+                true => args.push_arg(path.as_bytes()),
+                false => Ok(()),
+            });
+        if let Err(e) = copied {
+            return EventResult::failure(e);
+        }
+        match crate::exec::exec_current(path, &args) {
+            Ok(()) => {
                 context.set_u32("result", 0);
+                EventResult::success(())
             }
+            Err(e) => EventResult::failure(e),
         }
-        EventResult::success(())
     }
     
     fn name(&self) -> &'static str {
-        "sys_getpid"
+        "sys_exec"
+    }
+}
+
+/// Wait syscall event
+///
+/// arg1 = child PID (0 = any child), arg2 = pointer for the exit status
+/// (0 = don't store it). Result is the PID of the child collected; the
+/// caller sleeps until one has exited.
+struct SyscallWait;
+
+impl ChainableEvent for SyscallWait {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let pid = context.get_u32("arg1").unwrap_or(0);
+        let status_ptr = context.get_u32("arg2").unwrap_or(0);
+        
+        match crate::exec::wait_child(pid) {
+            Ok(Some((child, code))) => {
+                if status_ptr != 0 {
+                    if let Err(e) = usercopy::copy_to_user(status_ptr, &code.to_le_bytes()) {
+                        return EventResult::failure(e);
+                    }
+                }
+                context.set_u32("result", child);
+                EventResult::success(())
+            }
+            // Blocked: the syscall is restarted when a child exits
+            Ok(None) => {
+                context.set_u32("result", 0);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_wait"
+    }
+}
+
+/// Signal syscall event
+///
+/// arg1 = signal number, arg2 = 1 to ignore it or 0 for the default
+/// action (terminate). Result is the previous setting.
+struct SyscallSignal;
+
+impl ChainableEvent for SyscallSignal {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let signal = context.get_u32("arg1").unwrap_or(0);
+        let ignore = context.get_u32("arg2").unwrap_or(0) != 0;
+        
+        match crate::exec::set_signal(signal, ignore) {
+            Ok(was_ignored) => {
+                context.set_u32("result", was_ignored as u32);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_signal"
+    }
+}
+
+/// Rlimit syscall event
+///
+/// arg1 = resource (0 heap bytes, 1 open files, 2 windows), arg2 = new
+/// limit, arg3 = 1 to set it or 0 to only query. Result is the limit
+/// before the call.
+struct SyscallRlimit;
+
+impl ChainableEvent for SyscallRlimit {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let resource = context.get_u32("arg1").unwrap_or(u32::MAX);
+        let value = context.get_u32("arg2").unwrap_or(0);
+        let set = context.get_u32("arg3").unwrap_or(0) != 0;
+        
+        let Some(resource) = rlimit::Resource::from_u32(resource) else {
+            return EventResult::failure("invalid resource");
+        };
+        let Some(task) = (unsafe { crate::sched::SCHEDULER.current() }) else {
+            return EventResult::failure("no current task");
+        };
+        let limits = unsafe { &mut (*task).limits };
+        let result = if set { limits.set(resource, value) } else { Ok(limits.get(resource)) };
+        match result {
+            Ok(old) => {
+                context.set_u32("result", old);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_rlimit"
     }
 }
 
@@ -199,7 +618,9 @@ struct SyscallYield;
 
 impl ChainableEvent for SyscallYield {
     fn execute(&self, _context: &mut EventContext) -> EventResult<()> {
-        crate::sched::schedule();
+        if !crate::exec::yield_current() {
+            crate::sched::schedule();
+        }
         EventResult::success(())
     }
     
@@ -229,13 +650,23 @@ impl ChainableEvent for SyscallSleep {
 }
 
 /// Time syscall event
+///
+/// arg1 = clock (`time::Clock`): 0 milliseconds since boot, 1 Unix
+/// seconds (UTC), 2 Unix-style seconds in local time. Unknown clocks
+/// read as monotonic.
 struct SyscallTime;
 
+/// Read the clock named by a Time syscall argument
+fn sys_time(clock: u32) -> u32 {
+    let clock = crate::time::Clock::from_u32(clock).unwrap_or(crate::time::Clock::Monotonic);
+    crate::time::now(clock)
+}
+
 impl ChainableEvent for SyscallTime {
     fn execute(&self, context: &mut EventContext) -> EventResult<()> {
-        let uptime = crate::arch::x86::pit::uptime_ms();
-        context.set_u64("result64", uptime as u64);
-        context.set_u32("result", uptime);
+        let now = sys_time(context.get_u32("arg1").unwrap_or(0));
+        context.set_u64("result64", now as u64);
+        context.set_u32("result", now);
         EventResult::success(())
     }
     
@@ -244,6 +675,94 @@ impl ChainableEvent for SyscallTime {
     }
 }
 
+/// Reboot syscall event
+///
+/// Requires `REBOOT_MAGIC` in arg1 so a stray syscall number can't
+/// restart the machine. arg2 selects reboot (0) or power off
+/// (`REBOOT_POWER_OFF`); either way the shutdown chain runs first.
+struct SyscallReboot;
+
+/// Magic value expected in arg1 of the reboot syscall ("RBOT")
+pub const REBOOT_MAGIC: u32 = 0x524F_4254;
+
+/// Reboot syscall arg2: power off instead of restarting
+pub const REBOOT_POWER_OFF: u32 = 1;
+
+impl ChainableEvent for SyscallReboot {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        if context.get_u32("arg1") != Some(REBOOT_MAGIC) {
+            return EventResult::failure("bad reboot magic");
+        }
+        // Carried out by the main loop once this program is switched out
+        let action = match context.get_u32("arg2").unwrap_or(0) {
+            REBOOT_POWER_OFF => crate::shutdown::Action::PowerOff,
+            _ => crate::shutdown::Action::Reboot,
+        };
+        crate::shutdown::request(action);
+        context.set_u32("result", 0);
+        EventResult::success(())
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_reboot"
+    }
+}
+
+/// Sync syscall event
+struct SyscallSync;
+
+impl ChainableEvent for SyscallSync {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        match unsafe { crate::fs::vfs::VFS.sync() } {
+            Ok(()) => {
+                context.set_u32("result", 0);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e.as_str()),
+        }
+    }
+    
+    fn name(&self) -> &'static str {
+        "sys_sync"
+    }
+}
+
+/// GetRandom syscall event
+///
+/// arg1 = buffer, arg2 = length. Fills the buffer from the kernel RNG
+/// and returns the length; never blocks.
+struct SyscallGetRandom;
+
+impl ChainableEvent for SyscallGetRandom {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let buf = context.get_u32("arg1").unwrap_or(0);
+        let len = context.get_u32("arg2").unwrap_or(0);
+
+        if !usercopy::access_ok(buf, len as usize) {
+            return EventResult::failure(usercopy::EFAULT);
+        }
+
+        let mut chunk = [0u8; 256];
+        let mut done = 0u32;
+        while done < len {
+            let n = ((len - done) as usize).min(chunk.len());
+            crate::rng::rand_bytes(&mut chunk[..n]);
+            if let Err(e) = usercopy::copy_to_user(buf + done, &chunk[..n]) {
+                return EventResult::failure(e);
+            }
+            done += n as u32;
+        }
+        chunk.fill(0);
+
+        context.set_u32("result", len);
+        EventResult::success(())
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_getrandom"
+    }
+}
+
 /// Unknown syscall event
 struct SyscallUnknown;
 
@@ -272,15 +791,273 @@ static SYSCALL_EXIT: SyscallExit = SyscallExit;
 static SYSCALL_READ: SyscallRead = SyscallRead;
 static SYSCALL_WRITE: SyscallWrite = SyscallWrite;
 static SYSCALL_GETPID: SyscallGetPid = SyscallGetPid;
+static SYSCALL_FORK: SyscallFork = SyscallFork;
+static SYSCALL_EXEC: SyscallExec = SyscallExec;
+static SYSCALL_WAIT: SyscallWait = SyscallWait;
 static SYSCALL_YIELD: SyscallYield = SyscallYield;
 static SYSCALL_SLEEP: SyscallSleep = SyscallSleep;
 static SYSCALL_TIME: SyscallTime = SyscallTime;
+static SYSCALL_OPEN: SyscallOpen = SyscallOpen;
+static SYSCALL_CLOSE: SyscallClose = SyscallClose;
+static SYSCALL_STAT: SyscallStat = SyscallStat;
+static SYSCALL_READDIR: SyscallReaddir = SyscallReaddir;
+static SYSCALL_REBOOT: SyscallReboot = SyscallReboot;
+static SYSCALL_SYNC: SyscallSync = SyscallSync;
+static SYSCALL_DUP: SyscallDup = SyscallDup;
+static SYSCALL_DUP2: SyscallDup2 = SyscallDup2;
+static SYSCALL_CHDIR: SyscallChdir = SyscallChdir;
+static SYSCALL_GETCWD: SyscallGetcwd = SyscallGetcwd;
+static SYSCALL_BRK: SyscallBrk = SyscallBrk;
+static SYSCALL_SBRK: SyscallSbrk = SyscallSbrk;
+static SYSCALL_SIGNAL: SyscallSignal = SyscallSignal;
+static SYSCALL_IOCTL: SyscallIoctl = SyscallIoctl;
+static SYSCALL_RLIMIT: SyscallRlimit = SyscallRlimit;
+#[cfg(feature = "gui")]
+static SYSCALL_WIN_CREATE: SyscallWinCreate = SyscallWinCreate;
+#[cfg(feature = "gui")]
+static SYSCALL_WIN_DESTROY: SyscallWinDestroy = SyscallWinDestroy;
+#[cfg(feature = "gui")]
+static SYSCALL_WIN_PRESENT: SyscallWinPresent = SyscallWinPresent;
+#[cfg(feature = "gui")]
+static SYSCALL_WIN_EVENT: SyscallWinEvent = SyscallWinEvent;
+static SYSCALL_SHM_CREATE: SyscallShmCreate = SyscallShmCreate;
+static SYSCALL_SHM_MAP: SyscallShmMap = SyscallShmMap;
+static SYSCALL_SHM_UNMAP: SyscallShmUnmap = SyscallShmUnmap;
+static SYSCALL_SHM_DESTROY: SyscallShmDestroy = SyscallShmDestroy;
+#[cfg(feature = "gui")]
+static SYSCALL_WIN_ATTACH: SyscallWinAttach = SyscallWinAttach;
+static SYSCALL_PORT_CREATE: SyscallPortCreate = SyscallPortCreate;
+static SYSCALL_PORT_LOOKUP: SyscallPortLookup = SyscallPortLookup;
+static SYSCALL_PORT_DESTROY: SyscallPortDestroy = SyscallPortDestroy;
+static SYSCALL_PORT_SEND: SyscallPortSend = SyscallPortSend;
+static SYSCALL_PORT_RECEIVE: SyscallPortReceive = SyscallPortReceive;
+static SYSCALL_FSYNC: SyscallFsync = SyscallFsync;
+static SYSCALL_GET_RANDOM: SyscallGetRandom = SyscallGetRandom;
 static SYSCALL_UNKNOWN: SyscallUnknown = SyscallUnknown;
 
+/// Build the standard syscall chain around a single event
+///
+/// Evaluated at compile time for each syscall's static chain.
+const fn syscall_chain(event: &'static dyn ChainableEvent) -> EventChain<'static> {
+    EventChain::new()
+        .middleware(&LOGGING_MW)
+        .middleware(&PERMISSION_MW)
+        .middleware(&AUDIT_MW)
+        .event(event)
+        .with_fault_tolerance(FaultToleranceMode::Strict)
+}
+
+/// Prebuilt syscall chains (constructed once, reused on every dispatch)
+static EXIT_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_EXIT);
+static READ_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_READ);
+static WRITE_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_WRITE);
+static GETPID_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_GETPID);
+static FORK_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_FORK);
+static EXEC_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_EXEC);
+static WAIT_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_WAIT);
+static YIELD_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_YIELD);
+static SLEEP_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_SLEEP);
+static TIME_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_TIME);
+static OPEN_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_OPEN);
+static CLOSE_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_CLOSE);
+static STAT_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_STAT);
+static READDIR_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_READDIR);
+static REBOOT_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_REBOOT);
+static SYNC_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_SYNC);
+static DUP_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_DUP);
+static DUP2_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_DUP2);
+static CHDIR_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_CHDIR);
+static GETCWD_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_GETCWD);
+static BRK_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_BRK);
+static SBRK_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_SBRK);
+static SIGNAL_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_SIGNAL);
+static IOCTL_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_IOCTL);
+static RLIMIT_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_RLIMIT);
+#[cfg(feature = "gui")]
+static WIN_CREATE_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_WIN_CREATE);
+#[cfg(feature = "gui")]
+static WIN_DESTROY_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_WIN_DESTROY);
+#[cfg(feature = "gui")]
+static WIN_PRESENT_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_WIN_PRESENT);
+#[cfg(feature = "gui")]
+static WIN_EVENT_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_WIN_EVENT);
+static SHM_CREATE_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_SHM_CREATE);
+static SHM_MAP_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_SHM_MAP);
+static SHM_UNMAP_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_SHM_UNMAP);
+static SHM_DESTROY_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_SHM_DESTROY);
+#[cfg(feature = "gui")]
+static WIN_ATTACH_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_WIN_ATTACH);
+static PORT_CREATE_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_PORT_CREATE);
+static PORT_LOOKUP_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_PORT_LOOKUP);
+static PORT_DESTROY_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_PORT_DESTROY);
+static PORT_SEND_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_PORT_SEND);
+static PORT_RECEIVE_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_PORT_RECEIVE);
+static FSYNC_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_FSYNC);
+static GET_RANDOM_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_GET_RANDOM);
+static UNKNOWN_CHAIN: EventChain<'static> = syscall_chain(&SYSCALL_UNKNOWN);
+
+// ============================================================================
+// Fast Path
+// ============================================================================
+
+/// Direct syscall handler (no context, no middleware)
+pub type FastHandler = fn(&SyscallParams) -> u32;
+
+fn fast_getpid(_params: &SyscallParams) -> u32 {
+    sys_getpid()
+}
+
+fn fast_yield(_params: &SyscallParams) -> u32 {
+    if !crate::exec::yield_current() {
+        crate::sched::schedule();
+    }
+    0
+}
+
+fn fast_time(params: &SyscallParams) -> u32 {
+    sys_time(params.arg1)
+}
+
+/// Direct handler table, indexed by syscall number
+///
+/// Only syscalls with an entry here can be switched to the fast path.
+static FAST_TABLE: [Option<FastHandler>; NUM_SYSCALLS] = [
+    None,               // Exit
+    None,               // Read
+    None,               // Write
+    None,               // Open
+    None,               // Close
+    Some(fast_getpid),  // GetPid
+    None,               // Fork
+    None,               // Exec
+    None,               // Wait
+    None,               // Pipe
+    None,               // Mmap
+    None,               // Munmap
+    Some(fast_yield),   // Yield
+    None,               // Sleep
+    Some(fast_time),    // Time
+    None,               // Stat
+    None,               // Readdir
+    None,               // Reboot
+    None,               // Sync
+    None,               // Dup
+    None,               // Dup2
+    None,               // Chdir
+    None,               // Getcwd
+    None,               // Brk
+    None,               // Sbrk
+    None,               // Signal
+    None,               // Ioctl
+    None,               // Rlimit
+    None,               // WinCreate
+    None,               // WinDestroy
+    None,               // WinPresent
+    None,               // WinEvent
+    None,               // ShmCreate
+    None,               // ShmMap
+    None,               // ShmUnmap
+    None,               // ShmDestroy
+    None,               // WinAttach
+    None,               // PortCreate
+    None,               // PortLookup
+    None,               // PortDestroy
+    None,               // PortSend
+    None,               // PortReceive
+    None,               // Fsync
+    None,               // GetRandom
+];
+
+/// Per-syscall dispatch path (initialized from `default_path`)
+static mut DISPATCH_PATHS: [DispatchPath; NUM_SYSCALLS] = {
+    let mut paths = [DispatchPath::Chain; NUM_SYSCALLS];
+    paths[SyscallNumber::GetPid as usize] = SyscallNumber::GetPid.default_path();
+    paths[SyscallNumber::Yield as usize] = SyscallNumber::Yield.default_path();
+    paths[SyscallNumber::Time as usize] = SyscallNumber::Time.default_path();
+    paths
+};
+
+/// One in every N fast-path calls is routed through the full chain so
+/// logging and audit middleware still see a sample of hot syscalls.
+const AUDIT_SAMPLE_INTERVAL: u32 = 64;
+
+/// Calls eligible for the fast path (drives audit sampling)
+static FAST_ELIGIBLE: AtomicU32 = AtomicU32::new(0);
+
+/// Calls that took the fast path (sampled ones went through the chain)
+static FAST_CALLS: AtomicU32 = AtomicU32::new(0);
+
+/// Select the dispatch path for a syscall
+///
+/// Returns false if the syscall has no fast handler and so cannot be
+/// switched to the fast path.
+pub fn set_dispatch_path(number: SyscallNumber, path: DispatchPath) -> bool {
+    let idx = number as usize;
+    if idx >= NUM_SYSCALLS {
+        return false;
+    }
+    if path == DispatchPath::Fast && FAST_TABLE[idx].is_none() {
+        return false;
+    }
+    unsafe {
+        DISPATCH_PATHS[idx] = path;
+    }
+    true
+}
+
+/// Get the current dispatch path for a syscall
+pub fn dispatch_path(number: SyscallNumber) -> DispatchPath {
+    let idx = number as usize;
+    if idx >= NUM_SYSCALLS {
+        return DispatchPath::Chain;
+    }
+    unsafe { DISPATCH_PATHS[idx] }
+}
+
+/// Total number of calls that took the fast path
+pub fn fast_call_count() -> u32 {
+    FAST_CALLS.load(Ordering::Relaxed)
+}
+
+/// Try to handle a syscall on the fast path
+///
+/// Returns None when the syscall must go through the chain, either
+/// because it is not marked Fast or because this call was picked for
+/// audit sampling.
+fn try_fast_path(params: &SyscallParams) -> Option<u32> {
+    let idx = params.number as usize;
+    if idx >= NUM_SYSCALLS || dispatch_path(params.number) != DispatchPath::Fast {
+        return None;
+    }
+    let handler = FAST_TABLE[idx]?;
+
+    let n = FAST_ELIGIBLE.fetch_add(1, Ordering::Relaxed);
+    if n % AUDIT_SAMPLE_INTERVAL == 0 {
+        return None;
+    }
+
+    FAST_CALLS.fetch_add(1, Ordering::Relaxed);
+    Some(handler(params))
+}
+
 /// Handle a system call
 ///
 /// This is called from the interrupt handler for INT 0x80.
+/// Hot syscalls take the direct fast path; everything else (and a
+/// sample of hot calls) goes through the middleware chain.
 pub fn handle_syscall(params: SyscallParams) -> u32 {
+    crate::trace!(SyscallEntry, params.number as u32);
+    let result = dispatch_syscall(params);
+    crate::trace!(SyscallExit, result);
+    result
+}
+
+/// Route a syscall to the fast path or its middleware chain
+fn dispatch_syscall(params: SyscallParams) -> u32 {
+    if let Some(result) = try_fast_path(&params) {
+        return result;
+    }
+    
     // Set up context with syscall parameters
     let mut context = EventContext::new();
     context.set_u32("syscall_number", params.number as u32);
@@ -291,26 +1068,57 @@ pub fn handle_syscall(params: SyscallParams) -> u32 {
     context.set_u32("arg5", params.arg5);
     context.set_u32("ring", 3); // User mode
     
-    // Get the appropriate syscall event
-    let event: &dyn ChainableEvent = match params.number {
-        SyscallNumber::Exit => &SYSCALL_EXIT,
-        SyscallNumber::Read => &SYSCALL_READ,
-        SyscallNumber::Write => &SYSCALL_WRITE,
-        SyscallNumber::GetPid => &SYSCALL_GETPID,
-        SyscallNumber::Yield => &SYSCALL_YIELD,
-        SyscallNumber::Sleep => &SYSCALL_SLEEP,
-        SyscallNumber::Time => &SYSCALL_TIME,
-        _ => &SYSCALL_UNKNOWN,
+    // Get the prebuilt chain for this syscall
+    let chain: &EventChain<'static> = match params.number {
+        SyscallNumber::Exit => &EXIT_CHAIN,
+        SyscallNumber::Read => &READ_CHAIN,
+        SyscallNumber::Write => &WRITE_CHAIN,
+        SyscallNumber::GetPid => &GETPID_CHAIN,
+        SyscallNumber::Fork => &FORK_CHAIN,
+        SyscallNumber::Exec => &EXEC_CHAIN,
+        SyscallNumber::Wait => &WAIT_CHAIN,
+        SyscallNumber::Yield => &YIELD_CHAIN,
+        SyscallNumber::Sleep => &SLEEP_CHAIN,
+        SyscallNumber::Time => &TIME_CHAIN,
+        SyscallNumber::Open => &OPEN_CHAIN,
+        SyscallNumber::Close => &CLOSE_CHAIN,
+        SyscallNumber::Stat => &STAT_CHAIN,
+        SyscallNumber::Readdir => &READDIR_CHAIN,
+        SyscallNumber::Reboot => &REBOOT_CHAIN,
+        SyscallNumber::Sync => &SYNC_CHAIN,
+        SyscallNumber::Dup => &DUP_CHAIN,
+        SyscallNumber::Dup2 => &DUP2_CHAIN,
+        SyscallNumber::Chdir => &CHDIR_CHAIN,
+        SyscallNumber::Getcwd => &GETCWD_CHAIN,
+        SyscallNumber::Brk => &BRK_CHAIN,
+        SyscallNumber::Sbrk => &SBRK_CHAIN,
+        SyscallNumber::Signal => &SIGNAL_CHAIN,
+        SyscallNumber::Ioctl => &IOCTL_CHAIN,
+        SyscallNumber::Rlimit => &RLIMIT_CHAIN,
+        #[cfg(feature = "gui")]
+        SyscallNumber::WinCreate => &WIN_CREATE_CHAIN,
+        #[cfg(feature = "gui")]
+        SyscallNumber::WinDestroy => &WIN_DESTROY_CHAIN,
+        #[cfg(feature = "gui")]
+        SyscallNumber::WinPresent => &WIN_PRESENT_CHAIN,
+        #[cfg(feature = "gui")]
+        SyscallNumber::WinEvent => &WIN_EVENT_CHAIN,
+        SyscallNumber::ShmCreate => &SHM_CREATE_CHAIN,
+        SyscallNumber::ShmMap => &SHM_MAP_CHAIN,
+        SyscallNumber::ShmUnmap => &SHM_UNMAP_CHAIN,
+        SyscallNumber::ShmDestroy => &SHM_DESTROY_CHAIN,
+        #[cfg(feature = "gui")]
+        SyscallNumber::WinAttach => &WIN_ATTACH_CHAIN,
+        SyscallNumber::PortCreate => &PORT_CREATE_CHAIN,
+        SyscallNumber::PortLookup => &PORT_LOOKUP_CHAIN,
+        SyscallNumber::PortDestroy => &PORT_DESTROY_CHAIN,
+        SyscallNumber::PortSend => &PORT_SEND_CHAIN,
+        SyscallNumber::PortReceive => &PORT_RECEIVE_CHAIN,
+        SyscallNumber::Fsync => &FSYNC_CHAIN,
+        SyscallNumber::GetRandom => &GET_RANDOM_CHAIN,
+        _ => &UNKNOWN_CHAIN,
     };
     
-    // Build the event chain with middleware
-    let chain = EventChain::new()
-        .middleware(&LOGGING_MW)
-        .middleware(&PERMISSION_MW)
-        .middleware(&AUDIT_MW)
-        .event(event)
-        .with_fault_tolerance(FaultToleranceMode::Strict);
-    
     // Execute the chain
     let result = chain.execute(&mut context);
     
@@ -318,13 +1126,11 @@ pub fn handle_syscall(params: SyscallParams) -> u32 {
     if result.success {
         context.get_u32("result").unwrap_or(0)
     } else {
-        u32::MAX // -1 on error
+        error_code(&result)
     }
 }
 
 /// Initialize syscall handling
 pub fn init() {
-    // Set up INT 0x80 handler
-    // This would be done in the IDT setup
-    // For now, syscalls are dispatched manually
+    // INT 0x80 is installed with the rest of the IDT (idt::SYSCALL_VECTOR)
 }
@@ -0,0 +1,66 @@
+//! Terminal Syscalls
+//!
+//! Ioctl on a descriptor connected to a tty reads or changes its line
+//! discipline mode, the way programs call tcgetattr/tcsetattr. Only the
+//! local mode flags (`tty::lflag`) are supported.
+
+use crate::event_chains::{ChainableEvent, EventContext, result::EventResult};
+use super::usercopy;
+
+/// Ioctl requests (Linux numbers)
+pub mod request {
+    /// Read the mode: arg3 points to a u32 that receives the lflag bits
+    pub const TCGETS: u32 = 0x5401;
+    /// Set the mode: arg3 points to a u32 holding the new lflag bits
+    pub const TCSETS: u32 = 0x5402;
+}
+
+/// Tty behind a descriptor: fd 0-2 of a program with a controlling tty,
+/// unless redirected to a file
+fn fd_tty(fd: u32) -> Result<usize, &'static str> {
+    if fd >= crate::fs::vfs::FIRST_FD || super::file::stdio_target(fd).is_some() {
+        return Err("not a tty");
+    }
+    crate::exec::current_tty().ok_or("not a tty")
+}
+
+/// Ioctl syscall event
+///
+/// arg1 = fd, arg2 = request, arg3 = argument pointer.
+pub struct SyscallIoctl;
+
+impl ChainableEvent for SyscallIoctl {
+    fn execute(&self, context: &mut EventContext) -> EventResult<()> {
+        let fd = context.get_u32("arg1").unwrap_or(0);
+        let req = context.get_u32("arg2").unwrap_or(0);
+        let arg = context.get_u32("arg3").unwrap_or(0);
+
+        let tty = match fd_tty(fd) {
+            Ok(tty) => tty,
+            Err(e) => return EventResult::failure(e),
+        };
+        let result = match req {
+            request::TCGETS => {
+                let flags = crate::tty::lflag(tty).unwrap_or(0);
+                usercopy::copy_to_user(arg, &flags.to_le_bytes())
+            }
+            request::TCSETS => {
+                let mut bytes = [0u8; 4];
+                usercopy::copy_from_user(&mut bytes, arg)
+                    .and_then(|_| crate::tty::set_lflag(tty, u32::from_le_bytes(bytes)))
+            }
+            _ => Err("unsupported ioctl"),
+        };
+        match result {
+            Ok(()) => {
+                context.set_u32("result", 0);
+                EventResult::success(())
+            }
+            Err(e) => EventResult::failure(e),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "sys_ioctl"
+    }
+}
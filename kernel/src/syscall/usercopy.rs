@@ -0,0 +1,155 @@
+//! User Memory Access
+//!
+//! Every pointer handed to a syscall comes from user space and must be
+//! checked before the kernel touches it. These helpers validate that a
+//! range lies entirely within the calling task's address space, then
+//! perform the copy.
+//!
+//! The range check keeps a bad pointer away from the kernel's own data
+//! (identity mapped and writable from ring 0 in every address space), but
+//! a pointer inside the range can still be unmapped, or read-only when
+//! the kernel writes to it. The copy itself (`user_copy`) is one
+//! `rep movsb` the page fault handler knows about: a fault there that
+//! isn't a lazy or copy-on-write page resumes after it with the count
+//! left, and the helper returns EFAULT instead of the kernel halting.
+
+/// Start of the default user address range (above the identity-mapped RAM)
+pub const USER_SPACE_START: u32 = crate::mm::paging::KERNEL_SPACE_END; // 256MB
+
+/// End of the default user address range (exclusive; the kernel's
+/// device window starts here)
+pub const USER_SPACE_END: u32 = crate::mm::paging::IOMAP_BASE; // 2.875GB
+
+/// Error returned for any invalid user pointer
+pub const EFAULT: &str = "bad user address";
+
+/// Error returned when a user string is not terminated in time
+pub const ENAMETOOLONG: &str = "user string too long";
+
+extern "C" {
+    /// Copy `len` bytes; returns how many were left when a fault stopped
+    /// it (0 when the whole range was copied)
+    fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+
+    /// The faulting instruction in `user_copy`, and where to resume
+    static user_copy_insn: u8;
+    static user_copy_done: u8;
+}
+
+// cdecl: the arguments are above the return address and the two saved
+// registers. A fault during `rep movsb` leaves ECX at the bytes not yet
+// copied, which is what the fixup returns.
+core::arch::global_asm!(
+    ".global user_copy",
+    "user_copy:",
+    "    push esi",
+    "    push edi",
+    "    mov edi, [esp + 12]",
+    "    mov esi, [esp + 16]",
+    "    mov ecx, [esp + 20]",
+    ".global user_copy_insn",
+    "user_copy_insn:",
+    "    rep movsb",
+    ".global user_copy_done",
+    "user_copy_done:",
+    "    mov eax, ecx",
+    "    pop edi",
+    "    pop esi",
+    "    ret",
+);
+
+/// Where to resume after a page fault at `eip`, if it was in a user copy
+/// (page fault handler)
+pub fn fixup(eip: u32) -> Option<u32> {
+    let insn = core::ptr::addr_of!(user_copy_insn) as u32;
+    (eip == insn).then(|| core::ptr::addr_of!(user_copy_done) as u32)
+}
+
+/// Get the user address range for the calling task
+fn user_range() -> (u32, u32) {
+    unsafe {
+        match crate::sched::SCHEDULER.current() {
+            Some(task) => ((*task).user_base, (*task).user_limit),
+            None => (USER_SPACE_START, USER_SPACE_END),
+        }
+    }
+}
+
+/// Check that `[addr, addr + len)` lies within the caller's address space
+///
+/// Zero-length ranges are accepted as long as `addr` itself is valid.
+pub fn access_ok(addr: u32, len: usize) -> bool {
+    let (base, limit) = user_range();
+
+    if addr < base || addr >= limit {
+        return false;
+    }
+
+    let len = match u32::try_from(len) {
+        Ok(len) => len,
+        Err(_) => return false,
+    };
+
+    match addr.checked_add(len) {
+        Some(end) => end <= limit,
+        None => false,
+    }
+}
+
+/// Copy `dst.len()` bytes from user address `src` into a kernel buffer
+pub fn copy_from_user(dst: &mut [u8], src: u32) -> Result<(), &'static str> {
+    if !access_ok(src, dst.len()) {
+        return Err(EFAULT);
+    }
+
+    match unsafe { user_copy(dst.as_mut_ptr(), src as *const u8, dst.len()) } {
+        0 => Ok(()),
+        _ => Err(EFAULT),
+    }
+}
+
+/// Copy a kernel buffer to user address `dst`
+pub fn copy_to_user(dst: u32, src: &[u8]) -> Result<(), &'static str> {
+    if !access_ok(dst, src.len()) {
+        return Err(EFAULT);
+    }
+
+    match unsafe { user_copy(dst as *mut u8, src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(EFAULT),
+    }
+}
+
+/// Copy a NUL-terminated string from user address `src`
+///
+/// Copies at most `dst.len()` bytes and returns the string length
+/// (excluding the terminator). The terminator is copied if it fits.
+/// Each byte is validated before it is read, so a string running off
+/// the end of the address space (or into an unmapped page) fails with
+/// EFAULT.
+pub fn strncpy_from_user(dst: &mut [u8], src: u32) -> Result<usize, &'static str> {
+    let (base, limit) = user_range();
+
+    if src < base || src >= limit {
+        return Err(EFAULT);
+    }
+
+    for i in 0..dst.len() {
+        let addr = match src.checked_add(i as u32) {
+            Some(addr) if addr < limit => addr,
+            _ => return Err(EFAULT),
+        };
+
+        let mut byte = 0u8;
+        if unsafe { user_copy(&mut byte, addr as *const u8, 1) } != 0 {
+            return Err(EFAULT);
+        }
+        dst[i] = byte;
+
+        if byte == 0 {
+            return Ok(i);
+        }
+    }
+
+    Err(ENAMETOOLONG)
+}
@@ -0,0 +1,190 @@
+//! Text-Mode Shell
+//!
+//! A plain line editor over the VGA text console that runs the kernel
+//! command registry and follows the kernel log. Used two ways:
+//!
+//! - As the fallback when the GUI can't start (no VESA mode from the
+//!   bootloader, or the driver chain left no usable framebuffer): `run`
+//!   restores text mode if needed, prints the driver init report and
+//!   keeps the machine diagnosable instead of hanging on a dead screen.
+//! - As the text virtual console (`vt`), driven from the GUI loop.
+
+use alloc::string::String;
+use alloc::vec;
+use core::fmt::Write;
+use crate::drivers::vga;
+use crate::drivers::keyboard::{self, BufferedKey, KeyCode};
+
+/// Longest command line
+const INPUT_MAX: usize = 76;
+
+/// Text console width
+const COLUMNS: usize = 80;
+
+/// Text console height
+const ROWS: usize = 25;
+
+/// Log lines shown when the console is brought up
+const LOG_LINES: usize = 20;
+
+/// Command output to the VGA writer
+struct Console;
+
+impl crate::commands::Output for Console {
+    fn print(&mut self, line: &str) {
+        let _ = writeln!(writer(), "{}", line);
+    }
+
+    fn columns(&self) -> usize {
+        COLUMNS
+    }
+
+    fn rows(&self) -> usize {
+        ROWS
+    }
+}
+
+fn writer() -> &'static mut vga::Writer {
+    unsafe { vga::WRITER.as_mut().expect("VGA writer not initialized") }
+}
+
+/// Shell state
+pub struct TextShell {
+    input: String,
+    /// Kernel log position already on screen
+    log_seen: usize,
+}
+
+impl TextShell {
+    pub fn new() -> Self {
+        Self {
+            input: String::with_capacity(INPUT_MAX),
+            log_seen: crate::klog::written(),
+        }
+    }
+
+    /// Clear the screen, show the end of the kernel log and the prompt
+    pub fn show(&mut self) {
+        writer().clear();
+        let mut buf = vec![0u8; crate::klog::KLOG_SIZE];
+        let n = crate::klog::tail(&mut buf);
+        let text = String::from_utf8_lossy(&buf[..n]);
+        let skip = text.lines().count().saturating_sub(LOG_LINES);
+        for line in text.lines().skip(skip) {
+            writer().write_string(line);
+            writer().write_byte(b'\n');
+        }
+        self.log_seen = crate::klog::written();
+        self.prompt();
+    }
+
+    /// Print the prompt and any input typed so far
+    fn prompt(&mut self) {
+        writer().write_string("> ");
+        writer().write_string(&self.input);
+    }
+
+    /// Handle a key press
+    pub fn key(&mut self, key: BufferedKey) {
+        match key.keycode {
+            KeyCode::Enter | KeyCode::KeypadEnter => {
+                writer().write_byte(b'\n');
+                let line = core::mem::take(&mut self.input);
+                execute(line.trim(), &mut Console);
+                // Command output is logged too; don't echo it twice
+                self.log_seen = crate::klog::written();
+                self.prompt();
+            }
+            KeyCode::Backspace => {
+                if self.input.pop().is_some() {
+                    writer().backspace();
+                }
+            }
+            _ => {
+                if let Some(c) = key.ascii {
+                    if self.input.len() < INPUT_MAX && (' '..='~').contains(&c) {
+                        self.input.push(c);
+                        writer().write_byte(c as u8);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Show kernel log output written since the last call
+    pub fn poll(&mut self) {
+        let written = crate::klog::written();
+        if written == self.log_seen {
+            return;
+        }
+        let mut buf = vec![0u8; (written - self.log_seen).min(crate::klog::KLOG_SIZE)];
+        let n = crate::klog::tail(&mut buf);
+        self.log_seen = written;
+
+        if !writer().at_line_start() {
+            writer().write_byte(b'\n');
+        }
+        writer().write_string(&String::from_utf8_lossy(&buf[..n]));
+        if !writer().at_line_start() {
+            writer().write_byte(b'\n');
+        }
+        self.prompt();
+    }
+}
+
+/// Run the fallback shell (never returns)
+///
+/// `restore` switches the display back to text mode first, for when a
+/// graphics mode is still set; `reason` says why the GUI was abandoned.
+pub fn run(restore: bool, reason: &str) -> ! {
+    if restore {
+        unsafe { vga::restore_text_mode() };
+    }
+
+    let mut console = Console;
+    let out: &mut dyn crate::commands::Output = &mut console;
+    out.print(&alloc::format!("[TEXT] {}", reason));
+    out.print("[TEXT] Driver init report:");
+    let mut report = String::new();
+    crate::drivers::report::report(&mut report);
+    for line in report.lines() {
+        out.print(line);
+    }
+    out.print("");
+    out.print("Text-mode shell. Type 'help' for commands.");
+
+    let mut shell = TextShell::new();
+    shell.prompt();
+
+    loop {
+        // Keys arrive through the keyboard IRQ
+        while let Some(key) = keyboard::get_key() {
+            shell.key(key);
+        }
+        shell.poll();
+
+        // Reap programs, carry out a requested shutdown, apply hotkeys
+        crate::exec::poll();
+        crate::shutdown::poll();
+        crate::drivers::accessibility::poll();
+
+        crate::arch::x86::idle::halt();
+    }
+}
+
+/// Shell-only commands, then the registry
+fn execute(cmd: &str, out: &mut dyn crate::commands::Output) {
+    match cmd {
+        "" => {}
+        "help" => {
+            out.print("Commands: help clear");
+            crate::commands::help(out);
+        }
+        "clear" => writer().clear(),
+        _ => {
+            if !crate::commands::execute(cmd, out) {
+                out.print("Unknown cmd. Try 'help'");
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Timekeeping
+//!
+//! Three clocks, selected by the Time syscall's clock argument:
+//!
+//! - `Clock::Monotonic`: milliseconds since boot, never set or adjusted
+//! - `Clock::Realtime`: Unix seconds (UTC), from the RTC at boot plus the
+//!   PIT since, and settable
+//! - `Clock::Local`: `Realtime` shifted by the UTC offset, for display
+//!
+//! The UTC offset is a settings value (`utc_offset`, minutes east of
+//! UTC). Calendar conversion covers 1970-2105 (what fits a u32 of
+//! seconds), and the exFAT helpers convert the on-disk timestamp format.
+//!
+//! `now_ns` is for timing short intervals (profiling, frame pacing); it
+//! has no clock ID.
+
+use crate::arch::x86::{pit, rtc, tsc};
+
+/// Largest UTC offset in minutes (UTC+14:00)
+pub const MAX_UTC_OFFSET: i32 = 14 * 60;
+
+/// Smallest UTC offset in minutes (UTC-12:00)
+pub const MIN_UTC_OFFSET: i32 = -12 * 60;
+
+/// Clock IDs for the Time syscall
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Clock {
+    Monotonic = 0,
+    Realtime = 1,
+    Local = 2,
+}
+
+impl Clock {
+    pub fn from_u32(n: u32) -> Option<Self> {
+        match n {
+            0 => Some(Self::Monotonic),
+            1 => Some(Self::Realtime),
+            2 => Some(Self::Local),
+            _ => None,
+        }
+    }
+}
+
+/// Unix time at uptime 0
+static mut BOOT_EPOCH: u32 = 0;
+
+/// Take the wall clock from the RTC (call once the PIT is running)
+pub fn init() -> Option<DateTime> {
+    let now = rtc::read()?;
+    unsafe { BOOT_EPOCH = now.to_unix().saturating_sub(pit::uptime_secs()) };
+    Some(now)
+}
+
+/// Milliseconds since boot
+pub fn monotonic_ms() -> u32 {
+    pit::uptime_ms()
+}
+
+/// Nanoseconds since boot, for timing short intervals (see `arch::x86::tsc`)
+pub fn now_ns() -> u64 {
+    tsc::now_ns()
+}
+
+/// Unix seconds (0-based from boot if the RTC couldn't be read)
+pub fn realtime() -> u32 {
+    unsafe { BOOT_EPOCH }.wrapping_add(pit::uptime_secs())
+}
+
+/// Set the wall clock to a Unix time
+pub fn set_realtime(unix: u32) {
+    unsafe { BOOT_EPOCH = unix.wrapping_sub(pit::uptime_secs()) };
+}
+
+/// Minutes east of UTC (from settings)
+pub fn utc_offset() -> i32 {
+    crate::settings::get().utc_offset
+}
+
+/// Local time as seconds since 1970-01-01 00:00 local
+pub fn local() -> u32 {
+    realtime().saturating_add_signed(utc_offset() * 60)
+}
+
+/// Read a clock
+pub fn now(clock: Clock) -> u32 {
+    match clock {
+        Clock::Monotonic => monotonic_ms(),
+        Clock::Realtime => realtime(),
+        Clock::Local => local(),
+    }
+}
+
+/// Current local date and time
+pub fn local_now() -> DateTime {
+    DateTime::from_unix(local())
+}
+
+// =============================================================================
+// Calendar
+// =============================================================================
+
+/// A calendar date and time of day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn is_leap(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    /// Are all fields in range (and the year representable)?
+    pub fn is_valid(&self) -> bool {
+        (1970..=2105).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Seconds since 1970-01-01 00:00:00
+    pub fn to_unix(&self) -> u32 {
+        // Days from civil (Howard Hinnant's algorithm, March-based years)
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let month = self.month as i64;
+        let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        let secs = days * 86_400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        secs.clamp(0, u32::MAX as i64) as u32
+    }
+
+    /// Date and time of a count of seconds since 1970-01-01 00:00:00
+    pub fn from_unix(secs: u32) -> Self {
+        let days = (secs / 86_400) as i64 + 719_468;
+        let rem = secs % 86_400;
+        let era = days.div_euclid(146_097);
+        let doe = days - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + (month <= 2) as i64) as u16;
+        Self {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+}
+
+impl core::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// Parse a UTC offset such as "+05:30", "-8" or "0" into minutes
+pub fn parse_utc_offset(text: &str) -> Option<i32> {
+    let (sign, rest) = match text.as_bytes().first()? {
+        b'-' => (-1, &text[1..]),
+        b'+' => (1, &text[1..]),
+        _ => (1, text),
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if !(0..60).contains(&minutes) {
+        return None;
+    }
+    let offset = sign * (hours * 60 + minutes);
+    (MIN_UTC_OFFSET..=MAX_UTC_OFFSET).contains(&offset).then_some(offset)
+}
+
+/// Format a UTC offset as "+HH:MM"
+pub fn format_utc_offset(offset: i32) -> impl core::fmt::Display {
+    struct Offset(i32);
+    impl core::fmt::Display for Offset {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            let sign = if self.0 < 0 { '-' } else { '+' };
+            write!(f, "{}{:02}:{:02}", sign, self.0.abs() / 60, self.0.abs() % 60)
+        }
+    }
+    Offset(offset)
+}
+
+// =============================================================================
+// exFAT Timestamps
+// =============================================================================
+
+/// UTC offset byte: the offset field is valid
+const EXFAT_OFFSET_VALID: u8 = 0x80;
+
+/// Convert an exFAT timestamp to Unix seconds (UTC)
+///
+/// `timestamp` packs local date and time (2-second resolution),
+/// `ten_ms` adds 0-199 hundredths (so odd seconds), and `offset_byte`
+/// gives the offset of that local time in 15-minute steps when its top
+/// bit is set. Without a valid offset, the current setting is assumed.
+pub fn from_exfat(timestamp: u32, ten_ms: u8, offset_byte: u8) -> u32 {
+    let time = DateTime {
+        year: 1980 + (timestamp >> 25) as u16,
+        month: ((timestamp >> 21) & 0x0F) as u8,
+        day: ((timestamp >> 16) & 0x1F) as u8,
+        hour: ((timestamp >> 11) & 0x1F) as u8,
+        minute: ((timestamp >> 5) & 0x3F) as u8,
+        second: ((timestamp & 0x1F) * 2) as u8,
+    };
+    if !time.is_valid() {
+        return 0;
+    }
+    let offset = if offset_byte & EXFAT_OFFSET_VALID != 0 {
+        // 7-bit two's complement count of 15 minutes
+        (((offset_byte << 1) as i8) >> 1) as i32 * 15
+    } else {
+        utc_offset()
+    };
+    (time.to_unix() + (ten_ms / 100) as u32).saturating_add_signed(-offset * 60)
+}
+
+/// Convert Unix seconds to exFAT (timestamp, 10ms increment, UTC offset),
+/// stamped in the current local time
+pub fn to_exfat(unix: u32) -> (u32, u8, u8) {
+    let offset = utc_offset();
+    let local = DateTime::from_unix(unix.saturating_add_signed(offset * 60));
+    let year = local.year.saturating_sub(1980).min(127) as u32;
+    let timestamp = year << 25
+        | (local.month as u32) << 21
+        | (local.day as u32) << 16
+        | (local.hour as u32) << 11
+        | (local.minute as u32) << 5
+        | (local.second as u32) / 2;
+    let ten_ms = (local.second % 2) * 100;
+    let offset_byte = EXFAT_OFFSET_VALID | ((offset / 15) as i8 as u8 & 0x7F);
+    (timestamp, ten_ms, offset_byte)
+}
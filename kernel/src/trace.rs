@@ -0,0 +1,229 @@
+//! Kernel Event Tracing
+//!
+//! Fixed ring of `(timestamp, event, arg)` records for performance analysis
+//! on real hardware. Timestamps come from the TSC, so the cost of an enabled
+//! trace point is an `rdtsc` plus a 16-byte store with interrupts briefly
+//! masked; a disabled trace point is a single relaxed load.
+//!
+//! Instrument code with the `trace!` macro:
+//!
+//! ```ignore
+//! trace!(IrqEntry, irq as u32);
+//! ```
+//!
+//! The buffer is readable as `/proc/trace` and can be streamed to COM1 with
+//! `dump_serial()`. Tracing starts disabled; enable it with `set_enabled`.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Number of records kept (oldest are overwritten)
+pub const TRACE_RECORDS: usize = 1024;
+
+/// Trace point identifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum TraceEvent {
+    /// Hardware IRQ handler entered (arg = IRQ line)
+    IrqEntry = 1,
+    /// Hardware IRQ handler finished (arg = IRQ line)
+    IrqExit = 2,
+    /// System call entered (arg = syscall number)
+    SyscallEntry = 3,
+    /// System call returned (arg = return value)
+    SyscallExit = 4,
+    /// Scheduler switched tasks (arg = old pid << 16 | new pid)
+    ContextSwitch = 5,
+    /// Free-form marker for ad hoc instrumentation
+    Mark = 6,
+}
+
+impl TraceEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::IrqEntry => "irq_entry",
+            Self::IrqExit => "irq_exit",
+            Self::SyscallEntry => "sys_entry",
+            Self::SyscallExit => "sys_exit",
+            Self::ContextSwitch => "switch",
+            Self::Mark => "mark",
+        }
+    }
+
+    fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            1 => Some(Self::IrqEntry),
+            2 => Some(Self::IrqExit),
+            3 => Some(Self::SyscallEntry),
+            4 => Some(Self::SyscallExit),
+            5 => Some(Self::ContextSwitch),
+            6 => Some(Self::Mark),
+            _ => None,
+        }
+    }
+}
+
+/// One trace record
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TraceRecord {
+    /// TSC value when the event was recorded
+    pub tsc: u64,
+    /// `TraceEvent` discriminant
+    pub event: u16,
+    _reserved: u16,
+    /// Event-specific argument
+    pub arg: u32,
+}
+
+impl TraceRecord {
+    const EMPTY: Self = Self { tsc: 0, event: 0, _reserved: 0, arg: 0 };
+
+    pub fn event(&self) -> Option<TraceEvent> {
+        TraceEvent::from_u16(self.event)
+    }
+}
+
+/// Trace ring buffer
+pub struct TraceBuffer {
+    records: [TraceRecord; TRACE_RECORDS],
+    /// Next write position
+    head: usize,
+    /// Total records ever written
+    written: u64,
+}
+
+impl TraceBuffer {
+    pub const fn new() -> Self {
+        Self {
+            records: [TraceRecord::EMPTY; TRACE_RECORDS],
+            head: 0,
+            written: 0,
+        }
+    }
+
+    fn push(&mut self, record: TraceRecord) {
+        self.records[self.head] = record;
+        self.head = (self.head + 1) % TRACE_RECORDS;
+        self.written += 1;
+    }
+
+    /// Number of records currently held
+    pub fn len(&self) -> usize {
+        (self.written as usize).min(TRACE_RECORDS)
+    }
+
+    /// Records overwritten before they could be read
+    pub fn dropped(&self) -> u64 {
+        self.written.saturating_sub(TRACE_RECORDS as u64)
+    }
+
+    /// Visit held records, oldest first
+    pub fn for_each<F: FnMut(&TraceRecord)>(&self, mut f: F) {
+        let n = self.len();
+        let start = (self.head + TRACE_RECORDS - n) % TRACE_RECORDS;
+        for i in 0..n {
+            f(&self.records[(start + i) % TRACE_RECORDS]);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.written = 0;
+    }
+}
+
+/// Global trace buffer
+static mut TRACE: TraceBuffer = TraceBuffer::new();
+
+/// Trace points record only while set
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Is tracing enabled?
+#[inline(always)]
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Start or stop recording
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Append a record (use the `trace!` macro at call sites)
+#[inline]
+pub fn record(event: TraceEvent, arg: u32) {
+    if !is_enabled() {
+        return;
+    }
+    let record = TraceRecord { tsc: crate::arch::x86::tsc::read(), event: event as u16, _reserved: 0, arg };
+    crate::arch::x86::idt::without_interrupts(|| unsafe { TRACE.push(record) });
+}
+
+/// Discard all records
+pub fn clear() {
+    crate::arch::x86::idt::without_interrupts(|| unsafe { TRACE.clear() });
+}
+
+/// Format the buffer as the text of `/proc/trace`
+///
+/// Timestamps are TSC cycles relative to the oldest record.
+pub fn report(out: &mut String) {
+    // Snapshot first so formatting doesn't run with interrupts masked
+    let mut records = Vec::new();
+    let _ = records.try_reserve_exact(TRACE_RECORDS);
+    let dropped = crate::arch::x86::idt::without_interrupts(|| unsafe {
+        let trace = &*core::ptr::addr_of!(TRACE);
+        trace.for_each(|r| {
+            if records.len() < records.capacity() {
+                records.push(*r);
+            }
+        });
+        trace.dropped()
+    });
+
+    let _ = writeln!(out, "# {} records, {} dropped, tracing {}",
+        records.len(), dropped, if is_enabled() { "on" } else { "off" });
+    let _ = writeln!(out, "# cycles event arg");
+
+    let base = records.first().map_or(0, |r| r.tsc);
+    for r in &records {
+        let name = r.event().map_or("?", TraceEvent::as_str);
+        let _ = writeln!(out, "{} {} {:#x}", r.tsc.wrapping_sub(base), name, r.arg);
+    }
+}
+
+/// Stream the buffer to COM1
+///
+/// Returns false when there is no serial port.
+pub fn dump_serial() -> bool {
+    let mut text = String::new();
+    report(&mut text);
+
+    let serial = unsafe { &mut crate::drivers::serial::SERIAL };
+    if !serial.is_present() {
+        return false;
+    }
+    serial.write_bytes(b"--- trace begin ---\r\n");
+    for line in text.lines() {
+        serial.write_bytes(line.as_bytes());
+        serial.write_bytes(b"\r\n");
+    }
+    serial.write_bytes(b"--- trace end ---\r\n");
+    true
+}
+
+/// Record a trace event: `trace!(IrqEntry, irq as u32)`
+#[macro_export]
+macro_rules! trace {
+    ($event:ident, $arg:expr) => {
+        if $crate::trace::is_enabled() {
+            $crate::trace::record($crate::trace::TraceEvent::$event, $arg);
+        }
+    };
+    ($event:ident) => {
+        $crate::trace!($event, 0)
+    };
+}
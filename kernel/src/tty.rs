@@ -0,0 +1,279 @@
+//! Terminal Line Discipline
+//!
+//! A tty sits between a terminal window (the master side) and the user
+//! programs attached to it (the slave side). Keystrokes written by the
+//! window pass through the line discipline into the input queue that
+//! programs read; program output goes into an output queue the window
+//! drains each frame.
+//!
+//! In canonical mode input is collected a line at a time with erase,
+//! kill and word-erase editing, and a read returns at most one line. Raw
+//! mode hands every byte over as it arrives, for full-screen programs.
+//! Echo and Ctrl+C (SIGINT to the tty's programs) can be turned off
+//! separately. Mode flags use the termios `c_lflag` bit values.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Maximum ttys (one per terminal window)
+pub const MAX_TTYS: usize = 4;
+
+/// Most bytes buffered in each queue
+const QUEUE_LIMIT: usize = 4096;
+
+/// Longest line in canonical mode
+const LINE_MAX: usize = 255;
+
+/// Local mode flags
+pub mod lflag {
+    /// Generate signals for Ctrl+C
+    pub const ISIG: u32 = 0x0001;
+    /// Canonical (line at a time) input
+    pub const ICANON: u32 = 0x0002;
+    /// Echo input back to the terminal
+    pub const ECHO: u32 = 0x0008;
+}
+
+/// Control characters
+const VINTR: u8 = 0x03;     // Ctrl+C
+const VEOF: u8 = 0x04;      // Ctrl+D
+const BACKSPACE: u8 = 0x08;
+const VKILL: u8 = 0x15;     // Ctrl+U
+const VWERASE: u8 = 0x17;   // Ctrl+W
+const VERASE: u8 = 0x7F;
+
+/// One terminal
+struct Tty {
+    lflag: u32,
+    /// Bytes ready for programs to read
+    input: VecDeque<u8>,
+    /// Line being edited (canonical mode)
+    line: Vec<u8>,
+    /// Program output (and echo) for the window
+    output: VecDeque<u8>,
+    /// Ctrl+D on an empty line: the next read returns 0
+    eof: bool,
+}
+
+impl Tty {
+    fn new() -> Self {
+        Self {
+            lflag: lflag::ISIG | lflag::ICANON | lflag::ECHO,
+            input: VecDeque::new(),
+            line: Vec::new(),
+            output: VecDeque::new(),
+            eof: false,
+        }
+    }
+
+    fn canonical(&self) -> bool {
+        self.lflag & lflag::ICANON != 0
+    }
+
+    fn echo(&mut self, bytes: &[u8]) {
+        if self.lflag & lflag::ECHO != 0 {
+            self.put_output(bytes);
+        }
+    }
+
+    fn put_output(&mut self, bytes: &[u8]) -> usize {
+        let n = bytes.len().min(QUEUE_LIMIT.saturating_sub(self.output.len()));
+        self.output.extend(&bytes[..n]);
+        n
+    }
+
+    fn put_input(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(QUEUE_LIMIT.saturating_sub(self.input.len()));
+        self.input.extend(&bytes[..n]);
+    }
+
+    /// Erase the last character of the line being edited
+    fn erase(&mut self) -> bool {
+        if self.line.pop().is_some() {
+            self.echo(&[BACKSPACE, b' ', BACKSPACE]);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Hand the edited line to readers
+    fn commit_line(&mut self) {
+        let line = core::mem::take(&mut self.line);
+        self.put_input(&line);
+    }
+
+    /// Process one keystroke; returns true on an interrupt
+    fn receive(&mut self, byte: u8) -> bool {
+        if byte == VINTR && self.lflag & lflag::ISIG != 0 {
+            self.line.clear();
+            self.echo(b"^C\n");
+            return true;
+        }
+        if !self.canonical() {
+            self.put_input(&[byte]);
+            self.echo(&[byte]);
+            return false;
+        }
+        match byte {
+            b'\r' | b'\n' => {
+                self.line.push(b'\n');
+                self.commit_line();
+                self.echo(b"\n");
+            }
+            VEOF if self.line.is_empty() => self.eof = true,
+            VEOF => self.commit_line(),
+            VERASE | BACKSPACE => {
+                self.erase();
+            }
+            VKILL => while self.erase() {},
+            VWERASE => {
+                while self.line.last() == Some(&b' ') && self.erase() {}
+                while self.line.last().is_some_and(|&c| c != b' ') && self.erase() {}
+            }
+            0x20..=0x7E if self.line.len() < LINE_MAX => {
+                self.line.push(byte);
+                self.echo(&[byte]);
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Input only holds finished lines in canonical mode, so any is readable
+    fn readable(&self) -> bool {
+        self.eof || !self.input.is_empty()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if !self.readable() {
+            return None;
+        }
+        if self.input.is_empty() {
+            self.eof = false;
+            return Some(0);
+        }
+        let mut n = 0;
+        while n < buf.len() {
+            let Some(byte) = self.input.pop_front() else {
+                break;
+            };
+            buf[n] = byte;
+            n += 1;
+            // Canonical reads stop at the end of a line
+            if byte == b'\n' && self.canonical() {
+                break;
+            }
+        }
+        Some(n)
+    }
+}
+
+static mut TTYS: [Option<Tty>; MAX_TTYS] = [const { None }; MAX_TTYS];
+
+fn get(tty: usize) -> Option<&'static mut Tty> {
+    unsafe { (*core::ptr::addr_of_mut!(TTYS)).get_mut(tty)?.as_mut() }
+}
+
+/// Allocate a tty in canonical mode with echo and signals
+pub fn open() -> Option<usize> {
+    let ttys = unsafe { &mut *core::ptr::addr_of_mut!(TTYS) };
+    let slot = ttys.iter().position(|t| t.is_none())?;
+    ttys[slot] = Some(Tty::new());
+    Some(slot)
+}
+
+/// Release a tty
+pub fn close(tty: usize) {
+    if let Some(slot) = unsafe { (*core::ptr::addr_of_mut!(TTYS)).get_mut(tty) } {
+        *slot = None;
+    }
+}
+
+// =============================================================================
+// Master Side (terminal window)
+// =============================================================================
+
+/// Feed keystrokes through the line discipline
+///
+/// Ctrl+C (with ISIG set) sends SIGINT to the programs on the tty.
+pub fn master_write(tty: usize, bytes: &[u8]) {
+    let Some(t) = get(tty) else {
+        return;
+    };
+    let mut interrupt = false;
+    for &byte in bytes {
+        interrupt |= t.receive(byte);
+    }
+    if interrupt {
+        t.input.clear();
+        t.eof = false;
+        crate::exec::signal_tty(tty, crate::exec::SIGINT);
+    }
+}
+
+/// Take output waiting for the window
+pub fn master_read(tty: usize) -> Vec<u8> {
+    match get(tty) {
+        Some(t) => t.output.drain(..).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Drop pending input (e.g. when the foreground program exits)
+pub fn flush_input(tty: usize) {
+    if let Some(t) = get(tty) {
+        t.input.clear();
+        t.line.clear();
+        t.eof = false;
+    }
+}
+
+/// Is the tty in raw (non-canonical) mode?
+pub fn is_raw(tty: usize) -> bool {
+    get(tty).is_some_and(|t| !t.canonical())
+}
+
+// =============================================================================
+// Slave Side (programs)
+// =============================================================================
+
+/// Would a read return now?
+pub fn readable(tty: usize) -> bool {
+    get(tty).map_or(true, |t| t.readable())
+}
+
+/// Read input; None if the reader has to wait
+///
+/// Returns Some(0) at end of file (Ctrl+D) or for a closed tty.
+pub fn read(tty: usize, buf: &mut [u8]) -> Option<usize> {
+    match get(tty) {
+        Some(t) => t.read(buf),
+        None => Some(0),
+    }
+}
+
+/// Write program output, returning the bytes accepted
+pub fn write(tty: usize, bytes: &[u8]) -> usize {
+    match get(tty) {
+        Some(t) => t.put_output(bytes),
+        None => bytes.len(),
+    }
+}
+
+/// Local mode flags
+pub fn lflag(tty: usize) -> Option<u32> {
+    get(tty).map(|t| t.lflag)
+}
+
+/// Change the local mode flags
+///
+/// Leaving canonical mode hands any half-typed line to readers.
+pub fn set_lflag(tty: usize, flags: u32) -> Result<(), &'static str> {
+    let t = get(tty).ok_or("no such tty")?;
+    if t.canonical() && flags & lflag::ICANON == 0 {
+        t.commit_line();
+    }
+    t.lflag = flags & (lflag::ISIG | lflag::ICANON | lflag::ECHO);
+    Ok(())
+}
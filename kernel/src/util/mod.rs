@@ -0,0 +1,12 @@
+//! Kernel Utilities
+//!
+//! Small generic building blocks shared by drivers and subsystems.
+
+pub mod crc32;
+pub mod ring;
+pub mod slotmap;
+pub mod uuid;
+
+pub use ring::RingBuffer;
+pub use slotmap::{SlotKey, SlotMap, SlotStats};
+pub use uuid::Uuid;
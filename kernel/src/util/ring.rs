@@ -0,0 +1,122 @@
+//! IRQ-Safe Ring Buffer
+//!
+//! Fixed-size single-producer/single-consumer queue for handing data from
+//! an interrupt handler to the main loop without locks. The producer only
+//! moves `head` and the consumer only moves `tail`, so one side may
+//! interrupt the other at any point. Two producers (an IRQ handler and a
+//! polling loop, say) must not push concurrently; poll with interrupts
+//! disabled.
+//!
+//! A full buffer drops the new item and counts it, so a stalled consumer
+//! loses the latest input rather than corrupting what is queued. The fill
+//! high-water mark shows how close a buffer has come to overflowing.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// SPSC ring buffer of `N` items (`N` a power of two)
+pub struct RingBuffer<T: Copy, const N: usize> {
+    slots: UnsafeCell<[MaybeUninit<T>; N]>,
+    /// Items ever pushed (free-running, producer only)
+    head: AtomicUsize,
+    /// Items ever popped (free-running, consumer only)
+    tail: AtomicUsize,
+    /// Items dropped because the buffer was full
+    overflows: AtomicU32,
+    /// Highest fill level seen
+    high_water: AtomicUsize,
+}
+
+// Slots are only touched by the side that owns them (see module docs)
+unsafe impl<T: Copy + Send, const N: usize> Sync for RingBuffer<T, N> {}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    /// Free-running indices only wrap cleanly for power-of-two sizes
+    const SIZE_OK: () = assert!(N.is_power_of_two());
+
+    pub const fn new() -> Self {
+        let _ = Self::SIZE_OK;
+        Self {
+            slots: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overflows: AtomicU32::new(0),
+            high_water: AtomicUsize::new(0),
+        }
+    }
+
+    /// Queue an item (producer side); false if full and the item was dropped
+    pub fn push(&self, item: T) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        let used = head.wrapping_sub(tail);
+        if used >= N {
+            self.overflows.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        unsafe {
+            (*self.slots.get())[head % N].write(item);
+        }
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        self.high_water.fetch_max(used + 1, Ordering::Relaxed);
+        true
+    }
+
+    /// Take the oldest item (consumer side)
+    pub fn pop(&self) -> Option<T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail == head {
+            return None;
+        }
+
+        let item = unsafe { (*self.slots.get())[tail % N].assume_init() };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    /// Discard everything queued (consumer side)
+    pub fn clear(&self) {
+        self.tail.store(self.head.load(Ordering::Acquire), Ordering::Release);
+    }
+
+    /// Items currently queued
+    pub fn len(&self) -> usize {
+        self.head.load(Ordering::Acquire).wrapping_sub(self.tail.load(Ordering::Acquire)).min(N)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= N
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Items dropped because the buffer was full
+    pub fn overflows(&self) -> u32 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+
+    /// Highest number of items ever queued at once
+    pub fn high_water(&self) -> usize {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    /// Is the buffer filled to at least `percent` of capacity?
+    pub fn above(&self, percent: usize) -> bool {
+        self.len() * 100 >= N * percent
+    }
+
+    /// Restart overflow and high-water accounting
+    pub fn reset_stats(&self) {
+        self.overflows.store(0, Ordering::Relaxed);
+        self.high_water.store(self.len(), Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,226 @@
+//! Slot Map
+//!
+//! Fixed-capacity table for kernel objects that are handed out by slot
+//! (windows, open files, filesystem handles). Every insert and remove goes
+//! through the map, which keeps the occupancy counts /proc/slots reports:
+//! `inserts - removes` always equals the live count, and a live count
+//! that only grows is a leak.
+//!
+//! Each slot has a generation that changes whenever it is freed. A
+//! `SlotKey` carries the generation it was issued with, so a key kept
+//! past its object's removal stops resolving instead of finding whatever
+//! reused the slot. Code that tracks objects some other way can keep
+//! using plain slot indices.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+/// Names a slot and the object it held when the key was issued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotKey {
+    index: u16,
+    generation: u16,
+}
+
+impl SlotKey {
+    /// Slot index
+    pub fn index(self) -> usize {
+        self.index as usize
+    }
+
+    /// Pack into a handle value (generation in the upper half)
+    pub fn to_u64(self) -> u64 {
+        (self.generation as u64) << 32 | self.index as u64
+    }
+
+    /// Unpack a handle from `to_u64` (None if it can't be one)
+    pub fn from_u64(handle: u64) -> Option<Self> {
+        let index = u16::try_from(handle & 0xFFFF_FFFF).ok()?;
+        let generation = u16::try_from(handle >> 32).ok()?;
+        Some(Self { index, generation })
+    }
+}
+
+/// Occupancy counts
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlotStats {
+    pub len: usize,
+    pub capacity: usize,
+    /// Most slots ever in use at once
+    pub peak: usize,
+    pub inserts: u32,
+    pub removes: u32,
+    /// Inserts refused because every slot was taken
+    pub full: u32,
+    /// Lookups with a key whose object was already removed
+    pub stale: u32,
+}
+
+/// Table of up to `N` objects
+pub struct SlotMap<T, const N: usize> {
+    slots: [Option<T>; N],
+    generations: [u16; N],
+    stats: SlotStats,
+}
+
+impl<T, const N: usize> SlotMap<T, N> {
+    /// Indices must fit a key
+    const SIZE_OK: () = assert!(N <= u16::MAX as usize);
+
+    pub const fn new() -> Self {
+        let _ = Self::SIZE_OK;
+        Self {
+            slots: [const { None }; N],
+            generations: [0; N],
+            stats: SlotStats {
+                len: 0,
+                capacity: N,
+                peak: 0,
+                inserts: 0,
+                removes: 0,
+                full: 0,
+                stale: 0,
+            },
+        }
+    }
+
+    // =========================================================================
+    // Insert / remove
+    // =========================================================================
+
+    /// Store a value in the lowest free slot; None if the map is full
+    pub fn insert(&mut self, value: T) -> Option<SlotKey> {
+        let Some(index) = self.slots.iter().position(|s| s.is_none()) else {
+            self.stats.full += 1;
+            return None;
+        };
+        self.slots[index] = Some(value);
+        self.stats.len += 1;
+        self.stats.peak = self.stats.peak.max(self.stats.len);
+        self.stats.inserts += 1;
+        Some(SlotKey { index: index as u16, generation: self.generations[index] })
+    }
+
+    /// Take the value out of a slot
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        let value = self.slots.get_mut(index)?.take()?;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+        self.stats.len -= 1;
+        self.stats.removes += 1;
+        Some(value)
+    }
+
+    /// Take the value a key names, if it is still there
+    pub fn remove_key(&mut self, key: SlotKey) -> Option<T> {
+        self.resolve(key)?;
+        self.remove(key.index())
+    }
+
+    /// Remove every value
+    pub fn clear(&mut self) {
+        self.retain(|_| false);
+    }
+
+    /// Remove the values `keep` returns false for
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        for index in 0..N {
+            if self.get(index).is_some_and(|value| !keep(value)) {
+                self.remove(index);
+            }
+        }
+    }
+
+    // =========================================================================
+    // Lookup
+    // =========================================================================
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.slots.get(index)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.slots.get_mut(index)?.as_mut()
+    }
+
+    /// Is a slot in use?
+    pub fn contains(&self, index: usize) -> bool {
+        self.get(index).is_some()
+    }
+
+    /// Key for the value now in a slot
+    pub fn key(&self, index: usize) -> Option<SlotKey> {
+        self.contains(index).then(|| SlotKey { index: index as u16, generation: self.generations[index] })
+    }
+
+    /// Slot a key names, if its value is still there (counts stale keys)
+    fn resolve(&mut self, key: SlotKey) -> Option<usize> {
+        let index = key.index();
+        if index >= N || self.slots[index].is_none() {
+            return None;
+        }
+        if self.generations[index] != key.generation {
+            self.stats.stale += 1;
+            return None;
+        }
+        Some(index)
+    }
+
+    /// Value a key names, if it is still there
+    pub fn get_key(&mut self, key: SlotKey) -> Option<&mut T> {
+        let index = self.resolve(key)?;
+        self.slots[index].as_mut()
+    }
+
+    /// Slots in use and their values, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.slots.iter().enumerate().filter_map(|(i, s)| Some((i, s.as_ref()?)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(i, s)| Some((i, s.as_mut()?)))
+    }
+
+    /// Values in slot order
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().flatten()
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stats.len
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.stats.len == N
+    }
+
+    pub fn stats(&self) -> SlotStats {
+        self.stats
+    }
+}
+
+// =============================================================================
+// Reporting
+// =============================================================================
+
+/// One table's row in /proc/slots
+///
+/// `leaked` is the owner's count of live slots that nothing can reach
+/// any more (checked against its own bookkeeping).
+pub fn write_row(out: &mut String, name: &str, stats: SlotStats, leaked: usize) {
+    let _ = writeln!(out, "{:<12} {:>4}/{:<4} {:>5} {:>8} {:>8} {:>5} {:>5} {:>6}",
+        name, stats.len, stats.capacity, stats.peak, stats.inserts, stats.removes,
+        stats.full, stats.stale, leaked);
+}
+
+/// Slot tables and suspected leaks (/proc/slots)
+pub fn report(out: &mut String) {
+    let _ = writeln!(out, "{:<12} {:>9} {:>5} {:>8} {:>8} {:>5} {:>5} {:>6}",
+        "table", "used", "peak", "inserts", "removes", "full", "stale", "leaked");
+    unsafe { (*core::ptr::addr_of!(crate::fs::vfs::VFS)).slot_report(out) };
+    #[cfg(feature = "gui")]
+    crate::gui::desktop::slot_report(out);
+}
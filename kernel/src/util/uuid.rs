@@ -0,0 +1,110 @@
+//! UUIDs
+//!
+//! 128-bit identifiers (RFC 4122), kept as their 16 bytes in the order
+//! they are written out: `Display` gives the usual lowercase
+//! `8-4-4-4-12` hex form and `parse` reads it back. New ones are random
+//! (version 4) from the kernel RNG.
+//!
+//! Microsoft-style GUIDs, as stored by exFAT and GPT, keep the first three
+//! fields little-endian on disk; `from_guid_bytes` and `to_guid_bytes`
+//! convert, so the text form is the same one other systems print.
+
+use core::fmt;
+
+/// A 128-bit UUID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Uuid(pub [u8; 16]);
+
+/// Lengths of the dash-separated groups in the text form
+const GROUPS: [usize; 5] = [8, 4, 4, 4, 12];
+
+/// Length of the text form
+pub const TEXT_LEN: usize = 36;
+
+impl Uuid {
+    /// All zeros (no identity)
+    pub const NIL: Self = Self([0; 16]);
+
+    /// A random (version 4) UUID
+    pub fn new_v4() -> Self {
+        let mut bytes = [0u8; 16];
+        crate::rng::rand_bytes(&mut bytes);
+        bytes[6] = (bytes[6] & 0x0F) | 0x40;
+        bytes[8] = (bytes[8] & 0x3F) | 0x80;
+        Self(bytes)
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.0 == [0; 16]
+    }
+
+    /// Version field (4 for random)
+    pub fn version(&self) -> u8 {
+        self.0[6] >> 4
+    }
+
+    /// From a GUID as stored on disk (first three fields little-endian)
+    pub fn from_guid_bytes(raw: [u8; 16]) -> Self {
+        let mut bytes = raw;
+        bytes[0..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+        Self(bytes)
+    }
+
+    /// To the on-disk GUID layout (see `from_guid_bytes`)
+    pub fn to_guid_bytes(&self) -> [u8; 16] {
+        // The swap is its own inverse
+        Self::from_guid_bytes(self.0).0
+    }
+
+    /// Parse the `8-4-4-4-12` hex form (either case, optionally braced)
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.strip_prefix('{').and_then(|t| t.strip_suffix('}')).unwrap_or(text);
+        if text.len() != TEXT_LEN {
+            return None;
+        }
+        let mut bytes = [0u8; 16];
+        let mut at = 0;
+        let mut groups = text.split('-');
+        for len in GROUPS {
+            let group = groups.next()?;
+            if group.len() != len {
+                return None;
+            }
+            for pair in group.as_bytes().chunks(2) {
+                bytes[at] = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+                at += 1;
+            }
+        }
+        if groups.next().is_some() {
+            return None;
+        }
+        Some(Self(bytes))
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut at = 0;
+        for (i, len) in GROUPS.iter().enumerate() {
+            if i > 0 {
+                f.write_str("-")?;
+            }
+            for byte in &self.0[at..at + len / 2] {
+                write!(f, "{:02x}", byte)?;
+            }
+            at += len / 2;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+//! Virtual Consoles
+//!
+//! Two consoles share the screen and keyboard: the GUI and a text console
+//! showing the kernel log with a text shell. Ctrl+Alt+F1 switches to the
+//! text console and Ctrl+Alt+F7 back to the GUI. Switching saves the
+//! graphics mode, drops to VGA text mode and later sets the mode again;
+//! the GUI redraws everything when it comes back. Keys go to whichever
+//! console is active.
+
+use crate::drivers::keyboard::{BufferedKey, KeyCode};
+use crate::drivers::vga::{self, SavedMode, Writer};
+use crate::text_shell::TextShell;
+
+/// A virtual console
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    /// Desktop and windows
+    Gui,
+    /// Kernel log and text shell
+    Text,
+}
+
+/// Console currently on screen
+static mut ACTIVE: Console = Console::Gui;
+
+/// Graphics mode to restore when leaving the text console
+static mut SAVED_MODE: Option<SavedMode> = None;
+
+/// Boot console writer, parked while the text console owns `vga::WRITER`
+static mut GUI_WRITER: Option<Writer> = None;
+
+/// Text console shell (kept across switches)
+static mut SHELL: Option<TextShell> = None;
+
+/// Console currently on screen
+pub fn active() -> Console {
+    unsafe { ACTIVE }
+}
+
+/// Console a key selects (Ctrl+Alt+F1 text, Ctrl+Alt+F7 GUI)
+pub fn hotkey(key: &BufferedKey) -> Option<Console> {
+    if !(key.ctrl && key.alt) {
+        return None;
+    }
+    match key.keycode {
+        KeyCode::F1 => Some(Console::Text),
+        KeyCode::F7 => Some(Console::Gui),
+        _ => None,
+    }
+}
+
+/// Switch consoles; Ok(true) if the display changed
+///
+/// Switching to the GUI leaves its framebuffer blank: the caller must
+/// redraw the whole desktop.
+pub fn switch(to: Console) -> Result<bool, &'static str> {
+    if to == active() {
+        return Ok(false);
+    }
+    unsafe {
+        match to {
+            Console::Text => {
+                let saved = vga::save_graphics_mode()
+                    .ok_or("Text console needs a restorable display mode")?;
+                vga::enter_text_mode(saved);
+                SAVED_MODE = Some(saved);
+                GUI_WRITER = vga::WRITER.replace(Writer::text_mode());
+                let shell = (*core::ptr::addr_of_mut!(SHELL)).get_or_insert_with(TextShell::new);
+                shell.show();
+            }
+            Console::Gui => {
+                let saved = SAVED_MODE.ok_or("No graphics mode saved")?;
+                vga::restore_graphics_mode(saved)?;
+                SAVED_MODE = None;
+                vga::WRITER = GUI_WRITER.take();
+            }
+        }
+        ACTIVE = to;
+    }
+    Ok(true)
+}
+
+/// Give a key to the text console
+pub fn key(key: BufferedKey) {
+    if let Some(shell) = unsafe { (*core::ptr::addr_of_mut!(SHELL)).as_mut() } {
+        shell.key(key);
+    }
+}
+
+/// Text console housekeeping (follow the kernel log)
+pub fn poll() {
+    if active() != Console::Text {
+        return;
+    }
+    if let Some(shell) = unsafe { (*core::ptr::addr_of_mut!(SHELL)).as_mut() } {
+        shell.poll();
+    }
+}
@@ -0,0 +1,240 @@
+//! Software Watchdog
+//!
+//! Detects stalls in the GUI polling loop. The main loop pets the watchdog
+//! once per frame; the PIT interrupt checks how long it has been since the
+//! last pet. When the loop has been silent for longer than the timeout, the
+//! watchdog logs a warning, snapshots the scheduler's task states and,
+//! if configured, resets the machine.
+//!
+//! The interrupt only compares tick counts. A stall is handled by a
+//! tasklet (see `softirq`), which runs after the EOI with interrupts on
+//! and never inside another softirq, so it still runs while the main loop
+//! is stuck; it walks the run queues under the scheduler lock.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::arch::x86::pit;
+use crate::drivers::serial::SERIAL;
+use crate::sched::{Pid, Priority, Task, TaskState, SCHEDULER};
+
+/// Default stall timeout
+pub const DEFAULT_TIMEOUT_MS: u32 = 5000;
+
+/// Maximum tasks captured in a stall snapshot
+pub const MAX_SNAPSHOT_TASKS: usize = 16;
+
+/// Tick of the most recent pet
+static LAST_PET: AtomicU32 = AtomicU32::new(0);
+
+/// Stall timeout in PIT ticks (0 = disabled)
+static TIMEOUT_TICKS: AtomicU32 = AtomicU32::new(0);
+
+/// Reset the machine when a stall is detected
+static REBOOT_ON_STALL: AtomicBool = AtomicBool::new(false);
+
+/// Set once a stall has been reported, cleared by the next pet
+static STALLED: AtomicBool = AtomicBool::new(false);
+
+/// Number of stalls detected since boot
+static STALL_COUNT: AtomicU32 = AtomicU32::new(0);
+
+// =============================================================================
+// Task Snapshot
+// =============================================================================
+
+/// State of one task at the moment a stall was detected
+#[derive(Debug, Clone, Copy)]
+pub struct TaskSnapshot {
+    pub pid: Pid,
+    pub name: [u8; 16],
+    pub state: TaskState,
+    pub priority: Priority,
+    pub cpu_time: u64,
+    /// Was this the running task?
+    pub current: bool,
+}
+
+impl TaskSnapshot {
+    /// Task name as a string
+    pub fn name_str(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(16);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("???")
+    }
+}
+
+/// Snapshot of the scheduler taken on the most recent stall
+pub struct StallReport {
+    /// Uptime when the stall was detected
+    pub detected_ms: u32,
+    /// How long the loop had been silent
+    pub stalled_ms: u32,
+    pub tasks: [Option<TaskSnapshot>; MAX_SNAPSHOT_TASKS],
+    pub task_count: usize,
+    pub context_switches: u64,
+}
+
+impl StallReport {
+    const fn new() -> Self {
+        Self {
+            detected_ms: 0,
+            stalled_ms: 0,
+            tasks: [None; MAX_SNAPSHOT_TASKS],
+            task_count: 0,
+            context_switches: 0,
+        }
+    }
+
+    fn push(&mut self, task: &Task, current: bool) {
+        if self.task_count >= MAX_SNAPSHOT_TASKS {
+            return;
+        }
+        self.tasks[self.task_count] = Some(TaskSnapshot {
+            pid: task.pid,
+            name: task.name,
+            state: task.state,
+            priority: task.priority,
+            cpu_time: task.cpu_time,
+            current,
+        });
+        self.task_count += 1;
+    }
+
+    /// Captured tasks
+    pub fn tasks(&self) -> impl Iterator<Item = &TaskSnapshot> {
+        self.tasks[..self.task_count].iter().flatten()
+    }
+}
+
+/// Last stall report
+pub static mut LAST_STALL: StallReport = StallReport::new();
+
+// =============================================================================
+// Control
+// =============================================================================
+
+/// Arm the watchdog
+///
+/// The caller must start petting it right away; the first timeout is
+/// counted from this call.
+pub fn enable(timeout_ms: u32, reboot_on_stall: bool) {
+    let hz = pit::frequency();
+    let ticks = ((timeout_ms as u64 * hz as u64) / 1000).max(1) as u32;
+    LAST_PET.store(pit::ticks(), Ordering::Relaxed);
+    REBOOT_ON_STALL.store(reboot_on_stall, Ordering::Relaxed);
+    STALLED.store(false, Ordering::Relaxed);
+    TIMEOUT_TICKS.store(ticks, Ordering::Release);
+}
+
+/// Disarm the watchdog
+pub fn disable() {
+    TIMEOUT_TICKS.store(0, Ordering::Release);
+}
+
+/// Is the watchdog armed?
+pub fn is_enabled() -> bool {
+    TIMEOUT_TICKS.load(Ordering::Relaxed) != 0
+}
+
+/// Number of stalls detected since boot
+pub fn stall_count() -> u32 {
+    STALL_COUNT.load(Ordering::Relaxed)
+}
+
+/// Pet the watchdog (call once per main loop iteration)
+///
+/// Returns how long the loop was stalled if this pet ends a reported
+/// stall, so the caller can surface it once it is safe to do so.
+pub fn pet() -> Option<u32> {
+    let now = pit::ticks();
+    let last = LAST_PET.swap(now, Ordering::Relaxed);
+
+    if STALLED.swap(false, Ordering::Relaxed) {
+        let hz = pit::frequency().max(1);
+        let stalled_ms = (now.wrapping_sub(last) as u64 * 1000 / hz as u64) as u32;
+        crate::klog::write_fmt(format_args!(
+            "[WDOG] Main loop recovered after {} ms\n", stalled_ms));
+        Some(stalled_ms)
+    } else {
+        None
+    }
+}
+
+/// Check for a stall (called from the PIT interrupt)
+pub fn check() {
+    let timeout = TIMEOUT_TICKS.load(Ordering::Acquire);
+    if timeout == 0 || STALLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let now = pit::ticks();
+    let elapsed = now.wrapping_sub(LAST_PET.load(Ordering::Relaxed));
+    if elapsed < timeout {
+        return;
+    }
+
+    // With the tasklet queue full, the next tick tries again
+    let hz = pit::frequency().max(1);
+    let stalled_ms = (elapsed as u64 * 1000 / hz as u64) as u32;
+    if crate::softirq::schedule(handle_stall, stalled_ms) {
+        STALLED.store(true, Ordering::Relaxed);
+        STALL_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Report a stall (tasklet queued by `check`)
+fn handle_stall(stalled_ms: u32) {
+    capture(stalled_ms);
+    report();
+
+    if REBOOT_ON_STALL.load(Ordering::Relaxed) {
+        reset();
+    }
+}
+
+/// Snapshot scheduler state into `LAST_STALL`
+fn capture(stalled_ms: u32) {
+    unsafe {
+        let report = &mut LAST_STALL;
+        *report = StallReport::new();
+        report.detected_ms = pit::uptime_ms();
+        report.stalled_ms = stalled_ms;
+        report.context_switches = SCHEDULER.context_switches();
+
+        if let Some(current) = SCHEDULER.current() {
+            report.push(&*current, true);
+        }
+        SCHEDULER.for_each_ready(|task| report.push(task, false));
+    }
+}
+
+/// Write the last stall report to the kernel log and COM1
+fn report() {
+    let report = unsafe { &LAST_STALL };
+
+    crate::klog::write_fmt(format_args!(
+        "[WDOG] Main loop stalled for {} ms ({} tasks, {} switches)\n",
+        report.stalled_ms, report.task_count, report.context_switches));
+    for task in report.tasks() {
+        crate::klog::write_fmt(format_args!(
+            "[WDOG]   {}{} {} {:?} {:?} cpu={}\n",
+            if task.current { "*" } else { " " },
+            task.pid, task.name_str(), task.state, task.priority, task.cpu_time));
+    }
+
+    let serial = unsafe { &mut SERIAL };
+    if serial.is_present() {
+        let _ = writeln!(serial, "[WDOG] Main loop stalled for {} ms", report.stalled_ms);
+        for task in report.tasks() {
+            let _ = writeln!(serial, "[WDOG]   {}{} {} {:?}",
+                             if task.current { "*" } else { " " },
+                             task.pid, task.name_str(), task.state);
+        }
+    }
+}
+
+/// Controlled reset (driver shutdown hooks, then hardware reset)
+fn reset() {
+    crate::klog::write_str("[WDOG] Resetting\n");
+    crate::arch::x86::reboot::reboot();
+}
@@ -0,0 +1,131 @@
+#!/usr/bin/env python3
+"""Build the kernel symbol table from the linker map.
+
+Usage: ksyms.py <kernel.map> <ksyms.bin>
+       ksyms.py --size <kernel.map>
+
+Reads the map rust-lld writes for the kernel (build.rs asks for it), keeps
+the symbols in .text, demangles them and writes the table src/ksyms.rs
+reads, padded to the size of the .ksyms section. The Makefile then puts it
+in the kernel with `objcopy --update-section .ksyms=<ksyms.bin>`.
+
+With --size it prints how many bytes the table needs instead, for relinking
+with KSYMS_SIZE set to that (.ksyms follows .text, so the symbols don't
+move when it grows).
+"""
+
+import re
+import struct
+import sys
+
+# Must match MAX_NAME in src/ksyms.rs
+MAX_NAME = 255
+
+# VMA LMA Size Align, then the name indented by its depth: output
+# sections at 0, input sections at 8, symbols at 16
+MAP_LINE = re.compile(r"^\s*([0-9a-f]+)\s+[0-9a-f]+\s+[0-9a-f]+\s+\d+ (\s*)(\S.*)$")
+
+# Escapes in legacy Rust mangled names
+ESCAPES = {
+    "$SP$": "@", "$BP$": "*", "$RF$": "&", "$LT$": "<", "$GT$": ">",
+    "$LP$": "(", "$RP$": ")", "$C$": ",",
+}
+
+
+def demangle(name):
+    """Legacy Rust mangling (_ZN...E) to a path, without the hash"""
+    if not (name.startswith("_ZN") and name.endswith("E")):
+        return name
+    rest = name[3:-1]
+    parts = []
+    while rest:
+        m = re.match(r"(\d+)", rest)
+        if not m:
+            return name
+        length = int(m.group(1))
+        start = len(m.group(1))
+        parts.append(rest[start:start + length])
+        rest = rest[start + length:]
+    if parts and re.fullmatch(r"h[0-9a-f]{16}", parts[-1]):
+        parts.pop()
+
+    def unescape(part):
+        if part.startswith("_$"):
+            part = part[1:]
+        for code, text in ESCAPES.items():
+            part = part.replace(code, text)
+        part = re.sub(r"\$u([0-9a-f]+)\$", lambda m: chr(int(m.group(1), 16)), part)
+        return part.replace("..", "::")
+
+    return "::".join(unescape(p) for p in parts)
+
+
+def read_map(path):
+    """(address, name) for every symbol in the .text output section, and
+    the size of the .ksyms output section"""
+    symbols = {}
+    reserved = 0
+    in_text = False
+    with open(path) as f:
+        for line in f:
+            m = MAP_LINE.match(line)
+            if not m:
+                continue
+            addr, indent, name = int(m.group(1), 16), len(m.group(2)), m.group(3).strip()
+            if indent == 0:
+                in_text = name == ".text"
+                if name == ".ksyms":
+                    reserved = int(line.split()[2], 16)
+            elif in_text and indent >= 16 and not name.startswith(("__", ".")):
+                # First name wins where several share an address
+                symbols.setdefault(addr, demangle(name))
+    return sorted(symbols.items()), reserved
+
+
+def uleb128(value):
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def build_table(symbols):
+    first = symbols[0][0] if symbols else 0
+    table = bytearray(b"KSYM" + struct.pack("<II", len(symbols), first))
+    prev_addr, prev_name = first, b""
+    for addr, name in symbols:
+        name = name.encode("utf-8")[:MAX_NAME].decode("utf-8", "ignore").encode("utf-8")
+        shared = 0
+        while shared < min(len(name), len(prev_name)) and name[shared] == prev_name[shared]:
+            shared += 1
+        table += uleb128(addr - prev_addr)
+        table += bytes([shared, len(name) - shared]) + name[shared:]
+        prev_addr, prev_name = addr, name
+    # Whole words, like the section's alignment
+    return table + bytes(-len(table) % 4)
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit("\n".join(__doc__.strip().splitlines()[2:4]))
+    if sys.argv[1] == "--size":
+        symbols, _ = read_map(sys.argv[2])
+        print(len(build_table(symbols)))
+        return
+    symbols, reserved = read_map(sys.argv[1])
+    table = build_table(symbols)
+    if len(table) > reserved:
+        sys.exit("ksyms: table is %d bytes, .ksyms has room for %d (relink with KSYMS_SIZE=%d)"
+                 % (len(table), reserved, len(table)))
+    with open(sys.argv[2], "wb") as f:
+        f.write(table + bytes(reserved - len(table)))
+    print("ksyms: %d symbols, %d bytes" % (len(symbols), len(table)))
+
+
+if __name__ == "__main__":
+    main()
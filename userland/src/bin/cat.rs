@@ -0,0 +1,59 @@
+//! cat - concatenate files to standard output
+//!
+//! With no arguments (or "-") copies standard input.
+
+#![no_std]
+#![no_main]
+
+use userland::{env, eprintln, syscall::{self, flags, STDIN, STDOUT}};
+
+/// Copy everything from `fd` to stdout
+fn copy(fd: u32) -> Result<(), &'static str> {
+    let mut buf = [0u8; 512];
+    loop {
+        let n = match syscall::read(fd, &mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n as usize,
+            Err(_) => return Err("read error"),
+        };
+        let mut rest = &buf[..n];
+        while !rest.is_empty() {
+            match syscall::write(STDOUT, rest) {
+                Ok(w) if w > 0 => rest = &rest[w as usize..],
+                _ => return Err("write error"),
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn main() -> i32 {
+    let mut status = 0;
+    let mut files = env::args().skip(1).peekable();
+    if files.peek().is_none() {
+        if let Err(e) = copy(STDIN) {
+            eprintln!("cat: {}", e);
+            return 1;
+        }
+        return 0;
+    }
+    for path in files {
+        let result = if path == "-" {
+            copy(STDIN)
+        } else {
+            match syscall::open(path, flags::O_RDONLY) {
+                Ok(fd) => {
+                    let result = copy(fd);
+                    let _ = syscall::close(fd);
+                    result
+                }
+                Err(_) => Err("cannot open"),
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("cat: {}: {}", path, e);
+            status = 1;
+        }
+    }
+    status
+}
@@ -0,0 +1,34 @@
+//! date - print the local date and time
+//!
+//! With -u prints UTC instead.
+
+#![no_std]
+#![no_main]
+
+use userland::{env, println, syscall::{self, clock}};
+
+/// (year, month, day) of a day count since 1970-01-01
+fn civil(days: u32) -> (u32, u32, u32) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let doe = days % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u32;
+    (year, month, day)
+}
+
+#[no_mangle]
+pub extern "C" fn main() -> i32 {
+    let utc = env::args().skip(1).any(|a| a == "-u");
+    let secs = syscall::clock_gettime(if utc { clock::REALTIME } else { clock::LOCAL });
+    let (year, month, day) = civil(secs / 86_400);
+    let rem = secs % 86_400;
+    println!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}{}",
+        year, month, day, rem / 3600, rem / 60 % 60, rem % 60,
+        if utc { " UTC" } else { "" });
+    0
+}
@@ -0,0 +1,20 @@
+//! hello - print a greeting and a little about the process
+
+#![no_std]
+#![no_main]
+
+use userland::{println, syscall};
+
+#[no_mangle]
+pub extern "C" fn main() -> i32 {
+    println!("Hello from user space!");
+    println!("pid {} at {} ms", syscall::getpid(), syscall::time());
+
+    let mut cwd = [0u8; 128];
+    if let Ok(len) = syscall::getcwd(&mut cwd) {
+        if let Ok(cwd) = core::str::from_utf8(&cwd[..len as usize]) {
+            println!("cwd {}", cwd);
+        }
+    }
+    0
+}
@@ -0,0 +1,96 @@
+//! paint - draw in a window with the mouse
+//!
+//! A small client of the window server: hold the left button to draw,
+//! right-click to clear, press 'c' to cycle colors and 'q' to quit.
+//! The canvas is a shared memory object attached to the window, so
+//! drawing writes straight into what the desktop shows.
+
+#![no_std]
+#![no_main]
+
+use userland::{eprintln, syscall::{self, win_event}};
+
+const WIDTH: u32 = 240;
+const HEIGHT: u32 = 160;
+
+const BACKGROUND: u32 = 0x00FF_FFFF;
+const COLORS: [u32; 4] = [0x0000_0000, 0x00CC_2222, 0x0022_8822, 0x0022_44CC];
+
+/// Brush size in pixels
+const BRUSH: i32 = 3;
+
+fn dab(pixels: &mut [u32], x: u32, y: u32, color: u32) {
+    for dy in -BRUSH / 2..=BRUSH / 2 {
+        for dx in -BRUSH / 2..=BRUSH / 2 {
+            let (px, py) = (x as i32 + dx, y as i32 + dy);
+            if px >= 0 && py >= 0 && (px as u32) < WIDTH && (py as u32) < HEIGHT {
+                pixels[(py as u32 * WIDTH + px as u32) as usize] = color;
+            }
+        }
+    }
+}
+
+/// Create a shared buffer for the window and map it
+fn canvas(id: u32) -> Result<&'static mut [u32], syscall::SysError> {
+    let shm = syscall::shm_create(WIDTH * HEIGHT * 4)?;
+    let addr = syscall::shm_map(shm)?;
+    syscall::win_attach(id, shm)?;
+    // The mapping and the window keep the memory; the name isn't needed
+    let _ = syscall::shm_destroy(shm);
+    Ok(unsafe { core::slice::from_raw_parts_mut(addr as *mut u32, (WIDTH * HEIGHT) as usize) })
+}
+
+#[no_mangle]
+pub extern "C" fn main() -> i32 {
+    let id = match syscall::win_create("Paint", WIDTH, HEIGHT) {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("paint: cannot open window (error {})", e.0);
+            return 1;
+        }
+    };
+    let pixels = match canvas(id) {
+        Ok(pixels) => pixels,
+        Err(e) => {
+            eprintln!("paint: cannot share canvas (error {})", e.0);
+            let _ = syscall::win_destroy(id);
+            return 1;
+        }
+    };
+    pixels.fill(BACKGROUND);
+
+    let mut color = 0;
+    let mut drawing = false;
+    let _ = syscall::win_damage(id);
+
+    // Ends when the window is closed under us
+    while let Ok(Some(event)) = syscall::win_event(id, true) {
+        match event.kind {
+            win_event::KEY => match event.b as u8 {
+                b'q' => break,
+                b'c' => color = (color + 1) % COLORS.len(),
+                _ => continue,
+            },
+            win_event::CLOSE => break,
+            win_event::MOUSE_BUTTON => {
+                let pressed = event.c & win_event::PRESSED != 0;
+                match event.c & 0xFF {
+                    1 => {
+                        drawing = pressed;
+                        if pressed {
+                            dab(pixels, event.a, event.b, COLORS[color]);
+                        }
+                    }
+                    2 if pressed => pixels.fill(BACKGROUND),
+                    _ => continue,
+                }
+            }
+            win_event::MOUSE_MOVE if drawing => dab(pixels, event.a, event.b, COLORS[color]),
+            _ => continue,
+        }
+        let _ = syscall::win_damage(id);
+    }
+
+    let _ = syscall::win_destroy(id);
+    0
+}
@@ -0,0 +1,267 @@
+//! sh - the command shell
+//!
+//! Reads command lines from stdin, runs built-ins itself and anything
+//! else by forking and exec'ing the program with its words as arguments
+//! and the shell's environment, then waits for it. Names without a slash
+//! are looked up in the directories listed in PATH. `< file` and
+//! `> file` (or `>> file` to append) redirect the program's stdin and
+//! stdout.
+//!
+//! The shell ignores SIGINT, so Ctrl+C ends the running program but not
+//! the shell; children get the default action back before exec.
+
+#![no_std]
+#![no_main]
+
+use userland::{env, eprintln, print, println, syscall::{self, flags, rlimit, signal::SIGINT, STDIN, STDOUT}};
+
+/// Longest command line
+const LINE_MAX: usize = 256;
+
+/// Most words in a command
+const MAX_WORDS: usize = 16;
+
+/// Search path when PATH is unset
+const DEFAULT_PATH: &str = "/bin";
+
+/// A parsed command line
+struct Command<'a> {
+    words: [&'a str; MAX_WORDS],
+    count: usize,
+    stdin: Option<&'a str>,
+    stdout: Option<(&'a str, bool)>,
+}
+
+impl<'a> Command<'a> {
+    fn words(&self) -> &[&'a str] {
+        &self.words[..self.count]
+    }
+}
+
+/// Split a line into words and redirections
+fn parse(line: &str) -> Result<Command<'_>, &'static str> {
+    let mut cmd = Command { words: [""; MAX_WORDS], count: 0, stdin: None, stdout: None };
+    let mut tokens = line.split_ascii_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "<" => cmd.stdin = Some(tokens.next().ok_or("missing file after <")?),
+            ">" => cmd.stdout = Some((tokens.next().ok_or("missing file after >")?, false)),
+            ">>" => cmd.stdout = Some((tokens.next().ok_or("missing file after >>")?, true)),
+            _ if cmd.count == MAX_WORDS => return Err("too many words"),
+            _ => {
+                cmd.words[cmd.count] = token;
+                cmd.count += 1;
+            }
+        }
+    }
+    Ok(cmd)
+}
+
+/// Read one line from stdin into `buf`; None at end of input
+fn read_line(buf: &mut [u8; LINE_MAX]) -> Option<&str> {
+    let mut len = 0;
+    loop {
+        let mut byte = [0u8; 1];
+        match syscall::read(STDIN, &mut byte) {
+            Ok(1) => {}
+            _ if len > 0 => break,
+            _ => return None,
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        if len < buf.len() {
+            buf[len] = byte[0];
+            len += 1;
+        }
+    }
+    Some(core::str::from_utf8(&buf[..len]).unwrap_or(""))
+}
+
+/// Open a redirection target onto `fd`
+fn redirect(path: &str, open_flags: u32, fd: u32) -> bool {
+    match syscall::open(path, open_flags) {
+        Ok(file) => {
+            let moved = syscall::dup2(file, fd).is_ok();
+            let _ = syscall::close(file);
+            moved
+        }
+        Err(_) => false,
+    }
+}
+
+/// Child side of running a program: set up redirections and exec
+fn exec_child(cmd: &Command, program: &str) -> ! {
+    let _ = syscall::signal(SIGINT, false);
+    if let Some(path) = cmd.stdin {
+        if !redirect(path, flags::O_RDONLY, STDIN) {
+            eprintln!("sh: {}: cannot open", path);
+            syscall::exit(1);
+        }
+    }
+    if let Some((path, append)) = cmd.stdout {
+        let mode = if append { flags::O_APPEND } else { flags::O_TRUNC };
+        if !redirect(path, flags::O_WRONLY | flags::O_CREAT | mode, STDOUT) {
+            eprintln!("sh: {}: cannot create", path);
+            syscall::exit(1);
+        }
+    }
+
+    let argv = cmd.words().iter().copied();
+    if program.contains('/') {
+        syscall::exec(program, argv, env::vars());
+    } else {
+        let mut buf = [0u8; LINE_MAX];
+        for dir in env::getenv("PATH").unwrap_or(DEFAULT_PATH).split(':') {
+            let dir = if dir.is_empty() { "." } else { dir };
+            let len = dir.len() + 1 + program.len();
+            if len > buf.len() {
+                continue;
+            }
+            buf[..dir.len()].copy_from_slice(dir.as_bytes());
+            buf[dir.len()] = b'/';
+            buf[dir.len() + 1..len].copy_from_slice(program.as_bytes());
+            if let Ok(path) = core::str::from_utf8(&buf[..len]) {
+                syscall::exec(path, argv.clone(), env::vars());
+            }
+        }
+    }
+    eprintln!("sh: {}: not found", program);
+    syscall::exit(127)
+}
+
+/// Fork, exec and wait for a program; returns its exit status
+fn run(cmd: &Command, program: &str) -> i32 {
+    match syscall::fork() {
+        Ok(0) => exec_child(cmd, program),
+        Ok(pid) => match syscall::wait(pid) {
+            Ok((_, status)) => status,
+            Err(_) => {
+                eprintln!("sh: wait failed");
+                1
+            }
+        },
+        Err(_) => {
+            eprintln!("sh: fork failed");
+            1
+        }
+    }
+}
+
+fn pwd() {
+    let mut cwd = [0u8; LINE_MAX];
+    match syscall::getcwd(&mut cwd) {
+        Ok(len) => println!("{}", core::str::from_utf8(&cwd[..len as usize]).unwrap_or("?")),
+        Err(_) => eprintln!("sh: pwd failed"),
+    }
+}
+
+/// Resource names for `ulimit`
+const LIMITS: [(&str, u32); 3] = [
+    ("heap", rlimit::HEAP),
+    ("files", rlimit::FILES),
+    ("windows", rlimit::WINDOWS),
+];
+
+/// Show the resource limits, or set one (inherited by programs run later)
+fn ulimit(args: &[&str]) -> i32 {
+    match args {
+        [] => {
+            for (name, resource) in LIMITS {
+                match syscall::getrlimit(resource) {
+                    Ok(limit) => println!("{:<8} {}", name, limit),
+                    Err(_) => eprintln!("sh: ulimit: {}: unavailable", name),
+                }
+            }
+            0
+        }
+        [name, value] => {
+            let Some(&(_, resource)) = LIMITS.iter().find(|(n, _)| n == name) else {
+                eprintln!("sh: ulimit: {}: unknown resource", name);
+                return 1;
+            };
+            match value.parse().map(|v| syscall::setrlimit(resource, v)) {
+                Ok(Ok(_)) => 0,
+                _ => {
+                    eprintln!("sh: ulimit: {}: invalid limit", value);
+                    1
+                }
+            }
+        }
+        _ => {
+            eprintln!("usage: ulimit [heap|files|windows limit]");
+            2
+        }
+    }
+}
+
+/// Set variables, or list the environment with no arguments
+fn export(assignments: &[&str]) -> i32 {
+    if assignments.is_empty() {
+        env::vars().for_each(|var| println!("{}", var));
+        return 0;
+    }
+    let mut status = 0;
+    for assignment in assignments {
+        let (name, value) = assignment.split_once('=').unwrap_or((assignment, ""));
+        if env::setenv(name, value).is_err() {
+            eprintln!("sh: export: {}: cannot set", name);
+            status = 1;
+        }
+    }
+    status
+}
+
+#[no_mangle]
+pub extern "C" fn main() -> i32 {
+    let _ = syscall::signal(SIGINT, true);
+    let mut status = 0;
+    let mut buf = [0u8; LINE_MAX];
+    loop {
+        print!("$ ");
+        let Some(line) = read_line(&mut buf) else {
+            return status;
+        };
+        let cmd = match parse(line) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                eprintln!("sh: {}", e);
+                status = 2;
+                continue;
+            }
+        };
+        let Some(&program) = cmd.words().first() else {
+            continue;
+        };
+
+        status = match program {
+            "exit" => return cmd.words().get(1).and_then(|c| c.parse().ok()).unwrap_or(status),
+            "cd" => {
+                let dir = cmd.words().get(1).copied().unwrap_or("/");
+                if syscall::chdir(dir).is_err() {
+                    eprintln!("sh: cd: {}: no such directory", dir);
+                    1
+                } else {
+                    0
+                }
+            }
+            "pwd" => {
+                pwd();
+                0
+            }
+            "export" => export(&cmd.words()[1..]),
+            "ulimit" => ulimit(&cmd.words()[1..]),
+            "unset" => {
+                cmd.words()[1..].iter().for_each(|name| env::unsetenv(name));
+                0
+            }
+            "help" => {
+                println!("Built-ins: cd [dir]  pwd  export [NAME=value]  unset NAME");
+                println!("           ulimit [resource limit]  exit [code]  help");
+                println!("Programs run from PATH: name [args] [< in] [> out | >> out]");
+                0
+            }
+            _ => run(&cmd, program),
+        };
+    }
+}
@@ -0,0 +1,162 @@
+//! ubench - user-mode micro-benchmarks
+//!
+//! Measures what the kernel's `bench` command can't from inside the
+//! kernel:
+//!
+//! - syscall: GetPid round trip through INT 0x80 (ns)
+//! - switch: one context switch, from a parent and child yielding to each
+//!   other (ns)
+//!
+//! Results are compared with the baselines in /boot/bench.cfg, which
+//! `ubench save` updates (keeping the kernel benchmarks' entries).
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use userland::{env, eprintln, io::Fd, println, syscall::{self, flags}};
+
+const BASELINE_PATH: &str = "/boot/bench.cfg";
+
+/// How long each benchmark runs (ms)
+const RUN_MS: u32 = 500;
+
+/// Worse than the baseline by more than this is a regression
+const REGRESSION_PERCENT: i64 = 10;
+
+/// Syscalls made between clock reads
+const BATCH: u32 = 1000;
+
+/// Benchmark names, in the order they run
+const NAMES: [&str; 2] = ["syscall", "switch"];
+
+/// Average GetPid round trip in ns
+fn syscall_ns() -> u64 {
+    let start = syscall::time();
+    let mut calls = 0u64;
+    while syscall::time().wrapping_sub(start) < RUN_MS {
+        for _ in 0..BATCH {
+            core::hint::black_box(syscall::getpid());
+        }
+        calls += BATCH as u64;
+    }
+    let elapsed = syscall::time().wrapping_sub(start) as u64;
+    elapsed * 1_000_000 / calls.max(1)
+}
+
+/// Average context switch in ns
+///
+/// Parent and child both yield until the same deadline; each of the
+/// parent's yields is two switches (to the child and back).
+fn switch_ns() -> Option<u64> {
+    let start = syscall::time();
+    let deadline = start + RUN_MS;
+    let child = syscall::fork().ok()?;
+    if child == 0 {
+        while syscall::time() < deadline {
+            syscall::sched_yield();
+        }
+        syscall::exit(0);
+    }
+
+    let mut yields = 0u64;
+    while syscall::time() < deadline {
+        syscall::sched_yield();
+        yields += 1;
+    }
+    let elapsed = syscall::time().wrapping_sub(start) as u64;
+    let _ = syscall::wait(child);
+    Some(elapsed * 1_000_000 / (yields * 2).max(1))
+}
+
+/// Read the baselines file into `buf`
+fn read_baselines(buf: &mut [u8]) -> &str {
+    let Ok(fd) = syscall::open(BASELINE_PATH, flags::O_RDONLY) else {
+        return "";
+    };
+    let mut len = 0;
+    while len < buf.len() {
+        match syscall::read(fd, &mut buf[len..]) {
+            Ok(n) if n > 0 => len += n as usize,
+            _ => break,
+        }
+    }
+    let _ = syscall::close(fd);
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+fn baseline_of(baselines: &str, name: &str) -> Option<u64> {
+    baselines.lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(n, _)| n.trim() == name)
+        .and_then(|(_, v)| v.trim().parse().ok())
+}
+
+/// Rewrite the baselines file with `results`, keeping other entries
+fn save(baselines: &str, results: &[(&str, u64)]) -> Result<(), &'static str> {
+    let fd = syscall::open(BASELINE_PATH, flags::O_WRONLY | flags::O_CREAT | flags::O_TRUNC)
+        .map_err(|_| "cannot open baselines")?;
+    let mut out = Fd(fd);
+    let mut result = Ok(());
+    for line in baselines.lines() {
+        let ours = line.split_once('=').is_some_and(|(n, _)| results.iter().any(|(r, _)| *r == n.trim()));
+        if !ours && !line.trim().is_empty() && writeln!(out, "{}", line).is_err() {
+            result = Err("write error");
+        }
+    }
+    for (name, value) in results {
+        if writeln!(out, "{}={}", name, value).is_err() {
+            result = Err("write error");
+        }
+    }
+    let _ = syscall::close(fd);
+    result
+}
+
+#[no_mangle]
+pub extern "C" fn main() -> i32 {
+    let save_baselines = match env::args().nth(1) {
+        None => false,
+        Some("save") => true,
+        Some(_) => {
+            eprintln!("usage: ubench [save]");
+            return 2;
+        }
+    };
+
+    let mut results = [("", 0u64); NAMES.len()];
+    let mut count = 0;
+    results[count] = (NAMES[0], syscall_ns());
+    count += 1;
+    match switch_ns() {
+        Some(ns) => {
+            results[count] = (NAMES[1], ns);
+            count += 1;
+        }
+        None => eprintln!("ubench: fork failed, switch skipped"),
+    }
+    let results = &results[..count];
+
+    let mut buf = [0u8; 2048];
+    let baselines = read_baselines(&mut buf);
+    for &(name, value) in results {
+        match baseline_of(baselines, name) {
+            Some(baseline) => {
+                // Both are times: lower is better
+                let change = (baseline as i64 - value as i64) * 100 / baseline.max(1) as i64;
+                let verdict = if change < -REGRESSION_PERCENT { "REGRESSED" } else { "ok" };
+                println!("{:<10} {:>9} {:<6} base {:>9} {:+4}% {}", name, value, "ns", baseline, change, verdict);
+            }
+            None => println!("{:<10} {:>9} {:<6}", name, value, "ns"),
+        }
+    }
+
+    if save_baselines {
+        if let Err(e) = save(baselines, results) {
+            eprintln!("ubench: {}", e);
+            return 1;
+        }
+        println!("Baselines saved");
+    }
+    0
+}
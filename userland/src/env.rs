@@ -0,0 +1,123 @@
+//! Arguments and Environment
+//!
+//! `args` yields the argv strings the program was started with. The
+//! environment starts as the envp strings and can be changed with
+//! `setenv`/`unsetenv`. Strings never move once stored (new values go
+//! into an append-only arena), so `getenv` can hand out `&'static str`.
+
+use core::cell::UnsafeCell;
+use crate::syscall::{errno, SysError};
+
+/// Most environment variables
+pub const MAX_VARS: usize = 32;
+
+/// Bytes available for variables set at run time
+const ARENA_SIZE: usize = 4096;
+
+struct State {
+    argc: usize,
+    argv: *const *const u8,
+    /// "NAME=value" entries
+    vars: [Option<&'static str>; MAX_VARS],
+    arena: [u8; ARENA_SIZE],
+    arena_used: usize,
+}
+
+struct Env(UnsafeCell<State>);
+
+// Programs are single-threaded
+unsafe impl Sync for Env {}
+
+static ENV: Env = Env(UnsafeCell::new(State {
+    argc: 0,
+    argv: core::ptr::null(),
+    vars: [None; MAX_VARS],
+    arena: [0; ARENA_SIZE],
+    arena_used: 0,
+}));
+
+fn state() -> &'static mut State {
+    unsafe { &mut *ENV.0.get() }
+}
+
+/// A NUL-terminated string from the initial stack
+unsafe fn c_str(ptr: *const u8) -> &'static str {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap_or("")
+}
+
+/// Record the argument block `_start` found on the stack
+pub(crate) unsafe fn init(stack: *const u32) {
+    let state = state();
+    state.argc = *stack as usize;
+    state.argv = stack.add(1) as *const *const u8;
+
+    let mut envp = state.argv.add(state.argc + 1);
+    let mut count = 0;
+    while !(*envp).is_null() && count < MAX_VARS {
+        state.vars[count] = Some(c_str(*envp));
+        count += 1;
+        envp = envp.add(1);
+    }
+}
+
+/// Command-line arguments, program name first
+pub fn args() -> impl Iterator<Item = &'static str> {
+    let state = state();
+    let argv = state.argv;
+    (0..state.argc).map(move |i| unsafe { c_str(*argv.add(i)) })
+}
+
+/// Environment entries as "NAME=value"
+pub fn vars() -> impl Iterator<Item = &'static str> {
+    state().vars.iter().flatten().copied()
+}
+
+fn slot_of(name: &str) -> Option<usize> {
+    state().vars.iter().position(|v| {
+        v.and_then(|v| v.strip_prefix(name)).is_some_and(|rest| rest.starts_with('='))
+    })
+}
+
+/// Value of an environment variable
+pub fn getenv(name: &str) -> Option<&'static str> {
+    let entry = state().vars[slot_of(name)?]?;
+    Some(&entry[name.len() + 1..])
+}
+
+/// Set an environment variable, replacing any previous value
+pub fn setenv(name: &str, value: &str) -> Result<(), SysError> {
+    if name.is_empty() || name.contains('=') || name.contains('\0') || value.contains('\0') {
+        return Err(SysError(errno::EINVAL));
+    }
+    let state = state();
+    let slot = match slot_of(name) {
+        Some(slot) => slot,
+        None => state.vars.iter().position(|v| v.is_none()).ok_or(SysError(errno::ENOMEM))?,
+    };
+
+    let len = name.len() + 1 + value.len();
+    let start = state.arena_used;
+    if start + len > ARENA_SIZE {
+        return Err(SysError(errno::ENOMEM));
+    }
+    let dst = &mut state.arena[start..start + len];
+    dst[..name.len()].copy_from_slice(name.as_bytes());
+    dst[name.len()] = b'=';
+    dst[name.len() + 1..].copy_from_slice(value.as_bytes());
+    state.arena_used += len;
+
+    let entry = unsafe { core::str::from_utf8_unchecked(&*(dst as *const [u8])) };
+    state.vars[slot] = Some(entry);
+    Ok(())
+}
+
+/// Remove an environment variable
+pub fn unsetenv(name: &str) {
+    if let Some(slot) = slot_of(name) {
+        state().vars[slot] = None;
+    }
+}
@@ -0,0 +1,66 @@
+//! Program Heap
+//!
+//! A bump allocator over the program break: it never frees, and asks the
+//! kernel for more memory with sbrk when it runs out. Pages arrive on
+//! first touch, so growing the break is cheap.
+//!
+//! Programs that want `alloc` install it themselves:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static HEAP: userland::heap::BrkAllocator = userland::heap::BrkAllocator::new();
+//! ```
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use crate::syscall;
+
+/// Smallest amount the break is moved by
+const GROW_STEP: u32 = 64 * 1024;
+
+pub struct BrkAllocator {
+    /// (next free byte, current break); 0 = not yet initialized
+    state: UnsafeCell<(u32, u32)>,
+}
+
+// Programs are single-threaded
+unsafe impl Sync for BrkAllocator {}
+
+impl BrkAllocator {
+    pub const fn new() -> Self {
+        Self { state: UnsafeCell::new((0, 0)) }
+    }
+}
+
+unsafe impl GlobalAlloc for BrkAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let state = &mut *self.state.get();
+        if state.1 == 0 {
+            match syscall::brk(0) {
+                Ok(brk) => *state = (brk, brk),
+                Err(_) => return core::ptr::null_mut(),
+            }
+        }
+
+        let align = layout.align() as u32;
+        let Some(start) = state.0.checked_add(align - 1).map(|a| a & !(align - 1)) else {
+            return core::ptr::null_mut();
+        };
+        let Some(end) = start.checked_add(layout.size() as u32) else {
+            return core::ptr::null_mut();
+        };
+        if end > state.1 {
+            let grow = (end - state.1).max(GROW_STEP);
+            if syscall::sbrk(grow as i32).is_err() {
+                return core::ptr::null_mut();
+            }
+            state.1 += grow;
+        }
+        state.0 = end;
+        start as *mut u8
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        // Bump allocator: memory is reclaimed when the program exits
+    }
+}
@@ -0,0 +1,49 @@
+//! Console Output
+//!
+//! `print!`/`println!` write straight to stdout with one Write syscall per
+//! formatted piece; there is no buffering.
+
+use core::fmt;
+use crate::syscall::{self, STDERR, STDOUT};
+
+/// `fmt::Write` adapter for a file descriptor
+pub struct Fd(pub u32);
+
+impl fmt::Write for Fd {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut rest = s.as_bytes();
+        while !rest.is_empty() {
+            match syscall::write(self.0, rest) {
+                Ok(0) | Err(_) => return Err(fmt::Error),
+                Ok(n) => rest = &rest[n as usize..],
+            }
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Fd(STDOUT), args);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Fd(STDERR), args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => { $crate::io::_print(format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! println {
+    () => { $crate::print!("\n") };
+    ($($arg:tt)*) => { $crate::io::_print(format_args!("{}\n", format_args!($($arg)*))) };
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    ($($arg:tt)*) => { $crate::io::_eprint(format_args!("{}\n", format_args!($($arg)*))) };
+}
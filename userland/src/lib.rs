@@ -0,0 +1,31 @@
+//! Rustacean OS User Runtime
+//!
+//! Everything a user program needs to run on Rustacean OS without a libc:
+//! `_start`, a panic handler, INT 0x80 syscall wrappers, console printing,
+//! arguments and environment, and an sbrk-backed heap.
+//!
+//! A program is a `#![no_std]` `#![no_main]` binary that links this crate
+//! and defines `main`:
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! use userland::println;
+//!
+//! #[no_mangle]
+//! pub extern "C" fn main() -> i32 {
+//!     println!("Hello from ring 3");
+//!     0
+//! }
+//! ```
+
+#![no_std]
+
+pub mod syscall;
+pub mod io;
+pub mod env;
+pub mod heap;
+mod rt;
+
+pub use syscall::{exit, SysError, SysResult};
@@ -0,0 +1,41 @@
+//! Program Entry and Panic Handling
+//!
+//! The kernel enters `_start` in ring 3 with ESP pointing at argc, then
+//! the argv pointers, a NULL, the envp pointers and another NULL. Every
+//! other register is zeroed. `_start` clears the frame pointer (so
+//! backtraces end here), aligns the stack, hands the argument block to
+//! `env`, and calls `main`; whatever `main` returns becomes the exit
+//! code.
+
+use core::panic::PanicInfo;
+
+extern "C" {
+    /// Provided by the program
+    fn main() -> i32;
+}
+
+core::arch::global_asm!(
+    ".section .text.start",
+    ".global _start",
+    "_start:",
+    "    xor ebp, ebp",
+    "    mov eax, esp",
+    "    and esp, -16",
+    "    sub esp, 12",
+    "    push eax",
+    "    call {start}",
+    "    ud2",
+    start = sym rt_start,
+);
+
+extern "C" fn rt_start(stack: *const u32) -> ! {
+    unsafe { crate::env::init(stack) };
+    let code = unsafe { main() };
+    crate::syscall::exit(code)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    crate::eprintln!("panic: {}", info);
+    crate::syscall::exit(101)
+}
@@ -0,0 +1,526 @@
+//! System Call Stubs
+//!
+//! Arguments go in EBX, ECX, EDX, ESI, EDI with the syscall number in EAX;
+//! the kernel returns the result in EAX, or a negated error number on
+//! failure (-1 when there is no more specific one).
+//! Numbers must match `SyscallNumber` in the kernel.
+
+use core::arch::asm;
+
+/// System call numbers
+pub mod nr {
+    pub const EXIT: u32 = 0;
+    pub const READ: u32 = 1;
+    pub const WRITE: u32 = 2;
+    pub const OPEN: u32 = 3;
+    pub const CLOSE: u32 = 4;
+    pub const GETPID: u32 = 5;
+    pub const FORK: u32 = 6;
+    pub const EXEC: u32 = 7;
+    pub const WAIT: u32 = 8;
+    pub const YIELD: u32 = 12;
+    pub const SLEEP: u32 = 13;
+    pub const TIME: u32 = 14;
+    pub const STAT: u32 = 15;
+    pub const READDIR: u32 = 16;
+    pub const SYNC: u32 = 18;
+    pub const DUP: u32 = 19;
+    pub const DUP2: u32 = 20;
+    pub const CHDIR: u32 = 21;
+    pub const GETCWD: u32 = 22;
+    pub const BRK: u32 = 23;
+    pub const SBRK: u32 = 24;
+    pub const SIGNAL: u32 = 25;
+    pub const IOCTL: u32 = 26;
+    pub const RLIMIT: u32 = 27;
+    pub const WIN_CREATE: u32 = 28;
+    pub const WIN_DESTROY: u32 = 29;
+    pub const WIN_PRESENT: u32 = 30;
+    pub const WIN_EVENT: u32 = 31;
+    pub const SHM_CREATE: u32 = 32;
+    pub const SHM_MAP: u32 = 33;
+    pub const SHM_UNMAP: u32 = 34;
+    pub const SHM_DESTROY: u32 = 35;
+    pub const WIN_ATTACH: u32 = 36;
+    pub const PORT_CREATE: u32 = 37;
+    pub const PORT_LOOKUP: u32 = 38;
+    pub const PORT_DESTROY: u32 = 39;
+    pub const PORT_SEND: u32 = 40;
+    pub const PORT_RECEIVE: u32 = 41;
+    pub const FSYNC: u32 = 42;
+    pub const GET_RANDOM: u32 = 43;
+}
+
+/// Error numbers (kernel `syscall::errno`, plus a few used locally)
+pub mod errno {
+    /// Failure without a more specific code
+    pub const EFAIL: u32 = 1;
+    /// Argument list too long
+    pub const E2BIG: u32 = 7;
+    /// Out of memory, or the heap limit was hit
+    pub const ENOMEM: u32 = 12;
+    /// Invalid argument
+    pub const EINVAL: u32 = 22;
+    /// Open file limit hit
+    pub const EMFILE: u32 = 24;
+    /// Port send or receive timed out
+    pub const ETIMEDOUT: u32 = 110;
+    /// Window limit hit
+    pub const EMWINDOW: u32 = 200;
+}
+
+/// Resources for `getrlimit`/`setrlimit`
+pub mod rlimit {
+    /// Heap size in bytes
+    pub const HEAP: u32 = 0;
+    /// Open files (not counting stdin/stdout/stderr)
+    pub const FILES: u32 = 1;
+    /// Desktop windows
+    pub const WINDOWS: u32 = 2;
+}
+
+/// Window event kinds (kernel `gui::server::event_kind`)
+pub mod win_event {
+    /// a = key code (set 1 scancode), b = character (0 if none), c = modifiers
+    pub const KEY: u32 = 1;
+    /// a = x, b = y (content coordinates)
+    pub const MOUSE_MOVE: u32 = 2;
+    /// a = x, b = y, c = button bit (1 left, 2 right, 4 middle) | PRESSED
+    pub const MOUSE_BUTTON: u32 = 3;
+    /// The window should close (the system is shutting down)
+    pub const CLOSE: u32 = 4;
+    /// a = x, b = y, c = wheel notches (i32, positive = scroll down)
+    pub const WHEEL: u32 = 5;
+    /// Set in a MOUSE_BUTTON event's `c` when the button went down
+    pub const PRESSED: u32 = 0x100;
+    /// Modifier bit in a KEY event's `c`
+    pub const MOD_CTRL: u32 = 0x01;
+}
+
+/// An input event for a window
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct WinEvent {
+    pub kind: u32,
+    pub a: u32,
+    pub b: u32,
+    pub c: u32,
+}
+
+/// Payload bytes per port message
+pub const MSG_SIZE: usize = 64;
+
+/// A message received on a port
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Message {
+    /// Sending program's PID, set by the kernel
+    pub sender: u32,
+    pub len: u32,
+    pub data: [u8; MSG_SIZE],
+}
+
+impl Message {
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..(self.len as usize).min(MSG_SIZE)]
+    }
+}
+
+/// Clocks for `clock_gettime` (kernel `time::Clock`)
+pub mod clock {
+    /// Milliseconds since boot
+    pub const MONOTONIC: u32 = 0;
+    /// Unix seconds (UTC)
+    pub const REALTIME: u32 = 1;
+    /// Seconds since 1970-01-01 00:00 in local time
+    pub const LOCAL: u32 = 2;
+}
+
+/// Open flags (POSIX values, as the kernel expects)
+pub mod flags {
+    pub const O_RDONLY: u32 = 0x0000;
+    pub const O_WRONLY: u32 = 0x0001;
+    pub const O_RDWR: u32 = 0x0002;
+    pub const O_CREAT: u32 = 0x0040;
+    pub const O_EXCL: u32 = 0x0080;
+    pub const O_TRUNC: u32 = 0x0200;
+    pub const O_APPEND: u32 = 0x0400;
+}
+
+/// Signal numbers
+pub mod signal {
+    pub const SIGINT: u32 = 2;
+    pub const SIGSEGV: u32 = 11;
+}
+
+/// Tty local mode flags (termios `c_lflag` bits)
+pub mod lflag {
+    pub const ISIG: u32 = 0x0001;
+    pub const ICANON: u32 = 0x0002;
+    pub const ECHO: u32 = 0x0008;
+}
+
+/// Ioctl requests
+mod ioctl {
+    pub const TCGETS: u32 = 0x5401;
+    pub const TCSETS: u32 = 0x5402;
+}
+
+/// Standard file descriptors
+pub const STDIN: u32 = 0;
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+
+/// A syscall failed, with its error number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysError(pub u32);
+
+pub type SysResult = Result<u32, SysError>;
+
+/// Largest error number (results above -MAX_ERRNO are errors)
+const MAX_ERRNO: u32 = 4095;
+
+fn check(ret: u32) -> SysResult {
+    if ret > MAX_ERRNO.wrapping_neg() { Err(SysError(ret.wrapping_neg())) } else { Ok(ret) }
+}
+
+// EBX can't be named as an asm operand on x86 (LLVM reserves it), so it
+// is swapped in and out around the INT.
+
+#[inline(always)]
+pub unsafe fn syscall0(n: u32) -> u32 {
+    let ret;
+    asm!("int 0x80", inlateout("eax") n => ret, options(nostack));
+    ret
+}
+
+#[inline(always)]
+pub unsafe fn syscall1(n: u32, a1: u32) -> u32 {
+    let ret;
+    asm!("xchg ebx, {a1}", "int 0x80", "xchg ebx, {a1}",
+         a1 = in(reg) a1, inlateout("eax") n => ret, options(nostack));
+    ret
+}
+
+#[inline(always)]
+pub unsafe fn syscall2(n: u32, a1: u32, a2: u32) -> u32 {
+    let ret;
+    asm!("xchg ebx, {a1}", "int 0x80", "xchg ebx, {a1}",
+         a1 = in(reg) a1, in("ecx") a2, inlateout("eax") n => ret, options(nostack));
+    ret
+}
+
+#[inline(always)]
+pub unsafe fn syscall3(n: u32, a1: u32, a2: u32, a3: u32) -> u32 {
+    let ret;
+    asm!("xchg ebx, {a1}", "int 0x80", "xchg ebx, {a1}",
+         a1 = in(reg) a1, in("ecx") a2, in("edx") a3, inlateout("eax") n => ret,
+         options(nostack));
+    ret
+}
+
+pub unsafe fn syscall4(n: u32, a1: u32, a2: u32, a3: u32, a4: u32) -> u32 {
+    let ret;
+    asm!("xchg ebx, {a1}", "int 0x80", "xchg ebx, {a1}",
+         a1 = in(reg) a1, in("ecx") a2, in("edx") a3, in("esi") a4,
+         inlateout("eax") n => ret, options(nostack));
+    ret
+}
+
+/// Copy a path into a NUL-terminated buffer for the kernel
+fn with_cstr<T>(path: &str, f: impl FnOnce(u32) -> T) -> Result<T, SysError> {
+    let mut buf = [0u8; 256];
+    if path.len() >= buf.len() || path.as_bytes().contains(&0) {
+        return Err(SysError(errno::EINVAL));
+    }
+    buf[..path.len()].copy_from_slice(path.as_bytes());
+    Ok(f(buf.as_ptr() as u32))
+}
+
+// ============================================================================
+// Wrappers
+// ============================================================================
+
+/// Terminate the program
+pub fn exit(code: i32) -> ! {
+    unsafe { syscall1(nr::EXIT, code as u32) };
+    // The kernel never returns from Exit
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn read(fd: u32, buf: &mut [u8]) -> SysResult {
+    check(unsafe { syscall3(nr::READ, fd, buf.as_mut_ptr() as u32, buf.len() as u32) })
+}
+
+pub fn write(fd: u32, buf: &[u8]) -> SysResult {
+    check(unsafe { syscall3(nr::WRITE, fd, buf.as_ptr() as u32, buf.len() as u32) })
+}
+
+pub fn open(path: &str, flags: u32) -> SysResult {
+    check(with_cstr(path, |p| unsafe { syscall2(nr::OPEN, p, flags) })?)
+}
+
+pub fn close(fd: u32) -> SysResult {
+    check(unsafe { syscall1(nr::CLOSE, fd) })
+}
+
+pub fn getpid() -> u32 {
+    unsafe { syscall0(nr::GETPID) }
+}
+
+pub fn fork() -> SysResult {
+    check(unsafe { syscall0(nr::FORK) })
+}
+
+/// Most argv (or envp) entries passed to exec
+pub const MAX_ARGS: usize = 32;
+
+/// NUL-terminated strings plus a NULL-terminated pointer array, built in
+/// place for the kernel
+struct CVec {
+    offsets: [usize; MAX_ARGS],
+    ptrs: [u32; MAX_ARGS + 1],
+    bytes: [u8; 2048],
+    count: usize,
+    used: usize,
+}
+
+impl CVec {
+    fn new() -> Self {
+        Self { offsets: [0; MAX_ARGS], ptrs: [0; MAX_ARGS + 1], bytes: [0; 2048], count: 0, used: 0 }
+    }
+
+    fn push(&mut self, s: &str) -> Result<(), SysError> {
+        let end = self.used + s.len() + 1;
+        if self.count == MAX_ARGS || end > self.bytes.len() || s.as_bytes().contains(&0) {
+            return Err(SysError(errno::E2BIG));
+        }
+        self.bytes[self.used..end - 1].copy_from_slice(s.as_bytes());
+        self.bytes[end - 1] = 0;
+        self.offsets[self.count] = self.used;
+        self.count += 1;
+        self.used = end;
+        Ok(())
+    }
+
+    /// Pointer array address (valid while `self` stays put)
+    fn as_ptr(&mut self) -> u32 {
+        let base = self.bytes.as_ptr() as u32;
+        for i in 0..self.count {
+            self.ptrs[i] = base + self.offsets[i] as u32;
+        }
+        self.ptrs[self.count] = 0;
+        self.ptrs.as_ptr() as u32
+    }
+}
+
+/// Replace this program with another, passing arguments and an
+/// environment ("NAME=value" strings); only returns on failure
+pub fn exec<'a, 'b>(path: &str, argv: impl IntoIterator<Item = &'a str>,
+                    envp: impl IntoIterator<Item = &'b str>) -> SysError {
+    let mut args = CVec::new();
+    let mut env = CVec::new();
+    for arg in argv {
+        if let Err(e) = args.push(arg) {
+            return e;
+        }
+    }
+    for var in envp {
+        if let Err(e) = env.push(var) {
+            return e;
+        }
+    }
+    let (argv, envp) = (args.as_ptr(), env.as_ptr());
+    match with_cstr(path, |p| unsafe { syscall3(nr::EXEC, p, argv, envp) }) {
+        Ok(ret) => check(ret).err().unwrap_or(SysError(errno::EFAIL)),
+        Err(e) => e,
+    }
+}
+
+/// Wait for a child to exit (`pid` 0 = any), returning its PID and exit status
+pub fn wait(pid: u32) -> Result<(u32, i32), SysError> {
+    let mut status = 0i32;
+    let child = check(unsafe { syscall2(nr::WAIT, pid, &mut status as *mut i32 as u32) })?;
+    Ok((child, status))
+}
+
+pub fn sched_yield() {
+    unsafe { syscall0(nr::YIELD) };
+}
+
+pub fn sleep(ms: u32) {
+    unsafe { syscall1(nr::SLEEP, ms) };
+}
+
+/// Milliseconds since boot
+pub fn time() -> u32 {
+    clock_gettime(clock::MONOTONIC)
+}
+
+/// Read a clock (see `clock`)
+pub fn clock_gettime(clock: u32) -> u32 {
+    unsafe { syscall1(nr::TIME, clock) }
+}
+
+pub fn sync() -> SysResult {
+    check(unsafe { syscall0(nr::SYNC) })
+}
+
+/// Write one file's data and metadata through to the disk
+pub fn fsync(fd: u32) -> SysResult {
+    check(unsafe { syscall1(nr::FSYNC, fd) })
+}
+
+/// Fill `buf` with random bytes from the kernel
+pub fn getrandom(buf: &mut [u8]) -> SysResult {
+    check(unsafe { syscall2(nr::GET_RANDOM, buf.as_mut_ptr() as u32, buf.len() as u32) })
+}
+
+pub fn dup(fd: u32) -> SysResult {
+    check(unsafe { syscall1(nr::DUP, fd) })
+}
+
+pub fn dup2(fd: u32, new_fd: u32) -> SysResult {
+    check(unsafe { syscall2(nr::DUP2, fd, new_fd) })
+}
+
+pub fn chdir(path: &str) -> SysResult {
+    check(with_cstr(path, |p| unsafe { syscall1(nr::CHDIR, p) })?)
+}
+
+/// Write the working directory into `buf`, returning its length
+pub fn getcwd(buf: &mut [u8]) -> SysResult {
+    check(unsafe { syscall2(nr::GETCWD, buf.as_mut_ptr() as u32, buf.len() as u32) })
+}
+
+/// Set the program break (0 queries it)
+pub fn brk(addr: u32) -> SysResult {
+    check(unsafe { syscall1(nr::BRK, addr) })
+}
+
+/// Move the program break, returning the old one
+pub fn sbrk(increment: i32) -> SysResult {
+    check(unsafe { syscall1(nr::SBRK, increment as u32) })
+}
+
+/// Ignore a signal (`true`) or restore its default action of ending the
+/// program; returns whether it was ignored before
+pub fn signal(sig: u32, ignore: bool) -> Result<bool, SysError> {
+    check(unsafe { syscall2(nr::SIGNAL, sig, ignore as u32) }).map(|was| was != 0)
+}
+
+/// Tty mode flags of a descriptor (fails if it isn't a tty)
+pub fn tcgetattr(fd: u32) -> SysResult {
+    let mut flags = 0u32;
+    check(unsafe { syscall3(nr::IOCTL, fd, ioctl::TCGETS, &mut flags as *mut u32 as u32) })?;
+    Ok(flags)
+}
+
+/// Set the tty mode flags of a descriptor
+pub fn tcsetattr(fd: u32, flags: u32) -> SysResult {
+    check(unsafe { syscall3(nr::IOCTL, fd, ioctl::TCSETS, &flags as *const u32 as u32) })
+}
+
+/// Current limit on a resource (see `rlimit`)
+pub fn getrlimit(resource: u32) -> SysResult {
+    check(unsafe { syscall3(nr::RLIMIT, resource, 0, 0) })
+}
+
+/// Change the limit on a resource, returning the old one
+pub fn setrlimit(resource: u32, limit: u32) -> SysResult {
+    check(unsafe { syscall3(nr::RLIMIT, resource, limit, 1) })
+}
+
+/// Open a window with a `width` x `height` content area, returning its ID
+pub fn win_create(title: &str, width: u32, height: u32) -> SysResult {
+    check(with_cstr(title, |p| unsafe { syscall3(nr::WIN_CREATE, p, width, height) })?)
+}
+
+pub fn win_destroy(id: u32) -> SysResult {
+    check(unsafe { syscall1(nr::WIN_DESTROY, id) })
+}
+
+/// Show a full frame of 0x00RRGGBB pixels (width * height, row-major)
+pub fn win_present(id: u32, pixels: &[u32]) -> SysResult {
+    check(unsafe { syscall3(nr::WIN_PRESENT, id, pixels.as_ptr() as u32, pixels.len() as u32) })
+}
+
+/// Use a shared memory object as a window's buffer (0 = back to copying)
+pub fn win_attach(id: u32, shm: u32) -> SysResult {
+    check(unsafe { syscall2(nr::WIN_ATTACH, id, shm) })
+}
+
+/// Redraw a window whose shared buffer was changed
+pub fn win_damage(id: u32) -> SysResult {
+    check(unsafe { syscall3(nr::WIN_PRESENT, id, 0, 0) })
+}
+
+/// Next input event of a window; with `block`, waits until there is one
+pub fn win_event(id: u32, block: bool) -> Result<Option<WinEvent>, SysError> {
+    let mut event = WinEvent::default();
+    let got = check(unsafe {
+        syscall3(nr::WIN_EVENT, id, &mut event as *mut WinEvent as u32, block as u32)
+    })?;
+    Ok((got != 0).then_some(event))
+}
+
+/// Create a shared memory object of at least `size` bytes, returning its ID
+pub fn shm_create(size: u32) -> SysResult {
+    check(unsafe { syscall1(nr::SHM_CREATE, size) })
+}
+
+/// Map a shared memory object, returning its address
+pub fn shm_map(id: u32) -> SysResult {
+    check(unsafe { syscall1(nr::SHM_MAP, id) })
+}
+
+pub fn shm_unmap(addr: u32) -> SysResult {
+    check(unsafe { syscall1(nr::SHM_UNMAP, addr) })
+}
+
+/// Destroy a shared memory object (mappings stay valid until unmapped)
+pub fn shm_destroy(id: u32) -> SysResult {
+    check(unsafe { syscall1(nr::SHM_DESTROY, id) })
+}
+
+/// Port deadline for a timeout in milliseconds (None = wait forever)
+fn deadline(timeout_ms: Option<u32>) -> u32 {
+    match timeout_ms {
+        None => u32::MAX,
+        Some(0) => 0,
+        Some(ms) => time().saturating_add(ms).min(u32::MAX - 1),
+    }
+}
+
+/// Create a named port this program receives on, returning its ID
+pub fn port_create(name: &str) -> SysResult {
+    check(with_cstr(name, |p| unsafe { syscall1(nr::PORT_CREATE, p) })?)
+}
+
+/// ID of the port with this name
+pub fn port_lookup(name: &str) -> SysResult {
+    check(with_cstr(name, |p| unsafe { syscall1(nr::PORT_LOOKUP, p) })?)
+}
+
+pub fn port_destroy(id: u32) -> SysResult {
+    check(unsafe { syscall1(nr::PORT_DESTROY, id) })
+}
+
+/// Send up to MSG_SIZE bytes, waiting up to `timeout_ms` for queue room
+/// (Some(0) = don't wait, None = forever)
+pub fn port_send(id: u32, payload: &[u8], timeout_ms: Option<u32>) -> SysResult {
+    check(unsafe {
+        syscall4(nr::PORT_SEND, id, payload.as_ptr() as u32, payload.len() as u32, deadline(timeout_ms))
+    })
+}
+
+/// Receive a message, waiting up to `timeout_ms` for one
+pub fn port_receive(id: u32, timeout_ms: Option<u32>) -> Result<Message, SysError> {
+    let mut message = Message { sender: 0, len: 0, data: [0; MSG_SIZE] };
+    check(unsafe {
+        syscall3(nr::PORT_RECEIVE, id, &mut message as *mut Message as u32, deadline(timeout_ms))
+    })?;
+    Ok(message)
+}